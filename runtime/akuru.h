@@ -0,0 +1,365 @@
+/* runtime support for akuru programs compiled to C99 */
+#ifndef AKURU_H
+#define AKURU_H
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef uint8_t ak_unit;
+typedef uint32_t ak_char;
+
+typedef struct {
+    const char *ptr;
+    size_t len;
+} ak_str;
+
+static inline void ak_panic(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n", message);
+    exit(101);
+}
+
+static inline void ak_assert(bool cond) {
+    if (!cond) {
+        ak_panic("assertion failed");
+    }
+}
+
+/* ------------------------------------------------------------ integers */
+
+#define AK_UNSIGNED(N, T, MAX, BITS)                                          \
+    static inline T ak_add_##N(T a, T b) {                                    \
+        if (a > MAX - b) ak_panic("attempt to add with overflow");            \
+        return (T)(a + b);                                                    \
+    }                                                                         \
+    static inline T ak_sub_##N(T a, T b) {                                    \
+        if (b > a) ak_panic("attempt to subtract with overflow");             \
+        return (T)(a - b);                                                    \
+    }                                                                         \
+    static inline T ak_mul_##N(T a, T b) {                                    \
+        if (a != 0 && b > MAX / a) ak_panic("attempt to multiply with overflow"); \
+        return (T)((uint64_t)a * b);                                          \
+    }                                                                         \
+    static inline T ak_div_##N(T a, T b) {                                    \
+        if (b == 0) ak_panic("attempt to divide by zero");                    \
+        return (T)(a / b);                                                    \
+    }                                                                         \
+    static inline T ak_rem_##N(T a, T b) {                                    \
+        if (b == 0)                                                           \
+            ak_panic("attempt to calculate the remainder with a divisor of zero"); \
+        return (T)(a % b);                                                    \
+    }                                                                         \
+    static inline T ak_neg_##N(T a) {                                         \
+        if (a != 0) ak_panic("attempt to negate with overflow");              \
+        return 0;                                                             \
+    }                                                                         \
+    static inline T ak_shl_##N(T a, bool neg, uint64_t b) {                   \
+        if (neg || b >= BITS) ak_panic("attempt to shift left with overflow"); \
+        return (T)((uint64_t)a << b);                                         \
+    }                                                                         \
+    static inline T ak_shr_##N(T a, bool neg, uint64_t b) {                   \
+        if (neg || b >= BITS) ak_panic("attempt to shift right with overflow"); \
+        return (T)(a >> b);                                                   \
+    }                                                                         \
+    static inline T ak_f2i_##N(double v) {                                    \
+        if (v != v || v <= 0.0) return 0;                                     \
+        if (v >= (double)MAX) return MAX;                                     \
+        return (T)v;                                                          \
+    }
+
+#define AK_SIGNED(N, T, UT, MIN, MAX, BITS)                                   \
+    static inline T ak_add_##N(T a, T b) {                                    \
+        if ((b > 0 && a > MAX - b) || (b < 0 && a < MIN - b))                 \
+            ak_panic("attempt to add with overflow");                         \
+        return (T)(a + b);                                                    \
+    }                                                                         \
+    static inline T ak_sub_##N(T a, T b) {                                    \
+        if ((b < 0 && a > MAX + b) || (b > 0 && a < MIN + b))                 \
+            ak_panic("attempt to subtract with overflow");                    \
+        return (T)(a - b);                                                    \
+    }                                                                         \
+    static inline T ak_mul_##N(T a, T b) {                                    \
+        bool overflow;                                                        \
+        if (a > 0) {                                                          \
+            overflow = b > 0 ? a > MAX / b : b < MIN / a;                     \
+        } else {                                                              \
+            overflow = b > 0 ? a < MIN / b : a != 0 && b < MAX / a;           \
+        }                                                                     \
+        if (overflow) ak_panic("attempt to multiply with overflow");          \
+        return (T)((int64_t)a * b);                                           \
+    }                                                                         \
+    static inline T ak_div_##N(T a, T b) {                                    \
+        if (b == 0) ak_panic("attempt to divide by zero");                    \
+        if (a == MIN && b == -1) ak_panic("attempt to divide with overflow");  \
+        return (T)(a / b);                                                    \
+    }                                                                         \
+    static inline T ak_rem_##N(T a, T b) {                                    \
+        if (b == 0)                                                           \
+            ak_panic("attempt to calculate the remainder with a divisor of zero"); \
+        if (b == -1) return 0;                                                \
+        return (T)(a % b);                                                    \
+    }                                                                         \
+    static inline T ak_neg_##N(T a) {                                         \
+        if (a == MIN) ak_panic("attempt to negate with overflow");            \
+        return (T)-a;                                                         \
+    }                                                                         \
+    static inline T ak_shl_##N(T a, bool neg, uint64_t b) {                   \
+        if (neg || b >= BITS) ak_panic("attempt to shift left with overflow"); \
+        return (T)(UT)((UT)a << b);                                           \
+    }                                                                         \
+    static inline T ak_shr_##N(T a, bool neg, uint64_t b) {                   \
+        if (neg || b >= BITS) ak_panic("attempt to shift right with overflow"); \
+        return (T)(a >> b);                                                   \
+    }                                                                         \
+    static inline T ak_f2i_##N(double v) {                                    \
+        if (v != v) return 0;                                                 \
+        if (v <= (double)MIN) return MIN;                                     \
+        if (v >= (double)MAX) return MAX;                                     \
+        return (T)v;                                                          \
+    }
+
+AK_UNSIGNED(u8, uint8_t, UINT8_MAX, 8)
+AK_UNSIGNED(u16, uint16_t, UINT16_MAX, 16)
+AK_UNSIGNED(u32, uint32_t, UINT32_MAX, 32)
+AK_UNSIGNED(u64, uint64_t, UINT64_MAX, 64)
+AK_SIGNED(i8, int8_t, uint8_t, INT8_MIN, INT8_MAX, 8)
+AK_SIGNED(i16, int16_t, uint16_t, INT16_MIN, INT16_MAX, 16)
+AK_SIGNED(i32, int32_t, uint32_t, INT32_MIN, INT32_MAX, 32)
+AK_SIGNED(i64, int64_t, uint64_t, INT64_MIN, INT64_MAX, 64)
+
+/* --------------------------------------------------- arrays and strings */
+
+static inline size_t ak_index(bool neg, uint64_t idx, size_t len) {
+    if (neg || idx >= len) {
+        char message[96];
+        snprintf(message, sizeof message,
+                 "index out of bounds: the length is %zu but the index is %s%" PRIu64, len,
+                 neg ? "-" : "", neg ? (uint64_t)0 - idx : idx);
+        ak_panic(message);
+    }
+    return (size_t)idx;
+}
+
+static inline void ak_check_range(int64_t lo, int64_t hi, size_t len) {
+    if (lo < 0 || lo > hi || (uint64_t)hi > len) {
+        char message[96];
+        snprintf(message, sizeof message,
+                 "range %" PRId64 "..%" PRId64 " out of bounds for length %zu", lo, hi, len);
+        ak_panic(message);
+    }
+}
+
+static inline bool ak_str_eq(ak_str a, ak_str b) {
+    return a.len == b.len && memcmp(a.ptr, b.ptr, a.len) == 0;
+}
+
+static inline int ak_str_cmp(ak_str a, ak_str b) {
+    int order = memcmp(a.ptr, b.ptr, a.len < b.len ? a.len : b.len);
+    if (order != 0) return order;
+    return a.len < b.len ? -1 : a.len > b.len;
+}
+
+static inline ak_str ak_str_slice(ak_str s, int64_t lo, int64_t hi) {
+    ak_str sub;
+    ak_check_range(lo, hi, s.len);
+    if (((size_t)lo < s.len && (s.ptr[lo] & 0xc0) == 0x80) ||
+        ((size_t)hi < s.len && (s.ptr[hi] & 0xc0) == 0x80)) {
+        ak_panic("string slice does not fall on character boundaries");
+    }
+    sub.ptr = s.ptr + lo;
+    sub.len = (size_t)(hi - lo);
+    return sub;
+}
+
+/* decodes the character at `*pos` and moves past it */
+static inline ak_char ak_str_next(ak_str s, size_t *pos) {
+    const unsigned char *p = (const unsigned char *)s.ptr + *pos;
+    ak_char c;
+    size_t len;
+
+    if (p[0] < 0x80) {
+        c = p[0];
+        len = 1;
+    } else if (p[0] < 0xe0) {
+        c = (ak_char)(p[0] & 0x1f) << 6 | (p[1] & 0x3f);
+        len = 2;
+    } else if (p[0] < 0xf0) {
+        c = (ak_char)(p[0] & 0x0f) << 12 | (ak_char)(p[1] & 0x3f) << 6 | (p[2] & 0x3f);
+        len = 3;
+    } else {
+        c = (ak_char)(p[0] & 0x07) << 18 | (ak_char)(p[1] & 0x3f) << 12 |
+            (ak_char)(p[2] & 0x3f) << 6 | (p[3] & 0x3f);
+        len = 4;
+    }
+
+    *pos += len;
+    return c;
+}
+
+/* -------------------------------------------------------------- printing */
+
+static inline void ak_print_cstr(const char *s) {
+    fputs(s, stdout);
+}
+
+static inline void ak_newline(void) {
+    putchar('\n');
+}
+
+static inline void ak_print_int(int64_t v) {
+    printf("%" PRId64, v);
+}
+
+static inline void ak_print_uint(uint64_t v) {
+    printf("%" PRIu64, v);
+}
+
+static inline void ak_print_bool(bool v) {
+    fputs(v ? "true" : "false", stdout);
+}
+
+static inline void ak_write_utf8(ak_char c) {
+    if (c < 0x80) {
+        putchar((int)c);
+    } else if (c < 0x800) {
+        putchar((int)(0xc0 | c >> 6));
+        putchar((int)(0x80 | (c & 0x3f)));
+    } else if (c < 0x10000) {
+        putchar((int)(0xe0 | c >> 12));
+        putchar((int)(0x80 | (c >> 6 & 0x3f)));
+        putchar((int)(0x80 | (c & 0x3f)));
+    } else {
+        putchar((int)(0xf0 | c >> 18));
+        putchar((int)(0x80 | (c >> 12 & 0x3f)));
+        putchar((int)(0x80 | (c >> 6 & 0x3f)));
+        putchar((int)(0x80 | (c & 0x3f)));
+    }
+}
+
+/* writes a character as it appears inside quotes, `quote` is escaped */
+static inline void ak_write_escaped(ak_char c, ak_char quote) {
+    switch (c) {
+    case '\0': fputs("\\0", stdout); return;
+    case '\t': fputs("\\t", stdout); return;
+    case '\r': fputs("\\r", stdout); return;
+    case '\n': fputs("\\n", stdout); return;
+    case '\\': fputs("\\\\", stdout); return;
+    default: break;
+    }
+    if (c == quote) {
+        putchar('\\');
+        putchar((int)c);
+    } else if (c < 0x20 || c == 0x7f) {
+        printf("\\u{%x}", (unsigned)c);
+    } else {
+        ak_write_utf8(c);
+    }
+}
+
+/* strings and chars nested in other values are quoted */
+static inline void ak_print_char(ak_char c, bool nested) {
+    if (!nested) {
+        ak_write_utf8(c);
+        return;
+    }
+    putchar('\'');
+    ak_write_escaped(c, '\'');
+    putchar('\'');
+}
+
+static inline void ak_print_str(ak_str s, bool nested) {
+    size_t pos = 0;
+
+    if (!nested) {
+        fwrite(s.ptr, 1, s.len, stdout);
+        return;
+    }
+    putchar('"');
+    while (pos < s.len) {
+        ak_write_escaped(ak_str_next(s, &pos), '"');
+    }
+    putchar('"');
+}
+
+/*
+ * Writes the shortest decimal digits that read back as `v`, the way the
+ * interpreter shows floats: `1.0`, `0.25`, and `1e16` or `1.5e-7` outside
+ * [1e-4, 1e16).
+ */
+static inline void ak_print_float(double v, bool single) {
+    char buf[40], digits[24];
+    int precision, exp, len = 0;
+    const char *p;
+
+    if (v != v) {
+        fputs("NaN", stdout);
+        return;
+    }
+    if (signbit(v)) {
+        putchar('-');
+        v = -v;
+    }
+    if (isinf(v)) {
+        fputs("inf", stdout);
+        return;
+    }
+    if (v == 0.0) {
+        fputs("0.0", stdout);
+        return;
+    }
+
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(buf, sizeof buf, "%.*e", precision, v);
+        if (single ? (float)strtod(buf, NULL) == (float)v : strtod(buf, NULL) == v) {
+            break;
+        }
+    }
+
+    for (p = buf; *p != 'e'; p++) {
+        if (*p != '.') {
+            digits[len++] = *p;
+        }
+    }
+    exp = atoi(p + 1);
+    while (len > 1 && digits[len - 1] == '0') {
+        len--;
+    }
+    digits[len] = '\0';
+
+    if (single ? v < (double)1e-4f || v >= (double)1e16f : v < 1e-4 || v >= 1e16) {
+        putchar(digits[0]);
+        if (len > 1) {
+            printf(".%s", digits + 1);
+        }
+        printf("e%d", exp);
+    } else if (exp < 0) {
+        fputs("0.", stdout);
+        for (; exp < -1; exp++) {
+            putchar('0');
+        }
+        fputs(digits, stdout);
+    } else {
+        int idx;
+        for (idx = 0; idx <= exp; idx++) {
+            putchar(idx < len ? digits[idx] : '0');
+        }
+        putchar('.');
+        fputs(len > exp + 1 ? digits + exp + 1 : "0", stdout);
+    }
+}
+
+static inline void ak_print_f64(double v) {
+    ak_print_float(v, false);
+}
+
+static inline void ak_print_f32(float v) {
+    ak_print_float((double)v, true);
+}
+
+#endif
@@ -0,0 +1,844 @@
+# runtime support for akuru programs compiled to x86-64, for Linux
+#
+# The generated code defines `ak_main`, which runs the script. Output goes
+# through a buffer flushed on exit, and runtime errors print
+# `error: <message>` to stderr and exit with status 101. Every routine
+# follows the System V calling convention.
+
+        .text
+
+        .globl _start
+_start:
+        xorl %ebp, %ebp
+        andq $-16, %rsp
+        call ak_main
+        call ak_flush
+        movl $60, %eax
+        xorl %edi, %edi
+        syscall
+
+# -------------------------------------------------------------- output
+
+# writes the buffer to `ak_fd`
+        .globl ak_flush
+ak_flush:
+        movq ak_buf_len(%rip), %rdx
+        leaq ak_buf(%rip), %rsi
+.Lflush_loop:
+        testq %rdx, %rdx
+        jz .Lflush_done
+        movq ak_fd(%rip), %rdi
+        movl $1, %eax
+        syscall
+        testq %rax, %rax
+        jle .Lflush_done
+        addq %rax, %rsi
+        subq %rax, %rdx
+        jmp .Lflush_loop
+.Lflush_done:
+        movq $0, ak_buf_len(%rip)
+        ret
+
+# ak_write(ptr, len)
+        .globl ak_write
+ak_write:
+        pushq %rbx
+        pushq %r12
+        subq $8, %rsp
+        movq %rdi, %rbx
+        movq %rsi, %r12
+.Lwrite_loop:
+        testq %r12, %r12
+        jz .Lwrite_done
+        movq ak_buf_len(%rip), %rax
+        cmpq $4096, %rax
+        jb .Lwrite_byte
+        call ak_flush
+        xorl %eax, %eax
+.Lwrite_byte:
+        movzbl (%rbx), %ecx
+        leaq ak_buf(%rip), %rdx
+        movb %cl, (%rdx,%rax)
+        incq %rax
+        movq %rax, ak_buf_len(%rip)
+        incq %rbx
+        decq %r12
+        jmp .Lwrite_loop
+.Lwrite_done:
+        addq $8, %rsp
+        popq %r12
+        popq %rbx
+        ret
+
+# ak_write_byte(byte)
+        .globl ak_write_byte
+ak_write_byte:
+        subq $24, %rsp
+        movb %dil, (%rsp)
+        movq %rsp, %rdi
+        movl $1, %esi
+        call ak_write
+        addq $24, %rsp
+        ret
+
+# ak_write_utf8(char)
+ak_write_utf8:
+        subq $24, %rsp
+        movl %edi, %eax
+        cmpl $0x80, %eax
+        jae .Lutf8_2
+        movb %al, (%rsp)
+        movl $1, %esi
+        jmp .Lutf8_write
+.Lutf8_2:
+        cmpl $0x800, %eax
+        jae .Lutf8_3
+        shrl $6, %eax
+        orl $0xc0, %eax
+        movb %al, (%rsp)
+        movl %edi, %eax
+        andl $0x3f, %eax
+        orl $0x80, %eax
+        movb %al, 1(%rsp)
+        movl $2, %esi
+        jmp .Lutf8_write
+.Lutf8_3:
+        cmpl $0x10000, %eax
+        jae .Lutf8_4
+        shrl $12, %eax
+        orl $0xe0, %eax
+        movb %al, (%rsp)
+        movl %edi, %eax
+        shrl $6, %eax
+        andl $0x3f, %eax
+        orl $0x80, %eax
+        movb %al, 1(%rsp)
+        movl %edi, %eax
+        andl $0x3f, %eax
+        orl $0x80, %eax
+        movb %al, 2(%rsp)
+        movl $3, %esi
+        jmp .Lutf8_write
+.Lutf8_4:
+        shrl $18, %eax
+        orl $0xf0, %eax
+        movb %al, (%rsp)
+        movl %edi, %eax
+        shrl $12, %eax
+        andl $0x3f, %eax
+        orl $0x80, %eax
+        movb %al, 1(%rsp)
+        movl %edi, %eax
+        shrl $6, %eax
+        andl $0x3f, %eax
+        orl $0x80, %eax
+        movb %al, 2(%rsp)
+        movl %edi, %eax
+        andl $0x3f, %eax
+        orl $0x80, %eax
+        movb %al, 3(%rsp)
+        movl $4, %esi
+.Lutf8_write:
+        movq %rsp, %rdi
+        call ak_write
+        addq $24, %rsp
+        ret
+
+# ak_utf8_next(ptr) decodes the char at `ptr`, returning it in %eax and its
+# length in %rdx
+        .globl ak_utf8_next
+ak_utf8_next:
+        movzbl (%rdi), %eax
+        cmpl $0x80, %eax
+        jae .Lnext_2
+        movl $1, %edx
+        ret
+.Lnext_2:
+        cmpl $0xe0, %eax
+        jae .Lnext_3
+        andl $0x1f, %eax
+        shll $6, %eax
+        movzbl 1(%rdi), %ecx
+        andl $0x3f, %ecx
+        orl %ecx, %eax
+        movl $2, %edx
+        ret
+.Lnext_3:
+        cmpl $0xf0, %eax
+        jae .Lnext_4
+        andl $0x0f, %eax
+        shll $12, %eax
+        movzbl 1(%rdi), %ecx
+        andl $0x3f, %ecx
+        shll $6, %ecx
+        orl %ecx, %eax
+        movzbl 2(%rdi), %ecx
+        andl $0x3f, %ecx
+        orl %ecx, %eax
+        movl $3, %edx
+        ret
+.Lnext_4:
+        andl $0x07, %eax
+        shll $18, %eax
+        movzbl 1(%rdi), %ecx
+        andl $0x3f, %ecx
+        shll $12, %ecx
+        orl %ecx, %eax
+        movzbl 2(%rdi), %ecx
+        andl $0x3f, %ecx
+        shll $6, %ecx
+        orl %ecx, %eax
+        movzbl 3(%rdi), %ecx
+        andl $0x3f, %ecx
+        orl %ecx, %eax
+        movl $4, %edx
+        ret
+
+# ak_write_escaped(char, quote) writes a char as it appears inside quotes
+ak_write_escaped:
+        pushq %rbx
+        movl %edi, %ebx
+        movl $48, %eax                  # '0'
+        testl %ebx, %ebx
+        jz .Lescape
+        movl $116, %eax                 # 't'
+        cmpl $9, %ebx
+        je .Lescape
+        movl $114, %eax                 # 'r'
+        cmpl $13, %ebx
+        je .Lescape
+        movl $110, %eax                 # 'n'
+        cmpl $10, %ebx
+        je .Lescape
+        movl %ebx, %eax
+        cmpl $92, %ebx                  # '\\'
+        je .Lescape
+        cmpl %esi, %ebx
+        je .Lescape
+        cmpl $0x20, %ebx
+        jb .Lescape_hex
+        cmpl $0x7f, %ebx
+        je .Lescape_hex
+        movl %ebx, %edi
+        call ak_write_utf8
+        popq %rbx
+        ret
+.Lescape:
+        movl %eax, %ebx
+        movl $92, %edi
+        call ak_write_byte
+        movl %ebx, %edi
+        call ak_write_byte
+        popq %rbx
+        ret
+.Lescape_hex:
+        leaq ak_s_unicode(%rip), %rdi
+        movl $3, %esi
+        call ak_write
+        cmpl $16, %ebx
+        jb .Lescape_low
+        movl %ebx, %eax
+        shrl $4, %eax
+        leaq ak_hex(%rip), %rdi
+        movzbl (%rdi,%rax), %edi
+        call ak_write_byte
+.Lescape_low:
+        andl $15, %ebx
+        leaq ak_hex(%rip), %rdi
+        movzbl (%rdi,%rbx), %edi
+        call ak_write_byte
+        movl $125, %edi                 # '}'
+        call ak_write_byte
+        popq %rbx
+        ret
+
+# -------------------------------------------------------------- printing
+
+# ak_print_uint(value)
+        .globl ak_print_uint
+ak_print_uint:
+        subq $40, %rsp
+        movq %rdi, %rax
+        leaq 32(%rsp), %rsi
+        movl $10, %ecx
+.Luint_loop:
+        xorl %edx, %edx
+        divq %rcx
+        addb $48, %dl
+        decq %rsi
+        movb %dl, (%rsi)
+        testq %rax, %rax
+        jnz .Luint_loop
+        leaq 32(%rsp), %rdx
+        subq %rsi, %rdx
+        movq %rsi, %rdi
+        movq %rdx, %rsi
+        call ak_write
+        addq $40, %rsp
+        ret
+
+# ak_print_int(value)
+        .globl ak_print_int
+ak_print_int:
+        testq %rdi, %rdi
+        jns ak_print_uint
+        pushq %rdi
+        movl $45, %edi                  # '-'
+        call ak_write_byte
+        popq %rdi
+        negq %rdi
+        jmp ak_print_uint
+
+# ak_print_bool(value)
+        .globl ak_print_bool
+ak_print_bool:
+        testb %dil, %dil
+        jz .Lbool_false
+        leaq ak_s_true(%rip), %rdi
+        movl $4, %esi
+        jmp ak_write
+.Lbool_false:
+        leaq ak_s_false(%rip), %rdi
+        movl $5, %esi
+        jmp ak_write
+
+# ak_print_char(char, nested), quoted when nested in another value
+        .globl ak_print_char
+ak_print_char:
+        testl %esi, %esi
+        jz ak_write_utf8
+        pushq %rbx
+        movl %edi, %ebx
+        movl $39, %edi                  # '\''
+        call ak_write_byte
+        movl %ebx, %edi
+        movl $39, %esi
+        call ak_write_escaped
+        movl $39, %edi
+        call ak_write_byte
+        popq %rbx
+        ret
+
+# ak_print_str(ptr, len, nested), quoted when nested in another value
+        .globl ak_print_str
+ak_print_str:
+        testl %edx, %edx
+        jz ak_write
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        movq %rdi, %rbx
+        leaq (%rdi,%rsi), %r12
+        movl $34, %edi                  # '"'
+        call ak_write_byte
+.Lstr_loop:
+        cmpq %r12, %rbx
+        jae .Lstr_done
+        movq %rbx, %rdi
+        call ak_utf8_next
+        addq %rdx, %rbx
+        movl %eax, %edi
+        movl $34, %esi
+        call ak_write_escaped
+        jmp .Lstr_loop
+.Lstr_done:
+        movl $34, %edi
+        call ak_write_byte
+        popq %r13
+        popq %r12
+        popq %rbx
+        ret
+
+# ak_print_f64(value)
+        .globl ak_print_f64
+ak_print_f64:
+        xorl %edi, %edi
+        jmp ak_print_float
+
+# ak_print_f32(value)
+        .globl ak_print_f32
+ak_print_f32:
+        cvtss2sd %xmm0, %xmm0
+        movl $1, %edi
+        jmp ak_print_float
+
+# ak_print_float(value, single) writes the shortest digits that read back
+# as the value, the way the interpreter shows floats: `1.0`, `0.25`, and
+# `1e16` or `1.5e-7` outside [1e-4, 1e16)
+ak_print_float:
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        pushq %r14
+        pushq %r15
+        subq $48, %rsp
+        movl %edi, %r15d
+        ucomisd %xmm0, %xmm0
+        jp .Lfloat_nan
+        movq %xmm0, %rax
+        testq %rax, %rax
+        jns .Lfloat_positive
+        btrq $63, %rax
+        movq %rax, 32(%rsp)
+        movl $45, %edi                  # '-'
+        call ak_write_byte
+        movsd 32(%rsp), %xmm0
+.Lfloat_positive:
+        movsd %xmm0, 32(%rsp)
+        movq %xmm0, %rax
+        movabsq $0x7ff0000000000000, %rcx
+        cmpq %rcx, %rax
+        je .Lfloat_inf
+        testq %rax, %rax
+        jz .Lfloat_zero
+
+        # the exponent of the first digit, roughly, which only needs to be
+        # close for the search below
+        xorl %r13d, %r13d
+        movl $10, %eax
+        cvtsi2sdl %eax, %xmm1
+        movl $1, %eax
+        cvtsi2sdl %eax, %xmm2
+.Lfloat_down:
+        ucomisd %xmm1, %xmm0
+        jb .Lfloat_up
+        divsd %xmm1, %xmm0
+        incq %r13
+        jmp .Lfloat_down
+.Lfloat_up:
+        ucomisd %xmm2, %xmm0
+        jae .Lfloat_search
+        mulsd %xmm1, %xmm0
+        decq %r13
+        jmp .Lfloat_up
+
+        # for each precision p, round v to p digits as n * 10^-k and stop
+        # once that reads back as v
+.Lfloat_search:
+        movl $1, %r12d
+.Lfloat_try:
+        movq %r12, %rbx
+        decq %rbx
+        subq %r13, %rbx
+        movq %rbx, %rax
+        negq %rax
+        cmovsq %rbx, %rax
+        movl $10, %ecx
+        cvtsi2sdl %ecx, %xmm2
+        movl $1, %ecx
+        cvtsi2sdl %ecx, %xmm1
+.Lfloat_pow:
+        testq %rax, %rax
+        jz .Lfloat_round
+        mulsd %xmm2, %xmm1
+        decq %rax
+        jmp .Lfloat_pow
+.Lfloat_round:
+        movsd 32(%rsp), %xmm0
+        testq %rbx, %rbx
+        js .Lfloat_round_down
+        mulsd %xmm1, %xmm0
+        cvtsd2si %xmm0, %r14
+        cvtsi2sdq %r14, %xmm0
+        divsd %xmm1, %xmm0
+        jmp .Lfloat_check
+.Lfloat_round_down:
+        divsd %xmm1, %xmm0
+        cvtsd2si %xmm0, %r14
+        cvtsi2sdq %r14, %xmm0
+        mulsd %xmm1, %xmm0
+.Lfloat_check:
+        movsd 32(%rsp), %xmm3
+        testl %r15d, %r15d
+        jz .Lfloat_check_double
+        cvtsd2ss %xmm0, %xmm0
+        cvtsd2ss %xmm3, %xmm3
+        ucomiss %xmm3, %xmm0
+        jmp .Lfloat_checked
+.Lfloat_check_double:
+        ucomisd %xmm3, %xmm0
+.Lfloat_checked:
+        jp .Lfloat_next
+        je .Lfloat_digits
+.Lfloat_next:
+        incq %r12
+        cmpq $17, %r12
+        jbe .Lfloat_try
+        decq %r12
+
+        # the digits of n, backwards from 24(%rsp)
+.Lfloat_digits:
+        movq %r14, %rax
+        leaq 24(%rsp), %rsi
+        movl $10, %ecx
+.Lfloat_digit:
+        xorl %edx, %edx
+        divq %rcx
+        addb $48, %dl
+        decq %rsi
+        movb %dl, (%rsi)
+        testq %rax, %rax
+        jnz .Lfloat_digit
+        leaq 24(%rsp), %rbx
+        subq %rsi, %rbx
+        # the exponent of the first digit, now exactly
+        movq %r13, %rax
+        subq %r12, %rax
+        addq %rbx, %rax
+        movq %rax, %r13
+        movq %rsi, %r14
+.Lfloat_trim:
+        cmpq $1, %rbx
+        jbe .Lfloat_format
+        cmpb $48, -1(%r14,%rbx)
+        jne .Lfloat_format
+        decq %rbx
+        jmp .Lfloat_trim
+
+        # %r14 and %rbx are the digits, %r13 the exponent
+.Lfloat_format:
+        movsd 32(%rsp), %xmm0
+        testl %r15d, %r15d
+        jz .Lfloat_range_double
+        ucomisd ak_f32_small(%rip), %xmm0
+        jb .Lfloat_sci
+        ucomisd ak_f32_large(%rip), %xmm0
+        jae .Lfloat_sci
+        jmp .Lfloat_plain
+.Lfloat_range_double:
+        ucomisd ak_f64_small(%rip), %xmm0
+        jb .Lfloat_sci
+        ucomisd ak_f64_large(%rip), %xmm0
+        jae .Lfloat_sci
+.Lfloat_plain:
+        testq %r13, %r13
+        jns .Lfloat_whole
+        leaq ak_s_zero(%rip), %rdi
+        movl $2, %esi
+        call ak_write
+        movq %r13, %r12
+.Lfloat_zeros:
+        incq %r12
+        jz .Lfloat_fraction
+        movl $48, %edi
+        call ak_write_byte
+        jmp .Lfloat_zeros
+.Lfloat_fraction:
+        movq %r14, %rdi
+        movq %rbx, %rsi
+        call ak_write
+        jmp .Lfloat_done
+.Lfloat_whole:
+        xorl %r12d, %r12d
+.Lfloat_whole_digit:
+        cmpq %r13, %r12
+        jg .Lfloat_point
+        movl $48, %edi
+        cmpq %rbx, %r12
+        jae .Lfloat_whole_write
+        movzbl (%r14,%r12), %edi
+.Lfloat_whole_write:
+        call ak_write_byte
+        incq %r12
+        jmp .Lfloat_whole_digit
+.Lfloat_point:
+        movl $46, %edi                  # '.'
+        call ak_write_byte
+        leaq 1(%r13), %rax
+        cmpq %rax, %rbx
+        jbe .Lfloat_point_zero
+        leaq (%r14,%rax), %rdi
+        movq %rbx, %rsi
+        subq %rax, %rsi
+        call ak_write
+        jmp .Lfloat_done
+.Lfloat_point_zero:
+        movl $48, %edi
+        call ak_write_byte
+        jmp .Lfloat_done
+.Lfloat_sci:
+        movzbl (%r14), %edi
+        call ak_write_byte
+        cmpq $1, %rbx
+        jbe .Lfloat_exp
+        movl $46, %edi
+        call ak_write_byte
+        leaq 1(%r14), %rdi
+        leaq -1(%rbx), %rsi
+        call ak_write
+.Lfloat_exp:
+        movl $101, %edi                 # 'e'
+        call ak_write_byte
+        movq %r13, %rdi
+        call ak_print_int
+        jmp .Lfloat_done
+.Lfloat_nan:
+        leaq ak_s_nan(%rip), %rdi
+        movl $3, %esi
+        call ak_write
+        jmp .Lfloat_done
+.Lfloat_inf:
+        leaq ak_s_inf(%rip), %rdi
+        movl $3, %esi
+        call ak_write
+        jmp .Lfloat_done
+.Lfloat_zero:
+        leaq ak_s_zero(%rip), %rdi
+        movl $3, %esi
+        call ak_write
+.Lfloat_done:
+        addq $48, %rsp
+        popq %r15
+        popq %r14
+        popq %r13
+        popq %r12
+        popq %rbx
+        ret
+
+# -------------------------------------------------------------- strings
+
+# ak_str_eq(a, b) compares two strs
+        .globl ak_str_eq
+ak_str_eq:
+        movq 8(%rdi), %rcx
+        xorl %eax, %eax
+        cmpq 8(%rsi), %rcx
+        jne .Lstr_eq_done
+        movq (%rdi), %rdi
+        movq (%rsi), %rsi
+        repe cmpsb
+        sete %al
+.Lstr_eq_done:
+        ret
+
+# ak_str_cmp(a, b) orders two strs byte by byte, giving -1, 0 or 1
+        .globl ak_str_cmp
+ak_str_cmp:
+        movq 8(%rdi), %rcx
+        movq 8(%rsi), %rdx
+        cmpq %rdx, %rcx
+        cmovaq %rdx, %rcx
+        movq (%rdi), %r8
+        movq (%rsi), %r9
+        xorl %eax, %eax
+.Lcmp_loop:
+        cmpq %rcx, %rax
+        jae .Lcmp_len
+        movzbl (%r8,%rax), %r10d
+        movzbl (%r9,%rax), %r11d
+        incq %rax
+        cmpl %r11d, %r10d
+        je .Lcmp_loop
+        jmp .Lcmp_order
+.Lcmp_len:
+        movq 8(%rdi), %r10
+        movq 8(%rsi), %r11
+        cmpq %r11, %r10
+.Lcmp_order:
+        seta %al
+        setb %cl
+        movzbl %al, %eax
+        movzbl %cl, %ecx
+        subq %rcx, %rax
+        ret
+
+# ak_str_slice(src, lo, hi, dst) writes the substring of `src` between two
+# byte offsets to `dst`, returning it
+        .globl ak_str_slice
+ak_str_slice:
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        pushq %r14
+        subq $8, %rsp
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        movq %rdx, %r13
+        movq %rcx, %r14
+        movq %rsi, %rdi
+        movq %rdx, %rsi
+        movq 8(%rbx), %rdx
+        call ak_check_range
+        movq %r12, %rdi
+        call .Lslice_boundary
+        movq %r13, %rdi
+        call .Lslice_boundary
+        movq (%rbx), %rax
+        addq %r12, %rax
+        movq %rax, (%r14)
+        movq %r13, %rax
+        subq %r12, %rax
+        movq %rax, 8(%r14)
+        movq %r14, %rax
+        addq $8, %rsp
+        popq %r14
+        popq %r13
+        popq %r12
+        popq %rbx
+        ret
+        # an offset inside the string must be at the start of a char
+.Lslice_boundary:
+        cmpq 8(%rbx), %rdi
+        jae .Lslice_ok
+        movq (%rbx), %rax
+        movzbl (%rax,%rdi), %eax
+        andl $0xc0, %eax
+        cmpl $0x80, %eax
+        jne .Lslice_ok
+        subq $8, %rsp
+        leaq ak_s_boundary(%rip), %rdi
+        movl $50, %esi
+        call ak_panic
+.Lslice_ok:
+        ret
+
+# -------------------------------------------------------------- floats
+
+# ak_fmod(a, b), the remainder of `a / b` with the sign of `a`
+        .globl ak_fmod
+ak_fmod:
+        subq $24, %rsp
+        movsd %xmm1, (%rsp)
+        movsd %xmm0, 8(%rsp)
+        fldl (%rsp)
+        fldl 8(%rsp)
+.Lfmod_loop:
+        fprem
+        fnstsw %ax
+        testw $0x400, %ax
+        jnz .Lfmod_loop
+        fstpl 8(%rsp)
+        fstp %st(0)
+        movsd 8(%rsp), %xmm0
+        addq $24, %rsp
+        ret
+
+# -------------------------------------------------------------- errors
+
+# flushes stdout and starts writing a message to stderr
+ak_panic_begin:
+        subq $8, %rsp
+        call ak_flush
+        movq $2, ak_fd(%rip)
+        leaq ak_s_error(%rip), %rdi
+        movl $7, %esi
+        call ak_write
+        addq $8, %rsp
+        ret
+
+# ends the message and exits with status 101
+ak_panic_end:
+        subq $8, %rsp
+        movl $10, %edi
+        call ak_write_byte
+        call ak_flush
+        movl $60, %eax
+        movl $101, %edi
+        syscall
+
+# ak_panic(ptr, len)
+        .globl ak_panic
+ak_panic:
+        pushq %rbx
+        pushq %r12
+        subq $8, %rsp
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        call ak_panic_begin
+        movq %rbx, %rdi
+        movq %r12, %rsi
+        call ak_write
+        call ak_panic_end
+
+# ak_panic_index(len, index, signed)
+        .globl ak_panic_index
+ak_panic_index:
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        movl %edx, %r13d
+        call ak_panic_begin
+        leaq ak_s_index(%rip), %rdi
+        movl $35, %esi
+        call ak_write
+        movq %rbx, %rdi
+        call ak_print_uint
+        leaq ak_s_index_is(%rip), %rdi
+        movl $18, %esi
+        call ak_write
+        movq %r12, %rdi
+        testl %r13d, %r13d
+        jz .Lindex_unsigned
+        call ak_print_int
+        call ak_panic_end
+.Lindex_unsigned:
+        call ak_print_uint
+        call ak_panic_end
+
+# ak_check_range(lo, hi, len) stops unless 0 <= lo <= hi <= len
+        .globl ak_check_range
+ak_check_range:
+        testq %rdi, %rdi
+        js .Lrange_fail
+        cmpq %rsi, %rdi
+        jg .Lrange_fail
+        cmpq %rdx, %rsi
+        jg .Lrange_fail
+        ret
+.Lrange_fail:
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        movq %rdx, %r13
+        call ak_panic_begin
+        leaq ak_s_range(%rip), %rdi
+        movl $6, %esi
+        call ak_write
+        movq %rbx, %rdi
+        call ak_print_int
+        leaq ak_s_dots(%rip), %rdi
+        movl $2, %esi
+        call ak_write
+        movq %r12, %rdi
+        call ak_print_int
+        leaq ak_s_range_len(%rip), %rdi
+        movl $26, %esi
+        call ak_write
+        movq %r13, %rdi
+        call ak_print_uint
+        call ak_panic_end
+
+# -------------------------------------------------------------- data
+
+        .section .rodata
+ak_hex:         .ascii "0123456789abcdef"
+ak_s_true:      .ascii "true"
+ak_s_false:     .ascii "false"
+ak_s_nan:       .ascii "NaN"
+ak_s_inf:       .ascii "inf"
+ak_s_zero:      .ascii "0.0"
+ak_s_unicode:   .ascii "\\u{"
+ak_s_error:     .ascii "error: "
+ak_s_index:     .ascii "index out of bounds: the length is "
+ak_s_index_is:  .ascii " but the index is "
+ak_s_range:     .ascii "range "
+ak_s_dots:      .ascii ".."
+ak_s_range_len: .ascii " out of bounds for length "
+ak_s_boundary:  .ascii "string slice does not fall on character boundaries"
+        .balign 8
+ak_f64_small:   .quad 0x3f1a36e2eb1c432d   # 1e-4
+ak_f64_large:   .quad 0x4341c37937e08000   # 1e16
+ak_f32_small:   .quad 0x3f1a36e2e0000000   # 1e-4 as an f32
+ak_f32_large:   .quad 0x4341c37940000000   # 1e16 as an f32
+
+        .data
+        .balign 8
+ak_fd:          .quad 1
+ak_buf_len:     .quad 0
+
+        .bss
+ak_buf:         .zero 4096
@@ -0,0 +1,1035 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    ast::BinaryOp,
+    bytecode::{Function, Module, Op, Scalar},
+    source::{FileId, SourceMap},
+    span::Span,
+    ty::{FloatTy, IntTy},
+    value::{Builtin, Layout, LayoutKind, Value},
+};
+
+pub const MAGIC: [u8; 4] = *b"AKC\0";
+pub const VERSION: u16 = 1;
+/// The most slots a function may have, which the VM allocates on each call.
+pub const MAX_SLOTS: u32 = 1 << 16;
+const HEADER_LEN: usize = 16;
+
+/// Why a file could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AkcError {
+    NotAkc,
+    UnsupportedVersion(u16),
+    /// the file ends before the data at `offset`
+    Truncated {
+        offset: usize,
+    },
+    ChecksumMismatch,
+    /// the data at `offset` is malformed
+    Malformed {
+        offset: usize,
+        message: String,
+    },
+    /// the module decoded but refers to things that do not exist
+    Invalid(String),
+}
+
+impl fmt::Display for AkcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAkc => write!(f, "not an akuru bytecode file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported bytecode version {version}, expected {VERSION}"
+            ),
+            Self::Truncated { offset } => write!(f, "file is truncated at byte {offset}"),
+            Self::ChecksumMismatch => write!(f, "file is corrupt: checksum mismatch"),
+            Self::Malformed { offset, message } => {
+                write!(f, "file is corrupt at byte {offset}: {message}")
+            }
+            Self::Invalid(message) => write!(f, "invalid module: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for AkcError {}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+// ---------------------------------------------------------------- writing
+
+/// Serializes `module`, with the sources in `map` its spans point into.
+///
+/// A file starts with a 16 byte header: the magic `AKC\0`, the format
+/// version and a reserved field as little endian `u16`s, then the length
+/// and the FNV-1a hash of the payload as `u32`s. The payload holds, in
+/// order, the string table, the sources, the constants, the layouts, the
+/// functions, the globals and the index of `main`. Strings are written
+/// once in the string table and referred to by index everywhere else, and
+/// spans refer to sources by their index in the file.
+pub fn write(module: &Module, map: &SourceMap) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.module(module, map);
+
+    let mut strings = Writer::default();
+    strings.u32(writer.strings.len() as u32);
+    for string in &writer.strings {
+        strings.bytes(string.as_bytes());
+    }
+
+    let mut payload = strings.out;
+    payload.extend(writer.out);
+
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    out.extend(0u16.to_le_bytes());
+    out.extend((payload.len() as u32).to_le_bytes());
+    out.extend(fnv1a(&payload).to_le_bytes());
+    out.extend(payload);
+    out
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    file_ids: HashMap<FileId, u32>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.out.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.out.extend(value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.out.extend(bytes);
+    }
+
+    fn string(&mut self, string: &str) {
+        let idx = match self.string_ids.get(string) {
+            Some(idx) => *idx,
+            None => {
+                let idx = self.strings.len() as u32;
+                self.strings.push(string.to_owned());
+                self.string_ids.insert(string.to_owned(), idx);
+                idx
+            }
+        };
+        self.u32(idx);
+    }
+
+    fn module(&mut self, module: &Module, map: &SourceMap) {
+        // only the sources some span points into
+        let mut files: Vec<FileId> = module
+            .functions
+            .iter()
+            .flat_map(|f| f.spans.iter().map(|(_, span)| span.id))
+            .filter(|id| (id.0 as usize) < map.source.len())
+            .collect();
+        files.sort();
+        files.dedup();
+
+        self.u32(files.len() as u32);
+        for (idx, id) in files.iter().enumerate() {
+            self.file_ids.insert(*id, idx as u32);
+            self.string(&map[*id].name);
+            self.bytes(map[*id].content.as_bytes());
+        }
+
+        self.u32(module.constants.len() as u32);
+        for value in &module.constants {
+            self.constant(value);
+        }
+
+        self.u32(module.layouts.len() as u32);
+        for layout in &module.layouts {
+            self.string(&layout.name);
+            self.u8(match layout.kind {
+                LayoutKind::Struct => 0,
+                LayoutKind::Unit => 1,
+                LayoutKind::Tuple => 2,
+                LayoutKind::StructVariant => 3,
+            });
+            self.u32(layout.fields.len() as u32);
+            for field in &layout.fields {
+                self.string(field);
+            }
+        }
+
+        self.u32(module.functions.len() as u32);
+        for func in &module.functions {
+            self.function(func);
+        }
+
+        self.u32(module.globals.len() as u32);
+        for global in &module.globals {
+            self.u32(*global);
+        }
+        self.u32(module.main.unwrap_or(u32::MAX));
+    }
+
+    fn constant(&mut self, value: &Value) {
+        match value {
+            Value::Int(v, ty) => {
+                self.u8(0);
+                self.u8(int_code(*ty));
+                self.out.extend(v.to_le_bytes());
+            }
+            Value::Float(v, ty) => {
+                self.u8(1);
+                self.u8(float_code(*ty));
+                self.out.extend(v.to_bits().to_le_bytes());
+            }
+            Value::Char(c) => {
+                self.u8(2);
+                self.u32(*c as u32);
+            }
+            Value::Str(s) => {
+                self.u8(3);
+                self.string(s);
+            }
+            value => unreachable!("`{value}` is not a constant"),
+        }
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.string(&func.name);
+        self.u32(func.arity);
+        self.u32(func.slots);
+
+        self.u32(func.captures.len() as u32);
+        for slot in &func.captures {
+            self.u32(*slot);
+        }
+
+        self.u32(func.code.len() as u32);
+        for op in &func.code {
+            self.op(*op);
+        }
+
+        let spans: Vec<_> = func
+            .spans
+            .iter()
+            .filter_map(|(offset, span)| Some((*offset, *self.file_ids.get(&span.id)?, span)))
+            .collect();
+        self.u32(spans.len() as u32);
+        for (offset, file, span) in spans {
+            self.u32(offset);
+            self.u32(file);
+            self.u32(span.lo as u32);
+            self.u32(span.hi as u32);
+        }
+    }
+
+    fn op(&mut self, op: Op) {
+        let (code, operands): (u8, &[u32]) = match op {
+            Op::Const(a) => (0, &[a]),
+            Op::Unit => (1, &[]),
+            Op::True => (2, &[]),
+            Op::False => (3, &[]),
+            Op::None => (4, &[]),
+            Op::Pop => (5, &[]),
+            Op::PopN(a) => (6, &[a]),
+            Op::Dup => (7, &[]),
+            Op::Slide(a) => (8, &[a]),
+            Op::Load(a) => (9, &[a]),
+            Op::Store(a) => (10, &[a]),
+            Op::Define(a) => (11, &[a]),
+            Op::Ref(a) => (12, &[a]),
+            Op::Global(a) => (13, &[a]),
+            Op::Func(a) => (14, &[a]),
+            Op::Builtin(builtin) => (15, &[builtin_code(builtin) as u32]),
+            Op::Ctor(a) => (16, &[a]),
+            Op::Closure(a) => (17, &[a]),
+            Op::Tuple(a) => (18, &[a]),
+            Op::Array(a) => (19, &[a]),
+            Op::Struct { layout, len } => (20, &[layout, len]),
+            Op::Range {
+                start,
+                end,
+                inclusive,
+            } => (
+                21,
+                &[start as u32 | (end as u32) << 1 | (inclusive as u32) << 2],
+            ),
+            Op::Binary(op) => (22, &[binary_code(op) as u32]),
+            Op::Neg => (23, &[]),
+            Op::Not => (24, &[]),
+            Op::Cast(to) => (25, &[scalar_code(to) as u32]),
+            Op::Deref => (26, &[]),
+            Op::Field(a) => (27, &[a]),
+            Op::Index => (28, &[]),
+            Op::Temp => (29, &[]),
+            Op::Project(a) => (30, &[a]),
+            Op::ProjectIndex => (31, &[]),
+            Op::Set => (32, &[]),
+            Op::Update(op) => (33, &[binary_code(op) as u32]),
+            Op::Bump(op) => (34, &[binary_code(op) as u32]),
+            Op::Tag(a) => (35, &[a]),
+            Op::Jump(a) => (36, &[a]),
+            Op::JumpIfFalse(a) => (37, &[a]),
+            Op::JumpIfTrue(a) => (38, &[a]),
+            Op::Iter(a) => (39, &[a]),
+            Op::Next { slot, exit } => (40, &[slot, exit]),
+            Op::Call(a) => (41, &[a]),
+            Op::Return => (42, &[]),
+            Op::NoMatch(a) => (43, &[a]),
+        };
+
+        self.u8(code);
+        for operand in operands {
+            self.u32(*operand);
+        }
+    }
+}
+
+const BINARY_OPS: [BinaryOp; 18] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::Shl,
+    BinaryOp::Shr,
+    BinaryOp::BitAnd,
+    BinaryOp::BitOr,
+    BinaryOp::BitXor,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::Eq,
+    BinaryOp::Ne,
+    BinaryOp::Lt,
+    BinaryOp::Gt,
+    BinaryOp::Le,
+    BinaryOp::Ge,
+];
+
+const BUILTINS: [Builtin; 3] = [Builtin::Print, Builtin::Println, Builtin::Assert];
+
+fn binary_code(op: BinaryOp) -> u8 {
+    BINARY_OPS.iter().position(|o| *o == op).unwrap_or_default() as u8
+}
+
+fn builtin_code(builtin: Builtin) -> u8 {
+    BUILTINS
+        .iter()
+        .position(|b| *b == builtin)
+        .unwrap_or_default() as u8
+}
+
+fn int_code(ty: IntTy) -> u8 {
+    IntTy::ALL.iter().position(|t| *t == ty).unwrap_or_default() as u8
+}
+
+fn float_code(ty: FloatTy) -> u8 {
+    match ty {
+        FloatTy::F32 => 0,
+        FloatTy::F64 => 1,
+    }
+}
+
+/// Integer types take codes 0 to 7, then `f32`, `f64`, `bool` and `char`.
+fn scalar_code(scalar: Scalar) -> u8 {
+    match scalar {
+        Scalar::Int(ty) => int_code(ty),
+        Scalar::Float(ty) => 8 + float_code(ty),
+        Scalar::Bool => 10,
+        Scalar::Char => 11,
+    }
+}
+
+// ---------------------------------------------------------------- reading
+
+/// Loads a module written by [`write`], adding its sources to `map` so
+/// its spans point at them. The module is checked before it is returned,
+/// so running it cannot index out of its tables.
+pub fn read(bytes: &[u8], map: &mut SourceMap) -> Result<Module, AkcError> {
+    if bytes.len() < 4 || bytes[..4] != MAGIC {
+        return Err(AkcError::NotAkc);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(AkcError::Truncated {
+            offset: bytes.len(),
+        });
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(AkcError::UnsupportedVersion(version));
+    }
+
+    let len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() < len {
+        return Err(AkcError::Truncated {
+            offset: bytes.len(),
+        });
+    }
+    if payload.len() > len {
+        return Err(AkcError::Malformed {
+            offset: HEADER_LEN + len,
+            message: "trailing data after the module".to_owned(),
+        });
+    }
+    if fnv1a(payload) != checksum {
+        return Err(AkcError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader {
+        bytes: payload,
+        pos: 0,
+        strings: Vec::new(),
+        files: Vec::new(),
+    };
+    let module = reader.module(map)?;
+    validate(&module)?;
+    Ok(module)
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+    strings: Vec<Rc<str>>,
+    /// the sources in the file and their lengths
+    files: Vec<(FileId, usize)>,
+}
+
+impl Reader<'_> {
+    fn malformed<T>(&self, message: impl Into<String>) -> Result<T, AkcError> {
+        Err(AkcError::Malformed {
+            offset: HEADER_LEN + self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], AkcError> {
+        if self.bytes.len() - self.pos < len {
+            return Err(AkcError::Truncated {
+                offset: HEADER_LEN + self.bytes.len(),
+            });
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, AkcError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, AkcError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A count of items that each take at least `size` bytes.
+    fn count(&mut self, size: usize) -> Result<usize, AkcError> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(size) > self.bytes.len() - self.pos {
+            return Err(AkcError::Truncated {
+                offset: HEADER_LEN + self.bytes.len(),
+            });
+        }
+        Ok(count)
+    }
+
+    fn text(&mut self) -> Result<String, AkcError> {
+        let len = self.count(1)?;
+        let start = self.pos;
+        let bytes = self.take(len)?;
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(text.to_owned()),
+            Err(_) => {
+                self.pos = start;
+                self.malformed("string is not valid UTF-8")
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Rc<str>, AkcError> {
+        let idx = self.u32()? as usize;
+        match self.strings.get(idx) {
+            Some(string) => Ok(string.clone()),
+            None => {
+                self.pos -= 4;
+                self.malformed(format!("string {idx} is not in the string table"))
+            }
+        }
+    }
+
+    /// Reads an operand that picks one of `limit` choices.
+    fn code(&mut self, limit: u32, what: &str) -> Result<u32, AkcError> {
+        let code = self.u32()?;
+        if code >= limit {
+            self.pos -= 4;
+            return self.malformed(format!("unknown {what} {code}"));
+        }
+        Ok(code)
+    }
+
+    fn module(&mut self, map: &mut SourceMap) -> Result<Module, AkcError> {
+        let strings = self.count(4)?;
+        for _ in 0..strings {
+            let string = self.text()?;
+            self.strings.push(string.into());
+        }
+
+        let sources = self.count(8)?;
+        for _ in 0..sources {
+            let name = self.string()?;
+            let content = self.text()?;
+            let len = content.len();
+            self.files.push((map.with_content(&*name, content), len));
+        }
+
+        let mut module = Module::default();
+
+        let constants = self.count(5)?;
+        for _ in 0..constants {
+            let value = self.constant()?;
+            module.constants.push(value);
+        }
+
+        let layouts = self.count(9)?;
+        for id in 0..layouts {
+            let name = self.string()?.to_string();
+            let kind = match self.u8()? {
+                0 => LayoutKind::Struct,
+                1 => LayoutKind::Unit,
+                2 => LayoutKind::Tuple,
+                3 => LayoutKind::StructVariant,
+                kind => {
+                    self.pos -= 1;
+                    return self.malformed(format!("unknown layout kind {kind}"));
+                }
+            };
+            let fields = self.count(4)?;
+            let fields = (0..fields)
+                .map(|_| self.string().map(|s| s.to_string()))
+                .collect::<Result<_, _>>()?;
+
+            module.layouts.push(Rc::new(Layout {
+                id: id as u32,
+                name,
+                kind,
+                fields,
+            }));
+        }
+
+        let functions = self.count(24)?;
+        for _ in 0..functions {
+            let func = self.function()?;
+            module.functions.push(func);
+        }
+
+        let globals = self.count(4)?;
+        for _ in 0..globals {
+            let global = self.u32()?;
+            module.globals.push(global);
+        }
+
+        let main = self.u32()?;
+        module.main = (main != u32::MAX).then_some(main);
+
+        if self.pos != self.bytes.len() {
+            return self.malformed("trailing data after the module");
+        }
+        Ok(module)
+    }
+
+    fn constant(&mut self) -> Result<Value, AkcError> {
+        let start = self.pos;
+
+        match self.u8()? {
+            0 => {
+                let ty = self.int_ty()?;
+                let value = i128::from_le_bytes(self.take(16)?.try_into().unwrap());
+                Ok(Value::Int(value, ty))
+            }
+            1 => {
+                let ty = match self.u8()? {
+                    0 => FloatTy::F32,
+                    1 => FloatTy::F64,
+                    code => {
+                        self.pos -= 1;
+                        return self.malformed(format!("unknown float type {code}"));
+                    }
+                };
+                let bits = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                Ok(Value::Float(f64::from_bits(bits), ty))
+            }
+            2 => match char::from_u32(self.u32()?) {
+                Some(c) => Ok(Value::Char(c)),
+                None => {
+                    self.pos = start;
+                    self.malformed("invalid char constant")
+                }
+            },
+            3 => Ok(Value::Str(self.string()?)),
+            tag => {
+                self.pos = start;
+                self.malformed(format!("unknown constant kind {tag}"))
+            }
+        }
+    }
+
+    fn int_ty(&mut self) -> Result<IntTy, AkcError> {
+        let code = self.u8()?;
+        match IntTy::ALL.get(code as usize) {
+            Some(ty) => Ok(*ty),
+            None => {
+                self.pos -= 1;
+                self.malformed(format!("unknown integer type {code}"))
+            }
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, AkcError> {
+        let name = self.string()?.to_string();
+        let arity = self.u32()?;
+        let slots = self.u32()?;
+
+        let captures = self.count(4)?;
+        let captures = (0..captures)
+            .map(|_| self.u32())
+            .collect::<Result<_, _>>()?;
+
+        let len = self.count(1)?;
+        let code = (0..len).map(|_| self.op()).collect::<Result<_, _>>()?;
+
+        let spans = self.count(16)?;
+        let mut table = Vec::with_capacity(spans);
+        for _ in 0..spans {
+            let offset = self.u32()?;
+            let file = self.u32()? as usize;
+            let lo = self.u32()? as usize;
+            let hi = self.u32()? as usize;
+
+            let Some((id, len)) = self.files.get(file).copied() else {
+                self.pos -= 12;
+                return self.malformed(format!("source {file} is not in the file"));
+            };
+            if lo > hi || hi > len {
+                self.pos -= 8;
+                return self.malformed(format!("span {lo}..{hi} is outside its source"));
+            }
+            table.push((offset, Span::new(id, lo, hi)));
+        }
+
+        Ok(Function {
+            name,
+            arity,
+            slots,
+            captures,
+            code,
+            spans: table,
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, AkcError> {
+        let start = self.pos;
+
+        let op = match self.u8()? {
+            0 => Op::Const(self.u32()?),
+            1 => Op::Unit,
+            2 => Op::True,
+            3 => Op::False,
+            4 => Op::None,
+            5 => Op::Pop,
+            6 => Op::PopN(self.u32()?),
+            7 => Op::Dup,
+            8 => Op::Slide(self.u32()?),
+            9 => Op::Load(self.u32()?),
+            10 => Op::Store(self.u32()?),
+            11 => Op::Define(self.u32()?),
+            12 => Op::Ref(self.u32()?),
+            13 => Op::Global(self.u32()?),
+            14 => Op::Func(self.u32()?),
+            15 => Op::Builtin(BUILTINS[self.code(BUILTINS.len() as u32, "builtin")? as usize]),
+            16 => Op::Ctor(self.u32()?),
+            17 => Op::Closure(self.u32()?),
+            18 => Op::Tuple(self.u32()?),
+            19 => Op::Array(self.u32()?),
+            20 => Op::Struct {
+                layout: self.u32()?,
+                len: self.u32()?,
+            },
+            21 => {
+                let flags = self.code(8, "range kind")?;
+                Op::Range {
+                    start: flags & 1 != 0,
+                    end: flags & 2 != 0,
+                    inclusive: flags & 4 != 0,
+                }
+            }
+            22 => Op::Binary(self.binary_op()?),
+            23 => Op::Neg,
+            24 => Op::Not,
+            25 => {
+                let code = self.code(12, "cast target")?;
+                Op::Cast(match code {
+                    0..8 => Scalar::Int(IntTy::ALL[code as usize]),
+                    8 => Scalar::Float(FloatTy::F32),
+                    9 => Scalar::Float(FloatTy::F64),
+                    10 => Scalar::Bool,
+                    _ => Scalar::Char,
+                })
+            }
+            26 => Op::Deref,
+            27 => Op::Field(self.u32()?),
+            28 => Op::Index,
+            29 => Op::Temp,
+            30 => Op::Project(self.u32()?),
+            31 => Op::ProjectIndex,
+            32 => Op::Set,
+            33 => Op::Update(self.binary_op()?),
+            34 => Op::Bump(self.binary_op()?),
+            35 => Op::Tag(self.u32()?),
+            36 => Op::Jump(self.u32()?),
+            37 => Op::JumpIfFalse(self.u32()?),
+            38 => Op::JumpIfTrue(self.u32()?),
+            39 => Op::Iter(self.u32()?),
+            40 => Op::Next {
+                slot: self.u32()?,
+                exit: self.u32()?,
+            },
+            41 => Op::Call(self.u32()?),
+            42 => Op::Return,
+            43 => Op::NoMatch(self.u32()?),
+            code => {
+                self.pos = start;
+                return self.malformed(format!("unknown opcode {code}"));
+            }
+        };
+
+        Ok(op)
+    }
+
+    fn binary_op(&mut self) -> Result<BinaryOp, AkcError> {
+        let code = self.code(BINARY_OPS.len() as u32, "operator")?;
+        Ok(BINARY_OPS[code as usize])
+    }
+}
+
+/// Checks every index in `module` refers to something that exists, and
+/// that each function keeps its operand stack balanced.
+pub fn validate(module: &Module) -> Result<(), AkcError> {
+    let invalid = |message: String| Err(AkcError::Invalid(message));
+    let functions = module.functions.len();
+    let check = |what: &str, idx: u32, len: usize| {
+        if idx as usize >= len {
+            return Err(format!("{what} {idx} does not exist"));
+        }
+        Ok(())
+    };
+
+    if let Some(main) = module.main {
+        if let Err(message) = check("main function", main, functions) {
+            return invalid(message);
+        }
+        if module.functions[main as usize].arity != 0 {
+            return invalid("the main function takes arguments".to_owned());
+        }
+    }
+    for global in &module.globals {
+        if let Err(message) = check("function", *global, functions) {
+            return invalid(format!("global: {message}"));
+        }
+        let func = &module.functions[*global as usize];
+        if func.arity != 0 {
+            return invalid(format!("global: `{}` takes arguments", func.name));
+        }
+    }
+
+    for func in &module.functions {
+        let slots = func.slots as usize;
+        let code = func.code.len();
+        // `iter` and `next` use a slot and the one after it
+        let pair = |slot: u32| match slot.checked_add(1) {
+            Some(second) => check("slot", second, slots),
+            None => Err(format!("slot {slot} does not exist")),
+        };
+
+        if func.slots > MAX_SLOTS {
+            return invalid(format!(
+                "`{}` has {} slots, more than the {MAX_SLOTS} allowed",
+                func.name, func.slots
+            ));
+        }
+        if func.captures.len() + func.arity as usize > slots {
+            return invalid(format!(
+                "`{}` has fewer slots than captures and parameters",
+                func.name
+            ));
+        }
+        if func.code.last() != Some(&Op::Return) {
+            return invalid(format!("`{}` does not end with a return", func.name));
+        }
+
+        for (offset, op) in func.code.iter().enumerate() {
+            let result = match *op {
+                Op::Const(idx) => check("constant", idx, module.constants.len()),
+                Op::Load(slot)
+                | Op::Store(slot)
+                | Op::Define(slot)
+                | Op::Ref(slot)
+                | Op::NoMatch(slot) => check("slot", slot, slots),
+                Op::Iter(slot) => pair(slot),
+                Op::Next { slot, exit } => pair(slot).and(check("jump target", exit, code)),
+                Op::Global(idx) => check("global", idx, module.globals.len()),
+                Op::Func(idx) => check("function", idx, functions),
+                Op::Closure(idx) => check("function", idx, functions).and_then(|()| {
+                    let inner = &module.functions[idx as usize];
+                    match inner.captures.iter().find(|slot| **slot >= func.slots) {
+                        Some(slot) => Err(format!("captured slot {slot} does not exist")),
+                        None => Ok(()),
+                    }
+                }),
+                Op::Ctor(idx) | Op::Tag(idx) | Op::Struct { layout: idx, .. } => {
+                    check("layout", idx, module.layouts.len())
+                }
+                Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                    check("jump target", target, code)
+                }
+                _ => Ok(()),
+            };
+
+            if let Err(message) = result {
+                return invalid(format!("`{}` at {offset}: {message}", func.name));
+            }
+        }
+
+        if let Err((offset, message)) = check_stack(func) {
+            return invalid(format!("`{}` at {offset}: {message}", func.name));
+        }
+        if func.spans.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return invalid(format!("`{}` has an unsorted span table", func.name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Follows every path through `func`, whose jump targets are known to
+/// exist, to check that no op pops more values than the stack holds and
+/// that paths meeting at an op agree on the stack depth.
+fn check_stack(func: &Function) -> Result<(), (usize, String)> {
+    let mut depths = vec![None; func.code.len()];
+    let mut paths = vec![(0, 0u32)];
+
+    while let Some((offset, depth)) = paths.pop() {
+        match depths[offset] {
+            Some(seen) if seen == depth => continue,
+            Some(seen) => {
+                return Err((
+                    offset,
+                    format!(
+                        "the stack holds {seen} values on one path here and {depth} on another"
+                    ),
+                ));
+            }
+            None => depths[offset] = Some(depth),
+        }
+
+        let op = func.code[offset];
+        let (pops, pushes) = op.effect();
+        let Some(after) = depth.checked_sub(pops).map(|rest| rest + pushes) else {
+            return Err((
+                offset,
+                format!("`{op}` needs {pops} values but the stack holds {depth}"),
+            ));
+        };
+
+        match op {
+            Op::Return | Op::NoMatch(_) => (),
+            Op::Jump(target) => paths.push((target as usize, after)),
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                paths.push((target as usize, after));
+                paths.push((offset + 1, after));
+            }
+            Op::Next { exit, .. } => {
+                paths.push((exit as usize, depth));
+                paths.push((offset + 1, after));
+            }
+            _ => paths.push((offset + 1, after)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bytecode::disassemble, compiler::Compiler, testing, vm::Vm};
+
+    fn compile(src: &str) -> (SourceMap, Module) {
+        let checked = testing::check(src);
+        let mut compiler = Compiler::new(&checked.res, &checked.types);
+        let module = compiler.compile_script(&checked.script);
+        assert!(
+            compiler.bag.is_empty(),
+            "compile errors: {:?}",
+            compiler.bag.0
+        );
+        (checked.map, module)
+    }
+
+    fn run(module: &Module) -> String {
+        let mut out = Vec::new();
+        Vm::new(module, &mut out).run();
+        String::from_utf8(out).unwrap()
+    }
+
+    const SRC: &str = "enum Shape { Circle(f32), Empty }
+         const GREETING: str = \"hi\";
+         fn main() {
+            let shapes = [Shape::Circle(1.5), Shape::Empty];
+            let mut n: u8 = 0;
+            for s in shapes { n += 1; }
+            let show = |x: u8| { println((GREETING, x, 'c', shapes[0])); };
+            show(n);
+         }";
+
+    #[test]
+    fn test_round_trip() {
+        let (map, module) = compile(SRC);
+        let bytes = write(&module, &map);
+        assert_eq!(bytes[..4], MAGIC);
+
+        let mut fresh = SourceMap::fresh();
+        let loaded = read(&bytes, &mut fresh).unwrap();
+
+        assert_eq!(run(&loaded), "(\"hi\", 2, 'c', Shape::Circle(1.5))\n");
+        assert_eq!(run(&loaded), run(&module));
+        assert_eq!(disassemble(&loaded, &fresh), disassemble(&module, &map));
+        assert_eq!(fresh.source.len(), 1);
+    }
+
+    #[test]
+    fn test_reject_bad_headers() {
+        let (map, module) = compile(SRC);
+        let bytes = write(&module, &map);
+        let mut fresh = SourceMap::fresh();
+
+        assert_eq!(read(b"\x7fELF", &mut fresh).unwrap_err(), AkcError::NotAkc);
+
+        let mut newer = bytes.clone();
+        newer[4] = 9;
+        assert_eq!(
+            read(&newer, &mut fresh).unwrap_err(),
+            AkcError::UnsupportedVersion(9)
+        );
+
+        let truncated = &bytes[..bytes.len() - 3];
+        assert!(matches!(
+            read(truncated, &mut fresh),
+            Err(AkcError::Truncated { .. })
+        ));
+
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0xff;
+        assert_eq!(
+            read(&flipped, &mut fresh).unwrap_err(),
+            AkcError::ChecksumMismatch
+        );
+    }
+
+    #[test]
+    fn test_reject_invalid_modules() {
+        let (map, mut module) = compile(SRC);
+        let main = module.main.unwrap() as usize;
+        module.functions[main].code.insert(0, Op::Load(900));
+
+        let bytes = write(&module, &map);
+        let err = read(&bytes, &mut SourceMap::fresh()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid module: `main` at 0: slot 900 does not exist"
+        );
+
+        // an opcode no version has used, with the checksum fixed up
+        let (map, module) = compile("fn main() {}");
+        let mut bytes = write(&module, &map);
+        // from the end: `main`, the globals, one span and its count
+        let ret = bytes.len() - 4 - 4 - 16 - 4 - 1;
+        assert_eq!(bytes[ret], 42, "expected the `return` of `main`");
+        bytes[ret] = 200;
+        let checksum = fnv1a(&bytes[HEADER_LEN..]);
+        bytes[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+        let err = read(&bytes, &mut SourceMap::fresh()).unwrap_err();
+        assert_eq!(
+            err,
+            AkcError::Malformed {
+                offset: ret,
+                message: "unknown opcode 200".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_reject_unbalanced_stacks_and_huge_frames() {
+        let reject = |edit: &dyn Fn(&mut Function)| {
+            let (map, mut module) = compile("fn main() {}");
+            let main = module.main.unwrap() as usize;
+            edit(&mut module.functions[main]);
+            let bytes = write(&module, &map);
+            read(&bytes, &mut SourceMap::fresh())
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            reject(&|main| main.code.insert(0, Op::Pop)),
+            "invalid module: `main` at 0: `pop` needs 1 values but the stack holds 0"
+        );
+        assert_eq!(
+            reject(&|main| main.code =
+                vec![Op::True, Op::JumpIfFalse(3), Op::Unit, Op::Unit, Op::Return]),
+            "invalid module: `main` at 3: the stack holds 1 values on one path here and 0 on another"
+        );
+        assert_eq!(
+            reject(&|main| main.code = vec![Op::Return]),
+            "invalid module: `main` at 0: `return` needs 1 values but the stack holds 0"
+        );
+        assert_eq!(
+            reject(&|main| main.slots = u32::MAX),
+            format!(
+                "invalid module: `main` has 4294967295 slots, more than the {MAX_SLOTS} allowed"
+            )
+        );
+        assert_eq!(
+            reject(&|main| main.code.insert(0, Op::Iter(u32::MAX))),
+            "invalid module: `main` at 0: slot 4294967295 does not exist"
+        );
+    }
+
+    #[test]
+    fn test_corrupt_bytes_never_panic_the_reader() {
+        let (map, module) = compile(SRC);
+        let bytes = write(&module, &map);
+
+        for pos in HEADER_LEN..bytes.len() {
+            for value in [0, 1, 0x7f, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[pos] = value;
+                let checksum = fnv1a(&corrupt[HEADER_LEN..]);
+                corrupt[12..16].copy_from_slice(&checksum.to_le_bytes());
+                let _ = read(&corrupt, &mut SourceMap::fresh());
+            }
+        }
+    }
+}
@@ -0,0 +1,2823 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, Expr, FnItem, Item, Literal, Pattern,
+        PostfixOp, PrefixOp, Script, Section, Spanned, Stmt,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    resolve::{DefId, DefKind, Resolution, path_span},
+    span::Span,
+    ty::{FloatTy, IntTy, Ty},
+    typeck::{TypeckResults, VariantShape, range_elem},
+    value::{self, Value},
+};
+
+/// The runtime every generated program is linked with, GNU assembler text
+/// providing `_start`, printing and runtime errors.
+pub const RUNTIME: &str = include_str!("../runtime/akuru.s");
+
+/// The file name [`RUNTIME`] is usually assembled from.
+pub const RUNTIME_NAME: &str = "akuru.s";
+
+const INT_ARGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const FLOAT_ARGS: usize = 8;
+
+/// Translates a resolved and type checked script to x86-64 assembly in
+/// AT&T syntax, for Linux.
+///
+/// Expressions leave their value in `%rax`, or `%xmm0` for floats, with
+/// narrow integers sign or zero extended to 64 bits. Structs, enums,
+/// tuples, arrays, optionals, strings and slices live in memory and are
+/// handled by address, copied whenever they are stored. Every variable and
+/// temporary has a slot in its function's frame, addressed from `%rbp`.
+/// Calls follow the System V convention, except that values in memory are
+/// passed as a pointer to a copy and returned through a pointer passed
+/// before the other arguments. Items are named like the C backend names
+/// them, `math::add` is `ak_4math3add`.
+pub struct AsmGen<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    pub bag: DiagnosticsBag,
+    text: String,
+    rodata: String,
+    data: String,
+    bss: String,
+    /// where each constant is cached, and the flag set once it is computed
+    consts: HashMap<DefId, (String, String)>,
+    helpers: HashMap<String, String>,
+    /// the label of the `str` of each string literal
+    strings: HashMap<String, String>,
+    /// the label of the bytes of each string
+    bytes: HashMap<String, String>,
+    floats: HashMap<(u64, bool), String>,
+    labels: u32,
+    func: FnState,
+}
+
+/// The function being generated.
+#[derive(Default)]
+struct FnState {
+    code: String,
+    /// the bytes of the frame used by slots
+    frame: u32,
+    /// the bytes at the bottom of the frame for arguments passed on the
+    /// stack
+    outgoing: u32,
+    homes: HashMap<DefId, Home>,
+    loops: Vec<LoopLabels>,
+    /// the slot holding where to put a result returned in memory
+    sret: Option<i32>,
+    ret: Option<Ty>,
+    exit: String,
+}
+
+struct LoopLabels {
+    exit: String,
+    next: String,
+}
+
+/// Where a local variable lives, as an offset from `%rbp`.
+#[derive(Clone, Copy)]
+enum Home {
+    Frame(i32),
+    /// at the address held there, for parameters passed in memory
+    Ptr(i32),
+}
+
+/// How values of a type are represented.
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    /// types with a single value, which take no space
+    Unit,
+    /// integers, `bool`, `char` and references, in `%rax`
+    Int,
+    /// floats, in `%xmm0`
+    Float(FloatTy),
+    /// everything else, by address
+    Memory(u32),
+}
+
+/// The fields of a struct or variant, with their offsets.
+type Layout = Vec<(Option<Symbol>, u32, Ty)>;
+
+/// How an argument is passed.
+enum Arg {
+    /// the value in the slot
+    Value(Class, i32),
+    /// the address of the slot
+    Addr(i32),
+}
+
+impl<'a> AsmGen<'a> {
+    pub fn new(res: &'a Resolution, types: &'a TypeckResults) -> Self {
+        Self {
+            res,
+            types,
+            bag: DiagnosticsBag::new(),
+            text: String::new(),
+            rodata: String::new(),
+            data: String::new(),
+            bss: String::new(),
+            consts: HashMap::new(),
+            helpers: HashMap::new(),
+            strings: HashMap::new(),
+            bytes: HashMap::new(),
+            floats: HashMap::new(),
+            labels: 0,
+            func: FnState::default(),
+        }
+    }
+
+    /// The assembly of the script, defining the `ak_main` that the
+    /// [`RUNTIME`] calls.
+    pub fn generate(&mut self, script: &'a Script) -> String {
+        self.define(&script.root);
+
+        let main = self
+            .res
+            .member(self.res.root, "main".intern())
+            .filter(|def| self.res.def(*def).kind == DefKind::Fn);
+
+        let mut out = String::from("        .text\n        .globl ak_main\nak_main:\n");
+        if let Some(main) = main {
+            _ = writeln!(out, "        subq $8, %rsp");
+            _ = writeln!(out, "        call {}", self.item_name(main));
+            _ = writeln!(out, "        addq $8, %rsp");
+        }
+        out.push_str("        ret\n");
+        out.push_str(&self.text);
+
+        for (section, body) in [
+            ("        .section .rodata\n", &self.rodata),
+            ("        .data\n", &self.data),
+            ("        .bss\n", &self.bss),
+        ] {
+            if !body.is_empty() {
+                out.push('\n');
+                out.push_str(section);
+                out.push_str(body);
+            }
+        }
+        out
+    }
+
+    fn define(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.gen_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => self.gen_const(item),
+                Item::Section(item) => self.define(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn signature(&self, def: DefId) -> (Vec<Ty>, Ty) {
+        match self.types.fns.get(&def) {
+            Some(sig) => (sig.params.clone(), sig.ret.clone()),
+            None => (Vec::new(), Ty::unit()),
+        }
+    }
+
+    fn item_name(&self, def: DefId) -> String {
+        let mut name = "ak_".to_owned();
+        for segment in self.res.path_of(def) {
+            let segment = sanitize(segment.as_str());
+            _ = write!(name, "{}{segment}", segment.len());
+        }
+        name
+    }
+
+    fn begin(&mut self) {
+        self.func = FnState {
+            exit: self.new_label(),
+            ..FnState::default()
+        };
+    }
+
+    /// Finishes the function being generated as `name`, adding the code
+    /// setting up and tearing down its frame.
+    fn finish(&mut self, name: &str) {
+        let state = std::mem::take(&mut self.func);
+        let frame = align(state.frame + state.outgoing, 16);
+
+        _ = writeln!(self.text, "\n{name}:");
+        _ = writeln!(self.text, "        pushq %rbp");
+        _ = writeln!(self.text, "        movq %rsp, %rbp");
+        if frame != 0 {
+            _ = writeln!(self.text, "        subq ${frame}, %rsp");
+        }
+        self.text.push_str(&state.code);
+        _ = writeln!(self.text, "{}:", state.exit);
+        _ = writeln!(self.text, "        leave");
+        _ = writeln!(self.text, "        ret");
+    }
+
+    fn gen_fn(&mut self, item: &'a FnItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let (params, ret) = self.signature(def);
+        self.begin();
+        self.func.ret = Some(ret.clone());
+
+        // parameters are moved out of registers before anything can
+        // overwrite them
+        let (mut ints, mut floats, mut stack) = (0, 0, 0);
+        if let Class::Memory(_) = self.class(&ret) {
+            let slot = self.slot(8, 8);
+            self.asm(format!("movq %rdi, {slot}(%rbp)"));
+            self.func.sret = Some(slot);
+            ints += 1;
+        }
+        for (param, ty) in item.params.iter().zip(&params) {
+            let class = self.class(ty);
+            let home = match class {
+                Class::Unit => Home::Frame(0),
+                Class::Float(float) if floats < FLOAT_ARGS => {
+                    let slot = self.slot(8, 8);
+                    self.asm(format!("{} %xmm{floats}, {slot}(%rbp)", float_mov(float)));
+                    floats += 1;
+                    Home::Frame(slot)
+                }
+                Class::Int | Class::Memory(_) if ints < INT_ARGS.len() => {
+                    let slot = self.slot(8, 8);
+                    self.asm(format!("movq {}, {slot}(%rbp)", INT_ARGS[ints]));
+                    ints += 1;
+                    match class {
+                        Class::Memory(_) => Home::Ptr(slot),
+                        _ => Home::Frame(slot),
+                    }
+                }
+                _ => {
+                    let slot = 16 + 8 * stack;
+                    stack += 1;
+                    match class {
+                        Class::Memory(_) => Home::Ptr(slot),
+                        _ => Home::Frame(slot),
+                    }
+                }
+            };
+            if let Some(def) = self.res.lookup(param.node.name.span) {
+                self.func.homes.insert(def, home);
+            }
+        }
+
+        let ty = self.block(&item.body);
+        self.coerce(&ty, &ret);
+        if ty != Ty::Never {
+            self.copy_to_sret(&ret);
+        }
+        let name = self.item_name(def);
+        self.finish(&name);
+    }
+
+    /// Copies the value in `%rax` to where the caller wants the result,
+    /// leaving that address in `%rax`.
+    fn copy_to_sret(&mut self, ty: &Ty) {
+        let (Some(sret), Class::Memory(size)) = (self.func.sret, self.class(ty)) else {
+            return;
+        };
+        self.asm(format!("movq {sret}(%rbp), %rdi"));
+        self.copy(size);
+        self.asm(format!("movq {sret}(%rbp), %rax"));
+    }
+
+    /// Constants are computed on first use, like the interpreter does.
+    fn gen_const(&mut self, item: &'a ConstItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let Some(ty) = self.types.consts.get(&def) else {
+            return;
+        };
+        let (value, done) = self.const_cache(def, ty);
+
+        self.begin();
+        let ready = self.new_label();
+        self.asm(format!("cmpb $0, {done}(%rip)"));
+        self.asm(format!("jne {ready}"));
+        self.expr(&item.value);
+        self.coerce(&self.ty(item.value.span), ty);
+        self.asm(format!("leaq {value}(%rip), %rdi"));
+        self.store(ty);
+        self.asm(format!("movb $1, {done}(%rip)"));
+        self.label(&ready);
+        self.asm(format!("leaq {value}(%rip), %rax"));
+        self.load(ty);
+
+        let name = self.item_name(def);
+        self.finish(&name);
+    }
+
+    fn const_cache(&mut self, def: DefId, ty: &Ty) -> (String, String) {
+        if let Some(cache) = self.consts.get(&def) {
+            return cache.clone();
+        }
+        let (size, align) = self.size_align(ty);
+        let (value, done) = (self.new_label(), self.new_label());
+        _ = writeln!(self.bss, "        .balign {}", align.max(1));
+        _ = writeln!(self.bss, "{value}: .zero {}", size.max(1));
+        _ = writeln!(self.bss, "{done}: .zero 1");
+        self.consts.insert(def, (value.clone(), done.clone()));
+        (value, done)
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str) {
+        self.bag.push(
+            Diagnostic::error(format!("{what} are not supported by the x86-64 backend"))
+                .with_label(span.primary("used here")),
+        );
+        self.asm("xorl %eax, %eax");
+    }
+
+    // ---------------------------------------------------------- emitting
+
+    fn asm(&mut self, line: impl AsRef<str>) {
+        _ = writeln!(self.func.code, "        {}", line.as_ref());
+    }
+
+    fn label(&mut self, label: &str) {
+        _ = writeln!(self.func.code, "{label}:");
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    /// Reserves space in the frame, returning its offset from `%rbp`.
+    fn slot(&mut self, size: u32, align_to: u32) -> i32 {
+        self.func.frame = align(self.func.frame + size, align_to.max(1));
+        -(self.func.frame as i32)
+    }
+
+    /// Runs `f`, returning the code it generates instead of emitting it.
+    fn capture(&mut self, f: impl FnOnce(&mut Self)) -> String {
+        let code = std::mem::take(&mut self.func.code);
+        f(self);
+        std::mem::replace(&mut self.func.code, code)
+    }
+
+    /// The label of the bytes of `text`.
+    fn bytes(&mut self, text: &str) -> String {
+        if let Some(label) = self.bytes.get(text) {
+            return label.clone();
+        }
+        let label = self.new_label();
+        _ = write!(self.rodata, "{label}:");
+        for (idx, chunk) in text.as_bytes().chunks(16).enumerate() {
+            let chunk: Vec<_> = chunk.iter().map(|b| b.to_string()).collect();
+            let sep = if idx == 0 { " " } else { "        " };
+            _ = writeln!(self.rodata, "{sep}.byte {}", chunk.join(", "));
+        }
+        if text.is_empty() {
+            self.rodata.push('\n');
+        }
+        self.bytes.insert(text.to_owned(), label.clone());
+        label
+    }
+
+    /// The label of a `str` holding `text`.
+    fn string(&mut self, text: &str) -> String {
+        if let Some(label) = self.strings.get(text) {
+            return label.clone();
+        }
+        let bytes = self.bytes(text);
+        let label = self.new_label();
+        _ = writeln!(self.data, "        .balign 8");
+        _ = writeln!(self.data, "{label}: .quad {bytes}, {}", text.len());
+        self.strings.insert(text.to_owned(), label.clone());
+        label
+    }
+
+    fn float(&mut self, value: f64, float: FloatTy) -> String {
+        let key = match float {
+            FloatTy::F32 => ((value as f32).to_bits() as u64, true),
+            FloatTy::F64 => (value.to_bits(), false),
+        };
+        if let Some(label) = self.floats.get(&key) {
+            return label.clone();
+        }
+        let label = self.new_label();
+        _ = writeln!(self.rodata, "        .balign 8");
+        match key {
+            (bits, true) => _ = writeln!(self.rodata, "{label}: .long {bits:#x}"),
+            (bits, false) => _ = writeln!(self.rodata, "{label}: .quad {bits:#x}"),
+        }
+        self.floats.insert(key, label.clone());
+        label
+    }
+
+    fn text_out(&mut self, text: &str) {
+        let bytes = self.bytes(text);
+        self.asm(format!("leaq {bytes}(%rip), %rdi"));
+        self.asm(format!("movl ${}, %esi", text.len()));
+        self.asm("call ak_write");
+    }
+
+    fn panic(&mut self, message: &str) {
+        let bytes = self.bytes(message);
+        self.asm(format!("leaq {bytes}(%rip), %rdi"));
+        self.asm(format!("movl ${}, %esi", message.len()));
+        self.asm("call ak_panic");
+    }
+
+    /// Panics with `message` unless the condition of `jump` holds.
+    fn check(&mut self, jump: &str, message: &str) {
+        let ok = self.new_label();
+        self.asm(format!("{jump} {ok}"));
+        self.panic(message);
+        self.label(&ok);
+    }
+
+    // ------------------------------------------------------------- types
+
+    fn ty(&self, span: Span) -> Ty {
+        self.types
+            .exprs
+            .get(&span)
+            .cloned()
+            .unwrap_or_else(Ty::unit)
+    }
+
+    fn class(&self, ty: &Ty) -> Class {
+        match ty {
+            Ty::Int(_) | Ty::Bool | Ty::Char | Ty::Fn { .. } => Class::Int,
+            Ty::Float(float) => Class::Float(*float),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => Class::Int,
+            Ty::Tuple(elems) if elems.is_empty() => Class::Unit,
+            Ty::Str
+            | Ty::Ref { .. }
+            | Ty::Slice(_)
+            | Ty::Tuple(_)
+            | Ty::Array(..)
+            | Ty::Optional(_)
+            | Ty::Path(_) => Class::Memory(self.size_align(ty).0),
+            Ty::Generic { .. } | Ty::Infer | Ty::Var(_) | Ty::Never => Class::Unit,
+        }
+    }
+
+    fn size_align(&self, ty: &Ty) -> (u32, u32) {
+        match ty {
+            Ty::Int(int) => (int.bits() / 8, int.bits() / 8),
+            Ty::Float(FloatTy::F32) => (4, 4),
+            Ty::Float(FloatTy::F64) => (8, 8),
+            Ty::Bool => (1, 1),
+            Ty::Char => (4, 4),
+            Ty::Fn { .. } => (8, 8),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => (8, 8),
+            Ty::Str | Ty::Ref { .. } | Ty::Slice(_) => (16, 8),
+            Ty::Tuple(elems) => {
+                let (_, size, align) = self.fields_layout(elems.iter());
+                (size, align)
+            }
+            Ty::Array(elem, len) => {
+                let (size, align) = self.size_align(elem);
+                (size * *len as u32, align)
+            }
+            Ty::Optional(inner) => {
+                let (size, align) = self.size_align(inner);
+                (self::align(align.max(1) + size, align.max(1)), align.max(1))
+            }
+            Ty::Path(path) => match self.res.def_by_path(path) {
+                Some(def) if self.res.def(def).kind == DefKind::Enum => {
+                    let (_, size, align) = self.enum_layout(def);
+                    (size, align)
+                }
+                Some(def) => {
+                    let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+                    let (_, size, align) = self.fields_layout(fields.iter().map(|(_, ty)| ty));
+                    (size, align)
+                }
+                None => (0, 1),
+            },
+            Ty::Generic { .. } | Ty::Infer | Ty::Var(_) | Ty::Never => (0, 1),
+        }
+    }
+
+    /// The offset of each field laid out in order, and the size and
+    /// alignment of the whole.
+    fn fields_layout<'t>(&self, tys: impl Iterator<Item = &'t Ty>) -> (Vec<u32>, u32, u32) {
+        let mut offsets = Vec::new();
+        let mut size = 0;
+        let mut max_align = 1;
+        for ty in tys {
+            let (field_size, field_align) = self.size_align(ty);
+            let offset = align(size, field_align);
+            offsets.push(offset);
+            size = offset + field_size;
+            max_align = max_align.max(field_align);
+        }
+        (offsets, align(size, max_align), max_align)
+    }
+
+    /// The offset of the fields after the tag, and the size and alignment
+    /// of an enum.
+    fn enum_layout(&self, def: DefId) -> (u32, u32, u32) {
+        let mut max_align = 4;
+        let mut max_size = 0;
+        for variant in self.types.enums.get(&def).cloned().unwrap_or_default() {
+            let tys: Vec<_> = self
+                .variant_fields(variant)
+                .into_iter()
+                .map(|(_, ty)| ty)
+                .collect();
+            let (_, size, align) = self.fields_layout(tys.iter());
+            max_align = max_align.max(align);
+            max_size = max_size.max(size);
+        }
+        let payload = align(4, max_align);
+        (payload, align(payload + max_size, max_align), max_align)
+    }
+
+    fn variant_fields(&self, def: DefId) -> Vec<(Option<Symbol>, Ty)> {
+        match self.types.variants.get(&def) {
+            Some(VariantShape::Tuple(tys)) => tys.iter().map(|ty| (None, ty.clone())).collect(),
+            Some(VariantShape::Struct(fields)) => fields
+                .iter()
+                .map(|(name, ty)| (Some(*name), ty.clone()))
+                .collect(),
+            Some(VariantShape::Unit) | None => Vec::new(),
+        }
+    }
+
+    /// The enum of a variant and its tag.
+    fn variant_tag(&self, def: DefId) -> Option<(DefId, u32)> {
+        let parent = self.res.def(def).parent?;
+        let tag = self
+            .types
+            .enums
+            .get(&parent)?
+            .iter()
+            .position(|v| *v == def)?;
+        Some((parent, tag as u32))
+    }
+
+    /// The name, offset from the start of the enum and type of each field
+    /// of a variant.
+    fn variant_layout(&self, def: DefId) -> Layout {
+        let Some((parent, _)) = self.variant_tag(def) else {
+            return Vec::new();
+        };
+        let (payload, ..) = self.enum_layout(parent);
+        let fields = self.variant_fields(def);
+        let (offsets, ..) = self.fields_layout(fields.iter().map(|(_, ty)| ty));
+        fields
+            .into_iter()
+            .zip(offsets)
+            .map(|((name, ty), offset)| (name, payload + offset, ty))
+            .collect()
+    }
+
+    fn struct_layout(&self, def: DefId) -> Layout {
+        let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+        let (offsets, ..) = self.fields_layout(fields.iter().map(|(_, ty)| ty));
+        fields
+            .into_iter()
+            .zip(offsets)
+            .map(|((name, ty), offset)| (Some(name), offset, ty))
+            .collect()
+    }
+
+    /// The fields of a struct or variant, and the tag of a variant.
+    fn adt_layout(&self, def: DefId) -> (Option<u32>, Layout) {
+        match self.variant_tag(def) {
+            Some((_, tag)) => (Some(tag), self.variant_layout(def)),
+            None => (None, self.struct_layout(def)),
+        }
+    }
+
+    // ---------------------------------------------------- moving values
+
+    /// Replaces the address in `%rax` with the value there.
+    fn load(&mut self, ty: &Ty) {
+        let line = match (ty, self.class(ty)) {
+            (_, Class::Unit | Class::Memory(_)) => return,
+            (_, Class::Float(FloatTy::F32)) => "movss (%rax), %xmm0",
+            (_, Class::Float(FloatTy::F64)) => "movsd (%rax), %xmm0",
+            (Ty::Int(int), _) => match (int.bits(), int.is_signed()) {
+                (8, false) => "movzbl (%rax), %eax",
+                (8, true) => "movsbq (%rax), %rax",
+                (16, false) => "movzwl (%rax), %eax",
+                (16, true) => "movswq (%rax), %rax",
+                (32, false) => "movl (%rax), %eax",
+                (32, true) => "movslq (%rax), %rax",
+                _ => "movq (%rax), %rax",
+            },
+            (Ty::Bool, _) => "movzbl (%rax), %eax",
+            (Ty::Char, _) => "movl (%rax), %eax",
+            _ => "movq (%rax), %rax",
+        };
+        self.asm(line);
+    }
+
+    /// Stores the value in `%rax` or `%xmm0` at the address in `%rdi`.
+    fn store(&mut self, ty: &Ty) {
+        let line = match self.class(ty) {
+            Class::Unit => return,
+            Class::Memory(size) => return self.copy(size),
+            Class::Float(FloatTy::F32) => "movss %xmm0, (%rdi)",
+            Class::Float(FloatTy::F64) => "movsd %xmm0, (%rdi)",
+            Class::Int => match self.size_align(ty).0 {
+                1 => "movb %al, (%rdi)",
+                2 => "movw %ax, (%rdi)",
+                4 => "movl %eax, (%rdi)",
+                _ => "movq %rax, (%rdi)",
+            },
+        };
+        self.asm(line);
+    }
+
+    /// Copies `size` bytes from the address in `%rax` to the one in `%rdi`.
+    fn copy(&mut self, size: u32) {
+        self.asm("movq %rax, %rsi");
+        self.asm(format!("movl ${size}, %ecx"));
+        self.asm("rep movsb");
+    }
+
+    /// Saves the value in `%rax` or `%xmm0` to a new slot, returning it.
+    fn save(&mut self, ty: &Ty) -> i32 {
+        let class = self.class(ty);
+        if class == Class::Unit {
+            return 0;
+        }
+        let slot = self.slot(8, 8);
+        match class {
+            Class::Float(float) => self.asm(format!("{} %xmm0, {slot}(%rbp)", float_mov(float))),
+            _ => self.asm(format!("movq %rax, {slot}(%rbp)")),
+        }
+        slot
+    }
+
+    /// Loads a value saved by [`Self::save`] into `%rax` or `%xmm0`, or
+    /// into `%rcx` or `%xmm1` for the second operand of an operator.
+    fn restore(&mut self, ty: &Ty, slot: i32, second: bool) {
+        match self.class(ty) {
+            Class::Unit => (),
+            Class::Float(float) => {
+                let reg = if second { "%xmm1" } else { "%xmm0" };
+                self.asm(format!("{} {slot}(%rbp), {reg}", float_mov(float)));
+            }
+            _ => {
+                let reg = if second { "%rcx" } else { "%rax" };
+                self.asm(format!("movq {slot}(%rbp), {reg}"));
+            }
+        }
+    }
+
+    /// Moves the value in `%rax` or `%xmm0` to `%rcx` or `%xmm1`.
+    fn move_to_second(&mut self, ty: &Ty) {
+        match self.class(ty) {
+            Class::Unit => (),
+            Class::Float(_) => self.asm("movaps %xmm0, %xmm1"),
+            _ => self.asm("movq %rax, %rcx"),
+        }
+    }
+
+    /// Stores the value in `%rax` or `%xmm0` in a new slot holding the
+    /// whole value, returning it, so its address can be taken.
+    fn frame_slot(&mut self, ty: &Ty) -> i32 {
+        match self.class(ty) {
+            Class::Memory(size) => {
+                let slot = self.slot(size, self.size_align(ty).1);
+                self.asm(format!("leaq {slot}(%rbp), %rdi"));
+                self.copy(size);
+                slot
+            }
+            _ => self.save(ty),
+        }
+    }
+
+    /// Puts some value of `ty` where values of it go, for code that is
+    /// never reached.
+    fn zero(&mut self, ty: &Ty) {
+        match self.class(ty) {
+            Class::Unit => (),
+            Class::Float(_) => self.asm("xorps %xmm0, %xmm0"),
+            _ => self.asm("xorl %eax, %eax"),
+        }
+    }
+
+    /// Applies the implicit conversions type checking allows to the value
+    /// in `%rax` or `%xmm0`: `T` to `T?` and `&[T; N]` to `&[T]`.
+    fn coerce(&mut self, from: &Ty, to: &Ty) {
+        if from == to || *from == Ty::Never {
+            return;
+        }
+
+        match (from, to) {
+            // the value of a block ending in a `return`
+            (from, to) if from.is_unit() => self.zero(to),
+            (Ty::Optional(_), Ty::Optional(_)) => (),
+            (_, Ty::Optional(inner)) => {
+                self.coerce(from, inner);
+                let value = self.save(inner);
+                let (size, align) = self.size_align(to);
+                let slot = self.slot(size, align);
+                let offset = slot + self.size_align(inner).1.max(1) as i32;
+                self.asm(format!("movb $1, {slot}(%rbp)"));
+                self.restore(inner, value, false);
+                self.asm(format!("leaq {offset}(%rbp), %rdi"));
+                self.store(inner);
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            (Ty::Ref { inner: x, .. }, Ty::Ref { inner: y, .. }) => {
+                if let (Ty::Array(_, len), Ty::Slice(_)) = (&**x, &**y) {
+                    let slot = self.slot(16, 8);
+                    self.asm(format!("movq %rax, {slot}(%rbp)"));
+                    self.asm(format!("movq ${len}, {}(%rbp)", slot + 8));
+                    self.asm(format!("leaq {slot}(%rbp), %rax"));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Follows the references in `%rax`, except `&[T]` which are slices.
+    fn deref_all(&mut self, ty: &Ty) -> Ty {
+        let mut ty = ty.clone();
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            let inner = (**inner).clone();
+            self.load(&inner);
+            ty = inner;
+        }
+        ty
+    }
+
+    /// Truncates `%rax` to `int` and extends it back to 64 bits, into
+    /// `dst`.
+    fn normalize(&mut self, int: IntTy, dst: &str) {
+        let (byte, word, dword) = match dst {
+            "%rdx" => ("%edx", "%edx", "%edx"),
+            _ => ("%eax", "%eax", "%eax"),
+        };
+        let line = match (int.bits(), int.is_signed()) {
+            (8, true) => format!("movsbq %al, {dst}"),
+            (8, false) => format!("movzbl %al, {byte}"),
+            (16, true) => format!("movswq %ax, {dst}"),
+            (16, false) => format!("movzwl %ax, {word}"),
+            (32, true) => format!("movslq %eax, {dst}"),
+            (32, false) => format!("movl %eax, {dword}"),
+            _ if dst == "%rax" => return,
+            _ => format!("movq %rax, {dst}"),
+        };
+        self.asm(line);
+    }
+
+    /// Panics with `message` unless `%rax` holds a value of `int`.
+    fn check_range(&mut self, int: IntTy, message: &str) {
+        self.normalize(int, "%rdx");
+        self.asm("cmpq %rdx, %rax");
+        self.check("je", message);
+    }
+
+    // ------------------------------------------------------- expressions
+
+    /// Generates the statements of a block, leaving the value of its tail
+    /// in `%rax` or `%xmm0` and returning its type.
+    fn block(&mut self, block: &'a BlockExpr) -> Ty {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { name, value, .. } => {
+                    let Some(def) = self.res.lookup(name.span) else {
+                        continue;
+                    };
+                    let ty = self
+                        .types
+                        .locals
+                        .get(&def)
+                        .cloned()
+                        .unwrap_or_else(Ty::unit);
+                    let (size, align) = self.size_align(&ty);
+                    let slot = self.slot(size.max(1), align);
+
+                    match value {
+                        Some(value) => {
+                            self.expr(value);
+                            self.coerce(&self.ty(value.span), &ty);
+                        }
+                        None => self.zero(&ty),
+                    }
+                    if !matches!(self.class(&ty), Class::Memory(_)) || value.is_some() {
+                        self.asm(format!("leaq {slot}(%rbp), %rdi"));
+                        self.store(&ty);
+                    }
+                    self.func.homes.insert(def, Home::Frame(slot));
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => self.expr(expr),
+            }
+        }
+
+        match &block.expr {
+            Some(tail) => {
+                self.expr_at(&tail.node, tail.span);
+                self.ty(tail.span)
+            }
+            None => Ty::unit(),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Spanned<Expr>) {
+        self.expr_at(&expr.node, expr.span);
+    }
+
+    fn expr_boxed(&mut self, expr: &'a Spanned<Box<Expr>>) {
+        self.expr_at(&expr.node, expr.span);
+    }
+
+    /// Generates the code computing `expr`, which leaves its value in
+    /// `%rax` or `%xmm0`.
+    fn expr_at(&mut self, expr: &'a Expr, span: Span) {
+        let ty = self.ty(span);
+
+        match expr {
+            Expr::Literal(literal) => self.literal(literal, &ty),
+            Expr::Variable(_) | Expr::Path(_) => self.name(span, &ty),
+            Expr::Tuple(elems) => {
+                let Ty::Tuple(tys) = &ty else {
+                    return;
+                };
+                if elems.is_empty() {
+                    return;
+                }
+                let (offsets, size, align) = self.fields_layout(tys.iter());
+                let slot = self.slot(size, align);
+                for ((elem, elem_ty), offset) in elems.iter().zip(tys).zip(offsets) {
+                    self.expr(elem);
+                    self.coerce(&self.ty(elem.span), elem_ty);
+                    self.asm(format!("leaq {}(%rbp), %rdi", slot + offset as i32));
+                    self.store(elem_ty);
+                }
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            Expr::Array(elems) => {
+                let Ty::Array(elem_ty, _) = &ty else {
+                    return;
+                };
+                let (size, align) = self.size_align(&ty);
+                let slot = self.slot(size.max(1), align);
+                let elem_size = self.size_align(elem_ty).0;
+                for (idx, elem) in elems.iter().enumerate() {
+                    self.expr(elem);
+                    self.coerce(&self.ty(elem.span), elem_ty);
+                    let offset = slot + (idx as u32 * elem_size) as i32;
+                    self.asm(format!("leaq {offset}(%rbp), %rdi"));
+                    self.store(elem_ty);
+                }
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            Expr::Range { .. } => self.unsupported(span, "ranges outside `for` loops and slicing"),
+            Expr::Binary(lhs, op, rhs) if op.node.is_logical() => {
+                let end = self.new_label();
+                self.expr_boxed(lhs);
+                self.asm("testb %al, %al");
+                match op.node {
+                    BinaryOp::And => self.asm(format!("je {end}")),
+                    _ => self.asm(format!("jne {end}")),
+                }
+                self.expr_boxed(rhs);
+                self.label(&end);
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                self.expr_boxed(lhs);
+                let lhs_ty = self.deref_all(&self.ty(lhs.span));
+                let saved = self.save(&lhs_ty);
+                self.expr_boxed(rhs);
+                let rhs_ty = self.deref_all(&self.ty(rhs.span));
+                self.move_to_second(&rhs_ty);
+                self.restore(&lhs_ty, saved, false);
+                self.binary(op.node, &lhs_ty);
+            }
+            Expr::Prefix(op, operand) => self.prefix(op.node, operand),
+            Expr::Postfix(operand, op) => {
+                let operand_ty = self.ty(operand.span);
+                let Ty::Int(int) = operand_ty else {
+                    return;
+                };
+                self.place(operand);
+                let addr = self.save(&operand_ty);
+                self.load(&operand_ty);
+                let old = self.save(&operand_ty);
+                self.asm("movl $1, %ecx");
+                let op = match op.node {
+                    PostfixOp::Inc => BinaryOp::Add,
+                    PostfixOp::Dec => BinaryOp::Sub,
+                };
+                self.arith(op, int);
+                self.asm(format!("movq {addr}(%rbp), %rdi"));
+                self.store(&operand_ty);
+                self.asm(format!("movq {old}(%rbp), %rax"));
+            }
+            Expr::Call(callee, args) => self.call(callee, args, &ty),
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let Some(def) = self.res.lookup(method.span) else {
+                    return self.zero(&ty);
+                };
+                let (params, ret) = self.signature(def);
+
+                // borrow the receiver when the function takes a reference
+                let receiver_ty = self.ty(receiver.span);
+                let mut passed = Vec::new();
+                match params.first() {
+                    Some(Ty::Ref { .. }) if !matches!(receiver_ty, Ty::Ref { .. }) => {
+                        self.place(receiver);
+                        passed.push(Arg::Value(Class::Int, self.save(&receiver_ty)));
+                    }
+                    Some(param) => {
+                        self.expr_boxed(receiver);
+                        self.coerce(&receiver_ty, param);
+                        passed.push(self.arg(param));
+                    }
+                    None => self.expr_boxed(receiver),
+                }
+                for (arg, param) in args.iter().zip(params.iter().skip(1)) {
+                    self.expr(arg);
+                    self.coerce(&self.ty(arg.span), param);
+                    passed.push(self.arg(param));
+                }
+                let name = self.item_name(def);
+                self.call_fn(&name, passed, &ret);
+            }
+            Expr::Field(base, name) => {
+                let field_ty = self.field_addr(base, name.node);
+                self.load(&field_ty);
+            }
+            Expr::Index(_, index) if matches!(&*index.node, Expr::Range { .. }) => {
+                self.slice(expr, &ty)
+            }
+            Expr::Index(base, index) => {
+                let elem = self.index_addr(base, index);
+                self.load(&elem);
+            }
+            Expr::Cast(operand, _) => {
+                self.expr_boxed(operand);
+                let from = self.deref_all(&self.ty(operand.span));
+                self.cast(&from, &ty);
+            }
+            Expr::Closure { .. } => self.unsupported(span, "closures"),
+            Expr::If { cond, then, else_ } => {
+                let (other, end) = (self.new_label(), self.new_label());
+                self.expr_boxed(cond);
+                self.asm("testb %al, %al");
+                self.asm(format!("je {other}"));
+                self.expr_boxed(then);
+                self.coerce(&self.ty(then.span), &ty);
+                self.asm(format!("jmp {end}"));
+                self.label(&other);
+                if let Some(else_) = else_ {
+                    self.expr_boxed(else_);
+                    self.coerce(&self.ty(else_.span), &ty);
+                }
+                self.label(&end);
+            }
+            Expr::Loop(body) => {
+                let (next, exit) = (self.new_label(), self.new_label());
+                self.label(&next);
+                self.func.loops.push(LoopLabels {
+                    exit: exit.clone(),
+                    next: next.clone(),
+                });
+                self.block(&body.node);
+                self.func.loops.pop();
+                self.asm(format!("jmp {next}"));
+                self.label(&exit);
+            }
+            Expr::While { cond, body } => {
+                let (next, exit) = (self.new_label(), self.new_label());
+                self.label(&next);
+                self.expr_boxed(cond);
+                self.asm("testb %al, %al");
+                self.asm(format!("je {exit}"));
+                self.func.loops.push(LoopLabels {
+                    exit: exit.clone(),
+                    next: next.clone(),
+                });
+                self.block(body);
+                self.func.loops.pop();
+                self.asm(format!("jmp {next}"));
+                self.label(&exit);
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => self.for_loop(pattern, iter, body),
+            Expr::Break(value) => {
+                if let Some(value) = value {
+                    self.expr_boxed(value);
+                }
+                if let Some(labels) = self.func.loops.last() {
+                    let exit = labels.exit.clone();
+                    self.asm(format!("jmp {exit}"));
+                }
+            }
+            Expr::Continue => {
+                if let Some(labels) = self.func.loops.last() {
+                    let next = labels.next.clone();
+                    self.asm(format!("jmp {next}"));
+                }
+            }
+            Expr::Return(value) => {
+                let ret = self.func.ret.clone().unwrap_or_else(Ty::unit);
+                let value_ty = match value {
+                    Some(value) => {
+                        self.expr_boxed(value);
+                        self.ty(value.span)
+                    }
+                    None => Ty::unit(),
+                };
+                if value_ty == Ty::Never {
+                    return;
+                }
+                self.coerce(&value_ty, &ret);
+                self.copy_to_sret(&ret);
+                let exit = self.func.exit.clone();
+                self.asm(format!("jmp {exit}"));
+            }
+            Expr::Block(block) => {
+                let tail = self.block(&block.node);
+                self.coerce(&tail, &ty);
+            }
+            Expr::Assign { target, value } => {
+                let target_ty = self.ty(target.span);
+                self.expr_boxed(value);
+                self.coerce(&self.ty(value.span), &target_ty);
+                let saved = self.save(&target_ty);
+                self.place(target);
+                self.asm("movq %rax, %rdi");
+                self.restore(&target_ty, saved, false);
+                self.store(&target_ty);
+            }
+            Expr::AssignEq { op, target, value } => {
+                let target_ty = self.ty(target.span);
+                self.expr_boxed(value);
+                let value_ty = self.deref_all(&self.ty(value.span));
+                let saved = self.save(&value_ty);
+                self.place(target);
+                let addr = self.save(&Ty::Bool);
+                self.load(&target_ty);
+                self.restore(&value_ty, saved, true);
+                self.binary(op.node, &target_ty);
+                self.asm(format!("movq {addr}(%rbp), %rdi"));
+                self.store(&target_ty);
+            }
+            Expr::StructInit { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return self.zero(&ty);
+                };
+                let (size, align) = self.size_align(&ty);
+                let slot = self.slot(size.max(1), align);
+                let (tag, layout) = self.adt_layout(def);
+                if let Some(tag) = tag {
+                    self.asm(format!("movl ${tag}, {slot}(%rbp)"));
+                }
+
+                for field in fields {
+                    let name = field.node.name.node;
+                    let Some((_, offset, field_ty)) =
+                        layout.iter().find(|(n, ..)| *n == Some(name)).cloned()
+                    else {
+                        continue;
+                    };
+                    self.expr(&field.node.value);
+                    self.coerce(&self.ty(field.node.value.span), &field_ty);
+                    self.asm(format!("leaq {}(%rbp), %rdi", slot + offset as i32));
+                    self.store(&field_ty);
+                }
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            Expr::Match { scrutinee, arms } => {
+                let scrutinee_ty = self.ty(scrutinee.span);
+                self.expr_boxed(scrutinee);
+                let slot = self.frame_slot(&scrutinee_ty);
+                let addr = format!("leaq {slot}(%rbp), %rax");
+                let end = self.new_label();
+
+                let mut exhaustive = false;
+                for arm in arms {
+                    let mut conds = Vec::new();
+                    let mut binds = String::new();
+                    self.pattern(
+                        &arm.pattern,
+                        vec![addr.clone()],
+                        &scrutinee_ty,
+                        &mut conds,
+                        &mut binds,
+                    );
+
+                    let next = self.new_label();
+                    let refutable = !conds.is_empty();
+                    for cond in conds {
+                        self.func.code.push_str(&cond);
+                        self.asm("testb %al, %al");
+                        self.asm(format!("je {next}"));
+                    }
+                    self.func.code.push_str(&binds);
+                    self.expr(&arm.body);
+                    self.coerce(&self.ty(arm.body.span), &ty);
+                    self.asm(format!("jmp {end}"));
+                    self.label(&next);
+
+                    if !refutable {
+                        exhaustive = true;
+                        break;
+                    }
+                }
+
+                if !exhaustive {
+                    self.panic("no `match` arm matches the value");
+                }
+                self.label(&end);
+            }
+            Expr::Error(_) => self.zero(&ty),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal, ty: &Ty) {
+        match value::literal(literal, Some(ty)) {
+            Value::Int(value, int) => self.int(value, int),
+            Value::Float(value, float) => {
+                let label = self.float(value, float);
+                self.asm(format!("{} {label}(%rip), %xmm0", float_mov(float)));
+            }
+            Value::Bool(b) => self.asm(format!("movl ${}, %eax", b as u32)),
+            Value::Char(c) => self.asm(format!("movl ${}, %eax", c as u32)),
+            Value::Str(s) => {
+                let label = self.string(&s);
+                self.asm(format!("leaq {label}(%rip), %rax"));
+            }
+            Value::None => {
+                let (size, align) = self.size_align(ty);
+                let slot = self.slot(size.max(1), align);
+                self.asm(format!("movb $0, {slot}(%rbp)"));
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            _ => self.zero(ty),
+        }
+    }
+
+    /// Loads an integer of type `int` into `%rax`.
+    fn int(&mut self, value: i128, int: IntTy) {
+        // the 64 bits of the value, extended the way `int` extends
+        let value = match int.is_signed() {
+            true => value as i64,
+            false => value as u64 as i64,
+        };
+        if i32::try_from(value).is_ok() {
+            self.asm(format!("movq ${value}, %rax"));
+        } else {
+            self.asm(format!("movabsq ${value}, %rax"));
+        }
+    }
+
+    fn name(&mut self, span: Span, ty: &Ty) {
+        let Some(def) = self.res.lookup(span) else {
+            return self.zero(ty);
+        };
+
+        match self.res.def(def).kind {
+            DefKind::Local { .. } => {
+                self.home_addr(def);
+                self.load(ty);
+            }
+            DefKind::Const => {
+                let name = self.item_name(def);
+                self.asm(format!("call {name}"));
+            }
+            DefKind::Variant
+                if matches!(self.types.variants.get(&def), Some(VariantShape::Unit)) =>
+            {
+                let (size, align) = self.size_align(ty);
+                let slot = self.slot(size, align);
+                if let Some((_, tag)) = self.variant_tag(def) {
+                    self.asm(format!("movl ${tag}, {slot}(%rbp)"));
+                }
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            DefKind::Fn | DefKind::Variant => self.unsupported(span, "functions used as values"),
+            DefKind::Builtin => self.unsupported(span, "builtin functions used as values"),
+            DefKind::Section | DefKind::Struct | DefKind::Enum => self.zero(ty),
+        }
+    }
+
+    fn home_addr(&mut self, def: DefId) {
+        match self.func.homes.get(&def).copied() {
+            Some(Home::Frame(slot)) => self.asm(format!("leaq {slot}(%rbp), %rax")),
+            Some(Home::Ptr(slot)) => self.asm(format!("movq {slot}(%rbp), %rax")),
+            None => self.asm("xorl %eax, %eax"),
+        }
+    }
+
+    /// Applies `op` to the operands in `%rax` and `%rcx`, or `%xmm0` and
+    /// `%xmm1`, both of type `ty`.
+    fn binary(&mut self, op: BinaryOp, ty: &Ty) {
+        use BinaryOp::*;
+
+        if op.is_comparison() {
+            match (op, ty) {
+                (Eq, _) => self.eq(ty),
+                (Ne, _) => {
+                    self.eq(ty);
+                    self.asm("xorl $1, %eax");
+                }
+                (_, Ty::Str) => {
+                    self.asm("movq %rax, %rdi");
+                    self.asm("movq %rcx, %rsi");
+                    self.asm("call ak_str_cmp");
+                    self.asm("xorl %ecx, %ecx");
+                    self.compare(op, true);
+                }
+                (_, Ty::Float(float)) => self.compare_float(op, *float),
+                (_, Ty::Int(int)) => self.compare(op, int.is_signed()),
+                _ => self.compare(op, false),
+            }
+            return;
+        }
+
+        match ty {
+            Ty::Int(int) => match op {
+                Add | Sub | Mul | Div | Rem => self.arith(op, *int),
+                Shl | Shr => {
+                    let dir = if op == Shl { "left" } else { "right" };
+                    self.asm(format!("cmpq ${}, %rcx", int.bits()));
+                    self.check("jb", &format!("attempt to shift {dir} with overflow"));
+                    self.asm(match (op, int.is_signed()) {
+                        (Shl, _) => "shlq %cl, %rax",
+                        (_, true) => "sarq %cl, %rax",
+                        _ => "shrq %cl, %rax",
+                    });
+                    self.normalize(*int, "%rax");
+                }
+                BitAnd => self.asm("andq %rcx, %rax"),
+                BitOr => self.asm("orq %rcx, %rax"),
+                _ => self.asm("xorq %rcx, %rax"),
+            },
+            Ty::Float(float) => {
+                let suffix = float_suffix(*float);
+                match op {
+                    Rem => {
+                        if *float == FloatTy::F32 {
+                            self.asm("cvtss2sd %xmm0, %xmm0");
+                            self.asm("cvtss2sd %xmm1, %xmm1");
+                        }
+                        self.asm("call ak_fmod");
+                        if *float == FloatTy::F32 {
+                            self.asm("cvtsd2ss %xmm0, %xmm0");
+                        }
+                    }
+                    Add => self.asm(format!("add{suffix} %xmm1, %xmm0")),
+                    Sub => self.asm(format!("sub{suffix} %xmm1, %xmm0")),
+                    Mul => self.asm(format!("mul{suffix} %xmm1, %xmm0")),
+                    _ => self.asm(format!("div{suffix} %xmm1, %xmm0")),
+                }
+            }
+            _ => match op {
+                BitAnd | And => self.asm("andq %rcx, %rax"),
+                BitOr | Or => self.asm("orq %rcx, %rax"),
+                _ => self.asm("xorq %rcx, %rax"),
+            },
+        }
+    }
+
+    /// Compares `%rax` with `%rcx`.
+    fn compare(&mut self, op: BinaryOp, signed: bool) {
+        let cc = match (op, signed) {
+            (BinaryOp::Eq, _) => "e",
+            (BinaryOp::Ne, _) => "ne",
+            (BinaryOp::Lt, true) => "l",
+            (BinaryOp::Gt, true) => "g",
+            (BinaryOp::Le, true) => "le",
+            (BinaryOp::Ge, true) => "ge",
+            (BinaryOp::Lt, false) => "b",
+            (BinaryOp::Gt, false) => "a",
+            (BinaryOp::Le, false) => "be",
+            _ => "ae",
+        };
+        self.asm("cmpq %rcx, %rax");
+        self.asm(format!("set{cc} %al"));
+        self.asm("movzbl %al, %eax");
+    }
+
+    /// Compares `%xmm0` with `%xmm1`, which is false whenever either is NaN.
+    fn compare_float(&mut self, op: BinaryOp, float: FloatTy) {
+        let ucomi = match float {
+            FloatTy::F32 => "ucomiss",
+            FloatTy::F64 => "ucomisd",
+        };
+        // `a` and `ae` are false for unordered operands, so `<` and `<=`
+        // swap them
+        match op {
+            BinaryOp::Lt | BinaryOp::Le => self.asm(format!("{ucomi} %xmm0, %xmm1")),
+            _ => self.asm(format!("{ucomi} %xmm1, %xmm0")),
+        }
+        match op {
+            BinaryOp::Eq => {
+                self.asm("sete %al");
+                self.asm("setnp %cl");
+                self.asm("andb %cl, %al");
+            }
+            BinaryOp::Ne => {
+                self.asm("setne %al");
+                self.asm("setp %cl");
+                self.asm("orb %cl, %al");
+            }
+            BinaryOp::Lt | BinaryOp::Gt => self.asm("seta %al"),
+            _ => self.asm("setae %al"),
+        }
+        self.asm("movzbl %al, %eax");
+    }
+
+    /// Checked `+`, `-`, `*`, `/` and `%` of `%rax` and `%rcx`, which panic
+    /// on overflow like the interpreter.
+    fn arith(&mut self, op: BinaryOp, int: IntTy) {
+        let verb = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "subtract",
+            BinaryOp::Mul => "multiply",
+            BinaryOp::Div => "divide",
+            _ => "calculate the remainder",
+        };
+        let overflow = format!("attempt to {verb} with overflow");
+        let signed = int.is_signed();
+        let wide = int.bits() == 64;
+
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+                match op {
+                    BinaryOp::Add => self.asm("addq %rcx, %rax"),
+                    BinaryOp::Sub => self.asm("subq %rcx, %rax"),
+                    _ if wide && !signed => self.asm("mulq %rcx"),
+                    _ => self.asm("imulq %rcx, %rax"),
+                }
+                // narrow integers are computed exactly in 64 bits
+                match (wide, signed, op) {
+                    (false, ..) => self.check_range(int, &overflow),
+                    (true, true, _) | (true, false, BinaryOp::Mul) => self.check("jno", &overflow),
+                    _ => self.check("jnc", &overflow),
+                }
+            }
+            _ => {
+                let zero = match op {
+                    BinaryOp::Div => "attempt to divide by zero",
+                    _ => "attempt to calculate the remainder with a divisor of zero",
+                };
+                self.asm("testq %rcx, %rcx");
+                self.check("jne", zero);
+
+                let done = self.new_label();
+                if wide && signed {
+                    // `MIN / -1` traps
+                    let normal = self.new_label();
+                    self.asm("cmpq $-1, %rcx");
+                    self.asm(format!("jne {normal}"));
+                    if op == BinaryOp::Div {
+                        self.asm("movabsq $-9223372036854775808, %rdx");
+                        self.asm("cmpq %rdx, %rax");
+                        self.check("jne", &overflow);
+                        self.asm("negq %rax");
+                    } else {
+                        self.asm("xorl %eax, %eax");
+                    }
+                    self.asm(format!("jmp {done}"));
+                    self.label(&normal);
+                }
+                if signed {
+                    self.asm("cqto");
+                    self.asm("idivq %rcx");
+                } else {
+                    self.asm("xorl %edx, %edx");
+                    self.asm("divq %rcx");
+                }
+                if op == BinaryOp::Rem {
+                    self.asm("movq %rdx, %rax");
+                } else if !wide {
+                    self.check_range(int, &overflow);
+                }
+                self.label(&done);
+            }
+        }
+    }
+
+    fn prefix(&mut self, op: PrefixOp, operand: &'a Spanned<Box<Expr>>) {
+        let ty = self.ty(operand.span);
+
+        match op {
+            PrefixOp::Neg => {
+                // `-128` is in range for `i8` even though `128` is not
+                if let Expr::Literal(Literal::UInt(v, _)) = &*operand.node
+                    && let Ty::Int(int) = ty
+                {
+                    return self.int(-(*v as i128), int);
+                }
+                self.expr_boxed(operand);
+                match ty {
+                    Ty::Int(int) if !int.is_signed() => {
+                        self.asm("testq %rax, %rax");
+                        self.check("je", "attempt to negate with overflow");
+                    }
+                    Ty::Int(int) => {
+                        self.asm("negq %rax");
+                        match int.bits() {
+                            64 => self.check("jno", "attempt to negate with overflow"),
+                            _ => self.check_range(int, "attempt to negate with overflow"),
+                        }
+                    }
+                    Ty::Float(FloatTy::F32) => {
+                        self.asm("movd %xmm0, %eax");
+                        self.asm("xorl $0x80000000, %eax");
+                        self.asm("movd %eax, %xmm0");
+                    }
+                    _ => {
+                        self.asm("movq %xmm0, %rax");
+                        self.asm("btcq $63, %rax");
+                        self.asm("movq %rax, %xmm0");
+                    }
+                }
+            }
+            PrefixOp::Not => {
+                self.expr_boxed(operand);
+                match ty {
+                    Ty::Int(int) => {
+                        self.asm("notq %rax");
+                        self.normalize(int, "%rax");
+                    }
+                    _ => self.asm("xorl $1, %eax"),
+                }
+            }
+            PrefixOp::Deref => {
+                self.expr_boxed(operand);
+                if let Ty::Ref { inner, .. } = &ty
+                    && !matches!(**inner, Ty::Slice(_))
+                {
+                    self.load(inner);
+                }
+            }
+            PrefixOp::Ref | PrefixOp::RefMut => match ty {
+                Ty::Slice(_) => self.expr_boxed(operand),
+                _ => self.place(operand),
+            },
+        }
+    }
+
+    fn cast(&mut self, from: &Ty, to: &Ty) {
+        match (from, to) {
+            (Ty::Int(_) | Ty::Bool | Ty::Char, Ty::Int(int)) => self.normalize(*int, "%rax"),
+            (Ty::Int(int), Ty::Float(float)) => {
+                if *int == IntTy::U64 {
+                    // above `i64::MAX`, halve it keeping the lowest bit for
+                    // rounding, and double the result
+                    let (big, done) = (self.new_label(), self.new_label());
+                    self.asm("testq %rax, %rax");
+                    self.asm(format!("js {big}"));
+                    self.asm("cvtsi2sdq %rax, %xmm0");
+                    self.asm(format!("jmp {done}"));
+                    self.label(&big);
+                    self.asm("movq %rax, %rcx");
+                    self.asm("shrq $1, %rcx");
+                    self.asm("andl $1, %eax");
+                    self.asm("orq %rax, %rcx");
+                    self.asm("cvtsi2sdq %rcx, %xmm0");
+                    self.asm("addsd %xmm0, %xmm0");
+                    self.label(&done);
+                } else {
+                    self.asm("cvtsi2sdq %rax, %xmm0");
+                }
+                if *float == FloatTy::F32 {
+                    self.asm("cvtsd2ss %xmm0, %xmm0");
+                }
+            }
+            (Ty::Float(FloatTy::F32), Ty::Float(FloatTy::F64)) => self.asm("cvtss2sd %xmm0, %xmm0"),
+            (Ty::Float(FloatTy::F64), Ty::Float(FloatTy::F32)) => self.asm("cvtsd2ss %xmm0, %xmm0"),
+            (Ty::Float(float), Ty::Int(int)) => {
+                if *float == FloatTy::F32 {
+                    self.asm("cvtss2sd %xmm0, %xmm0");
+                }
+                self.float_to_int(*int);
+            }
+            (Ty::Int(_), Ty::Char) => self.asm("movzbl %al, %eax"),
+            _ => (),
+        }
+    }
+
+    /// Converts `%xmm0` to `int`, saturating, with NaN becoming zero.
+    fn float_to_int(&mut self, int: IntTy) {
+        let done = self.new_label();
+        let zero = self.new_label();
+        self.asm("ucomisd %xmm0, %xmm0");
+        self.asm(format!("jp {zero}"));
+
+        match (int.bits(), int.is_signed()) {
+            (64, true) => {
+                let max = self.float(2f64.powi(63), FloatTy::F64);
+                let big = self.new_label();
+                self.asm(format!("ucomisd {max}(%rip), %xmm0"));
+                self.asm(format!("jae {big}"));
+                // below `i64::MIN` the conversion gives `i64::MIN`
+                self.asm("cvttsd2si %xmm0, %rax");
+                self.asm(format!("jmp {done}"));
+                self.label(&big);
+                self.asm("movabsq $9223372036854775807, %rax");
+            }
+            (64, false) => {
+                let (half, max) = (
+                    self.float(2f64.powi(63), FloatTy::F64),
+                    self.float(2f64.powi(64), FloatTy::F64),
+                );
+                let (high, big) = (self.new_label(), self.new_label());
+                self.asm("xorps %xmm1, %xmm1");
+                self.asm("ucomisd %xmm1, %xmm0");
+                self.asm(format!("jbe {zero}"));
+                self.asm(format!("ucomisd {max}(%rip), %xmm0"));
+                self.asm(format!("jae {big}"));
+                self.asm(format!("ucomisd {half}(%rip), %xmm0"));
+                self.asm(format!("jae {high}"));
+                self.asm("cvttsd2si %xmm0, %rax");
+                self.asm(format!("jmp {done}"));
+                self.label(&high);
+                self.asm(format!("subsd {half}(%rip), %xmm0"));
+                self.asm("cvttsd2si %xmm0, %rax");
+                self.asm("btcq $63, %rax");
+                self.asm(format!("jmp {done}"));
+                self.label(&big);
+                self.asm("movq $-1, %rax");
+            }
+            _ => {
+                let min = self.float(value::int_min(int) as f64, FloatTy::F64);
+                let max = self.float(value::int_max(int) as f64, FloatTy::F64);
+                self.asm(format!("maxsd {min}(%rip), %xmm0"));
+                self.asm(format!("minsd {max}(%rip), %xmm0"));
+                self.asm("cvttsd2si %xmm0, %rax");
+            }
+        }
+        self.asm(format!("jmp {done}"));
+        self.label(&zero);
+        self.asm("xorl %eax, %eax");
+        self.label(&done);
+    }
+
+    // ------------------------------------------------------------- calls
+
+    /// Saves the argument in `%rax` or `%xmm0` until the call, copying it
+    /// when it is passed in memory, so the callee has its own.
+    fn arg(&mut self, ty: &Ty) -> Arg {
+        match self.class(ty) {
+            Class::Memory(_) => Arg::Addr(self.frame_slot(ty)),
+            class => Arg::Value(class, self.save(ty)),
+        }
+    }
+
+    /// Calls `name` with saved arguments, leaving the result in `%rax` or
+    /// `%xmm0`.
+    fn call_fn(&mut self, name: &str, args: Vec<Arg>, ret: &Ty) {
+        let mut ints = Vec::new();
+        let mut floats = Vec::new();
+        let mut stack = Vec::new();
+
+        if let Class::Memory(size) = self.class(ret) {
+            let slot = self.slot(size, self.size_align(ret).1);
+            ints.push(format!("leaq {slot}(%rbp), {{}}"));
+        }
+        for arg in args {
+            let (line, float) = match arg {
+                Arg::Value(Class::Unit, _) => continue,
+                Arg::Value(Class::Float(float), slot) => {
+                    (format!("{} {slot}(%rbp), {{}}", float_mov(float)), true)
+                }
+                Arg::Value(_, slot) => (format!("movq {slot}(%rbp), {{}}"), false),
+                Arg::Addr(slot) => (format!("leaq {slot}(%rbp), {{}}"), false),
+            };
+            match float {
+                true if floats.len() < FLOAT_ARGS => floats.push(line),
+                false if ints.len() < INT_ARGS.len() => ints.push(line),
+                _ => stack.push((line, float)),
+            }
+        }
+
+        for (idx, (line, float)) in stack.iter().enumerate() {
+            let reg = if *float { "%xmm0" } else { "%rax" };
+            self.asm(line.replace("{}", reg));
+            let mov = if *float { "movsd" } else { "movq" };
+            self.asm(format!("{mov} {reg}, {}(%rsp)", idx * 8));
+        }
+        self.func.outgoing = self.func.outgoing.max(stack.len() as u32 * 8);
+        for (idx, line) in floats.iter().enumerate() {
+            self.asm(line.replace("{}", &format!("%xmm{idx}")));
+        }
+        for (line, reg) in ints.iter().zip(INT_ARGS) {
+            self.asm(line.replace("{}", reg));
+        }
+        self.asm(format!("call {name}"));
+    }
+
+    fn call(&mut self, callee: &'a Spanned<Box<Expr>>, args: &'a [Spanned<Expr>], ty: &Ty) {
+        let def = match &*callee.node {
+            Expr::Variable(_) | Expr::Path(_) => self.res.lookup(callee.span),
+            _ => None,
+        };
+        let Some(def) = def.filter(|def| {
+            matches!(
+                self.res.def(*def).kind,
+                DefKind::Fn | DefKind::Builtin | DefKind::Variant
+            )
+        }) else {
+            for arg in args {
+                self.expr(arg);
+            }
+            return self.unsupported(callee.span, "calls of function values");
+        };
+
+        let info = self.res.def(def);
+        match info.kind {
+            DefKind::Builtin => {
+                let arg_ty = match args.first() {
+                    Some(arg) => {
+                        self.expr(arg);
+                        self.ty(arg.span)
+                    }
+                    None => Ty::unit(),
+                };
+                match info.name.as_str() {
+                    "assert" => {
+                        self.asm("testb %al, %al");
+                        self.check("jne", "assertion failed");
+                    }
+                    name => {
+                        if !args.is_empty() {
+                            self.print(&arg_ty, "$0");
+                        }
+                        if name == "println" {
+                            self.asm("movl $10, %edi");
+                            self.asm("call ak_write_byte");
+                        }
+                    }
+                }
+            }
+            DefKind::Variant => {
+                let (size, align) = self.size_align(ty);
+                let slot = self.slot(size, align);
+                let (tag, layout) = self.adt_layout(def);
+                if let Some(tag) = tag {
+                    self.asm(format!("movl ${tag}, {slot}(%rbp)"));
+                }
+                for (arg, (_, offset, field_ty)) in args.iter().zip(layout) {
+                    self.expr(arg);
+                    self.coerce(&self.ty(arg.span), &field_ty);
+                    self.asm(format!("leaq {}(%rbp), %rdi", slot + offset as i32));
+                    self.store(&field_ty);
+                }
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+            _ => {
+                let (params, ret) = self.signature(def);
+                let mut passed = Vec::new();
+                for (arg, param) in args.iter().zip(&params) {
+                    self.expr(arg);
+                    self.coerce(&self.ty(arg.span), param);
+                    passed.push(self.arg(param));
+                }
+                let name = self.item_name(def);
+                self.call_fn(&name, passed, &ret);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------ places
+
+    fn is_place(&self, expr: &Expr, span: Span) -> bool {
+        match expr {
+            Expr::Variable(_) => self
+                .res
+                .lookup(span)
+                .is_some_and(|def| matches!(self.res.def(def).kind, DefKind::Local { .. })),
+            Expr::Field(..) => true,
+            Expr::Index(_, index) => !matches!(&*index.node, Expr::Range { .. }),
+            Expr::Prefix(op, _) => op.node == PrefixOp::Deref,
+            _ => false,
+        }
+    }
+
+    /// Leaves in `%rax` the address of the location `expr` denotes.
+    /// Anything that is not a variable, field, element or dereference is
+    /// copied to the frame first.
+    fn place(&mut self, expr: &'a Spanned<Box<Expr>>) {
+        if !self.is_place(&expr.node, expr.span) {
+            self.expr_boxed(expr);
+            let slot = self.frame_slot(&self.ty(expr.span));
+            self.asm(format!("leaq {slot}(%rbp), %rax"));
+            return;
+        }
+
+        match &*expr.node {
+            Expr::Variable(_) => match self.res.lookup(expr.span) {
+                Some(def) => self.home_addr(def),
+                None => self.asm("xorl %eax, %eax"),
+            },
+            Expr::Field(base, name) => _ = self.field_addr(base, name.node),
+            Expr::Index(base, index) => _ = self.index_addr(base, index),
+            Expr::Prefix(_, inner) => self.expr_boxed(inner),
+            _ => self.asm("xorl %eax, %eax"),
+        }
+    }
+
+    /// Leaves in `%rax` the address of the value of `expr`, following
+    /// references, and returns the type found there.
+    fn operand_addr(&mut self, expr: &'a Spanned<Box<Expr>>) -> Ty {
+        let ty = self.ty(expr.span);
+        if self.is_place(&expr.node, expr.span) {
+            self.place(expr);
+        } else {
+            self.expr_boxed(expr);
+            if !matches!(self.class(&ty), Class::Memory(_)) {
+                let slot = self.save(&ty);
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+            }
+        }
+
+        let mut ty = ty;
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            let inner = (**inner).clone();
+            self.asm("movq (%rax), %rax");
+            ty = inner;
+        }
+        ty
+    }
+
+    /// Leaves the address of a field in `%rax`, returning its type.
+    fn field_addr(&mut self, base: &'a Spanned<Box<Expr>>, name: Symbol) -> Ty {
+        let ty = self.operand_addr(base);
+        let (offset, field_ty) = match &ty {
+            Ty::Tuple(elems) => {
+                let idx: usize = name.as_str().parse().unwrap_or(0);
+                let (offsets, ..) = self.fields_layout(elems.iter());
+                match (offsets.get(idx), elems.get(idx)) {
+                    (Some(offset), Some(elem)) => (*offset, elem.clone()),
+                    _ => (0, Ty::unit()),
+                }
+            }
+            Ty::Path(path) => match self.res.def_by_path(path) {
+                Some(def) => self
+                    .struct_layout(def)
+                    .into_iter()
+                    .find(|(field, ..)| *field == Some(name))
+                    .map(|(_, offset, ty)| (offset, ty))
+                    .unwrap_or((0, Ty::unit())),
+                None => (0, Ty::unit()),
+            },
+            _ => (0, Ty::unit()),
+        };
+        if offset != 0 {
+            self.asm(format!("addq ${offset}, %rax"));
+        }
+        field_ty
+    }
+
+    /// Leaves the address of an element in `%rax`, checking the index, and
+    /// returns its type.
+    fn index_addr(&mut self, base: &'a Spanned<Box<Expr>>, index: &'a Spanned<Box<Expr>>) -> Ty {
+        let ty = self.operand_addr(base);
+        let base = self.save(&Ty::Bool);
+
+        let (elem, len) = match &ty {
+            Ty::Array(elem, len) => ((**elem).clone(), Some(*len)),
+            Ty::Slice(elem) => ((**elem).clone(), None),
+            Ty::Ref { inner, .. } => match &**inner {
+                Ty::Slice(elem) => ((**elem).clone(), None),
+                _ => (Ty::unit(), None),
+            },
+            _ => (Ty::unit(), None),
+        };
+
+        self.expr_boxed(index);
+        let index_ty = self.ty(index.span);
+        match len {
+            Some(len) => self.asm(format!("movq ${len}, %rcx")),
+            None => {
+                self.asm(format!("movq {base}(%rbp), %rcx"));
+                self.asm("movq 8(%rcx), %rcx");
+            }
+        }
+        // a negative index is also too large, as an unsigned number
+        let ok = self.new_label();
+        let signed = matches!(index_ty, Ty::Int(int) if int.is_signed());
+        self.asm("cmpq %rcx, %rax");
+        self.asm(format!("jb {ok}"));
+        self.asm("movq %rcx, %rdi");
+        self.asm("movq %rax, %rsi");
+        self.asm(format!("movl ${}, %edx", signed as u32));
+        self.asm("call ak_panic_index");
+        self.label(&ok);
+
+        let size = self.size_align(&elem).0;
+        self.asm(format!("imulq ${size}, %rax"));
+        self.asm(format!("movq {base}(%rbp), %rcx"));
+        if len.is_none() {
+            self.asm("movq (%rcx), %rcx");
+        }
+        self.asm("addq %rcx, %rax");
+        elem
+    }
+
+    /// `base[a..b]`, a slice into an array or a substring.
+    fn slice(&mut self, expr: &'a Expr, ty: &Ty) {
+        let Expr::Index(base, index) = expr else {
+            return;
+        };
+        let Expr::Range {
+            start,
+            end,
+            inclusive,
+        } = &*index.node
+        else {
+            return;
+        };
+
+        let base_ty = self.operand_addr(base);
+        let base = self.save(&Ty::Bool);
+        let len = |this: &mut Self| match &base_ty {
+            Ty::Array(_, len) => this.asm(format!("movq ${len}, %rax")),
+            _ => {
+                this.asm(format!("movq {base}(%rbp), %rax"));
+                this.asm("movq 8(%rax), %rax");
+            }
+        };
+
+        match start {
+            Some(start) => self.expr_boxed(start),
+            None => self.asm("xorl %eax, %eax"),
+        }
+        let lo = self.save(&Ty::Bool);
+        match end {
+            Some(end) => {
+                self.expr_boxed(end);
+                if *inclusive {
+                    self.asm("incq %rax");
+                }
+            }
+            None => len(self),
+        }
+        let hi = self.save(&Ty::Bool);
+        let slot = self.slot(16, 8);
+
+        if base_ty == Ty::Str {
+            self.asm(format!("movq {base}(%rbp), %rdi"));
+            self.asm(format!("movq {lo}(%rbp), %rsi"));
+            self.asm(format!("movq {hi}(%rbp), %rdx"));
+            self.asm(format!("leaq {slot}(%rbp), %rcx"));
+            self.asm("call ak_str_slice");
+            return;
+        }
+
+        len(self);
+        self.asm("movq %rax, %rdx");
+        self.asm(format!("movq {lo}(%rbp), %rdi"));
+        self.asm(format!("movq {hi}(%rbp), %rsi"));
+        self.asm("call ak_check_range");
+
+        let elem = match ty {
+            Ty::Slice(elem) => (**elem).clone(),
+            _ => Ty::unit(),
+        };
+        let size = self.size_align(&elem).0;
+        self.asm(format!("movq {base}(%rbp), %rax"));
+        if !matches!(base_ty, Ty::Array(..)) {
+            self.asm("movq (%rax), %rax");
+        }
+        self.asm(format!("movq {lo}(%rbp), %rcx"));
+        self.asm(format!("imulq ${size}, %rcx"));
+        self.asm("addq %rcx, %rax");
+        self.asm(format!("movq %rax, {slot}(%rbp)"));
+        self.asm(format!("movq {hi}(%rbp), %rax"));
+        self.asm(format!("subq {lo}(%rbp), %rax"));
+        self.asm(format!("movq %rax, {}(%rbp)", slot + 8));
+        self.asm(format!("leaq {slot}(%rbp), %rax"));
+    }
+
+    fn for_loop(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        iter: &'a Spanned<Box<Expr>>,
+        body: &'a BlockExpr,
+    ) {
+        let iter_ty = self.ty(iter.span);
+        let (top, exit) = (self.new_label(), self.new_label());
+
+        if let Some(elem_ty) = range_elem(&iter_ty) {
+            let Expr::Range {
+                start: Some(start),
+                end,
+                inclusive,
+            } = &*iter.node
+            else {
+                return self.unsupported(iter.span, "ranges outside `for` loops and slicing");
+            };
+            let Ty::Int(int) = elem_ty.clone() else {
+                return;
+            };
+            let signed = int.is_signed();
+
+            self.expr_boxed(start);
+            let counter = self.save(elem_ty);
+            let end = end.as_ref().map(|end| {
+                self.expr_boxed(end);
+                self.save(elem_ty)
+            });
+
+            // `a..=b` keeps a flag rather than stepping past `b`, which could
+            // overflow
+            let more = self.slot(8, 8);
+            if let (Some(end), true) = (end, *inclusive) {
+                self.asm(format!("movq {counter}(%rbp), %rax"));
+                self.asm(format!("movq {end}(%rbp), %rcx"));
+                self.compare(BinaryOp::Le, signed);
+                self.asm(format!("movq %rax, {more}(%rbp)"));
+            }
+
+            self.label(&top);
+            match (end, *inclusive) {
+                (Some(end), false) => {
+                    self.asm(format!("movq {counter}(%rbp), %rax"));
+                    self.asm(format!("cmpq {end}(%rbp), %rax"));
+                    self.asm(format!("{} {exit}", if signed { "jge" } else { "jae" }));
+                }
+                (Some(_), true) => {
+                    self.asm(format!("cmpq $0, {more}(%rbp)"));
+                    self.asm(format!("je {exit}"));
+                }
+                (None, _) => (),
+            }
+
+            self.asm(format!("movq {counter}(%rbp), %rax"));
+            self.bind_element(pattern, elem_ty);
+            let next = self.loop_body(&exit, body);
+
+            self.label(&next);
+            match (end, *inclusive) {
+                (Some(end), true) => {
+                    let skip = self.new_label();
+                    self.asm(format!("movq {counter}(%rbp), %rax"));
+                    self.asm(format!("movq {end}(%rbp), %rcx"));
+                    self.compare(BinaryOp::Ne, signed);
+                    self.asm(format!("movq %rax, {more}(%rbp)"));
+                    self.asm("testq %rax, %rax");
+                    self.asm(format!("je {skip}"));
+                    self.asm(format!("incq {counter}(%rbp)"));
+                    self.label(&skip);
+                }
+                (Some(_), false) => self.asm(format!("incq {counter}(%rbp)")),
+                (None, _) => {
+                    self.asm(format!("movq {counter}(%rbp), %rax"));
+                    self.int(value::int_max(int), int);
+                    self.asm("movq %rax, %rcx");
+                    self.asm(format!("movq {counter}(%rbp), %rax"));
+                    self.asm("cmpq %rcx, %rax");
+                    self.check(
+                        "jne",
+                        "attempt to iterate past the end of the integer range",
+                    );
+                    self.asm(format!("incq {counter}(%rbp)"));
+                }
+            }
+            self.asm(format!("jmp {top}"));
+            self.label(&exit);
+            return;
+        }
+
+        // the loop visits a copy, like the interpreter does
+        self.expr_boxed(iter);
+        let ty = self.deref_all(&iter_ty);
+        let slot = self.frame_slot(&ty);
+        let (data, len, idx) = (self.slot(8, 8), self.slot(8, 8), self.slot(8, 8));
+        self.asm(format!("movq $0, {idx}(%rbp)"));
+        match &ty {
+            Ty::Array(_, n) => {
+                self.asm(format!("leaq {slot}(%rbp), %rax"));
+                self.asm(format!("movq %rax, {data}(%rbp)"));
+                self.asm(format!("movq ${n}, {len}(%rbp)"));
+            }
+            _ => {
+                self.asm(format!("movq {slot}(%rbp), %rax"));
+                self.asm(format!("movq %rax, {data}(%rbp)"));
+                self.asm(format!("movq {}(%rbp), %rax", slot + 8));
+                self.asm(format!("movq %rax, {len}(%rbp)"));
+            }
+        }
+
+        self.label(&top);
+        self.asm(format!("movq {idx}(%rbp), %rax"));
+        self.asm(format!("cmpq {len}(%rbp), %rax"));
+        self.asm(format!("jae {exit}"));
+
+        let elem = match &ty {
+            Ty::Str => {
+                self.asm(format!("movq {data}(%rbp), %rdi"));
+                self.asm(format!("addq {idx}(%rbp), %rdi"));
+                self.asm("call ak_utf8_next");
+                self.asm(format!("addq %rdx, {idx}(%rbp)"));
+                Ty::Char
+            }
+            Ty::Array(elem, _) | Ty::Slice(elem) => (**elem).clone(),
+            Ty::Ref { inner, .. } => match &**inner {
+                Ty::Slice(elem) => (**elem).clone(),
+                _ => Ty::unit(),
+            },
+            _ => Ty::unit(),
+        };
+        if ty != Ty::Str {
+            let size = self.size_align(&elem).0;
+            self.asm(format!("imulq ${size}, %rax"));
+            self.asm(format!("addq {data}(%rbp), %rax"));
+            self.load(&elem);
+            self.asm(format!("incq {idx}(%rbp)"));
+        }
+        self.bind_element(pattern, &elem);
+        let next = self.loop_body(&exit, body);
+        self.label(&next);
+        self.asm(format!("jmp {top}"));
+        self.label(&exit);
+    }
+
+    /// Binds the loop pattern to the element in `%rax` or `%xmm0`.
+    fn bind_element(&mut self, pattern: &'a Spanned<Pattern>, elem: &Ty) {
+        let slot = self.frame_slot(elem);
+        let mut conds = Vec::new();
+        let mut binds = String::new();
+        self.pattern(
+            pattern,
+            vec![format!("leaq {slot}(%rbp), %rax")],
+            elem,
+            &mut conds,
+            &mut binds,
+        );
+        if !conds.is_empty() {
+            let fail = self.new_label();
+            let ok = self.new_label();
+            for cond in conds {
+                self.func.code.push_str(&cond);
+                self.asm("testb %al, %al");
+                self.asm(format!("je {fail}"));
+            }
+            self.asm(format!("jmp {ok}"));
+            self.label(&fail);
+            self.panic("pattern did not match the element");
+            self.label(&ok);
+        }
+        self.func.code.push_str(&binds);
+    }
+
+    /// The body of a `for` loop, returning the label `continue` goes to.
+    fn loop_body(&mut self, exit: &str, body: &'a BlockExpr) -> String {
+        let next = self.new_label();
+        self.func.loops.push(LoopLabels {
+            exit: exit.to_owned(),
+            next: next.clone(),
+        });
+        self.block(body);
+        self.func.loops.pop();
+        next
+    }
+
+    // ---------------------------------------------------------- patterns
+
+    /// Tests the value whose address `addr` leaves in `%rax`, of type `ty`,
+    /// against `pattern`. The code for each condition that must hold, which
+    /// leaves whether it does in `%al`, goes in `conds`, and the code
+    /// binding its variables, which may only run once they hold, in `binds`.
+    fn pattern(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        mut addr: Vec<String>,
+        ty: &Ty,
+        conds: &mut Vec<String>,
+        binds: &mut String,
+    ) {
+        let emit = |this: &mut Self, lines: &[String]| {
+            for line in lines {
+                this.asm(line);
+            }
+        };
+
+        let local = match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => return,
+            Pattern::Variable(name) => self
+                .res
+                .lookup(name.span)
+                .filter(|def| matches!(self.res.def(*def).kind, DefKind::Local { .. })),
+            _ => None,
+        };
+        if let Some(def) = local {
+            let (size, align) = self.size_align(ty);
+            let slot = self.slot(size.max(1), align);
+            self.func.homes.insert(def, Home::Frame(slot));
+            let code = self.capture(|this| {
+                emit(this, &addr);
+                this.load(ty);
+                this.asm(format!("leaq {slot}(%rbp), %rdi"));
+                this.store(ty);
+            });
+            binds.push_str(&code);
+            return;
+        }
+
+        // patterns look through references and into optionals
+        let mut ty = ty.clone();
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            addr.push("movq (%rax), %rax".to_owned());
+            ty = (**inner).clone();
+        }
+        let none = matches!(&pattern.node, Pattern::Literal(l) if matches!(l.node, Literal::None));
+        if let Ty::Optional(inner) = &ty {
+            let cond = self.capture(|this| {
+                emit(this, &addr);
+                this.asm("movzbl (%rax), %eax");
+                if none {
+                    this.asm("xorl $1, %eax");
+                }
+            });
+            conds.push(cond);
+            if none {
+                return;
+            }
+            let offset = self.size_align(inner).1.max(1);
+            addr.push(format!("addq ${offset}, %rax"));
+            ty = (**inner).clone();
+        }
+
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => (),
+            Pattern::Literal(literal) => {
+                let literal_ty = self.ty(literal.span);
+                let cond = self.capture(|this| {
+                    emit(this, &addr);
+                    this.load(&ty);
+                    this.move_to_second(&ty);
+                    this.literal(&literal.node, &literal_ty);
+                    this.eq(&ty);
+                });
+                conds.push(cond);
+            }
+            Pattern::Variable(name) => {
+                let Some(def) = self.res.lookup(name.span) else {
+                    return;
+                };
+                if self.res.def(def).kind == DefKind::Const {
+                    let getter = self.item_name(def);
+                    let cond = self.capture(|this| {
+                        this.asm(format!("call {getter}"));
+                        this.move_to_second(&ty);
+                        emit(this, &addr);
+                        this.load(&ty);
+                        this.eq(&ty);
+                    });
+                    conds.push(cond);
+                } else if let Some((_, tag)) = self.variant_tag(def) {
+                    conds.push(self.tag_test(&addr, tag));
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let Some((_, tag)) = self.variant_tag(def) else {
+                    return;
+                };
+                conds.push(self.tag_test(&addr, tag));
+                for (elem, (_, offset, field_ty)) in elems.iter().zip(self.variant_layout(def)) {
+                    let mut field = addr.clone();
+                    field.push(format!("addq ${offset}, %rax"));
+                    self.pattern(elem, field, &field_ty, conds, binds);
+                }
+            }
+            Pattern::Struct { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let (tag, layout) = self.adt_layout(def);
+                if let Some(tag) = tag {
+                    conds.push(self.tag_test(&addr, tag));
+                }
+
+                for field in fields {
+                    let name = field.node.name.node;
+                    let Some((_, offset, field_ty)) =
+                        layout.iter().find(|(n, ..)| *n == Some(name)).cloned()
+                    else {
+                        continue;
+                    };
+                    let mut field_addr = addr.clone();
+                    field_addr.push(format!("addq ${offset}, %rax"));
+                    self.pattern(&field.node.pattern, field_addr, &field_ty, conds, binds);
+                }
+            }
+        }
+    }
+
+    /// The code testing whether the enum at `addr` is the variant `tag`.
+    fn tag_test(&mut self, addr: &[String], tag: u32) -> String {
+        self.capture(|this| {
+            for line in addr {
+                this.asm(line);
+            }
+            this.asm(format!("cmpl ${tag}, (%rax)"));
+            this.asm("sete %al");
+        })
+    }
+
+    // ------------------------------------------------ printing and equality
+
+    /// Prints the value in `%rax` or `%xmm0`. `nested`, an operand, tells
+    /// whether strings and chars are quoted.
+    fn print(&mut self, ty: &Ty, nested: &str) {
+        match ty {
+            Ty::Int(int) => {
+                self.asm("movq %rax, %rdi");
+                match int.is_signed() {
+                    true => self.asm("call ak_print_int"),
+                    false => self.asm("call ak_print_uint"),
+                }
+            }
+            Ty::Float(FloatTy::F32) => self.asm("call ak_print_f32"),
+            Ty::Float(FloatTy::F64) => self.asm("call ak_print_f64"),
+            Ty::Bool => {
+                self.asm("movl %eax, %edi");
+                self.asm("call ak_print_bool");
+            }
+            Ty::Char => {
+                self.asm(format!("movl {nested}, %esi"));
+                self.asm("movl %eax, %edi");
+                self.asm("call ak_print_char");
+            }
+            Ty::Str => {
+                self.asm(format!("movl {nested}, %edx"));
+                self.asm("movq (%rax), %rdi");
+                self.asm("movq 8(%rax), %rsi");
+                self.asm("call ak_print_str");
+            }
+            Ty::Fn { .. } => self.text_out("<fn>"),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => {
+                self.load(inner);
+                self.print(inner, "$1");
+            }
+            Ty::Ref { .. }
+            | Ty::Slice(_)
+            | Ty::Tuple(_)
+            | Ty::Array(..)
+            | Ty::Optional(_)
+            | Ty::Path(_)
+                if matches!(self.class(ty), Class::Memory(_)) =>
+            {
+                let helper = self.show(ty);
+                self.asm(format!("movl {nested}, %esi"));
+                self.asm("movq %rax, %rdi");
+                self.asm(format!("call {helper}"));
+            }
+            _ => self.text_out("()"),
+        }
+    }
+
+    /// Compares the operands in `%rax` and `%rcx`, or `%xmm0` and `%xmm1`,
+    /// leaving whether they are equal in `%rax`.
+    fn eq(&mut self, ty: &Ty) {
+        match ty {
+            Ty::Float(float) => self.compare_float(BinaryOp::Eq, *float),
+            Ty::Int(_) | Ty::Bool | Ty::Char | Ty::Fn { .. } => self.compare(BinaryOp::Eq, false),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => {
+                let b = self.save(&Ty::Bool);
+                self.load(inner);
+                let a = self.save(inner);
+                self.asm(format!("movq {b}(%rbp), %rax"));
+                self.load(inner);
+                self.move_to_second(inner);
+                self.restore(inner, a, false);
+                self.eq(inner);
+            }
+            Ty::Str => {
+                self.asm("movq %rax, %rdi");
+                self.asm("movq %rcx, %rsi");
+                self.asm("call ak_str_eq");
+            }
+            _ if matches!(self.class(ty), Class::Memory(_)) => {
+                let helper = self.eq_helper(ty);
+                self.asm("movq %rax, %rdi");
+                self.asm("movq %rcx, %rsi");
+                self.asm(format!("call {helper}"));
+            }
+            _ => self.asm("movl $1, %eax"),
+        }
+    }
+
+    // ----------------------------------------------------------- helpers
+
+    /// A function generated by `build` on first use, which gets its
+    /// arguments in the slots it is passed.
+    fn helper(&mut self, key: String, build: impl FnOnce(&mut Self, i32, i32)) -> String {
+        if let Some(name) = self.helpers.get(&key) {
+            return name.clone();
+        }
+        let name = format!("ak_helper{}", self.helpers.len());
+        self.helpers.insert(key, name.clone());
+
+        let outer = std::mem::take(&mut self.func);
+        self.begin();
+        let (a, b) = (self.slot(8, 8), self.slot(8, 8));
+        self.asm(format!("movq %rdi, {a}(%rbp)"));
+        self.asm(format!("movq %rsi, {b}(%rbp)"));
+        build(self, a, b);
+        self.finish(&name);
+        self.func = outer;
+        name
+    }
+
+    /// A function printing values of a type kept in memory, taking their
+    /// address and whether strings and chars are quoted.
+    fn show(&mut self, ty: &Ty) -> String {
+        let ty = ty.clone();
+        self.helper(format!("show {ty}"), |this, ptr, nested| {
+            let field = |this: &mut Self, offset: u32, ty: &Ty, nested: &str| {
+                this.asm(format!("movq {ptr}(%rbp), %rax"));
+                if offset != 0 {
+                    this.asm(format!("addq ${offset}, %rax"));
+                }
+                this.load(ty);
+                this.print(ty, nested);
+            };
+
+            match &ty {
+                Ty::Tuple(elems) => {
+                    let (offsets, ..) = this.fields_layout(elems.iter());
+                    this.text_out("(");
+                    for (idx, (elem, offset)) in elems.iter().zip(offsets).enumerate() {
+                        if idx != 0 {
+                            this.text_out(", ");
+                        }
+                        field(this, offset, elem, "$1");
+                    }
+                    this.text_out(if elems.len() == 1 { ",)" } else { ")" });
+                }
+                Ty::Optional(inner) => {
+                    let (none, end) = (this.new_label(), this.new_label());
+                    this.asm(format!("movq {ptr}(%rbp), %rax"));
+                    this.asm("cmpb $0, (%rax)");
+                    this.asm(format!("je {none}"));
+                    let offset = this.size_align(inner).1.max(1);
+                    field(this, offset, inner, &format!("{nested}(%rbp)"));
+                    this.asm(format!("jmp {end}"));
+                    this.label(&none);
+                    this.text_out("none");
+                    this.label(&end);
+                }
+                Ty::Array(elem, _) | Ty::Slice(elem) | Ty::Ref { inner: elem, .. } => {
+                    let elem = match &**elem {
+                        Ty::Slice(elem) => (**elem).clone(),
+                        elem => elem.clone(),
+                    };
+                    let size = this.size_align(&elem).0;
+                    let (data, len, idx) = (this.slot(8, 8), this.slot(8, 8), this.slot(8, 8));
+                    this.asm(format!("movq {ptr}(%rbp), %rax"));
+                    match &ty {
+                        Ty::Array(_, n) => {
+                            this.asm(format!("movq %rax, {data}(%rbp)"));
+                            this.asm(format!("movq ${n}, {len}(%rbp)"));
+                        }
+                        _ => {
+                            this.asm("movq 8(%rax), %rcx");
+                            this.asm(format!("movq %rcx, {len}(%rbp)"));
+                            this.asm("movq (%rax), %rax");
+                            this.asm(format!("movq %rax, {data}(%rbp)"));
+                        }
+                    }
+                    this.asm(format!("movq $0, {idx}(%rbp)"));
+
+                    this.text_out("[");
+                    let (top, first, exit) = (this.new_label(), this.new_label(), this.new_label());
+                    this.label(&top);
+                    this.asm(format!("movq {idx}(%rbp), %rax"));
+                    this.asm(format!("cmpq {len}(%rbp), %rax"));
+                    this.asm(format!("jae {exit}"));
+                    this.asm("testq %rax, %rax");
+                    this.asm(format!("je {first}"));
+                    this.text_out(", ");
+                    this.label(&first);
+                    this.asm(format!("movq {idx}(%rbp), %rax"));
+                    this.asm(format!("imulq ${size}, %rax"));
+                    this.asm(format!("addq {data}(%rbp), %rax"));
+                    this.load(&elem);
+                    this.print(&elem, "$1");
+                    this.asm(format!("incq {idx}(%rbp)"));
+                    this.asm(format!("jmp {top}"));
+                    this.label(&exit);
+                    this.text_out("]");
+                }
+                Ty::Path(path) => {
+                    let Some(def) = this.res.def_by_path(path) else {
+                        return;
+                    };
+                    let name = this.res.def(def).name.as_str();
+                    if this.res.def(def).kind != DefKind::Enum {
+                        this.text_out(&format!("{name} {{ "));
+                        for (idx, (field_name, offset, field_ty)) in
+                            this.struct_layout(def).iter().enumerate()
+                        {
+                            let sep = if idx == 0 { "" } else { ", " };
+                            let field_name = field_name.map_or("", |n| n.as_str());
+                            this.text_out(&format!("{sep}{field_name}: "));
+                            field(this, *offset, field_ty, "$1");
+                        }
+                        this.text_out(" }");
+                        return;
+                    }
+
+                    let end = this.new_label();
+                    let variants = this.types.enums.get(&def).cloned().unwrap_or_default();
+                    for (tag, variant) in variants.iter().enumerate() {
+                        let next = this.new_label();
+                        this.asm(format!("movq {ptr}(%rbp), %rax"));
+                        this.asm(format!("cmpl ${tag}, (%rax)"));
+                        this.asm(format!("jne {next}"));
+                        this.text_out(&format!("{name}::{}", this.res.def(*variant).name.as_str()));
+                        let fields = this.variant_layout(*variant);
+                        let named = matches!(
+                            this.types.variants.get(variant),
+                            Some(VariantShape::Struct(_))
+                        );
+                        if named {
+                            this.text_out(" { ");
+                        } else if !fields.is_empty() {
+                            this.text_out("(");
+                        }
+                        for (idx, (field_name, offset, field_ty)) in fields.iter().enumerate() {
+                            if idx != 0 {
+                                this.text_out(", ");
+                            }
+                            if let Some(field_name) = field_name {
+                                this.text_out(&format!("{}: ", field_name.as_str()));
+                            }
+                            field(this, *offset, field_ty, "$1");
+                        }
+                        if named {
+                            this.text_out(" }");
+                        } else if !fields.is_empty() {
+                            this.text_out(")");
+                        }
+                        this.asm(format!("jmp {end}"));
+                        this.label(&next);
+                    }
+                    this.label(&end);
+                }
+                _ => (),
+            }
+        })
+    }
+
+    /// A function comparing two values of a type kept in memory, by address.
+    fn eq_helper(&mut self, ty: &Ty) -> String {
+        let ty = ty.clone();
+        self.helper(format!("eq {ty}"), |this, a, b| {
+            let (unequal, done) = (this.new_label(), this.new_label());
+            // jumps to `unequal` unless the fields at `offset` are equal
+            let field = |this: &mut Self, offset: u32, ty: &Ty| {
+                for (ptr, second) in [(b, true), (a, false)] {
+                    this.asm(format!("movq {ptr}(%rbp), %rax"));
+                    if offset != 0 {
+                        this.asm(format!("addq ${offset}, %rax"));
+                    }
+                    this.load(ty);
+                    if second {
+                        let saved = this.save(ty);
+                        this.restore(ty, saved, true);
+                    }
+                }
+                this.eq(ty);
+                this.asm("testb %al, %al");
+                this.asm(format!("je {unequal}"));
+            };
+
+            match &ty {
+                Ty::Tuple(elems) => {
+                    let (offsets, ..) = this.fields_layout(elems.iter());
+                    for (elem, offset) in elems.iter().zip(offsets) {
+                        field(this, offset, elem);
+                    }
+                }
+                Ty::Optional(inner) => {
+                    this.asm(format!("movq {a}(%rbp), %rax"));
+                    this.asm(format!("movq {b}(%rbp), %rcx"));
+                    this.asm("movzbl (%rax), %eax");
+                    this.asm("cmpb (%rcx), %al");
+                    this.asm(format!("jne {unequal}"));
+                    let equal = this.new_label();
+                    this.asm("testb %al, %al");
+                    this.asm(format!("je {equal}"));
+                    let offset = this.size_align(inner).1.max(1);
+                    field(this, offset, inner);
+                    this.label(&equal);
+                }
+                Ty::Array(elem, _) | Ty::Slice(elem) | Ty::Ref { inner: elem, .. } => {
+                    let elem = match &**elem {
+                        Ty::Slice(elem) => (**elem).clone(),
+                        elem => elem.clone(),
+                    };
+                    let size = this.size_align(&elem).0;
+                    let (len, idx) = (this.slot(8, 8), this.slot(8, 8));
+                    match &ty {
+                        Ty::Array(_, n) => this.asm(format!("movq ${n}, {len}(%rbp)")),
+                        _ => {
+                            // compare the elements where each slice points
+                            this.asm(format!("movq {a}(%rbp), %rax"));
+                            this.asm(format!("movq {b}(%rbp), %rcx"));
+                            this.asm("movq 8(%rax), %rdx");
+                            this.asm(format!("movq %rdx, {len}(%rbp)"));
+                            this.asm("cmpq 8(%rcx), %rdx");
+                            this.asm(format!("jne {unequal}"));
+                            this.asm("movq (%rax), %rax");
+                            this.asm("movq (%rcx), %rcx");
+                            this.asm(format!("movq %rax, {a}(%rbp)"));
+                            this.asm(format!("movq %rcx, {b}(%rbp)"));
+                        }
+                    }
+                    this.asm(format!("movq $0, {idx}(%rbp)"));
+
+                    let (top, exit) = (this.new_label(), this.new_label());
+                    this.label(&top);
+                    this.asm(format!("movq {idx}(%rbp), %rax"));
+                    this.asm(format!("cmpq {len}(%rbp), %rax"));
+                    this.asm(format!("jae {exit}"));
+                    for (ptr, second) in [(b, true), (a, false)] {
+                        this.asm(format!("movq {idx}(%rbp), %rax"));
+                        this.asm(format!("imulq ${size}, %rax"));
+                        this.asm(format!("addq {ptr}(%rbp), %rax"));
+                        this.load(&elem);
+                        if second {
+                            let saved = this.save(&elem);
+                            this.restore(&elem, saved, true);
+                        }
+                    }
+                    this.eq(&elem);
+                    this.asm("testb %al, %al");
+                    this.asm(format!("je {unequal}"));
+                    this.asm(format!("incq {idx}(%rbp)"));
+                    this.asm(format!("jmp {top}"));
+                    this.label(&exit);
+                }
+                Ty::Path(path) => {
+                    let Some(def) = this.res.def_by_path(path) else {
+                        return;
+                    };
+                    if this.res.def(def).kind != DefKind::Enum {
+                        for (_, offset, field_ty) in this.struct_layout(def) {
+                            field(this, offset, &field_ty);
+                        }
+                    } else {
+                        this.asm(format!("movq {a}(%rbp), %rax"));
+                        this.asm(format!("movq {b}(%rbp), %rcx"));
+                        this.asm("movl (%rax), %eax");
+                        this.asm("cmpl (%rcx), %eax");
+                        this.asm(format!("jne {unequal}"));
+                        // only the fields of the variant both values are
+                        let variants = this.types.enums.get(&def).cloned().unwrap_or_default();
+                        let equal = this.new_label();
+                        for (tag, variant) in variants.iter().enumerate() {
+                            let fields = this.variant_layout(*variant);
+                            if fields.is_empty() {
+                                continue;
+                            }
+                            let next = this.new_label();
+                            this.asm(format!("movq {a}(%rbp), %rax"));
+                            this.asm(format!("cmpl ${tag}, (%rax)"));
+                            this.asm(format!("jne {next}"));
+                            for (_, offset, field_ty) in fields {
+                                field(this, offset, &field_ty);
+                            }
+                            this.asm(format!("jmp {equal}"));
+                            this.label(&next);
+                        }
+                        this.label(&equal);
+                    }
+                }
+                _ => (),
+            }
+            this.asm("movl $1, %eax");
+            this.asm(format!("jmp {done}"));
+            this.label(&unequal);
+            this.asm("xorl %eax, %eax");
+            this.label(&done);
+        })
+    }
+}
+
+fn align(value: u32, to: u32) -> u32 {
+    value.div_ceil(to) * to
+}
+
+fn float_mov(float: FloatTy) -> &'static str {
+    match float {
+        FloatTy::F32 => "movss",
+        FloatTy::F64 => "movsd",
+    }
+}
+
+fn float_suffix(float: FloatTy) -> &'static str {
+    match float {
+        FloatTy::F32 => "ss",
+        FloatTy::F64 => "sd",
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            out.push(c);
+        } else {
+            _ = write!(out, "_u{:x}_", c as u32);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        process::{Command, Output},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::testing;
+
+    struct Outcome {
+        asm: String,
+        /// what running the linked program did, if there are binutils
+        run: Option<Output>,
+        expected: String,
+    }
+
+    fn tool(dir: &Path, program: &str, args: &[&str]) -> Option<()> {
+        let output = Command::new(program)
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .ok()?;
+        assert!(
+            output.status.success(),
+            "{program} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Some(())
+    }
+
+    /// Compiles `src` to assembly, then assembles, links and runs it with
+    /// the system binutils next to the interpreter.
+    fn run(src: &str) -> Outcome {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let checked = testing::check(src);
+        let mut asmgen = AsmGen::new(&checked.res, &checked.types);
+        let asm = asmgen.generate(&checked.script);
+        assert!(asmgen.bag.is_empty(), "codegen errors: {:?}", asmgen.bag.0);
+
+        let (expected, _) = checked.interpret();
+
+        let dir: PathBuf = std::env::temp_dir().join(format!(
+            "akuru-asmgen-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(RUNTIME_NAME), RUNTIME).unwrap();
+        std::fs::write(dir.join("main.s"), &asm).unwrap();
+
+        let built = cfg!(all(target_arch = "x86_64", target_os = "linux"))
+            .then(|| {
+                tool(&dir, "as", &["-o", "main.o", "main.s"])?;
+                tool(&dir, "as", &["-o", "akuru.o", RUNTIME_NAME])?;
+                tool(&dir, "ld", &["-o", "main", "main.o", "akuru.o"])
+            })
+            .flatten();
+        let run = built.map(|()| Command::new(dir.join("main")).output().unwrap());
+        _ = std::fs::remove_dir_all(&dir);
+        Outcome { asm, run, expected }
+    }
+
+    fn assert_agrees(outcome: &Outcome) {
+        if let Some(run) = &outcome.run {
+            assert!(
+                run.status.success(),
+                "{}",
+                String::from_utf8_lossy(&run.stderr)
+            );
+            assert_eq!(String::from_utf8_lossy(&run.stdout), outcome.expected);
+        }
+    }
+
+    #[test]
+    fn test_pass_arguments_beyond_the_registers() {
+        let outcome = run(
+            "fn many(a: i8, b: f64, c: u16, d: f32, e: i64, f: i32, g: u8, h: i32, i: f64,
+                    j: i64, k: f64, l: f64, m: f64, n: f64, o: f64, p: f64, q: u32): f64 {
+                a as f64 + b + c as f64 + d as f64 + e as f64 + f as f64 + g as f64 + h as f64 + i
+                    + j as f64 + k + l + m + n + o + p + q as f64
+             }
+             fn main() {
+                println(many(-1, 0.5, 60000, 1.5, -4000000000, 6, 255, 8, 0.25,
+                    10, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 4000000000));
+             }",
+        );
+
+        assert_eq!(outcome.expected, "60301.25\n");
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_nested_calls_keep_their_temporaries() {
+        let outcome = run("fn g(x: i32): i32 { x * 10 }
+             fn h(x: i32): i32 { x + 3 }
+             fn pair(x: i32, y: i32): i32 { x - y }
+             fn main() {
+                println((pair(g(1), h(2)), pair(h(g(2)), g(h(1))), 1 + g(2) * h(3) - pair(4, g(5))));
+             }");
+
+        assert_eq!(outcome.expected, "(5, -17, 167)\n");
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_return_structs_by_value() {
+        let outcome = run("struct Big { a: i64, b: f64, c: [i32; 3] }
+             fn make(n: i64): Big { Big { a: n, b: n as f64 / 2.0, c: [1, 2, n as i32] } }
+             fn sum(b: Big): f64 { b.a as f64 + b.b + (b.c[0] + b.c[1] + b.c[2]) as f64 }
+             fn main() {
+                let big = make(7);
+                println((big, sum(make(40)), make(-3).c[2]));
+             }");
+
+        assert_eq!(
+            outcome.expected,
+            "(Big { a: 7, b: 3.5, c: [1, 2, 7] }, 103.0, -3)\n"
+        );
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_signed_division_and_shifts() {
+        let outcome = run("fn main() {
+                let n: i32 = -17;
+                let s: u8 = 3;
+                let w: i64 = -1;
+                println((n / 5, n % 5, 17 / -5, n >> s, n << s, w as u64 >> 60 as u64, 1 as i64 << 40 as u8));
+             }");
+
+        assert_eq!(
+            outcome.expected,
+            "(-3, -2, -3, -3, -136, 15, 1099511627776)\n"
+        );
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_runtime_errors_exit_with_a_message() {
+        let outcome = run("fn main() {
+                println(1);
+                let xs = [1, 2, 3];
+                let mut i = 1;
+                while i < 5 { i += 2; }
+                println(xs[i]);
+             }");
+
+        assert_eq!(outcome.expected, "1\n");
+        if let Some(run) = &outcome.run {
+            assert_eq!(run.status.code(), Some(101));
+            assert_eq!(String::from_utf8_lossy(&run.stdout), "1\n");
+            assert_eq!(
+                String::from_utf8_lossy(&run.stderr),
+                "error: index out of bounds: the length is 3 but the index is 5\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_mangle_section_paths() {
+        let outcome = run("section math {
+                pub fn add(a: i32, b: i32): i32 { a + b }
+             }
+             fn main() { println(math::add(1, 2)); }");
+
+        assert!(outcome.asm.contains("\nak_4math3add:\n"));
+        assert!(outcome.asm.contains("call ak_4main\n"));
+        assert_agrees(&outcome);
+    }
+}
@@ -1,4 +1,11 @@
-use crate::{interner::Symbol, span::Span, ty::Ty};
+use std::fmt;
+
+use crate::{
+    interner::Symbol,
+    span::Span,
+    tokens::TokenKind,
+    ty::{FloatTy, IntTy, Ty},
+};
 
 #[derive(Debug, Clone)]
 pub struct Script {
@@ -8,9 +15,14 @@ pub struct Script {
 #[derive(Debug, Clone)]
 pub struct Section {
     pub name: Spanned<Symbol>,
+    /// the `//!` comments at its start
+    pub docs: Docs,
     pub items: Vec<Item>,
 }
 
+/// The lines of a run of `///` or `//!` comments, after the markers.
+pub type Docs = Vec<Spanned<Symbol>>;
+
 #[derive(Debug, Clone)]
 pub enum Item {
     Fn(FnItem),
@@ -20,8 +32,31 @@ pub enum Item {
     Const(ConstItem),
     Define(DefineItem),
     Open(OpenItem),
+    /// an item that failed to parse
+    Error(Span),
     // tbc
 }
+
+impl Item {
+    /// The `///` comments documenting the item.
+    pub fn docs(&self) -> &[Spanned<Symbol>] {
+        match self {
+            Self::Fn(item) | Self::Define(DefineItem::Fn(item)) => &item.docs,
+            Self::Const(item) | Self::Define(DefineItem::Const(item)) => &item.docs,
+            Self::Struct(item) => &item.docs,
+            Self::Enum(item) => &item.docs,
+            Self::Section(item) => &item.docs,
+            Self::Open(_) | Self::Error(_) => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    Private,
+}
+
 #[derive(Debug, Clone)]
 pub struct OpenItem {
     pub path: Vec<Spanned<Symbol>>,
@@ -38,6 +73,8 @@ pub enum ImportItem {
 
 #[derive(Debug, Clone)]
 pub struct FnItem {
+    pub docs: Docs,
+    pub vis: Visibility,
     pub name: Spanned<Symbol>,
     pub params: Vec<Spanned<Field>>,
     pub ret: Option<Spanned<Ty>>,
@@ -52,12 +89,16 @@ pub struct Field {
 
 #[derive(Debug, Clone)]
 pub struct StructItem {
+    pub docs: Docs,
+    pub vis: Visibility,
     pub name: Spanned<Symbol>,
     pub fields: Vec<Spanned<Field>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct EnumItem {
+    pub docs: Docs,
+    pub vis: Visibility,
     pub name: Spanned<Symbol>,
     pub variants: Vec<Spanned<EnumVariants>>,
 }
@@ -76,13 +117,19 @@ pub enum EnumVariants {
 
 #[derive(Debug, Clone)]
 pub struct SectionItem {
+    pub docs: Docs,
+    pub vis: Visibility,
     pub sections: Spanned<Section>,
+    /// `section name;`, whose items live in `name.ak` or `name/section.ak`
+    pub external: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstItem {
+    pub docs: Docs,
+    pub vis: Visibility,
     pub name: Spanned<Symbol>,
-    pub ty: Spanned<Ty>,
+    pub ty: Option<Spanned<Ty>>,
     pub value: Spanned<Expr>,
 }
 
@@ -94,10 +141,16 @@ pub enum DefineItem {
 
 pub type SpannedBox<T> = Spanned<Box<T>>;
 
+/// `a::b::c`, `root::a`, `self::a`
+pub type Path = Vec<Spanned<Symbol>>;
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Literal(Literal),
     Variable(Symbol),
+    Path(Path),
+    Tuple(Vec<Spanned<Expr>>),
+    Array(Vec<Spanned<Expr>>),
     Range {
         start: Option<SpannedBox<Expr>>,
         end: Option<SpannedBox<Expr>>,
@@ -114,6 +167,14 @@ pub enum Expr {
         args: Vec<Spanned<Expr>>,
     },
     Index(SpannedBox<Expr>, SpannedBox<Expr>),
+    /// expr as Ty
+    Cast(SpannedBox<Expr>, Spanned<Ty>),
+    /// |x, y: u8| x + y, |x|: u8 { x }, || 0
+    Closure {
+        params: Vec<Spanned<ClosureParam>>,
+        ret: Option<Spanned<Ty>>,
+        body: SpannedBox<Expr>,
+    },
     If {
         cond: SpannedBox<Expr>,
         then: SpannedBox<Expr>,
@@ -124,6 +185,12 @@ pub enum Expr {
         cond: SpannedBox<Expr>,
         body: BlockExpr,
     },
+    /// `for pattern in iter { body }`, see SPECIFICATION.md for what can be iterated
+    For {
+        pattern: Spanned<Pattern>,
+        iter: SpannedBox<Expr>,
+        body: BlockExpr,
+    },
     Break(Option<SpannedBox<Expr>>),
     Continue,
     Return(Option<SpannedBox<Expr>>),
@@ -138,23 +205,33 @@ pub enum Expr {
         value: SpannedBox<Expr>,
     },
     StructInit {
-        name: Spanned<Symbol>,
-        fields: Vec<Spanned<Field>>,
-    },
-    TupleInit {
-        name: Spanned<Symbol>,
-        tys: Vec<Spanned<Ty>>,
+        path: Path,
+        fields: Vec<Spanned<FieldInit>>,
     },
     Match {
         scrutinee: SpannedBox<Expr>,
         arms: Vec<MatchArm>,
     },
+    /// an expression that failed to parse
+    Error(Span),
+}
+
+#[derive(Debug, Clone)]
+pub struct ClosureParam {
+    pub name: Spanned<Symbol>,
+    pub ty: Option<Spanned<Ty>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldInit {
+    pub name: Spanned<Symbol>,
+    pub value: Spanned<Expr>,
 }
 
 #[derive(Debug, Clone)]
 pub struct MatchArm {
     pub pattern: Spanned<Pattern>,
-    pub body: Expr,
+    pub body: Spanned<Expr>,
 }
 
 #[derive(Debug, Clone)]
@@ -163,53 +240,255 @@ pub enum Pattern {
     Literal(Spanned<Literal>),
     Variable(Spanned<Symbol>),
     Tuple {
-        name: Spanned<Symbol>,
-        tys: Vec<Spanned<Pattern>>,
+        path: Path,
+        elems: Vec<Spanned<Pattern>>,
     },
     Struct {
-        name: Spanned<Symbol>,
-        fields: Vec<Spanned<Field>>,
+        path: Path,
+        fields: Vec<Spanned<FieldPattern>>,
     },
+    /// a pattern that failed to parse
+    Error(Span),
     // tbc
 }
 
+#[derive(Debug, Clone)]
+pub struct FieldPattern {
+    pub name: Spanned<Symbol>,
+    pub pattern: Spanned<Pattern>,
+}
+
 #[derive(Debug, Clone)]
 pub struct BlockExpr {
     pub stmts: Vec<Stmt>,
     pub expr: Option<SpannedBox<Expr>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-    UInt(u64),
+    /// with the type its suffix names, as in `255u8`
+    UInt(u64, Option<IntTy>),
     Int(i64),
-    Float(f64),
+    Float(f64, Option<FloatTy>),
     Bool(bool),
     Char(char),
     String(Symbol),
     None,
 }
 
-#[derive(Debug, Clone)]
-pub enum BinaryOp {}
-#[derive(Debug, Clone)]
-pub enum PrefixOp {}
-#[derive(Debug, Clone)]
-pub enum PostfixOp {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    BitAnd,
+    BitOr,
+    BitXor,
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+impl BinaryOp {
+    /// The operator applied by a compound assignment, `+=` gives `Add`.
+    pub fn from_compound_assign(kind: TokenKind) -> Option<Self> {
+        let op = match kind {
+            TokenKind::PlusEq => Self::Add,
+            TokenKind::MinusEq => Self::Sub,
+            TokenKind::StarEq => Self::Mul,
+            TokenKind::SlashEq => Self::Div,
+            TokenKind::PercentEq => Self::Rem,
+            TokenKind::LShiftEq => Self::Shl,
+            TokenKind::RShiftEq => Self::Shr,
+            TokenKind::AndEq => Self::BitAnd,
+            TokenKind::PipeEq => Self::BitOr,
+            TokenKind::CaretEq => Self::BitXor,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            Self::Add | Self::Sub | Self::Mul | Self::Div | Self::Rem
+        )
+    }
+
+    pub fn is_bitwise(&self) -> bool {
+        matches!(
+            self,
+            Self::Shl | Self::Shr | Self::BitAnd | Self::BitOr | Self::BitXor
+        )
+    }
+
+    pub fn is_logical(&self) -> bool {
+        matches!(self, Self::And | Self::Or)
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Ne | Self::Lt | Self::Gt | Self::Le | Self::Ge
+        )
+    }
+}
+
+impl TryFrom<TokenKind> for BinaryOp {
+    type Error = TokenKind;
+
+    fn try_from(kind: TokenKind) -> Result<Self, Self::Error> {
+        let op = match kind {
+            TokenKind::Plus => Self::Add,
+            TokenKind::Minus => Self::Sub,
+            TokenKind::Star => Self::Mul,
+            TokenKind::Slash => Self::Div,
+            TokenKind::Percent => Self::Rem,
+            TokenKind::LShift => Self::Shl,
+            TokenKind::RShift => Self::Shr,
+            TokenKind::And => Self::BitAnd,
+            TokenKind::Pipe => Self::BitOr,
+            TokenKind::Caret => Self::BitXor,
+            TokenKind::AndAnd => Self::And,
+            TokenKind::PipePipe => Self::Or,
+            TokenKind::EqEq => Self::Eq,
+            TokenKind::BangEq => Self::Ne,
+            TokenKind::Lt => Self::Lt,
+            TokenKind::Gt => Self::Gt,
+            TokenKind::LtEq => Self::Le,
+            TokenKind::GtEq => Self::Ge,
+            _ => return Err(kind),
+        };
+
+        Ok(op)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::BitAnd => "&",
+            Self::BitOr => "|",
+            Self::BitXor => "^",
+            Self::And => "&&",
+            Self::Or => "||",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Gt => ">",
+            Self::Le => "<=",
+            Self::Ge => ">=",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixOp {
+    /// -x
+    Neg,
+    /// !x
+    Not,
+    /// *x
+    Deref,
+    /// &x
+    Ref,
+    /// &mut x
+    RefMut,
+}
+
+/// `&mut` spans two tokens, so `&` converts to `Ref` and the parser upgrades it.
+impl TryFrom<TokenKind> for PrefixOp {
+    type Error = TokenKind;
+
+    fn try_from(kind: TokenKind) -> Result<Self, Self::Error> {
+        let op = match kind {
+            TokenKind::Minus => Self::Neg,
+            TokenKind::Bang => Self::Not,
+            TokenKind::Star => Self::Deref,
+            TokenKind::And => Self::Ref,
+            _ => return Err(kind),
+        };
+
+        Ok(op)
+    }
+}
+
+impl fmt::Display for PrefixOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Neg => "-",
+            Self::Not => "!",
+            Self::Deref => "*",
+            Self::Ref => "&",
+            Self::RefMut => "&mut ",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostfixOp {
+    /// x++
+    Inc,
+    /// x--
+    Dec,
+}
+
+impl TryFrom<TokenKind> for PostfixOp {
+    type Error = TokenKind;
+
+    fn try_from(kind: TokenKind) -> Result<Self, Self::Error> {
+        match kind {
+            TokenKind::PlusPlus => Ok(Self::Inc),
+            TokenKind::MinusMinus => Ok(Self::Dec),
+            _ => Err(kind),
+        }
+    }
+}
+
+impl fmt::Display for PostfixOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inc => write!(f, "++"),
+            Self::Dec => write!(f, "--"),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Let {
+        mutable: bool,
         name: Spanned<Symbol>,
         ty: Option<Spanned<Ty>>,
-        value: Option<Expr>,
+        value: Option<Spanned<Expr>>,
     },
     Open(OpenItem),
-    Expr(Expr),
-    Semi(Expr),
+    Expr(Spanned<Expr>),
+    Semi(Spanned<Expr>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Spanned<T> {
     pub node: T,
     pub span: Span,
@@ -224,3 +503,74 @@ impl<T: Sized> Spannable for T {
         Spanned { node: self, span }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_op_round_trips_through_tokens() {
+        let tokens = [
+            TokenKind::Plus,
+            TokenKind::Minus,
+            TokenKind::Star,
+            TokenKind::Slash,
+            TokenKind::Percent,
+            TokenKind::LShift,
+            TokenKind::RShift,
+            TokenKind::And,
+            TokenKind::Pipe,
+            TokenKind::Caret,
+            TokenKind::AndAnd,
+            TokenKind::PipePipe,
+            TokenKind::EqEq,
+            TokenKind::BangEq,
+            TokenKind::Lt,
+            TokenKind::Gt,
+            TokenKind::LtEq,
+            TokenKind::GtEq,
+        ];
+
+        for token in tokens {
+            let op = BinaryOp::try_from(token).unwrap();
+            assert_eq!(format!("`{op}`"), token.to_string());
+        }
+
+        assert_eq!(BinaryOp::try_from(TokenKind::Eq), Err(TokenKind::Eq));
+    }
+
+    #[test]
+    fn test_compound_assign_maps_to_binary_op() {
+        let pairs = [
+            (TokenKind::PlusEq, BinaryOp::Add),
+            (TokenKind::MinusEq, BinaryOp::Sub),
+            (TokenKind::StarEq, BinaryOp::Mul),
+            (TokenKind::SlashEq, BinaryOp::Div),
+            (TokenKind::PercentEq, BinaryOp::Rem),
+            (TokenKind::LShiftEq, BinaryOp::Shl),
+            (TokenKind::RShiftEq, BinaryOp::Shr),
+            (TokenKind::AndEq, BinaryOp::BitAnd),
+            (TokenKind::PipeEq, BinaryOp::BitOr),
+            (TokenKind::CaretEq, BinaryOp::BitXor),
+        ];
+
+        for (token, op) in pairs {
+            assert!(token.is_assignment());
+            assert_eq!(BinaryOp::from_compound_assign(token), Some(op));
+            assert_eq!(format!("`{op}=`"), token.to_string());
+        }
+
+        assert_eq!(BinaryOp::from_compound_assign(TokenKind::Eq), None);
+    }
+
+    #[test]
+    fn test_unary_op_display() {
+        assert_eq!(PrefixOp::try_from(TokenKind::And), Ok(PrefixOp::Ref));
+        assert_eq!(PrefixOp::RefMut.to_string(), "&mut ");
+        assert_eq!(
+            PostfixOp::try_from(TokenKind::MinusMinus),
+            Ok(PostfixOp::Dec)
+        );
+        assert_eq!(PostfixOp::Inc.to_string(), "++");
+    }
+}
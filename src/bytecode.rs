@@ -0,0 +1,383 @@
+use std::{fmt, fmt::Write, rc::Rc};
+
+use crate::{
+    ast::BinaryOp,
+    source::SourceMap,
+    span::Span,
+    ty::{FloatTy, IntTy, Ty},
+    value::{Builtin, Layout, Value},
+};
+
+/// A single VM instruction. Operands index into the tables of the
+/// [`Module`] or the slots of the running function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// push a constant from the pool
+    Const(u32),
+    Unit,
+    True,
+    False,
+    None,
+    Pop,
+    /// drop `n` values
+    PopN(u32),
+    Dup,
+    /// drop `n` values under the top one
+    Slide(u32),
+
+    /// push the value in a slot
+    Load(u32),
+    /// pop into the variable already in a slot
+    Store(u32),
+    /// pop into a new variable in a slot, leaving closures that captured
+    /// the old one untouched
+    Define(u32),
+    /// push a reference to a slot
+    Ref(u32),
+    /// push the value of a constant item, computing it on first use
+    Global(u32),
+    Func(u32),
+    Builtin(Builtin),
+    /// push a tuple variant constructor
+    Ctor(u32),
+    /// create a closure over the current function's slots
+    Closure(u32),
+
+    Tuple(u32),
+    Array(u32),
+    Struct {
+        layout: u32,
+        len: u32,
+    },
+    Range {
+        start: bool,
+        end: bool,
+        inclusive: bool,
+    },
+
+    Binary(BinaryOp),
+    Neg,
+    Not,
+    Cast(Scalar),
+    /// replace a reference with the value it points to
+    Deref,
+    /// replace a struct or tuple with one of its fields
+    Field(u32),
+    /// pop an index or range and index the value under it
+    Index,
+
+    /// turn a value into a reference to a temporary
+    Temp,
+    /// replace a reference with one to a field of its target
+    Project(u32),
+    /// pop an index and replace the reference under it with one to an element
+    ProjectIndex,
+    /// pop a reference and a value, and store the value
+    Set,
+    /// pop a reference and a value, and apply the operator in place
+    Update(BinaryOp),
+    /// replace a reference with its integer target, which is then stepped
+    /// by one with the given operator
+    Bump(BinaryOp),
+    /// replace a value with whether it is a struct or variant of a layout
+    Tag(u32),
+
+    Jump(u32),
+    /// pop a value and jump if it is false
+    JumpIfFalse(u32),
+    /// pop a value and jump if it is true
+    JumpIfTrue(u32),
+    /// pop something to iterate, keeping its state in two slots
+    Iter(u32),
+    /// push the next element of the iteration in a slot, or jump when done
+    Next {
+        slot: u32,
+        exit: u32,
+    },
+    /// pop the callee and its arguments, and push its result
+    Call(u32),
+    Return,
+    /// report that no `match` arm fits the value in a slot
+    NoMatch(u32),
+}
+
+impl Op {
+    /// How many values the op pops and then pushes when it carries on to
+    /// the next op. `next` pushes nothing when it jumps to its exit.
+    pub fn effect(&self) -> (u32, u32) {
+        match *self {
+            Self::Const(_)
+            | Self::Unit
+            | Self::True
+            | Self::False
+            | Self::None
+            | Self::Load(_)
+            | Self::Ref(_)
+            | Self::Global(_)
+            | Self::Func(_)
+            | Self::Builtin(_)
+            | Self::Ctor(_)
+            | Self::Closure(_)
+            | Self::Next { .. } => (0, 1),
+            Self::Pop | Self::Store(_) | Self::Define(_) | Self::Iter(_) => (1, 0),
+            Self::PopN(count) => (count, 0),
+            Self::Dup => (1, 2),
+            Self::Slide(count) => (count.saturating_add(1), 1),
+            Self::Tuple(len) | Self::Array(len) | Self::Struct { len, .. } => (len, 1),
+            Self::Range { start, end, .. } => (start as u32 + end as u32, 1),
+            Self::Binary(_) | Self::Index | Self::ProjectIndex => (2, 1),
+            Self::Neg
+            | Self::Not
+            | Self::Cast(_)
+            | Self::Deref
+            | Self::Field(_)
+            | Self::Temp
+            | Self::Project(_)
+            | Self::Bump(_)
+            | Self::Tag(_) => (1, 1),
+            Self::Set | Self::Update(_) => (2, 0),
+            Self::Jump(_) | Self::NoMatch(_) => (0, 0),
+            Self::JumpIfFalse(_) | Self::JumpIfTrue(_) | Self::Return => (1, 0),
+            Self::Call(argc) => (argc.saturating_add(1), 1),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Const(idx) => write!(f, "const {idx}"),
+            Self::Unit => write!(f, "unit"),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+            Self::None => write!(f, "none"),
+            Self::Pop => write!(f, "pop"),
+            Self::PopN(count) => write!(f, "pop_n {count}"),
+            Self::Dup => write!(f, "dup"),
+            Self::Slide(count) => write!(f, "slide {count}"),
+            Self::Load(slot) => write!(f, "load {slot}"),
+            Self::Store(slot) => write!(f, "store {slot}"),
+            Self::Define(slot) => write!(f, "define {slot}"),
+            Self::Ref(slot) => write!(f, "ref {slot}"),
+            Self::Global(idx) => write!(f, "global {idx}"),
+            Self::Func(idx) => write!(f, "func {idx}"),
+            Self::Builtin(builtin) => write!(f, "builtin {}", builtin.name()),
+            Self::Ctor(layout) => write!(f, "ctor {layout}"),
+            Self::Closure(idx) => write!(f, "closure {idx}"),
+            Self::Tuple(len) => write!(f, "tuple {len}"),
+            Self::Array(len) => write!(f, "array {len}"),
+            Self::Struct { layout, len } => write!(f, "struct {layout} {len}"),
+            Self::Range {
+                start,
+                end,
+                inclusive,
+            } => write!(
+                f,
+                "range {}{}{}",
+                if *start { "a" } else { "" },
+                if *inclusive { "..=" } else { ".." },
+                if *end { "b" } else { "" }
+            ),
+            Self::Binary(op) => write!(f, "binary {op}"),
+            Self::Neg => write!(f, "neg"),
+            Self::Not => write!(f, "not"),
+            Self::Cast(to) => write!(f, "cast {}", to.ty()),
+            Self::Deref => write!(f, "deref"),
+            Self::Field(idx) => write!(f, "field {idx}"),
+            Self::Index => write!(f, "index"),
+            Self::Temp => write!(f, "temp"),
+            Self::Project(idx) => write!(f, "project {idx}"),
+            Self::ProjectIndex => write!(f, "project_index"),
+            Self::Set => write!(f, "set"),
+            Self::Update(op) => write!(f, "update {op}"),
+            Self::Bump(op) => write!(f, "bump {op}"),
+            Self::Tag(layout) => write!(f, "tag {layout}"),
+            Self::Jump(target) => write!(f, "jump {target}"),
+            Self::JumpIfFalse(target) => write!(f, "jump_if_false {target}"),
+            Self::JumpIfTrue(target) => write!(f, "jump_if_true {target}"),
+            Self::Iter(slot) => write!(f, "iter {slot}"),
+            Self::Next { slot, exit } => write!(f, "next {slot} {exit}"),
+            Self::Call(argc) => write!(f, "call {argc}"),
+            Self::Return => write!(f, "return"),
+            Self::NoMatch(slot) => write!(f, "no_match {slot}"),
+        }
+    }
+}
+
+/// The target of a cast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    Int(IntTy),
+    Float(FloatTy),
+    Bool,
+    Char,
+}
+
+impl Scalar {
+    pub fn from_ty(ty: &Ty) -> Option<Self> {
+        match ty {
+            Ty::Int(ty) => Some(Self::Int(*ty)),
+            Ty::Float(ty) => Some(Self::Float(*ty)),
+            Ty::Bool => Some(Self::Bool),
+            Ty::Char => Some(Self::Char),
+            _ => None,
+        }
+    }
+
+    pub fn ty(&self) -> Ty {
+        match self {
+            Self::Int(ty) => Ty::Int(*ty),
+            Self::Float(ty) => Ty::Float(*ty),
+            Self::Bool => Ty::Bool,
+            Self::Char => Ty::Char,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Function {
+    /// the path of the function, `<closure>` or the constant it computes
+    pub name: String,
+    pub arity: u32,
+    /// locals, parameters and temporaries
+    pub slots: u32,
+    /// the enclosing function's slots a closure captures, which fill this
+    /// function's first slots, before the parameters
+    pub captures: Vec<u32>,
+    pub code: Vec<Op>,
+    /// the span of the code starting at each offset, sorted by offset
+    pub spans: Vec<(u32, Span)>,
+}
+
+impl Function {
+    /// The span of the instruction at `offset`.
+    pub fn span_at(&self, offset: usize) -> Option<Span> {
+        let idx = self
+            .spans
+            .partition_point(|(start, _)| *start as usize <= offset);
+        idx.checked_sub(1).map(|idx| self.spans[idx].1)
+    }
+}
+
+/// A compiled script.
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    /// literal values: integers, floats, chars and strings
+    pub constants: Vec<Value>,
+    pub functions: Vec<Function>,
+    /// structs and enum variants, a layout's id is its index here
+    pub layouts: Vec<Rc<Layout>>,
+    /// for each constant item, the function computing its value
+    pub globals: Vec<u32>,
+    pub main: Option<u32>,
+}
+
+impl Module {
+    /// What an instruction's operand refers to, for listings.
+    fn describe(&self, op: Op) -> Option<String> {
+        let layout = |idx: u32| self.layouts.get(idx as usize).map(|l| l.name.clone());
+        let function = |idx: u32| self.functions.get(idx as usize).map(|f| f.name.clone());
+
+        match op {
+            Op::Const(idx) => self.constants.get(idx as usize).map(|value| match value {
+                Value::Str(s) => format!("{s:?}"),
+                Value::Char(c) => format!("{c:?}"),
+                Value::Int(v, ty) => format!("{v}{}", ty.name()),
+                Value::Float(v, ty) => format!("{v:?}{}", ty.name()),
+                value => value.to_string(),
+            }),
+            Op::Func(idx) | Op::Closure(idx) => function(idx),
+            Op::Global(idx) => self
+                .globals
+                .get(idx as usize)
+                .and_then(|idx| function(*idx)),
+            Op::Ctor(idx) | Op::Tag(idx) | Op::Struct { layout: idx, .. } => layout(idx),
+            _ => None,
+        }
+    }
+}
+
+/// Lists every function of `module`, each instruction next to the source
+/// line it was compiled from.
+pub fn disassemble(module: &Module, map: &SourceMap) -> String {
+    let mut out = String::new();
+
+    for (idx, func) in module.functions.iter().enumerate() {
+        if idx != 0 {
+            out.push('\n');
+        }
+        let main = if module.main == Some(idx as u32) {
+            ", main"
+        } else {
+            ""
+        };
+        _ = write!(
+            out,
+            "fn {idx} `{}` (arity {}, slots {}",
+            func.name, func.arity, func.slots
+        );
+        if !func.captures.is_empty() {
+            _ = write!(out, ", captures {:?}", func.captures);
+        }
+        _ = writeln!(out, "{main})");
+
+        let mut last_line = None;
+        for (offset, op) in func.code.iter().enumerate() {
+            if let Some(span) = func.span_at(offset)
+                && let Some(source) = map.source.get(span.id.0 as usize)
+            {
+                let line = source.get_offset_line(span.lo);
+                if last_line != Some((span.id, line)) {
+                    last_line = Some((span.id, line));
+                    _ = writeln!(
+                        out,
+                        "           ; {}:{}: {}",
+                        source.name,
+                        line + 1,
+                        source.line_text(line).trim()
+                    );
+                }
+            }
+
+            let text = op.to_string();
+            match module.describe(*op) {
+                Some(note) => _ = writeln!(out, "    {offset:04}   {text:<20} ; {note}"),
+                None => _ = writeln!(out, "    {offset:04}   {text}"),
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::Compiler, testing};
+
+    #[test]
+    fn test_disassemble_with_source_lines() {
+        let checked = testing::check("fn main() {\n    let x: u8 = 1 + 2;\n    println(x);\n}");
+        let module = Compiler::new(&checked.res, &checked.types).compile_script(&checked.script);
+
+        let listing = disassemble(&module, &checked.map);
+        assert_eq!(
+            listing,
+            "fn 0 `main` (arity 0, slots 1, main)
+           ; test.ak:2: let x: u8 = 1 + 2;
+    0000   const 0              ; 1u8
+    0001   const 1              ; 2u8
+    0002   binary +
+    0003   define 0
+           ; test.ak:3: println(x);
+    0004   builtin println
+    0005   load 0
+    0006   call 1
+    0007   pop
+           ; test.ak:1: fn main() {
+    0008   unit
+    0009   return
+"
+        );
+    }
+}
@@ -0,0 +1,2025 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, Expr, FnItem, Item, Literal, Pattern,
+        PostfixOp, PrefixOp, Script, Section, Spanned, Stmt,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    resolve::{DefId, DefKind, Resolution, path_span},
+    span::Span,
+    ty::{FloatTy, IntTy, Ty},
+    typeck::{TypeckResults, VariantShape, range_elem},
+    value::{self, Value},
+};
+
+/// The runtime header every generated translation unit includes.
+pub const RUNTIME: &str = include_str!("../runtime/akuru.h");
+
+/// The file name generated code includes [`RUNTIME`] by.
+pub const RUNTIME_NAME: &str = "akuru.h";
+
+/// Translates a resolved and type checked script to a C99 translation unit.
+///
+/// Every expression becomes the statements computing it followed by a C
+/// expression for its value, usually a temporary, so blocks, `if`, `match`
+/// and `loop` work anywhere a value does. Items are named by their section
+/// path with each segment prefixed by its length, `math::add` is
+/// `ak_4math3add`. Structs are C structs and enums are a tag next to a
+/// union of their variants' fields.
+pub struct CGen<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    pub bag: DiagnosticsBag,
+    forward: String,
+    /// type definitions, each after the types it contains
+    typedefs: String,
+    helper_protos: String,
+    /// printing and comparison functions for compound types
+    helpers: String,
+    protos: String,
+    bodies: String,
+    /// the C name of each compound type, by its rendering
+    type_names: HashMap<String, String>,
+    /// structs and enums already defined, or being defined
+    adts: HashSet<DefId>,
+    helper_names: HashMap<String, String>,
+    func: FnState,
+}
+
+/// The function being generated.
+#[derive(Default)]
+struct FnState {
+    out: String,
+    depth: usize,
+    temps: u32,
+    ret: Option<Ty>,
+    /// for each enclosing loop, the temporary its value is broken into
+    loops: Vec<Option<(String, Ty)>>,
+}
+
+impl<'a> CGen<'a> {
+    pub fn new(res: &'a Resolution, types: &'a TypeckResults) -> Self {
+        Self {
+            res,
+            types,
+            bag: DiagnosticsBag::new(),
+            forward: String::new(),
+            typedefs: String::new(),
+            helper_protos: String::new(),
+            helpers: String::new(),
+            protos: String::new(),
+            bodies: String::new(),
+            type_names: HashMap::new(),
+            adts: HashSet::new(),
+            helper_names: HashMap::new(),
+            func: FnState::default(),
+        }
+    }
+
+    /// The C source of the script, which includes [`RUNTIME_NAME`].
+    pub fn generate(&mut self, script: &'a Script) -> String {
+        self.declare(&script.root);
+        self.define(&script.root);
+
+        let main = self
+            .res
+            .member(self.res.root, "main".intern())
+            .filter(|def| self.res.def(*def).kind == DefKind::Fn);
+
+        let mut out = format!("#include \"{RUNTIME_NAME}\"\n");
+        for part in [
+            &self.forward,
+            &self.typedefs,
+            &self.helper_protos,
+            &self.protos,
+            &self.helpers,
+            &self.bodies,
+        ] {
+            if !part.is_empty() {
+                out.push('\n');
+                out.push_str(part);
+            }
+        }
+
+        out.push_str("\nint main(void) {\n");
+        if let Some(main) = main {
+            _ = writeln!(out, "    {}();", self.item_name(main));
+        }
+        out.push_str("    return 0;\n}\n");
+        out
+    }
+
+    /// Writes the prototype of every function and constant, so bodies can
+    /// use items defined after them.
+    fn declare(&mut self, section: &Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        let head = self.fn_head(def, None);
+                        _ = writeln!(self.protos, "{head};");
+                    }
+                }
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span)
+                        && let Some(ty) = self.types.consts.get(&def)
+                    {
+                        let ty = self.c_type(ty);
+                        _ = writeln!(self.protos, "static {ty} {}(void);", self.item_name(def));
+                    }
+                }
+                Item::Section(item) => self.declare(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn define(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.gen_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => self.gen_const(item),
+                Item::Section(item) => self.define(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    /// `static R name(A, B)`, naming the parameters when `item` is given.
+    fn fn_head(&mut self, def: DefId, item: Option<&FnItem>) -> String {
+        let (tys, ret) = match self.types.fns.get(&def) {
+            Some(sig) => (sig.params.clone(), sig.ret.clone()),
+            None => (Vec::new(), Ty::unit()),
+        };
+
+        let ret = self.c_type(&ret);
+        let mut params = Vec::new();
+        for (idx, ty) in tys.iter().enumerate() {
+            let ty = self.c_type(ty);
+            let name = item
+                .and_then(|item| item.params.get(idx))
+                .and_then(|param| self.res.lookup(param.node.name.span))
+                .map(|def| format!(" {}", self.local_name(def)))
+                .unwrap_or_default();
+            params.push(format!("{ty}{name}"));
+        }
+        if params.is_empty() {
+            params.push("void".to_owned());
+        }
+
+        format!(
+            "static {ret} {}({})",
+            self.item_name(def),
+            params.join(", ")
+        )
+    }
+
+    fn gen_fn(&mut self, item: &'a FnItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let ret = self
+            .types
+            .fns
+            .get(&def)
+            .map(|sig| sig.ret.clone())
+            .unwrap_or_else(Ty::unit);
+
+        self.func = FnState {
+            depth: 1,
+            ret: Some(ret.clone()),
+            ..FnState::default()
+        };
+        let (value, ty) = self.block(&item.body);
+        if ty != Ty::Never {
+            let value = self.coerce(value, &ty, &ret);
+            self.line(format!("return {value};"));
+        }
+
+        let head = self.fn_head(def, Some(item));
+        let body = std::mem::take(&mut self.func).out;
+        _ = write!(self.bodies, "{head} {{\n{body}}}\n\n");
+    }
+
+    /// Constants are computed on first use, like the interpreter does.
+    fn gen_const(&mut self, item: &'a ConstItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let Some(ty) = self.types.consts.get(&def) else {
+            return;
+        };
+
+        self.func = FnState {
+            depth: 2,
+            ret: Some(ty.clone()),
+            ..FnState::default()
+        };
+        let value = self.expr(&item.value);
+        let value = self.coerce(value, &self.ty(item.value.span), ty);
+        self.line(format!("value = {value};"));
+        self.line("done = true;".to_owned());
+
+        let c_ty = self.c_type(ty);
+        let body = std::mem::take(&mut self.func).out;
+        _ = write!(
+            self.bodies,
+            "static {c_ty} {}(void) {{
+    static bool done = false;
+    static {c_ty} value;
+    if (!done) {{
+{body}    }}
+    return value;
+}}\n\n",
+            self.item_name(def)
+        );
+    }
+
+    // --------------------------------------------------------------- names
+
+    fn item_name(&self, def: DefId) -> String {
+        let mut name = "ak_".to_owned();
+        for segment in self.res.path_of(def) {
+            let segment = sanitize(segment.as_str());
+            _ = write!(name, "{}{segment}", segment.len());
+        }
+        name
+    }
+
+    fn local_name(&self, def: DefId) -> String {
+        format!("l_{}_{}", sanitize(self.res.def(def).name.as_str()), def.0)
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str) -> String {
+        self.bag.push(
+            Diagnostic::error(format!("{what} are not supported by the C backend"))
+                .with_label(span.primary("used here")),
+        );
+        "0".to_owned()
+    }
+
+    // --------------------------------------------------------------- types
+
+    fn c_type(&mut self, ty: &Ty) -> String {
+        match ty {
+            Ty::Int(int) => int_c_type(*int).to_owned(),
+            Ty::Float(FloatTy::F32) => "float".to_owned(),
+            Ty::Float(FloatTy::F64) => "double".to_owned(),
+            Ty::Bool => "bool".to_owned(),
+            Ty::Char => "ak_char".to_owned(),
+            Ty::Str => "ak_str".to_owned(),
+            // `&[T]` carries its length, so it is the slice itself
+            Ty::Ref { inner, .. } if matches!(**inner, Ty::Slice(_)) => self.c_type(inner),
+            Ty::Ref { inner, .. } => format!("{}*", self.c_type(inner)),
+            Ty::Tuple(elems) if elems.is_empty() => "ak_unit".to_owned(),
+            Ty::Path(path) => match self.res.def_by_path(path) {
+                Some(def) => self.adt(def),
+                None => "ak_unit".to_owned(),
+            },
+            Ty::Optional(_) | Ty::Tuple(_) | Ty::Array(..) | Ty::Slice(_) | Ty::Fn { .. } => {
+                self.compound(ty)
+            }
+            Ty::Generic { .. } | Ty::Infer | Ty::Var(_) | Ty::Never => "ak_unit".to_owned(),
+        }
+    }
+
+    /// The typedef of an optional, tuple, array, slice or function pointer.
+    fn compound(&mut self, ty: &Ty) -> String {
+        let key = ty.to_string();
+        if let Some(name) = self.type_names.get(&key) {
+            return name.clone();
+        }
+
+        let body = match ty {
+            Ty::Optional(inner) => format!("struct {{ bool some; {} value; }}", self.c_type(inner)),
+            Ty::Tuple(elems) => {
+                let mut fields = String::new();
+                for (idx, elem) in elems.iter().enumerate() {
+                    _ = write!(fields, "{} _{idx}; ", self.c_type(elem));
+                }
+                format!("struct {{ {fields}}}")
+            }
+            Ty::Array(elem, len) => {
+                format!("struct {{ {} at[{}]; }}", self.c_type(elem), (*len).max(1))
+            }
+            Ty::Slice(elem) => format!("struct {{ {} *ptr; size_t len; }}", self.c_type(elem)),
+            Ty::Fn { params, ret } => {
+                let ret = self.c_type(ret);
+                let mut params: Vec<_> = params.iter().map(|p| self.c_type(p)).collect();
+                if params.is_empty() {
+                    params.push("void".to_owned());
+                }
+                let name = format!("ak_t{}", self.type_names.len());
+                _ = writeln!(
+                    self.typedefs,
+                    "typedef {ret} (*{name})({}); /* {key} */",
+                    params.join(", ")
+                );
+                self.type_names.insert(key, name.clone());
+                return name;
+            }
+            _ => unreachable!("`{key}` is not a compound type"),
+        };
+
+        let name = format!("ak_t{}", self.type_names.len());
+        _ = writeln!(self.typedefs, "typedef {body} {name}; /* {key} */");
+        self.type_names.insert(key, name.clone());
+        name
+    }
+
+    /// The C name of a struct or enum, defining it on first use.
+    fn adt(&mut self, def: DefId) -> String {
+        let name = self.item_name(def);
+        if !self.adts.insert(def) {
+            return name;
+        }
+        _ = writeln!(self.forward, "typedef struct {name} {name};");
+
+        let mut body = String::new();
+        match self.res.def(def).kind {
+            DefKind::Enum => {
+                body.push_str("    uint32_t tag;\n    union {\n        uint8_t _none;\n");
+                let variants = self.types.enums.get(&def).cloned().unwrap_or_default();
+                for (idx, variant) in variants.iter().enumerate() {
+                    let fields = self.variant_fields(*variant);
+                    if fields.is_empty() {
+                        continue;
+                    }
+                    let mut members = String::new();
+                    for (field, ty) in fields {
+                        _ = write!(members, "{} {field}; ", self.c_type(&ty));
+                    }
+                    _ = writeln!(body, "        struct {{ {members}}} v{idx};");
+                }
+                body.push_str("    } as;\n");
+            }
+            _ => {
+                let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+                if fields.is_empty() {
+                    body.push_str("    uint8_t _none;\n");
+                }
+                for (field, ty) in fields {
+                    _ = writeln!(body, "    {} {};", self.c_type(&ty), field_name(field));
+                }
+            }
+        }
+
+        _ = write!(self.typedefs, "struct {name} {{\n{body}}};\n");
+        name
+    }
+
+    /// The C member names and types of a variant's fields.
+    fn variant_fields(&self, def: DefId) -> Vec<(String, Ty)> {
+        match self.types.variants.get(&def) {
+            Some(VariantShape::Tuple(tys)) => tys
+                .iter()
+                .enumerate()
+                .map(|(idx, ty)| (format!("_{idx}"), ty.clone()))
+                .collect(),
+            Some(VariantShape::Struct(fields)) => fields
+                .iter()
+                .map(|(name, ty)| (field_name(*name), ty.clone()))
+                .collect(),
+            Some(VariantShape::Unit) | None => Vec::new(),
+        }
+    }
+
+    /// The enum of a variant, and the variant's tag.
+    fn variant_tag(&self, def: DefId) -> Option<(DefId, usize)> {
+        let parent = self.res.def(def).parent?;
+        let tag = self
+            .types
+            .enums
+            .get(&parent)?
+            .iter()
+            .position(|v| *v == def)?;
+        Some((parent, tag))
+    }
+
+    fn ty(&self, span: Span) -> Ty {
+        self.types
+            .exprs
+            .get(&span)
+            .cloned()
+            .unwrap_or_else(Ty::unit)
+    }
+
+    // ------------------------------------------------------------- helpers
+
+    /// A function printing values of `ty`, generated on first use.
+    fn helper(&mut self, kind: &str, ty: &Ty) -> String {
+        let key = format!("{kind} {ty}");
+        if let Some(name) = self.helper_names.get(&key) {
+            return name.clone();
+        }
+
+        let name = format!("ak_{kind}{}", self.helper_names.len());
+        self.helper_names.insert(key, name.clone());
+        let c_ty = self.c_type(ty);
+
+        let (head, body) = match kind {
+            "show" => (
+                format!("static void {name}({c_ty} v, bool nested)"),
+                self.show_body(ty),
+            ),
+            _ => (
+                format!("static bool {name}({c_ty} a, {c_ty} b)"),
+                self.eq_body(ty),
+            ),
+        };
+
+        _ = writeln!(self.helper_protos, "{head};");
+        _ = write!(self.helpers, "{head} {{\n{body}}}\n\n");
+        name
+    }
+
+    /// A statement printing `value`, quoting strings and chars when `nested`.
+    fn print(&mut self, ty: &Ty, value: &str, nested: bool) -> String {
+        match ty {
+            Ty::Int(int) if int.is_signed() => format!("ak_print_int((int64_t)({value}))"),
+            Ty::Int(_) => format!("ak_print_uint((uint64_t)({value}))"),
+            Ty::Float(FloatTy::F32) => format!("ak_print_f32({value})"),
+            Ty::Float(FloatTy::F64) => format!("ak_print_f64({value})"),
+            Ty::Bool => format!("ak_print_bool({value})"),
+            Ty::Char => format!("ak_print_char({value}, {nested})"),
+            Ty::Str => format!("ak_print_str({value}, {nested})"),
+            Ty::Fn { .. } => "ak_print_cstr(\"<fn>\")".to_owned(),
+            Ty::Ref { inner, .. } if matches!(**inner, Ty::Slice(_)) => {
+                self.print(inner, value, nested)
+            }
+            Ty::Ref { inner, .. } => self.print(inner, &format!("(*{value})"), true),
+            Ty::Optional(_) | Ty::Array(..) | Ty::Slice(_) | Ty::Path(_) => {
+                format!("{}({value}, {nested})", self.helper("show", ty))
+            }
+            Ty::Tuple(elems) if !elems.is_empty() => {
+                format!("{}({value}, {nested})", self.helper("show", ty))
+            }
+            _ => "ak_print_cstr(\"()\")".to_owned(),
+        }
+    }
+
+    fn show_body(&mut self, ty: &Ty) -> String {
+        let mut body = String::new();
+        let text = |body: &mut String, text: &str| {
+            _ = writeln!(body, "    ak_print_cstr({});", c_string(text));
+        };
+
+        match ty {
+            Ty::Optional(inner) => {
+                let inner = self.print(inner, "v.value", true);
+                _ = write!(
+                    body,
+                    "    if (v.some) {{\n        {};\n    }} else {{\n        ak_print_cstr(\"none\");\n    }}\n",
+                    inner.replace(", true)", ", nested)")
+                );
+                return body;
+            }
+            Ty::Tuple(elems) => {
+                text(&mut body, "(");
+                for (idx, elem) in elems.iter().enumerate() {
+                    if idx != 0 {
+                        text(&mut body, ", ");
+                    }
+                    let print = self.print(elem, &format!("v._{idx}"), true);
+                    _ = writeln!(body, "    {print};");
+                }
+                text(&mut body, if elems.len() == 1 { ",)" } else { ")" });
+            }
+            Ty::Array(elem, _) | Ty::Slice(elem) => {
+                let (len, at) = match ty {
+                    Ty::Array(_, len) => (len.to_string(), "v.at"),
+                    _ => ("v.len".to_owned(), "v.ptr"),
+                };
+                let print = self.print(elem, &format!("{at}[idx]"), true);
+                text(&mut body, "[");
+                _ = write!(
+                    body,
+                    "    for (size_t idx = 0; idx < {len}; idx++) {{
+        if (idx != 0) {{
+            ak_print_cstr(\", \");
+        }}
+        {print};
+    }}\n"
+                );
+                text(&mut body, "]");
+            }
+            Ty::Path(path) => {
+                let Some(def) = self.res.def_by_path(path) else {
+                    return body;
+                };
+                match self.res.def(def).kind {
+                    DefKind::Enum => {
+                        body.push_str("    switch (v.tag) {\n");
+                        let variants = self.types.enums.get(&def).cloned().unwrap_or_default();
+                        for (tag, variant) in variants.iter().enumerate() {
+                            _ = writeln!(body, "    case {tag}:");
+                            let name = format!(
+                                "{}::{}",
+                                self.res.def(def).name.as_str(),
+                                self.res.def(*variant).name.as_str()
+                            );
+                            let shape = self.types.variants.get(variant).cloned();
+                            let fields = self.variant_fields(*variant);
+                            let names = match &shape {
+                                Some(VariantShape::Struct(fields)) => {
+                                    Some(fields.iter().map(|(f, _)| f.as_str()).collect())
+                                }
+                                _ => None,
+                            };
+                            self.show_fields(
+                                &mut body,
+                                &name,
+                                &format!("v.as.v{tag}."),
+                                &fields,
+                                names,
+                            );
+                            body.push_str("        break;\n");
+                        }
+                        body.push_str("    }\n");
+                    }
+                    _ => {
+                        let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+                        let names = fields.iter().map(|(f, _)| f.as_str()).collect();
+                        let members = fields
+                            .into_iter()
+                            .map(|(f, ty)| (field_name(f), ty))
+                            .collect::<Vec<_>>();
+                        let name = self.res.def(def).name.as_str();
+                        self.show_fields(&mut body, name, "v.", &members, Some(names));
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        body.push_str("    (void)nested;\n");
+        body
+    }
+
+    /// Prints `Name`, `Name(a, b)` or `Name { x: a, y: b }` depending on
+    /// whether the fields have `names`.
+    fn show_fields(
+        &mut self,
+        body: &mut String,
+        name: &str,
+        prefix: &str,
+        fields: &[(String, Ty)],
+        names: Option<Vec<&str>>,
+    ) {
+        let text = |body: &mut String, text: &str| {
+            _ = writeln!(body, "        ak_print_cstr({});", c_string(text));
+        };
+
+        match names {
+            Some(names) => {
+                text(body, &format!("{name} {{ "));
+                for (idx, ((member, ty), field)) in fields.iter().zip(names).enumerate() {
+                    let sep = if idx == 0 { "" } else { ", " };
+                    text(body, &format!("{sep}{field}: "));
+                    let print = self.print(ty, &format!("{prefix}{member}"), true);
+                    _ = writeln!(body, "        {print};");
+                }
+                text(body, " }");
+            }
+            None if fields.is_empty() => text(body, name),
+            None => {
+                text(body, &format!("{name}("));
+                for (idx, (member, ty)) in fields.iter().enumerate() {
+                    if idx != 0 {
+                        text(body, ", ");
+                    }
+                    let print = self.print(ty, &format!("{prefix}{member}"), true);
+                    _ = writeln!(body, "        {print};");
+                }
+                text(body, ")");
+            }
+        }
+    }
+
+    /// A C expression comparing two values of `ty`.
+    fn eq(&mut self, ty: &Ty, a: &str, b: &str) -> String {
+        match ty {
+            Ty::Int(_) | Ty::Float(_) | Ty::Bool | Ty::Char | Ty::Fn { .. } => {
+                format!("({a} == {b})")
+            }
+            Ty::Str => format!("ak_str_eq({a}, {b})"),
+            Ty::Ref { inner, .. } if matches!(**inner, Ty::Slice(_)) => self.eq(inner, a, b),
+            Ty::Ref { inner, .. } => self.eq(inner, &format!("(*{a})"), &format!("(*{b})")),
+            Ty::Optional(_) | Ty::Array(..) | Ty::Slice(_) | Ty::Path(_) => {
+                format!("{}({a}, {b})", self.helper("eq", ty))
+            }
+            Ty::Tuple(elems) if !elems.is_empty() => {
+                format!("{}({a}, {b})", self.helper("eq", ty))
+            }
+            _ => "true".to_owned(),
+        }
+    }
+
+    fn eq_body(&mut self, ty: &Ty) -> String {
+        let all = |conds: Vec<String>| {
+            if conds.is_empty() {
+                "true".to_owned()
+            } else {
+                conds.join(" && ")
+            }
+        };
+
+        match ty {
+            Ty::Optional(inner) => {
+                let eq = self.eq(inner, "a.value", "b.value");
+                format!("    return a.some == b.some && (!a.some || {eq});\n")
+            }
+            Ty::Tuple(elems) => {
+                let conds = elems
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, elem)| self.eq(elem, &format!("a._{idx}"), &format!("b._{idx}")))
+                    .collect();
+                format!("    return {};\n", all(conds))
+            }
+            Ty::Array(elem, _) | Ty::Slice(elem) => {
+                let (len, at, check) = match ty {
+                    Ty::Array(_, len) => (len.to_string(), "at", ""),
+                    _ => (
+                        "a.len".to_owned(),
+                        "ptr",
+                        "    if (a.len != b.len) {\n        return false;\n    }\n",
+                    ),
+                };
+                let eq = self.eq(elem, &format!("a.{at}[idx]"), &format!("b.{at}[idx]"));
+                format!(
+                    "{check}    for (size_t idx = 0; idx < {len}; idx++) {{
+        if (!{eq}) {{
+            return false;
+        }}
+    }}
+    return true;\n"
+                )
+            }
+            Ty::Path(path) => {
+                let Some(def) = self.res.def_by_path(path) else {
+                    return "    return true;\n".to_owned();
+                };
+                if self.res.def(def).kind != DefKind::Enum {
+                    let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+                    let conds = fields
+                        .into_iter()
+                        .map(|(f, ty)| {
+                            let f = field_name(f);
+                            self.eq(&ty, &format!("a.{f}"), &format!("b.{f}"))
+                        })
+                        .collect();
+                    return format!("    return {};\n", all(conds));
+                }
+
+                let mut body = "    if (a.tag != b.tag) {\n        return false;\n    }\n    switch (a.tag) {\n".to_owned();
+                let variants = self.types.enums.get(&def).cloned().unwrap_or_default();
+                for (tag, variant) in variants.iter().enumerate() {
+                    let conds = self
+                        .variant_fields(*variant)
+                        .into_iter()
+                        .map(|(f, ty)| {
+                            self.eq(
+                                &ty,
+                                &format!("a.as.v{tag}.{f}"),
+                                &format!("b.as.v{tag}.{f}"),
+                            )
+                        })
+                        .collect();
+                    _ = writeln!(body, "    case {tag}:\n        return {};", all(conds));
+                }
+                body.push_str("    }\n    return true;\n");
+                body
+            }
+            _ => "    return true;\n".to_owned(),
+        }
+    }
+
+    // ------------------------------------------------------------ emitting
+
+    fn line(&mut self, text: String) {
+        for _ in 0..self.func.depth {
+            self.func.out.push_str("    ");
+        }
+        self.func.out.push_str(&text);
+        self.func.out.push('\n');
+    }
+
+    fn fresh(&mut self) -> String {
+        self.func.temps += 1;
+        format!("t{}", self.func.temps - 1)
+    }
+
+    /// A new temporary of type `ty`, holding `value` if given.
+    fn temp(&mut self, ty: &Ty, value: Option<&str>) -> String {
+        let name = self.fresh();
+        let c_ty = self.c_type(ty);
+        match value {
+            Some(value) => self.line(format!("{c_ty} {name} = {value};")),
+            None => self.line(format!("{c_ty} {name};")),
+        }
+        name
+    }
+
+    /// A temporary for the value of an expression of type `ty`, if it has one.
+    fn result(&mut self, ty: &Ty) -> Option<String> {
+        has_value(ty).then(|| self.temp(ty, None))
+    }
+
+    /// Stores `value` in the result temporary, if there is one.
+    fn assign(&mut self, result: &Option<String>, value: String, from: &Ty, to: &Ty) {
+        if let Some(result) = result
+            && *from != Ty::Never
+        {
+            let value = self.coerce(value, from, to);
+            self.line(format!("{result} = {value};"));
+        }
+    }
+
+    /// Applies the implicit conversions type checking allows: `T` to `T?`
+    /// and `&[T; N]` to `&[T]`.
+    fn coerce(&mut self, value: String, from: &Ty, to: &Ty) -> String {
+        if from == to {
+            return value;
+        }
+
+        match (from, to) {
+            // the value of code that does not finish, or of a block ending
+            // in a `return`
+            (Ty::Never, _) => format!("(({}){{0}})", self.c_type(to)),
+            (from, to) if from.is_unit() && !to.is_unit() => {
+                format!("(({}){{0}})", self.c_type(to))
+            }
+            (Ty::Optional(_), Ty::Optional(_)) => value,
+            (_, Ty::Optional(inner)) => {
+                let value = self.coerce(value, from, inner);
+                format!("(({}){{ true, {value} }})", self.c_type(to))
+            }
+            (Ty::Ref { inner: x, .. }, Ty::Ref { inner: y, .. }) => match (&**x, &**y) {
+                (Ty::Array(_, len), Ty::Slice(_)) => {
+                    format!("(({}){{ {value}->at, {len} }})", self.c_type(to))
+                }
+                _ => value,
+            },
+            _ => value,
+        }
+    }
+
+    // ---------------------------------------------------------- expressions
+
+    /// Generates the statements of a block in the current C scope, returning
+    /// its value and type.
+    fn block(&mut self, block: &'a BlockExpr) -> (String, Ty) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { name, value, .. } => {
+                    let Some(def) = self.res.lookup(name.span) else {
+                        continue;
+                    };
+                    let ty = self
+                        .types
+                        .locals
+                        .get(&def)
+                        .cloned()
+                        .unwrap_or_else(Ty::unit);
+                    let c_ty = self.c_type(&ty);
+                    let local = self.local_name(def);
+
+                    match value {
+                        Some(value) => {
+                            let init = self.expr(value);
+                            let init = self.coerce(init, &self.ty(value.span), &ty);
+                            self.line(format!("{c_ty} {local} = {init};"));
+                        }
+                        None => self.line(format!("{c_ty} {local} = {{0}};")),
+                    }
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => _ = self.expr(expr),
+            }
+        }
+
+        match &block.expr {
+            Some(tail) => (self.expr_boxed(tail), self.ty(tail.span)),
+            None => ("0".to_owned(), Ty::unit()),
+        }
+    }
+
+    /// Generates a block in a C scope of its own, storing its value in
+    /// `result`.
+    fn scoped_block(&mut self, block: &'a BlockExpr, result: &Option<String>, ty: &Ty) {
+        self.func.depth += 1;
+        let (value, from) = self.block(block);
+        self.assign(result, value, &from, ty);
+        self.func.depth -= 1;
+    }
+
+    fn expr(&mut self, expr: &'a Spanned<Expr>) -> String {
+        self.expr_at(&expr.node, expr.span)
+    }
+
+    fn expr_boxed(&mut self, expr: &'a Spanned<Box<Expr>>) -> String {
+        self.expr_at(&expr.node, expr.span)
+    }
+
+    /// Generates the statements computing `expr`, and returns a C expression
+    /// for its value. Anything that can fail or has effects is computed into
+    /// a temporary, so it happens in order.
+    fn expr_at(&mut self, expr: &'a Expr, span: Span) -> String {
+        match expr {
+            Expr::Literal(literal) => self.literal(literal, &self.ty(span)),
+            Expr::Variable(_) | Expr::Path(_) => self.name(span),
+            Expr::Tuple(elems) if elems.is_empty() => "0".to_owned(),
+            Expr::Tuple(elems) => {
+                let Ty::Tuple(tys) = self.ty(span) else {
+                    return "0".to_owned();
+                };
+                let mut values = Vec::new();
+                for (elem, ty) in elems.iter().zip(&tys) {
+                    let value = self.expr(elem);
+                    values.push(self.coerce(value, &self.ty(elem.span), ty));
+                }
+                format!(
+                    "(({}){{ {} }})",
+                    self.c_type(&Ty::Tuple(tys)),
+                    values.join(", ")
+                )
+            }
+            Expr::Array(elems) => {
+                let ty = self.ty(span);
+                let Ty::Array(elem_ty, _) = &ty else {
+                    return "0".to_owned();
+                };
+                let mut values = Vec::new();
+                for elem in elems {
+                    let value = self.expr(elem);
+                    values.push(self.coerce(value, &self.ty(elem.span), elem_ty));
+                }
+                if values.is_empty() {
+                    values.push("0".to_owned());
+                }
+                format!("(({}){{ {{ {} }} }})", self.c_type(&ty), values.join(", "))
+            }
+            Expr::Range { .. } => self.unsupported(span, "ranges outside `for` loops and slicing"),
+            Expr::Binary(lhs, op, rhs) if op.node.is_logical() => {
+                let lhs = self.expr_boxed(lhs);
+                let result = self.temp(&Ty::Bool, Some(&lhs));
+                match op.node {
+                    BinaryOp::And => self.line(format!("if ({result}) {{")),
+                    _ => self.line(format!("if (!{result}) {{")),
+                }
+                self.func.depth += 1;
+                let rhs = self.expr_boxed(rhs);
+                self.line(format!("{result} = {rhs};"));
+                self.func.depth -= 1;
+                self.line("}".to_owned());
+                result
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let a = self.expr_boxed(lhs);
+                let b = self.expr_boxed(rhs);
+                self.binary(op.node, a, b, &self.ty(lhs.span), &self.ty(rhs.span))
+            }
+            Expr::Prefix(op, operand) => self.prefix(op.node, operand),
+            Expr::Postfix(operand, op) => {
+                let place = self.place(operand);
+                let ty = self.ty(operand.span);
+                let old = self.temp(&ty, Some(&place));
+                let step = match op.node {
+                    PostfixOp::Inc => BinaryOp::Add,
+                    PostfixOp::Dec => BinaryOp::Sub,
+                };
+                let one = self.literal(&Literal::UInt(1, None), &ty);
+                let new = self.binary(step, old.clone(), one, &ty, &ty);
+                self.line(format!("{place} = {new};"));
+                old
+            }
+            Expr::Call(callee, args) => self.call(callee, args, span),
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let Some(def) = self.res.lookup(method.span) else {
+                    return "0".to_owned();
+                };
+                let params = self
+                    .types
+                    .fns
+                    .get(&def)
+                    .map(|sig| sig.params.clone())
+                    .unwrap_or_default();
+
+                // borrow the receiver when the function takes a reference
+                let wants_ref = matches!(params.first(), Some(Ty::Ref { .. }));
+                let receiver_ty = self.ty(receiver.span);
+                let mut values = vec![if wants_ref && !matches!(receiver_ty, Ty::Ref { .. }) {
+                    format!("(&{})", self.place(receiver))
+                } else {
+                    let value = self.expr_boxed(receiver);
+                    match params.first() {
+                        Some(param) => self.coerce(value, &receiver_ty, param),
+                        None => value,
+                    }
+                }];
+                values.extend(self.args(args, params.get(1..).unwrap_or_default()));
+
+                let call = format!("{}({})", self.item_name(def), values.join(", "));
+                self.call_result(call, &self.ty(span))
+            }
+            Expr::Field(base, name) => self.field(base, name.node),
+            Expr::Index(_, index) if matches!(&*index.node, Expr::Range { .. }) => {
+                self.slice(expr, span)
+            }
+            Expr::Index(..) => self.place_at(expr, span),
+            Expr::Cast(operand, _) => {
+                let value = self.expr_boxed(operand);
+                let (value, from) = self.deref_all(value, &self.ty(operand.span));
+                self.cast(value, &from, &self.ty(span))
+            }
+            Expr::Closure { .. } => self.unsupported(span, "closures"),
+            Expr::If { cond, then, else_ } => {
+                let ty = self.ty(span);
+                let result = self.result(&ty);
+                let cond = self.expr_boxed(cond);
+
+                self.line(format!("if ({cond}) {{"));
+                self.func.depth += 1;
+                let value = self.expr_boxed(then);
+                self.assign(&result, value, &self.ty(then.span), &ty);
+                self.func.depth -= 1;
+
+                if let Some(else_) = else_ {
+                    self.line("} else {".to_owned());
+                    self.func.depth += 1;
+                    let value = self.expr_boxed(else_);
+                    self.assign(&result, value, &self.ty(else_.span), &ty);
+                    self.func.depth -= 1;
+                }
+                self.line("}".to_owned());
+                result.unwrap_or_else(|| "0".to_owned())
+            }
+            Expr::Loop(body) => {
+                let ty = self.ty(span);
+                let result = self.result(&ty);
+
+                self.line("for (;;) {".to_owned());
+                self.func
+                    .loops
+                    .push(result.clone().map(|r| (r, ty.clone())));
+                self.scoped_block(&body.node, &None, &Ty::unit());
+                self.func.loops.pop();
+                self.line("}".to_owned());
+                result.unwrap_or_else(|| "0".to_owned())
+            }
+            Expr::While { cond, body } => {
+                self.line("for (;;) {".to_owned());
+                self.func.depth += 1;
+                let cond = self.expr_boxed(cond);
+                self.line(format!("if (!{cond}) {{"));
+                self.line("    break;".to_owned());
+                self.line("}".to_owned());
+                self.func.depth -= 1;
+
+                self.func.loops.push(None);
+                self.scoped_block(body, &None, &Ty::unit());
+                self.func.loops.pop();
+                self.line("}".to_owned());
+                "0".to_owned()
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => {
+                self.for_loop(pattern, iter, body);
+                "0".to_owned()
+            }
+            Expr::Break(value) => {
+                let target = self.func.loops.last().cloned().flatten();
+                if let Some(value) = value {
+                    let atom = self.expr_boxed(value);
+                    if let Some((result, ty)) = target {
+                        self.assign(&Some(result), atom, &self.ty(value.span), &ty);
+                    }
+                }
+                self.line("break;".to_owned());
+                "0".to_owned()
+            }
+            Expr::Continue => {
+                self.line("continue;".to_owned());
+                "0".to_owned()
+            }
+            Expr::Return(value) => {
+                let ret = self.func.ret.clone().unwrap_or_else(Ty::unit);
+                let (atom, ty) = match value {
+                    Some(value) => (self.expr_boxed(value), self.ty(value.span)),
+                    None => ("0".to_owned(), Ty::unit()),
+                };
+                if ty != Ty::Never {
+                    let atom = self.coerce(atom, &ty, &ret);
+                    self.line(format!("return {atom};"));
+                }
+                "0".to_owned()
+            }
+            Expr::Block(block) => {
+                let (value, ty) = self.block(&block.node);
+                self.coerce(value, &ty, &self.ty(span))
+            }
+            Expr::Assign { target, value } => {
+                let atom = self.expr_boxed(value);
+                let place = self.place(target);
+                let atom = self.coerce(atom, &self.ty(value.span), &self.ty(target.span));
+                self.line(format!("{place} = {atom};"));
+                "0".to_owned()
+            }
+            Expr::AssignEq { op, target, value } => {
+                let atom = self.expr_boxed(value);
+                let place = self.place(target);
+                let new = self.binary(
+                    op.node,
+                    place.clone(),
+                    atom,
+                    &self.ty(target.span),
+                    &self.ty(value.span),
+                );
+                self.line(format!("{place} = {new};"));
+                "0".to_owned()
+            }
+            Expr::StructInit { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return "0".to_owned();
+                };
+                let (members, name) = match self.res.def(def).kind {
+                    DefKind::Variant => (self.variant_fields(def), None),
+                    _ => (
+                        self.types
+                            .structs
+                            .get(&def)
+                            .cloned()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(f, ty)| (field_name(f), ty))
+                            .collect(),
+                        Some(self.adt(def)),
+                    ),
+                };
+
+                // evaluate in source order, then initialize in layout order
+                let mut values = HashMap::new();
+                for field in fields {
+                    let member = field_name(field.node.name.node);
+                    let Some((_, ty)) = members.iter().find(|(m, _)| *m == member) else {
+                        continue;
+                    };
+                    let ty = ty.clone();
+                    let value = self.expr(&field.node.value);
+                    let value = self.coerce(value, &self.ty(field.node.value.span), &ty);
+                    values.insert(member, value);
+                }
+                let values: Vec<_> = members
+                    .iter()
+                    .map(|(m, _)| values.remove(m).unwrap_or_else(|| "0".to_owned()))
+                    .collect();
+
+                match name {
+                    Some(name) if values.is_empty() => format!("(({name}){{ 0 }})"),
+                    Some(name) => {
+                        let inits: Vec<_> = members
+                            .iter()
+                            .zip(values)
+                            .map(|((m, _), v)| format!(".{m} = {v}"))
+                            .collect();
+                        format!("(({name}){{ {} }})", inits.join(", "))
+                    }
+                    None => self.variant_value(def, values),
+                }
+            }
+            Expr::Match { scrutinee, arms } => {
+                let scrutinee_ty = self.ty(scrutinee.span);
+                let value = self.expr_boxed(scrutinee);
+                let value = self.temp(&scrutinee_ty, Some(&value));
+                let ty = self.ty(span);
+                let result = self.result(&ty);
+
+                let mut exhaustive = false;
+                for (idx, arm) in arms.iter().enumerate() {
+                    let mut conds = Vec::new();
+                    let mut binds = Vec::new();
+                    self.pattern(&arm.pattern, &value, &scrutinee_ty, &mut conds, &mut binds);
+
+                    let cond = conds.join(" && ");
+                    match (idx, conds.is_empty()) {
+                        (0, true) => self.line("{".to_owned()),
+                        (0, false) => self.line(format!("if ({cond}) {{")),
+                        (_, true) => self.line("} else {".to_owned()),
+                        (_, false) => self.line(format!("}} else if ({cond}) {{")),
+                    }
+
+                    self.func.depth += 1;
+                    for bind in binds {
+                        self.line(bind);
+                    }
+                    let atom = self.expr(&arm.body);
+                    self.assign(&result, atom, &self.ty(arm.body.span), &ty);
+                    self.func.depth -= 1;
+
+                    if conds.is_empty() {
+                        exhaustive = true;
+                        break;
+                    }
+                }
+
+                if !exhaustive {
+                    let panic = "ak_panic(\"no `match` arm matches the value\");".to_owned();
+                    if arms.is_empty() {
+                        self.line(panic);
+                    } else {
+                        self.line("} else {".to_owned());
+                        self.line(format!("    {panic}"));
+                    }
+                }
+                if !arms.is_empty() {
+                    self.line("}".to_owned());
+                }
+                result.unwrap_or_else(|| "0".to_owned())
+            }
+            Expr::Error(_) => "0".to_owned(),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal, ty: &Ty) -> String {
+        match value::literal(literal, Some(ty)) {
+            Value::Int(value, int) => int_literal(value, int),
+            Value::Float(value, float) => float_literal(value, float),
+            Value::Bool(b) => b.to_string(),
+            Value::Char(c) => format!("((ak_char){})", c as u32),
+            Value::Str(s) => format!("((ak_str){{ {}, {} }})", c_string(&s), s.len()),
+            Value::None => format!("(({}){{ .some = false }})", self.c_type(ty)),
+            _ => "0".to_owned(),
+        }
+    }
+
+    fn name(&mut self, span: Span) -> String {
+        let Some(def) = self.res.lookup(span) else {
+            return "0".to_owned();
+        };
+
+        match self.res.def(def).kind {
+            DefKind::Local { .. } => self.local_name(def),
+            DefKind::Fn => self.item_name(def),
+            DefKind::Const => format!("{}()", self.item_name(def)),
+            DefKind::Variant => match self.types.variants.get(&def) {
+                Some(VariantShape::Unit) => self.variant_value(def, Vec::new()),
+                _ => self.unsupported(span, "tuple variants used as functions"),
+            },
+            DefKind::Builtin => self.unsupported(span, "builtin functions used as values"),
+            DefKind::Section | DefKind::Struct | DefKind::Enum => "0".to_owned(),
+        }
+    }
+
+    /// A variant of an enum with its fields' values, in layout order.
+    fn variant_value(&mut self, def: DefId, values: Vec<String>) -> String {
+        let Some((parent, tag)) = self.variant_tag(def) else {
+            return "0".to_owned();
+        };
+        let name = self.adt(parent);
+
+        let mut inits = vec![format!(".tag = {tag}")];
+        for ((member, _), value) in self.variant_fields(def).iter().zip(values) {
+            inits.push(format!(".as.v{tag}.{member} = {value}"));
+        }
+        format!("(({name}){{ {} }})", inits.join(", "))
+    }
+
+    /// Follows references, except the `&[T]` ones which are slices already.
+    fn deref_all(&self, mut value: String, ty: &Ty) -> (String, Ty) {
+        let mut ty = ty.clone();
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            value = format!("(*{value})");
+            ty = (**inner).clone();
+        }
+        (value, ty)
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: String, b: String, lhs: &Ty, rhs: &Ty) -> String {
+        let (a, lhs) = self.deref_all(a, lhs);
+        let (b, rhs) = self.deref_all(b, rhs);
+
+        if op.is_comparison() {
+            return match (op, &lhs) {
+                (BinaryOp::Eq, _) => self.eq(&lhs, &a, &b),
+                (BinaryOp::Ne, _) => format!("(!{})", self.eq(&lhs, &a, &b)),
+                (_, Ty::Str) => format!("(ak_str_cmp({a}, {b}) {op} 0)"),
+                _ => format!("({a} {op} {b})"),
+            };
+        }
+
+        match &lhs {
+            Ty::Int(int) => {
+                let name = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Sub => "sub",
+                    BinaryOp::Mul => "mul",
+                    BinaryOp::Div => "div",
+                    BinaryOp::Rem => "rem",
+                    BinaryOp::Shl | BinaryOp::Shr => {
+                        let neg = match rhs {
+                            Ty::Int(int) if int.is_signed() => format!("{b} < 0"),
+                            _ => "false".to_owned(),
+                        };
+                        let name = if op == BinaryOp::Shl { "shl" } else { "shr" };
+                        let call = format!("ak_{name}_{}({a}, {neg}, (uint64_t)({b}))", int.name());
+                        return self.temp(&lhs, Some(&call));
+                    }
+                    _ => return format!("(({})({a} {op} {b}))", int_c_type(*int)),
+                };
+                let call = format!("ak_{name}_{}({a}, {b})", int.name());
+                self.temp(&lhs, Some(&call))
+            }
+            Ty::Float(float) => match op {
+                BinaryOp::Rem if *float == FloatTy::F32 => format!("fmodf({a}, {b})"),
+                BinaryOp::Rem => format!("fmod({a}, {b})"),
+                _ => format!("({a} {op} {b})"),
+            },
+            _ => format!("((bool)({a} {op} {b}))"),
+        }
+    }
+
+    fn prefix(&mut self, op: PrefixOp, operand: &'a Spanned<Box<Expr>>) -> String {
+        let ty = self.ty(operand.span);
+
+        match op {
+            PrefixOp::Neg => {
+                // `-128` is in range for `i8` even though `128` is not
+                if let Expr::Literal(Literal::UInt(v, _)) = &*operand.node
+                    && let Ty::Int(int) = ty
+                {
+                    return int_literal(-(*v as i128), int);
+                }
+                let value = self.expr_boxed(operand);
+                match ty {
+                    Ty::Int(int) => {
+                        let call = format!("ak_neg_{}({value})", int.name());
+                        self.temp(&ty, Some(&call))
+                    }
+                    _ => format!("(-{value})"),
+                }
+            }
+            PrefixOp::Not => {
+                let value = self.expr_boxed(operand);
+                match ty {
+                    Ty::Int(int) => format!("(({})~{value})", int_c_type(int)),
+                    _ => format!("(!{value})"),
+                }
+            }
+            PrefixOp::Deref => {
+                let value = self.expr_boxed(operand);
+                match ty {
+                    Ty::Ref { inner, .. } if matches!(*inner, Ty::Slice(_)) => value,
+                    _ => format!("(*{value})"),
+                }
+            }
+            PrefixOp::Ref | PrefixOp::RefMut => match ty {
+                Ty::Slice(_) => self.expr_boxed(operand),
+                _ => {
+                    let place = self.place_at(&operand.node, operand.span);
+                    format!("(&{place})")
+                }
+            },
+        }
+    }
+
+    fn cast(&mut self, value: String, from: &Ty, to: &Ty) -> String {
+        match (from, to) {
+            (Ty::Int(_) | Ty::Bool | Ty::Char, Ty::Int(int)) => {
+                format!("(({})({value}))", int_c_type(*int))
+            }
+            (Ty::Int(_), Ty::Float(_)) => format!("(({})(double)({value}))", self.c_type(to)),
+            (Ty::Float(_), Ty::Float(_)) => format!("(({})({value}))", self.c_type(to)),
+            (Ty::Float(_), Ty::Int(int)) => format!("ak_f2i_{}((double)({value}))", int.name()),
+            (Ty::Int(_), Ty::Char) => format!("((ak_char)(uint8_t)({value}))"),
+            _ => value,
+        }
+    }
+
+    fn call(
+        &mut self,
+        callee: &'a Spanned<Box<Expr>>,
+        args: &'a [Spanned<Expr>],
+        span: Span,
+    ) -> String {
+        if let Expr::Variable(_) | Expr::Path(_) = &*callee.node
+            && let Some(def) = self.res.lookup(callee.span)
+        {
+            let info = self.res.def(def);
+            match info.kind {
+                DefKind::Builtin => {
+                    let (value, ty) = match args.first() {
+                        Some(arg) => (self.expr(arg), self.ty(arg.span)),
+                        None => ("0".to_owned(), Ty::unit()),
+                    };
+                    match info.name.as_str() {
+                        "assert" => self.line(format!("ak_assert({value});")),
+                        name => {
+                            if !args.is_empty() {
+                                let print = self.print(&ty, &value, false);
+                                self.line(format!("{print};"));
+                            }
+                            if name == "println" {
+                                self.line("ak_newline();".to_owned());
+                            }
+                        }
+                    }
+                    return "0".to_owned();
+                }
+                DefKind::Variant => {
+                    let tys: Vec<_> = self
+                        .variant_fields(def)
+                        .into_iter()
+                        .map(|(_, ty)| ty)
+                        .collect();
+                    let values = self.args(args, &tys);
+                    return self.variant_value(def, values);
+                }
+                _ => (),
+            }
+        }
+
+        let callee_value = self.expr_boxed(callee);
+        let params = match self.ty(callee.span) {
+            Ty::Fn { params, .. } => params,
+            _ => Vec::new(),
+        };
+        let values = self.args(args, &params);
+        let call = format!("{callee_value}({})", values.join(", "));
+        self.call_result(call, &self.ty(span))
+    }
+
+    fn args(&mut self, args: &'a [Spanned<Expr>], params: &[Ty]) -> Vec<String> {
+        let mut values = Vec::new();
+        for (idx, arg) in args.iter().enumerate() {
+            let value = self.expr(arg);
+            values.push(match params.get(idx) {
+                Some(param) => self.coerce(value, &self.ty(arg.span), param),
+                None => value,
+            });
+        }
+        values
+    }
+
+    fn call_result(&mut self, call: String, ty: &Ty) -> String {
+        if has_value(ty) {
+            self.temp(ty, Some(&call))
+        } else {
+            self.line(format!("{call};"));
+            "0".to_owned()
+        }
+    }
+
+    fn field(&mut self, base: &'a Spanned<Box<Expr>>, name: Symbol) -> String {
+        let value = self.operand(base);
+        let (value, ty) = self.deref_all(value, &self.ty(base.span));
+        match ty {
+            Ty::Tuple(_) => format!("{value}._{}", name.as_str()),
+            _ => format!("{value}.{}", field_name(name)),
+        }
+    }
+
+    /// `base[a..b]`, a slice into an array or a substring.
+    fn slice(&mut self, expr: &'a Expr, span: Span) -> String {
+        let Expr::Index(base, index) = expr else {
+            return "0".to_owned();
+        };
+        let Expr::Range {
+            start,
+            end,
+            inclusive,
+        } = &*index.node
+        else {
+            return "0".to_owned();
+        };
+
+        let value = self.operand(base);
+        let (value, ty) = self.deref_all(value, &self.ty(base.span));
+        let (len, data) = match &ty {
+            Ty::Array(_, len) => (len.to_string(), format!("{value}.at")),
+            _ => (format!("{value}.len"), format!("{value}.ptr")),
+        };
+
+        let i64 = Ty::Int(IntTy::I64);
+        let lo = match start {
+            Some(start) => {
+                let lo = self.expr_boxed(start);
+                self.temp(&i64, Some(&format!("(int64_t)({lo})")))
+            }
+            None => self.temp(&i64, Some("0")),
+        };
+        let hi = match end {
+            Some(end) => {
+                let hi = self.expr_boxed(end);
+                let hi = format!("(int64_t)({hi}) + {}", *inclusive as u8);
+                self.temp(&i64, Some(&hi))
+            }
+            None => self.temp(&i64, Some(&format!("(int64_t){len}"))),
+        };
+
+        if ty == Ty::Str {
+            let sub = format!("ak_str_slice({value}, {lo}, {hi})");
+            return self.temp(&Ty::Str, Some(&sub));
+        }
+        self.line(format!("ak_check_range({lo}, {hi}, {len});"));
+        let slice = self.c_type(&self.ty(span));
+        format!("(({slice}){{ {data} + {lo}, (size_t)({hi} - {lo}) }})")
+    }
+
+    fn for_loop(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        iter: &'a Spanned<Box<Expr>>,
+        body: &'a BlockExpr,
+    ) {
+        let iter_ty = self.ty(iter.span);
+
+        let elem = if let Some(elem_ty) = range_elem(&iter_ty) {
+            let Expr::Range {
+                start: Some(start),
+                end,
+                inclusive,
+            } = &*iter.node
+            else {
+                self.unsupported(iter.span, "ranges outside `for` loops and slicing");
+                return;
+            };
+            let Ty::Int(int) = elem_ty else {
+                return;
+            };
+            let c_ty = int_c_type(*int);
+
+            let start = self.expr_boxed(start);
+            let idx = self.fresh();
+            match end {
+                Some(end) => {
+                    let end = self.expr_boxed(end);
+                    let end = self.temp(elem_ty, Some(&end));
+                    if *inclusive {
+                        // stepping past the end could overflow
+                        let more = self.fresh();
+                        self.line(format!("bool {more} = {start} <= {end};"));
+                        self.line(format!(
+                            "for ({c_ty} {idx} = {start}; {more}; {more} = {idx} != {end}, {idx} += {more}) {{"
+                        ));
+                    } else {
+                        self.line(format!(
+                            "for ({c_ty} {idx} = {start}; {idx} < {end}; {idx}++) {{"
+                        ));
+                    }
+                }
+                None => self.line(format!(
+                    "for ({c_ty} {idx} = {start};; {idx} = {idx} == {} ? (ak_panic(\"attempt to iterate past the end of the integer range\"), {idx}) : {idx} + 1) {{",
+                    int_literal(value::int_max(*int), *int)
+                )),
+            }
+            (idx, elem_ty.clone())
+        } else {
+            let value = self.expr_boxed(iter);
+            let (value, ty) = self.deref_all(value, &iter_ty);
+            // the loop visits a copy, like the interpreter does
+            let items = self.temp(&ty, Some(&value));
+            let idx = self.fresh();
+
+            match &ty {
+                Ty::Str => {
+                    self.line(format!("for (size_t {idx} = 0; {idx} < {items}.len;) {{"));
+                    let c = self.fresh();
+                    self.line(format!("    ak_char {c} = ak_str_next({items}, &{idx});"));
+                    (c, Ty::Char)
+                }
+                Ty::Array(elem, len) => {
+                    self.line(format!("for (size_t {idx} = 0; {idx} < {len}; {idx}++) {{"));
+                    (format!("{items}.at[{idx}]"), (**elem).clone())
+                }
+                Ty::Slice(elem) => {
+                    self.line(format!(
+                        "for (size_t {idx} = 0; {idx} < {items}.len; {idx}++) {{"
+                    ));
+                    (format!("{items}.ptr[{idx}]"), (**elem).clone())
+                }
+                Ty::Ref { inner, .. } => match &**inner {
+                    Ty::Slice(elem) => {
+                        self.line(format!(
+                            "for (size_t {idx} = 0; {idx} < {items}.len; {idx}++) {{"
+                        ));
+                        (format!("{items}.ptr[{idx}]"), (**elem).clone())
+                    }
+                    _ => return,
+                },
+                _ => return,
+            }
+        };
+
+        self.func.depth += 1;
+        let mut conds = Vec::new();
+        let mut binds = Vec::new();
+        self.pattern(pattern, &elem.0, &elem.1, &mut conds, &mut binds);
+        if !conds.is_empty() {
+            self.line(format!("if (!({})) {{", conds.join(" && ")));
+            self.line("    ak_panic(\"pattern did not match the element\");".to_owned());
+            self.line("}".to_owned());
+        }
+        for bind in binds {
+            self.line(bind);
+        }
+        self.func.depth -= 1;
+
+        self.func.loops.push(None);
+        self.scoped_block(body, &None, &Ty::unit());
+        self.func.loops.pop();
+        self.line("}".to_owned());
+    }
+
+    // -------------------------------------------------------------- places
+
+    fn is_place(&self, expr: &Expr, span: Span) -> bool {
+        match expr {
+            Expr::Variable(_) => self
+                .res
+                .lookup(span)
+                .is_some_and(|def| matches!(self.res.def(def).kind, DefKind::Local { .. })),
+            Expr::Field(..) => true,
+            Expr::Index(_, index) => !matches!(&*index.node, Expr::Range { .. }),
+            Expr::Prefix(op, _) => op.node == PrefixOp::Deref,
+            _ => false,
+        }
+    }
+
+    /// `expr` as an lvalue when it denotes a location, or a temporary
+    /// holding its value.
+    fn operand(&mut self, expr: &'a Spanned<Box<Expr>>) -> String {
+        if self.is_place(&expr.node, expr.span) {
+            self.place(expr)
+        } else {
+            let value = self.expr_boxed(expr);
+            self.temp(&self.ty(expr.span), Some(&value))
+        }
+    }
+
+    fn place(&mut self, expr: &'a Spanned<Box<Expr>>) -> String {
+        self.place_at(&expr.node, expr.span)
+    }
+
+    /// The location `expr` denotes, for assignments and borrows. Anything
+    /// that is not a variable, field, element or dereference is a temporary.
+    fn place_at(&mut self, expr: &'a Expr, span: Span) -> String {
+        if !self.is_place(expr, span) {
+            let value = self.expr_at(expr, span);
+            return self.temp(&self.ty(span), Some(&value));
+        }
+
+        match expr {
+            Expr::Field(base, name) => self.field(base, name.node),
+            Expr::Index(base, index) => {
+                let value = self.operand(base);
+                let (value, ty) = self.deref_all(value, &self.ty(base.span));
+                let idx = self.expr_boxed(index);
+                let neg = match self.ty(index.span) {
+                    Ty::Int(int) if int.is_signed() => format!("{idx} < 0"),
+                    _ => "false".to_owned(),
+                };
+
+                let (len, data) = match &ty {
+                    Ty::Array(_, len) => (len.to_string(), format!("{value}.at")),
+                    _ => (format!("{value}.len"), format!("{value}.ptr")),
+                };
+                let checked = self.fresh();
+                self.line(format!(
+                    "size_t {checked} = ak_index({neg}, (uint64_t)({idx}), {len});"
+                ));
+                format!("{data}[{checked}]")
+            }
+            Expr::Prefix(_, inner) => {
+                let value = self.expr_boxed(inner);
+                format!("(*{value})")
+            }
+            _ => self.name(span),
+        }
+    }
+
+    // ------------------------------------------------------------ patterns
+
+    /// Tests `value`, of type `ty`, against `pattern`. The conditions that
+    /// must hold go in `conds`, and the declarations of its variables, which
+    /// may only run once they hold, in `binds`.
+    fn pattern(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        value: &str,
+        ty: &Ty,
+        conds: &mut Vec<String>,
+        binds: &mut Vec<String>,
+    ) {
+        let binding = match &pattern.node {
+            Pattern::Wildcard => return,
+            Pattern::Variable(name) => self
+                .res
+                .lookup(name.span)
+                .is_none_or(|def| matches!(self.res.def(def).kind, DefKind::Local { .. })),
+            _ => false,
+        };
+        if binding {
+            if let Pattern::Variable(name) = &pattern.node
+                && let Some(def) = self.res.lookup(name.span)
+            {
+                let c_ty = self.c_type(ty);
+                binds.push(format!("{c_ty} {} = {value};", self.local_name(def)));
+            }
+            return;
+        }
+
+        // patterns look through references and into optionals
+        let (mut value, mut ty) = self.deref_all(value.to_owned(), ty);
+        let none = matches!(&pattern.node, Pattern::Literal(l) if matches!(l.node, Literal::None));
+        if let Ty::Optional(inner) = &ty
+            && !none
+        {
+            conds.push(format!("{value}.some"));
+            value = format!("{value}.value");
+            ty = (**inner).clone();
+        }
+
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => (),
+            Pattern::Literal(literal) => {
+                if none {
+                    conds.push(format!("!{value}.some"));
+                    return;
+                }
+                let expected = self.literal(&literal.node, &self.ty(literal.span));
+                conds.push(self.eq(&ty, &value, &expected));
+            }
+            Pattern::Variable(name) => {
+                let Some(def) = self.res.lookup(name.span) else {
+                    return;
+                };
+                match self.res.def(def).kind {
+                    DefKind::Const => {
+                        let expected = format!("{}()", self.item_name(def));
+                        conds.push(self.eq(&ty, &value, &expected));
+                    }
+                    _ => {
+                        if let Some((_, tag)) = self.variant_tag(def) {
+                            conds.push(format!("{value}.tag == {tag}"));
+                        }
+                    }
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let Some((_, tag)) = self.variant_tag(def) else {
+                    return;
+                };
+                conds.push(format!("{value}.tag == {tag}"));
+
+                for (elem, (member, ty)) in elems.iter().zip(self.variant_fields(def)) {
+                    self.pattern(
+                        elem,
+                        &format!("{value}.as.v{tag}.{member}"),
+                        &ty,
+                        conds,
+                        binds,
+                    );
+                }
+            }
+            Pattern::Struct { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let (prefix, members) = match self.variant_tag(def) {
+                    Some((_, tag)) => {
+                        conds.push(format!("{value}.tag == {tag}"));
+                        (format!("{value}.as.v{tag}."), self.variant_fields(def))
+                    }
+                    None => (
+                        format!("{value}."),
+                        self.types
+                            .structs
+                            .get(&def)
+                            .cloned()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(f, ty)| (field_name(f), ty))
+                            .collect(),
+                    ),
+                };
+
+                for field in fields {
+                    let member = field_name(field.node.name.node);
+                    let Some((_, ty)) = members.iter().find(|(m, _)| *m == member) else {
+                        continue;
+                    };
+                    let ty = ty.clone();
+                    self.pattern(
+                        &field.node.pattern,
+                        &format!("{prefix}{member}"),
+                        &ty,
+                        conds,
+                        binds,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Whether values of `ty` need storing, unit and never types have nothing
+/// to store.
+fn has_value(ty: &Ty) -> bool {
+    !ty.is_unit() && *ty != Ty::Never
+}
+
+fn int_c_type(int: IntTy) -> &'static str {
+    match int {
+        IntTy::U8 => "uint8_t",
+        IntTy::U16 => "uint16_t",
+        IntTy::U32 => "uint32_t",
+        IntTy::U64 => "uint64_t",
+        IntTy::I8 => "int8_t",
+        IntTy::I16 => "int16_t",
+        IntTy::I32 => "int32_t",
+        IntTy::I64 => "int64_t",
+    }
+}
+
+fn int_literal(value: i128, int: IntTy) -> String {
+    let bits = int.bits();
+    if int.is_signed() && value == value::int_min(int) {
+        // the negation of a literal that is out of range
+        return format!("INT{bits}_MIN");
+    }
+
+    match (int.is_signed(), bits) {
+        (true, 64) => format!("INT64_C({value})"),
+        (false, 64) => format!("UINT64_C({value})"),
+        _ => format!("(({}){value})", int_c_type(int)),
+    }
+}
+
+fn float_literal(value: f64, float: FloatTy) -> String {
+    let suffix = if float == FloatTy::F32 { "f" } else { "" };
+    if value.is_nan() {
+        return "NAN".to_owned();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 {
+            "(-INFINITY)"
+        } else {
+            "INFINITY"
+        }
+        .to_owned();
+    }
+
+    // the shortest digits that read back as the value
+    let digits = match float {
+        FloatTy::F32 => format!("{:?}", value as f32),
+        FloatTy::F64 => format!("{value:?}"),
+    };
+    if value < 0.0 {
+        format!("({digits}{suffix})")
+    } else {
+        format!("{digits}{suffix}")
+    }
+}
+
+/// A C string literal with the bytes of `text`. Octal escapes, unlike hex
+/// ones, cannot run into the characters after them.
+fn c_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b' '..=b'~' => out.push(byte as char),
+            _ => _ = write!(out, "\\{byte:03o}"),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `name` with anything C does not allow in identifiers spelled out.
+fn sanitize(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            out.push(c);
+        } else {
+            _ = write!(out, "_u{:x}_", c as u32);
+        }
+    }
+    out
+}
+
+fn field_name(name: Symbol) -> String {
+    format!("f_{}", sanitize(name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        process::{Command, Output},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::testing;
+
+    struct Outcome {
+        c: String,
+        /// what running the compiled program did, if there is a C compiler
+        run: Option<Output>,
+        expected: String,
+    }
+
+    /// Compiles `src` to C, then builds and runs it with the system `cc`
+    /// next to the interpreter.
+    fn run(src: &str) -> Outcome {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let checked = testing::check(src);
+        let mut cgen = CGen::new(&checked.res, &checked.types);
+        let c = cgen.generate(&checked.script);
+        assert!(cgen.bag.is_empty(), "codegen errors: {:?}", cgen.bag.0);
+
+        let (expected, _) = checked.interpret();
+
+        let dir: PathBuf = std::env::temp_dir().join(format!(
+            "akuru-cgen-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(RUNTIME_NAME), RUNTIME).unwrap();
+        std::fs::write(dir.join("main.c"), &c).unwrap();
+
+        let Ok(cc) = Command::new("cc")
+            .args([
+                "-std=c99",
+                "-Wall",
+                "-Wno-unused",
+                "-o",
+                "main",
+                "main.c",
+                "-lm",
+            ])
+            .current_dir(&dir)
+            .output()
+        else {
+            return Outcome {
+                c,
+                run: None,
+                expected,
+            };
+        };
+        assert!(
+            cc.status.success(),
+            "cc failed:\n{}\n{c}",
+            String::from_utf8_lossy(&cc.stderr)
+        );
+
+        let run = Command::new(dir.join("main")).output().unwrap();
+        _ = std::fs::remove_dir_all(&dir);
+        Outcome {
+            c,
+            run: Some(run),
+            expected,
+        }
+    }
+
+    fn assert_agrees(outcome: &Outcome) {
+        if let Some(run) = &outcome.run {
+            assert!(
+                run.status.success(),
+                "{}",
+                String::from_utf8_lossy(&run.stderr)
+            );
+            assert_eq!(String::from_utf8_lossy(&run.stdout), outcome.expected);
+        }
+    }
+
+    #[test]
+    fn test_small_integers_do_not_promote() {
+        let outcome = run("fn main() {
+                let a: u8 = 200;
+                let b: u8 = 3;
+                let c = a / b * b + a % b;
+                let d: i8 = -128;
+                let e: u16 = 65535;
+                println((c, d as i32 / -1 as i32, e as u32 + 1, (a << 1 as u8), d >> 7 as u8, -7 / 2, -7 % 2));
+             }");
+
+        assert_eq!(outcome.expected, "(200, 128, 65536, 144, -1, -3, -1)\n");
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_aggregates_are_passed_by_value() {
+        let outcome = run("struct Pair { int: i32, double: f64 }
+             fn static(printf: i32): i32 { printf * 2 }
+             fn bump(ys: [i32; 3], q: Pair): i32 {
+                let mut xs = ys;
+                let mut p = q;
+                xs[0] = 100;
+                p.int = 7;
+                xs[0] + p.int
+             }
+             fn main() {
+                let xs = [1, 2, 3];
+                let p = Pair { int: 1, double: 0.5 };
+                println((bump(xs, p), xs, p, static(21)));
+             }");
+
+        assert_eq!(
+            outcome.expected,
+            "(107, [1, 2, 3], Pair { int: 1, double: 0.5 }, 42)\n"
+        );
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_print_floats_and_text_like_the_interpreter() {
+        let outcome = run("fn main() {
+                println((0.1 + 0.2, 1e20, 1.0 / 3.0, 100.0, 1.5e-7, -0.0, 2.5 as f32 / 3.0 as f32));
+                println((\"añb\", 'ñ', \"tab\\there\"));
+             }");
+
+        assert_eq!(
+            outcome.expected,
+            "(0.30000000000000004, 1e20, 0.3333333333333333, 100.0, 1.5e-7, -0.0, 0.8333333)\n\
+             (\"añb\", 'ñ', \"tab\\there\")\n"
+        );
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_small_integer_overflow_is_caught() {
+        let outcome = run("fn main() {
+                let a: u8 = 20;
+                println(a * 12);
+                println(a * 13);
+             }");
+
+        assert_eq!(outcome.expected, "240\n");
+        if let Some(run) = &outcome.run {
+            assert_eq!(run.status.code(), Some(101));
+            assert_eq!(String::from_utf8_lossy(&run.stdout), "240\n");
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors_exit_with_a_message() {
+        let outcome = run("fn main() {
+                println(1);
+                let x: u8 = 200;
+                let y = x + 100;
+                println(y);
+             }");
+
+        assert_eq!(outcome.expected, "1\n");
+        if let Some(run) = &outcome.run {
+            assert_eq!(run.status.code(), Some(101));
+            assert_eq!(String::from_utf8_lossy(&run.stdout), "1\n");
+            assert_eq!(
+                String::from_utf8_lossy(&run.stderr),
+                "error: attempt to add with overflow\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_mangle_section_paths() {
+        let outcome = run("section math {
+                pub fn add(a: i32, b: i32): i32 { a + b }
+             }
+             fn main() { println(math::add(1, 2)); }");
+
+        assert!(
+            outcome
+                .c
+                .contains("static int32_t ak_4math3add(int32_t, int32_t);")
+        );
+        assert!(outcome.c.contains("ak_4main();"));
+        assert_agrees(&outcome);
+    }
+}
@@ -0,0 +1,1002 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ClosureParam, ConstItem, DefineItem, Expr, FieldInit, FnItem, Item,
+        Literal, Pattern, PostfixOp, PrefixOp, Script, Section, Spanned, Stmt,
+    },
+    bytecode::{Function, Module, Op, Scalar},
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    resolve::{DefId, DefKind, Resolution, path_span},
+    span::Span,
+    ty::Ty,
+    typeck::{TypeckResults, VariantShape},
+    value::{self, Builtin, Layout, LayoutKind, Value},
+};
+
+/// Compiles a resolved and type checked script to bytecode for the
+/// [`Vm`](crate::vm::Vm).
+///
+/// Every local gets a slot of its own in its function, so inner scopes
+/// never reuse the slots of outer ones. The compiler tracks how many values
+/// each instruction leaves on the stack, which tells `break` and `continue`
+/// how many temporaries to drop.
+pub struct Compiler<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    pub bag: DiagnosticsBag,
+    module: Module,
+    fn_ids: HashMap<DefId, u32>,
+    global_ids: HashMap<DefId, u32>,
+    layout_ids: HashMap<DefId, u32>,
+    constants: HashMap<String, u32>,
+    func: FnState,
+}
+
+/// The function being compiled.
+#[derive(Default)]
+struct FnState {
+    code: Vec<Op>,
+    spans: Vec<(u32, Span)>,
+    slots: HashMap<DefId, u32>,
+    next_slot: u32,
+    /// values on the stack at the current instruction
+    depth: i32,
+    loops: Vec<LoopState>,
+}
+
+struct LoopState {
+    depth: i32,
+    start: u32,
+    breaks: Vec<usize>,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(res: &'a Resolution, types: &'a TypeckResults) -> Self {
+        Self {
+            res,
+            types,
+            bag: DiagnosticsBag::new(),
+            module: Module::default(),
+            fn_ids: HashMap::new(),
+            global_ids: HashMap::new(),
+            layout_ids: HashMap::new(),
+            constants: HashMap::new(),
+            func: FnState::default(),
+        }
+    }
+
+    pub fn compile_script(&mut self, script: &'a Script) -> Module {
+        self.declare(&script.root);
+        self.define(&script.root);
+
+        self.module.main = self
+            .res
+            .member(self.res.root, "main".intern())
+            .and_then(|def| self.fn_ids.get(&def).copied());
+
+        std::mem::take(&mut self.module)
+    }
+
+    /// Gives every function and constant an index, so calls can refer to
+    /// functions compiled later.
+    fn declare(&mut self, section: &Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        let idx = self.reserve(self.path_name(def));
+                        self.fn_ids.insert(def, idx);
+                    }
+                }
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        let idx = self.reserve(self.path_name(def));
+                        self.global_ids
+                            .insert(def, self.module.globals.len() as u32);
+                        self.module.globals.push(idx);
+                    }
+                }
+                Item::Section(item) => self.declare(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn define(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.compile_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    self.compile_const(item)
+                }
+                Item::Section(item) => self.define(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn reserve(&mut self, name: String) -> u32 {
+        self.module.functions.push(Function {
+            name,
+            ..Function::default()
+        });
+        self.module.functions.len() as u32 - 1
+    }
+
+    fn path_name(&self, def: DefId) -> String {
+        let path: Vec<_> = self.res.path_of(def).iter().map(|s| s.as_str()).collect();
+        path.join("::")
+    }
+
+    fn compile_fn(&mut self, item: &'a FnItem) {
+        let Some(idx) = self
+            .res
+            .lookup(item.name.span)
+            .and_then(|def| self.fn_ids.get(&def).copied())
+        else {
+            return;
+        };
+
+        self.func = FnState::default();
+        for param in &item.params {
+            self.bind(param.node.name.span);
+        }
+
+        self.compile_block(&item.body, item.name.span);
+        self.emit(Op::Return, item.name.span);
+        self.finish(idx, item.params.len(), Vec::new());
+    }
+
+    fn compile_const(&mut self, item: &'a ConstItem) {
+        let Some(idx) = self
+            .res
+            .lookup(item.name.span)
+            .and_then(|def| self.global_ids.get(&def))
+            .map(|global| self.module.globals[*global as usize])
+        else {
+            return;
+        };
+
+        self.func = FnState::default();
+        self.compile(&item.value);
+        self.emit(Op::Return, item.name.span);
+        self.finish(idx, 0, Vec::new());
+    }
+
+    /// Moves the finished function into its reserved place.
+    fn finish(&mut self, idx: u32, arity: usize, captures: Vec<u32>) {
+        let state = std::mem::take(&mut self.func);
+        let func = &mut self.module.functions[idx as usize];
+
+        func.arity = arity as u32;
+        func.slots = state.next_slot;
+        func.captures = captures;
+        func.code = state.code;
+        func.spans = state.spans;
+    }
+
+    // ------------------------------------------------------------ emitting
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let offset = self.func.code.len();
+        if self.func.spans.last().map(|(_, last)| *last) != Some(span) {
+            self.func.spans.push((offset as u32, span));
+        }
+
+        self.func.code.push(op);
+        self.func.depth += effect(op);
+        offset
+    }
+
+    fn label(&self) -> u32 {
+        self.func.code.len() as u32
+    }
+
+    /// Points the jump at `offset` to the current instruction.
+    fn patch(&mut self, offset: usize) {
+        let target = self.label();
+        match &mut self.func.code[offset] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => *to = target,
+            Op::Next { exit, .. } => *exit = target,
+            op => unreachable!("cannot patch `{op:?}`"),
+        }
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let op = match value {
+            Value::Unit => Op::Unit,
+            Value::Bool(true) => Op::True,
+            Value::Bool(false) => Op::False,
+            Value::None => Op::None,
+            value => {
+                let key = format!("{value:?}");
+                let idx = match self.constants.get(&key) {
+                    Some(idx) => *idx,
+                    None => {
+                        let idx = self.module.constants.len() as u32;
+                        self.module.constants.push(value);
+                        self.constants.insert(key, idx);
+                        idx
+                    }
+                };
+                Op::Const(idx)
+            }
+        };
+
+        self.emit(op, span);
+    }
+
+    /// A new slot for the local bound at `span`.
+    fn bind(&mut self, span: Span) -> u32 {
+        let slot = self.temp_slots(1);
+        if let Some(def) = self.res.lookup(span) {
+            self.func.slots.insert(def, slot);
+        }
+        slot
+    }
+
+    fn temp_slots(&mut self, count: u32) -> u32 {
+        let slot = self.func.next_slot;
+        self.func.next_slot += count;
+        slot
+    }
+
+    fn slot(&mut self, def: DefId, span: Span) -> u32 {
+        match self.func.slots.get(&def) {
+            Some(slot) => *slot,
+            None => {
+                // a local bound by code that failed to compile
+                let slot = self.temp_slots(1);
+                self.func.slots.insert(def, slot);
+                self.bag.push(
+                    Diagnostic::error("variable is not available here")
+                        .with_label(span.primary("used here")),
+                );
+                slot
+            }
+        }
+    }
+
+    fn layout(&mut self, def: DefId) -> u32 {
+        if let Some(idx) = self.layout_ids.get(&def) {
+            return *idx;
+        }
+
+        let info = self.res.def(def);
+        let (name, kind, fields) = match self.types.variants.get(&def) {
+            Some(shape) => {
+                let parent = info
+                    .parent
+                    .map(|p| self.res.def(p).name.as_str())
+                    .unwrap_or("");
+                let name = format!("{parent}::{}", info.name.as_str());
+                match shape {
+                    VariantShape::Unit => (name, LayoutKind::Unit, Vec::new()),
+                    VariantShape::Tuple(_) => (name, LayoutKind::Tuple, Vec::new()),
+                    VariantShape::Struct(fields) => (
+                        name,
+                        LayoutKind::StructVariant,
+                        fields.iter().map(|(f, _)| f.as_str().to_owned()).collect(),
+                    ),
+                }
+            }
+            None => (
+                info.name.as_str().to_owned(),
+                LayoutKind::Struct,
+                self.types
+                    .structs
+                    .get(&def)
+                    .map(|fields| fields.iter().map(|(f, _)| f.as_str().to_owned()).collect())
+                    .unwrap_or_default(),
+            ),
+        };
+
+        let idx = self.module.layouts.len() as u32;
+        self.module.layouts.push(Rc::new(Layout {
+            id: idx,
+            name,
+            kind,
+            fields,
+        }));
+        self.layout_ids.insert(def, idx);
+        idx
+    }
+
+    /// The index of field `name` in values of the type of the expression at
+    /// `base`.
+    fn field_index(&mut self, base: Span, name: Spanned<Symbol>) -> u32 {
+        let ty = self.types.exprs.get(&base).map(Ty::peel_refs);
+
+        let idx = match ty {
+            Some(Ty::Tuple(_)) => name.node.as_str().parse().ok(),
+            Some(Ty::Path(path)) => self
+                .res
+                .def_by_path(path)
+                .and_then(|def| self.types.structs.get(&def))
+                .and_then(|fields| fields.iter().position(|(f, _)| *f == name.node)),
+            _ => None,
+        };
+
+        idx.map(|idx| idx as u32).unwrap_or_else(|| {
+            self.bag.push(
+                Diagnostic::error(format!("no field `{}`", name.node.as_str()))
+                    .with_label(name.span.primary("unknown field")),
+            );
+            0
+        })
+    }
+
+    // ---------------------------------------------------------- expressions
+
+    /// Compiles a block to its value, `span` covers the block.
+    fn compile_block(&mut self, block: &'a BlockExpr, span: Span) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { name, value, .. } => {
+                    match value {
+                        Some(value) => self.compile(value),
+                        None => _ = self.emit(Op::Unit, name.span),
+                    }
+                    let slot = self.bind(name.span);
+                    self.emit(Op::Define(slot), name.span);
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => {
+                    self.compile(expr);
+                    self.emit(Op::Pop, expr.span);
+                }
+            }
+        }
+
+        match &block.expr {
+            Some(tail) => self.compile_boxed(tail),
+            None => _ = self.emit(Op::Unit, span),
+        }
+    }
+
+    fn compile(&mut self, expr: &'a Spanned<Expr>) {
+        self.compile_at(&expr.node, expr.span)
+    }
+
+    fn compile_boxed(&mut self, expr: &'a Spanned<Box<Expr>>) {
+        self.compile_at(&expr.node, expr.span)
+    }
+
+    fn compile_at(&mut self, expr: &'a Expr, span: Span) {
+        match expr {
+            Expr::Literal(literal) => {
+                let value = value::literal(literal, self.types.exprs.get(&span));
+                self.constant(value, span);
+            }
+            Expr::Variable(_) | Expr::Path(_) => self.compile_name(span),
+            Expr::Tuple(elems) => {
+                self.compile_all(elems);
+                self.emit(Op::Tuple(elems.len() as u32), span);
+            }
+            Expr::Array(elems) => {
+                self.compile_all(elems);
+                self.emit(Op::Array(elems.len() as u32), span);
+            }
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if let Some(start) = start {
+                    self.compile_boxed(start);
+                }
+                if let Some(end) = end {
+                    self.compile_boxed(end);
+                }
+                let op = Op::Range {
+                    start: start.is_some(),
+                    end: end.is_some(),
+                    inclusive: *inclusive,
+                };
+                self.emit(op, span);
+            }
+            Expr::Binary(lhs, op, rhs) if op.node.is_logical() => {
+                self.compile_boxed(lhs);
+                self.emit(Op::Dup, op.span);
+                let jump = match op.node {
+                    BinaryOp::And => self.emit(Op::JumpIfFalse(0), op.span),
+                    _ => self.emit(Op::JumpIfTrue(0), op.span),
+                };
+                self.emit(Op::Pop, op.span);
+                self.compile_boxed(rhs);
+                self.patch(jump);
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                self.compile_boxed(lhs);
+                self.compile_boxed(rhs);
+                self.emit(Op::Binary(op.node), op.span);
+            }
+            Expr::Prefix(op, operand) => match op.node {
+                PrefixOp::Neg => {
+                    // `-128` is in range for `i8` even though `128` is not
+                    if let Expr::Literal(Literal::UInt(v, _)) = &*operand.node
+                        && let Some(Ty::Int(ty)) = self.types.exprs.get(&operand.span)
+                    {
+                        self.constant(Value::Int(-(*v as i128), *ty), span);
+                        return;
+                    }
+                    self.compile_boxed(operand);
+                    self.emit(Op::Neg, span);
+                }
+                PrefixOp::Not => {
+                    self.compile_boxed(operand);
+                    self.emit(Op::Not, span);
+                }
+                PrefixOp::Deref => {
+                    self.compile_boxed(operand);
+                    self.emit(Op::Deref, span);
+                }
+                PrefixOp::Ref | PrefixOp::RefMut => self.compile_place(operand),
+            },
+            Expr::Postfix(operand, op) => {
+                self.compile_place(operand);
+                let op_kind = match op.node {
+                    PostfixOp::Inc => BinaryOp::Add,
+                    PostfixOp::Dec => BinaryOp::Sub,
+                };
+                self.emit(Op::Bump(op_kind), op.span);
+            }
+            Expr::Call(callee, args) => {
+                self.compile_boxed(callee);
+                self.compile_all(args);
+                self.emit(Op::Call(args.len() as u32), span);
+            }
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                self.compile_name(method.span);
+
+                // borrow the receiver when the function takes a reference
+                let def = self.res.lookup(method.span);
+                let wants_ref = def
+                    .and_then(|def| self.types.fns.get(&def))
+                    .and_then(|sig| sig.params.first())
+                    .is_some_and(|param| matches!(param, Ty::Ref { .. }));
+                let is_ref = matches!(self.types.exprs.get(&receiver.span), Some(Ty::Ref { .. }));
+
+                if wants_ref && !is_ref {
+                    self.compile_place(receiver);
+                } else {
+                    self.compile_boxed(receiver);
+                }
+                self.compile_all(args);
+                self.emit(Op::Call(args.len() as u32 + 1), span);
+            }
+            Expr::Field(base, name) => {
+                self.compile_boxed(base);
+                let idx = self.field_index(base.span, *name);
+                self.emit(Op::Field(idx), name.span);
+            }
+            Expr::Index(base, index) => {
+                self.compile_boxed(base);
+                self.compile_boxed(index);
+                self.emit(Op::Index, index.span);
+            }
+            Expr::Cast(operand, _) => {
+                self.compile_boxed(operand);
+                if let Some(to) = self.types.exprs.get(&span).and_then(Scalar::from_ty) {
+                    self.emit(Op::Cast(to), span);
+                }
+            }
+            Expr::Closure { params, body, .. } => self.compile_closure(params, body, span),
+            Expr::If { cond, then, else_ } => {
+                self.compile_boxed(cond);
+                let to_else = self.emit(Op::JumpIfFalse(0), cond.span);
+                let depth = self.func.depth;
+
+                self.compile_boxed(then);
+                let to_end = self.emit(Op::Jump(0), span);
+
+                self.func.depth = depth;
+                self.patch(to_else);
+                match else_ {
+                    Some(else_) => self.compile_boxed(else_),
+                    None => _ = self.emit(Op::Unit, span),
+                }
+                self.patch(to_end);
+            }
+            Expr::Loop(body) => {
+                let start = self.label();
+                self.enter_loop(start);
+                self.compile_block(&body.node, body.span);
+                self.emit(Op::Pop, body.span);
+                self.emit(Op::Jump(start), body.span);
+                self.exit_loop();
+            }
+            Expr::While { cond, body } => {
+                let start = self.label();
+                self.enter_loop(start);
+                self.compile_boxed(cond);
+                let exit = self.emit(Op::JumpIfFalse(0), cond.span);
+                self.compile_block(body, span);
+                self.emit(Op::Pop, span);
+                self.emit(Op::Jump(start), span);
+
+                self.patch(exit);
+                self.emit(Op::Unit, span);
+                self.exit_loop();
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => {
+                self.compile_boxed(iter);
+                let state = self.temp_slots(2);
+                self.emit(Op::Iter(state), iter.span);
+
+                let start = self.label();
+                self.enter_loop(start);
+                let depth = self.func.depth;
+                let next = self.emit(
+                    Op::Next {
+                        slot: state,
+                        exit: 0,
+                    },
+                    iter.span,
+                );
+
+                let elem = self.temp_slots(1);
+                self.emit(Op::Define(elem), pattern.span);
+                let mut fails = Vec::new();
+                self.compile_pattern(pattern, elem, &mut fails);
+
+                self.compile_block(body, span);
+                self.emit(Op::Pop, span);
+                self.emit(Op::Jump(start), span);
+
+                if !fails.is_empty() {
+                    for fail in fails {
+                        self.patch(fail);
+                    }
+                    self.emit(Op::NoMatch(elem), pattern.span);
+                }
+
+                self.func.depth = depth;
+                self.patch(next);
+                self.emit(Op::Unit, span);
+                self.exit_loop();
+            }
+            Expr::Break(value) => {
+                let depth = self.func.depth;
+                match value {
+                    Some(value) => self.compile_boxed(value),
+                    None => _ = self.emit(Op::Unit, span),
+                }
+
+                if let Some(target) = self.func.loops.last().map(|l| l.depth) {
+                    if depth > target {
+                        self.emit(Op::Slide((depth - target) as u32), span);
+                    }
+                    let jump = self.emit(Op::Jump(0), span);
+                    if let Some(state) = self.func.loops.last_mut() {
+                        state.breaks.push(jump);
+                    }
+                }
+                self.func.depth = depth + 1;
+            }
+            Expr::Continue => {
+                let depth = self.func.depth;
+                if let Some((target, start)) = self.func.loops.last().map(|l| (l.depth, l.start)) {
+                    if depth > target {
+                        self.emit(Op::PopN((depth - target) as u32), span);
+                    }
+                    self.emit(Op::Jump(start), span);
+                }
+                self.func.depth = depth + 1;
+            }
+            Expr::Return(value) => {
+                let depth = self.func.depth;
+                match value {
+                    Some(value) => self.compile_boxed(value),
+                    None => _ = self.emit(Op::Unit, span),
+                }
+                self.emit(Op::Return, span);
+                self.func.depth = depth + 1;
+            }
+            Expr::Block(block) => self.compile_block(&block.node, block.span),
+            Expr::Assign { target, value } => {
+                self.compile_boxed(value);
+                match self.local(target) {
+                    Some(slot) => _ = self.emit(Op::Store(slot), target.span),
+                    None => {
+                        self.compile_place(target);
+                        self.emit(Op::Set, target.span);
+                    }
+                }
+                self.emit(Op::Unit, span);
+            }
+            Expr::AssignEq { op, target, value } => {
+                self.compile_boxed(value);
+                self.compile_place(target);
+                self.emit(Op::Update(op.node), op.span);
+                self.emit(Op::Unit, span);
+            }
+            Expr::StructInit { path, fields } => self.compile_struct(path, fields, span),
+            Expr::Match { scrutinee, arms } => {
+                self.compile_boxed(scrutinee);
+                let slot = self.temp_slots(1);
+                self.emit(Op::Define(slot), scrutinee.span);
+
+                let depth = self.func.depth;
+                let mut ends = Vec::new();
+                for arm in arms {
+                    self.func.depth = depth;
+                    let mut fails = Vec::new();
+                    self.compile_pattern(&arm.pattern, slot, &mut fails);
+                    self.compile(&arm.body);
+                    ends.push(self.emit(Op::Jump(0), arm.body.span));
+
+                    for fail in fails {
+                        self.patch(fail);
+                    }
+                }
+
+                self.func.depth = depth;
+                self.emit(Op::NoMatch(slot), scrutinee.span);
+                for end in ends {
+                    self.patch(end);
+                }
+                self.func.depth = depth + 1;
+            }
+            Expr::Error(_) => _ = self.emit(Op::Unit, span),
+        }
+    }
+
+    fn compile_all(&mut self, exprs: &'a [Spanned<Expr>]) {
+        for expr in exprs {
+            self.compile(expr);
+        }
+    }
+
+    fn compile_name(&mut self, span: Span) {
+        let Some(def) = self.res.lookup(span) else {
+            self.emit(Op::Unit, span);
+            return;
+        };
+        let info = self.res.def(def);
+
+        let op = match info.kind {
+            DefKind::Local { .. } => Op::Load(self.slot(def, span)),
+            DefKind::Fn => match self.fn_ids.get(&def) {
+                Some(idx) => Op::Func(*idx),
+                None => Op::Unit,
+            },
+            DefKind::Builtin => match Builtin::from_name(info.name.as_str()) {
+                Some(builtin) => Op::Builtin(builtin),
+                None => Op::Unit,
+            },
+            DefKind::Const => match self.global_ids.get(&def) {
+                Some(idx) => Op::Global(*idx),
+                None => Op::Unit,
+            },
+            DefKind::Variant => {
+                let layout = self.layout(def);
+                match self.types.variants.get(&def) {
+                    Some(VariantShape::Unit) => Op::Struct { layout, len: 0 },
+                    _ => Op::Ctor(layout),
+                }
+            }
+            DefKind::Section | DefKind::Struct | DefKind::Enum => {
+                self.bag.push(
+                    Diagnostic::error(format!(
+                        "{} `{}` is not a value",
+                        info.kind.describe(),
+                        info.name.as_str()
+                    ))
+                    .with_label(span.primary("not a value")),
+                );
+                Op::Unit
+            }
+        };
+
+        self.emit(op, span);
+    }
+
+    /// The slot of `expr` if it names a local variable.
+    fn local(&mut self, expr: &Spanned<Box<Expr>>) -> Option<u32> {
+        let Expr::Variable(_) = &*expr.node else {
+            return None;
+        };
+        let def = self.res.lookup(expr.span)?;
+        matches!(self.res.def(def).kind, DefKind::Local { .. }).then(|| self.slot(def, expr.span))
+    }
+
+    /// Compiles the location `expr` denotes to a reference, for assignments
+    /// and borrows. Anything that is not a variable, field, element or
+    /// dereference is a temporary.
+    fn compile_place(&mut self, expr: &'a Spanned<Box<Expr>>) {
+        if let Some(slot) = self.local(expr) {
+            self.emit(Op::Ref(slot), expr.span);
+            return;
+        }
+
+        match &*expr.node {
+            Expr::Field(base, name) => {
+                self.compile_place(base);
+                let idx = self.field_index(base.span, *name);
+                self.emit(Op::Project(idx), name.span);
+            }
+            Expr::Index(base, index) => {
+                self.compile_place(base);
+                self.compile_boxed(index);
+                self.emit(Op::ProjectIndex, index.span);
+            }
+            Expr::Prefix(op, inner) if op.node == PrefixOp::Deref => self.compile_boxed(inner),
+            _ => {
+                self.compile_boxed(expr);
+                self.emit(Op::Temp, expr.span);
+            }
+        }
+    }
+
+    fn compile_struct(
+        &mut self,
+        path: &[Spanned<Symbol>],
+        fields: &'a [Spanned<FieldInit>],
+        span: Span,
+    ) {
+        let Some(def) = self.res.lookup(path_span(path)) else {
+            self.emit(Op::Unit, span);
+            return;
+        };
+        let layout = self.layout(def);
+        let names = self.module.layouts[layout as usize].fields.clone();
+
+        let in_order = fields.len() == names.len()
+            && fields
+                .iter()
+                .zip(&names)
+                .all(|(field, name)| field.node.name.node.as_str() == name);
+
+        if in_order {
+            for field in fields {
+                self.compile(&field.node.value);
+            }
+        } else {
+            // evaluate in source order, then push in layout order
+            let first = self.temp_slots(names.len() as u32);
+            for field in fields {
+                self.compile(&field.node.value);
+                let idx = names
+                    .iter()
+                    .position(|name| field.node.name.node.as_str() == name)
+                    .unwrap_or_default();
+                self.emit(Op::Define(first + idx as u32), field.span);
+            }
+            for idx in 0..names.len() as u32 {
+                self.emit(Op::Load(first + idx), span);
+            }
+        }
+
+        let len = names.len() as u32;
+        self.emit(Op::Struct { layout, len }, span);
+    }
+
+    fn compile_closure(
+        &mut self,
+        params: &'a [Spanned<ClosureParam>],
+        body: &'a Spanned<Box<Expr>>,
+        span: Span,
+    ) {
+        // the locals used in the body that are bound outside the closure
+        let mut captures: Vec<DefId> = self
+            .res
+            .names
+            .iter()
+            .filter(|(used, def)| {
+                contains(body.span, **used)
+                    && matches!(self.res.def(**def).kind, DefKind::Local { .. })
+                    && !contains(span, self.res.def(**def).span)
+            })
+            .map(|(_, def)| *def)
+            .filter(|def| self.func.slots.contains_key(def))
+            .collect();
+        captures.sort();
+        captures.dedup();
+
+        let idx = self.reserve("<closure>".to_owned());
+        let outer = std::mem::take(&mut self.func);
+        let outer_slots = captures.iter().map(|def| outer.slots[def]).collect();
+
+        for def in &captures {
+            let slot = self.temp_slots(1);
+            self.func.slots.insert(*def, slot);
+        }
+        for param in params {
+            self.bind(param.node.name.span);
+        }
+
+        self.compile_boxed(body);
+        self.emit(Op::Return, body.span);
+        self.finish(idx, params.len(), outer_slots);
+
+        self.func = outer;
+        self.emit(Op::Closure(idx), span);
+    }
+
+    fn enter_loop(&mut self, start: u32) {
+        self.func.loops.push(LoopState {
+            depth: self.func.depth,
+            start,
+            breaks: Vec::new(),
+        });
+    }
+
+    /// Points the loop's `break`s at the current instruction, where the
+    /// loop's value is on the stack.
+    fn exit_loop(&mut self) {
+        let Some(state) = self.func.loops.pop() else {
+            return;
+        };
+        for jump in state.breaks {
+            self.patch(jump);
+        }
+        self.func.depth = state.depth + 1;
+    }
+
+    // ------------------------------------------------------------- patterns
+
+    /// Tests the value in `slot` against `pattern`, binding its variables.
+    /// Failing tests jump to the offsets left in `fails`.
+    fn compile_pattern(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        slot: u32,
+        fails: &mut Vec<usize>,
+    ) {
+        let span = pattern.span;
+
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => (),
+            Pattern::Literal(literal) => {
+                self.emit(Op::Load(slot), span);
+                let value = value::literal(&literal.node, self.types.exprs.get(&literal.span));
+                self.constant(value, span);
+                self.emit(Op::Binary(BinaryOp::Eq), span);
+                fails.push(self.emit(Op::JumpIfFalse(0), span));
+            }
+            Pattern::Variable(name) => {
+                let Some(def) = self.res.lookup(name.span) else {
+                    return;
+                };
+
+                match self.res.def(def).kind {
+                    DefKind::Variant => {
+                        let layout = self.layout(def);
+                        self.emit(Op::Load(slot), span);
+                        self.emit(Op::Tag(layout), span);
+                        fails.push(self.emit(Op::JumpIfFalse(0), span));
+                    }
+                    DefKind::Const => {
+                        self.emit(Op::Load(slot), span);
+                        self.compile_name(name.span);
+                        self.emit(Op::Binary(BinaryOp::Eq), span);
+                        fails.push(self.emit(Op::JumpIfFalse(0), span));
+                    }
+                    _ => {
+                        self.emit(Op::Load(slot), span);
+                        let local = self.bind(name.span);
+                        self.emit(Op::Define(local), span);
+                    }
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                if self.pattern_layout(path, slot, span, fails).is_none() {
+                    return;
+                }
+
+                for (idx, elem) in elems.iter().enumerate() {
+                    self.compile_subpattern(elem, slot, idx as u32, fails);
+                }
+            }
+            Pattern::Struct { path, fields } => {
+                let Some(layout) = self.pattern_layout(path, slot, span, fails) else {
+                    return;
+                };
+                let names = self.module.layouts[layout as usize].fields.clone();
+
+                for field in fields {
+                    let name = field.node.name.node.as_str();
+                    let Some(idx) = names.iter().position(|f| f == name) else {
+                        continue;
+                    };
+                    self.compile_subpattern(&field.node.pattern, slot, idx as u32, fails);
+                }
+            }
+        }
+    }
+
+    /// Checks the value in `slot` has the layout `path` names.
+    fn pattern_layout(
+        &mut self,
+        path: &[Spanned<Symbol>],
+        slot: u32,
+        span: Span,
+        fails: &mut Vec<usize>,
+    ) -> Option<u32> {
+        let def = self.res.lookup(path_span(path))?;
+        let layout = self.layout(def);
+
+        self.emit(Op::Load(slot), span);
+        self.emit(Op::Tag(layout), span);
+        fails.push(self.emit(Op::JumpIfFalse(0), span));
+        Some(layout)
+    }
+
+    fn compile_subpattern(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        slot: u32,
+        field: u32,
+        fails: &mut Vec<usize>,
+    ) {
+        if let Pattern::Wildcard = pattern.node {
+            return;
+        }
+
+        let inner = self.temp_slots(1);
+        self.emit(Op::Load(slot), pattern.span);
+        self.emit(Op::Field(field), pattern.span);
+        self.emit(Op::Define(inner), pattern.span);
+        self.compile_pattern(pattern, inner, fails);
+    }
+}
+
+/// How many values `op` adds to the stack, or removes when negative.
+fn effect(op: Op) -> i32 {
+    match op {
+        Op::Const(_)
+        | Op::Unit
+        | Op::True
+        | Op::False
+        | Op::None
+        | Op::Dup
+        | Op::Load(_)
+        | Op::Ref(_)
+        | Op::Global(_)
+        | Op::Func(_)
+        | Op::Builtin(_)
+        | Op::Ctor(_)
+        | Op::Closure(_)
+        | Op::Next { .. } => 1,
+        Op::Pop
+        | Op::Store(_)
+        | Op::Define(_)
+        | Op::Binary(_)
+        | Op::Index
+        | Op::ProjectIndex
+        | Op::JumpIfFalse(_)
+        | Op::JumpIfTrue(_)
+        | Op::Iter(_)
+        | Op::Return => -1,
+        Op::Set | Op::Update(_) => -2,
+        Op::PopN(n) | Op::Slide(n) | Op::Call(n) => -(n as i32),
+        Op::Tuple(n) | Op::Array(n) | Op::Struct { len: n, .. } => 1 - n as i32,
+        Op::Range { start, end, .. } => 1 - start as i32 - end as i32,
+        Op::Neg
+        | Op::Not
+        | Op::Cast(_)
+        | Op::Deref
+        | Op::Field(_)
+        | Op::Temp
+        | Op::Project(_)
+        | Op::Bump(_)
+        | Op::Tag(_)
+        | Op::Jump(_)
+        | Op::NoMatch(_) => 0,
+    }
+}
+
+fn contains(outer: Span, inner: Span) -> bool {
+    outer.id == inner.id && outer.lo <= inner.lo && inner.hi <= outer.hi
+}
@@ -1,8 +1,13 @@
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+};
 
 use crate::{
-    source::{FileId, Position, SourceMap},
+    source::{FileId, Position, Source, SourceMap},
     span::Span,
+    unicode,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -34,6 +39,13 @@ impl Label {
             style: LabelStyle::Secondary,
         }
     }
+
+    /// The message with `{}` replaced by the labelled source text.
+    pub fn message(&self, sm: &SourceMap) -> Option<String> {
+        let message = self.message.as_ref()?;
+        let snippet = sm[self.span.id].snippet(self.span);
+        Some(message.replace("{}", snippet))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -45,7 +57,7 @@ pub enum DiagnosticKind {
 }
 
 impl DiagnosticKind {
-    pub fn color(&self) -> &str {
+    pub fn color(&self) -> &'static str {
         match self {
             Self::Error => "\x1b[31m",   // red
             Self::Warning => "\x1b[33m", // yellow
@@ -163,9 +175,24 @@ impl Diagnostic {
         self.labels = merged;
     }
 
+    /// Renders to stderr with colors, see [`Diagnostic::write`].
     pub fn render(&self, sm: &SourceMap) {
-        let color = self.kind.color();
-        eprintln!("{}{}:{}{}", color, self.kind, RESET, self.message);
+        let _ = self.write(sm, &mut io::stderr().lock(), true);
+    }
+
+    /// Writes the diagnostic in the human readable format, with the labelled
+    /// source lines underneath the message.
+    pub fn write(&self, sm: &SourceMap, out: &mut dyn Write, color: bool) -> io::Result<()> {
+        let paint = |code: &'static str| if color { code } else { "" };
+        let reset = paint(RESET);
+
+        writeln!(
+            out,
+            "{}{}:{reset} {}",
+            paint(self.kind.color()),
+            self.kind,
+            self.message
+        )?;
 
         let mut line_map: BTreeMap<(FileId, usize), Vec<(&Label, usize)>> = BTreeMap::new();
 
@@ -192,43 +219,109 @@ impl Diagnostic {
 
         for ((file_id, line_no), mut entries) in line_map {
             let src = &sm[file_id];
-            let line_idx = line_no - 1;
-            let line_start = src.line_offsets[line_idx];
-            let line_end = src
-                .line_offsets
-                .get(line_idx + 1)
-                .copied()
-                .unwrap_or(src.content.len());
-            let line_content = &src.content[line_start..line_end];
+            let line_content = src.line_text(line_no - 1);
 
             // Show header
-            eprintln!("   --> {}:{}:{}", src.name, line_no, entries[0].1);
-            eprintln!("     |");
-            eprintln!("{:>4} | {}", line_no, line_content.trim_end());
+            writeln!(out, "   --> {}:{}:{}", src.name, line_no, entries[0].1)?;
+            writeln!(out, "     |")?;
+            writeln!(out, "{:>4} | {}", line_no, line_content.trim_end())?;
 
             // Sort by column position
             entries.sort_by_key(|(_, col)| *col);
 
             for (label, col) in entries {
-                let underline_len = label.span.len().max(1);
+                // pad and underline by what the terminal shows, not by bytes
+                let before: String = line_content.chars().take(col - 1).collect();
+                let underline_len = match src.get_offset_line(label.span.lo)
+                    == src.get_offset_line(label.span.hi)
+                {
+                    true => unicode::width(src.snippet(label.span)),
+                    false => label.span.len(),
+                };
+                let underline_len = underline_len.max(1);
                 let underline = "^".repeat(underline_len);
-                let color = match label.style {
-                    LabelStyle::Primary => "\x1b[91m",
-                    LabelStyle::Secondary => "\x1b[94m",
+                let style = match label.style {
+                    LabelStyle::Primary => paint("\x1b[91m"),
+                    LabelStyle::Secondary => paint("\x1b[94m"),
                 };
 
-                print!("     | {}", " ".repeat(col.saturating_sub(1)));
-                print!("{color}{}{RESET}", underline);
+                write!(out, "     | {}", " ".repeat(unicode::width(&before)))?;
+                write!(out, "{style}{underline}{reset}")?;
 
-                if let Some(ref msg) = label.message {
-                    let snippet = &src.content[label.span.to_range()];
-                    print!(" {}", msg.replace("{}", snippet));
+                if let Some(message) = label.message(sm) {
+                    write!(out, " {message}")?;
                 }
 
-                println!();
+                writeln!(out)?;
             }
         }
+
+        Ok(())
+    }
+
+    /// The diagnostic as a single line JSON object. Lines and columns are one
+    /// based, byte offsets zero based and end exclusive.
+    pub fn to_json(&self, sm: &SourceMap) -> String {
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let src = &sm[label.span.id];
+                let (line, col) = position(src, label.span.lo);
+                let (end_line, end_col) = position(src, label.span.hi);
+                let message = match label.message(sm) {
+                    Some(message) => json_string(&message),
+                    None => "null".to_string(),
+                };
+
+                format!(
+                    "{{\"file\":{},\"byte_start\":{},\"byte_end\":{},\"line_start\":{line},\"column_start\":{col},\"line_end\":{end_line},\"column_end\":{end_col},\"primary\":{},\"message\":{message}}}",
+                    json_string(&src.name),
+                    label.span.lo,
+                    label.span.hi,
+                    label.style == LabelStyle::Primary,
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"severity\":\"{}\",\"message\":{},\"labels\":[{}]}}",
+            self.kind,
+            json_string(&self.message),
+            labels.join(",")
+        )
+    }
+}
+
+/// The one based line and column of `offset`, counting columns in chars.
+fn position(src: &Source, offset: usize) -> (usize, usize) {
+    let line = src.get_offset_line(offset);
+    let start = src.line_offsets[line];
+    let col = src
+        .content
+        .get(start..offset)
+        .map_or(offset - start, |text| text.chars().count());
+    (line + 1, col + 1)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
     }
+
+    out.push('"');
+    out
 }
 
 #[derive(Debug, Clone, Default)]
@@ -240,16 +333,22 @@ impl DiagnosticsBag {
     }
 
     pub fn push(&mut self, diagnostic: Diagnostic) {
-        if let Some(last) = self.0.last_mut() {
-            if last.can_merge(&diagnostic) {
-                last.merge(diagnostic);
-                return;
-            }
+        if let Some(last) = self.0.last_mut()
+            && last.can_merge(&diagnostic)
+        {
+            last.merge(diagnostic);
+            return;
         }
 
         self.0.push(diagnostic);
     }
 
+    pub fn extend(&mut self, other: DiagnosticsBag) {
+        for diagnostic in other.0 {
+            self.push(diagnostic);
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
@@ -258,17 +357,36 @@ impl DiagnosticsBag {
         self.0.len()
     }
 
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(|d| d.kind == DiagnosticKind::Error)
+    }
+
     pub fn clear(&mut self) {
         self.0.clear();
     }
 
     pub fn render_all(self, sm: &SourceMap) {
+        let _ = self.write_all(sm, &mut io::stderr().lock(), true);
+    }
+
+    /// Writes every diagnostic in the human readable format, separated by
+    /// blank lines.
+    pub fn write_all(&self, sm: &SourceMap, out: &mut dyn Write, color: bool) -> io::Result<()> {
         for (idx, diag) in self.0.iter().enumerate() {
             if idx != 0 {
-                eprintln!();
+                writeln!(out)?;
             }
-            diag.render(sm);
+            diag.write(sm, out, color)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every diagnostic as a JSON object on a line of its own.
+    pub fn write_json(&self, sm: &SourceMap, out: &mut dyn Write) -> io::Result<()> {
+        for diag in &self.0 {
+            writeln!(out, "{}", diag.to_json(sm))?;
         }
+        Ok(())
     }
 }
 
@@ -0,0 +1,1125 @@
+use std::{
+    env, fmt, fs,
+    io::{self, Write},
+    mem,
+};
+
+use crate::{
+    akc,
+    asmgen::AsmGen,
+    ast::Script,
+    bytecode::{Module, disassemble},
+    cgen::CGen,
+    compiler::Compiler,
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    editor::{Editor, History, Input},
+    interner::Internable,
+    lexer::Lexer,
+    loader::Loader,
+    lower::Lowerer,
+    mir::Program,
+    mireval::Evaluator,
+    opt::{Optimizer, Passes},
+    parser::Parser,
+    pretty::Printer,
+    repl::Session,
+    resolve::{DefKind, Resolution, Resolver},
+    source::{FileId, Position, SourceMap},
+    tokens::TokenKind,
+    typeck::{TypeChecker, TypeckResults},
+    vm::Vm,
+    wasmgen::WasmGen,
+};
+
+/// the script compiled, ran or was formatted without errors
+pub const SUCCESS: i32 = 0;
+/// the script has errors, or `fmt --check` found a file to format
+pub const FAILURE: i32 = 1;
+/// the command line could not be understood
+pub const USAGE: i32 = 2;
+/// the script stopped with a runtime error, as compiled programs do
+pub const RUNTIME_ERROR: i32 = 101;
+
+pub const USAGE_TEXT: &str = "\
+usage: akuru <command> [options] <file>...
+       akuru repl
+
+commands:
+    lex      print the tokens of each file
+    parse    print the syntax tree of each file
+    check    report the errors in each file
+    run      run the `main` function of a script
+    fmt      format each file in place
+    emit     print a script in the form given by `--emit`
+    repl     read and run input line by line, `:help` lists its commands
+
+options:
+    --emit=tokens|ast|ir|bytecode|c|wasm|asm
+                              what `emit` prints
+    -o, --output=<path>       where `emit` writes, a `.wasm` or `.akc` path gets the
+                              binary module
+    -O                        optimize before `run` or `--emit=ir`
+    --disable-pass=<names>    a comma separated list of passes `-O` skips:
+                              inline, fold, propagate, simplify-cfg, dce
+    --check                   with `fmt`, list the files it would change instead
+    --color=auto|always|never
+    --error-format=human|json
+    -h, --help                print this message
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Lex,
+    Parse,
+    Check,
+    Run,
+    Fmt,
+    Emit,
+    Repl,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        let command = match name {
+            "lex" => Self::Lex,
+            "parse" => Self::Parse,
+            "check" => Self::Check,
+            "run" => Self::Run,
+            "fmt" => Self::Fmt,
+            "emit" => Self::Emit,
+            "repl" => Self::Repl,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Lex => "lex",
+            Self::Parse => "parse",
+            Self::Check => "check",
+            Self::Run => "run",
+            Self::Fmt => "fmt",
+            Self::Emit => "emit",
+            Self::Repl => "repl",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    Bytecode,
+    C,
+    Wasm,
+    Asm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output going to a terminal or not. `auto` follows
+    /// the `NO_COLOR` convention.
+    pub fn enabled(self, terminal: bool) -> bool {
+        match self {
+            Self::Auto => terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    /// one JSON object per diagnostic and line
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command: Command,
+    pub files: Vec<String>,
+    pub emit: Option<Emit>,
+    pub output: Option<String>,
+    pub optimize: bool,
+    pub passes: Passes,
+    pub check: bool,
+    pub color: ColorChoice,
+    pub error_format: ErrorFormat,
+}
+
+/// The result of reading the command line.
+#[derive(Debug)]
+pub enum Args {
+    Help,
+    Options(Options),
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut command = None;
+        let mut files = Vec::new();
+        let mut emit = None;
+        let mut output = None;
+        let mut optimize = false;
+        let mut passes = Passes::all();
+        let mut check = false;
+        let mut color = ColorChoice::Auto;
+        let mut error_format = ErrorFormat::Human;
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline {
+                Some(value) => Ok(value.to_string()),
+                None => args.next().ok_or(format!("`{flag}` needs a value")),
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(Self::Help),
+                "--emit" => {
+                    emit = Some(match value()?.as_str() {
+                        "tokens" => Emit::Tokens,
+                        "ast" => Emit::Ast,
+                        "ir" => Emit::Ir,
+                        "bytecode" => Emit::Bytecode,
+                        "c" => Emit::C,
+                        "wasm" => Emit::Wasm,
+                        "asm" => Emit::Asm,
+                        other => return Err(format!("unknown `--emit` kind `{other}`")),
+                    })
+                }
+                "-o" | "--output" => output = Some(value()?),
+                "-O" => optimize = true,
+                "--disable-pass" => {
+                    for name in value()?.split(',') {
+                        if !passes.set(name, false) {
+                            return Err(format!("unknown pass `{name}`"));
+                        }
+                    }
+                }
+                "--check" => check = true,
+                "--color" => {
+                    color = match value()?.as_str() {
+                        "auto" => ColorChoice::Auto,
+                        "always" => ColorChoice::Always,
+                        "never" => ColorChoice::Never,
+                        other => return Err(format!("unknown `--color` choice `{other}`")),
+                    }
+                }
+                "--error-format" => {
+                    error_format = match value()?.as_str() {
+                        "human" => ErrorFormat::Human,
+                        "json" => ErrorFormat::Json,
+                        other => return Err(format!("unknown `--error-format` `{other}`")),
+                    }
+                }
+                _ if flag.starts_with('-') && flag != "-" => {
+                    return Err(format!("unknown option `{flag}`"));
+                }
+                _ if command.is_none() => {
+                    command =
+                        Some(Command::from_name(&arg).ok_or(format!("unknown command `{arg}`"))?);
+                }
+                _ => files.push(arg),
+            }
+        }
+
+        let command = command.ok_or("no command given")?;
+        let only = |used: bool, flag: &str, commands: &[Command]| {
+            if used && !commands.contains(&command) {
+                return Err(format!("`{flag}` cannot be used with `{command}`"));
+            }
+            Ok(())
+        };
+        only(emit.is_some(), "--emit", &[Command::Emit])?;
+        only(output.is_some(), "--output", &[Command::Emit])?;
+        only(optimize, "-O", &[Command::Run, Command::Emit])?;
+        only(check, "--check", &[Command::Fmt])?;
+
+        if command == Command::Emit && emit.is_none() {
+            return Err("`emit` needs `--emit=<kind>`".to_string());
+        }
+        if command == Command::Repl {
+            if !files.is_empty() {
+                return Err("`repl` takes no files".to_string());
+            }
+        } else if files.is_empty() {
+            return Err(format!("`{command}` needs a file"));
+        }
+        if matches!(command, Command::Run | Command::Emit) && files.len() > 1 {
+            return Err(format!("`{command}` takes a single script"));
+        }
+
+        Ok(Self::Options(Options {
+            command,
+            files,
+            emit,
+            output,
+            optimize,
+            passes,
+            check,
+            color,
+            error_format,
+        }))
+    }
+}
+
+/// Runs the command line `args` and returns the exit status.
+pub fn main(
+    args: impl IntoIterator<Item = String>,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+    terminal: bool,
+) -> i32 {
+    let args: Vec<_> = args.into_iter().collect();
+    if args.is_empty() {
+        let _ = write!(err, "{USAGE_TEXT}");
+        return USAGE;
+    }
+
+    match Args::parse(args) {
+        Ok(Args::Help) => {
+            let _ = write!(out, "{USAGE_TEXT}");
+            SUCCESS
+        }
+        Ok(Args::Options(options)) => {
+            let code = Driver::new(options, out, err, terminal).run();
+            let _ = out.flush();
+            code
+        }
+        Err(message) => {
+            let _ = writeln!(err, "error: {message}\nrun `akuru --help` for usage");
+            USAGE
+        }
+    }
+}
+
+/// Runs the pipeline up to the stage the command asks for, writing results
+/// to `out` and diagnostics to `err`.
+pub struct Driver<'io> {
+    pub options: Options,
+    pub map: SourceMap,
+    out: &'io mut dyn Write,
+    err: &'io mut dyn Write,
+    color: bool,
+    /// how many diagnostics were written so far
+    reported: usize,
+}
+
+impl<'io> Driver<'io> {
+    pub fn new(
+        options: Options,
+        out: &'io mut dyn Write,
+        err: &'io mut dyn Write,
+        terminal: bool,
+    ) -> Self {
+        let color = options.color.enabled(terminal);
+
+        Self {
+            options,
+            map: SourceMap::fresh(),
+            out,
+            err,
+            color,
+            reported: 0,
+        }
+    }
+
+    pub fn run(&mut self) -> i32 {
+        let files = self.options.files.clone();
+
+        match self.options.command {
+            Command::Lex => self.each(&files, Self::lex),
+            Command::Parse => self.each(&files, Self::parse),
+            Command::Check => self.each(&files, Self::check),
+            Command::Fmt => self.each(&files, Self::fmt),
+            Command::Run => self.execute(&files[0]),
+            Command::Emit => self.emit(&files[0]),
+            Command::Repl => self.repl(),
+        }
+    }
+
+    /// Runs `f` on every file, failing if it fails on any.
+    fn each(&mut self, files: &[String], f: fn(&mut Self, &str) -> i32) -> i32 {
+        files
+            .iter()
+            .map(|file| f(self, file))
+            .fold(SUCCESS, |code, next| code.max(next))
+    }
+
+    fn lex(&mut self, path: &str) -> i32 {
+        let Some(id) = self.read(path) else {
+            return FAILURE;
+        };
+
+        let source = &self.map[id];
+        let mut lexer = Lexer::new(id, source);
+        let mut lines = String::new();
+
+        loop {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+
+            let (line, col) = match source.get_pos(token.span) {
+                Position::Single(line, col) => (line, col),
+                Position::Multi { lines } => lines[0],
+            };
+            let text = &source.content[token.span.to_range()];
+            lines.push_str(&format!("{line}:{col} {:?} {text:?}\n", token.kind));
+        }
+
+        let bag = lexer.bag;
+        self.write(&lines);
+        self.status(bag)
+    }
+
+    fn parse(&mut self, path: &str) -> i32 {
+        let Some((script, code)) = self.load(path) else {
+            return FAILURE;
+        };
+        self.write(&format!("{script:#?}\n"));
+        code
+    }
+
+    fn check(&mut self, path: &str) -> i32 {
+        match self.load(path) {
+            Some((script, SUCCESS)) => match self.analyze(&script) {
+                Some(_) => SUCCESS,
+                None => FAILURE,
+            },
+            _ => FAILURE,
+        }
+    }
+
+    fn fmt(&mut self, path: &str) -> i32 {
+        let Some(id) = self.read(path) else {
+            return FAILURE;
+        };
+
+        let source = &self.map[id];
+        let mut parser = Parser::new(id, source);
+        let script = parser.parse_script();
+        if parser.bag.has_errors() {
+            let bag = parser.bag;
+            return self.status(bag);
+        }
+
+        let mut printer = Printer::new(source);
+        let formatted = printer.print_script(&script);
+        let bag = printer.bag;
+        let (Some(formatted), SUCCESS) = (formatted, self.status(bag)) else {
+            return FAILURE;
+        };
+
+        if formatted == self.map[id].content {
+            return SUCCESS;
+        }
+        if self.options.check {
+            self.write(&format!("{path}\n"));
+            return FAILURE;
+        }
+
+        match fs::write(path, formatted) {
+            Ok(()) => SUCCESS,
+            Err(err) => {
+                self.error(format!("couldn't write `{path}`: {err}"));
+                FAILURE
+            }
+        }
+    }
+
+    fn execute(&mut self, path: &str) -> i32 {
+        if path.ends_with(".akc") {
+            if self.options.optimize {
+                self.error(format!("`-O` cannot be used with the compiled `{path}`"));
+                return FAILURE;
+            }
+            let Some(module) = self.load_akc(path) else {
+                return FAILURE;
+            };
+            if module.main.is_none() {
+                self.error(format!("`main` function not found in `{path}`"));
+                return FAILURE;
+            }
+            let bag = self.run_module(&module);
+            return self.runtime_status(bag);
+        }
+
+        let Some((script, SUCCESS)) = self.load(path) else {
+            return FAILURE;
+        };
+        let Some((res, types)) = self.analyze(&script) else {
+            return FAILURE;
+        };
+        if !self.has_main(&script, &res) {
+            return FAILURE;
+        }
+
+        let bag = if self.options.optimize {
+            let Some(program) = self.lower(&script, &res, &types) else {
+                return FAILURE;
+            };
+            let mut eval = Evaluator::new(&res, &types, &program, &mut *self.out);
+            eval.run();
+            eval.bag
+        } else {
+            let Some(module) = self.compile(&script, &res, &types) else {
+                return FAILURE;
+            };
+            self.run_module(&module)
+        };
+
+        self.runtime_status(bag)
+    }
+
+    fn run_module(&mut self, module: &Module) -> DiagnosticsBag {
+        let mut vm = Vm::new(module, &mut *self.out);
+        vm.run();
+        vm.bag
+    }
+
+    fn runtime_status(&mut self, bag: DiagnosticsBag) -> i32 {
+        let _ = self.out.flush();
+        match self.status(bag) {
+            SUCCESS => SUCCESS,
+            _ => RUNTIME_ERROR,
+        }
+    }
+
+    fn emit(&mut self, path: &str) -> i32 {
+        let emit = self.options.emit.unwrap_or(Emit::Ast);
+        if path.ends_with(".akc") {
+            if emit != Emit::Bytecode {
+                self.error(format!(
+                    "the compiled `{path}` can only be emitted as bytecode"
+                ));
+                return FAILURE;
+            }
+            let Some(module) = self.load_akc(path) else {
+                return FAILURE;
+            };
+            return self.output_module(&module);
+        }
+        if emit == Emit::Tokens {
+            return self.lex(path);
+        }
+
+        let Some((script, SUCCESS)) = self.load(path) else {
+            return FAILURE;
+        };
+        if emit == Emit::Ast {
+            return self.output(format!("{script:#?}\n").into_bytes());
+        }
+
+        let Some((res, types)) = self.analyze(&script) else {
+            return FAILURE;
+        };
+
+        let (text, bag) = match emit {
+            Emit::Ir => {
+                let Some(program) = self.lower(&script, &res, &types) else {
+                    return FAILURE;
+                };
+                (program.to_string(), DiagnosticsBag::new())
+            }
+            Emit::Bytecode => {
+                let Some(module) = self.compile(&script, &res, &types) else {
+                    return FAILURE;
+                };
+                return self.output_module(&module);
+            }
+            Emit::C => {
+                let mut cgen = CGen::new(&res, &types);
+                (cgen.generate(&script), cgen.bag)
+            }
+            Emit::Asm => {
+                let mut asmgen = AsmGen::new(&res, &types);
+                (asmgen.generate(&script), asmgen.bag)
+            }
+            Emit::Wasm => {
+                let mut wasmgen = WasmGen::new(&res, &types);
+                let module = wasmgen.generate(&script);
+                let bag = wasmgen.bag;
+                if self.status(bag) != SUCCESS {
+                    return FAILURE;
+                }
+                if let Err(err) = module.validate() {
+                    self.error(format!("internal error: invalid module: {err}"));
+                    return FAILURE;
+                }
+
+                let binary = self
+                    .options
+                    .output
+                    .as_ref()
+                    .is_some_and(|p| p.ends_with(".wasm"));
+                return match binary {
+                    true => self.output(module.encode()),
+                    false => self.output(module.to_wat().into_bytes()),
+                };
+            }
+            Emit::Tokens | Emit::Ast => unreachable!(),
+        };
+
+        match self.status(bag) {
+            SUCCESS => self.output(text.into_bytes()),
+            code => code,
+        }
+    }
+
+    /// Writes `module` as a `.akc` file when the output path asks for one,
+    /// and disassembled otherwise.
+    fn output_module(&mut self, module: &Module) -> i32 {
+        let binary = self
+            .options
+            .output
+            .as_ref()
+            .is_some_and(|p| p.ends_with(".akc"));
+        match binary {
+            true => self.output(akc::write(module, &self.map)),
+            false => self.output(disassemble(module, &self.map).into_bytes()),
+        }
+    }
+
+    /// Reads a compiled module, adding the sources it was compiled from to
+    /// the map so its runtime errors can show them.
+    fn load_akc(&mut self, path: &str) -> Option<Module> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                self.error(format!("couldn't read `{path}`: {err}"));
+                return None;
+            }
+        };
+
+        match akc::read(&bytes, &mut self.map) {
+            Ok(module) => Some(module),
+            Err(err) => {
+                self.error(format!("couldn't load `{path}`: {err}"));
+                None
+            }
+        }
+    }
+
+    /// Loads a script with its sections, with the status of parsing it.
+    fn load(&mut self, path: &str) -> Option<(Script, i32)> {
+        let mut loader = Loader::new(&mut self.map);
+        let script = loader.load(path);
+        let bag = loader.bag;
+        let code = self.status(bag);
+        script.map(|script| (script, code))
+    }
+
+    fn analyze(&mut self, script: &Script) -> Option<(Resolution, TypeckResults)> {
+        let mut resolver = Resolver::new();
+        let res = resolver.resolve_script(script);
+        if self.status(resolver.bag) != SUCCESS {
+            return None;
+        }
+
+        let mut checker = TypeChecker::new(&res);
+        let types = checker.check_script(script);
+        let bag = checker.bag;
+        match self.status(bag) {
+            SUCCESS => Some((res, types)),
+            _ => None,
+        }
+    }
+
+    /// Lowers to the mid-level IR, optimized with `-O`.
+    fn lower(
+        &mut self,
+        script: &Script,
+        res: &Resolution,
+        types: &TypeckResults,
+    ) -> Option<Program> {
+        let mut lowerer = Lowerer::new(res, types);
+        let mut program = lowerer.lower_script(script);
+        if self.status(lowerer.bag) != SUCCESS {
+            return None;
+        }
+        if let Err(err) = program.verify() {
+            self.error(format!("internal error: {err}"));
+            return None;
+        }
+
+        if self.options.optimize {
+            let optimizer = Optimizer::new(self.options.passes);
+            if let Err(err) = optimizer.run(&mut program) {
+                self.error(format!("internal error: {err}"));
+                return None;
+            }
+        }
+
+        Some(program)
+    }
+
+    fn compile(
+        &mut self,
+        script: &Script,
+        res: &Resolution,
+        types: &TypeckResults,
+    ) -> Option<Module> {
+        let mut compiler = Compiler::new(res, types);
+        let module = compiler.compile_script(script);
+        match self.status(compiler.bag) {
+            SUCCESS => Some(module),
+            _ => None,
+        }
+    }
+
+    fn read(&mut self, path: &str) -> Option<FileId> {
+        match self.map.insert(path) {
+            Ok(id) => Some(id),
+            Err(err) => {
+                self.error(format!("couldn't read `{path}`: {err}"));
+                None
+            }
+        }
+    }
+
+    /// Reads inputs until end of file or `:quit`, continuing an input over
+    /// lines while its brackets are open.
+    fn repl(&mut self) -> i32 {
+        let mut session = Session::new();
+        let mut editor = Editor::new(History::load(History::default_path()));
+        let mut input = String::new();
+
+        loop {
+            let _ = self.out.flush();
+            let prompt = if input.is_empty() { "> " } else { "... " };
+            match editor.read_line(prompt) {
+                Ok(Input::Line(line)) => {
+                    if !input.is_empty() {
+                        input.push('\n');
+                    }
+                    input.push_str(&line);
+                }
+                Ok(Input::Interrupt) => {
+                    input.clear();
+                    continue;
+                }
+                Ok(Input::Eof) => break,
+                Err(err) => {
+                    self.error(format!("couldn't read input: {err}"));
+                    break;
+                }
+            }
+
+            if !Session::is_complete(&input) {
+                continue;
+            }
+            let input = mem::take(&mut input);
+            editor.history.push(&input);
+            let more = session.input(&input, self.out);
+
+            // diagnostics point into the session's inputs, and follow the
+            // input they are about rather than each other
+            let bag = mem::take(&mut session.bag);
+            self.reported = 0;
+            mem::swap(&mut self.map, &mut session.map);
+            self.report(&bag);
+            mem::swap(&mut self.map, &mut session.map);
+            if !more {
+                break;
+            }
+        }
+
+        if let Err(err) = editor.history.save() {
+            self.error(format!("couldn't save the history: {err}"));
+        }
+        SUCCESS
+    }
+
+    /// Writes what `emit` produced to `--output` or `out`.
+    fn output(&mut self, bytes: Vec<u8>) -> i32 {
+        let Some(path) = self.options.output.clone() else {
+            let _ = self.out.write_all(&bytes);
+            return SUCCESS;
+        };
+
+        match fs::write(&path, bytes) {
+            Ok(()) => SUCCESS,
+            Err(err) => {
+                self.error(format!("couldn't write `{path}`: {err}"));
+                FAILURE
+            }
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        let _ = self.out.write_all(text.as_bytes());
+    }
+
+    fn error(&mut self, message: String) {
+        let mut bag = DiagnosticsBag::new();
+        bag.push(Diagnostic::error(message));
+        self.report(&bag);
+    }
+
+    /// Whether the script has a `main` function to run, reporting it if not,
+    /// before any of it runs and makes that a runtime error.
+    fn has_main(&mut self, script: &Script, res: &Resolution) -> bool {
+        let main = res
+            .member(res.root, "main".intern())
+            .filter(|def| res.def(*def).kind == DefKind::Fn);
+        if main.is_some() {
+            return true;
+        }
+
+        let mut bag = DiagnosticsBag::new();
+        bag.push(
+            Diagnostic::error("`main` function not found").with_label(
+                script
+                    .root
+                    .name
+                    .span
+                    .primary("add `fn main() { .. }` to run this script"),
+            ),
+        );
+        self.status(bag);
+        false
+    }
+
+    /// Reports `bag` and returns the status it amounts to.
+    fn status(&mut self, bag: DiagnosticsBag) -> i32 {
+        self.report(&bag);
+        match bag.has_errors() {
+            true => FAILURE,
+            false => SUCCESS,
+        }
+    }
+
+    fn report(&mut self, bag: &DiagnosticsBag) {
+        if bag.is_empty() {
+            return;
+        }
+
+        let _ = match self.options.error_format {
+            ErrorFormat::Human => {
+                if self.reported != 0 {
+                    let _ = writeln!(self.err);
+                }
+                bag.write_all(&self.map, self.err, self.color)
+            }
+            ErrorFormat::Json => bag.write_json(&self.map, self.err),
+        };
+        self.reported += bag.len();
+    }
+}
+
+/// The standard streams, for the `akuru` binary.
+pub fn run_with_std_io() -> i32 {
+    use std::io::IsTerminal;
+
+    let terminal = io::stderr().is_terminal();
+    let mut out = io::stdout().lock();
+    let mut err = io::stderr().lock();
+    main(env::args().skip(1), &mut out, &mut err, terminal)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A scratch directory holding `files`, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = env::temp_dir().join(format!("akuru-driver-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+
+            for (path, content) in files {
+                fs::write(root.join(path), content).unwrap();
+            }
+
+            Self(root)
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.0.join(file).display().to_string()
+        }
+
+        /// Runs `akuru` with `args`, where `{dir}` stands for the scratch
+        /// directory, and returns the status and what it wrote.
+        fn akuru(&self, args: &str) -> (i32, String, String) {
+            let dir = self.0.display().to_string();
+            let args = args.split(' ').map(|arg| arg.replace("{dir}", &dir));
+
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            let code = main(args, &mut out, &mut err, false);
+            let err = String::from_utf8(err).unwrap().replace(&dir, "{dir}");
+            (code, String::from_utf8(out).unwrap(), err)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn parse(args: &str) -> Result<Options, String> {
+        match Args::parse(args.split(' ').map(String::from))? {
+            Args::Options(options) => Ok(options),
+            Args::Help => panic!("unexpected help"),
+        }
+    }
+
+    #[test]
+    fn test_parse_command_line() {
+        let options =
+            parse("emit --emit ir -O --disable-pass=fold,dce main.ak --color=never").unwrap();
+        assert_eq!(options.command, Command::Emit);
+        assert_eq!(options.emit, Some(Emit::Ir));
+        assert_eq!(options.files, ["main.ak"]);
+        assert!(options.optimize && options.passes.inline && !options.passes.fold);
+        assert!(!options.passes.dce);
+        assert_eq!(options.color, ColorChoice::Never);
+        assert_eq!(options.error_format, ErrorFormat::Human);
+
+        assert!(matches!(
+            Args::parse(["run".into(), "-h".into()]),
+            Ok(Args::Help)
+        ));
+        assert_eq!(
+            parse("emit a.ak").unwrap_err(),
+            "`emit` needs `--emit=<kind>`"
+        );
+        assert_eq!(
+            parse("run --emit=c a.ak").unwrap_err(),
+            "`--emit` cannot be used with `run`"
+        );
+        assert_eq!(
+            parse("run a.ak b.ak").unwrap_err(),
+            "`run` takes a single script"
+        );
+        assert_eq!(
+            parse("check --color").unwrap_err(),
+            "`--color` needs a value"
+        );
+        assert_eq!(
+            parse("check -O a.ak").unwrap_err(),
+            "`-O` cannot be used with `check`"
+        );
+        assert_eq!(
+            parse("run --disable-pass=cse a.ak").unwrap_err(),
+            "unknown pass `cse`"
+        );
+        assert_eq!(parse("repl").unwrap().command, Command::Repl);
+        assert_eq!(parse("repl a.ak").unwrap_err(), "`repl` takes no files");
+        assert_eq!(parse("build a.ak").unwrap_err(), "unknown command `build`");
+    }
+
+    #[test]
+    fn test_run_reports_status() {
+        let scratch = Scratch::new(
+            "run",
+            &[
+                ("ok.ak", "fn main() { let x = 40; println(x + 2); }"),
+                ("panic.ak", "fn main() { let a = [1]; println(a[3]); }"),
+                ("typo.ak", "fn main() { let x: i32 = true; }"),
+                ("lib.ak", "fn helper() {}"),
+                (
+                    "overflow.ak",
+                    "fn main() { let mut x: u8 = 250; x += 3; x++; println(x * 2 + 1); }",
+                ),
+            ],
+        );
+
+        assert_eq!(
+            scratch.akuru("run {dir}/ok.ak"),
+            (SUCCESS, "42\n".into(), String::new())
+        );
+        assert_eq!(scratch.akuru("run -O {dir}/ok.ak").1, "42\n");
+
+        let (code, _, err) = scratch.akuru("run {dir}/panic.ak");
+        assert_eq!(code, RUNTIME_ERROR);
+        assert!(err.starts_with("error: index out of bounds"), "{err}");
+
+        let (code, out, err) = scratch.akuru("run {dir}/typo.ak");
+        assert_eq!((code, out.as_str()), (FAILURE, ""));
+        assert!(err.contains("--> {dir}/typo.ak:1:26"), "{err}");
+
+        // `-O` reports the same error at the same place
+        let (code, _, err) = scratch.akuru("run {dir}/overflow.ak");
+        assert_eq!(code, RUNTIME_ERROR);
+        assert!(err.contains("--> {dir}/overflow.ak:1:57"), "{err}");
+        assert_eq!(
+            scratch.akuru("run -O {dir}/overflow.ak"),
+            (code, String::new(), err)
+        );
+
+        for flags in ["", "-O "] {
+            let (code, out, err) = scratch.akuru(&format!("run {flags}{{dir}}/lib.ak"));
+            assert_eq!((code, out.as_str()), (FAILURE, ""));
+            assert!(err.starts_with("error: `main` function not found"), "{err}");
+        }
+
+        let (code, _, err) = scratch.akuru("check {dir}/missing.ak");
+        assert_eq!(code, FAILURE);
+        assert!(
+            err.starts_with("error: couldn't read `{dir}/missing.ak`"),
+            "{err}"
+        );
+
+        let (code, _, err) = scratch.akuru("run");
+        assert_eq!(code, USAGE);
+        assert_eq!(
+            err,
+            "error: `run` needs a file\nrun `akuru --help` for usage\n"
+        );
+    }
+
+    #[test]
+    fn test_json_error_format() {
+        let scratch = Scratch::new("json", &[("main.ak", "fn main() {\n    missing;\n}")]);
+
+        let (code, _, err) = scratch.akuru("check --error-format=json {dir}/main.ak");
+        assert_eq!(code, FAILURE);
+        assert_eq!(
+            err,
+            "{\"severity\":\"error\",\"message\":\"cannot find `missing` in this scope\",\"labels\":[{\"file\":\"{dir}/main.ak\",\"byte_start\":16,\"byte_end\":23,\"line_start\":2,\"column_start\":5,\"line_end\":2,\"column_end\":12,\"primary\":true,\"message\":\"not found in this scope\"}]}\n"
+        );
+
+        let (_, _, err) = scratch.akuru("check --color=always {dir}/main.ak");
+        assert!(
+            err.starts_with("\x1b[31merror:\x1b[0m cannot find"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn test_fmt_rewrites_files() {
+        let scratch = Scratch::new(
+            "fmt",
+            &[
+                ("messy.ak", "fn main(){println(1)}"),
+                ("tidy.ak", "fn main() {\n    println(1)\n}\n"),
+                ("broken.ak", "fn main( {}"),
+                ("nul.ak", "fn main(){}\0 fn junk( {"),
+            ],
+        );
+
+        let (code, out, _) = scratch.akuru("fmt --check {dir}/messy.ak {dir}/tidy.ak");
+        assert_eq!(code, FAILURE);
+        assert_eq!(out, scratch.path("messy.ak") + "\n");
+
+        assert_eq!(scratch.akuru("fmt {dir}/messy.ak").0, SUCCESS);
+        let formatted = fs::read_to_string(scratch.path("messy.ak")).unwrap();
+        assert_eq!(formatted, "fn main() {\n    println(1)\n}\n");
+
+        let (code, _, err) = scratch.akuru("fmt {dir}/broken.ak");
+        assert_eq!(code, FAILURE);
+        assert!(err.starts_with("error: syntax error"), "{err}");
+        assert_eq!(
+            fs::read_to_string(scratch.path("broken.ak")).unwrap(),
+            "fn main( {}"
+        );
+
+        // a NUL does not end the file, so the error after it is still found
+        let (code, _, err) = scratch.akuru("fmt {dir}/nul.ak");
+        assert_eq!(code, FAILURE);
+        assert!(err.contains("expected identifier, found `{`"), "{err}");
+        assert_eq!(
+            fs::read_to_string(scratch.path("nul.ak")).unwrap(),
+            "fn main(){}\0 fn junk( {"
+        );
+    }
+
+    #[test]
+    fn test_emit_stages() {
+        let scratch = Scratch::new(
+            "emit",
+            &[(
+                "main.ak",
+                "fn twice(x: i32): i32 { x * 2 }\nfn main() { println(twice(3)); }",
+            )],
+        );
+
+        let (code, out, _) = scratch.akuru("emit --emit=ir -O {dir}/main.ak");
+        assert_eq!(code, SUCCESS);
+        assert!(out.contains("const 6"), "{out}");
+        assert!(!out.contains("call"), "{out}");
+
+        let (_, out, _) = scratch.akuru("emit --emit=ast {dir}/main.ak");
+        assert!(out.contains("node: \"twice\",\n"), "{out}");
+        assert!(out.contains("span: 0:3..8,\n"), "{out}");
+
+        let (_, out, _) = scratch.akuru("emit --emit=tokens {dir}/main.ak");
+        assert!(
+            out.starts_with("1:1 Fn \"fn\"\n1:4 Ident \"twice\"\n"),
+            "{out}"
+        );
+
+        assert!(
+            scratch
+                .akuru("emit --emit=c {dir}/main.ak")
+                .1
+                .starts_with("#include \"akuru.h\"")
+        );
+        assert!(
+            scratch
+                .akuru("emit --emit=wasm {dir}/main.ak")
+                .1
+                .starts_with("(module")
+        );
+
+        let wasm = scratch.path("main.wasm");
+        let code = scratch
+            .akuru(&format!("emit --emit=wasm -o {wasm} {{dir}}/main.ak"))
+            .0;
+        assert_eq!(code, SUCCESS);
+        assert!(fs::read(wasm).unwrap().starts_with(b"\0asm"));
+
+        let (code, listing, _) = scratch.akuru("emit --emit=bytecode {dir}/main.ak");
+        assert_eq!(code, SUCCESS);
+        assert!(listing.starts_with("fn 0 `twice` (arity 1"), "{listing}");
+
+        // a compiled script runs and disassembles without its source
+        let akc = scratch.path("main.akc");
+        let code = scratch
+            .akuru(&format!("emit --emit=bytecode -o {akc} {{dir}}/main.ak"))
+            .0;
+        assert_eq!(code, SUCCESS);
+        assert!(fs::read(&akc).unwrap().starts_with(b"AKC\0"));
+        fs::remove_file(scratch.path("main.ak")).unwrap();
+        assert_eq!(
+            scratch.akuru("run {dir}/main.akc"),
+            (SUCCESS, "6\n".into(), String::new())
+        );
+        assert_eq!(
+            scratch.akuru("emit --emit=bytecode {dir}/main.akc").1,
+            listing
+        );
+
+        fs::write(&akc, b"AKC\0\x07").unwrap();
+        let (code, _, err) = scratch.akuru("run {dir}/main.akc");
+        assert_eq!(code, FAILURE);
+        assert_eq!(
+            err,
+            "error: couldn't load `{dir}/main.akc`: file is truncated at byte 5\n"
+        );
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// How many lines the history file keeps.
+pub const HISTORY_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    /// ctrl-c, which drops the input typed so far
+    Interrupt,
+    /// ctrl-d on an empty line, or the end of a piped input
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// ctrl-u, deletes up to the cursor
+    KillStart,
+    /// ctrl-k, deletes from the cursor
+    KillEnd,
+    /// ctrl-w, deletes the word before the cursor
+    KillWord,
+    Interrupt,
+    Eof,
+    /// anything else, which is ignored
+    Unknown,
+}
+
+impl Key {
+    /// Decodes the next key from the bytes of a terminal in raw mode.
+    /// Returns `None` once the bytes run out.
+    pub fn decode(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        let key = match bytes.next()? {
+            b'\r' | b'\n' => Self::Enter,
+            0x7f | 0x08 => Self::Backspace,
+            0x01 => Self::Home,
+            0x02 => Self::Left,
+            0x03 => Self::Interrupt,
+            0x04 => Self::Eof,
+            0x05 => Self::End,
+            0x06 => Self::Right,
+            0x0b => Self::KillEnd,
+            0x0e => Self::Down,
+            0x10 => Self::Up,
+            0x15 => Self::KillStart,
+            0x17 => Self::KillWord,
+            0x1b => match bytes.next() {
+                Some(b'[' | b'O') => Self::escape(bytes),
+                _ => Self::Unknown,
+            },
+            byte if byte < 0x20 => Self::Unknown,
+            byte if byte < 0x80 => Self::Char(byte as char),
+            byte => {
+                let len = match byte {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    _ => 4,
+                };
+                let mut buf = vec![byte];
+                buf.extend(bytes.take(len - 1));
+                match std::str::from_utf8(&buf) {
+                    Ok(text) => text.chars().next().map_or(Self::Unknown, Self::Char),
+                    Err(_) => Self::Unknown,
+                }
+            }
+        };
+
+        Some(key)
+    }
+
+    /// The rest of an `ESC [` or `ESC O` sequence.
+    fn escape(bytes: &mut impl Iterator<Item = u8>) -> Self {
+        let mut param = 0;
+        loop {
+            match bytes.next() {
+                Some(digit @ b'0'..=b'9') => param = param * 10 + u32::from(digit - b'0'),
+                Some(b';') => (),
+                Some(b'A') => return Self::Up,
+                Some(b'B') => return Self::Down,
+                Some(b'C') => return Self::Right,
+                Some(b'D') => return Self::Left,
+                Some(b'H') => return Self::Home,
+                Some(b'F') => return Self::End,
+                Some(b'~') => {
+                    return match param {
+                        1 | 7 => Self::Home,
+                        3 => Self::Delete,
+                        4 | 8 => Self::End,
+                        _ => Self::Unknown,
+                    };
+                }
+                _ => return Self::Unknown,
+            }
+        }
+    }
+}
+
+/// The line being edited and the cursor in it, counted in chars.
+#[derive(Debug, Default)]
+pub struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    /// Applies an editing key. Keys that do not edit the line are ignored.
+    pub fn edit(&mut self, key: Key) {
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::KillStart => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillEnd => self.chars.truncate(self.cursor),
+            Key::KillWord => {
+                let mut start = self.cursor;
+                while start > 0 && self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Lines entered before, oldest first, kept in a file between sessions.
+#[derive(Debug, Default)]
+pub struct History {
+    pub entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// The history in `$AKURU_HISTORY`, or `~/.akuru_history`.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("AKURU_HISTORY") {
+            return Some(path.into());
+        }
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".akuru_history"))
+    }
+
+    /// Loads the history at `path`, which need not exist yet.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(unescape).collect())
+            .unwrap_or_default();
+
+        Self { entries, path }
+    }
+
+    /// Adds `line`, unless it is blank or repeats the last one.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        self.entries.push(line.to_string());
+        if self.entries.len() > HISTORY_LIMIT {
+            self.entries.drain(..self.entries.len() - HISTORY_LIMIT);
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut text = String::new();
+        for entry in &self.entries {
+            text.push_str(&escape(entry));
+            text.push('\n');
+        }
+        fs::write(path, text)
+    }
+}
+
+/// Entries span several lines, which the file keeps on one.
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
+
+/// Reads lines from the terminal with editing and history, or plainly from
+/// stdin when it is not a terminal.
+pub struct Editor {
+    pub history: History,
+    terminal: bool,
+}
+
+impl Editor {
+    pub fn new(history: History) -> Self {
+        Self {
+            history,
+            terminal: io::stdin().is_terminal() && io::stdout().is_terminal(),
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        let mut stdout = io::stdout();
+        write!(stdout, "{prompt}")?;
+        stdout.flush()?;
+
+        if !self.terminal {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(Input::Eof);
+            }
+            return Ok(Input::Line(line.trim_end_matches(['\n', '\r']).to_string()));
+        }
+
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        let input = self.edit(prompt);
+        stty(&[saved.trim()])?;
+        input
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Input> {
+        let mut stdout = io::stdout();
+        let mut bytes = io::stdin().lock().bytes().map_while(Result::ok);
+        let mut line = Line::default();
+        // the entry shown, counted back from the newest, and the line being
+        // typed before moving into the history
+        let mut back = 0;
+        let mut typed = String::new();
+
+        loop {
+            let Some(key) = Key::decode(&mut bytes) else {
+                return Ok(Input::Eof);
+            };
+
+            match key {
+                Key::Enter => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(Input::Line(line.text()));
+                }
+                Key::Interrupt => {
+                    write!(stdout, "^C\r\n")?;
+                    return Ok(Input::Interrupt);
+                }
+                Key::Eof if line.text().is_empty() => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(Input::Eof);
+                }
+                Key::Eof => line.edit(Key::Delete),
+                Key::Up if back < self.history.entries.len() => {
+                    if back == 0 {
+                        typed = line.text();
+                    }
+                    back += 1;
+                    line.set(&self.history.entries[self.history.entries.len() - back]);
+                }
+                Key::Down if back > 0 => {
+                    back -= 1;
+                    match back {
+                        0 => line.set(&typed),
+                        _ => line.set(&self.history.entries[self.history.entries.len() - back]),
+                    }
+                }
+                key => line.edit(key),
+            }
+
+            // multi-line entries from the history are shown on one line
+            let text = line.text().replace('\n', " ");
+            write!(stdout, "\r\x1b[K{prompt}{text}")?;
+            let left = line.text().chars().count() - line.cursor();
+            if left > 0 {
+                write!(stdout, "\x1b[{left}D")?;
+            }
+            stdout.flush()?;
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(io::Error::other("could not set up the terminal")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut bytes = bytes.iter().copied();
+        std::iter::from_fn(|| Key::decode(&mut bytes)).collect()
+    }
+
+    #[test]
+    fn test_decode_keys() {
+        assert_eq!(
+            keys(b"a\x1b[A\x1b[D\x1b[3~\x1bOH\x7f\r\x03"),
+            [
+                Key::Char('a'),
+                Key::Up,
+                Key::Left,
+                Key::Delete,
+                Key::Home,
+                Key::Backspace,
+                Key::Enter,
+                Key::Interrupt,
+            ]
+        );
+        assert_eq!(keys("ދ".as_bytes()), [Key::Char('ދ')]);
+    }
+
+    #[test]
+    fn test_edit_line() {
+        let mut line = Line::default();
+        for key in keys(b"let y = 1\x01\x06\x06\x06\x06\x06\x7fx") {
+            line.edit(key);
+        }
+        assert_eq!(line.text(), "let x = 1");
+        assert_eq!(line.cursor(), 5);
+
+        line.edit(Key::End);
+        line.edit(Key::KillWord);
+        assert_eq!(line.text(), "let x = ");
+        line.edit(Key::Home);
+        line.edit(Key::KillEnd);
+        assert_eq!(line.text(), "");
+    }
+
+    #[test]
+    fn test_history_round_trips_through_its_file() {
+        let path = env::temp_dir().join(format!("akuru-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::load(Some(path.clone()));
+        assert!(history.entries.is_empty());
+        history.push("fn f() {\n    \"a\\n\"\n}");
+        history.push("f()");
+        history.push("f()");
+        history.push("  ");
+        history.save().unwrap();
+
+        let loaded = History::load(Some(path.clone()));
+        assert_eq!(loaded.entries, ["fn f() {\n    \"a\\n\"\n}", "f()"]);
+        fs::remove_file(path).unwrap();
+    }
+}
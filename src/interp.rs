@@ -0,0 +1,1035 @@
+use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, Expr, FnItem, Item, Literal, Pattern,
+        PostfixOp, PrefixOp, Script, Section, Spanned, Stmt,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::Internable,
+    resolve::{DefId, DefKind, Resolution, path_span},
+    span::Span,
+    ty::{IntTy, Ty},
+    typeck::{TypeckResults, VariantShape},
+    value::{self, Builtin, Closure, Layout, LayoutKind, Place, Value},
+};
+
+/// How deep calls may nest before the script is stopped.
+pub const MAX_CALL_DEPTH: usize = 2_000;
+
+/// Why evaluation stopped before producing a value.
+enum Flow {
+    Break(Value),
+    Continue,
+    Return(Value),
+    Error(Diagnostic),
+}
+
+type Eval<T = Value> = Result<T, Flow>;
+
+struct ClosureCode {
+    params: Vec<DefId>,
+    body: Spanned<Box<Expr>>,
+}
+
+/// Runs a resolved and type checked script by walking its syntax tree.
+///
+/// Every local has its own cell, keyed by the [`DefId`] the resolver gave
+/// it, so shadowing needs no special handling. Closures share the cells of
+/// the variables around them.
+pub struct Interpreter<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    pub bag: DiagnosticsBag,
+    out: Box<dyn Write + 'a>,
+    fns: HashMap<DefId, &'a FnItem>,
+    consts: HashMap<DefId, &'a ConstItem>,
+    const_values: HashMap<DefId, Value>,
+    layouts: HashMap<DefId, Rc<Layout>>,
+    closures: Vec<Rc<ClosureCode>>,
+    closure_ids: HashMap<Span, u32>,
+    env: HashMap<DefId, Rc<RefCell<Value>>>,
+    depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(res: &'a Resolution, types: &'a TypeckResults, out: impl Write + 'a) -> Self {
+        Self {
+            res,
+            types,
+            bag: DiagnosticsBag::new(),
+            out: Box::new(out),
+            fns: HashMap::new(),
+            consts: HashMap::new(),
+            const_values: HashMap::new(),
+            layouts: HashMap::new(),
+            closures: Vec::new(),
+            closure_ids: HashMap::new(),
+            env: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    /// Runs the `main` function of the script's root section.
+    pub fn run(&mut self, script: &'a Script) -> Option<Value> {
+        self.load(&script.root);
+
+        let main = self
+            .res
+            .member(self.res.root, "main".intern())
+            .filter(|def| self.res.def(*def).kind == DefKind::Fn);
+
+        let Some(main) = main else {
+            self.bag.push(
+                Diagnostic::error("`main` function not found").with_label(
+                    script
+                        .root
+                        .name
+                        .span
+                        .primary("add `fn main() { .. }` to run this script"),
+                ),
+            );
+            return None;
+        };
+
+        let span = self.res.def(main).span;
+        let result = self.call(Value::Fn(main.0), Vec::new(), span);
+        self.finish(result)
+    }
+
+    /// Evaluates a single expression, in the environment left by earlier calls.
+    pub fn eval_expr(&mut self, expr: &Spanned<Expr>) -> Option<Value> {
+        let result = self.eval(expr);
+        self.finish(result)
+    }
+
+    /// Runs the statements of `block`, in the environment left by earlier calls.
+    pub fn run_block(&mut self, block: &BlockExpr) -> Option<Value> {
+        let result = self.eval_block(block);
+        self.finish(result)
+    }
+
+    /// Gives the local `def` the variable `cell`, shared with whoever else
+    /// holds it.
+    pub fn bind(&mut self, def: DefId, cell: Rc<RefCell<Value>>) {
+        self.env.insert(def, cell);
+    }
+
+    /// The variable of the local `def` in the current environment.
+    pub fn binding(&self, def: DefId) -> Option<Rc<RefCell<Value>>> {
+        self.env.get(&def).cloned()
+    }
+
+    /// Makes the items of `section` and the sections in it callable.
+    pub fn load(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        self.fns.insert(def, item);
+                    }
+                }
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        self.consts.insert(def, item);
+                    }
+                }
+                Item::Section(item) => self.load(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn finish(&mut self, result: Eval) -> Option<Value> {
+        let _ = self.out.flush();
+
+        match result {
+            Ok(value) | Err(Flow::Return(value)) => Some(value),
+            Err(Flow::Error(diagnostic)) => {
+                self.bag.push(diagnostic);
+                None
+            }
+            Err(Flow::Break(_) | Flow::Continue) => None,
+        }
+    }
+
+    fn error<T>(&self, span: Span, message: impl Into<String>, label: &str) -> Eval<T> {
+        Err(Flow::Error(
+            Diagnostic::error(message).with_label(span.primary(label)),
+        ))
+    }
+
+    // ---------------------------------------------------------------- calls
+
+    fn call(&mut self, callee: Value, args: Vec<Value>, span: Span) -> Eval {
+        match callee {
+            Value::Fn(id) => {
+                let def = DefId(id);
+                let Some(item) = self.fns.get(&def).copied() else {
+                    return self.error(span, "function has no body", "called here");
+                };
+
+                let params = item
+                    .params
+                    .iter()
+                    .filter_map(|param| self.res.lookup(param.node.name.span));
+                let env = params
+                    .zip(args)
+                    .map(|(def, arg)| (def, cell(arg)))
+                    .collect();
+
+                self.enter(env, span, |this| this.eval_block(&item.body))
+            }
+            Value::Closure(closure) => {
+                let code = self.closures[closure.code as usize].clone();
+                let mut env: HashMap<_, _> = closure
+                    .captures
+                    .iter()
+                    .map(|(def, value)| (DefId(*def), value.clone()))
+                    .collect();
+                env.extend(
+                    code.params
+                        .iter()
+                        .zip(args)
+                        .map(|(def, arg)| (*def, cell(arg))),
+                );
+
+                self.enter(env, span, |this| this.eval_boxed(&code.body))
+            }
+            Value::Builtin(builtin) => self.builtin(builtin, args, span),
+            Value::Ctor(layout) => Ok(Value::Struct(layout, args)),
+            value => self.error(span, format!("`{value}` is not a function"), "called here"),
+        }
+    }
+
+    /// Runs `f` in a fresh environment, turning a `return` into its value.
+    fn enter(
+        &mut self,
+        env: HashMap<DefId, Rc<RefCell<Value>>>,
+        span: Span,
+        f: impl FnOnce(&mut Self) -> Eval,
+    ) -> Eval {
+        if self.depth >= MAX_CALL_DEPTH {
+            return self.error(
+                span,
+                format!("stack overflow: calls nested more than {MAX_CALL_DEPTH} deep"),
+                "while calling this",
+            );
+        }
+
+        let saved = std::mem::replace(&mut self.env, env);
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        self.env = saved;
+
+        match result {
+            Err(Flow::Return(value)) => Ok(value),
+            result => result,
+        }
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: Vec<Value>, span: Span) -> Eval {
+        match builtin {
+            Builtin::Print | Builtin::Println => {
+                for arg in &args {
+                    let _ = write!(self.out, "{arg}");
+                }
+                if builtin == Builtin::Println {
+                    let _ = writeln!(self.out);
+                }
+                Ok(Value::Unit)
+            }
+            Builtin::Assert => match args.first() {
+                Some(Value::Bool(true)) => Ok(Value::Unit),
+                _ => self.error(span, "assertion failed", "this assertion does not hold"),
+            },
+        }
+    }
+
+    // ---------------------------------------------------------- expressions
+
+    fn eval_block(&mut self, block: &BlockExpr) -> Eval {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { name, value, .. } => {
+                    let Some(def) = self.res.lookup(name.span) else {
+                        continue;
+                    };
+                    if let Some(value) = value {
+                        let value = self.eval(value)?;
+                        self.env.insert(def, cell(value));
+                    }
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => {
+                    self.eval(expr)?;
+                }
+            }
+        }
+
+        match &block.expr {
+            Some(tail) => self.eval_boxed(tail),
+            None => Ok(Value::Unit),
+        }
+    }
+
+    fn eval(&mut self, expr: &Spanned<Expr>) -> Eval {
+        self.eval_at(&expr.node, expr.span)
+    }
+
+    fn eval_boxed(&mut self, expr: &Spanned<Box<Expr>>) -> Eval {
+        self.eval_at(&expr.node, expr.span)
+    }
+
+    fn eval_at(&mut self, expr: &Expr, span: Span) -> Eval {
+        match expr {
+            Expr::Literal(literal) => Ok(self.literal(literal, span)),
+            Expr::Variable(_) | Expr::Path(_) => self.name(span),
+            Expr::Tuple(elems) => Ok(Value::Tuple(self.eval_all(elems)?)),
+            Expr::Array(elems) => Ok(Value::Array(self.eval_all(elems)?)),
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = match start {
+                    Some(start) => Some(Box::new(self.eval_boxed(start)?)),
+                    None => None,
+                };
+                let end = match end {
+                    Some(end) => Some(Box::new(self.eval_boxed(end)?)),
+                    None => None,
+                };
+                Ok(Value::Range {
+                    start,
+                    end,
+                    inclusive: *inclusive,
+                })
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = self.eval_boxed(lhs)?;
+
+                match (op.node, &lhs) {
+                    (BinaryOp::And, Value::Bool(false)) => return Ok(lhs),
+                    (BinaryOp::Or, Value::Bool(true)) => return Ok(lhs),
+                    _ => (),
+                }
+
+                let rhs = self.eval_boxed(rhs)?;
+                self.binary(op.node, &lhs, &rhs, op.span)
+            }
+            Expr::Prefix(op, operand) => match op.node {
+                PrefixOp::Neg => {
+                    // `-128` is in range for `i8` even though `128` is not
+                    if let Expr::Literal(literal @ Literal::UInt(v, _)) = &*operand.node
+                        && let Value::Int(_, ty) = self.literal(literal, operand.span)
+                    {
+                        return Ok(Value::Int(-(*v as i128), ty));
+                    }
+                    let value = self.eval_boxed(operand)?;
+                    value::negate(&value).or_else(|err| self.error(span, err, "negated here"))
+                }
+                PrefixOp::Not => {
+                    let value = self.eval_boxed(operand)?;
+                    value::not(&value).or_else(|err| self.error(span, err, "here"))
+                }
+                PrefixOp::Deref => match self.eval_boxed(operand)? {
+                    Value::Ref(place) => Ok(place.get()),
+                    value => Ok(value),
+                },
+                PrefixOp::Ref | PrefixOp::RefMut => Ok(Value::Ref(self.place(operand)?)),
+            },
+            Expr::Postfix(operand, op) => {
+                let place = self.place(operand)?;
+                let old = place.get();
+                let Value::Int(_, ty) = old else {
+                    return self.error(span, "only integers can be incremented", "here");
+                };
+                let op_kind = match op.node {
+                    PostfixOp::Inc => BinaryOp::Add,
+                    PostfixOp::Dec => BinaryOp::Sub,
+                };
+                let new = self.binary(op_kind, &old, &Value::Int(1, ty), op.span)?;
+                place.set(new);
+                Ok(old)
+            }
+            Expr::Call(callee, args) => {
+                let callee = self.eval_boxed(callee)?;
+                let args = self.eval_all(args)?;
+                self.call(callee, args, span)
+            }
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let Some(def) = self.res.lookup(method.span) else {
+                    return self.error(method.span, "method not found", "no such function");
+                };
+                let callee = self.value_of(def, method.span)?;
+
+                // borrow the receiver when the function takes a reference
+                let wants_ref = self
+                    .types
+                    .fns
+                    .get(&def)
+                    .and_then(|sig| sig.params.first())
+                    .is_some_and(|param| matches!(param, Ty::Ref { .. }));
+                let is_ref = matches!(self.types.exprs.get(&receiver.span), Some(Ty::Ref { .. }));
+
+                let receiver = if wants_ref && !is_ref {
+                    Value::Ref(self.place(receiver)?)
+                } else {
+                    self.eval_boxed(receiver)?
+                };
+
+                let mut values = vec![receiver];
+                values.extend(self.eval_all(args)?);
+                self.call(callee, values, span)
+            }
+            Expr::Field(base, name) => {
+                let base = self.eval_boxed(base)?.deref();
+                match &base {
+                    Value::Struct(layout, fields) => {
+                        match layout.fields.iter().position(|f| f == name.node.as_str()) {
+                            Some(idx) => Ok(fields[idx].clone()),
+                            None => self.error(
+                                name.span,
+                                format!("no field `{}`", name.node.as_str()),
+                                "unknown field",
+                            ),
+                        }
+                    }
+                    _ => self.error(
+                        name.span,
+                        format!("no field `{}` on `{base}`", name.node.as_str()),
+                        "unknown field",
+                    ),
+                }
+            }
+            Expr::Index(base, index) => {
+                let base_value = self.eval_boxed(base)?.deref();
+                let index_value = self.eval_boxed(index)?;
+                value::index(base_value, &index_value)
+                    .or_else(|err| self.error(index.span, err, "this index"))
+            }
+            Expr::Cast(operand, _) => {
+                let value = self.eval_boxed(operand)?;
+                let to = self.types.exprs.get(&span).cloned().unwrap_or(Ty::Infer);
+                Ok(value::cast(&value, &to))
+            }
+            Expr::Closure { params, body, .. } => {
+                let code = match self.closure_ids.get(&span) {
+                    Some(code) => *code,
+                    None => {
+                        let code = self.closures.len() as u32;
+                        self.closures.push(Rc::new(ClosureCode {
+                            params: params
+                                .iter()
+                                .filter_map(|p| self.res.lookup(p.node.name.span))
+                                .collect(),
+                            body: body.clone(),
+                        }));
+                        self.closure_ids.insert(span, code);
+                        code
+                    }
+                };
+
+                let captures = self
+                    .env
+                    .iter()
+                    .map(|(def, value)| (def.0, value.clone()))
+                    .collect();
+                Ok(Value::Closure(Rc::new(Closure { code, captures })))
+            }
+            Expr::If { cond, then, else_ } => {
+                if self.eval_boxed(cond)?.is_truthy() {
+                    self.eval_boxed(then)
+                } else if let Some(else_) = else_ {
+                    self.eval_boxed(else_)
+                } else {
+                    Ok(Value::Unit)
+                }
+            }
+            Expr::Loop(body) => loop {
+                match self.eval_block(&body.node) {
+                    Ok(_) | Err(Flow::Continue) => (),
+                    Err(Flow::Break(value)) => return Ok(value),
+                    Err(flow) => return Err(flow),
+                }
+            },
+            Expr::While { cond, body } => {
+                while self.eval_boxed(cond)?.is_truthy() {
+                    match self.eval_block(body) {
+                        Ok(_) | Err(Flow::Continue) => (),
+                        Err(Flow::Break(_)) => break,
+                        Err(flow) => return Err(flow),
+                    }
+                }
+                Ok(Value::Unit)
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => {
+                let iter_value = self.eval_boxed(iter)?.deref();
+                let mut items = Items::new(iter_value, iter.span)?;
+
+                while let Some(item) = items.next().map_err(|err| {
+                    Flow::Error(
+                        Diagnostic::error(err)
+                            .with_label(iter.span.primary("while iterating this")),
+                    )
+                })? {
+                    if !self.matches(pattern, item)? {
+                        return self.error(
+                            pattern.span,
+                            "pattern did not match the element",
+                            "refutable pattern",
+                        );
+                    }
+                    match self.eval_block(body) {
+                        Ok(_) | Err(Flow::Continue) => (),
+                        Err(Flow::Break(_)) => break,
+                        Err(flow) => return Err(flow),
+                    }
+                }
+                Ok(Value::Unit)
+            }
+            Expr::Break(value) => {
+                let value = match value {
+                    Some(value) => self.eval_boxed(value)?,
+                    None => Value::Unit,
+                };
+                Err(Flow::Break(value))
+            }
+            Expr::Continue => Err(Flow::Continue),
+            Expr::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval_boxed(value)?,
+                    None => Value::Unit,
+                };
+                Err(Flow::Return(value))
+            }
+            Expr::Block(block) => self.eval_block(&block.node),
+            Expr::Assign { target, value } => {
+                let value = self.eval_boxed(value)?;
+                self.place(target)?.set(value);
+                Ok(Value::Unit)
+            }
+            Expr::AssignEq { op, target, value } => {
+                let value = self.eval_boxed(value)?;
+                let place = self.place(target)?;
+                let new = self.binary(op.node, &place.get(), &value, op.span)?;
+                place.set(new);
+                Ok(Value::Unit)
+            }
+            Expr::StructInit { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return self.error(span, "unknown struct", "here");
+                };
+                let layout = self.layout(def);
+
+                let mut values = vec![Value::Unit; layout.fields.len()];
+                for field in fields {
+                    let value = self.eval(&field.node.value)?;
+                    if let Some(idx) = layout
+                        .fields
+                        .iter()
+                        .position(|f| f == field.node.name.node.as_str())
+                    {
+                        values[idx] = value;
+                    }
+                }
+                Ok(Value::Struct(layout, values))
+            }
+            Expr::Match { scrutinee, arms } => {
+                let value = self.eval_boxed(scrutinee)?;
+
+                for arm in arms {
+                    if self.matches(&arm.pattern, value.clone())? {
+                        return self.eval(&arm.body);
+                    }
+                }
+
+                self.error(
+                    scrutinee.span,
+                    format!("no `match` arm matches `{value}`"),
+                    "this value was not matched",
+                )
+            }
+            Expr::Error(_) => self.error(span, "cannot run code that failed to parse", "here"),
+        }
+    }
+
+    fn eval_all(&mut self, exprs: &[Spanned<Expr>]) -> Eval<Vec<Value>> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    fn binary(&self, op: BinaryOp, lhs: &Value, rhs: &Value, span: Span) -> Eval {
+        value::binary(op, lhs, rhs).or_else(|err| self.error(span, err, "in this operation"))
+    }
+
+    fn literal(&self, literal: &Literal, span: Span) -> Value {
+        value::literal(literal, self.types.exprs.get(&span))
+    }
+
+    fn name(&mut self, span: Span) -> Eval {
+        match self.res.lookup(span) {
+            Some(def) => self.value_of(def, span),
+            None => self.error(span, "unresolved name", "not found"),
+        }
+    }
+
+    fn value_of(&mut self, def: DefId, span: Span) -> Eval {
+        let info = self.res.def(def);
+
+        match info.kind {
+            DefKind::Local { .. } => match self.env.get(&def) {
+                Some(value) => Ok(value.borrow().clone()),
+                None => self.error(
+                    span,
+                    format!("`{}` is used before it is assigned", info.name.as_str()),
+                    "not assigned yet",
+                ),
+            },
+            DefKind::Fn => Ok(Value::Fn(def.0)),
+            DefKind::Builtin => match Builtin::from_name(info.name.as_str()) {
+                Some(builtin) => Ok(Value::Builtin(builtin)),
+                None => self.error(span, "unknown builtin", "here"),
+            },
+            DefKind::Const => self.const_value(def, span),
+            DefKind::Variant => {
+                let layout = self.layout(def);
+                match layout.kind {
+                    LayoutKind::Unit => Ok(Value::Struct(layout, Vec::new())),
+                    _ => Ok(Value::Ctor(layout)),
+                }
+            }
+            DefKind::Section | DefKind::Struct | DefKind::Enum => self.error(
+                span,
+                format!(
+                    "{} `{}` is not a value",
+                    info.kind.describe(),
+                    info.name.as_str()
+                ),
+                "not a value",
+            ),
+        }
+    }
+
+    fn const_value(&mut self, def: DefId, span: Span) -> Eval {
+        if let Some(value) = self.const_values.get(&def) {
+            return Ok(value.clone());
+        }
+        let Some(item) = self.consts.get(&def).copied() else {
+            return self.error(span, "constant has no value", "here");
+        };
+
+        let value = self.enter(HashMap::new(), span, |this| this.eval(&item.value))?;
+        self.const_values.insert(def, value.clone());
+        Ok(value)
+    }
+
+    fn layout(&mut self, def: DefId) -> Rc<Layout> {
+        if let Some(layout) = self.layouts.get(&def) {
+            return layout.clone();
+        }
+
+        let layout = Rc::new(layout(self.res, self.types, def));
+        self.layouts.insert(def, layout.clone());
+        layout
+    }
+
+    /// The location `expr` denotes, for assignments and borrows. Anything
+    /// that is not a variable, field, element or dereference is a temporary.
+    fn place(&mut self, expr: &Spanned<Box<Expr>>) -> Eval<Place> {
+        match &*expr.node {
+            Expr::Variable(_) => {
+                let def = self.res.lookup(expr.span);
+                match def.and_then(|def| self.env.get(&def)) {
+                    Some(cell) => Ok(Place::new(cell.clone())),
+                    None => {
+                        // a `let x;` assigned for the first time
+                        let Some(def) =
+                            def.filter(|d| matches!(self.res.def(*d).kind, DefKind::Local { .. }))
+                        else {
+                            return Ok(Place::temporary(self.eval_boxed(expr)?));
+                        };
+                        let cell = cell(Value::Unit);
+                        self.env.insert(def, cell.clone());
+                        Ok(Place::new(cell))
+                    }
+                }
+            }
+            Expr::Field(base, name) => {
+                let place = self.place(base)?;
+                let place = self.auto_deref(place);
+                match place.get() {
+                    Value::Struct(layout, _) => {
+                        match layout.fields.iter().position(|f| f == name.node.as_str()) {
+                            Some(idx) => Ok(place.project(idx)),
+                            None => self.error(
+                                name.span,
+                                format!("no field `{}`", name.node.as_str()),
+                                "unknown field",
+                            ),
+                        }
+                    }
+                    value => self.error(
+                        name.span,
+                        format!("no field `{}` on `{value}`", name.node.as_str()),
+                        "unknown field",
+                    ),
+                }
+            }
+            Expr::Index(base, index) => {
+                let place = self.place(base)?;
+                let place = self.auto_deref(place);
+                let index_value = self.eval_boxed(index)?;
+                // a slice is a copy of the elements, as in `eval`
+                if let Value::Range { .. } = index_value {
+                    return value::index(place.get(), &index_value)
+                        .map(Place::temporary)
+                        .or_else(|err| self.error(index.span, err, "this index"));
+                }
+                let idx = index_value.as_int().unwrap_or(-1);
+                let len = place.get().elems().len();
+
+                match usize::try_from(idx) {
+                    Ok(idx) if idx < len => Ok(place.project(idx)),
+                    _ => self.error(
+                        index.span,
+                        format!("index out of bounds: the length is {len} but the index is {idx}"),
+                        "this index",
+                    ),
+                }
+            }
+            Expr::Prefix(op, inner) if op.node == PrefixOp::Deref => {
+                match self.eval_boxed(inner)? {
+                    Value::Ref(place) => Ok(place),
+                    value => Ok(Place::temporary(value)),
+                }
+            }
+            _ => Ok(Place::temporary(self.eval_boxed(expr)?)),
+        }
+    }
+
+    /// Fields and elements are reached through any references in the way.
+    fn auto_deref(&self, mut place: Place) -> Place {
+        while let Value::Ref(inner) = place.get() {
+            place = inner;
+        }
+        place
+    }
+
+    // ------------------------------------------------------------- patterns
+
+    /// Tests `value` against `pattern`, binding its variables on success.
+    fn matches(&mut self, pattern: &Spanned<Pattern>, value: Value) -> Eval<bool> {
+        let binding = matches!(pattern.node, Pattern::Variable(_) | Pattern::Wildcard);
+        let value = if binding { value } else { value.deref() };
+
+        match &pattern.node {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Error(_) => Ok(false),
+            Pattern::Literal(literal) => {
+                let expected = self.literal(&literal.node, literal.span);
+                Ok(value::values_eq(&expected, &value))
+            }
+            Pattern::Variable(name) => {
+                let Some(def) = self.res.lookup(name.span) else {
+                    return Ok(false);
+                };
+
+                match self.res.def(def).kind {
+                    DefKind::Variant => {
+                        Ok(matches!(&value.deref(), Value::Struct(layout, _) if layout.id == def.0))
+                    }
+                    DefKind::Const => {
+                        let expected = self.const_value(def, name.span)?;
+                        Ok(value::values_eq(&expected, &value))
+                    }
+                    _ => {
+                        self.env.insert(def, cell(value));
+                        Ok(true)
+                    }
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                let def = self.res.lookup(path_span(path));
+                let Value::Struct(layout, fields) = value else {
+                    return Ok(false);
+                };
+                if Some(layout.id) != def.map(|d| d.0) {
+                    return Ok(false);
+                }
+
+                for (elem, field) in elems.iter().zip(fields) {
+                    if !self.matches(elem, field)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::Struct { path, fields } => {
+                let def = self.res.lookup(path_span(path));
+                let Value::Struct(layout, values) = value else {
+                    return Ok(false);
+                };
+                if Some(layout.id) != def.map(|d| d.0) {
+                    return Ok(false);
+                }
+
+                for field in fields {
+                    let Some(idx) = layout
+                        .fields
+                        .iter()
+                        .position(|f| f == field.node.name.node.as_str())
+                    else {
+                        return Ok(false);
+                    };
+                    if !self.matches(&field.node.pattern, values[idx].clone())? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// The layout of the values of a struct or enum variant.
+pub fn layout(res: &Resolution, types: &TypeckResults, def: DefId) -> Layout {
+    let info = res.def(def);
+    let (name, kind, fields) = match types.variants.get(&def) {
+        Some(shape) => {
+            let parent = info.parent.map(|p| res.def(p).name.as_str()).unwrap_or("");
+            let name = format!("{parent}::{}", info.name.as_str());
+            match shape {
+                VariantShape::Unit => (name, LayoutKind::Unit, Vec::new()),
+                VariantShape::Tuple(_) => (name, LayoutKind::Tuple, Vec::new()),
+                VariantShape::Struct(fields) => (
+                    name,
+                    LayoutKind::StructVariant,
+                    fields.iter().map(|(f, _)| f.as_str().to_owned()).collect(),
+                ),
+            }
+        }
+        None => (
+            info.name.as_str().to_owned(),
+            LayoutKind::Struct,
+            types
+                .structs
+                .get(&def)
+                .map(|fields| fields.iter().map(|(f, _)| f.as_str().to_owned()).collect())
+                .unwrap_or_default(),
+        ),
+    };
+
+    Layout {
+        id: def.0,
+        name,
+        kind,
+        fields,
+    }
+}
+
+fn cell(value: Value) -> Rc<RefCell<Value>> {
+    Rc::new(RefCell::new(value))
+}
+
+/// The elements a `for` loop visits.
+enum Items {
+    Range {
+        next: i128,
+        end: Option<i128>,
+        ty: IntTy,
+    },
+    Values(std::vec::IntoIter<Value>),
+}
+
+impl Items {
+    fn new(value: Value, span: Span) -> Eval<Self> {
+        match value {
+            Value::Range {
+                start: Some(start),
+                end,
+                inclusive,
+            } => {
+                let Value::Int(next, ty) = *start else {
+                    return Err(not_iterable(span));
+                };
+                let end = end
+                    .and_then(|end| end.as_int())
+                    .map(|end| end + inclusive as i128);
+                Ok(Self::Range { next, end, ty })
+            }
+            Value::Array(elems) => Ok(Self::Values(elems.into_iter())),
+            Value::Str(s) => Ok(Self::Values(
+                s.chars().map(Value::Char).collect::<Vec<_>>().into_iter(),
+            )),
+            _ => Err(not_iterable(span)),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Value>, String> {
+        match self {
+            Self::Range { next, end, ty } => {
+                if end.is_some_and(|end| *next >= end) {
+                    return Ok(None);
+                }
+                if *next > value::int_max(*ty) {
+                    return Err("attempt to iterate past the end of the integer range".to_owned());
+                }
+                let value = Value::Int(*next, *ty);
+                *next += 1;
+                Ok(Some(value))
+            }
+            Self::Values(values) => Ok(values.next()),
+        }
+    }
+}
+
+fn not_iterable(span: Span) -> Flow {
+    Flow::Error(
+        Diagnostic::error("value is not iterable")
+            .with_label(span.primary("cannot loop over this")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, with_big_stack};
+
+    /// Runs `src` and returns what it printed, or the runtime error messages.
+    fn run(src: &str) -> Result<String, Vec<String>> {
+        with_big_stack(|| {
+            let (out, errors) = testing::check(src).interpret();
+            match errors.is_empty() {
+                true => Ok(out),
+                false => Err(errors.into_iter().map(|d| d.message).collect()),
+            }
+        })
+    }
+
+    #[test]
+    fn test_run_arithmetic_and_shadowing() {
+        let out = run("fn main() {
+                let x = 5;
+                let x = x * 2;
+                let mut y: u8 = 250;
+                y += 5;
+                let z = -128 as i8;
+                println((x, y, z, 7 / 2, 7.0 / 2.0, 300 as u8));
+                {
+                    let x = \"inner\";
+                    println(x);
+                }
+                println(x);
+             }");
+
+        assert_eq!(out.unwrap(), "(10, 255, -128, 3, 3.5, 44)\ninner\n10\n");
+    }
+
+    #[test]
+    fn test_run_control_flow() {
+        let out = run("fn fib(n: u64): u64 {
+                if n < 2 { return n; }
+                fib(n - 1) + fib(n - 2)
+             }
+             fn main() {
+                let mut i = 0;
+                let found = loop {
+                    i++;
+                    if i % 2 == 0 { continue; }
+                    if i > 6 { break i * 10; }
+                };
+                let mut sum = 0;
+                for n in 1..=4 { sum += n; }
+                for c in \"ab\" { print(c); }
+                while sum > 5 { sum -= 3; }
+                println((found, sum, fib(10)));
+             }");
+
+        assert_eq!(out.unwrap(), "ab(70, 4, 55)\n");
+    }
+
+    #[test]
+    fn test_borrow_slices() {
+        let out = run("fn sum(values: &[i32]): i32 {
+                let mut total = 0;
+                for v in values { total += v; }
+                total
+             }
+             fn main() {
+                let a = [1, 2, 3, 4];
+                let s = &a[1..3];
+                println((sum(s), sum(&a[..]), sum(&a[2..=3]), s[0]));
+             }");
+
+        assert_eq!(out.unwrap(), "(5, 10, 7, 2)\n");
+    }
+
+    #[test]
+    fn test_run_structs_enums_and_match() {
+        let out = run("struct Point { x: f32, y: f32 }
+             enum Shape { Circle(f32), Rect { w: f32, h: f32 }, Empty }
+             fn area(s: &Shape): f32 {
+                match s {
+                    Shape::Circle(r) => r * r * 3.0,
+                    Shape::Rect { w, h } => w * h,
+                    Shape::Empty => 0.0,
+                }
+             }
+             fn shift(p: &mut Point) { p.x += 1.0; }
+             fn main() {
+                let mut p = Point { x: 1.0, y: 2.0 };
+                shift(&mut p);
+                p.shift();
+                let shapes = [Shape::Circle(1.0), Shape::Rect { w: p.x, h: p.y }, Shape::Empty];
+                for s in shapes { print(area(&s)); print(\" \"); }
+                println(p);
+                let label = match 3 { 0 => \"zero\", n => \"many\" };
+                println(label);
+             }");
+
+        assert_eq!(out.unwrap(), "3.0 6.0 0.0 Point { x: 3.0, y: 2.0 }\nmany\n");
+    }
+
+    #[test]
+    fn test_run_closures_capture_environment() {
+        let out = run("fn apply(f: fn(i32): i32, x: i32): i32 { f(x) }
+             fn main() {
+                let mut count = 0;
+                let base = 10;
+                let add = |x| x + base;
+                let bump = || { count += 1; };
+                bump();
+                bump();
+                println((apply(add, 5), count));
+             }");
+
+        assert_eq!(out.unwrap(), "(15, 2)\n");
+    }
+
+    #[test]
+    fn test_report_runtime_errors() {
+        let overflow = run("fn main() { let x: u8 = 255; let y = x + 1; }");
+        assert_eq!(overflow.unwrap_err(), ["attempt to add with overflow"]);
+
+        let bounds = run("fn main() { let a = [1, 2]; let i = 2; println(a[i]); }");
+        assert_eq!(
+            bounds.unwrap_err(),
+            ["index out of bounds: the length is 2 but the index is 2"]
+        );
+
+        let assert = run("fn main() { assert(1 > 2); }");
+        assert_eq!(assert.unwrap_err(), ["assertion failed"]);
+
+        let deep = run("fn f(n: i32): i32 { f(n + 1) } fn main() { f(0); }");
+        assert!(deep.unwrap_err()[0].starts_with("stack overflow"));
+    }
+}
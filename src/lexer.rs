@@ -1,10 +1,19 @@
-use std::{ops::ControlFlow, str::Chars};
+use std::{
+    collections::{HashMap, HashSet},
+    num::IntErrorKind,
+    ops::ControlFlow,
+    str::Chars,
+};
 
 use crate::{
+    ast::Literal,
     diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::Internable,
     source::{FileId, Source},
     span::Span,
     tokens::{Lexicable, Token, TokenKind},
+    ty::{FloatTy, Ty},
+    unicode::{self, Script},
 };
 
 #[derive(Debug, Clone)]
@@ -14,6 +23,12 @@ pub struct Lexer<'src> {
     pub chars: Chars<'src>,
     pub start: usize,
     pub bag: DiagnosticsBag,
+    /// whether whitespace, comments and unlexable text come out as tokens
+    pub trivia: bool,
+    /// the identifiers checked for look-alikes so far
+    idents: HashSet<String>,
+    /// the first identifier seen with each skeleton
+    skeletons: HashMap<String, (String, Span)>,
 }
 
 impl<'src> Lexer<'src> {
@@ -27,19 +42,48 @@ impl<'src> Lexer<'src> {
             chars,
             start: 0,
             bag: DiagnosticsBag::new(),
+            trivia: false,
+            idents: HashSet::new(),
+            skeletons: HashMap::new(),
+        }
+    }
+
+    /// A lexer that keeps every byte of the source: the spans of its tokens,
+    /// up to and including `Eof`, cover the content without gaps.
+    pub fn lossless(id: FileId, source: &'src Source) -> Self {
+        Self {
+            trivia: true,
+            ..Self::new(id, source)
         }
     }
 
     pub fn next_token(&mut self) -> Token {
         let kind;
+        let mut first = true;
         loop {
-            self.skip_ws();
+            if self.trivia {
+                // text an earlier iteration reported and skipped
+                if !first {
+                    return Token::new(TokenKind::Unknown, self.span());
+                }
+                self.start = self.position();
+                if let Some(kind) = self.whitespace() {
+                    return Token::new(kind, self.span());
+                }
+            } else {
+                self.skip_ws();
+            }
+            first = false;
             self.start = self.position();
 
+            // a NUL in the text is not the end, only running out of it is
+            if self.is_eof() {
+                kind = TokenKind::Eof;
+                break;
+            }
             let char = self.bump();
 
             kind = match char {
-                '\0' => TokenKind::Eof,
                 '.' => {
                     if self.first().is_ascii_digit() {
                         match self.float_suffix() {
@@ -79,6 +123,8 @@ impl<'src> Lexer<'src> {
                 '=' => {
                     if self.accept('=') {
                         TokenKind::EqEq
+                    } else if self.accept('>') {
+                        TokenKind::FatArrow
                     } else {
                         TokenKind::Eq
                     }
@@ -120,12 +166,28 @@ impl<'src> Lexer<'src> {
                     if self.accept('=') {
                         TokenKind::SlashEq
                     } else if self.accept('/') {
-                        self.bump_while(|c| matches!(c, '\n'));
-                        continue;
+                        match self.line_comment() {
+                            Some(kind) => kind,
+                            None if self.trivia => TokenKind::LineComment,
+                            None => continue,
+                        }
+                    } else if self.accept('*') {
+                        self.block_comment();
+                        match self.trivia {
+                            true => TokenKind::BlockComment,
+                            false => continue,
+                        }
                     } else {
                         TokenKind::Slash
                     }
                 }
+                '%' => {
+                    if self.accept('=') {
+                        TokenKind::PercentEq
+                    } else {
+                        TokenKind::Percent
+                    }
+                }
                 '<' => {
                     if self.accept('<') {
                         if self.accept('=') {
@@ -189,10 +251,16 @@ impl<'src> Lexer<'src> {
                     ControlFlow::Continue(_) => continue,
                     ControlFlow::Break(t) => t,
                 },
-                'a'..='z' | 'A'..='Z' | '_' => {
-                    self.bump_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '_'));
+                c if c == '_' || unicode::is_xid_start(c) => {
+                    self.bump_while(unicode::is_xid_continue);
 
-                    TokenKind::correspond(&self.content[self.start..self.position()])
+                    match TokenKind::correspond(&self.content[self.start..self.position()]) {
+                        TokenKind::Ident => {
+                            self.check_ident();
+                            TokenKind::Ident
+                        }
+                        keyword => keyword,
+                    }
                 }
                 _ => {
                     self.bag.push(
@@ -208,6 +276,121 @@ impl<'src> Lexer<'src> {
         Token::new(kind, self.span())
     }
 
+    /// Decodes the value of an int, float, char or string literal token.
+    /// Values that do not fit and unknown escapes are reported on the part
+    /// of the literal at fault.
+    pub fn cook(&mut self, token: Token) -> Option<Literal> {
+        let content = self.content;
+        let text = &content[token.span.to_range()];
+        let lo = token.span.lo;
+
+        let literal = match token.kind {
+            TokenKind::IntLiteral => {
+                let (base, start, end) = split_number(text);
+                let suffix = match Ty::primitive(&text[end..]) {
+                    Some(Ty::Int(int)) => Some(int),
+                    _ => None,
+                };
+
+                match u64::from_str_radix(&text[start..end].replace('_', ""), base) {
+                    Ok(value) => Literal::UInt(value, suffix),
+                    Err(err) if *err.kind() == IntErrorKind::PosOverflow => {
+                        self.bag.push(
+                            Diagnostic::error("literal out of range for u64").with_label(
+                                Span::new(self.id, lo + start, lo + end)
+                                    .primary(format!("the largest value is {}", u64::MAX)),
+                            ),
+                        );
+                        return None;
+                    }
+                    // missing digits or digits outside the base, which the
+                    // lexer has reported
+                    Err(_) => return None,
+                }
+            }
+            TokenKind::FloatLiteral => {
+                let (_, start, end) = split_number(text);
+                let suffix = match Ty::primitive(&text[end..]) {
+                    Some(Ty::Float(float)) => Some(float),
+                    _ => None,
+                };
+                let digits = Span::new(self.id, lo + start, lo + end);
+
+                match text[start..end].replace('_', "").parse::<f64>() {
+                    Ok(value)
+                        if value.is_infinite()
+                            || (suffix == Some(FloatTy::F32) && (value as f32).is_infinite()) =>
+                    {
+                        let float = suffix.unwrap_or(FloatTy::F64);
+                        self.bag.push(
+                            Diagnostic::error(format!("literal out of range for {}", float.name()))
+                                .with_label(digits.primary("this rounds to infinity")),
+                        );
+                        return None;
+                    }
+                    Ok(value) => Literal::Float(value, suffix),
+                    Err(_) => {
+                        self.bag.push(
+                            Diagnostic::error("invalid float literal")
+                                .with_label(digits.primary("this is not a number")),
+                        );
+                        return None;
+                    }
+                }
+            }
+            TokenKind::CharLiteral => {
+                let inner = text.strip_prefix('\'').unwrap_or(text);
+                let inner = inner.strip_suffix('\'').unwrap_or(inner);
+                let value = self.unescape(inner, lo + 1);
+                Literal::Char(value.chars().next().unwrap_or('\0'))
+            }
+            TokenKind::StringLiteral => {
+                let inner = text.strip_prefix('"').unwrap_or(text);
+                let inner = inner.strip_suffix('"').unwrap_or(inner);
+                Literal::String(self.unescape(inner, lo + 1).intern())
+            }
+            _ => return None,
+        };
+
+        Some(literal)
+    }
+
+    /// Resolves the escapes in the text of a char or string literal that
+    /// starts at `lo`.
+    fn unescape(&mut self, text: &str, lo: usize) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.char_indices();
+
+        while let Some((i, c)) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+
+            // a trailing backslash is in an unterminated literal, which the
+            // lexer has reported
+            let Some((j, escaped)) = chars.next() else {
+                break;
+            };
+            match escaped.normalize() {
+                Some(c) => out.push(c),
+                None => {
+                    let span = Span::new(self.id, lo + i, lo + j + escaped.len_utf8());
+                    self.bag.push(
+                        Diagnostic::error(format!(
+                            "unknown character escape `\\{}`",
+                            escaped.escape_default()
+                        ))
+                        .with_label(span.primary("unknown escape")),
+                    );
+                    out.push(escaped);
+                }
+            }
+        }
+
+        out
+    }
+
     fn bump(&mut self) -> char {
         self.chars.next().unwrap_or('\0')
     }
@@ -216,6 +399,12 @@ impl<'src> Lexer<'src> {
         self.chars.clone().next().unwrap_or('\0')
     }
 
+    fn second(&self) -> char {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next().unwrap_or('\0')
+    }
+
     fn position(&self) -> usize {
         self.content.len() - self.chars.as_str().len()
     }
@@ -239,6 +428,30 @@ impl<'src> Lexer<'src> {
         self.bump_while(|c| matches!(c, ' ' | '\r' | '\t' | '\n'));
     }
 
+    /// A run of spaces and tabs or a single line break, for lossless lexing.
+    fn whitespace(&mut self) -> Option<TokenKind> {
+        match (self.first(), self.second()) {
+            ('\n', _) => {
+                self.bump();
+                Some(TokenKind::Newline)
+            }
+            ('\r', '\n') => {
+                self.bump();
+                self.bump();
+                Some(TokenKind::Newline)
+            }
+            (' ' | '\t' | '\r', _) => {
+                while matches!(self.first(), ' ' | '\t')
+                    || (self.first() == '\r' && self.second() != '\n')
+                {
+                    self.bump();
+                }
+                Some(TokenKind::Whitespace)
+            }
+            _ => None,
+        }
+    }
+
     fn check(&self, expected: char) -> bool {
         self.first() == expected
     }
@@ -252,8 +465,95 @@ impl<'src> Lexer<'src> {
         }
     }
 
+    /// Warns about an identifier that could be mistaken for another: one
+    /// that looks like an identifier seen before, one that mixes letters of
+    /// different scripts, or one written entirely in letters that look like
+    /// ASCII ones.
+    fn check_ident(&mut self) {
+        let span = self.span();
+        let name = unicode::nfc(&self.content[span.to_range()]);
+        if self.idents.contains(name.as_ref()) {
+            return;
+        }
+        self.idents.insert(name.to_string());
+        let skeleton = unicode::skeleton(&name);
+
+        let warning = match self.skeletons.get(skeleton.as_ref()) {
+            Some((other, other_span)) => {
+                Diagnostic::warning(format!("identifier `{name}` looks like `{other}`"))
+                    .with_label(span.primary("this is a different name"))
+                    .with_label(other_span.secondary(format!("`{other}` is used here")))
+            }
+            None => {
+                let warning = match unicode::mixed_scripts(&name) {
+                    Some((first, second)) => Some(
+                        Diagnostic::warning(format!(
+                            "identifier `{name}` mixes {first} and {second} letters"
+                        ))
+                        .with_label(span.primary("letters of different scripts can look alike")),
+                    ),
+                    None if !name.is_ascii() && skeleton.is_ascii() => {
+                        let script = name.chars().find_map(Script::of);
+                        Some(
+                            Diagnostic::warning(format!(
+                                "identifier `{name}` looks like `{skeleton}`"
+                            ))
+                            .with_labels(script.map(|script| {
+                                span.primary(format!("this is written in {script} letters"))
+                            })),
+                        )
+                    }
+                    None => None,
+                };
+
+                self.skeletons
+                    .insert(skeleton.into_owned(), (name.into_owned(), span));
+                match warning {
+                    Some(warning) => warning,
+                    None => return,
+                }
+            }
+        };
+
+        self.bag.push(warning);
+    }
+
+    /// The rest of a line after `//`. Only `///` and `//!` make a token,
+    /// `////` and longer rules are plain comments.
+    fn line_comment(&mut self) -> Option<TokenKind> {
+        let kind = match (self.first(), self.second()) {
+            ('/', second) if second != '/' => Some(TokenKind::DocComment),
+            ('!', _) => Some(TokenKind::InnerDocComment),
+            _ => None,
+        };
+
+        self.bump_while(|c| c != '\n');
+        kind
+    }
+
+    /// The rest of a `/* .. */` comment, which may nest.
+    fn block_comment(&mut self) {
+        let opener = self.span();
+        let mut depth = 1;
+
+        while depth > 0 {
+            match self.bump() {
+                '\0' if self.is_eof() => {
+                    self.bag.push(
+                        Diagnostic::error("unterminated block comment")
+                            .with_label(opener.primary("this comment is never closed")),
+                    );
+                    return;
+                }
+                '/' if self.accept('*') => depth += 1,
+                '*' if self.accept('/') => depth -= 1,
+                _ => (),
+            }
+        }
+    }
+
     fn float_suffix(&mut self) -> ControlFlow<TokenKind> {
-        self.bump_while(|x| x.is_ascii_digit());
+        self.digits(10, false);
 
         if matches!(self.first(), 'e' | 'E') {
             self.bump();
@@ -262,18 +562,23 @@ impl<'src> Lexer<'src> {
             }
 
             if !self.first().is_ascii_digit() {
+                let next = if self.is_eof() {
+                    0
+                } else {
+                    self.first().len_utf8()
+                };
                 self.bag.push(
                     Diagnostic::error("syntax error")
                         .with_label(self.span().primary("invalid float literal"))
                         .with_label(
-                            Span::new(self.id, self.position(), self.position() + 1)
+                            Span::new(self.id, self.position(), self.position() + next)
                                 .secondary("expected digit after float literal exponent"),
                         ),
                 );
                 return ControlFlow::Continue(());
             }
 
-            self.bump_while(|x| x.is_ascii_digit());
+            self.digits(10, false);
         }
 
         ControlFlow::Break(TokenKind::FloatLiteral)
@@ -281,30 +586,124 @@ impl<'src> Lexer<'src> {
 
     fn numeric_literals(&mut self, cur: char) -> ControlFlow<TokenKind> {
         let mut base = 10;
+        let mut prefixed = true;
 
         if cur == '0' {
             match self.first().to_ascii_lowercase() {
-                'x' => {
-                    self.bump();
-                    base = 16;
-                }
-                'b' => {
-                    self.bump();
-                    base = 2;
-                }
-                x if x.is_digit(8) => {
+                'x' => base = 16,
+                'o' => base = 8,
+                'b' => base = 2,
+                // any leading zero makes an octal, so `09` is an error rather than nine
+                x if x.is_ascii_digit() || x == '_' => {
                     base = 8;
+                    prefixed = false;
                 }
-                _ => (),
+                _ => prefixed = false,
             }
+        } else {
+            prefixed = false;
+        }
+
+        if prefixed {
+            self.bump();
+        }
+
+        if !self.digits(base, !prefixed) && prefixed {
+            self.bag
+                .push(Diagnostic::error("invalid integer literal").with_label(
+                    self.span().primary(format!(
+                        "expected digits after `{}`",
+                        &self.content[self.span()]
+                    )),
+                ));
         }
 
-        self.bump_while(|c| c.is_digit(base as u32));
+        // `1..2` is a range and `1.max(2)` a method call, not floats
+        let fraction =
+            self.check('.') && !matches!(self.second(), '.' | 'a'..='z' | 'A'..='Z' | '_');
 
-        if matches!(self.first(), |'e'| 'E') || self.accept('.') {
-            self.float_suffix()
+        if base == 10 && (matches!(self.first(), 'e' | 'E') || (fraction && self.accept('.'))) {
+            match self.float_suffix() {
+                ControlFlow::Break(kind) => self.type_suffix(kind, base),
+                ControlFlow::Continue(()) => ControlFlow::Continue(()),
+            }
         } else {
-            ControlFlow::Break(TokenKind::IntLiteral)
+            self.type_suffix(TokenKind::IntLiteral, base)
+        }
+    }
+
+    /// Eats the digits of a number and the `_` separators in them. A run of
+    /// separators must follow a digit, `after_digit` telling whether one came
+    /// just before, and lead to a digit or a type suffix. Returns whether any
+    /// digits were eaten.
+    fn digits(&mut self, base: u32, after_digit: bool) -> bool {
+        let is_digit = |c: char| c.is_ascii_digit() || (base == 16 && c.is_ascii_hexdigit());
+        let mut after_digit = after_digit;
+        let mut any = false;
+
+        loop {
+            let lo = self.position();
+            if self.check('_') {
+                self.bump_while(|c| c == '_');
+                if !after_digit || !(is_digit(self.first()) || unicode::is_xid_start(self.first()))
+                {
+                    self.bag.push(
+                        Diagnostic::error("invalid digit separator").with_label(
+                            Span::new(self.id, lo, self.position())
+                                .primary("`_` may only go between digits"),
+                        ),
+                    );
+                }
+                after_digit = false;
+            } else if is_digit(self.first()) {
+                let digit = self.bump();
+                if !digit.is_digit(base) {
+                    let name = if base == 2 { "a binary" } else { "an octal" };
+                    self.bag.push(
+                        Diagnostic::error(format!("invalid digit for a base {base} literal"))
+                            .with_label(
+                                Span::new(self.id, lo, self.position())
+                                    .primary(format!("`{digit}` is not {name} digit")),
+                            ),
+                    );
+                }
+                after_digit = true;
+                any = true;
+            } else {
+                return any;
+            }
+        }
+    }
+
+    /// Eats the type suffix of a number, as in `255u8` or `1f32`. A float
+    /// suffix makes a decimal integer a float.
+    fn type_suffix(&mut self, kind: TokenKind, base: u32) -> ControlFlow<TokenKind> {
+        if !unicode::is_xid_start(self.first()) {
+            return ControlFlow::Break(kind);
+        }
+
+        let lo = self.position();
+        self.bump_while(unicode::is_xid_continue);
+        let suffix = &self.content[lo..self.position()];
+
+        match Ty::primitive(suffix) {
+            Some(Ty::Int(_)) if kind == TokenKind::IntLiteral => ControlFlow::Break(kind),
+            Some(Ty::Float(_)) if base == 10 => ControlFlow::Break(TokenKind::FloatLiteral),
+            _ => {
+                let expected = match (kind, base) {
+                    (TokenKind::FloatLiteral, _) => "`f32` and `f64`",
+                    (_, 10) => "the integer types, `f32` and `f64`",
+                    _ => "the integer types",
+                };
+                self.bag.push(
+                    Diagnostic::error(format!("invalid suffix `{suffix}` for a number literal"))
+                        .with_label(
+                            Span::new(self.id, lo, self.position())
+                                .primary(format!("the suffixes are {expected}")),
+                        ),
+                );
+                ControlFlow::Break(kind)
+            }
         }
     }
 
@@ -326,12 +725,7 @@ impl<'src> Lexer<'src> {
             }
             '\\' => {
                 self.bump();
-                if self.bump().normalize().is_none() {
-                    self.bag.push(
-                        Diagnostic::error("syntax error")
-                            .with_label(self.span().primary("invalid char literal escape '{}'")),
-                    );
-                }
+                self.bump();
             }
             _ => {
                 self.bump();
@@ -361,13 +755,6 @@ impl<'src> Lexer<'src> {
                 }
                 '\\' => {
                     self.bump();
-                    if self.first().normalize().is_none() {
-                        self.bag.push(
-                            Diagnostic::error("syntax error").with_label(
-                                self.span().primary("invalid char literal escape '{}'"),
-                            ),
-                        );
-                    }
                 }
                 _ => (),
             }
@@ -375,7 +762,7 @@ impl<'src> Lexer<'src> {
         }
 
         self.bump_while(|c| !matches!(c, '"'));
-        if self.check('\0') || !self.accept('"') {
+        if !self.accept('"') {
             self.bag.push(
                 Diagnostic::error("syntax error")
                     .with_label(self.span().primary("unexpected end of file within literal")),
@@ -385,3 +772,348 @@ impl<'src> Lexer<'src> {
         ControlFlow::Break(TokenKind::StringLiteral)
     }
 }
+
+/// The base of a number literal and where its digits start and end, after
+/// any base prefix and before any type suffix.
+fn split_number(text: &str) -> (u32, usize, usize) {
+    let (base, start) = match text.as_bytes() {
+        [b'0', b'x' | b'X', ..] => (16, 2),
+        [b'0', b'o' | b'O', ..] => (8, 2),
+        [b'0', b'b' | b'B', ..] => (2, 2),
+        [b'0', b'0'..=b'9' | b'_', ..] => (8, 0),
+        _ => (10, 0),
+    };
+    let end = text[start..]
+        .find(|c: char| match base {
+            16 => !c.is_ascii_hexdigit() && c != '_',
+            _ => c.is_alphabetic() && !matches!(c, 'e' | 'E'),
+        })
+        .map_or(text.len(), |end| start + end);
+
+    (base, start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{source::SourceMap, ty::IntTy};
+
+    fn lex(text: &str) -> (Vec<(TokenKind, String)>, DiagnosticsBag) {
+        let source = Source::with_content("test.ak", text);
+        let mut lexer = Lexer::new(FileId(0), &source);
+        let mut tokens = Vec::new();
+
+        loop {
+            let token = lexer.next_token();
+            if token.is_eof() {
+                break;
+            }
+            tokens.push((token.kind, text[token.span.to_range()].to_string()));
+        }
+
+        (tokens, lexer.bag)
+    }
+
+    fn kinds(text: &str) -> Vec<TokenKind> {
+        let (tokens, bag) = lex(text);
+        assert!(bag.is_empty(), "{:?}", bag.0);
+        tokens.into_iter().map(|(kind, _)| kind).collect()
+    }
+
+    #[test]
+    fn test_line_comments_run_to_the_end_of_the_line() {
+        assert_eq!(
+            kinds("a // b c\nd //\n// e\n////f\n"),
+            [TokenKind::Ident, TokenKind::Ident]
+        );
+        assert_eq!(
+            kinds("a /= b / c"),
+            [
+                TokenKind::Ident,
+                TokenKind::SlashEq,
+                TokenKind::Ident,
+                TokenKind::Slash,
+                TokenKind::Ident,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments_nest() {
+        assert_eq!(
+            kinds("a /* b /* c */ d */ e /**/ f /* // */ g"),
+            [TokenKind::Ident; 4]
+        );
+        assert_eq!(kinds("a /*\n * b\n */\nc"), [TokenKind::Ident; 2]);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment_points_at_its_opener() {
+        let (tokens, bag) = lex("a /* b /* c */ d");
+        assert_eq!(tokens, [(TokenKind::Ident, "a".to_string())]);
+        assert_eq!(bag.len(), 1);
+        assert_eq!(bag.0[0].message, "unterminated block comment");
+        assert_eq!(bag.0[0].labels[0].span.to_range(), 2..4);
+    }
+
+    fn cook(text: &str) -> (Option<Literal>, DiagnosticsBag) {
+        let source = Source::with_content("test.ak", text);
+        let mut lexer = Lexer::new(FileId(0), &source);
+        let token = lexer.next_token();
+        let literal = lexer.cook(token);
+        (literal, lexer.bag)
+    }
+
+    fn cooked(text: &str) -> Literal {
+        let (literal, bag) = cook(text);
+        assert!(bag.is_empty(), "{:?}", bag.0);
+        literal.unwrap()
+    }
+
+    #[test]
+    fn test_cook_integers_in_every_base() {
+        assert_eq!(cooked("42"), Literal::UInt(42, None));
+        assert_eq!(cooked("0x1F"), Literal::UInt(31, None));
+        assert_eq!(cooked("0o17"), Literal::UInt(15, None));
+        assert_eq!(cooked("017"), Literal::UInt(15, None));
+        assert_eq!(cooked("0b1010"), Literal::UInt(10, None));
+        assert_eq!(cooked("0xffffffffffffffff"), Literal::UInt(u64::MAX, None));
+        assert_eq!(cooked("2.5e3"), Literal::Float(2500.0, None));
+
+        let (literal, bag) = cook("0x");
+        assert_eq!(literal, None);
+        assert_eq!(bag.0[0].message, "invalid integer literal");
+
+        let (literal, bag) = cook("0x10000000000000000");
+        assert_eq!(literal, None);
+        assert_eq!(bag.len(), 1);
+        assert_eq!(bag.0[0].message, "literal out of range for u64");
+        assert_eq!(bag.0[0].labels[0].span.to_range(), 2..19);
+    }
+
+    #[test]
+    fn test_separators_and_suffixes() {
+        assert_eq!(cooked("0b1010_0000u8"), Literal::UInt(160, Some(IntTy::U8)));
+        assert_eq!(
+            cooked("1_000_000i64"),
+            Literal::UInt(1_000_000, Some(IntTy::I64))
+        );
+        assert_eq!(cooked("0xff_u16"), Literal::UInt(255, Some(IntTy::U16)));
+        assert_eq!(cooked("0o7_7"), Literal::UInt(63, None));
+        assert_eq!(cooked("017"), Literal::UInt(15, None));
+        assert_eq!(cooked("0_17"), Literal::UInt(15, None));
+        assert_eq!(cooked("1f32"), Literal::Float(1.0, Some(FloatTy::F32)));
+        assert_eq!(
+            cooked("1_0.2_5e1_0f64"),
+            Literal::Float(10.25e10, Some(FloatTy::F64))
+        );
+        assert_eq!(kinds("0x1f32 1.max(2)").len(), 7);
+
+        let (literal, bag) = cook("1e39f32");
+        assert_eq!(literal, None);
+        assert_eq!(bag.0[0].message, "literal out of range for f32");
+    }
+
+    #[test]
+    fn test_reject_malformed_numbers() {
+        let errors = |text: &str| {
+            let (_, bag) = lex(text);
+            let errors = bag.0.iter();
+            errors
+                .map(|d| (d.message.clone(), d.labels[0].span.to_range()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            errors("0b102"),
+            [("invalid digit for a base 2 literal".to_string(), 4..5)]
+        );
+        assert_eq!(
+            errors("0o9"),
+            [("invalid digit for a base 8 literal".to_string(), 2..3)]
+        );
+        for (text, range) in [("08", 1..2), ("09", 1..2), ("0_19", 3..4)] {
+            assert_eq!(
+                errors(text),
+                [("invalid digit for a base 8 literal".to_string(), range)],
+                "{text}"
+            );
+        }
+        for (text, range) in [
+            ("0x_1", 2..3),
+            ("1__", 1..3),
+            ("1_.5", 1..2),
+            ("1.5e1_", 5..6),
+        ] {
+            assert_eq!(
+                errors(text),
+                [("invalid digit separator".to_string(), range)],
+                "{text}"
+            );
+        }
+        assert_eq!(
+            errors("1u7 2.5u8 0b1f32"),
+            [
+                ("invalid suffix `u7` for a number literal".to_string(), 1..3),
+                ("invalid suffix `u8` for a number literal".to_string(), 7..9),
+                (
+                    "invalid suffix `f32` for a number literal".to_string(),
+                    13..16
+                ),
+            ]
+        );
+        assert_eq!(errors("1__000u32"), []);
+    }
+
+    #[test]
+    fn test_exponent_at_end_of_file() {
+        let mut sm = SourceMap::fresh();
+        let id = sm.with_content("test.ak", "1.0e");
+        let mut lexer = Lexer::new(id, &sm[id]);
+        lexer.next_token();
+
+        let spans: Vec<_> = lexer.bag.0[0]
+            .labels
+            .iter()
+            .map(|l| l.span.to_range())
+            .collect();
+        assert_eq!(spans, [0..4, 4..4]);
+        let mut out = Vec::new();
+        lexer.bag.write_all(&sm, &mut out, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("^ expected digit after float literal exponent"));
+    }
+
+    #[test]
+    fn test_cook_escapes() {
+        assert_eq!(cooked(r"'\n'"), Literal::Char('\n'));
+        assert_eq!(cooked(r"'\''"), Literal::Char('\''));
+        assert_eq!(cooked("'ދ'"), Literal::Char('ދ'));
+        assert_eq!(
+            cooked(r#""a\tb\\c\"d\0""#),
+            Literal::String("a\tb\\c\"d\0".intern())
+        );
+
+        let (literal, bag) = cook(r#""ab\qc\ޅ""#);
+        assert_eq!(literal, Some(Literal::String("abqcޅ".intern())));
+        assert_eq!(bag.len(), 2);
+        assert_eq!(bag.0[0].message, "unknown character escape `\\q`");
+        assert_eq!(bag.0[0].labels[0].span.to_range(), 3..5);
+        assert_eq!(bag.0[1].labels[0].span.to_range(), 6..9);
+    }
+
+    fn lossless(text: &str) -> Vec<Token> {
+        let source = Source::with_content("test.ak", text);
+        let mut lexer = Lexer::lossless(FileId(0), &source);
+        let mut tokens = Vec::new();
+
+        loop {
+            let token = lexer.next_token();
+            tokens.push(token);
+            if token.is_eof() {
+                return tokens;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossless_tokens_rebuild_the_source() {
+        let texts = [
+            "fn main() {\r\n\tlet x = 1; // one\r\n\t/* a /* b */ */ x\n}\n",
+            "  /// doc\n//! inner\n \r \n",
+            "let a = 1e; let b = $ 'c' \"s\\n\" 0x1F 1.5;",
+            "a /* never closed\n ",
+            "fn main() {}\0 fn junk( {",
+            "\"a\0b\"",
+            "",
+        ];
+
+        for text in texts {
+            let tokens = lossless(text);
+            let mut rebuilt = String::new();
+            let mut end = 0;
+            for token in &tokens {
+                assert_eq!(token.span.lo, end, "gap before {token:?} in {text:?}");
+                rebuilt.push_str(&text[token.span.to_range()]);
+                end = token.span.hi;
+            }
+            assert_eq!(rebuilt, text);
+        }
+    }
+
+    #[test]
+    fn test_lossless_trivia_kinds() {
+        let trivia: Vec<_> = lossless("a \t// b\r\n/* c */$\n")
+            .iter()
+            .map(|token| token.kind)
+            .collect();
+        assert_eq!(
+            trivia,
+            [
+                TokenKind::Ident,
+                TokenKind::Whitespace,
+                TokenKind::LineComment,
+                TokenKind::Newline,
+                TokenKind::BlockComment,
+                TokenKind::Unknown,
+                TokenKind::Newline,
+                TokenKind::Eof,
+            ]
+        );
+        // the default lexer skips all of it
+        assert_eq!(kinds("a \t// b\r\n/* c */\n"), [TokenKind::Ident]);
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let (tokens, bag) = lex("let ޢަދަދު = x1 + _y2 + café;");
+        assert!(bag.is_empty(), "{:?}", bag.0);
+
+        let idents: Vec<_> = tokens
+            .iter()
+            .filter(|(kind, _)| *kind == TokenKind::Ident)
+            .map(|(_, text)| text.as_str())
+            .collect();
+        assert_eq!(idents, ["ޢަދަދު", "x1", "_y2", "café"]);
+    }
+
+    #[test]
+    fn test_warn_about_look_alike_identifiers() {
+        let warnings = |text: &str| -> Vec<String> {
+            let (_, bag) = lex(text);
+            assert!(!bag.has_errors());
+            bag.0.into_iter().map(|d| d.message).collect()
+        };
+
+        // `р` and `а` are Cyrillic
+        assert_eq!(
+            warnings("path + \u{0440}\u{0430}th + \u{0440}\u{0430}th"),
+            ["identifier `\u{0440}\u{0430}th` looks like `path`"]
+        );
+        assert_eq!(
+            warnings("p\u{0430}th"),
+            ["identifier `p\u{0430}th` mixes Latin and Cyrillic letters"]
+        );
+        assert_eq!(
+            warnings("\u{0441}\u{043E}\u{0440}"),
+            ["identifier `\u{0441}\u{043E}\u{0440}` looks like `cop`"]
+        );
+        // spellings with the same normal form are the same name
+        assert!(warnings("caf\u{00E9} + cafe\u{0301} + ޢަދަދު").is_empty());
+    }
+
+    #[test]
+    fn test_doc_comments_are_tokens() {
+        let (tokens, bag) = lex("//! a section\n/// an item\n///\nfn f() {}");
+        assert!(bag.is_empty());
+        assert_eq!(
+            tokens[..3],
+            [
+                (TokenKind::InnerDocComment, "//! a section".to_string()),
+                (TokenKind::DocComment, "/// an item".to_string()),
+                (TokenKind::DocComment, "///".to_string()),
+            ]
+        );
+        assert_eq!(tokens[3].0, TokenKind::Fn);
+    }
+}
@@ -1,10 +1,34 @@
+pub mod akc;
+pub mod asmgen;
 pub mod ast;
+pub mod bytecode;
+pub mod cgen;
+pub mod compiler;
 pub mod diagnostics;
+pub mod driver;
+pub mod editor;
 pub mod interner;
+pub mod interp;
 pub mod lexer;
+pub mod loader;
+pub mod lower;
+pub mod mir;
+pub mod mireval;
+pub mod opt;
 pub mod parser;
 pub mod precedence;
+pub mod pretty;
+pub mod repl;
+pub mod resolve;
 pub mod source;
 pub mod span;
+#[cfg(test)]
+mod testing;
 pub mod tokens;
 pub mod ty;
+pub mod typeck;
+pub mod unicode;
+pub mod value;
+pub mod vm;
+pub mod wasm;
+pub mod wasmgen;
@@ -0,0 +1,299 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{Item, Script, Spanned},
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::Symbol,
+    parser::Parser,
+    source::{FileId, SourceMap},
+};
+
+/// Parses a script together with the files its `section name;` items refer
+/// to. A section file is looked up next to the file declaring it, as
+/// `name.ak` or `name/section.ak`; sections nested inline in a file look in
+/// a directory named after the enclosing section.
+pub struct Loader<'map> {
+    pub map: &'map mut SourceMap,
+    pub bag: DiagnosticsBag,
+    /// the files being expanded, outermost first
+    stack: Vec<(PathBuf, String)>,
+}
+
+impl<'map> Loader<'map> {
+    pub fn new(map: &'map mut SourceMap) -> Self {
+        Self {
+            map,
+            bag: DiagnosticsBag::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Option<Script> {
+        let path = path.as_ref();
+
+        match self.map.insert(path.display().to_string()) {
+            Ok(id) => Some(self.load_file(id)),
+            Err(err) => {
+                self.bag.push(Diagnostic::error(format!(
+                    "couldn't read `{}`: {err}",
+                    path.display()
+                )));
+                None
+            }
+        }
+    }
+
+    /// Parses a file that is already in the source map and loads its sections.
+    pub fn load_file(&mut self, id: FileId) -> Script {
+        let mut script = self.parse(id);
+        let name = self.map[id].name.clone();
+        let path = PathBuf::from(&name);
+
+        self.stack.push((canonical(&path), name));
+        self.expand(&mut script.root.items, parent(&path));
+        self.stack.pop();
+
+        script
+    }
+
+    fn parse(&mut self, id: FileId) -> Script {
+        let mut parser = Parser::new(id, &self.map[id]);
+        let script = parser.parse_script();
+        self.bag.extend(parser.bag);
+        script
+    }
+
+    fn expand(&mut self, items: &mut [Item], dir: &Path) {
+        for item in items {
+            let Item::Section(item) = item else {
+                continue;
+            };
+            let section = &mut item.sections.node;
+
+            if !item.external {
+                self.expand(&mut section.items, &dir.join(section.name.node.as_str()));
+                continue;
+            }
+
+            let Some(path) = self.find(dir, section.name) else {
+                continue;
+            };
+
+            let key = canonical(&path);
+            if let Some(idx) = self.stack.iter().position(|(open, _)| *open == key) {
+                let chain: Vec<_> = self.stack[idx..]
+                    .iter()
+                    .map(|(_, name)| format!("`{name}`"))
+                    .chain([format!("`{}`", path.display())])
+                    .collect();
+
+                self.bag.push(
+                    Diagnostic::error(format!("circular section include: {}", chain.join(" -> ")))
+                        .with_label(
+                            section
+                                .name
+                                .span
+                                .primary(format!("`{}` is already being loaded", path.display())),
+                        ),
+                );
+                continue;
+            }
+
+            let id = match self.map.insert(path.display().to_string()) {
+                Ok(id) => id,
+                Err(err) => {
+                    self.bag.push(
+                        Diagnostic::error(format!("couldn't read `{}`", path.display()))
+                            .with_label(section.name.span.primary(err.to_string())),
+                    );
+                    continue;
+                }
+            };
+
+            let mut script = self.parse(id);
+            self.stack.push((key, path.display().to_string()));
+            self.expand(&mut script.root.items, parent(&path));
+            self.stack.pop();
+
+            section.docs = script.root.docs;
+            section.items = script.root.items;
+        }
+    }
+
+    fn find(&mut self, dir: &Path, name: Spanned<Symbol>) -> Option<PathBuf> {
+        let flat = dir.join(format!("{}.ak", name.node.as_str()));
+        let nested = dir.join(name.node.as_str()).join("section.ak");
+
+        match (flat.is_file(), nested.is_file()) {
+            (true, false) => Some(flat),
+            (false, true) => Some(nested),
+            (true, true) => {
+                self.bag.push(
+                    Diagnostic::error(format!(
+                        "file for section `{}` found at both `{}` and `{}`",
+                        name.node.as_str(),
+                        flat.display(),
+                        nested.display()
+                    ))
+                    .with_label(name.span.primary("remove one of the two files")),
+                );
+                None
+            }
+            (false, false) => {
+                self.bag.push(
+                    Diagnostic::error(format!(
+                        "file not found for section `{}`",
+                        name.node.as_str()
+                    ))
+                    .with_label(name.span.primary(format!(
+                        "expected `{}` or `{}`",
+                        flat.display(),
+                        nested.display()
+                    ))),
+                );
+                None
+            }
+        }
+    }
+}
+
+fn parent(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SectionItem;
+
+    /// A scratch directory holding `files`, removed when dropped.
+    struct Project(PathBuf);
+
+    impl Project {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir().join(format!("akuru-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+
+            for (path, content) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+
+            Self(root)
+        }
+
+        fn load(&self, map: &mut SourceMap) -> (Option<Script>, DiagnosticsBag) {
+            let mut loader = Loader::new(map);
+            let script = loader.load(self.0.join("main.ak"));
+            (script, loader.bag)
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn section(item: &Item) -> &SectionItem {
+        match item {
+            Item::Section(section) => section,
+            _ => panic!("expected section item"),
+        }
+    }
+
+    #[test]
+    fn test_load_section_files() {
+        let project = Project::new(
+            "sections",
+            &[
+                (
+                    "main.ak",
+                    "section math;\nsection util { section strings; }",
+                ),
+                ("math.ak", "fn add() {}\nsection consts;"),
+                ("consts.ak", "const PI = 3;"),
+                ("util/strings/section.ak", "fn upper() {}"),
+            ],
+        );
+
+        let mut map = SourceMap::fresh();
+        let (script, bag) = project.load(&mut map);
+        assert!(bag.is_empty(), "unexpected diagnostics: {:?}", bag.0);
+        let script = script.unwrap();
+
+        let math = section(&script.root.items[0]);
+        assert!(math.external);
+        assert_eq!(math.sections.node.items.len(), 2);
+
+        let consts = section(&math.sections.node.items[1]);
+        let Item::Const(pi) = &consts.sections.node.items[0] else {
+            panic!("expected const item");
+        };
+        assert!(map[pi.name.span.id].name.ends_with("consts.ak"));
+
+        let util = section(&script.root.items[1]);
+        let strings = section(&util.sections.node.items[0]);
+        assert_eq!(strings.sections.node.items.len(), 1);
+
+        let files: Vec<_> = map.source.iter().map(|s| s.name.clone()).collect();
+        assert_eq!(files.len(), 4);
+    }
+
+    #[test]
+    fn test_report_missing_section_file() {
+        let project = Project::new("missing", &[("main.ak", "fn main() {}\nsection math;")]);
+
+        let mut map = SourceMap::fresh();
+        let (script, bag) = project.load(&mut map);
+        assert!(script.is_some());
+        assert_eq!(bag.len(), 1);
+        assert_eq!(bag.0[0].message, "file not found for section `math`");
+
+        let span = bag.0[0].labels[0].span;
+        assert_eq!(&map[span.id].content[span.lo..span.hi], "math");
+    }
+
+    #[test]
+    fn test_report_circular_sections() {
+        let project = Project::new(
+            "cycle",
+            &[
+                ("main.ak", "section a;"),
+                ("a.ak", "section b;"),
+                ("b.ak", "section a;"),
+            ],
+        );
+
+        let mut map = SourceMap::fresh();
+        let (_, bag) = project.load(&mut map);
+        assert_eq!(bag.len(), 1);
+        assert!(bag.0[0].message.starts_with("circular section include: "));
+        assert!(map[bag.0[0].labels[0].span.id].name.ends_with("b.ak"));
+    }
+
+    #[test]
+    fn test_report_ambiguous_section_file() {
+        let project = Project::new(
+            "ambiguous",
+            &[
+                ("main.ak", "section a;"),
+                ("a.ak", ""),
+                ("a/section.ak", ""),
+            ],
+        );
+
+        let mut map = SourceMap::fresh();
+        let (_, bag) = project.load(&mut map);
+        assert_eq!(bag.len(), 1);
+        assert!(bag.0[0].message.contains("found at both"));
+    }
+}
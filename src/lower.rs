@@ -0,0 +1,2049 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, Expr, FnItem, Item, Literal, MatchArm, Pattern,
+        PostfixOp, PrefixOp, Script, Section, Spannable, Spanned, Stmt,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    mir::{
+        Block, BlockId, Body, BodyKind, DISCRIMINANT, Inst, InstKind, LENGTH, Program, Target,
+        Terminator, ValueId,
+    },
+    resolve::{DefId, DefKind, Resolution, path_span},
+    source::FileId,
+    span::Span,
+    ty::{IntTy, Ty},
+    typeck::{TypeckResults, VariantShape, range_elem},
+    value::{self, Builtin, Value},
+};
+
+/// Lowers a resolved and type checked script to the SSA form of
+/// [`mir`](crate::mir).
+///
+/// Locals become SSA values, built as in "Simple and Efficient Construction
+/// of Static Single Assignment Form" (Braun et al.): each block remembers
+/// the value a variable last got in it, and reading one a block does not
+/// define asks its predecessors, adding a block parameter where they may
+/// disagree. Blocks are sealed once all their predecessors are known, and
+/// parameters every predecessor agrees on are dropped at the end. Locals
+/// whose address is taken, or that are assigned through a field or an
+/// element, live in a location allocated in the entry block instead.
+pub struct Lowerer<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    pub bag: DiagnosticsBag,
+    program: Program,
+    func: FnState,
+}
+
+/// A variable the SSA construction tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Var {
+    Local(DefId),
+    /// the value of an `if`, `match`, `loop` or loop counter, which its
+    /// branches assign
+    Temp(u32),
+}
+
+struct BlockData {
+    params: Vec<ValueId>,
+    insts: Vec<Inst>,
+    term: Option<Spanned<Terminator>>,
+    preds: Vec<BlockId>,
+    sealed: bool,
+}
+
+/// The body being lowered.
+struct FnState {
+    blocks: Vec<BlockData>,
+    values: Vec<Ty>,
+    current: BlockId,
+    /// the value of each variable at the end of each block that assigns it
+    defs: HashMap<(Var, BlockId), ValueId>,
+    /// the parameters added to blocks before they were sealed
+    incomplete: HashMap<BlockId, Vec<(Var, ValueId)>>,
+    temps: Vec<Ty>,
+    /// the locals whose address is taken, and their locations
+    addressed: HashSet<DefId>,
+    slots: HashMap<DefId, ValueId>,
+    loops: Vec<LoopTargets>,
+    ret: Ty,
+    /// the name of the item, for code with no span of its own
+    span: Span,
+}
+
+struct LoopTargets {
+    next: BlockId,
+    exit: BlockId,
+    result: Option<Var>,
+    ty: Ty,
+}
+
+/// A location an expression denotes.
+#[derive(Debug, Clone, Copy)]
+enum Place {
+    Local(DefId),
+    Addr(ValueId),
+    /// a value with no location, like a field of a local in SSA form
+    Value(ValueId),
+}
+
+impl FnState {
+    fn new(ret: Ty, span: Span) -> Self {
+        Self {
+            blocks: Vec::new(),
+            values: Vec::new(),
+            current: BlockId(0),
+            defs: HashMap::new(),
+            incomplete: HashMap::new(),
+            temps: Vec::new(),
+            addressed: HashSet::new(),
+            slots: HashMap::new(),
+            loops: Vec::new(),
+            ret,
+            span,
+        }
+    }
+}
+
+impl<'a> Lowerer<'a> {
+    pub fn new(res: &'a Resolution, types: &'a TypeckResults) -> Self {
+        Self {
+            res,
+            types,
+            bag: DiagnosticsBag::new(),
+            program: Program::default(),
+            func: FnState::new(Ty::unit(), Span::new(FileId(0), 0, 0)),
+        }
+    }
+
+    pub fn lower_script(&mut self, script: &'a Script) -> Program {
+        self.define(&script.root);
+
+        self.program.main = self
+            .res
+            .member(self.res.root, "main".intern())
+            .and_then(|def| self.program.bodies.iter().position(|body| body.def == def));
+
+        std::mem::take(&mut self.program)
+    }
+
+    fn define(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.lower_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => self.lower_const(item),
+                Item::Section(item) => self.define(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    /// The path of `def`, which the program remembers for printing.
+    fn name(&mut self, def: DefId) -> String {
+        let path: Vec<_> = self.res.path_of(def).iter().map(|s| s.as_str()).collect();
+        let name = path.join("::");
+        self.program.names.insert(def, name.clone());
+        name
+    }
+
+    fn signature(&self, def: DefId) -> (Vec<Ty>, Ty) {
+        match self.types.fns.get(&def) {
+            Some(sig) => (sig.params.clone(), sig.ret.clone()),
+            None => (Vec::new(), Ty::unit()),
+        }
+    }
+
+    fn lower_fn(&mut self, item: &'a FnItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let (params, ret) = self.signature(def);
+        self.begin(ret.clone(), item.name.span);
+        self.mark_block(&item.body);
+
+        for (param, ty) in item.params.iter().zip(params) {
+            let value = self.param(BlockId(0), ty);
+            if let Some(local) = self.res.lookup(param.node.name.span) {
+                self.bind(local, value, param.span);
+            }
+        }
+
+        let span = item
+            .body
+            .expr
+            .as_ref()
+            .map_or(item.name.span, |tail| tail.span);
+        let (value, ty) = self.block(&item.body);
+        let value = self.coerce(value, &ty, &ret, span);
+        self.terminate(Terminator::Return(value), span);
+        self.finish(def, BodyKind::Fn, item.name.span);
+    }
+
+    fn lower_const(&mut self, item: &'a ConstItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let ty = self
+            .types
+            .consts
+            .get(&def)
+            .cloned()
+            .unwrap_or_else(Ty::unit);
+        self.begin(ty.clone(), item.name.span);
+        self.mark(&item.value.node);
+
+        let value = self.expr(&item.value);
+        let value = self.coerce(value, &self.ty(item.value.span), &ty, item.value.span);
+        self.terminate(Terminator::Return(value), item.value.span);
+        self.finish(def, BodyKind::Const, item.name.span);
+    }
+
+    fn begin(&mut self, ret: Ty, span: Span) {
+        self.func = FnState::new(ret, span);
+        let entry = self.new_block();
+        self.seal(entry);
+    }
+
+    fn finish(&mut self, def: DefId, kind: BodyKind, span: Span) {
+        let state = std::mem::replace(&mut self.func, FnState::new(Ty::unit(), span));
+        let blocks = state
+            .blocks
+            .into_iter()
+            .map(|block| Block {
+                params: block.params,
+                insts: block.insts,
+                term: block
+                    .term
+                    .unwrap_or_else(|| Terminator::Unreachable.spanned(span)),
+            })
+            .collect();
+
+        let mut body = Body {
+            def,
+            kind,
+            name: self.name(def),
+            ret: state.ret,
+            span,
+            values: state.values,
+            blocks,
+        };
+        body.compact();
+        body.prune_params();
+        body.compact();
+        self.program.bodies.push(body);
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str, ty: &Ty) -> ValueId {
+        self.bag.push(
+            Diagnostic::error(format!("{what} are not supported by the mid-level IR"))
+                .with_label(span.primary("used here")),
+        );
+        self.undef(ty, span)
+    }
+
+    // ---------------------------------------------------------- building
+
+    fn new_block(&mut self) -> BlockId {
+        self.func.blocks.push(BlockData {
+            params: Vec::new(),
+            insts: Vec::new(),
+            term: None,
+            preds: Vec::new(),
+            sealed: false,
+        });
+        BlockId(self.func.blocks.len() as u32 - 1)
+    }
+
+    fn data(&mut self, block: BlockId) -> &mut BlockData {
+        &mut self.func.blocks[block.0 as usize]
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.func.current = block;
+    }
+
+    fn new_value(&mut self, ty: Ty) -> ValueId {
+        self.func.values.push(ty);
+        ValueId(self.func.values.len() as u32 - 1)
+    }
+
+    fn param(&mut self, block: BlockId, ty: Ty) -> ValueId {
+        let value = self.new_value(ty);
+        self.data(block).params.push(value);
+        value
+    }
+
+    fn emit(&mut self, kind: InstKind, ty: Ty, span: Span) -> ValueId {
+        let result = self.new_value(ty);
+        let current = self.func.current;
+        self.data(current).insts.push(Inst {
+            result: Some(result),
+            kind,
+            span,
+        });
+        result
+    }
+
+    /// Emits an instruction with no result.
+    fn effect(&mut self, kind: InstKind, span: Span) {
+        let current = self.func.current;
+        self.data(current).insts.push(Inst {
+            result: None,
+            kind,
+            span,
+        });
+    }
+
+    fn constant(&mut self, value: Value, ty: &Ty, span: Span) -> ValueId {
+        self.emit(InstKind::Const(value), ty.clone(), span)
+    }
+
+    fn unit(&mut self, span: Span) -> ValueId {
+        self.constant(Value::Unit, &Ty::unit(), span)
+    }
+
+    fn undef(&mut self, ty: &Ty, span: Span) -> ValueId {
+        self.emit(InstKind::Undef, ty.clone(), span)
+    }
+
+    /// Ends the current block, which its targets learn is a predecessor.
+    fn terminate(&mut self, term: Terminator, span: Span) {
+        let current = self.func.current;
+        if self.data(current).term.is_some() {
+            return;
+        }
+        for target in term.targets() {
+            let preds = &mut self.data(target.block).preds;
+            if !preds.contains(&current) {
+                preds.push(current);
+            }
+        }
+        self.data(current).term = Some(term.spanned(span));
+    }
+
+    fn jump(&mut self, block: BlockId, span: Span) {
+        self.terminate(Terminator::Jump(Target::new(block)), span);
+    }
+
+    fn branch(&mut self, cond: ValueId, then: BlockId, else_: BlockId, span: Span) {
+        self.terminate(
+            Terminator::Branch {
+                cond,
+                then: Target::new(then),
+                else_: Target::new(else_),
+            },
+            span,
+        );
+    }
+
+    /// Continues in a block no code reaches, after a `return`, `break` or
+    /// `continue`.
+    fn diverge(&mut self, ty: &Ty, span: Span) -> ValueId {
+        let dead = self.new_block();
+        self.seal(dead);
+        self.switch_to(dead);
+        self.undef(ty, span)
+    }
+
+    /// Goes on in a new block if `cond` holds, and to `fail` otherwise.
+    fn check(&mut self, cond: ValueId, fail: BlockId, span: Span) {
+        let ok = self.new_block();
+        self.branch(cond, ok, fail, span);
+        self.seal(ok);
+        self.switch_to(ok);
+    }
+
+    fn panic(&mut self, message: &str, span: Span) {
+        self.effect(InstKind::Panic(message.to_owned()), span);
+        self.terminate(Terminator::Unreachable, span);
+    }
+
+    // --------------------------------------------------------------- SSA
+
+    fn var_ty(&self, var: Var) -> Ty {
+        match var {
+            Var::Local(def) => self.local_ty(def),
+            Var::Temp(idx) => self.func.temps[idx as usize].clone(),
+        }
+    }
+
+    fn temp(&mut self, ty: &Ty) -> Var {
+        self.func.temps.push(ty.clone());
+        Var::Temp(self.func.temps.len() as u32 - 1)
+    }
+
+    /// The variable holding the value of an expression whose branches
+    /// join, unless it is always `()` or never produced.
+    fn result_var(&mut self, ty: &Ty) -> Option<Var> {
+        match ty.is_unit() || *ty == Ty::Never {
+            true => None,
+            false => Some(self.temp(ty)),
+        }
+    }
+
+    fn read_result(&mut self, var: Option<Var>, ty: &Ty, span: Span) -> ValueId {
+        match var {
+            Some(var) => self.read_var(var, span),
+            None if ty.is_unit() => self.unit(span),
+            None => self.undef(ty, span),
+        }
+    }
+
+    fn write_var(&mut self, var: Var, value: ValueId) {
+        self.func.defs.insert((var, self.func.current), value);
+    }
+
+    fn read_var(&mut self, var: Var, span: Span) -> ValueId {
+        self.read_var_in(var, self.func.current, span)
+    }
+
+    fn read_var_in(&mut self, var: Var, block: BlockId, span: Span) -> ValueId {
+        if let Some(value) = self.func.defs.get(&(var, block)) {
+            return *value;
+        }
+
+        let ty = self.var_ty(var);
+        let preds = self.data(block).preds.clone();
+        let value = if !self.data(block).sealed {
+            let param = self.param(block, ty);
+            self.func
+                .incomplete
+                .entry(block)
+                .or_default()
+                .push((var, param));
+            param
+        } else if let [pred] = preds[..] {
+            self.read_var_in(var, pred, span)
+        } else if preds.is_empty() {
+            // read before anything is assigned, or in code no one reaches
+            let value = self.new_value(ty);
+            self.data(block).insts.insert(
+                0,
+                Inst {
+                    result: Some(value),
+                    kind: InstKind::Undef,
+                    span,
+                },
+            );
+            value
+        } else {
+            let param = self.param(block, ty);
+            self.func.defs.insert((var, block), param);
+            self.add_args(var, block, span);
+            param
+        };
+        self.func.defs.insert((var, block), value);
+        value
+    }
+
+    /// Passes the value `var` has at the end of each predecessor of
+    /// `block` to the parameter just added for it.
+    fn add_args(&mut self, var: Var, block: BlockId, span: Span) {
+        for pred in self.data(block).preds.clone() {
+            let value = self.read_var_in(var, pred, span);
+            if let Some(term) = &mut self.data(pred).term {
+                for target in term.node.targets_mut() {
+                    if target.block == block {
+                        target.args.push(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records that every predecessor of `block` is known.
+    fn seal(&mut self, block: BlockId) {
+        let span = self.func.blocks[block.0 as usize]
+            .term
+            .as_ref()
+            .map_or(self.func.span, |term| term.span);
+        for (var, _) in self.func.incomplete.remove(&block).unwrap_or_default() {
+            self.add_args(var, block, span);
+        }
+        self.data(block).sealed = true;
+    }
+
+    // ------------------------------------------------------------ locals
+
+    fn local_ty(&self, def: DefId) -> Ty {
+        self.types
+            .locals
+            .get(&def)
+            .cloned()
+            .unwrap_or_else(Ty::unit)
+    }
+
+    /// The location of a local whose address is taken, allocated in the
+    /// entry block so it dominates every use.
+    fn slot(&mut self, def: DefId, span: Span) -> ValueId {
+        if let Some(slot) = self.func.slots.get(&def) {
+            return *slot;
+        }
+        let ty = Ty::Ref {
+            mutable: true,
+            inner: Box::new(self.local_ty(def)),
+        };
+        let slot = self.new_value(ty);
+        let entry = self.data(BlockId(0));
+        let at = entry
+            .insts
+            .iter()
+            .take_while(|inst| matches!(inst.kind, InstKind::Alloc | InstKind::Undef))
+            .count();
+        entry.insts.insert(
+            at,
+            Inst {
+                result: Some(slot),
+                kind: InstKind::Alloc,
+                span,
+            },
+        );
+        self.func.slots.insert(def, slot);
+        slot
+    }
+
+    fn bind(&mut self, def: DefId, value: ValueId, span: Span) {
+        if self.func.addressed.contains(&def) {
+            let slot = self.slot(def, span);
+            self.effect(InstKind::Store(slot, value), span);
+        } else {
+            self.write_var(Var::Local(def), value);
+        }
+    }
+
+    /// Finds the locals that need a location: those borrowed, assigned
+    /// through a field or element, or sliced.
+    fn mark_block(&mut self, block: &BlockExpr) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let {
+                    value: Some(value), ..
+                } => self.mark(&value.node),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => self.mark(&expr.node),
+                Stmt::Let { .. } | Stmt::Open(_) => (),
+            }
+        }
+        if let Some(tail) = &block.expr {
+            self.mark(&tail.node);
+        }
+    }
+
+    fn mark(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(_)
+            | Expr::Variable(_)
+            | Expr::Path(_)
+            | Expr::Continue
+            | Expr::Closure { .. }
+            | Expr::Error(_) => (),
+            Expr::Tuple(elems) | Expr::Array(elems) => {
+                for elem in elems {
+                    self.mark(&elem.node);
+                }
+            }
+            Expr::Range { start, end, .. } => {
+                for bound in [start, end].into_iter().flatten() {
+                    self.mark(&bound.node);
+                }
+            }
+            Expr::Binary(lhs, _, rhs) => {
+                self.mark(&lhs.node);
+                self.mark(&rhs.node);
+            }
+            Expr::Prefix(op, operand) => {
+                if matches!(op.node, PrefixOp::Ref | PrefixOp::RefMut) {
+                    self.mark_root(&operand.node, operand.span);
+                }
+                self.mark(&operand.node);
+            }
+            Expr::Postfix(operand, _) => {
+                self.mark_projected(&operand.node, operand.span);
+                self.mark(&operand.node);
+            }
+            Expr::Field(base, _) | Expr::Cast(base, _) => self.mark(&base.node),
+            Expr::Call(callee, args) => {
+                self.mark(&callee.node);
+                for arg in args {
+                    self.mark(&arg.node);
+                }
+            }
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let takes_ref = self
+                    .res
+                    .lookup(method.span)
+                    .and_then(|def| self.types.fns.get(&def))
+                    .is_some_and(|sig| matches!(sig.params.first(), Some(Ty::Ref { .. })));
+                if takes_ref && !matches!(self.ty(receiver.span), Ty::Ref { .. }) {
+                    self.mark_root(&receiver.node, receiver.span);
+                }
+                self.mark(&receiver.node);
+                for arg in args {
+                    self.mark(&arg.node);
+                }
+            }
+            Expr::Index(base, index) => {
+                if matches!(&*index.node, Expr::Range { .. })
+                    && matches!(self.ty(base.span), Ty::Array(..))
+                {
+                    self.mark_root(&base.node, base.span);
+                }
+                self.mark(&base.node);
+                self.mark(&index.node);
+            }
+            Expr::If { cond, then, else_ } => {
+                self.mark(&cond.node);
+                self.mark(&then.node);
+                if let Some(else_) = else_ {
+                    self.mark(&else_.node);
+                }
+            }
+            Expr::Loop(body) => self.mark_block(&body.node),
+            Expr::While { cond, body } => {
+                self.mark(&cond.node);
+                self.mark_block(body);
+            }
+            Expr::For { iter, body, .. } => {
+                self.mark(&iter.node);
+                self.mark_block(body);
+            }
+            Expr::Break(value) | Expr::Return(value) => {
+                if let Some(value) = value {
+                    self.mark(&value.node);
+                }
+            }
+            Expr::Block(block) => self.mark_block(&block.node),
+            Expr::Assign { target, value } | Expr::AssignEq { target, value, .. } => {
+                self.mark_projected(&target.node, target.span);
+                self.mark(&target.node);
+                self.mark(&value.node);
+            }
+            Expr::StructInit { fields, .. } => {
+                for field in fields {
+                    self.mark(&field.node.value.node);
+                }
+            }
+            Expr::Match { scrutinee, arms } => {
+                self.mark(&scrutinee.node);
+                for arm in arms {
+                    self.mark(&arm.body.node);
+                }
+            }
+        }
+    }
+
+    /// Marks the local a field or element assigned to belongs to.
+    fn mark_projected(&mut self, expr: &Expr, span: Span) {
+        if !matches!(expr, Expr::Variable(_)) {
+            self.mark_root(expr, span);
+        }
+    }
+
+    /// Marks the local a place is part of, unless the place is reached
+    /// through a reference.
+    fn mark_root(&mut self, expr: &Expr, span: Span) {
+        match expr {
+            Expr::Variable(_) => {
+                if let Some(def) = self.res.lookup(span)
+                    && matches!(self.res.def(def).kind, DefKind::Local { .. })
+                {
+                    self.func.addressed.insert(def);
+                }
+            }
+            Expr::Field(base, _) | Expr::Index(base, _)
+                if !matches!(self.ty(base.span), Ty::Ref { .. } | Ty::Slice(_)) =>
+            {
+                self.mark_root(&base.node, base.span)
+            }
+            _ => (),
+        }
+    }
+
+    // ------------------------------------------------------------- types
+
+    fn ty(&self, span: Span) -> Ty {
+        self.types
+            .exprs
+            .get(&span)
+            .cloned()
+            .unwrap_or_else(Ty::unit)
+    }
+
+    fn variant_tag(&self, def: DefId) -> Option<u32> {
+        let parent = self.res.def(def).parent?;
+        let tag = self
+            .types
+            .enums
+            .get(&parent)?
+            .iter()
+            .position(|v| *v == def)?;
+        Some(tag as u32)
+    }
+
+    /// The fields of a struct or variant in declaration order, with the
+    /// names of those that have one.
+    fn fields(&self, def: DefId) -> Vec<(Option<Symbol>, Ty)> {
+        if let Some(fields) = self.types.structs.get(&def) {
+            return fields
+                .iter()
+                .map(|(name, ty)| (Some(*name), ty.clone()))
+                .collect();
+        }
+        match self.types.variants.get(&def) {
+            Some(VariantShape::Tuple(tys)) => tys.iter().map(|ty| (None, ty.clone())).collect(),
+            Some(VariantShape::Struct(fields)) => fields
+                .iter()
+                .map(|(name, ty)| (Some(*name), ty.clone()))
+                .collect(),
+            Some(VariantShape::Unit) | None => Vec::new(),
+        }
+    }
+
+    fn field_idx(&self, ty: &Ty, name: Symbol) -> u32 {
+        match ty {
+            Ty::Tuple(_) => name.as_str().parse().unwrap_or(0),
+            Ty::Path(path) => self
+                .res
+                .def_by_path(path)
+                .and_then(|def| {
+                    self.fields(def)
+                        .iter()
+                        .position(|(field, _)| *field == Some(name))
+                })
+                .unwrap_or(0) as u32,
+            _ => 0,
+        }
+    }
+
+    /// Converts a value to the type its context expects.
+    fn coerce(&mut self, value: ValueId, from: &Ty, to: &Ty, span: Span) -> ValueId {
+        if from == to || *from == Ty::Never {
+            return value;
+        }
+
+        match (from, to) {
+            // the value of a block ending in a `return`
+            (from, to) if from.is_unit() => self.undef(to, span),
+            (Ty::Optional(_), Ty::Optional(_)) => value,
+            (_, Ty::Optional(inner)) => {
+                let value = self.coerce(value, from, inner, span);
+                self.emit(InstKind::Some(value), to.clone(), span)
+            }
+            (Ty::Ref { inner: x, .. }, Ty::Ref { inner: y, .. })
+                if matches!((&**x, &**y), (Ty::Array(..), Ty::Slice(_))) =>
+            {
+                self.emit(InstKind::Unsize(value), to.clone(), span)
+            }
+            _ => value,
+        }
+    }
+
+    /// Follows references, except `&[T]` which are slices, loading what
+    /// they point at.
+    fn deref(&mut self, mut value: ValueId, ty: &Ty, span: Span) -> (ValueId, Ty) {
+        let mut ty = ty.clone();
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            let inner = (**inner).clone();
+            value = self.emit(InstKind::Load(value), inner.clone(), span);
+            ty = inner;
+        }
+        (value, ty)
+    }
+
+    // ------------------------------------------------------- expressions
+
+    fn block(&mut self, block: &'a BlockExpr) -> (ValueId, Ty) {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { name, value, .. } => {
+                    let Some(def) = self.res.lookup(name.span) else {
+                        continue;
+                    };
+                    let ty = self.local_ty(def);
+                    let value = match value {
+                        Some(value) => {
+                            let lowered = self.expr(value);
+                            self.coerce(lowered, &self.ty(value.span), &ty, value.span)
+                        }
+                        None => self.undef(&ty, name.span),
+                    };
+                    self.bind(def, value, name.span);
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => _ = self.expr(expr),
+            }
+        }
+
+        match &block.expr {
+            Some(tail) => (self.expr_boxed(tail), self.ty(tail.span)),
+            None => {
+                let span = match block.stmts.last() {
+                    Some(Stmt::Expr(expr) | Stmt::Semi(expr)) => expr.span,
+                    Some(Stmt::Let { name, .. }) => name.span,
+                    _ => self.func.span,
+                };
+                (self.unit(span), Ty::unit())
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Spanned<Expr>) -> ValueId {
+        self.expr_at(&expr.node, expr.span)
+    }
+
+    fn expr_boxed(&mut self, expr: &'a Spanned<Box<Expr>>) -> ValueId {
+        self.expr_at(&expr.node, expr.span)
+    }
+
+    fn expr_at(&mut self, expr: &'a Expr, span: Span) -> ValueId {
+        let ty = self.ty(span);
+
+        match expr {
+            Expr::Literal(literal) => {
+                let value = value::literal(literal, Some(&ty));
+                self.constant(value, &ty, span)
+            }
+            Expr::Variable(_) | Expr::Path(_) => self.name_value(span, &ty),
+            Expr::Tuple(elems) => {
+                if elems.is_empty() {
+                    return self.unit(span);
+                }
+                let tys = match &ty {
+                    Ty::Tuple(tys) => tys.clone(),
+                    _ => Vec::new(),
+                };
+                let values = elems
+                    .iter()
+                    .zip(&tys)
+                    .map(|(elem, elem_ty)| {
+                        let value = self.expr(elem);
+                        self.coerce(value, &self.ty(elem.span), elem_ty, elem.span)
+                    })
+                    .collect();
+                self.emit(InstKind::Tuple(values), ty, span)
+            }
+            Expr::Array(elems) => {
+                let elem_ty = match &ty {
+                    Ty::Array(elem, _) => (**elem).clone(),
+                    _ => Ty::unit(),
+                };
+                let values = elems
+                    .iter()
+                    .map(|elem| {
+                        let value = self.expr(elem);
+                        self.coerce(value, &self.ty(elem.span), &elem_ty, elem.span)
+                    })
+                    .collect();
+                self.emit(InstKind::Array(values), ty, span)
+            }
+            Expr::Range { .. } => {
+                self.unsupported(span, "ranges outside `for` loops and slicing", &ty)
+            }
+            Expr::Binary(lhs, op, rhs) if op.node.is_logical() => {
+                let result = self.temp(&Ty::Bool);
+                let lhs = self.expr_boxed(lhs);
+                self.write_var(result, lhs);
+
+                let (other, end) = (self.new_block(), self.new_block());
+                match op.node {
+                    BinaryOp::And => self.branch(lhs, other, end, op.span),
+                    _ => self.branch(lhs, end, other, op.span),
+                }
+                self.seal(other);
+                self.switch_to(other);
+                let rhs = self.expr_boxed(rhs);
+                self.write_var(result, rhs);
+                self.jump(end, op.span);
+
+                self.seal(end);
+                self.switch_to(end);
+                self.read_var(result, span)
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs_value = self.expr_boxed(lhs);
+                let (lhs_value, _) = self.deref(lhs_value, &self.ty(lhs.span), lhs.span);
+                let rhs_value = self.expr_boxed(rhs);
+                let (rhs_value, _) = self.deref(rhs_value, &self.ty(rhs.span), rhs.span);
+                // errors point at the operator, as the interpreter's do
+                self.emit(InstKind::Binary(op.node, lhs_value, rhs_value), ty, op.span)
+            }
+            Expr::Prefix(op, operand) => self.prefix(op.node, operand, &ty, span),
+            Expr::Postfix(operand, op) => {
+                let (place, operand_ty) = self.place(&operand.node, operand.span);
+                let Ty::Int(int) = operand_ty else {
+                    return self.undef(&ty, span);
+                };
+                let old = self.read(place, &operand_ty, span);
+                let one = self.constant(Value::Int(1, int), &operand_ty, span);
+                let op_kind = match op.node {
+                    PostfixOp::Inc => BinaryOp::Add,
+                    PostfixOp::Dec => BinaryOp::Sub,
+                };
+                let new = self.emit(InstKind::Binary(op_kind, old, one), operand_ty, op.span);
+                self.write(place, new, span);
+                old
+            }
+            Expr::Call(callee, args) => self.call(callee, args, &ty, span),
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let Some(def) = self.res.lookup(method.span) else {
+                    return self.undef(&ty, span);
+                };
+                let (params, ret) = self.signature(def);
+
+                // borrow the receiver when the function takes a reference
+                let receiver_ty = self.ty(receiver.span);
+                let mut values = Vec::new();
+                match params.first() {
+                    Some(Ty::Ref { .. }) if !matches!(receiver_ty, Ty::Ref { .. }) => {
+                        values.push(self.addr_of(&receiver.node, receiver.span));
+                    }
+                    Some(param) => {
+                        let value = self.expr_boxed(receiver);
+                        values.push(self.coerce(value, &receiver_ty, param, receiver.span));
+                    }
+                    None => _ = self.expr_boxed(receiver),
+                }
+                for (arg, param) in args.iter().zip(params.iter().skip(1)) {
+                    let value = self.expr(arg);
+                    values.push(self.coerce(value, &self.ty(arg.span), param, arg.span));
+                }
+                self.name(def);
+                self.emit(InstKind::Call(def, values), ret, span)
+            }
+            Expr::Field(..) => {
+                let (place, ty) = self.place(expr, span);
+                self.read(place, &ty, span)
+            }
+            Expr::Index(base, index) => match &*index.node {
+                Expr::Range {
+                    start,
+                    end,
+                    inclusive,
+                } => self.slice(base, start, end, *inclusive, &ty, span),
+                _ => {
+                    let (place, ty) = self.place(expr, span);
+                    self.read(place, &ty, span)
+                }
+            },
+            Expr::Cast(operand, _) => {
+                let value = self.expr_boxed(operand);
+                let (value, from) = self.deref(value, &self.ty(operand.span), operand.span);
+                match from == ty {
+                    true => value,
+                    false => self.emit(InstKind::Cast(value), ty, span),
+                }
+            }
+            Expr::Closure { .. } => self.unsupported(span, "closures", &ty),
+            Expr::If { cond, then, else_ } => {
+                let cond = self.expr_boxed(cond);
+                let (then_block, end) = (self.new_block(), self.new_block());
+                let else_block = match else_ {
+                    Some(_) => self.new_block(),
+                    None => end,
+                };
+                self.branch(cond, then_block, else_block, span);
+                self.seal(then_block);
+                let result = self.result_var(&ty);
+
+                self.switch_to(then_block);
+                self.arm(then, &ty, result);
+                self.jump(end, then.span);
+                if let Some(else_) = else_ {
+                    self.seal(else_block);
+                    self.switch_to(else_block);
+                    self.arm(else_, &ty, result);
+                    self.jump(end, else_.span);
+                }
+
+                self.seal(end);
+                self.switch_to(end);
+                self.read_result(result, &ty, span)
+            }
+            Expr::Loop(body) => {
+                let (next, exit) = (self.new_block(), self.new_block());
+                self.jump(next, span);
+                self.switch_to(next);
+                let result = self.result_var(&ty);
+                self.func.loops.push(LoopTargets {
+                    next,
+                    exit,
+                    result,
+                    ty: ty.clone(),
+                });
+                self.block(&body.node);
+                self.func.loops.pop();
+                self.jump(next, body.span);
+
+                self.seal(next);
+                self.seal(exit);
+                self.switch_to(exit);
+                self.read_result(result, &ty, span)
+            }
+            Expr::While { cond, body } => {
+                let (next, exit) = (self.new_block(), self.new_block());
+                self.jump(next, span);
+                self.switch_to(next);
+                let cond = self.expr_boxed(cond);
+                self.check(cond, exit, span);
+                self.func.loops.push(LoopTargets {
+                    next,
+                    exit,
+                    result: None,
+                    ty: Ty::unit(),
+                });
+                self.block(body);
+                self.func.loops.pop();
+                self.jump(next, span);
+
+                self.seal(next);
+                self.seal(exit);
+                self.switch_to(exit);
+                self.unit(span)
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => {
+                self.for_loop(pattern, iter, body, span);
+                self.unit(span)
+            }
+            Expr::Break(value) => {
+                let value = value.as_ref().map(|value| {
+                    let lowered = self.expr_boxed(value);
+                    (lowered, self.ty(value.span), value.span)
+                });
+                if let Some(targets) = self.func.loops.last() {
+                    let (exit, result, loop_ty) =
+                        (targets.exit, targets.result, targets.ty.clone());
+                    if let (Some(result), Some((value, value_ty, value_span))) = (result, value) {
+                        let value = self.coerce(value, &value_ty, &loop_ty, value_span);
+                        self.write_var(result, value);
+                    }
+                    self.jump(exit, span);
+                }
+                self.diverge(&ty, span)
+            }
+            Expr::Continue => {
+                if let Some(targets) = self.func.loops.last() {
+                    let next = targets.next;
+                    self.jump(next, span);
+                }
+                self.diverge(&ty, span)
+            }
+            Expr::Return(value) => {
+                let ret = self.func.ret.clone();
+                let value = match value {
+                    Some(value) => {
+                        let lowered = self.expr_boxed(value);
+                        self.coerce(lowered, &self.ty(value.span), &ret, value.span)
+                    }
+                    None => self.unit(span),
+                };
+                self.terminate(Terminator::Return(value), span);
+                self.diverge(&ty, span)
+            }
+            Expr::Block(block) => {
+                let (value, tail) = self.block(&block.node);
+                self.coerce(value, &tail, &ty, span)
+            }
+            Expr::Assign { target, value } => {
+                let target_ty = self.ty(target.span);
+                let lowered = self.expr_boxed(value);
+                let lowered = self.coerce(lowered, &self.ty(value.span), &target_ty, value.span);
+                let (place, _) = self.place(&target.node, target.span);
+                self.write(place, lowered, span);
+                self.unit(span)
+            }
+            Expr::AssignEq { op, target, value } => {
+                let lowered = self.expr_boxed(value);
+                let (lowered, _) = self.deref(lowered, &self.ty(value.span), value.span);
+                let (place, target_ty) = self.place(&target.node, target.span);
+                let old = self.read(place, &target_ty, span);
+                let new = self.emit(InstKind::Binary(op.node, old, lowered), target_ty, op.span);
+                self.write(place, new, span);
+                self.unit(span)
+            }
+            Expr::StructInit { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return self.undef(&ty, span);
+                };
+                let layout = self.fields(def);
+                let mut values = vec![None; layout.len()];
+                for field in fields {
+                    let name = field.node.name.node;
+                    let Some(idx) = layout.iter().position(|(n, _)| *n == Some(name)) else {
+                        continue;
+                    };
+                    let value = self.expr(&field.node.value);
+                    let value_ty = self.ty(field.node.value.span);
+                    values[idx] =
+                        Some(self.coerce(value, &value_ty, &layout[idx].1, field.node.value.span));
+                }
+                let values = values
+                    .into_iter()
+                    .zip(&layout)
+                    .map(|(value, (_, field_ty))| {
+                        value.unwrap_or_else(|| self.undef(field_ty, span))
+                    })
+                    .collect();
+                self.name(def);
+                self.emit(InstKind::Adt(def, values), ty, span)
+            }
+            Expr::Match { scrutinee, arms } => self.lower_match(scrutinee, arms, &ty, span),
+            Expr::Error(_) => self.undef(&ty, span),
+        }
+    }
+
+    /// A branch of an `if`, assigning its value to `result`.
+    fn arm(&mut self, expr: &'a Spanned<Box<Expr>>, ty: &Ty, result: Option<Var>) {
+        let value = self.expr_boxed(expr);
+        let value = self.coerce(value, &self.ty(expr.span), ty, expr.span);
+        if let Some(result) = result {
+            self.write_var(result, value);
+        }
+    }
+
+    fn name_value(&mut self, span: Span, ty: &Ty) -> ValueId {
+        let Some(def) = self.res.lookup(span) else {
+            return self.undef(ty, span);
+        };
+
+        match self.res.def(def).kind {
+            DefKind::Local { .. } => {
+                let place = self.local_place(def, span);
+                self.read(place, ty, span)
+            }
+            DefKind::Const => {
+                self.name(def);
+                self.emit(InstKind::Global(def), ty.clone(), span)
+            }
+            DefKind::Variant
+                if matches!(self.types.variants.get(&def), Some(VariantShape::Unit)) =>
+            {
+                self.name(def);
+                self.emit(InstKind::Adt(def, Vec::new()), ty.clone(), span)
+            }
+            DefKind::Fn | DefKind::Variant => {
+                self.unsupported(span, "functions used as values", ty)
+            }
+            DefKind::Builtin => self.unsupported(span, "builtin functions used as values", ty),
+            DefKind::Section | DefKind::Struct | DefKind::Enum => self.undef(ty, span),
+        }
+    }
+
+    fn prefix(
+        &mut self,
+        op: PrefixOp,
+        operand: &'a Spanned<Box<Expr>>,
+        ty: &Ty,
+        span: Span,
+    ) -> ValueId {
+        match op {
+            PrefixOp::Neg => {
+                // `-128` is in range for `i8` even though `128` is not
+                if let Expr::Literal(Literal::UInt(v, _)) = &*operand.node
+                    && let Ty::Int(int) = ty
+                {
+                    return self.constant(Value::Int(-(*v as i128), *int), ty, span);
+                }
+                let value = self.expr_boxed(operand);
+                self.emit(InstKind::Neg(value), ty.clone(), span)
+            }
+            PrefixOp::Not => {
+                let value = self.expr_boxed(operand);
+                self.emit(InstKind::Not(value), ty.clone(), span)
+            }
+            PrefixOp::Deref => {
+                let place = self.deref_place(operand);
+                self.read(place, ty, span)
+            }
+            PrefixOp::Ref | PrefixOp::RefMut => match self.ty(operand.span) {
+                Ty::Slice(_) => self.expr_boxed(operand),
+                _ => self.addr_of(&operand.node, operand.span),
+            },
+        }
+    }
+
+    fn call(
+        &mut self,
+        callee: &'a Spanned<Box<Expr>>,
+        args: &'a [Spanned<Expr>],
+        ty: &Ty,
+        span: Span,
+    ) -> ValueId {
+        let def = match &*callee.node {
+            Expr::Variable(_) | Expr::Path(_) => self.res.lookup(callee.span),
+            _ => None,
+        };
+        let Some(def) = def.filter(|def| {
+            matches!(
+                self.res.def(*def).kind,
+                DefKind::Fn | DefKind::Builtin | DefKind::Variant
+            )
+        }) else {
+            for arg in args {
+                self.expr(arg);
+            }
+            return self.unsupported(callee.span, "calls of function values", ty);
+        };
+
+        let info = self.res.def(def);
+        match info.kind {
+            DefKind::Builtin => {
+                let values: Vec<_> = args.iter().map(|arg| self.expr(arg)).collect();
+                match Builtin::from_name(info.name.as_str()) {
+                    Some(Builtin::Assert) => {
+                        let fail = self.new_block();
+                        if let Some(cond) = values.first() {
+                            self.check(*cond, fail, span);
+                        }
+                        let ok = self.func.current;
+                        self.seal(fail);
+                        self.switch_to(fail);
+                        self.panic("assertion failed", span);
+                        self.switch_to(ok);
+                        self.unit(span)
+                    }
+                    Some(builtin) => {
+                        self.emit(InstKind::Builtin(builtin, values), Ty::unit(), span)
+                    }
+                    None => self.undef(ty, span),
+                }
+            }
+            DefKind::Variant => {
+                let fields = self.fields(def);
+                let values = args
+                    .iter()
+                    .zip(&fields)
+                    .map(|(arg, (_, field_ty))| {
+                        let value = self.expr(arg);
+                        self.coerce(value, &self.ty(arg.span), field_ty, arg.span)
+                    })
+                    .collect();
+                self.name(def);
+                self.emit(InstKind::Adt(def, values), ty.clone(), span)
+            }
+            _ => {
+                let (params, ret) = self.signature(def);
+                let values = args
+                    .iter()
+                    .zip(&params)
+                    .map(|(arg, param)| {
+                        let value = self.expr(arg);
+                        self.coerce(value, &self.ty(arg.span), param, arg.span)
+                    })
+                    .collect();
+                self.name(def);
+                self.emit(InstKind::Call(def, values), ret, span)
+            }
+        }
+    }
+
+    // ------------------------------------------------------------ places
+
+    fn local_place(&mut self, def: DefId, span: Span) -> Place {
+        match self.func.addressed.contains(&def) {
+            true => Place::Addr(self.slot(def, span)),
+            false => Place::Local(def),
+        }
+    }
+
+    /// The location `expr` denotes, and the type of what is there.
+    fn place(&mut self, expr: &'a Expr, span: Span) -> (Place, Ty) {
+        let ty = self.ty(span);
+
+        let place = match expr {
+            Expr::Variable(_) => match self.res.lookup(span) {
+                Some(def) if matches!(self.res.def(def).kind, DefKind::Local { .. }) => {
+                    self.local_place(def, span)
+                }
+                _ => Place::Value(self.expr_at(expr, span)),
+            },
+            Expr::Field(base, name) => {
+                let (base, base_ty) = self.place_deref(base);
+                let idx = self.field_idx(&base_ty, name.node);
+                match base {
+                    Place::Addr(ptr) => {
+                        let field_ty = Ty::Ref {
+                            mutable: true,
+                            inner: Box::new(ty.clone()),
+                        };
+                        Place::Addr(self.emit(InstKind::FieldAddr(ptr, idx), field_ty, span))
+                    }
+                    base => {
+                        let base = self.read(base, &base_ty, span);
+                        let field = InstKind::Field {
+                            base,
+                            variant: None,
+                            idx,
+                        };
+                        Place::Value(self.emit(field, ty.clone(), span))
+                    }
+                }
+            }
+            Expr::Index(base, index) if !matches!(&*index.node, Expr::Range { .. }) => {
+                let (base, base_ty) = self.place_deref(base);
+                let elem_ty = Ty::Ref {
+                    mutable: true,
+                    inner: Box::new(ty.clone()),
+                };
+                match (base, &base_ty) {
+                    (base, Ty::Slice(_) | Ty::Ref { .. }) => {
+                        let slice = self.read(base, &base_ty, span);
+                        let index = self.expr_boxed(index);
+                        Place::Addr(self.emit(InstKind::IndexAddr(slice, index), elem_ty, span))
+                    }
+                    (Place::Addr(ptr), _) => {
+                        let index = self.expr_boxed(index);
+                        Place::Addr(self.emit(InstKind::IndexAddr(ptr, index), elem_ty, span))
+                    }
+                    (base, _) => {
+                        let index = self.expr_boxed(index);
+                        let base = self.read(base, &base_ty, span);
+                        Place::Value(self.emit(InstKind::Index(base, index), ty.clone(), span))
+                    }
+                }
+            }
+            Expr::Prefix(op, inner) if op.node == PrefixOp::Deref => self.deref_place(inner),
+            _ => Place::Value(self.expr_at(expr, span)),
+        };
+        (place, ty)
+    }
+
+    /// The place `*inner` names.
+    fn deref_place(&mut self, inner: &'a Spanned<Box<Expr>>) -> Place {
+        let value = self.expr_boxed(inner);
+        match self.ty(inner.span) {
+            Ty::Ref { inner, .. } if matches!(*inner, Ty::Slice(_)) => Place::Value(value),
+            _ => Place::Addr(value),
+        }
+    }
+
+    /// The place of `expr`, followed through references.
+    fn place_deref(&mut self, expr: &'a Spanned<Box<Expr>>) -> (Place, Ty) {
+        let (mut place, mut ty) = self.place(&expr.node, expr.span);
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            let inner = (**inner).clone();
+            place = Place::Addr(self.read(place, &ty, expr.span));
+            ty = inner;
+        }
+        (place, ty)
+    }
+
+    fn read(&mut self, place: Place, ty: &Ty, span: Span) -> ValueId {
+        match place {
+            Place::Local(def) => self.read_var(Var::Local(def), span),
+            Place::Addr(ptr) => self.emit(InstKind::Load(ptr), ty.clone(), span),
+            Place::Value(value) => value,
+        }
+    }
+
+    fn write(&mut self, place: Place, value: ValueId, span: Span) {
+        match place {
+            Place::Local(def) => self.write_var(Var::Local(def), value),
+            Place::Addr(ptr) => self.effect(InstKind::Store(ptr, value), span),
+            Place::Value(_) => (),
+        }
+    }
+
+    /// The address of the location `expr` denotes. Anything that is not a
+    /// local with a location, field, element or dereference is stored in a
+    /// new one first.
+    fn addr_of(&mut self, expr: &'a Expr, span: Span) -> ValueId {
+        let (place, ty) = self.place(expr, span);
+        self.place_addr(place, &ty, span)
+    }
+
+    fn place_addr(&mut self, place: Place, ty: &Ty, span: Span) -> ValueId {
+        if let Place::Addr(ptr) = place {
+            return ptr;
+        }
+        let value = self.read(place, ty, span);
+        let ptr_ty = Ty::Ref {
+            mutable: true,
+            inner: Box::new(ty.clone()),
+        };
+        let ptr = self.emit(InstKind::Alloc, ptr_ty, span);
+        self.effect(InstKind::Store(ptr, value), span);
+        ptr
+    }
+
+    /// `base[a..b]`, a slice into an array or a substring.
+    fn slice(
+        &mut self,
+        base: &'a Spanned<Box<Expr>>,
+        start: &'a Option<Spanned<Box<Expr>>>,
+        end: &'a Option<Spanned<Box<Expr>>>,
+        inclusive: bool,
+        ty: &Ty,
+        span: Span,
+    ) -> ValueId {
+        // an array is sliced through its address, slices and strings by
+        // value
+        let (mut place, mut base_ty) = self.place(&base.node, base.span);
+        let base = loop {
+            match &base_ty {
+                Ty::Ref { inner, .. } if !matches!(**inner, Ty::Array(..) | Ty::Slice(_)) => {
+                    let inner = (**inner).clone();
+                    place = Place::Addr(self.read(place, &base_ty, span));
+                    base_ty = inner;
+                }
+                Ty::Array(..) => break self.place_addr(place, &base_ty, span),
+                _ => break self.read(place, &base_ty, span),
+            }
+        };
+
+        let lo = match start {
+            Some(start) => self.expr_boxed(start),
+            None => self.constant(Value::Int(0, IntTy::U64), &LENGTH, span),
+        };
+        let hi = match end {
+            Some(end) => {
+                let hi = self.expr_boxed(end);
+                match (inclusive, self.ty(end.span)) {
+                    (true, Ty::Int(int)) => {
+                        let one = self.constant(Value::Int(1, int), &Ty::Int(int), span);
+                        self.emit(InstKind::Binary(BinaryOp::Add, hi, one), Ty::Int(int), span)
+                    }
+                    _ => hi,
+                }
+            }
+            None => self.emit(InstKind::Len(base), LENGTH, span),
+        };
+        self.emit(InstKind::Slice { base, lo, hi }, ty.clone(), span)
+    }
+
+    // ----------------------------------------------------- loops and match
+
+    fn for_loop(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        iter: &'a Spanned<Box<Expr>>,
+        body: &'a BlockExpr,
+        span: Span,
+    ) {
+        let iter_ty = self.ty(iter.span);
+        if let Some(elem_ty) = range_elem(&iter_ty) {
+            let Expr::Range {
+                start: Some(start),
+                end,
+                inclusive,
+            } = &*iter.node
+            else {
+                self.unsupported(
+                    iter.span,
+                    "ranges outside `for` loops and slicing",
+                    &iter_ty,
+                );
+                return;
+            };
+            let elem_ty = elem_ty.clone();
+            let Ty::Int(int) = elem_ty else {
+                return;
+            };
+            return self.for_range(pattern, (start, end, *inclusive), int, body, span);
+        }
+
+        // the loop visits a copy, like the interpreter does
+        let iterated = self.expr_boxed(iter);
+        let (iterated, ty) = self.deref(iterated, &iter_ty, iter.span);
+        let len = self.emit(InstKind::Len(iterated), LENGTH, iter.span);
+        let idx = self.temp(&LENGTH);
+        let zero = self.constant(Value::Int(0, IntTy::U64), &LENGTH, iter.span);
+        self.write_var(idx, zero);
+
+        let (next, exit) = (self.new_block(), self.new_block());
+        self.jump(next, span);
+        self.switch_to(next);
+        let offset = self.read_var(idx, iter.span);
+        let more = self.emit(
+            InstKind::Binary(BinaryOp::Lt, offset, len),
+            Ty::Bool,
+            iter.span,
+        );
+        self.check(more, exit, iter.span);
+
+        let (elem, step, elem_ty) = match &ty {
+            Ty::Str => {
+                let c = self.emit(InstKind::CharAt(iterated, offset), Ty::Char, iter.span);
+                let width = self.emit(InstKind::CharLen(c), LENGTH, iter.span);
+                (c, width, Ty::Char)
+            }
+            _ => {
+                let elem_ty = match &ty {
+                    Ty::Array(elem, _) | Ty::Slice(elem) => (**elem).clone(),
+                    Ty::Ref { inner, .. } => match &**inner {
+                        Ty::Slice(elem) => (**elem).clone(),
+                        _ => Ty::unit(),
+                    },
+                    _ => Ty::unit(),
+                };
+                let elem = self.emit(
+                    InstKind::Index(iterated, offset),
+                    elem_ty.clone(),
+                    iter.span,
+                );
+                let one = self.constant(Value::Int(1, IntTy::U64), &LENGTH, iter.span);
+                (elem, one, elem_ty)
+            }
+        };
+        let offset = self.emit(
+            InstKind::Binary(BinaryOp::Add, offset, step),
+            LENGTH,
+            iter.span,
+        );
+        self.write_var(idx, offset);
+
+        self.bind_element(pattern, elem, &elem_ty);
+        self.loop_body(next, exit, body, span);
+        self.seal(next);
+        self.seal(exit);
+        self.switch_to(exit);
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn for_range(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        (start, end, inclusive): (&'a Spanned<Box<Expr>>, &'a Option<Spanned<Box<Expr>>>, bool),
+        int: IntTy,
+        body: &'a BlockExpr,
+        span: Span,
+    ) {
+        let ty = Ty::Int(int);
+        let counter = self.temp(&ty);
+        let start = self.expr_boxed(start);
+        self.write_var(counter, start);
+        let end = end.as_ref().map(|end| self.expr_boxed(end));
+
+        // `a..=b` keeps a flag rather than stepping past `b`, which could
+        // overflow
+        let more = match (end, inclusive) {
+            (Some(end), true) => {
+                let more = self.temp(&Ty::Bool);
+                let le = self.emit(InstKind::Binary(BinaryOp::Le, start, end), Ty::Bool, span);
+                self.write_var(more, le);
+                Some(more)
+            }
+            _ => None,
+        };
+
+        let (next, exit, step) = (self.new_block(), self.new_block(), self.new_block());
+        self.jump(next, span);
+        self.switch_to(next);
+        match (end, more) {
+            (_, Some(more)) => {
+                let more = self.read_var(more, span);
+                self.check(more, exit, span);
+            }
+            (Some(end), None) => {
+                let value = self.read_var(counter, span);
+                let lt = self.emit(InstKind::Binary(BinaryOp::Lt, value, end), Ty::Bool, span);
+                self.check(lt, exit, span);
+            }
+            (None, _) => (),
+        }
+
+        let value = self.read_var(counter, span);
+        self.bind_element(pattern, value, &ty);
+        self.loop_body(step, exit, body, span);
+
+        self.seal(step);
+        self.switch_to(step);
+        let value = self.read_var(counter, span);
+        let one = self.constant(Value::Int(1, int), &ty, span);
+        match (end, more) {
+            (Some(end), Some(more)) => {
+                let ne = self.emit(InstKind::Binary(BinaryOp::Ne, value, end), Ty::Bool, span);
+                self.write_var(more, ne);
+                let inc = self.new_block();
+                self.branch(ne, inc, next, span);
+                self.seal(inc);
+                self.switch_to(inc);
+            }
+            (None, _) => {
+                let max = self.constant(Value::Int(value::int_max(int), int), &ty, span);
+                let ne = self.emit(InstKind::Binary(BinaryOp::Ne, value, max), Ty::Bool, span);
+                let fail = self.new_block();
+                self.check(ne, fail, span);
+                let ok = self.func.current;
+                self.seal(fail);
+                self.switch_to(fail);
+                self.panic("attempt to iterate past the end of the integer range", span);
+                self.switch_to(ok);
+            }
+            (Some(_), None) => (),
+        }
+        let new = self.emit(InstKind::Binary(BinaryOp::Add, value, one), ty, span);
+        self.write_var(counter, new);
+        self.jump(next, span);
+
+        self.seal(next);
+        self.seal(exit);
+        self.switch_to(exit);
+    }
+
+    /// Lowers the body of a `for` loop, whose `continue` goes to `next`,
+    /// and jumps to `next` after it.
+    fn loop_body(&mut self, next: BlockId, exit: BlockId, body: &'a BlockExpr, span: Span) {
+        self.func.loops.push(LoopTargets {
+            next,
+            exit,
+            result: None,
+            ty: Ty::unit(),
+        });
+        self.block(body);
+        self.func.loops.pop();
+        self.jump(next, span);
+    }
+
+    /// Binds the loop pattern to an element.
+    fn bind_element(&mut self, pattern: &'a Spanned<Pattern>, elem: ValueId, ty: &Ty) {
+        let fail = self.new_block();
+        let mut binds = Vec::new();
+        self.test(pattern, elem, ty, fail, &mut binds, false);
+        for (def, value) in binds {
+            self.bind(def, value, pattern.span);
+        }
+        let ok = self.func.current;
+        self.seal(fail);
+        self.switch_to(fail);
+        self.panic("pattern did not match the element", pattern.span);
+        self.switch_to(ok);
+    }
+
+    /// The case of a `switch` a pattern matches, if it tests the variant of
+    /// an enum, whether an optional is present, or a scalar.
+    fn switch_key(&self, pattern: &Pattern, ty: &Ty) -> Option<i128> {
+        let variant = |span| {
+            let def = self.res.lookup(span)?;
+            (self.res.def(def).kind == DefKind::Variant)
+                .then(|| self.variant_tag(def))
+                .flatten()
+        };
+
+        match (pattern, ty) {
+            (Pattern::Wildcard | Pattern::Error(_), _) => None,
+            (Pattern::Variable(name), _)
+                if self
+                    .res
+                    .lookup(name.span)
+                    .is_some_and(|def| matches!(self.res.def(def).kind, DefKind::Local { .. })) =>
+            {
+                None
+            }
+            (Pattern::Literal(literal), Ty::Optional(_))
+                if matches!(literal.node, Literal::None) =>
+            {
+                Some(0)
+            }
+            (_, Ty::Optional(_)) => Some(1),
+            (Pattern::Variable(name), Ty::Path(_)) => variant(name.span).map(i128::from),
+            (Pattern::Tuple { path, .. } | Pattern::Struct { path, .. }, Ty::Path(_)) => {
+                variant(path_span(path)).map(i128::from)
+            }
+            (Pattern::Literal(literal), Ty::Int(_) | Ty::Char | Ty::Bool) => {
+                match value::literal(&literal.node, Some(ty)) {
+                    Value::Int(value, _) => Some(value),
+                    Value::Char(c) => Some(c as i128),
+                    Value::Bool(b) => Some(b as i128),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn lower_match(
+        &mut self,
+        scrutinee: &'a Spanned<Box<Expr>>,
+        arms: &'a [MatchArm],
+        ty: &Ty,
+        span: Span,
+    ) -> ValueId {
+        let scrutinee_ty = self.ty(scrutinee.span);
+        let value = self.expr_boxed(scrutinee);
+        let (derefed, derefed_ty) = self.deref(value, &scrutinee_ty, scrutinee.span);
+
+        let switchable = match &derefed_ty {
+            Ty::Path(path) => self
+                .res
+                .def_by_path(path)
+                .is_some_and(|def| self.res.def(def).kind == DefKind::Enum),
+            ty => matches!(ty, Ty::Optional(_) | Ty::Int(_) | Ty::Char | Ty::Bool),
+        };
+        let keys: Vec<_> = arms
+            .iter()
+            .map(|arm| {
+                switchable
+                    .then(|| self.switch_key(&arm.pattern.node, &derefed_ty))
+                    .flatten()
+            })
+            .collect();
+
+        let bodies: Vec<_> = arms.iter().map(|_| self.new_block()).collect();
+        let end = self.new_block();
+        let result = self.result_var(ty);
+        let subject = [(value, scrutinee_ty), (derefed, derefed_ty.clone())];
+
+        if keys.iter().any(Option::is_some) {
+            // each case tests the arms with its key, or with none, in
+            // order, knowing the key matches
+            let switched = match derefed_ty {
+                Ty::Int(_) | Ty::Char | Ty::Bool => derefed,
+                _ => self.emit(InstKind::Discriminant(derefed), DISCRIMINANT, span),
+            };
+            let mut cases: Vec<(i128, Target)> = Vec::new();
+            for key in keys.iter().flatten() {
+                if !cases.iter().any(|(case, _)| case == key) {
+                    let block = self.new_block();
+                    cases.push((*key, Target::new(block)));
+                }
+            }
+            let default = self.new_block();
+            let blocks: Vec<_> = cases
+                .iter()
+                .map(|(key, target)| (*key, target.block))
+                .collect();
+            self.terminate(
+                Terminator::Switch {
+                    value: switched,
+                    cases,
+                    default: Target::new(default),
+                },
+                span,
+            );
+
+            for (key, block) in blocks {
+                self.seal(block);
+                self.switch_to(block);
+                let chain: Vec<_> = keys
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, k)| k.is_none() || **k == Some(key))
+                    .map(|(idx, k)| (idx, k.is_some()))
+                    .collect();
+                self.arm_chain(arms, &chain, &subject, &bodies, span);
+            }
+            self.seal(default);
+            self.switch_to(default);
+            let chain: Vec<_> = (keys.iter().enumerate())
+                .filter(|(_, k)| k.is_none())
+                .map(|(idx, _)| (idx, false))
+                .collect();
+            self.arm_chain(arms, &chain, &subject, &bodies, span);
+        } else {
+            let chain: Vec<_> = (0..arms.len()).map(|idx| (idx, false)).collect();
+            self.arm_chain(arms, &chain, &subject, &bodies, span);
+        }
+
+        for (arm, block) in arms.iter().zip(bodies) {
+            self.seal(block);
+            self.switch_to(block);
+            let value = self.expr(&arm.body);
+            let value = self.coerce(value, &self.ty(arm.body.span), ty, arm.body.span);
+            if let Some(result) = result {
+                self.write_var(result, value);
+            }
+            self.jump(end, arm.body.span);
+        }
+
+        self.seal(end);
+        self.switch_to(end);
+        self.read_result(result, ty, span)
+    }
+
+    /// Tests the arms `chain` lists in order, going to the body of the
+    /// first that matches and panicking if none does. Arms listed with
+    /// `true` are known to match the key of the `switch` before them, and
+    /// test what is left on the dereferenced scrutinee.
+    fn arm_chain(
+        &mut self,
+        arms: &'a [MatchArm],
+        chain: &[(usize, bool)],
+        subject: &[(ValueId, Ty); 2],
+        bodies: &[BlockId],
+        span: Span,
+    ) {
+        for (idx, known) in chain {
+            let arm = &arms[*idx];
+            let (value, ty) = subject[*known as usize].clone();
+            let fail = self.new_block();
+            let mut binds = Vec::new();
+            self.test(&arm.pattern, value, &ty, fail, &mut binds, *known);
+            for (def, value) in binds {
+                self.bind(def, value, arm.pattern.span);
+            }
+            self.jump(bodies[*idx], arm.pattern.span);
+            self.seal(fail);
+            self.switch_to(fail);
+        }
+        self.panic("no `match` arm matches the value", span);
+    }
+
+    /// Tests `value` of type `ty` against `pattern`, going to `fail` if it
+    /// does not match. The locals it binds, which may only be bound once
+    /// every test passed, go in `binds`. A `known` pattern is known to
+    /// match at the top, so only what it holds is tested.
+    fn test(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        value: ValueId,
+        ty: &Ty,
+        fail: BlockId,
+        binds: &mut Vec<(DefId, ValueId)>,
+        known: bool,
+    ) {
+        let span = pattern.span;
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => return,
+            Pattern::Variable(name) => {
+                if let Some(def) = self.res.lookup(name.span)
+                    && matches!(self.res.def(def).kind, DefKind::Local { .. })
+                {
+                    binds.push((def, value));
+                    return;
+                }
+            }
+            _ => (),
+        }
+
+        // patterns look through references and into optionals
+        let (mut value, mut ty) = self.deref(value, ty, span);
+        let mut known = known;
+        if let Ty::Optional(inner) = &ty {
+            let none =
+                matches!(&pattern.node, Pattern::Literal(l) if matches!(l.node, Literal::None));
+            if !known {
+                self.tag_test(value, !none as u32, fail, span);
+            }
+            if none {
+                return;
+            }
+            let inner = (**inner).clone();
+            value = self.emit(InstKind::Unwrap(value), inner.clone(), span);
+            ty = inner;
+            known = false;
+        }
+
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => (),
+            Pattern::Literal(literal) => {
+                if !known {
+                    let expected = value::literal(&literal.node, Some(&ty));
+                    let expected = self.constant(expected, &ty, literal.span);
+                    let eq = InstKind::Binary(BinaryOp::Eq, value, expected);
+                    let eq = self.emit(eq, Ty::Bool, span);
+                    self.check(eq, fail, span);
+                }
+            }
+            Pattern::Variable(name) => {
+                let Some(def) = self.res.lookup(name.span) else {
+                    return;
+                };
+                if self.res.def(def).kind == DefKind::Const {
+                    self.name(def);
+                    let const_ty = self
+                        .types
+                        .consts
+                        .get(&def)
+                        .cloned()
+                        .unwrap_or_else(Ty::unit);
+                    let expected = self.emit(InstKind::Global(def), const_ty, name.span);
+                    let eq = InstKind::Binary(BinaryOp::Eq, value, expected);
+                    let eq = self.emit(eq, Ty::Bool, span);
+                    self.check(eq, fail, span);
+                } else if let Some(tag) = self.variant_tag(def)
+                    && !known
+                {
+                    self.tag_test(value, tag, fail, span);
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                if let Some(tag) = self.variant_tag(def)
+                    && !known
+                {
+                    self.tag_test(value, tag, fail, span);
+                }
+                self.name(def);
+                for (idx, (elem, (_, field_ty))) in elems.iter().zip(self.fields(def)).enumerate() {
+                    let field = InstKind::Field {
+                        base: value,
+                        variant: Some(def),
+                        idx: idx as u32,
+                    };
+                    let field = self.emit(field, field_ty.clone(), elem.span);
+                    self.test(elem, field, &field_ty, fail, binds, false);
+                }
+            }
+            Pattern::Struct { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let tag = self.variant_tag(def);
+                if let Some(tag) = tag
+                    && !known
+                {
+                    self.tag_test(value, tag, fail, span);
+                }
+                self.name(def);
+                let layout = self.fields(def);
+                for field in fields {
+                    let name = field.node.name.node;
+                    let Some(idx) = layout.iter().position(|(n, _)| *n == Some(name)) else {
+                        continue;
+                    };
+                    let field_ty = layout[idx].1.clone();
+                    let kind = InstKind::Field {
+                        base: value,
+                        variant: tag.map(|_| def),
+                        idx: idx as u32,
+                    };
+                    let value = self.emit(kind, field_ty.clone(), field.span);
+                    self.test(&field.node.pattern, value, &field_ty, fail, binds, false);
+                }
+            }
+        }
+    }
+
+    /// Goes on if the enum or optional `value` has the discriminant `tag`,
+    /// and to `fail` otherwise.
+    fn tag_test(&mut self, value: ValueId, tag: u32, fail: BlockId, span: Span) {
+        let discriminant = self.emit(InstKind::Discriminant(value), DISCRIMINANT, span);
+        let expected = self.constant(Value::Int(tag as i128, IntTy::U32), &DISCRIMINANT, span);
+        let eq = InstKind::Binary(BinaryOp::Eq, discriminant, expected);
+        let eq = self.emit(eq, Ty::Bool, span);
+        self.check(eq, fail, span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Lowers `src` and checks the result is well formed.
+    fn lower(src: &str) -> Program {
+        let checked = testing::check(src);
+        let mut lowerer = Lowerer::new(&checked.res, &checked.types);
+        let program = lowerer.lower_script(&checked.script);
+        assert!(
+            lowerer.bag.is_empty(),
+            "lowering errors: {:?}",
+            lowerer.bag.0
+        );
+        if let Err(err) = program.verify() {
+            panic!("{err}\n{program}");
+        }
+        program
+    }
+
+    #[test]
+    fn test_lower_to_ssa() {
+        let program = lower(
+            "fn sum(n: i32): i32 {
+                let mut total = 0;
+                let mut i = 0;
+                while i < n { i += 1; total += i; }
+                total
+             }",
+        );
+
+        assert_eq!(
+            program.to_string(),
+            "fn sum(v0: i32): i32 {
+bb0:
+    v1: i32 = const 0
+    v2: i32 = const 0
+    jump bb1(v2, v1)
+bb1(v3: i32, v4: i32):
+    v5: bool = lt v3, v0
+    branch v5, bb3, bb2
+bb2:
+    v6: () = const ()
+    return v4
+bb3:
+    v7: i32 = const 1
+    v8: i32 = add v3, v7
+    v9: () = const ()
+    v10: i32 = add v4, v8
+    v11: () = const ()
+    v12: () = const ()
+    jump bb1(v8, v10)
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_lower_match_to_switch() {
+        let program = lower(
+            "enum Shape { Circle(f32), Rect { w: f32, h: f32 }, Empty }
+             fn area(s: Shape): f32 {
+                match s {
+                    Shape::Circle(r) => r * r * 3.0,
+                    Shape::Rect { w, h } => w * h,
+                    Shape::Empty => 0.0,
+                }
+             }
+             fn grade(n: i32): char { match n { 0 => 'a', 1 => 'b', _ => 'c' } }",
+        );
+        let dump = program.to_string();
+
+        assert!(dump.contains("v1: u32 = discriminant v0\n    switch v1 [0: bb"));
+        assert!(dump.contains("field v0 as Shape::Rect.1"));
+        assert!(dump.contains("panic \"no `match` arm matches the value\""));
+        assert!(dump.contains("switch v0 [0: bb"));
+    }
+
+    #[test]
+    fn test_addressed_locals_live_in_allocations() {
+        let program = lower(
+            "struct Point { x: i32, y: i32 }
+             fn bump(p: &mut Point) { p.x += 1; }
+             fn main() {
+                let mut p = Point { x: 1, y: 2 };
+                bump(&mut p);
+                let mut n = p.x;
+                n *= 2;
+                println(n);
+             }",
+        );
+        let dump = program.to_string();
+
+        assert!(dump.contains("v0: &mut Point = alloc"));
+        assert!(dump.contains("call bump(v0)"));
+        assert!(dump.contains("field_addr v0.0"));
+        assert!(!dump.contains("&mut i32 = alloc"));
+    }
+
+    #[test]
+    fn test_report_unsupported_expressions() {
+        let checked = testing::check("fn main() { let f = |x: i32| x; }");
+        let mut lowerer = Lowerer::new(&checked.res, &checked.types);
+        let program = lowerer.lower_script(&checked.script);
+        assert_eq!(
+            lowerer.bag.0[0].message,
+            "closures are not supported by the mid-level IR"
+        );
+        assert!(program.verify().is_ok());
+    }
+}
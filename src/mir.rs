@@ -0,0 +1,1323 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    ast::{BinaryOp, Spanned},
+    resolve::DefId,
+    span::Span,
+    ty::{IntTy, Ty},
+    value::{Builtin, Value},
+};
+
+/// A value, defined once by an instruction or a block parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ValueId(pub u32);
+
+impl fmt::Display for ValueId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(pub u32);
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+/// The type of [`InstKind::Discriminant`].
+pub const DISCRIMINANT: Ty = Ty::Int(IntTy::U32);
+
+/// The type of lengths and offsets.
+pub const LENGTH: Ty = Ty::Int(IntTy::U64);
+
+#[derive(Debug, Clone)]
+pub enum InstKind {
+    /// an integer, float, `bool`, `char`, `str`, `()` or `none`
+    Const(Value),
+    /// a value of the result's type that is never looked at, for variables
+    /// read before they are assigned
+    Undef,
+    /// any operator but `&&` and `||`, panicking on overflow like the
+    /// interpreter
+    Binary(BinaryOp, ValueId, ValueId),
+    Neg(ValueId),
+    Not(ValueId),
+    /// converts a scalar to the result's type
+    Cast(ValueId),
+    /// wraps a value in a present optional
+    Some(ValueId),
+    /// turns `&[T; N]` into `&[T]`
+    Unsize(ValueId),
+    Tuple(Vec<ValueId>),
+    Array(Vec<ValueId>),
+    /// a struct or enum variant, with its fields in declaration order
+    Adt(DefId, Vec<ValueId>),
+    /// a field of a tuple or struct, or of a value known to be `variant`
+    Field {
+        base: ValueId,
+        variant: Option<DefId>,
+        idx: u32,
+    },
+    /// the index of the variant of an enum, or 1 for a present optional
+    /// and 0 for `none`
+    Discriminant(ValueId),
+    /// the value in an optional known to be present
+    Unwrap(ValueId),
+    /// an element of an array or slice, checking the index
+    Index(ValueId, ValueId),
+    /// the elements or bytes `lo..hi` of an array behind a reference, a
+    /// slice or a `str`, checking the bounds
+    Slice {
+        base: ValueId,
+        lo: ValueId,
+        hi: ValueId,
+    },
+    /// the elements of an array or slice, or the bytes of a `str`
+    Len(ValueId),
+    /// the char starting at a byte offset of a `str`
+    CharAt(ValueId, ValueId),
+    /// the bytes a char takes in UTF-8
+    CharLen(ValueId),
+    /// the value of a constant item, computed by its body on first use
+    Global(DefId),
+    Call(DefId, Vec<ValueId>),
+    Builtin(Builtin, Vec<ValueId>),
+    /// a new location holding a value of the type the result refers to
+    Alloc,
+    Load(ValueId),
+    /// stores the value in the second operand at the first, with no result
+    Store(ValueId, ValueId),
+    /// the address of a field of the tuple or struct at an address
+    FieldAddr(ValueId, u32),
+    /// the address of an element of the array at an address or of a slice,
+    /// checking the index
+    IndexAddr(ValueId, ValueId),
+    /// reports a runtime error, with no result, before an
+    /// [`Terminator::Unreachable`]
+    Panic(String),
+}
+
+impl InstKind {
+    /// The values the instruction reads, in order.
+    pub fn operands(&self) -> Vec<ValueId> {
+        let mut kind = self.clone();
+        kind.operands_mut().into_iter().map(|v| *v).collect()
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            Self::Const(_) | Self::Undef | Self::Global(_) | Self::Alloc | Self::Panic(_) => {
+                Vec::new()
+            }
+            Self::Neg(v)
+            | Self::Not(v)
+            | Self::Cast(v)
+            | Self::Some(v)
+            | Self::Unsize(v)
+            | Self::Discriminant(v)
+            | Self::Unwrap(v)
+            | Self::Len(v)
+            | Self::CharLen(v)
+            | Self::Load(v)
+            | Self::FieldAddr(v, _)
+            | Self::Field { base: v, .. } => vec![v],
+            Self::Binary(_, a, b)
+            | Self::Index(a, b)
+            | Self::CharAt(a, b)
+            | Self::Store(a, b)
+            | Self::IndexAddr(a, b) => vec![a, b],
+            Self::Slice { base, lo, hi } => vec![base, lo, hi],
+            Self::Tuple(values)
+            | Self::Array(values)
+            | Self::Adt(_, values)
+            | Self::Call(_, values)
+            | Self::Builtin(_, values) => values.iter_mut().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Inst {
+    /// `None` for stores and panics
+    pub result: Option<ValueId>,
+    pub kind: InstKind,
+    pub span: Span,
+}
+
+/// A block to go to and the values of its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub block: BlockId,
+    pub args: Vec<ValueId>,
+}
+
+impl Target {
+    pub fn new(block: BlockId) -> Self {
+        Self {
+            block,
+            args: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(Target),
+    Branch {
+        cond: ValueId,
+        then: Target,
+        else_: Target,
+    },
+    /// goes to the case equal to an integer, `bool`, `char` or
+    /// discriminant
+    Switch {
+        value: ValueId,
+        cases: Vec<(i128, Target)>,
+        default: Target,
+    },
+    Return(ValueId),
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn targets(&self) -> Vec<&Target> {
+        match self {
+            Self::Jump(target) => vec![target],
+            Self::Branch { then, else_, .. } => vec![then, else_],
+            Self::Switch { cases, default, .. } => cases
+                .iter()
+                .map(|(_, target)| target)
+                .chain([default])
+                .collect(),
+            Self::Return(_) | Self::Unreachable => Vec::new(),
+        }
+    }
+
+    pub fn targets_mut(&mut self) -> Vec<&mut Target> {
+        match self {
+            Self::Jump(target) => vec![target],
+            Self::Branch { then, else_, .. } => vec![then, else_],
+            Self::Switch { cases, default, .. } => cases
+                .iter_mut()
+                .map(|(_, target)| target)
+                .chain([default])
+                .collect(),
+            Self::Return(_) | Self::Unreachable => Vec::new(),
+        }
+    }
+
+    /// The values the terminator reads, including the arguments of its
+    /// targets.
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        let (own, targets) = match self {
+            Self::Jump(target) => (None, vec![target]),
+            Self::Branch { cond, then, else_ } => (Some(cond), vec![then, else_]),
+            Self::Switch {
+                value,
+                cases,
+                default,
+            } => (
+                Some(value),
+                cases
+                    .iter_mut()
+                    .map(|(_, target)| target)
+                    .chain([default])
+                    .collect(),
+            ),
+            Self::Return(value) => (Some(value), Vec::new()),
+            Self::Unreachable => (None, Vec::new()),
+        };
+        own.into_iter()
+            .chain(
+                targets
+                    .into_iter()
+                    .flat_map(|target| target.args.iter_mut()),
+            )
+            .collect()
+    }
+
+    pub fn operands(&self) -> Vec<ValueId> {
+        let mut term = self.clone();
+        term.operands_mut().into_iter().map(|v| *v).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub params: Vec<ValueId>,
+    pub insts: Vec<Inst>,
+    pub term: Spanned<Terminator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    Fn,
+    /// computes the value of a constant item
+    Const,
+}
+
+/// The code of a function or constant. The parameters of the entry block,
+/// the first one, are the function's.
+#[derive(Debug, Clone)]
+pub struct Body {
+    pub def: DefId,
+    pub kind: BodyKind,
+    /// the path of the item, `math::add`
+    pub name: String,
+    pub ret: Ty,
+    pub span: Span,
+    /// the type of each value
+    pub values: Vec<Ty>,
+    pub blocks: Vec<Block>,
+}
+
+impl Body {
+    pub fn ty(&self, value: ValueId) -> &Ty {
+        &self.values[value.0 as usize]
+    }
+
+    pub fn block(&self, block: BlockId) -> &Block {
+        &self.blocks[block.0 as usize]
+    }
+
+    pub fn params(&self) -> &[ValueId] {
+        self.blocks
+            .first()
+            .map(|block| &block.params[..])
+            .unwrap_or_default()
+    }
+
+    pub fn successors(&self, block: BlockId) -> Vec<BlockId> {
+        let mut succs = Vec::new();
+        for target in self.block(block).term.node.targets() {
+            if !succs.contains(&target.block) {
+                succs.push(target.block);
+            }
+        }
+        succs
+    }
+
+    /// The predecessors of each block, each listed once.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for idx in 0..self.blocks.len() {
+            let block = BlockId(idx as u32);
+            for succ in self.successors(block) {
+                if let Some(preds) = preds.get_mut(succ.0 as usize) {
+                    preds.push(block);
+                }
+            }
+        }
+        preds
+    }
+
+    /// The blocks reachable from the entry, each before its successors
+    /// except along back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![(BlockId(0), 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            let succs = self.successors(block);
+            match succs.get(next) {
+                Some(succ) => {
+                    stack.push((block, next + 1));
+                    if let Some(seen) = visited.get_mut(succ.0 as usize)
+                        && !*seen
+                    {
+                        *seen = true;
+                        stack.push((*succ, 0));
+                    }
+                }
+                None => order.push(block),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// The immediate dominator of each block, `None` for the entry and for
+    /// blocks that can not be reached.
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (idx, block) in order.iter().enumerate() {
+            rank[block.0 as usize] = idx;
+        }
+        let preds = self.predecessors();
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        if let Some(entry) = order.first() {
+            idom[entry.0 as usize] = Some(*entry);
+        }
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while rank[a.0 as usize] > rank[b.0 as usize] {
+                    a = idom[a.0 as usize].unwrap_or(a);
+                }
+                while rank[b.0 as usize] > rank[a.0 as usize] {
+                    b = idom[b.0 as usize].unwrap_or(b);
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let mut new = None;
+                for pred in &preds[block.0 as usize] {
+                    if idom[pred.0 as usize].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        Some(new) => intersect(&idom, *pred, new),
+                        None => *pred,
+                    });
+                }
+                if new.is_some() && idom[block.0 as usize] != new {
+                    idom[block.0 as usize] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        if let Some(entry) = order.first() {
+            idom[entry.0 as usize] = None;
+        }
+        idom
+    }
+
+    /// Drops the blocks that can not be reached and numbers the rest in
+    /// reverse postorder, then numbers the values in the order they are
+    /// defined.
+    pub fn compact(&mut self) {
+        let order = self.reverse_postorder();
+        let mut block_map = vec![None; self.blocks.len()];
+        for (idx, block) in order.iter().enumerate() {
+            block_map[block.0 as usize] = Some(BlockId(idx as u32));
+        }
+        let mut old = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let mut blocks = order
+            .iter()
+            .filter_map(|block| old[block.0 as usize].take())
+            .collect::<Vec<_>>();
+
+        let mut value_map: HashMap<ValueId, ValueId> = HashMap::new();
+        let mut values = Vec::new();
+        let mut renumber = |value: &mut ValueId| {
+            let new = *value_map.entry(*value).or_insert_with(|| {
+                values.push(self.values[value.0 as usize].clone());
+                ValueId(values.len() as u32 - 1)
+            });
+            *value = new;
+        };
+        for block in &mut blocks {
+            block.params.iter_mut().for_each(&mut renumber);
+            for inst in &mut block.insts {
+                if let Some(result) = &mut inst.result {
+                    renumber(result);
+                }
+            }
+        }
+        for block in &mut blocks {
+            for inst in &mut block.insts {
+                inst.kind.operands_mut().into_iter().for_each(&mut renumber);
+            }
+            block
+                .term
+                .node
+                .operands_mut()
+                .into_iter()
+                .for_each(&mut renumber);
+            for target in block.term.node.targets_mut() {
+                if let Some(new) = block_map[target.block.0 as usize] {
+                    target.block = new;
+                }
+            }
+        }
+        self.blocks = blocks;
+        self.values = values;
+    }
+
+    /// Removes the parameters of blocks whose incoming arguments are all the
+    /// same value, or the parameter itself, until none are left.
+    pub fn prune_params(&mut self) {
+        let mut aliases = HashMap::new();
+        loop {
+            let mut changed = false;
+            for idx in 1..self.blocks.len() {
+                let block = BlockId(idx as u32);
+                let mut pos = self.blocks[idx].params.len();
+                while pos > 0 {
+                    pos -= 1;
+                    let param = self.blocks[idx].params[pos];
+                    let mut incoming = HashSet::new();
+                    for source in &self.blocks {
+                        for target in source.term.node.targets() {
+                            if target.block == block {
+                                let arg = resolve(&aliases, target.args[pos]);
+                                if arg != param {
+                                    incoming.insert(arg);
+                                }
+                            }
+                        }
+                    }
+                    if incoming.len() != 1 {
+                        continue;
+                    }
+                    let value = incoming.into_iter().next().unwrap_or(param);
+                    aliases.insert(param, value);
+                    self.blocks[idx].params.remove(pos);
+                    for source in &mut self.blocks {
+                        for target in source.term.node.targets_mut() {
+                            if target.block == block {
+                                target.args.remove(pos);
+                            }
+                        }
+                    }
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.replace_uses(&aliases);
+    }
+
+    /// Replaces every use of a value by the one it maps to, following the
+    /// map until a value it does not map.
+    pub fn replace_uses(&mut self, aliases: &HashMap<ValueId, ValueId>) {
+        if aliases.is_empty() {
+            return;
+        }
+        for block in &mut self.blocks {
+            for inst in &mut block.insts {
+                for value in inst.kind.operands_mut() {
+                    *value = resolve(aliases, *value);
+                }
+            }
+            for value in block.term.node.operands_mut() {
+                *value = resolve(aliases, *value);
+            }
+        }
+    }
+}
+
+fn resolve(aliases: &HashMap<ValueId, ValueId>, mut value: ValueId) -> ValueId {
+    while let Some(next) = aliases.get(&value) {
+        value = *next;
+    }
+    value
+}
+
+/// A lowered script, see [`Lowerer`](crate::lower::Lowerer).
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub bodies: Vec<Body>,
+    /// the body of `main`
+    pub main: Option<usize>,
+    /// the path of every item the bodies refer to
+    pub names: HashMap<DefId, String>,
+}
+
+impl Program {
+    pub fn body(&self, def: DefId) -> Option<&Body> {
+        self.bodies.iter().find(|body| body.def == def)
+    }
+
+    fn name(&self, def: DefId) -> &str {
+        self.names.get(&def).map_or("?", |name| name.as_str())
+    }
+
+    /// Checks that every body is well formed: blocks end in terminators
+    /// whose targets exist and get an argument of the right type for each
+    /// parameter, values are defined once before every use along every
+    /// path, and the operands of instructions have the types they expect.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        for body in &self.bodies {
+            Verifier::new(self, body).body()?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a program is not well formed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub body: String,
+    pub block: Option<BlockId>,
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.block {
+            Some(block) => write!(f, "in `{}` at {block}: ", self.body)?,
+            None => write!(f, "in `{}`: ", self.body)?,
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Whether values of two types can be used for each other. References
+/// differ in mutability only, and `&[T]` is the same slice as `[T]`.
+fn compatible(a: &Ty, b: &Ty) -> bool {
+    match (a, b) {
+        (Ty::Never, _) | (_, Ty::Never) => true,
+        (Ty::Ref { inner: a, .. }, Ty::Ref { inner: b, .. }) => compatible(a, b),
+        (Ty::Ref { inner, .. }, Ty::Slice(elem)) | (Ty::Slice(elem), Ty::Ref { inner, .. }) => {
+            matches!(&**inner, Ty::Slice(inner) if compatible(inner, elem))
+        }
+        (Ty::Slice(a), Ty::Slice(b)) | (Ty::Optional(a), Ty::Optional(b)) => compatible(a, b),
+        (Ty::Array(a, n), Ty::Array(b, m)) => n == m && compatible(a, b),
+        (Ty::Tuple(a), Ty::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| compatible(a, b))
+        }
+        _ => a == b,
+    }
+}
+
+/// The element type of an array, a slice or a reference to either.
+fn elem_ty(ty: &Ty) -> Option<&Ty> {
+    match ty {
+        Ty::Array(elem, _) | Ty::Slice(elem) => Some(elem),
+        Ty::Ref { inner, .. } => match &**inner {
+            Ty::Array(elem, _) | Ty::Slice(elem) => Some(elem),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    body: &'a Body,
+    /// where each value is defined, the parameters of a block before its
+    /// first instruction
+    defs: HashMap<ValueId, (BlockId, usize)>,
+    idom: Vec<Option<BlockId>>,
+    reachable: HashSet<BlockId>,
+    block: Option<BlockId>,
+    span: Option<Span>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(program: &'a Program, body: &'a Body) -> Self {
+        Self {
+            program,
+            body,
+            defs: HashMap::new(),
+            idom: body.dominators(),
+            reachable: body.reverse_postorder().into_iter().collect(),
+            block: None,
+            span: None,
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, VerifyError> {
+        Err(VerifyError {
+            body: self.body.name.clone(),
+            block: self.block,
+            span: self.span,
+            message: message.into(),
+        })
+    }
+
+    fn body(&mut self) -> Result<(), VerifyError> {
+        if self.body.blocks.is_empty() {
+            return self.error("the body has no blocks");
+        }
+        if self.body.kind == BodyKind::Const && !self.body.params().is_empty() {
+            return self.error("a constant can not have parameters");
+        }
+
+        // the parameters of a block are defined at index 0, and the result
+        // of its nth instruction at index n + 1
+        for (idx, block) in self.body.blocks.iter().enumerate() {
+            let id = BlockId(idx as u32);
+            self.block = Some(id);
+            let params = block.params.iter().map(|value| (*value, 0));
+            let results = (block.insts.iter().enumerate())
+                .filter_map(|(at, inst)| Some((inst.result?, at + 1)));
+            for (value, at) in params.chain(results) {
+                if value.0 as usize >= self.body.values.len() {
+                    return self.error(format!("{value} has no type"));
+                }
+                if self.defs.insert(value, (id, at)).is_some() {
+                    return self.error(format!("{value} is defined more than once"));
+                }
+            }
+        }
+
+        for (idx, block) in self.body.blocks.iter().enumerate() {
+            let id = BlockId(idx as u32);
+            self.block = Some(id);
+            for (at, inst) in block.insts.iter().enumerate() {
+                self.span = Some(inst.span);
+                for operand in inst.kind.operands() {
+                    self.use_at(operand, id, at + 1)?;
+                }
+                self.inst(inst)?;
+            }
+            self.span = Some(block.term.span);
+            for operand in block.term.node.operands() {
+                self.use_at(operand, id, block.insts.len() + 1)?;
+            }
+            self.terminator(&block.term.node)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that `value` is defined before index `at` of `block`.
+    fn use_at(&self, value: ValueId, block: BlockId, at: usize) -> Result<(), VerifyError> {
+        let Some((def_block, def_at)) = self.defs.get(&value).copied() else {
+            return self.error(format!("{value} is never defined"));
+        };
+        if !self.reachable.contains(&block) {
+            return Ok(());
+        }
+        let dominated = match def_block == block {
+            true => def_at < at,
+            false => {
+                let mut current = block;
+                loop {
+                    match self.idom[current.0 as usize] {
+                        Some(idom) if idom == def_block => break true,
+                        Some(idom) => current = idom,
+                        None => break false,
+                    }
+                }
+            }
+        };
+        if !dominated {
+            return self.error(format!("{value} is used where it may not be defined"));
+        }
+        Ok(())
+    }
+
+    fn ty(&self, value: ValueId) -> &'a Ty {
+        self.body.ty(value)
+    }
+
+    fn expect(&self, value: ValueId, ty: &Ty) -> Result<(), VerifyError> {
+        let found = self.ty(value);
+        if !compatible(found, ty) {
+            return self.error(format!("expected {value} to be {ty} but it is {found}"));
+        }
+        Ok(())
+    }
+
+    fn expect_int(&self, value: ValueId) -> Result<(), VerifyError> {
+        match self.ty(value) {
+            Ty::Int(_) | Ty::Never => Ok(()),
+            ty => self.error(format!("expected {value} to be an integer but it is {ty}")),
+        }
+    }
+
+    fn inst(&self, inst: &Inst) -> Result<(), VerifyError> {
+        let has_result = !matches!(inst.kind, InstKind::Store(..) | InstKind::Panic(_));
+        let Some(result) = inst.result else {
+            if has_result {
+                return self.error("the instruction is missing its result");
+            }
+            return match &inst.kind {
+                InstKind::Store(ptr, value) => self.expect(
+                    *ptr,
+                    &Ty::Ref {
+                        mutable: true,
+                        inner: Box::new(self.ty(*value).clone()),
+                    },
+                ),
+                _ => Ok(()),
+            };
+        };
+        if !has_result {
+            return self.error(format!("the instruction can not define {result}"));
+        }
+        let ty = self.ty(result);
+
+        match &inst.kind {
+            InstKind::Const(value) => {
+                let valid = match (value, ty) {
+                    (Value::Int(_, a), Ty::Int(b)) => a == b,
+                    (Value::Float(_, a), Ty::Float(b)) => a == b,
+                    (Value::Bool(_), Ty::Bool)
+                    | (Value::Char(_), Ty::Char)
+                    | (Value::Str(_), Ty::Str)
+                    | (Value::None, Ty::Optional(_)) => true,
+                    (Value::Unit, ty) => ty.is_unit(),
+                    _ => false,
+                };
+                if !valid {
+                    return self.error(format!("the constant {value} is not {ty}"));
+                }
+            }
+            InstKind::Binary(op, a, b) => {
+                if op.is_logical() {
+                    return self.error(format!("`{op}` must be lowered to branches"));
+                }
+                // a shift amount may be any integer, as typeck allows
+                if matches!(op, BinaryOp::Shl | BinaryOp::Shr) {
+                    self.expect_int(*b)?;
+                } else {
+                    self.expect(*b, self.ty(*a))?;
+                }
+                if op.is_comparison() {
+                    self.expect(result, &Ty::Bool)?;
+                } else {
+                    self.expect(result, self.ty(*a))?;
+                }
+            }
+            InstKind::Neg(v) | InstKind::Not(v) => self.expect(*v, ty)?,
+            InstKind::Cast(v) => {
+                let scalar = |ty: &Ty| {
+                    matches!(
+                        ty,
+                        Ty::Int(_) | Ty::Float(_) | Ty::Bool | Ty::Char | Ty::Never
+                    )
+                };
+                if !scalar(self.ty(*v)) || !scalar(ty) {
+                    return self.error(format!("can not cast {} to {ty}", self.ty(*v)));
+                }
+            }
+            InstKind::Some(v) => {
+                self.expect(result, &Ty::Optional(Box::new(self.ty(*v).clone())))?
+            }
+            InstKind::Unsize(v) => match self.ty(*v) {
+                Ty::Ref { inner, .. } if matches!(**inner, Ty::Array(..)) => {
+                    let elem = elem_ty(self.ty(*v)).cloned().unwrap_or_else(Ty::unit);
+                    self.expect(result, &Ty::Slice(Box::new(elem)))?;
+                }
+                found => return self.error(format!("can not unsize {found}")),
+            },
+            InstKind::Tuple(values) => {
+                let tys = values.iter().map(|v| self.ty(*v).clone()).collect();
+                self.expect(result, &Ty::Tuple(tys))?;
+            }
+            InstKind::Array(values) => {
+                let Ty::Array(elem, len) = ty else {
+                    return self.error(format!("an array can not be {ty}"));
+                };
+                if *len != values.len() as u64 {
+                    return self.error(format!("{ty} can not have {} elements", values.len()));
+                }
+                for value in values {
+                    self.expect(*value, elem)?;
+                }
+            }
+            InstKind::Field { base, variant, idx } => {
+                if let (Ty::Tuple(elems), None) = (self.ty(*base), variant) {
+                    match elems.get(*idx as usize) {
+                        Some(elem) => self.expect(result, elem)?,
+                        None => return self.error(format!("{base} has no field {idx}")),
+                    }
+                }
+            }
+            InstKind::Discriminant(v) => {
+                self.expect(result, &DISCRIMINANT)?;
+                if !matches!(self.ty(*v), Ty::Optional(_) | Ty::Path(_) | Ty::Never) {
+                    return self.error(format!("{} has no discriminant", self.ty(*v)));
+                }
+            }
+            InstKind::Unwrap(v) => match self.ty(*v) {
+                Ty::Optional(inner) => self.expect(result, inner)?,
+                found => return self.error(format!("can not unwrap {found}")),
+            },
+            InstKind::Index(base, idx) => {
+                self.expect_int(*idx)?;
+                match elem_ty(self.ty(*base)) {
+                    Some(elem) if !matches!(self.ty(*base), Ty::Ref { inner, .. } if matches!(**inner, Ty::Array(..))) => {
+                        self.expect(result, elem)?
+                    }
+                    _ => return self.error(format!("can not index {}", self.ty(*base))),
+                }
+            }
+            InstKind::Slice { base, lo, hi } => {
+                self.expect_int(*lo)?;
+                self.expect_int(*hi)?;
+                let expected = match self.ty(*base) {
+                    Ty::Str => Ty::Str,
+                    Ty::Array(..) => {
+                        return self.error("can not slice an array that has no address");
+                    }
+                    base => match elem_ty(base) {
+                        Some(elem) => Ty::Slice(Box::new(elem.clone())),
+                        None => return self.error(format!("can not slice {base}")),
+                    },
+                };
+                self.expect(result, &expected)?;
+            }
+            InstKind::Len(v) => {
+                self.expect(result, &LENGTH)?;
+                if *self.ty(*v) != Ty::Str && elem_ty(self.ty(*v)).is_none() {
+                    return self.error(format!("{} has no length", self.ty(*v)));
+                }
+            }
+            InstKind::CharAt(s, offset) => {
+                self.expect(*s, &Ty::Str)?;
+                self.expect_int(*offset)?;
+                self.expect(result, &Ty::Char)?;
+            }
+            InstKind::CharLen(c) => {
+                self.expect(*c, &Ty::Char)?;
+                self.expect(result, &LENGTH)?;
+            }
+            InstKind::Global(def) => match self.program.body(*def) {
+                Some(body) if body.kind == BodyKind::Const => self.expect(result, &body.ret)?,
+                _ => {
+                    return self.error(format!("`{}` is not a constant", self.program.name(*def)));
+                }
+            },
+            InstKind::Call(def, args) => {
+                let Some(callee) = self.program.body(*def).filter(|b| b.kind == BodyKind::Fn)
+                else {
+                    return self.error(format!("`{}` is not a function", self.program.name(*def)));
+                };
+                if callee.params().len() != args.len() {
+                    return self.error(format!(
+                        "`{}` takes {} argument{} but gets {}",
+                        callee.name,
+                        callee.params().len(),
+                        if callee.params().len() == 1 { "" } else { "s" },
+                        args.len()
+                    ));
+                }
+                for (arg, param) in args.iter().zip(callee.params()) {
+                    self.expect(*arg, callee.ty(*param))?;
+                }
+                self.expect(result, &callee.ret)?;
+            }
+            InstKind::Builtin(_, _) => self.expect(result, &Ty::unit())?,
+            InstKind::Alloc => {
+                if !matches!(ty, Ty::Ref { .. }) {
+                    return self.error(format!("an allocation can not be {ty}"));
+                }
+            }
+            InstKind::Load(ptr) => self.expect(
+                *ptr,
+                &Ty::Ref {
+                    mutable: false,
+                    inner: Box::new(ty.clone()),
+                },
+            )?,
+            InstKind::FieldAddr(ptr, idx) => match self.ty(*ptr) {
+                Ty::Ref { inner, .. } => {
+                    if let Ty::Tuple(elems) = &**inner {
+                        match elems.get(*idx as usize) {
+                            Some(elem) => self.expect(
+                                result,
+                                &Ty::Ref {
+                                    mutable: true,
+                                    inner: Box::new(elem.clone()),
+                                },
+                            )?,
+                            None => return self.error(format!("{ptr} has no field {idx}")),
+                        }
+                    }
+                }
+                found => return self.error(format!("{found} is not an address")),
+            },
+            InstKind::IndexAddr(base, idx) => {
+                self.expect_int(*idx)?;
+                match elem_ty(self.ty(*base)) {
+                    Some(elem) if !matches!(self.ty(*base), Ty::Array(..)) => self.expect(
+                        result,
+                        &Ty::Ref {
+                            mutable: true,
+                            inner: Box::new(elem.clone()),
+                        },
+                    )?,
+                    _ => return self.error(format!("{} is not an address", self.ty(*base))),
+                }
+            }
+            InstKind::Undef | InstKind::Store(..) | InstKind::Panic(_) | InstKind::Adt(..) => (),
+        }
+        Ok(())
+    }
+
+    fn target(&self, target: &Target) -> Result<(), VerifyError> {
+        let Some(block) = self.body.blocks.get(target.block.0 as usize) else {
+            return self.error(format!("{} does not exist", target.block));
+        };
+        if target.block.0 == 0 {
+            return self.error("the entry block can not be branched to");
+        }
+        if block.params.len() != target.args.len() {
+            return self.error(format!(
+                "{} takes {} argument{} but gets {}",
+                target.block,
+                block.params.len(),
+                if block.params.len() == 1 { "" } else { "s" },
+                target.args.len()
+            ));
+        }
+        for (arg, param) in target.args.iter().zip(&block.params) {
+            self.expect(*arg, self.ty(*param))?;
+        }
+        Ok(())
+    }
+
+    fn terminator(&self, term: &Terminator) -> Result<(), VerifyError> {
+        for target in term.targets() {
+            self.target(target)?;
+        }
+        match term {
+            Terminator::Branch { cond, .. } => self.expect(*cond, &Ty::Bool),
+            Terminator::Switch { value, cases, .. } => {
+                if !matches!(
+                    self.ty(*value),
+                    Ty::Int(_) | Ty::Bool | Ty::Char | Ty::Never
+                ) {
+                    return self.error(format!("can not switch on {}", self.ty(*value)));
+                }
+                let mut seen = HashSet::new();
+                for (case, _) in cases {
+                    if !seen.insert(case) {
+                        return self.error(format!("the case {case} appears more than once"));
+                    }
+                }
+                Ok(())
+            }
+            Terminator::Return(value) => self.expect(*value, &self.body.ret),
+            Terminator::Jump(_) | Terminator::Unreachable => Ok(()),
+        }
+    }
+}
+
+// ------------------------------------------------------------- printing
+
+fn op_name(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div => "div",
+        BinaryOp::Rem => "rem",
+        BinaryOp::Shl => "shl",
+        BinaryOp::Shr => "shr",
+        BinaryOp::BitAnd => "and",
+        BinaryOp::BitOr => "or",
+        BinaryOp::BitXor => "xor",
+        BinaryOp::And => "logical_and",
+        BinaryOp::Or => "logical_or",
+        BinaryOp::Eq => "eq",
+        BinaryOp::Ne => "ne",
+        BinaryOp::Lt => "lt",
+        BinaryOp::Gt => "gt",
+        BinaryOp::Le => "le",
+        BinaryOp::Ge => "ge",
+    }
+}
+
+fn write_values(f: &mut fmt::Formatter<'_>, values: &[ValueId]) -> fmt::Result {
+    for (idx, value) in values.iter().enumerate() {
+        if idx != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{value}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.block)?;
+        if !self.args.is_empty() {
+            write!(f, "(")?;
+            write_values(f, &self.args)?;
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jump(target) => write!(f, "jump {target}"),
+            Self::Branch { cond, then, else_ } => write!(f, "branch {cond}, {then}, {else_}"),
+            Self::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                write!(f, "switch {value} [")?;
+                for (idx, (case, target)) in cases.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{case}: {target}")?;
+                }
+                write!(f, "], {default}")
+            }
+            Self::Return(value) => write!(f, "return {value}"),
+            Self::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+struct ShowInst<'a>(&'a Program, &'a InstKind);
+
+impl fmt::Display for ShowInst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.0;
+        match self.1 {
+            InstKind::Const(value) => match value {
+                Value::Str(s) => write!(f, "const {s:?}"),
+                Value::Char(c) => write!(f, "const {c:?}"),
+                value => write!(f, "const {value}"),
+            },
+            InstKind::Undef => write!(f, "undef"),
+            InstKind::Binary(op, a, b) => write!(f, "{} {a}, {b}", op_name(*op)),
+            InstKind::Neg(v) => write!(f, "neg {v}"),
+            InstKind::Not(v) => write!(f, "not {v}"),
+            InstKind::Cast(v) => write!(f, "cast {v}"),
+            InstKind::Some(v) => write!(f, "some {v}"),
+            InstKind::Unsize(v) => write!(f, "unsize {v}"),
+            InstKind::Tuple(values) => {
+                write!(f, "tuple (")?;
+                write_values(f, values)?;
+                write!(f, ")")
+            }
+            InstKind::Array(values) => {
+                write!(f, "array [")?;
+                write_values(f, values)?;
+                write!(f, "]")
+            }
+            InstKind::Adt(def, values) => {
+                write!(f, "adt {}(", program.name(*def))?;
+                write_values(f, values)?;
+                write!(f, ")")
+            }
+            InstKind::Field {
+                base,
+                variant: None,
+                idx,
+            } => write!(f, "field {base}.{idx}"),
+            InstKind::Field {
+                base,
+                variant: Some(variant),
+                idx,
+            } => write!(f, "field {base} as {}.{idx}", program.name(*variant)),
+            InstKind::Discriminant(v) => write!(f, "discriminant {v}"),
+            InstKind::Unwrap(v) => write!(f, "unwrap {v}"),
+            InstKind::Index(base, idx) => write!(f, "index {base}[{idx}]"),
+            InstKind::Slice { base, lo, hi } => write!(f, "slice {base}[{lo}..{hi}]"),
+            InstKind::Len(v) => write!(f, "len {v}"),
+            InstKind::CharAt(s, offset) => write!(f, "char_at {s}, {offset}"),
+            InstKind::CharLen(c) => write!(f, "char_len {c}"),
+            InstKind::Global(def) => write!(f, "global {}", program.name(*def)),
+            InstKind::Call(def, args) => {
+                write!(f, "call {}(", program.name(*def))?;
+                write_values(f, args)?;
+                write!(f, ")")
+            }
+            InstKind::Builtin(builtin, args) => {
+                write!(f, "builtin {}(", builtin.name())?;
+                write_values(f, args)?;
+                write!(f, ")")
+            }
+            InstKind::Alloc => write!(f, "alloc"),
+            InstKind::Load(ptr) => write!(f, "load {ptr}"),
+            InstKind::Store(ptr, value) => write!(f, "store {ptr}, {value}"),
+            InstKind::FieldAddr(ptr, idx) => write!(f, "field_addr {ptr}.{idx}"),
+            InstKind::IndexAddr(base, idx) => write!(f, "index_addr {base}[{idx}]"),
+            InstKind::Panic(message) => write!(f, "panic {message:?}"),
+        }
+    }
+}
+
+impl Program {
+    fn write_body(&self, f: &mut fmt::Formatter<'_>, body: &Body) -> fmt::Result {
+        match body.kind {
+            BodyKind::Fn => {
+                write!(f, "fn {}(", body.name)?;
+                for (idx, param) in body.params().iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{param}: {}", body.ty(*param))?;
+                }
+                write!(f, ")")?;
+                if !body.ret.is_unit() {
+                    write!(f, ": {}", body.ret)?;
+                }
+            }
+            BodyKind::Const => write!(f, "const {}: {}", body.name, body.ret)?,
+        }
+        writeln!(f, " {{")?;
+
+        for (idx, block) in body.blocks.iter().enumerate() {
+            write!(f, "{}", BlockId(idx as u32))?;
+            if idx != 0 && !block.params.is_empty() {
+                write!(f, "(")?;
+                for (idx, param) in block.params.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{param}: {}", body.ty(*param))?;
+                }
+                write!(f, ")")?;
+            }
+            writeln!(f, ":")?;
+            for inst in &block.insts {
+                match inst.result {
+                    Some(result) => writeln!(
+                        f,
+                        "    {result}: {} = {}",
+                        body.ty(result),
+                        ShowInst(self, &inst.kind)
+                    )?,
+                    None => writeln!(f, "    {}", ShowInst(self, &inst.kind))?,
+                }
+            }
+            writeln!(f, "    {}", block.term.node)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// The textual form of the program, one body after another.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, body) in self.bodies.iter().enumerate() {
+            if idx != 0 {
+                writeln!(f)?;
+            }
+            self.write_body(f, body)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Spannable, source::FileId};
+
+    fn span() -> Span {
+        Span::new(FileId(0), 0, 0)
+    }
+
+    fn inst(result: u32, kind: InstKind) -> Inst {
+        Inst {
+            result: Some(ValueId(result)),
+            kind,
+            span: span(),
+        }
+    }
+
+    /// `fn f(v0: i32): i32` jumping from its entry to a block returning
+    /// its parameter.
+    fn program(entry: Vec<Inst>, jump: Vec<u32>, values: Vec<Ty>) -> Program {
+        let int = Ty::Int(IntTy::I32);
+        let args = jump.into_iter().map(ValueId).collect();
+        let body = Body {
+            def: DefId(1),
+            kind: BodyKind::Fn,
+            name: "f".to_string(),
+            ret: int.clone(),
+            span: span(),
+            values,
+            blocks: vec![
+                Block {
+                    params: vec![ValueId(0)],
+                    insts: entry,
+                    term: Terminator::Jump(Target {
+                        block: BlockId(1),
+                        args,
+                    })
+                    .spanned(span()),
+                },
+                Block {
+                    params: vec![ValueId(1)],
+                    insts: Vec::new(),
+                    term: Terminator::Return(ValueId(1)).spanned(span()),
+                },
+            ],
+        };
+        Program {
+            bodies: vec![body],
+            main: None,
+            names: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_verify_and_print() {
+        let int = Ty::Int(IntTy::I32);
+        let add = InstKind::Binary(BinaryOp::Add, ValueId(0), ValueId(0));
+        let program = program(
+            vec![inst(2, add)],
+            vec![2],
+            vec![int.clone(), int.clone(), int],
+        );
+
+        assert!(program.verify().is_ok());
+        assert_eq!(
+            program.to_string(),
+            "fn f(v0: i32): i32 {\nbb0:\n    v2: i32 = add v0, v0\n    jump bb1(v2)\nbb1(v1: i32):\n    return v1\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_reject_wrong_arguments() {
+        let int = Ty::Int(IntTy::I32);
+        let program = program(Vec::new(), Vec::new(), vec![int.clone(), int]);
+
+        let err = program.verify().unwrap_err();
+        assert_eq!(err.block, Some(BlockId(0)));
+        assert_eq!(
+            err.to_string(),
+            "in `f` at bb0: bb1 takes 1 argument but gets 0"
+        );
+    }
+
+    #[test]
+    fn test_reject_use_before_definition() {
+        let int = Ty::Int(IntTy::I32);
+        let program = program(
+            vec![
+                inst(2, InstKind::Neg(ValueId(3))),
+                inst(3, InstKind::Neg(ValueId(0))),
+            ],
+            vec![2],
+            vec![int.clone(), int.clone(), int.clone(), int],
+        );
+
+        let err = program.verify().unwrap_err();
+        assert_eq!(err.message, "v3 is used where it may not be defined");
+    }
+
+    #[test]
+    fn test_reject_mismatched_types() {
+        let int = Ty::Int(IntTy::I32);
+        let program = program(
+            vec![inst(2, InstKind::Const(Value::Bool(true)))],
+            vec![2],
+            vec![int.clone(), int, Ty::Bool],
+        );
+
+        let err = program.verify().unwrap_err();
+        assert_eq!(err.message, "expected v2 to be i32 but it is bool");
+    }
+}
@@ -0,0 +1,375 @@
+use std::{collections::HashMap, io::Write, rc::Rc};
+
+use crate::{
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interp::{self, MAX_CALL_DEPTH},
+    mir::{BlockId, Body, Inst, InstKind, Program, Terminator, ValueId},
+    resolve::{DefId, Resolution},
+    span::Span,
+    ty::{IntTy, Ty},
+    typeck::TypeckResults,
+    value::{self, Builtin, Layout, Place, Value},
+};
+
+/// Runs a [`Program`] of the mid-level IR, usually one the
+/// [`Optimizer`](crate::opt::Optimizer) went over.
+///
+/// Each call keeps the values of its body in a vector indexed by
+/// [`ValueId`], and block arguments are copied into the parameters of the
+/// block they go to. Locations are cells shared through [`Place`]s, like
+/// the variables of the [`Interpreter`](crate::interp::Interpreter), so
+/// both print and fail the same way.
+pub struct Evaluator<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    program: &'a Program,
+    pub bag: DiagnosticsBag,
+    out: Box<dyn Write + 'a>,
+    globals: HashMap<DefId, Value>,
+    layouts: HashMap<DefId, Rc<Layout>>,
+    depth: usize,
+}
+
+type Eval<T = Value> = Result<T, Diagnostic>;
+
+impl<'a> Evaluator<'a> {
+    pub fn new(
+        res: &'a Resolution,
+        types: &'a TypeckResults,
+        program: &'a Program,
+        out: impl Write + 'a,
+    ) -> Self {
+        Self {
+            res,
+            types,
+            program,
+            bag: DiagnosticsBag::new(),
+            out: Box::new(out),
+            globals: HashMap::new(),
+            layouts: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    /// Runs the body of `main`.
+    pub fn run(&mut self) -> Option<Value> {
+        let program = self.program;
+        let Some(main) = program.main.and_then(|idx| program.bodies.get(idx)) else {
+            self.bag
+                .push(Diagnostic::error("`main` function not found"));
+            return None;
+        };
+
+        let result = self.call(main, Vec::new(), main.span);
+        let _ = self.out.flush();
+        match result {
+            Ok(value) => Some(value),
+            Err(diagnostic) => {
+                self.bag.push(diagnostic);
+                None
+            }
+        }
+    }
+
+    fn error<T>(&self, span: Span, message: impl Into<String>, label: &str) -> Eval<T> {
+        Err(Diagnostic::error(message).with_label(span.primary(label)))
+    }
+
+    fn call(&mut self, body: &'a Body, args: Vec<Value>, span: Span) -> Eval {
+        if self.depth >= MAX_CALL_DEPTH {
+            return self.error(
+                span,
+                format!("stack overflow: calls nested more than {MAX_CALL_DEPTH} deep"),
+                "while calling this",
+            );
+        }
+
+        self.depth += 1;
+        let result = self.body(body, args);
+        self.depth -= 1;
+        result
+    }
+
+    fn body(&mut self, body: &'a Body, args: Vec<Value>) -> Eval {
+        let mut values = vec![Value::Unit; body.values.len()];
+        for (param, arg) in body.params().iter().zip(args) {
+            values[param.0 as usize] = arg;
+        }
+
+        let mut block = BlockId(0);
+        loop {
+            let data = body.block(block);
+            for inst in &data.insts {
+                let value = self.inst(body, &values, inst)?;
+                if let Some(result) = inst.result {
+                    values[result.0 as usize] = value;
+                }
+            }
+
+            let get = |value: &ValueId| &values[value.0 as usize];
+            let target = match &data.term.node {
+                Terminator::Jump(target) => target,
+                Terminator::Branch { cond, then, else_ } => match get(cond).is_truthy() {
+                    true => then,
+                    false => else_,
+                },
+                Terminator::Switch {
+                    value,
+                    cases,
+                    default,
+                } => {
+                    let key = match get(value) {
+                        Value::Int(v, _) => *v,
+                        Value::Bool(b) => *b as i128,
+                        Value::Char(c) => *c as i128,
+                        _ => -1,
+                    };
+                    cases
+                        .iter()
+                        .find(|(case, _)| *case == key)
+                        .map_or(default, |(_, target)| target)
+                }
+                Terminator::Return(value) => return Ok(get(value).clone()),
+                Terminator::Unreachable => {
+                    return self.error(data.term.span, "entered unreachable code", "here");
+                }
+            };
+
+            let args: Vec<Value> = target.args.iter().map(|arg| get(arg).clone()).collect();
+            for (param, arg) in body.block(target.block).params.iter().zip(args) {
+                values[param.0 as usize] = arg;
+            }
+            block = target.block;
+        }
+    }
+
+    fn inst(&mut self, body: &'a Body, values: &[Value], inst: &Inst) -> Eval {
+        let get = |value: &ValueId| values[value.0 as usize].clone();
+        let span = inst.span;
+        let result_ty = || inst.result.map_or(&Ty::Never, |result| body.ty(result));
+
+        Ok(match &inst.kind {
+            InstKind::Const(value) => value.clone(),
+            InstKind::Undef => Value::Unit,
+            InstKind::Binary(op, a, b) => value::binary(*op, &get(a), &get(b))
+                .or_else(|err| self.error(span, err, "in this operation"))?,
+            InstKind::Neg(v) => {
+                value::negate(&get(v)).or_else(|err| self.error(span, err, "negated here"))?
+            }
+            InstKind::Not(v) => value::not(&get(v)).or_else(|err| self.error(span, err, "here"))?,
+            InstKind::Cast(v) => value::cast(&get(v), result_ty()),
+            InstKind::Some(v) | InstKind::Unwrap(v) | InstKind::Unsize(v) => get(v),
+            InstKind::Tuple(elems) => Value::Tuple(elems.iter().map(get).collect()),
+            InstKind::Array(elems) => Value::Array(elems.iter().map(get).collect()),
+            InstKind::Adt(def, fields) => {
+                Value::Struct(self.layout(*def), fields.iter().map(get).collect())
+            }
+            InstKind::Field { base, idx, .. } => get(base)
+                .deref()
+                .elems()
+                .get(*idx as usize)
+                .cloned()
+                .unwrap_or(Value::Unit),
+            InstKind::Discriminant(v) => {
+                let tag = match (body.ty(*v), get(v)) {
+                    (Ty::Optional(_), Value::None) => 0,
+                    (Ty::Optional(_), _) => 1,
+                    (_, Value::Struct(layout, _)) => self.tag(DefId(layout.id)),
+                    _ => 0,
+                };
+                Value::Int(tag, IntTy::U32)
+            }
+            InstKind::Index(base, idx) => value::index(get(base).deref(), &get(idx))
+                .or_else(|err| self.error(span, err, "this index"))?,
+            InstKind::Slice { base, lo, hi } => {
+                let range = Value::Range {
+                    start: Some(Box::new(get(lo))),
+                    end: Some(Box::new(get(hi))),
+                    inclusive: false,
+                };
+                value::index(get(base).deref(), &range)
+                    .or_else(|err| self.error(span, err, "this index"))?
+            }
+            InstKind::Len(v) => {
+                let len = match get(v).deref() {
+                    Value::Array(elems) => elems.len(),
+                    Value::Str(s) => s.len(),
+                    _ => 0,
+                };
+                Value::Int(len as i128, IntTy::U64)
+            }
+            InstKind::CharAt(s, offset) => {
+                let (Value::Str(s), Some(offset)) = (get(s).deref(), get(offset).as_int()) else {
+                    return self.error(span, "cannot iterate over this value", "here");
+                };
+                match s
+                    .get(offset as usize..)
+                    .and_then(|rest| rest.chars().next())
+                {
+                    Some(c) => Value::Char(c),
+                    None => return self.error(span, "offset is not a character boundary", "here"),
+                }
+            }
+            InstKind::CharLen(c) => match get(c) {
+                Value::Char(c) => Value::Int(c.len_utf8() as i128, IntTy::U64),
+                _ => Value::Int(0, IntTy::U64),
+            },
+            InstKind::Global(def) => self.global(*def, span)?,
+            InstKind::Call(def, args) => match self.program.body(*def) {
+                Some(callee) => self.call(callee, args.iter().map(get).collect(), span)?,
+                None => return self.error(span, "function has no body", "called here"),
+            },
+            InstKind::Builtin(builtin, args) => {
+                self.builtin(*builtin, args.iter().map(get).collect(), span)?
+            }
+            InstKind::Alloc => Value::Ref(Place::temporary(Value::Unit)),
+            InstKind::Load(ptr) => self.place(get(ptr), span)?.get(),
+            InstKind::Store(ptr, value) => {
+                self.place(get(ptr), span)?.set(get(value));
+                Value::Unit
+            }
+            InstKind::FieldAddr(ptr, idx) => {
+                Value::Ref(self.place(get(ptr), span)?.project(*idx as usize))
+            }
+            InstKind::IndexAddr(base, idx) => {
+                let place = match get(base) {
+                    Value::Ref(place) => self.place(Value::Ref(place), span)?,
+                    value => Place::temporary(value),
+                };
+                let len = place.get().elems().len();
+                let idx = get(idx).as_int().unwrap_or(-1);
+                if idx < 0 || idx as usize >= len {
+                    return self.error(
+                        span,
+                        format!("index out of bounds: the length is {len} but the index is {idx}"),
+                        "this index",
+                    );
+                }
+                Value::Ref(place.project(idx as usize))
+            }
+            InstKind::Panic(message) => return self.error(span, message.clone(), "panicked here"),
+        })
+    }
+
+    /// The location a pointer value points to, following references to
+    /// references.
+    fn place(&self, ptr: Value, span: Span) -> Eval<Place> {
+        let Value::Ref(mut place) = ptr else {
+            return self.error(span, "not a reference", "here");
+        };
+        while let Value::Ref(inner) = place.get() {
+            place = inner;
+        }
+        Ok(place)
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: Vec<Value>, span: Span) -> Eval {
+        match builtin {
+            Builtin::Print | Builtin::Println => {
+                for arg in &args {
+                    let _ = write!(self.out, "{arg}");
+                }
+                if builtin == Builtin::Println {
+                    let _ = writeln!(self.out);
+                }
+                Ok(Value::Unit)
+            }
+            Builtin::Assert => match args.first() {
+                Some(Value::Bool(true)) => Ok(Value::Unit),
+                _ => self.error(span, "assertion failed", "this assertion does not hold"),
+            },
+        }
+    }
+
+    fn global(&mut self, def: DefId, span: Span) -> Eval {
+        if let Some(value) = self.globals.get(&def) {
+            return Ok(value.clone());
+        }
+        let Some(body) = self.program.body(def) else {
+            return self.error(span, "constant has no value", "here");
+        };
+        let value = self.call(body, Vec::new(), span)?;
+        self.globals.insert(def, value.clone());
+        Ok(value)
+    }
+
+    fn layout(&mut self, def: DefId) -> Rc<Layout> {
+        if let Some(layout) = self.layouts.get(&def) {
+            return layout.clone();
+        }
+
+        let layout = Rc::new(interp::layout(self.res, self.types, def));
+        self.layouts.insert(def, layout.clone());
+        layout
+    }
+
+    /// The position of a variant in its enum.
+    fn tag(&self, variant: DefId) -> i128 {
+        self.res
+            .def(variant)
+            .parent
+            .and_then(|parent| self.types.enums.get(&parent))
+            .and_then(|variants| variants.iter().position(|v| *v == variant))
+            .unwrap_or(0) as i128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lower::Lowerer,
+        testing::{self, with_big_stack},
+    };
+
+    /// Lowers and runs `src`, returning what it printed and its error.
+    fn run(src: &str) -> (String, Option<String>) {
+        with_big_stack(|| {
+            let checked = testing::check(src);
+            let mut lowerer = Lowerer::new(&checked.res, &checked.types);
+            let program = lowerer.lower_script(&checked.script);
+
+            let mut out = Vec::new();
+            let mut eval = Evaluator::new(&checked.res, &checked.types, &program, &mut out);
+            eval.run();
+            let error = eval.bag.0.first().map(|err| err.message.clone());
+            drop(eval);
+            (String::from_utf8(out).unwrap(), error)
+        })
+    }
+
+    #[test]
+    fn test_run_structs_and_references() {
+        let (out, error) = run("struct Point { x: i32, y: i32 }
+             enum Dir { Up, Right(i32) }
+             fn step(p: &mut Point, d: Dir) {
+                match d { Dir::Up => p.y += 1, Dir::Right(n) => p.x += n }
+             }
+             fn main() {
+                let mut p = Point { x: 0, y: 0 };
+                for d in [Dir::Up, Dir::Right(3), Dir::Up] { step(&mut p, d); }
+                let o: i32? = none;
+                println((p, Dir::Right(2), o));
+             }");
+
+        assert_eq!(out, "(Point { x: 3, y: 2 }, Dir::Right(2), none)\n");
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn test_report_runtime_errors() {
+        let (out, error) =
+            run("fn main() { let xs = [1, 2]; let i = 2; println(0); println(xs[i]); }");
+        assert_eq!(out, "0\n");
+        assert_eq!(
+            error.as_deref(),
+            Some("index out of bounds: the length is 2 but the index is 2")
+        );
+
+        let (_, error) = run("fn f(n: i32): i32 { f(n + 1) } fn main() { f(0); }");
+        assert_eq!(
+            error.as_deref(),
+            Some("stack overflow: calls nested more than 2000 deep")
+        );
+    }
+}
@@ -0,0 +1,1111 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{BinaryOp, Spannable},
+    mir::{
+        Block, BlockId, Body, BodyKind, InstKind, Program, Target, Terminator, ValueId, VerifyError,
+    },
+    resolve::DefId,
+    ty::Ty,
+    value::{self, Value},
+};
+
+/// The passes an [`Optimizer`] runs, each of which can be turned off on its
+/// own to find the one that miscompiles a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Passes {
+    /// replaces calls to small functions by their bodies
+    pub inline: bool,
+    /// evaluates operators on constants, and constant items with a constant
+    /// body
+    pub fold: bool,
+    /// forwards values through copies, fields of known aggregates and
+    /// identities like `x + 0`, and merges equal constants
+    pub propagate: bool,
+    /// folds constant branches and merges or skips trivial blocks
+    pub simplify_cfg: bool,
+    /// removes instructions and block parameters whose values are never
+    /// used, and stores to locations never read
+    pub dce: bool,
+}
+
+impl Passes {
+    pub const NAMES: [&'static str; 5] = ["inline", "fold", "propagate", "simplify-cfg", "dce"];
+
+    pub fn all() -> Self {
+        Self {
+            inline: true,
+            fold: true,
+            propagate: true,
+            simplify_cfg: true,
+            dce: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            inline: false,
+            fold: false,
+            propagate: false,
+            simplify_cfg: false,
+            dce: false,
+        }
+    }
+
+    /// Turns the pass called `name`, one of [`Passes::NAMES`], on or off.
+    /// Returns `false` if there is no such pass.
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        let flag = match name {
+            "inline" => &mut self.inline,
+            "fold" => &mut self.fold,
+            "propagate" => &mut self.propagate,
+            "simplify-cfg" => &mut self.simplify_cfg,
+            "dce" => &mut self.dce,
+            _ => return false,
+        };
+        *flag = enabled;
+        true
+    }
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Runs the enabled passes over a [`Program`] until they stop changing it.
+/// Inlining runs once, first, and the others after it in rounds.
+#[derive(Debug, Clone)]
+pub struct Optimizer {
+    pub passes: Passes,
+    /// the most instructions a function can have to be inlined
+    pub inline_limit: usize,
+    /// verifies the program after every pass, to report the first pass that
+    /// breaks it
+    pub verify: bool,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self {
+            passes: Passes::all(),
+            inline_limit: 24,
+            verify: cfg!(debug_assertions),
+        }
+    }
+}
+
+const MAX_ROUNDS: usize = 8;
+
+impl Optimizer {
+    pub fn new(passes: Passes) -> Self {
+        Self {
+            passes,
+            ..Self::default()
+        }
+    }
+
+    pub fn run(&self, program: &mut Program) -> Result<(), VerifyError> {
+        if self.passes.inline {
+            inline(program, self.inline_limit);
+            self.check(program, "inline")?;
+        }
+
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            if self.passes.fold {
+                let globals = globals(program);
+                changed |= self.each(program, "fold", |body| fold(body, &globals))?;
+            }
+            if self.passes.propagate {
+                changed |= self.each(program, "propagate", propagate)?;
+            }
+            if self.passes.simplify_cfg {
+                changed |= self.each(program, "simplify-cfg", simplify_cfg)?;
+            }
+            if self.passes.dce {
+                changed |= self.each(program, "dce", dce)?;
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn each(
+        &self,
+        program: &mut Program,
+        pass: &str,
+        mut run: impl FnMut(&mut Body) -> bool,
+    ) -> Result<bool, VerifyError> {
+        let mut changed = false;
+        for body in &mut program.bodies {
+            if run(body) {
+                body.compact();
+                changed = true;
+            }
+        }
+        self.check(program, pass)?;
+        Ok(changed)
+    }
+
+    fn check(&self, program: &Program, pass: &str) -> Result<(), VerifyError> {
+        if !self.verify {
+            return Ok(());
+        }
+        program.verify().map_err(|mut err| {
+            err.message = format!("after `{pass}`: {}", err.message);
+            err
+        })
+    }
+}
+
+// ---------------------------------------------------------------- helpers
+
+/// Where a value is defined.
+#[derive(Debug, Clone, Copy)]
+enum Def {
+    Param(BlockId, usize),
+    Inst(BlockId, usize),
+}
+
+fn definitions(body: &Body) -> HashMap<ValueId, Def> {
+    let mut defs = HashMap::new();
+    for (idx, block) in body.blocks.iter().enumerate() {
+        let id = BlockId(idx as u32);
+        for (pos, param) in block.params.iter().enumerate() {
+            defs.insert(*param, Def::Param(id, pos));
+        }
+        for (pos, inst) in block.insts.iter().enumerate() {
+            if let Some(result) = inst.result {
+                defs.insert(result, Def::Inst(id, pos));
+            }
+        }
+    }
+    defs
+}
+
+/// The instruction defining each value that one defines.
+fn producers(body: &Body) -> HashMap<ValueId, InstKind> {
+    body.blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| Some((inst.result?, inst.kind.clone())))
+        .collect()
+}
+
+fn constants(body: &Body) -> HashMap<ValueId, Value> {
+    producers(body)
+        .into_iter()
+        .filter_map(|(value, kind)| match kind {
+            InstKind::Const(constant) => Some((value, constant)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn int(value: i128, ty: &Ty) -> Option<Value> {
+    match ty {
+        Ty::Int(ty) => Some(Value::Int(value, *ty)),
+        _ => None,
+    }
+}
+
+/// The key a `switch` compares a constant by.
+fn switch_key(value: &Value) -> Option<i128> {
+    match value {
+        Value::Int(v, _) => Some(*v),
+        Value::Bool(b) => Some(*b as i128),
+        Value::Char(c) => Some(*c as i128),
+        _ => None,
+    }
+}
+
+/// Whether the instruction does anything but compute its result: writes
+/// memory, prints, calls a function or may panic.
+fn has_effects(values: &[Ty], kind: &InstKind) -> bool {
+    let ty = |value: &ValueId| &values[value.0 as usize];
+    match kind {
+        InstKind::Binary(op, a, _) => {
+            matches!(ty(a), Ty::Int(_))
+                && matches!(
+                    op,
+                    BinaryOp::Add
+                        | BinaryOp::Sub
+                        | BinaryOp::Mul
+                        | BinaryOp::Div
+                        | BinaryOp::Rem
+                        | BinaryOp::Shl
+                        | BinaryOp::Shr
+                )
+        }
+        InstKind::Neg(v) => matches!(ty(v), Ty::Int(_)),
+        InstKind::Index(..)
+        | InstKind::Slice { .. }
+        | InstKind::IndexAddr(..)
+        | InstKind::CharAt(..)
+        | InstKind::Global(_)
+        | InstKind::Call(..)
+        | InstKind::Builtin(..)
+        | InstKind::Store(..)
+        | InstKind::Panic(_) => true,
+        _ => false,
+    }
+}
+
+// ----------------------------------------------------------------- inline
+
+/// Inlines calls to functions no bigger than `limit` that do not call
+/// themselves. Callees are handled before their callers, so a caller sees
+/// them with their own calls already inlined.
+fn inline(program: &mut Program, limit: usize) {
+    let index: HashMap<DefId, usize> = program
+        .bodies
+        .iter()
+        .enumerate()
+        .map(|(idx, body)| (body.def, idx))
+        .collect();
+
+    let mut order = Vec::new();
+    let mut visited = vec![false; program.bodies.len()];
+    for root in 0..program.bodies.len() {
+        postorder(program, &index, root, &mut visited, &mut order);
+    }
+
+    for idx in order {
+        let mut body = program.bodies[idx].clone();
+        if inline_calls(&mut body, program, limit) {
+            body.compact();
+            program.bodies[idx] = body;
+        }
+    }
+}
+
+fn calls(body: &Body) -> impl Iterator<Item = DefId> + '_ {
+    body.blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match inst.kind {
+            InstKind::Call(def, _) => Some(def),
+            _ => None,
+        })
+}
+
+fn postorder(
+    program: &Program,
+    index: &HashMap<DefId, usize>,
+    idx: usize,
+    visited: &mut [bool],
+    order: &mut Vec<usize>,
+) {
+    if visited[idx] {
+        return;
+    }
+    visited[idx] = true;
+    for callee in calls(&program.bodies[idx]) {
+        if let Some(callee) = index.get(&callee) {
+            postorder(program, index, *callee, visited, order);
+        }
+    }
+    order.push(idx);
+}
+
+fn inlinable(caller: &Body, callee: &Body, limit: usize) -> bool {
+    callee.kind == BodyKind::Fn
+        && callee.def != caller.def
+        && callee
+            .blocks
+            .iter()
+            .map(|block| block.insts.len())
+            .sum::<usize>()
+            <= limit
+        && calls(callee).all(|def| def != callee.def)
+}
+
+fn inline_calls(body: &mut Body, program: &Program, limit: usize) -> bool {
+    let mut changed = false;
+    let mut work: Vec<BlockId> = (0..body.blocks.len() as u32).map(BlockId).collect();
+    while let Some(block) = work.pop() {
+        let site = body.block(block).insts.iter().position(|inst| {
+            matches!(&inst.kind, InstKind::Call(def, _)
+                if program.body(*def).is_some_and(|callee| inlinable(body, callee, limit)))
+        });
+        let Some(pos) = site else {
+            continue;
+        };
+        let InstKind::Call(def, args) = body.block(block).insts[pos].kind.clone() else {
+            continue;
+        };
+        let Some(callee) = program.body(def) else {
+            continue;
+        };
+        let cont = splice(body, block, pos, callee, &args);
+        work.push(cont);
+        changed = true;
+    }
+    changed
+}
+
+/// Replaces the call at `pos` in `block` by a copy of `callee`, moving the
+/// rest of the block into a new block that gets the result as a parameter.
+/// Returns that block.
+fn splice(body: &mut Body, block: BlockId, pos: usize, callee: &Body, args: &[ValueId]) -> BlockId {
+    let at = block.0 as usize;
+    let rest = body.blocks[at].insts.split_off(pos + 1);
+    let Some(call) = body.blocks[at].insts.pop() else {
+        return block;
+    };
+    let result = call.result.unwrap_or_else(|| {
+        body.values.push(callee.ret.clone());
+        ValueId(body.values.len() as u32 - 1)
+    });
+
+    let cont = BlockId(body.blocks.len() as u32);
+    let offset = cont.0 + 1;
+    let term = std::mem::replace(
+        &mut body.blocks[at].term,
+        Terminator::Jump(Target::new(BlockId(offset))).spanned(call.span),
+    );
+    body.blocks.push(Block {
+        params: vec![result],
+        insts: rest,
+        term,
+    });
+
+    let mut map: HashMap<ValueId, ValueId> = callee
+        .params()
+        .iter()
+        .copied()
+        .zip(args.iter().copied())
+        .collect();
+    let mut rename = |value: &mut ValueId| {
+        *value = *map.entry(*value).or_insert_with(|| {
+            body.values.push(callee.ty(*value).clone());
+            ValueId(body.values.len() as u32 - 1)
+        });
+    };
+
+    let mut copies = Vec::new();
+    for (idx, block) in callee.blocks.iter().enumerate() {
+        let mut block = block.clone();
+        if idx == 0 {
+            block.params.clear();
+        }
+        block.params.iter_mut().for_each(&mut rename);
+        for inst in &mut block.insts {
+            if let Some(result) = &mut inst.result {
+                rename(result);
+            }
+            inst.kind.operands_mut().into_iter().for_each(&mut rename);
+        }
+        block
+            .term
+            .node
+            .operands_mut()
+            .into_iter()
+            .for_each(&mut rename);
+        for target in block.term.node.targets_mut() {
+            target.block.0 += offset;
+        }
+        if let Terminator::Return(value) = block.term.node {
+            block.term.node = Terminator::Jump(Target {
+                block: cont,
+                args: vec![value],
+            });
+        }
+        copies.push(block);
+    }
+    body.blocks.extend(copies);
+    cont
+}
+
+// ------------------------------------------------------------------- fold
+
+/// The values of constant items whose body returns a constant.
+fn globals(program: &Program) -> HashMap<DefId, Value> {
+    program
+        .bodies
+        .iter()
+        .filter(|body| body.kind == BodyKind::Const && body.blocks.len() == 1)
+        .filter_map(|body| {
+            let block = &body.blocks[0];
+            let Terminator::Return(value) = block.term.node else {
+                return None;
+            };
+            block.insts.iter().find_map(|inst| match &inst.kind {
+                InstKind::Const(constant) if inst.result == Some(value) => {
+                    Some((body.def, constant.clone()))
+                }
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+/// Evaluates instructions whose operands are constants, leaving those that
+/// would panic to panic at runtime.
+fn fold(body: &mut Body, globals: &HashMap<DefId, Value>) -> bool {
+    let mut consts = constants(body);
+    let mut changed = false;
+    for idx in body.reverse_postorder() {
+        for pos in 0..body.block(idx).insts.len() {
+            let inst = &body.blocks[idx.0 as usize].insts[pos];
+            let Some(result) = inst.result else {
+                continue;
+            };
+            let get = |value: &ValueId| consts.get(value);
+            let folded = match &inst.kind {
+                InstKind::Binary(op, a, b) => match (get(a), get(b)) {
+                    (Some(a), Some(b)) => value::binary(*op, a, b).ok(),
+                    _ => None,
+                },
+                InstKind::Neg(v) => get(v).and_then(|v| value::negate(v).ok()),
+                InstKind::Not(v) => get(v).and_then(|v| value::not(v).ok()),
+                InstKind::Cast(v) => get(v).map(|v| value::cast(v, body.ty(result))),
+                InstKind::Len(v) => match get(v) {
+                    Some(Value::Str(s)) => int(s.len() as i128, body.ty(result)),
+                    _ => None,
+                },
+                InstKind::CharLen(v) => match get(v) {
+                    Some(Value::Char(c)) => int(c.len_utf8() as i128, body.ty(result)),
+                    _ => None,
+                },
+                InstKind::Discriminant(v) => match get(v) {
+                    Some(Value::None) => int(0, body.ty(result)),
+                    _ => None,
+                },
+                InstKind::Global(def) => globals.get(def).cloned(),
+                _ => None,
+            };
+            if let Some(folded) = folded {
+                consts.insert(result, folded.clone());
+                body.blocks[idx.0 as usize].insts[pos].kind = InstKind::Const(folded);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// -------------------------------------------------------------- propagate
+
+/// Forwards the uses of values that are copies of others, replaces values
+/// known from the shape of an aggregate by constants and merges equal
+/// constants into the entry block, so that block parameters every
+/// predecessor passes the same constant to are dropped.
+fn propagate(body: &mut Body) -> bool {
+    let producers = producers(body);
+    let consts = constants(body);
+    let is = |value: ValueId, expected: i128| {
+        consts.get(&value).and_then(Value::as_int) == Some(expected)
+    };
+
+    let mut aliases = HashMap::new();
+    let mut known = Vec::new();
+    for block in &body.blocks {
+        for inst in &block.insts {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            let ty = body.ty(result);
+            let same = |value: ValueId| (body.ty(value) == ty).then_some(value);
+            let forward = match &inst.kind {
+                InstKind::Cast(v) => same(*v),
+                InstKind::Binary(op, a, b) if matches!(ty, Ty::Int(_)) => match op {
+                    BinaryOp::Add | BinaryOp::BitOr | BinaryOp::BitXor if is(*b, 0) => same(*a),
+                    BinaryOp::Add | BinaryOp::BitOr | BinaryOp::BitXor if is(*a, 0) => same(*b),
+                    BinaryOp::Sub | BinaryOp::Shl | BinaryOp::Shr if is(*b, 0) => same(*a),
+                    BinaryOp::Mul | BinaryOp::Div if is(*b, 1) => same(*a),
+                    BinaryOp::Mul if is(*a, 1) => same(*b),
+                    BinaryOp::BitAnd | BinaryOp::BitOr if a == b => same(*a),
+                    _ => None,
+                },
+                InstKind::Field { base, variant, idx } => match producers.get(base) {
+                    Some(InstKind::Tuple(elems)) if variant.is_none() => {
+                        elems.get(*idx as usize).copied()
+                    }
+                    Some(InstKind::Adt(def, fields))
+                        if variant.is_none_or(|variant| variant == *def) =>
+                    {
+                        fields.get(*idx as usize).copied()
+                    }
+                    _ => None,
+                },
+                InstKind::Unwrap(v) => match producers.get(v) {
+                    Some(InstKind::Some(inner)) => Some(*inner),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(value) = forward {
+                aliases.insert(result, value);
+                continue;
+            }
+
+            let constant = match &inst.kind {
+                InstKind::Discriminant(v) => match producers.get(v) {
+                    Some(InstKind::Some(_)) => int(1, ty),
+                    _ => None,
+                },
+                InstKind::Len(v) => {
+                    let v = match producers.get(v) {
+                        Some(InstKind::Unsize(array)) => array,
+                        _ => v,
+                    };
+                    match body.ty(*v) {
+                        Ty::Array(_, n) => int(*n as i128, ty),
+                        Ty::Ref { inner, .. } => match &**inner {
+                            Ty::Array(_, n) => int(*n as i128, ty),
+                            _ => None,
+                        },
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some(constant) = constant {
+                known.push((result, constant));
+            }
+        }
+    }
+
+    let used: HashSet<ValueId> = body
+        .blocks
+        .iter()
+        .flat_map(|block| {
+            let insts = block.insts.iter().flat_map(|inst| inst.kind.operands());
+            insts.chain(block.term.node.operands())
+        })
+        .collect();
+    let mut changed = !known.is_empty() || aliases.keys().any(|value| used.contains(value));
+    let known: HashMap<ValueId, Value> = known.into_iter().collect();
+    for block in &mut body.blocks {
+        for inst in &mut block.insts {
+            if let Some(constant) = inst.result.and_then(|result| known.get(&result)) {
+                inst.kind = InstKind::Const(constant.clone());
+            }
+        }
+    }
+    body.replace_uses(&aliases);
+    changed |= hoist_constants(body);
+
+    let params = body
+        .blocks
+        .iter()
+        .map(|block| block.params.len())
+        .sum::<usize>();
+    body.prune_params();
+    changed
+        | (params
+            != body
+                .blocks
+                .iter()
+                .map(|block| block.params.len())
+                .sum::<usize>())
+}
+
+/// Moves every constant to the top of the entry block, keeping one of each
+/// type and value.
+fn hoist_constants(body: &mut Body) -> bool {
+    let mut first: HashMap<(String, String), ValueId> = HashMap::new();
+    let mut aliases = HashMap::new();
+    let mut hoisted = Vec::new();
+    let mut changed = false;
+
+    for (idx, block) in body.blocks.iter_mut().enumerate() {
+        let mut kept = Vec::with_capacity(block.insts.len());
+        for (pos, inst) in std::mem::take(&mut block.insts).into_iter().enumerate() {
+            let (Some(result), InstKind::Const(constant)) = (inst.result, &inst.kind) else {
+                kept.push(inst);
+                continue;
+            };
+            if idx != 0 || pos != hoisted.len() {
+                changed = true;
+            }
+            let key = (
+                body.values[result.0 as usize].to_string(),
+                format!("{constant:?}"),
+            );
+            match first.get(&key) {
+                Some(value) => {
+                    aliases.insert(result, *value);
+                }
+                None => {
+                    first.insert(key, result);
+                    hoisted.push(inst);
+                }
+            }
+        }
+        block.insts = kept;
+    }
+
+    if let Some(entry) = body.blocks.first_mut() {
+        hoisted.append(&mut entry.insts);
+        entry.insts = hoisted;
+    }
+    body.replace_uses(&aliases);
+    changed || !aliases.is_empty()
+}
+
+// ----------------------------------------------------------- simplify-cfg
+
+/// Turns branches on constants and branches with two equal targets into
+/// jumps, sends jumps to empty blocks straight on to where those go, and
+/// merges blocks into their only predecessor when it jumps to them.
+fn simplify_cfg(body: &mut Body) -> bool {
+    let mut changed = false;
+    loop {
+        let round = fold_branches(body) | skip_empty_blocks(body) | merge_blocks(body);
+        if !round {
+            break;
+        }
+        changed = true;
+    }
+    changed
+}
+
+fn fold_branches(body: &mut Body) -> bool {
+    let consts = constants(body);
+    let mut changed = false;
+    for block in &mut body.blocks {
+        let jump = match &block.term.node {
+            Terminator::Branch { cond, then, else_ } => match consts.get(cond) {
+                Some(Value::Bool(true)) => Some(then.clone()),
+                Some(Value::Bool(false)) => Some(else_.clone()),
+                _ if then == else_ => Some(then.clone()),
+                _ => None,
+            },
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+            } => match consts.get(value).and_then(switch_key) {
+                Some(key) => Some(
+                    cases
+                        .iter()
+                        .find(|(case, _)| *case == key)
+                        .map_or(default, |(_, target)| target)
+                        .clone(),
+                ),
+                None if cases.iter().all(|(_, target)| target == default) => Some(default.clone()),
+                None => None,
+            },
+            _ => None,
+        };
+        if let Some(target) = jump {
+            block.term.node = Terminator::Jump(target);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Redirects edges into blocks that only jump elsewhere to where they
+/// go, passing the arguments the skipped block would have. Blocks whose
+/// parameters are used past their own jump are kept.
+fn skip_empty_blocks(body: &mut Body) -> bool {
+    let mut uses: HashMap<ValueId, usize> = HashMap::new();
+    for block in &body.blocks {
+        let insts = block.insts.iter().flat_map(|inst| inst.kind.operands());
+        for value in insts.chain(block.term.node.operands()) {
+            *uses.entry(value).or_default() += 1;
+        }
+    }
+
+    let mut forward = HashMap::new();
+    for (idx, block) in body.blocks.iter().enumerate().skip(1) {
+        let Terminator::Jump(next) = &block.term.node else {
+            continue;
+        };
+        let local = |param: &ValueId| {
+            uses.get(param).copied().unwrap_or(0)
+                == next.args.iter().filter(|arg| *arg == param).count()
+        };
+        if block.insts.is_empty() && block.params.iter().all(local) {
+            forward.insert(BlockId(idx as u32), (block.params.clone(), next.clone()));
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut body.blocks {
+        for target in block.term.node.targets_mut() {
+            let mut current = target.clone();
+            let mut seen = HashSet::new();
+            while let Some((params, next)) = forward.get(&current.block) {
+                if !seen.insert(current.block) {
+                    current = target.clone();
+                    break;
+                }
+                let args: HashMap<ValueId, ValueId> = params
+                    .iter()
+                    .copied()
+                    .zip(current.args.iter().copied())
+                    .collect();
+                current = Target {
+                    block: next.block,
+                    args: next
+                        .args
+                        .iter()
+                        .map(|arg| *args.get(arg).unwrap_or(arg))
+                        .collect(),
+                };
+            }
+            if current != *target {
+                *target = current;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Appends blocks to the block jumping to them when that is their only
+/// predecessor.
+fn merge_blocks(body: &mut Body) -> bool {
+    let mut edges = vec![0usize; body.blocks.len()];
+    for block in &body.blocks {
+        for target in block.term.node.targets() {
+            edges[target.block.0 as usize] += 1;
+        }
+    }
+
+    let mut changed = false;
+    let mut aliases = HashMap::new();
+    for idx in body.reverse_postorder() {
+        let idx = idx.0 as usize;
+        while let Terminator::Jump(target) = &body.blocks[idx].term.node {
+            let next = target.block.0 as usize;
+            if next == 0 || next == idx || edges[next] != 1 {
+                break;
+            }
+            let target = target.clone();
+            let merged = std::mem::replace(
+                &mut body.blocks[next],
+                Block {
+                    params: Vec::new(),
+                    insts: Vec::new(),
+                    term: Terminator::Unreachable.spanned(body.span),
+                },
+            );
+            edges[next] = 0;
+            aliases.extend(merged.params.into_iter().zip(target.args));
+            let block = &mut body.blocks[idx];
+            block.insts.extend(merged.insts);
+            block.term = merged.term;
+            changed = true;
+        }
+    }
+    body.replace_uses(&aliases);
+    changed
+}
+
+// -------------------------------------------------------------------- dce
+
+/// Removes instructions without effects whose results are never used,
+/// block parameters that are only passed on to themselves, and locations
+/// that are only ever stored to.
+fn dce(body: &mut Body) -> bool {
+    let mut changed = dead_stores(body);
+    let defs = definitions(body);
+
+    let mut live = HashSet::new();
+    let mut work = Vec::new();
+    for block in &body.blocks {
+        for inst in &block.insts {
+            if has_effects(&body.values, &inst.kind) {
+                work.extend(inst.kind.operands());
+            }
+        }
+        let own = match &block.term.node {
+            Terminator::Branch { cond, .. } => Some(*cond),
+            Terminator::Switch { value, .. } | Terminator::Return(value) => Some(*value),
+            _ => None,
+        };
+        work.extend(own);
+    }
+    work.extend(body.params().iter().copied());
+
+    while let Some(value) = work.pop() {
+        if !live.insert(value) {
+            continue;
+        }
+        match defs.get(&value) {
+            Some(Def::Inst(block, pos)) => {
+                work.extend(body.block(*block).insts[*pos].kind.operands());
+            }
+            Some(Def::Param(block, pos)) => {
+                for source in &body.blocks {
+                    for target in source.term.node.targets() {
+                        if target.block == *block {
+                            work.push(target.args[*pos]);
+                        }
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+
+    let mut dead_params = Vec::new();
+    for (idx, block) in body.blocks.iter_mut().enumerate() {
+        let before = block.insts.len();
+        block.insts.retain(|inst| match inst.result {
+            Some(result) => live.contains(&result) || has_effects(&body.values, &inst.kind),
+            None => true,
+        });
+        changed |= block.insts.len() != before;
+        if idx != 0 {
+            for (pos, param) in block.params.iter().enumerate().rev() {
+                if !live.contains(param) {
+                    dead_params.push((BlockId(idx as u32), pos));
+                }
+            }
+        }
+    }
+
+    for (block, pos) in &dead_params {
+        body.blocks[block.0 as usize].params.remove(*pos);
+        for source in &mut body.blocks {
+            for target in source.term.node.targets_mut() {
+                if target.block == *block {
+                    target.args.remove(*pos);
+                }
+            }
+        }
+    }
+    changed || !dead_params.is_empty()
+}
+
+/// Removes the locations whose only uses are stores to them, with the
+/// stores.
+fn dead_stores(body: &mut Body) -> bool {
+    let mut allocs: HashSet<ValueId> = body
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match inst.kind {
+            InstKind::Alloc => inst.result,
+            _ => None,
+        })
+        .collect();
+    for block in &body.blocks {
+        for inst in &block.insts {
+            let operands = match &inst.kind {
+                InstKind::Store(_, value) => vec![*value],
+                kind => kind.operands(),
+            };
+            for value in operands {
+                allocs.remove(&value);
+            }
+        }
+        for value in block.term.node.operands() {
+            allocs.remove(&value);
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut body.blocks {
+        block.insts.retain(|inst| {
+            let dead = match &inst.kind {
+                InstKind::Store(ptr, _) => allocs.contains(ptr),
+                _ => inst.result.is_some_and(|result| allocs.contains(&result)),
+            };
+            changed |= dead;
+            !dead
+        });
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lower::Lowerer, mireval::Evaluator, testing};
+
+    struct Outcome {
+        program: Program,
+        /// what the interpreter printed, and its error if it failed
+        expected: (String, Option<String>),
+        /// what the optimized program printed, and its error if it failed
+        actual: (String, Option<String>),
+    }
+
+    /// Lowers and optimizes `src` with `passes`, verifying the program after
+    /// each pass, then runs it next to the interpreter.
+    fn run(src: &str, passes: Passes) -> Outcome {
+        let checked = testing::check(src);
+        let (res, types) = (&checked.res, &checked.types);
+        let mut lowerer = Lowerer::new(res, types);
+        let mut program = lowerer.lower_script(&checked.script);
+        assert!(
+            lowerer.bag.is_empty(),
+            "lowering errors: {:?}",
+            lowerer.bag.0
+        );
+
+        let optimizer = Optimizer {
+            verify: true,
+            ..Optimizer::new(passes)
+        };
+        if let Err(err) = optimizer.run(&mut program) {
+            panic!("{err}\n{program}");
+        }
+
+        let (out, errors) = checked.interpret();
+        let expected = (out, errors.first().map(|err| err.message.clone()));
+
+        let mut out = Vec::new();
+        let mut eval = Evaluator::new(res, types, &program, &mut out);
+        eval.run();
+        let error = eval.bag.0.first().map(|err| err.message.clone());
+        drop(eval);
+        let actual = (String::from_utf8(out).unwrap(), error);
+
+        Outcome {
+            program,
+            expected,
+            actual,
+        }
+    }
+
+    /// Checks that `src` behaves the same with every pass on, with each
+    /// pass on its own, and with none.
+    fn assert_agrees(src: &str) -> Outcome {
+        let mut configs = vec![Passes::none()];
+        for name in Passes::NAMES {
+            let mut passes = Passes::none();
+            passes.set(name, true);
+            configs.push(passes);
+        }
+        for passes in configs {
+            let outcome = run(src, passes);
+            assert_eq!(
+                outcome.actual, outcome.expected,
+                "with {passes:?}\n{}",
+                outcome.program
+            );
+        }
+        let outcome = run(src, Passes::all());
+        assert_eq!(outcome.actual, outcome.expected, "\n{}", outcome.program);
+        outcome
+    }
+
+    #[test]
+    fn test_passes_preserve_behaviour() {
+        let outcome = assert_agrees(
+            "fn note(tag: i32, v: i32): i32 { print(tag); v }
+             fn sub(a: i32, b: i32): i32 { a - b }
+             fn first(a: i32, b: i32): i32 { a }
+             fn main() {
+                println((sub(note(1, 10), note(2, 3)), first(note(3, 5), note(4, 6))));
+                let mut x = 1;
+                let mut i = 0;
+                while i < 5 { x = x * 3; i += 1; }
+                let y = if x > 100 { x - 100 } else { 0 };
+                let mut z = 0;
+                if false { z = 99; } else { z = y + 1; }
+                let unused = note(5, 0);
+                let mut k = 0;
+                loop { k += 2; if k >= 6 { break; } }
+                println((x, y, z, k));
+                let big: i32 = 2147483647;
+                let lost = big + x;
+                println(0);
+             }",
+        );
+        // arguments still run in order once inlined, a value changed in a
+        // loop is not propagated out of it, and dead code keeps its effects
+        assert_eq!(
+            outcome.expected,
+            (
+                "1234(7, 5)\n5(243, 143, 144, 6)\n".to_owned(),
+                Some("attempt to add with overflow".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn test_fold_respects_overflow() {
+        let outcome = run(
+            "fn main() {
+                let a: u8 = 200;
+                println(a + 50);
+                println(a + 100);
+             }",
+            Passes::all(),
+        );
+        let dump = outcome.program.to_string();
+
+        assert!(dump.contains("u8 = const 250"));
+        assert!(dump.contains("u8 = add"));
+        assert_eq!(
+            outcome.actual,
+            (
+                "250\n".to_owned(),
+                Some("attempt to add with overflow".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn test_shift_by_any_integer_type() {
+        let outcome = assert_agrees(
+            "fn main() {
+                let s: u32 = 1;
+                println(s << 31);
+                let mut x: u16 = 1;
+                x <<= 3;
+                let by: i64 = 2;
+                println(x >> by);
+                let k: u8 = 40;
+                println(s << k);
+             }",
+        );
+        assert_eq!(
+            outcome.actual,
+            (
+                "2147483648\n2\n".to_owned(),
+                Some("attempt to shift left with overflow".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn test_inline_small_functions() {
+        let src = "fn add(a: i32, b: i32): i32 { a + b }
+             fn fact(n: i32): i32 { if n <= 1 { 1 } else { n * fact(n - 1) } }
+             fn main() { println((add(1, 2), fact(5))); }";
+        let outcome = run(src, Passes::all());
+        let main = &outcome.program.bodies[outcome.program.main.unwrap()];
+        let main = main.blocks.iter().flat_map(|block| &block.insts);
+        let callees: Vec<_> = main
+            .filter_map(|inst| match inst.kind {
+                InstKind::Call(def, _) => Some(outcome.program.names[&def].as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(callees, ["fact"]);
+        assert_eq!(outcome.actual, ("(3, 120)\n".to_owned(), None));
+    }
+
+    #[test]
+    fn test_disabled_passes_leave_the_program_alone() {
+        let src = "fn main() { let x = 1 + 2; if x > 2 { println(x); } }";
+        let unoptimized = run(src, Passes::none());
+        let mut passes = Passes::all();
+        for name in Passes::NAMES {
+            assert!(passes.set(name, false));
+        }
+        assert!(!passes.set("unroll", true));
+
+        assert_eq!(passes, Passes::none());
+        assert!(unoptimized.program.to_string().contains("add v"));
+        assert_eq!(
+            run(src, passes).program.to_string(),
+            unoptimized.program.to_string()
+        );
+    }
+}
@@ -0,0 +1,2046 @@
+use std::{collections::VecDeque, mem, path::Path as FsPath};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ClosureParam, ConstItem, DefineItem, Docs, EnumItem, EnumVariants,
+        Expr, Field, FieldInit, FieldPattern, FnItem, ImportItem, Item, Literal, MatchArm,
+        OpenItem, Path, Pattern, PostfixOp, PrefixOp, Script, Section, SectionItem, Spannable,
+        Spanned, Stmt, StructItem, Visibility,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    lexer::Lexer,
+    precedence::{Associativity, Precedence},
+    source::{FileId, Source},
+    span::Span,
+    tokens::{Token, TokenKind},
+    ty::Ty,
+    unicode,
+};
+
+#[derive(Debug, Clone)]
+pub struct Parser<'src> {
+    pub lexer: Lexer<'src>,
+    pub name: &'src str,
+    pub token: Token,
+    pub prev: Token,
+    pub bag: DiagnosticsBag,
+    lookahead: VecDeque<Token>,
+    /// set while parsing `if`/`while`/`match` heads, where `Path {` opens a block
+    no_struct: bool,
+}
+
+impl<'src> Parser<'src> {
+    pub fn new(id: FileId, source: &'src Source) -> Self {
+        let mut lexer = Lexer::new(id, source);
+        let token = lexer.next_token();
+        let name = FsPath::new(&source.name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&source.name);
+
+        Self {
+            lexer,
+            name,
+            token,
+            prev: token,
+            bag: DiagnosticsBag::new(),
+            lookahead: VecDeque::new(),
+            no_struct: false,
+        }
+    }
+
+    pub fn parse_script(&mut self) -> Script {
+        let mut name = self.name.intern().spanned(Span::new(self.lexer.id, 0, 0));
+        let mut docs = self.parse_docs(TokenKind::InnerDocComment);
+
+        if self.accept(TokenKind::Script).is_some() {
+            if let Some(ident) = self.expect_ident() {
+                name = ident;
+            }
+            self.expect(TokenKind::Semi);
+            docs.extend(self.parse_docs(TokenKind::InnerDocComment));
+        }
+
+        let items = self.parse_items(TokenKind::Eof);
+        self.finish();
+
+        Script {
+            root: Section { name, docs, items },
+        }
+    }
+
+    /// Moves the lexer diagnostics in front of the parser ones, so that
+    /// errors are reported in the order they were caused.
+    fn finish(&mut self) {
+        let mut bag = mem::take(&mut self.lexer.bag);
+        bag.extend(mem::take(&mut self.bag));
+        self.bag = bag;
+    }
+
+    fn parse_items(&mut self, end: TokenKind) -> Vec<Item> {
+        let mut items = Vec::new();
+
+        while !self.check(end) && !self.check(TokenKind::Eof) {
+            let start = self.token.span;
+
+            match self.parse_item() {
+                Some(item) => items.push(item),
+                None => {
+                    self.synchronize(ITEM_START);
+                    self.ensure_progress(start);
+                    items.push(Item::Error(self.error_span(start)));
+                }
+            }
+        }
+
+        items
+    }
+
+    pub fn parse_item(&mut self) -> Option<Item> {
+        let docs = self.parse_docs(TokenKind::DocComment);
+        if let Some(last) = docs.last()
+            && !ITEM_START.contains(&self.token.kind)
+        {
+            self.bag.push(
+                Diagnostic::error("expected item after doc comment")
+                    .with_label(last.span.primary("this documents nothing")),
+            );
+            return None;
+        }
+
+        let vis = self.parse_vis();
+
+        match self.token.kind {
+            TokenKind::Fn => self.parse_fn(docs, vis).map(Item::Fn),
+            TokenKind::Struct => self.parse_struct(docs, vis).map(Item::Struct),
+            TokenKind::Enum => self.parse_enum(docs, vis).map(Item::Enum),
+            TokenKind::Section => self.parse_section(docs, vis).map(Item::Section),
+            TokenKind::Const => self.parse_const(docs, vis).map(Item::Const),
+            TokenKind::Define => {
+                self.bump();
+                match self.token.kind {
+                    TokenKind::Fn => self.parse_fn(docs, vis).map(DefineItem::Fn),
+                    TokenKind::Const => self.parse_const(docs, vis).map(DefineItem::Const),
+                    _ => self.unexpected("`fn` or `const`"),
+                }
+                .map(Item::Define)
+            }
+            TokenKind::Open => {
+                if vis == Visibility::Public {
+                    self.bag.push(
+                        Diagnostic::error("syntax error")
+                            .with_label(self.prev.span.primary("`open` cannot be public")),
+                    );
+                }
+                if let Some(first) = docs.first() {
+                    self.bag.push(
+                        Diagnostic::error("`open` cannot be documented")
+                            .with_label(first.span.primary("remove this doc comment")),
+                    );
+                }
+                self.parse_open().map(Item::Open)
+            }
+            _ => self.unexpected("item"),
+        }
+    }
+
+    /// A run of `kind` comments, `///` before an item or `//!` at the start
+    /// of a section. `//!` anywhere else is reported and skipped.
+    fn parse_docs(&mut self, kind: TokenKind) -> Docs {
+        let mut docs = Vec::new();
+
+        loop {
+            if let Some(token) = self.accept(kind) {
+                // both markers are three bytes long
+                let text = &self.text(token.span)[3..];
+                docs.push(text.intern().spanned(token.span));
+            } else if kind == TokenKind::DocComment
+                && let Some(token) = self.accept(TokenKind::InnerDocComment)
+            {
+                self.bag.push(
+                    Diagnostic::error("inner doc comments only go at the start of a section")
+                        .with_label(
+                            token
+                                .span
+                                .primary("use `///` to document the item after it"),
+                        ),
+                );
+            } else {
+                return docs;
+            }
+        }
+    }
+
+    fn parse_vis(&mut self) -> Visibility {
+        if self.accept(TokenKind::Pub).is_some() {
+            Visibility::Public
+        } else {
+            Visibility::Private
+        }
+    }
+
+    fn parse_fn(&mut self, docs: Docs, vis: Visibility) -> Option<FnItem> {
+        self.expect(TokenKind::Fn)?;
+        let name = self.expect_ident()?;
+
+        self.expect(TokenKind::LParen)?;
+        let params = self.parse_header_list(TokenKind::RParen, Self::parse_param)?;
+
+        let ret = match self.accept(TokenKind::Colon) {
+            Some(_) => Some(self.parse_ty()?),
+            None => None,
+        };
+        let body = self.parse_block()?.node;
+
+        Some(FnItem {
+            docs,
+            vis,
+            name,
+            params,
+            ret,
+            body,
+        })
+    }
+
+    fn parse_param(&mut self) -> Option<Spanned<Field>> {
+        let name = match self.accept(TokenKind::SelfLow) {
+            Some(token) => self.symbol(token).spanned(token.span),
+            None => self.expect_ident()?,
+        };
+
+        self.parse_field_ty(name)
+    }
+
+    fn parse_field(&mut self) -> Option<Spanned<Field>> {
+        let name = self.expect_ident()?;
+        self.parse_field_ty(name)
+    }
+
+    fn parse_field_ty(&mut self, name: Spanned<Symbol>) -> Option<Spanned<Field>> {
+        self.expect(TokenKind::Colon)?;
+        let ty = self.parse_ty()?;
+        let span = name.span + ty.span;
+
+        Some(Field { name, ty }.spanned(span))
+    }
+
+    fn parse_struct(&mut self, docs: Docs, vis: Visibility) -> Option<StructItem> {
+        self.expect(TokenKind::Struct)?;
+        let name = self.expect_ident()?;
+
+        self.expect(TokenKind::LBrace)?;
+        let fields = self.parse_header_list(TokenKind::RBrace, Self::parse_field)?;
+
+        Some(StructItem {
+            docs,
+            vis,
+            name,
+            fields,
+        })
+    }
+
+    fn parse_enum(&mut self, docs: Docs, vis: Visibility) -> Option<EnumItem> {
+        self.expect(TokenKind::Enum)?;
+        let name = self.expect_ident()?;
+
+        self.expect(TokenKind::LBrace)?;
+        let variants = self.parse_header_list(TokenKind::RBrace, Self::parse_variant)?;
+
+        Some(EnumItem {
+            docs,
+            vis,
+            name,
+            variants,
+        })
+    }
+
+    fn parse_variant(&mut self) -> Option<Spanned<EnumVariants>> {
+        let name = self.expect_ident()?;
+
+        let variant = if self.accept(TokenKind::LBrace).is_some() {
+            let fields = self.parse_header_list(TokenKind::RBrace, Self::parse_field)?;
+            EnumVariants::Struct { name, fields }
+        } else if self.accept(TokenKind::LParen).is_some() {
+            let types = self.parse_header_list(TokenKind::RParen, Self::parse_ty)?;
+            EnumVariants::Tuple { name, types }
+        } else {
+            EnumVariants::Tuple {
+                name,
+                types: Vec::new(),
+            }
+        };
+
+        Some(variant.spanned(name.span + self.prev.span))
+    }
+
+    fn parse_section(&mut self, docs: Docs, vis: Visibility) -> Option<SectionItem> {
+        let start = self.expect(TokenKind::Section)?.span;
+        let name = self.expect_ident()?;
+
+        if let Some(semi) = self.accept(TokenKind::Semi) {
+            return Some(SectionItem {
+                docs,
+                vis,
+                sections: Section {
+                    name,
+                    docs: Vec::new(),
+                    items: Vec::new(),
+                }
+                .spanned(start + semi.span),
+                external: true,
+            });
+        }
+
+        self.expect(TokenKind::LBrace)?;
+        let inner = self.parse_docs(TokenKind::InnerDocComment);
+        let items = self.parse_items(TokenKind::RBrace);
+        let end = self.expect(TokenKind::RBrace)?.span;
+
+        Some(SectionItem {
+            docs,
+            vis,
+            sections: Section {
+                name,
+                docs: inner,
+                items,
+            }
+            .spanned(start + end),
+            external: false,
+        })
+    }
+
+    fn parse_const(&mut self, docs: Docs, vis: Visibility) -> Option<ConstItem> {
+        self.expect(TokenKind::Const)?;
+        let name = self.expect_ident()?;
+
+        let ty = match self.accept(TokenKind::Colon) {
+            Some(_) => Some(self.parse_ty()?),
+            None => None,
+        };
+
+        self.expect(TokenKind::Eq)?;
+        let value = self.parse_expr()?;
+        self.expect_semi()?;
+
+        Some(ConstItem {
+            docs,
+            vis,
+            name,
+            ty,
+            value,
+        })
+    }
+
+    /// open a::b;
+    /// open a::b as c;
+    /// open a::*;
+    /// open a::{self, b, *};
+    fn parse_open(&mut self) -> Option<OpenItem> {
+        self.expect(TokenKind::Open)?;
+
+        let mut path = vec![self.parse_path_segment()?];
+        let mut imports = Vec::new();
+
+        while self.accept(TokenKind::ColonColon).is_some() {
+            match self.token.kind {
+                TokenKind::Star => {
+                    let token = self.bump();
+                    imports.push(ImportItem::Star.spanned(token.span));
+                    break;
+                }
+                TokenKind::LBrace => {
+                    self.bump();
+                    imports = self.parse_header_list(TokenKind::RBrace, Self::parse_import)?;
+                    break;
+                }
+                _ => path.push(self.parse_path_segment()?),
+            }
+        }
+
+        let alias = match self.accept(TokenKind::As) {
+            Some(token) => {
+                let alias = self.expect_ident()?;
+                if !imports.is_empty() {
+                    self.bag.push(Diagnostic::error("syntax error").with_label(
+                        (token.span + alias.span).primary("cannot alias a group import"),
+                    ));
+                }
+                Some(alias)
+            }
+            None => None,
+        };
+
+        self.expect_semi()?;
+
+        Some(OpenItem {
+            path,
+            alias,
+            imports,
+        })
+    }
+
+    fn parse_import(&mut self) -> Option<Spanned<ImportItem>> {
+        let token = self.token;
+        let import = match token.kind {
+            TokenKind::Star => ImportItem::Star,
+            TokenKind::SelfLow => ImportItem::SelfImport,
+            TokenKind::Ident => ImportItem::Ident(self.symbol(token)),
+            _ => return self.unexpected("import"),
+        };
+        self.bump();
+
+        Some(import.spanned(token.span))
+    }
+
+    fn parse_path_segment(&mut self) -> Option<Spanned<Symbol>> {
+        let token = self.token;
+        match token.kind {
+            TokenKind::Ident | TokenKind::Root | TokenKind::SelfLow | TokenKind::SelfUp => {
+                self.bump();
+                Some(self.symbol(token).spanned(token.span))
+            }
+            _ => self.unexpected("identifier"),
+        }
+    }
+
+    fn parse_path(&mut self) -> Option<Spanned<Path>> {
+        let mut path = vec![self.parse_path_segment()?];
+
+        while self.accept(TokenKind::ColonColon).is_some() {
+            path.push(self.parse_path_segment()?);
+        }
+
+        let span = path[0].span + path[path.len() - 1].span;
+        Some(path.spanned(span))
+    }
+
+    pub fn parse_ty(&mut self) -> Option<Spanned<Ty>> {
+        let start = self.token;
+
+        let mut ty = match start.kind {
+            TokenKind::And => {
+                self.bump();
+                let mutable = self.accept(TokenKind::Mut).is_some();
+                let inner = self.parse_ty()?;
+                Ty::Ref {
+                    mutable,
+                    inner: Box::new(inner.node),
+                }
+            }
+            TokenKind::LParen => {
+                self.bump();
+                let mut elems = self.parse_delimited(TokenKind::RParen, Self::parse_ty)?;
+
+                // `(T)` is a grouping, `(T,)` a one element tuple
+                let trailing = self.source_before(self.prev.span).ends_with(',');
+                if elems.len() == 1 && !trailing {
+                    elems.remove(0).node
+                } else {
+                    Ty::Tuple(elems.into_iter().map(|ty| ty.node).collect())
+                }
+            }
+            TokenKind::LBracket => {
+                self.bump();
+                let elem = Box::new(self.parse_ty()?.node);
+
+                let ty = if self.accept(TokenKind::Semi).is_some() {
+                    let len = self.expect(TokenKind::IntLiteral)?;
+                    match self.parse_literal(len)? {
+                        Literal::UInt(len, _) => Ty::Array(elem, len),
+                        _ => return self.unexpected("array length"),
+                    }
+                } else {
+                    Ty::Slice(elem)
+                };
+
+                self.expect(TokenKind::RBracket)?;
+                ty
+            }
+            TokenKind::Fn => {
+                self.bump();
+                self.expect(TokenKind::LParen)?;
+                let params = self.parse_delimited(TokenKind::RParen, Self::parse_ty)?;
+
+                let ret = match self.accept(TokenKind::Colon) {
+                    Some(_) => self.parse_ty()?.node,
+                    None => Ty::unit(),
+                };
+
+                Ty::Fn {
+                    params: params.into_iter().map(|ty| ty.node).collect(),
+                    ret: Box::new(ret),
+                }
+            }
+            TokenKind::Ident if self.text(start.span) == "_" => {
+                self.bump();
+                Ty::Infer
+            }
+            _ => {
+                let path = self.parse_path()?;
+                let primitive = match path.node.as_slice() {
+                    [name] => Ty::primitive(name.node.as_str()),
+                    _ => None,
+                };
+                let path = path.node.iter().map(|s| s.node).collect();
+
+                if let Some(primitive) = primitive {
+                    primitive
+                } else if self.accept(TokenKind::Lt).is_some() {
+                    let mut args = Vec::new();
+                    loop {
+                        args.push(self.parse_ty()?.node);
+                        if self.accept(TokenKind::Comma).is_none() {
+                            self.expect_gt()?;
+                            break;
+                        }
+                    }
+                    Ty::Generic { path, args }
+                } else {
+                    Ty::Path(path)
+                }
+            }
+        };
+
+        while self.accept(TokenKind::Question).is_some() {
+            ty = Ty::Optional(Box::new(ty));
+        }
+
+        Some(ty.spanned(start.span + self.prev.span))
+    }
+
+    /// Accepts a `>`, splitting `>>` so that `Vec<Vec<u8>>` closes twice.
+    fn expect_gt(&mut self) -> Option<Token> {
+        if self.check(TokenKind::RShift) {
+            let span = self.token.span;
+            self.prev = Token::new(TokenKind::Gt, Span::new(span.id, span.lo, span.lo + 1));
+            self.token = Token::new(TokenKind::Gt, Span::new(span.id, span.lo + 1, span.hi));
+            return Some(self.prev);
+        }
+
+        self.expect(TokenKind::Gt)
+    }
+
+    pub fn parse_block(&mut self) -> Option<Spanned<BlockExpr>> {
+        self.restricted(false, |p| {
+            let start = p.expect(TokenKind::LBrace)?.span;
+            let block = p.parse_stmts(TokenKind::RBrace, None);
+            let end = p.expect(TokenKind::RBrace)?.span;
+            Some(block.spanned(start + end))
+        })
+    }
+
+    /// Parses a chunk typed at the REPL: items and statements in any order,
+    /// up to the end of the input.
+    pub fn parse_input(&mut self) -> (Vec<Item>, BlockExpr) {
+        let mut items = Vec::new();
+        let block = self.parse_stmts(TokenKind::Eof, Some(&mut items));
+        self.finish();
+        (items, block)
+    }
+
+    /// Parses statements up to `close`, which is not consumed. With `items`,
+    /// items may appear between the statements and are collected there.
+    fn parse_stmts(&mut self, close: TokenKind, mut items: Option<&mut Vec<Item>>) -> BlockExpr {
+        let mut stmts = Vec::new();
+        let mut expr = None;
+
+        while !self.check(close) && !self.check(TokenKind::Eof) {
+            let stmt_start = self.token.span;
+
+            if let Some(items) = items.as_deref_mut()
+                && ITEM_START.contains(&self.token.kind)
+            {
+                match self.parse_item() {
+                    Some(item) => items.push(item),
+                    None => {
+                        self.synchronize(ITEM_START);
+                        self.ensure_progress(stmt_start);
+                        items.push(Item::Error(self.error_span(stmt_start)));
+                    }
+                }
+                continue;
+            }
+
+            let stmt = match self.token.kind {
+                TokenKind::Semi => {
+                    self.bump();
+                    continue;
+                }
+                TokenKind::Let => self.parse_let(),
+                TokenKind::Open => self.parse_open().map(Stmt::Open),
+                _ => match self.parse_stmt_expr() {
+                    Some(e) if self.accept(TokenKind::Semi).is_some() => Some(Stmt::Semi(e)),
+                    Some(e) if self.check(close) => {
+                        expr = Some(boxed(e));
+                        continue;
+                    }
+                    Some(e) if is_block_like(&e.node) => Some(Stmt::Expr(e)),
+                    Some(e) => self.expect_semi().map(|_| Stmt::Semi(e)),
+                    None => None,
+                },
+            };
+
+            match stmt {
+                Some(stmt) => stmts.push(stmt),
+                None => {
+                    self.synchronize(&[TokenKind::Semi, TokenKind::Let]);
+                    self.accept(TokenKind::Semi);
+                    self.ensure_progress(stmt_start);
+
+                    let span = self.error_span(stmt_start);
+                    stmts.push(Stmt::Semi(Expr::Error(span).spanned(span)));
+                }
+            }
+        }
+
+        BlockExpr { stmts, expr }
+    }
+
+    /// A statement that starts with a block-like expression ends with its
+    /// closing brace, so `loop {} *x = 1;` is two statements.
+    fn parse_stmt_expr(&mut self) -> Option<Spanned<Expr>> {
+        if !matches!(
+            self.token.kind,
+            TokenKind::If
+                | TokenKind::Loop
+                | TokenKind::While
+                | TokenKind::For
+                | TokenKind::Match
+                | TokenKind::LBrace
+        ) {
+            return self.parse_expr();
+        }
+
+        let expr = self.parse_primary()?;
+        if is_block_like(&expr.node) && !self.check(TokenKind::Dot) {
+            Some(expr)
+        } else {
+            self.parse_infix(expr, Precedence::Assignment)
+        }
+    }
+
+    fn parse_let(&mut self) -> Option<Stmt> {
+        self.expect(TokenKind::Let)?;
+        let mutable = self.accept(TokenKind::Mut).is_some();
+        let name = self.expect_ident()?;
+
+        let ty = match self.accept(TokenKind::Colon) {
+            Some(_) => Some(self.parse_ty()?),
+            None => None,
+        };
+
+        let value = match self.accept(TokenKind::Eq) {
+            Some(_) => Some(self.parse_expr()?),
+            None => None,
+        };
+
+        self.expect_semi()?;
+
+        Some(Stmt::Let {
+            mutable,
+            name,
+            ty,
+            value,
+        })
+    }
+
+    pub fn parse_expr(&mut self) -> Option<Spanned<Expr>> {
+        self.parse_expr_bp(Precedence::Assignment)
+    }
+
+    /// Parses an expression whose operators all bind at least as tightly as `min`.
+    fn parse_expr_bp(&mut self, min: Precedence) -> Option<Spanned<Expr>> {
+        let lhs = self.parse_prefix()?;
+        self.parse_infix(lhs, min)
+    }
+
+    fn parse_prefix(&mut self) -> Option<Spanned<Expr>> {
+        let token = self.token;
+
+        if matches!(token.kind, TokenKind::DotDot | TokenKind::DotDotEq) {
+            self.bump();
+            let end = self.parse_range_end()?;
+
+            return Some(
+                Expr::Range {
+                    start: None,
+                    end,
+                    inclusive: token.kind == TokenKind::DotDotEq,
+                }
+                .spanned(token.span + self.prev.span),
+            );
+        }
+
+        if matches!(token.kind, TokenKind::Pipe | TokenKind::PipePipe) {
+            return self.parse_closure();
+        }
+
+        let Ok(mut op) = PrefixOp::try_from(token.kind) else {
+            return self.parse_primary();
+        };
+
+        if op == PrefixOp::Ref && self.peek(1).kind == TokenKind::Mut {
+            self.bump();
+            op = PrefixOp::RefMut;
+        }
+
+        let op = op.spanned(token.span + self.bump().span);
+        let operand = self.parse_expr_bp(Precedence::Prefix)?;
+        let span = op.span + operand.span;
+
+        Some(Expr::Prefix(op, boxed(operand)).spanned(span))
+    }
+
+    /// A `|` in operand position opens a closure, `||` is one without parameters.
+    fn parse_closure(&mut self) -> Option<Spanned<Expr>> {
+        let start = self.token.span;
+
+        let params = match self.bump().kind {
+            TokenKind::PipePipe => Vec::new(),
+            _ => self.parse_delimited(TokenKind::Pipe, Self::parse_closure_param)?,
+        };
+
+        let (ret, body) = match self.accept(TokenKind::Colon) {
+            // with an explicit return type the body has to be a block
+            Some(_) => {
+                let ret = self.parse_ty()?;
+                let block = self.parse_block()?;
+                let span = block.span;
+                (Some(ret), Expr::Block(block).spanned(span))
+            }
+            None => (None, self.restricted(false, Self::parse_expr)?),
+        };
+
+        let span = start + body.span;
+        Some(
+            Expr::Closure {
+                params,
+                ret,
+                body: boxed(body),
+            }
+            .spanned(span),
+        )
+    }
+
+    fn parse_closure_param(&mut self) -> Option<Spanned<ClosureParam>> {
+        let name = self.expect_ident()?;
+
+        let ty = match self.accept(TokenKind::Colon) {
+            Some(_) => Some(self.parse_ty()?),
+            None => None,
+        };
+        let span = name.span + self.prev.span;
+
+        Some(ClosureParam { name, ty }.spanned(span))
+    }
+
+    fn parse_infix(&mut self, mut lhs: Spanned<Expr>, min: Precedence) -> Option<Spanned<Expr>> {
+        while let Some((prec, assoc)) = Precedence::of_infix(self.token.kind) {
+            if prec < min {
+                break;
+            }
+
+            let start = lhs.span;
+            let token = self.bump();
+            let expr = match prec {
+                Precedence::Postfix => {
+                    let op = PostfixOp::try_from(token.kind).ok()?;
+                    Expr::Postfix(boxed(lhs), op.spanned(token.span))
+                }
+                Precedence::FieldAccess => {
+                    let name = self.expect_ident()?;
+
+                    if self.accept(TokenKind::LParen).is_some() {
+                        let args = self.parse_args(TokenKind::RParen)?;
+                        Expr::MethodCall {
+                            receiver: boxed(lhs),
+                            method: name,
+                            args,
+                        }
+                    } else {
+                        Expr::Field(boxed(lhs), name)
+                    }
+                }
+                Precedence::Call => {
+                    let args = self.parse_args(TokenKind::RParen)?;
+                    Expr::Call(boxed(lhs), args)
+                }
+                Precedence::Index => {
+                    let index = self.restricted(false, Self::parse_expr)?;
+                    self.expect(TokenKind::RBracket)?;
+                    Expr::Index(boxed(lhs), boxed(index))
+                }
+                Precedence::As => {
+                    let ty = self.parse_ty()?;
+                    Expr::Cast(boxed(lhs), ty)
+                }
+                Precedence::Range => {
+                    let end = self.parse_range_end()?;
+                    Expr::Range {
+                        start: Some(boxed(lhs)),
+                        end,
+                        inclusive: token.kind == TokenKind::DotDotEq,
+                    }
+                }
+                Precedence::Assignment => {
+                    if !is_place(&lhs.node) {
+                        self.bag.push(
+                            Diagnostic::error("invalid assignment")
+                                .with_label(lhs.span.primary("cannot assign to this expression")),
+                        );
+                    }
+
+                    let value = self.parse_expr_bp(Precedence::Assignment)?;
+                    match BinaryOp::from_compound_assign(token.kind) {
+                        Some(op) => Expr::AssignEq {
+                            op: op.spanned(token.span),
+                            target: boxed(lhs),
+                            value: boxed(value),
+                        },
+                        None => Expr::Assign {
+                            target: boxed(lhs),
+                            value: boxed(value),
+                        },
+                    }
+                }
+                _ => {
+                    let rhs = match assoc {
+                        Associativity::Right => self.parse_expr_bp(prec)?,
+                        _ => self.parse_expr_bp(prec.next())?,
+                    };
+                    let op = BinaryOp::try_from(token.kind).ok()?.spanned(token.span);
+                    Expr::Binary(boxed(lhs), op, boxed(rhs))
+                }
+            };
+
+            lhs = expr.spanned(start + self.prev.span);
+
+            if assoc == Associativity::None
+                && let Some((next, _)) = Precedence::of_infix(self.token.kind)
+                && next == prec
+            {
+                self.bag.push(
+                    Diagnostic::error("syntax error").with_label(
+                        self.token
+                            .span
+                            .primary(format!("{} operators cannot be chained", self.token.kind)),
+                    ),
+                );
+                return None;
+            }
+        }
+
+        Some(lhs)
+    }
+
+    fn parse_args(&mut self, close: TokenKind) -> Option<Vec<Spanned<Expr>>> {
+        self.restricted(false, |p| {
+            p.parse_delimited(close, |p| Some(p.parse_expr_or_error(&[TokenKind::Comma])))
+        })
+    }
+
+    /// the optional upper bound of `a..`, `..b` and `a..=b`
+    fn parse_range_end(&mut self) -> Option<Option<Spanned<Box<Expr>>>> {
+        let block = self.no_struct && self.check(TokenKind::LBrace);
+        if self.token.kind.can_begin_expr() && !block {
+            Some(Some(boxed(self.parse_expr_bp(Precedence::Range.next())?)))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn parse_primary(&mut self) -> Option<Spanned<Expr>> {
+        let token = self.token;
+
+        match token.kind {
+            k if k.is_literal()
+                || matches!(k, TokenKind::True | TokenKind::False | TokenKind::None) =>
+            {
+                self.bump();
+                let literal = self.parse_literal(token)?;
+                Some(Expr::Literal(literal).spanned(token.span))
+            }
+            TokenKind::Ident | TokenKind::Root | TokenKind::SelfLow | TokenKind::SelfUp => {
+                self.parse_path_expr()
+            }
+            TokenKind::LParen => {
+                self.bump();
+                let mut elems = self.parse_args(TokenKind::RParen)?;
+                let span = token.span + self.prev.span;
+
+                // `(expr)` is a grouping, `(expr,)` a one element tuple
+                let trailing = self.source_before(self.prev.span).ends_with(',');
+                if elems.len() == 1 && !trailing {
+                    let inner = elems.remove(0);
+                    Some(inner.node.spanned(span))
+                } else {
+                    Some(Expr::Tuple(elems).spanned(span))
+                }
+            }
+            TokenKind::LBracket => {
+                self.bump();
+                let elems = self.parse_args(TokenKind::RBracket)?;
+                Some(Expr::Array(elems).spanned(token.span + self.prev.span))
+            }
+            TokenKind::LBrace => {
+                let block = self.parse_block()?;
+                let span = block.span;
+                Some(Expr::Block(block).spanned(span))
+            }
+            TokenKind::If => self.parse_if(),
+            TokenKind::Loop => {
+                self.bump();
+                let body = self.parse_block()?;
+                let span = token.span + body.span;
+                Some(Expr::Loop(body).spanned(span))
+            }
+            TokenKind::While => {
+                self.bump();
+                let cond = self.restricted(true, Self::parse_expr)?;
+                let body = self.parse_block()?;
+                let span = token.span + body.span;
+
+                Some(
+                    Expr::While {
+                        cond: boxed(cond),
+                        body: body.node,
+                    }
+                    .spanned(span),
+                )
+            }
+            TokenKind::For => {
+                self.bump();
+                let pattern = self.parse_pattern()?;
+                self.expect(TokenKind::In)?;
+                let iter = self.restricted(true, Self::parse_expr)?;
+                let body = self.parse_block()?;
+                let span = token.span + body.span;
+
+                Some(
+                    Expr::For {
+                        pattern,
+                        iter: boxed(iter),
+                        body: body.node,
+                    }
+                    .spanned(span),
+                )
+            }
+            TokenKind::Match => self.parse_match(),
+            TokenKind::Break => {
+                self.bump();
+                let value = self.parse_opt_operand()?;
+                Some(Expr::Break(value).spanned(token.span + self.prev.span))
+            }
+            TokenKind::Continue => {
+                self.bump();
+                Some(Expr::Continue.spanned(token.span))
+            }
+            TokenKind::Return => {
+                self.bump();
+                let value = self.parse_opt_operand()?;
+                Some(Expr::Return(value).spanned(token.span + self.prev.span))
+            }
+            _ => self.unexpected("expression"),
+        }
+    }
+
+    /// the optional value of `break` and `return`
+    fn parse_opt_operand(&mut self) -> Option<Option<Spanned<Box<Expr>>>> {
+        if self.token.kind.can_begin_expr() {
+            Some(Some(boxed(self.parse_expr()?)))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn parse_path_expr(&mut self) -> Option<Spanned<Expr>> {
+        let path = self.parse_path()?;
+
+        if !self.no_struct && self.check(TokenKind::LBrace) {
+            self.bump();
+            let fields = self.parse_delimited(TokenKind::RBrace, Self::parse_field_init)?;
+            let span = path.span + self.prev.span;
+
+            return Some(
+                Expr::StructInit {
+                    path: path.node,
+                    fields,
+                }
+                .spanned(span),
+            );
+        }
+
+        let span = path.span;
+        let mut path = path.node;
+        if path.len() == 1 {
+            Some(Expr::Variable(path.remove(0).node).spanned(span))
+        } else {
+            Some(Expr::Path(path).spanned(span))
+        }
+    }
+
+    fn parse_field_init(&mut self) -> Option<Spanned<FieldInit>> {
+        let name = self.expect_ident()?;
+
+        let value = match self.accept(TokenKind::Colon) {
+            Some(_) => self.parse_expr()?,
+            None => Expr::Variable(name.node).spanned(name.span),
+        };
+        let span = name.span + value.span;
+
+        Some(FieldInit { name, value }.spanned(span))
+    }
+
+    fn parse_if(&mut self) -> Option<Spanned<Expr>> {
+        let start = self.expect(TokenKind::If)?.span;
+        let cond = self.restricted(true, Self::parse_expr)?;
+        let then = self.parse_expr()?;
+
+        let else_ = match self.accept(TokenKind::Else) {
+            Some(_) => Some(boxed(self.parse_expr()?)),
+            None => None,
+        };
+
+        let span = start + self.prev.span;
+        Some(
+            Expr::If {
+                cond: boxed(cond),
+                then: boxed(then),
+                else_,
+            }
+            .spanned(span),
+        )
+    }
+
+    fn parse_match(&mut self) -> Option<Spanned<Expr>> {
+        let start = self.expect(TokenKind::Match)?.span;
+        let scrutinee = self.restricted(true, Self::parse_expr)?;
+
+        self.expect(TokenKind::LBrace)?;
+        let mut arms = Vec::new();
+
+        while self.accept(TokenKind::RBrace).is_none() {
+            if self.check(TokenKind::Eof) {
+                return self.unexpected(&TokenKind::RBrace.to_string());
+            }
+
+            let start = self.token.span;
+            let pattern = match self.parse_pattern() {
+                Some(pattern) => pattern,
+                None => {
+                    self.synchronize(&[TokenKind::FatArrow, TokenKind::Comma]);
+                    let span = self.error_span(start);
+                    Pattern::Error(span).spanned(span)
+                }
+            };
+
+            let body = if self.expect(TokenKind::FatArrow).is_some() {
+                self.restricted(false, |p| p.parse_expr_or_error(&[TokenKind::Comma]))
+            } else {
+                let start = self.token.span;
+                self.synchronize(&[TokenKind::Comma]);
+                let span = self.error_span(start);
+                Expr::Error(span).spanned(span)
+            };
+
+            let block_like = is_block_like(&body.node);
+            arms.push(MatchArm { pattern, body });
+
+            if self.accept(TokenKind::Comma).is_none() && !block_like {
+                self.expect(TokenKind::RBrace)?;
+                break;
+            }
+        }
+
+        Some(
+            Expr::Match {
+                scrutinee: boxed(scrutinee),
+                arms,
+            }
+            .spanned(start + self.prev.span),
+        )
+    }
+
+    pub fn parse_pattern(&mut self) -> Option<Spanned<Pattern>> {
+        let token = self.token;
+
+        match token.kind {
+            TokenKind::Ident if self.text(token.span) == "_" => {
+                self.bump();
+                Some(Pattern::Wildcard.spanned(token.span))
+            }
+            k if k.is_literal()
+                || matches!(k, TokenKind::True | TokenKind::False | TokenKind::None) =>
+            {
+                self.bump();
+                let literal = self.parse_literal(token)?;
+                Some(Pattern::Literal(literal.spanned(token.span)).spanned(token.span))
+            }
+            TokenKind::Ident | TokenKind::Root | TokenKind::SelfLow | TokenKind::SelfUp => {
+                let path = self.parse_path()?;
+
+                let pattern = if self.accept(TokenKind::LParen).is_some() {
+                    let elems = self.parse_delimited(TokenKind::RParen, Self::parse_pattern)?;
+                    Pattern::Tuple {
+                        path: path.node,
+                        elems,
+                    }
+                } else if self.accept(TokenKind::LBrace).is_some() {
+                    let fields =
+                        self.parse_delimited(TokenKind::RBrace, Self::parse_field_pattern)?;
+                    Pattern::Struct {
+                        path: path.node,
+                        fields,
+                    }
+                } else if path.node.len() == 1 {
+                    Pattern::Variable(path.node[0])
+                } else {
+                    Pattern::Tuple {
+                        path: path.node,
+                        elems: Vec::new(),
+                    }
+                };
+
+                Some(pattern.spanned(path.span + self.prev.span))
+            }
+            _ => self.unexpected("pattern"),
+        }
+    }
+
+    fn parse_field_pattern(&mut self) -> Option<Spanned<FieldPattern>> {
+        let name = self.expect_ident()?;
+
+        let pattern = match self.accept(TokenKind::Colon) {
+            Some(_) => self.parse_pattern()?,
+            None => Pattern::Variable(name).spanned(name.span),
+        };
+        let span = name.span + pattern.span;
+
+        Some(FieldPattern { name, pattern }.spanned(span))
+    }
+
+    fn parse_literal(&mut self, token: Token) -> Option<Literal> {
+        let literal = match token.kind {
+            TokenKind::True => Literal::Bool(true),
+            TokenKind::False => Literal::Bool(false),
+            TokenKind::None => Literal::None,
+            TokenKind::IntLiteral
+            | TokenKind::FloatLiteral
+            | TokenKind::CharLiteral
+            | TokenKind::StringLiteral => return self.lexer.cook(token),
+            _ => return self.unexpected("literal"),
+        };
+
+        Some(literal)
+    }
+
+    /// Parses `item, item, ...` up to and including `close`, allowing a trailing comma.
+    fn parse_delimited<T>(
+        &mut self,
+        close: TokenKind,
+        f: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        self.parse_list(close, false, f)
+    }
+
+    /// Like `parse_delimited`, for the lists of an item header, where an item
+    /// keyword starting a line more likely begins the next item than belongs
+    /// to a malformed element, so recovery stops there.
+    fn parse_header_list<T>(
+        &mut self,
+        close: TokenKind,
+        f: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        self.parse_list(close, true, f)
+    }
+
+    fn parse_list<T>(
+        &mut self,
+        close: TokenKind,
+        header: bool,
+        mut f: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        let mut items = Vec::new();
+
+        while self.accept(close).is_none() {
+            match f(self) {
+                Some(item) => items.push(item),
+                None => {
+                    // the element was already reported, resume silently
+                    if header {
+                        let stops = [&[TokenKind::Comma], ITEM_START].concat();
+                        self.synchronize(&stops);
+                        // `fn` also starts a type, as in `f: fn(i32): i32`
+                        while ITEM_START.contains(&self.token.kind) && !self.starts_line() {
+                            self.bump();
+                            self.synchronize(&stops);
+                        }
+                    } else {
+                        self.synchronize(&[TokenKind::Comma]);
+                    }
+                    if self.accept(TokenKind::Comma).is_none() {
+                        self.accept(close)?;
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            if self.accept(TokenKind::Comma).is_none() {
+                self.expect(close)?;
+                break;
+            }
+        }
+
+        Some(items)
+    }
+
+    /// Parses an expression, replacing it by `Expr::Error` up to the next
+    /// `stops` token if it is malformed.
+    fn parse_expr_or_error(&mut self, stops: &[TokenKind]) -> Spanned<Expr> {
+        let start = self.token.span;
+
+        match self.parse_expr() {
+            Some(expr) => expr,
+            None => {
+                self.synchronize(stops);
+                let span = self.error_span(start);
+                Expr::Error(span).spanned(span)
+            }
+        }
+    }
+
+    /// Skips tokens until one of `stops` at the current nesting level, or the
+    /// closing delimiter of the enclosing one. Neither is consumed.
+    fn synchronize(&mut self, stops: &[TokenKind]) {
+        let mut depth = 0usize;
+
+        loop {
+            let kind = self.token.kind;
+
+            match kind {
+                TokenKind::Eof => break,
+                _ if depth == 0 && stops.contains(&kind) => break,
+                TokenKind::LParen | TokenKind::LBrace | TokenKind::LBracket => depth += 1,
+                TokenKind::RParen | TokenKind::RBrace | TokenKind::RBracket => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                }
+                _ => (),
+            }
+
+            self.bump();
+        }
+    }
+
+    /// Whether the current token is the first on its line.
+    fn starts_line(&self) -> bool {
+        self.lexer.content[self.prev.span.hi..self.token.span.lo].contains('\n')
+    }
+
+    /// Skips the token recovery started at if nothing was consumed, so that
+    /// a token no rule accepts cannot stall the parser.
+    fn ensure_progress(&mut self, start: Span) {
+        if self.token.span == start && !self.token.is_eof() {
+            self.bump();
+        }
+    }
+
+    /// The span of the tokens consumed since `start`.
+    fn error_span(&self, start: Span) -> Span {
+        if self.prev.span.hi > start.lo {
+            start + self.prev.span
+        } else {
+            start
+        }
+    }
+
+    /// A missing `;` is reported, but the statement is kept when the next
+    /// one clearly starts on its own.
+    fn expect_semi(&mut self) -> Option<Token> {
+        if let Some(token) = self.accept(TokenKind::Semi) {
+            return Some(token);
+        }
+
+        let newline = self.starts_line();
+        let missing = self.unexpected::<Token>(&TokenKind::Semi.to_string());
+
+        if newline || matches!(self.token.kind, TokenKind::Let | TokenKind::RBrace) {
+            Some(self.prev)
+        } else {
+            missing
+        }
+    }
+
+    fn restricted<T>(&mut self, no_struct: bool, f: impl FnOnce(&mut Self) -> T) -> T {
+        let prev = mem::replace(&mut self.no_struct, no_struct);
+        let result = f(self);
+        self.no_struct = prev;
+        result
+    }
+
+    pub fn bump(&mut self) -> Token {
+        self.prev = self.token;
+        self.token = match self.lookahead.pop_front() {
+            Some(token) => token,
+            None => self.lexer.next_token(),
+        };
+        self.prev
+    }
+
+    /// Returns the token `n` positions after the current one.
+    pub fn peek(&mut self, n: usize) -> Token {
+        while self.lookahead.len() < n {
+            let token = self.lexer.next_token();
+            self.lookahead.push_back(token);
+        }
+
+        match n {
+            0 => self.token,
+            _ => self.lookahead[n - 1],
+        }
+    }
+
+    pub fn check(&self, kind: TokenKind) -> bool {
+        self.token.kind == kind
+    }
+
+    pub fn accept(&mut self, kind: TokenKind) -> Option<Token> {
+        if self.check(kind) {
+            Some(self.bump())
+        } else {
+            None
+        }
+    }
+
+    pub fn expect(&mut self, kind: TokenKind) -> Option<Token> {
+        match self.accept(kind) {
+            Some(token) => Some(token),
+            None => self.unexpected(&kind.to_string()),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Option<Spanned<Symbol>> {
+        let token = self.expect(TokenKind::Ident)?;
+        Some(self.symbol(token).spanned(token.span))
+    }
+
+    fn unexpected<T>(&mut self, expected: &str) -> Option<T> {
+        self.bag.push(
+            Diagnostic::error("syntax error").with_label(
+                self.token
+                    .span
+                    .primary(format!("expected {expected}, found {}", self.token.kind)),
+            ),
+        );
+        None
+    }
+
+    fn text(&self, span: Span) -> &'src str {
+        &self.lexer.content[span]
+    }
+
+    fn source_before(&self, span: Span) -> &'src str {
+        self.lexer.content[..span.lo].trim_end()
+    }
+
+    fn symbol(&self, token: Token) -> Symbol {
+        unicode::nfc(self.text(token.span)).intern()
+    }
+}
+
+/// Tokens item-level recovery resumes at.
+const ITEM_START: &[TokenKind] = &[
+    TokenKind::DocComment,
+    TokenKind::Fn,
+    TokenKind::Struct,
+    TokenKind::Enum,
+    TokenKind::Section,
+    TokenKind::Const,
+    TokenKind::Define,
+    TokenKind::Open,
+    TokenKind::Pub,
+];
+
+fn boxed(expr: Spanned<Expr>) -> Spanned<Box<Expr>> {
+    Box::new(expr.node).spanned(expr.span)
+}
+
+/// Expressions ending in a block do not need a `;` to be used as statements.
+pub fn is_block_like(expr: &Expr) -> bool {
+    match expr {
+        Expr::Block(_)
+        | Expr::Loop(_)
+        | Expr::While { .. }
+        | Expr::For { .. }
+        | Expr::Match { .. } => true,
+        Expr::If { then, else_, .. } => {
+            let last = else_.as_ref().unwrap_or(then);
+            is_block_like(&last.node)
+        }
+        _ => false,
+    }
+}
+
+/// Expressions that denote a memory location and can be assigned to.
+fn is_place(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Variable(_)
+            | Expr::Path(_)
+            | Expr::Field(..)
+            | Expr::Index(..)
+            | Expr::Prefix(
+                Spanned {
+                    node: PrefixOp::Deref,
+                    ..
+                },
+                _
+            )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::SpannedBox, source::SourceMap};
+
+    fn parse(src: &str) -> (Script, DiagnosticsBag) {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", src);
+        let mut parser = Parser::new(id, &map[id]);
+        let script = parser.parse_script();
+        (script, parser.bag)
+    }
+
+    fn tail(block: &BlockExpr) -> Option<&Expr> {
+        block.expr.as_ref().map(|e| e.node.as_ref())
+    }
+
+    fn parse_ok(src: &str) -> Script {
+        let (script, bag) = parse(src);
+        assert!(bag.is_empty(), "unexpected diagnostics: {:?}", bag.0);
+        script
+    }
+
+    fn parse_expr(src: &str) -> (Option<Spanned<Expr>>, DiagnosticsBag) {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", src);
+        let mut parser = Parser::new(id, &map[id]);
+        let expr = parser.parse_expr();
+        assert!(
+            !parser.bag.is_empty() || parser.token.is_eof(),
+            "trailing input in {src:?}"
+        );
+        (expr, parser.bag)
+    }
+
+    /// Renders an expression as an s-expression to check its nesting.
+    fn sexp(expr: &Expr) -> String {
+        let list = |head: String, items: Vec<String>| format!("({head} {})", items.join(" "));
+        let boxed = |e: &SpannedBox<Expr>| sexp(&e.node);
+
+        match expr {
+            Expr::Literal(Literal::UInt(v, _)) => v.to_string(),
+            Expr::Literal(l) => format!("{l:?}"),
+            Expr::Variable(s) => s.as_str().to_owned(),
+            Expr::Path(p) => p
+                .iter()
+                .map(|s| s.node.as_str())
+                .collect::<Vec<_>>()
+                .join("::"),
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => list(
+                if *inclusive { "..=" } else { ".." }.to_owned(),
+                [start, end]
+                    .iter()
+                    .map(|e| e.as_ref().map_or("_".to_owned(), boxed))
+                    .collect(),
+            ),
+            Expr::Binary(l, op, r) => list(format!("{:?}", op.node), vec![boxed(l), boxed(r)]),
+            Expr::Prefix(op, e) => list(format!("{:?}", op.node), vec![boxed(e)]),
+            Expr::Postfix(e, op) => list(format!("{:?}", op.node), vec![boxed(e)]),
+            Expr::Call(f, args) => list(
+                format!("call {}", boxed(f)),
+                args.iter().map(|a| sexp(&a.node)).collect(),
+            ),
+            Expr::Field(e, name) => list(".".to_owned(), vec![boxed(e), name.node.as_str().into()]),
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => list(
+                format!(".{}", method.node.as_str()),
+                std::iter::once(boxed(receiver))
+                    .chain(args.iter().map(|a| sexp(&a.node)))
+                    .collect(),
+            ),
+            Expr::Index(e, i) => list("[]".to_owned(), vec![boxed(e), boxed(i)]),
+            Expr::Closure { params, body, .. } => list(
+                "closure".to_owned(),
+                params
+                    .iter()
+                    .map(|p| p.node.name.node.as_str().to_owned())
+                    .chain(std::iter::once(boxed(body)))
+                    .collect(),
+            ),
+            Expr::Cast(e, ty) => list("Cast".to_owned(), vec![boxed(e), ty.node.to_string()]),
+            Expr::Assign { target, value } => {
+                list("=".to_owned(), vec![boxed(target), boxed(value)])
+            }
+            Expr::AssignEq { op, target, value } => {
+                list(format!("{:?}=", op.node), vec![boxed(target), boxed(value)])
+            }
+            other => format!("{other:?}"),
+        }
+    }
+
+    fn assert_expr(src: &str, expected: &str) {
+        let (expr, bag) = parse_expr(src);
+        assert!(bag.is_empty(), "unexpected diagnostics: {:?}", bag.0);
+        assert_eq!(sexp(&expr.unwrap().node), expected, "while parsing {src:?}");
+    }
+
+    fn assert_ty(src: &str, expected: &str) {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", src);
+        let mut parser = Parser::new(id, &map[id]);
+        let ty = parser.parse_ty();
+
+        assert!(
+            parser.bag.is_empty(),
+            "unexpected diagnostics: {:?}",
+            parser.bag.0
+        );
+        assert!(parser.token.is_eof(), "trailing input in {src:?}");
+        assert_eq!(ty.unwrap().node.to_string(), expected);
+    }
+
+    #[test]
+    fn test_parse_types() {
+        assert_ty("u8", "u8");
+        assert_ty("i64?", "i64?");
+        assert_ty("&mut str", "&mut str");
+        assert_ty("&[u8]", "&[u8]");
+        assert_ty("[f32; 4]", "[f32; 4]");
+        assert_ty("(u8, bool)", "(u8, bool)");
+        assert_ty("(char,)", "(char,)");
+        assert_ty("()", "()");
+        assert_ty("(u8)", "u8");
+        assert_ty("fn(u8, u8): u8", "fn(u8, u8): u8");
+        assert_ty("fn()", "fn()");
+        assert_ty("(&u8)?", "(&u8)?");
+        assert_ty("math::Vector", "math::Vector");
+        assert_ty("Map<str, Vec<u8>>", "Map<str, Vec<u8>>");
+        assert_ty("_", "_");
+    }
+
+    #[test]
+    fn test_parse_closures() {
+        assert_expr("|x, y| x + y", "(closure x y (Add x y))");
+        assert_expr("|| 0", "(closure 0)");
+        assert_expr("a | b", "(BitOr a b)");
+        assert_expr("a || b", "(Or a b)");
+        assert_expr("f(|x| x, || y)", "(call f (closure x x) (closure y))");
+        assert_expr("|f| || f", "(closure f (closure f))");
+    }
+
+    #[test]
+    fn test_parse_typed_closure() {
+        let script = parse_ok(
+            "fn main() {
+                let mul = |x, y| x + y;
+                let div = |x: u8, y: u8|: u8 { return x + y; };
+             }",
+        );
+
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        let Stmt::Let {
+            value: Some(div), ..
+        } = &main.body.stmts[1]
+        else {
+            panic!("expected let");
+        };
+        let Expr::Closure { params, ret, body } = &div.node else {
+            panic!("expected closure");
+        };
+        assert!(params.iter().all(|p| p.node.ty.is_some()));
+        assert_eq!(ret.as_ref().map(|t| t.node.to_string()), Some("u8".into()));
+        assert!(matches!(body.node.as_ref(), Expr::Block(_)));
+    }
+
+    #[test]
+    fn test_parse_for_loops() {
+        let script = parse_ok(
+            "fn main() {
+                for i in 1..10 { continue; }
+                for x in [1, 2, 3] { if x { break; } }
+                for Point { x, y } in points[1..=n] {}
+                return;
+             }",
+        );
+
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        let fors: Vec<_> = main
+            .body
+            .stmts
+            .iter()
+            .take(3)
+            .map(|stmt| match stmt {
+                Stmt::Expr(Spanned {
+                    node: Expr::For { pattern, iter, .. },
+                    ..
+                }) => (&pattern.node, iter.node.as_ref()),
+                other => panic!("expected for loop, found {other:?}"),
+            })
+            .collect();
+
+        assert!(matches!(
+            fors[0],
+            (Pattern::Variable(_), Expr::Range { .. })
+        ));
+        assert!(matches!(fors[1], (Pattern::Variable(_), Expr::Array(_))));
+        assert!(matches!(fors[2], (Pattern::Struct { .. }, Expr::Index(..))));
+    }
+
+    #[test]
+    fn test_parse_cast() {
+        assert_expr("x as u8 + 1", "(Add (Cast x u8) 1)");
+        assert_expr("-x as i64 as f64", "(Cast (Cast (Neg x) i64) f64)");
+    }
+
+    #[test]
+    fn test_parse_binary_precedence() {
+        assert_expr("a + b * c - d", "(Sub (Add a (Mul b c)) d)");
+        assert_expr("a % b * c", "(Mul (Rem a b) c)");
+        assert_expr(
+            "a || b && c | d ^ e & f",
+            "(Or a (And b (BitOr c (BitXor d (BitAnd e f)))))",
+        );
+        assert_expr("a == b << 1 + 2", "(Eq a (Shl b (Add 1 2)))");
+        assert_expr("a < b == c >= d", "(Eq (Lt a b) (Ge c d))");
+        assert_expr("(a + b) * c", "(Mul (Add a b) c)");
+    }
+
+    #[test]
+    fn test_parse_prefix_and_postfix() {
+        assert_expr("-a.b * !c", "(Mul (Neg (. a b)) (Not c))");
+        assert_expr("&mut self", "(RefMut self)");
+        assert_expr("*self", "(Deref self)");
+        assert_expr("-x++", "(Neg (Inc x))");
+        assert_expr("&a[0]--", "(Ref (Dec ([] a 0)))");
+    }
+
+    #[test]
+    fn test_parse_postfix_chain() {
+        assert_expr(
+            "self.method(1, 2).method2(10)[1..2]",
+            "([] (.method2 (.method self 1 2) 10) (.. 1 2))",
+        );
+        assert_expr("math::add(1)(2).x", "(. (call (call math::add 1) 2) x)");
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_expr("1..10", "(.. 1 10)");
+        assert_expr("1..=20", "(..= 1 20)");
+        assert_expr("index[1..]", "([] index (.. 1 _))");
+        assert_expr("index[..20]", "([] index (.. _ 20))");
+        assert_expr("..", "(.. _ _)");
+        assert_expr("a + 1..b * 2", "(.. (Add a 1) (Mul b 2))");
+    }
+
+    #[test]
+    fn test_parse_assignment() {
+        assert_expr("a = b = c", "(= a (= b c))");
+        assert_expr("x.y += 1 << 2", "(Add= (. x y) (Shl 1 2))");
+        assert_expr("x %= 2", "(Rem= x 2)");
+        assert_expr("*p = a..b", "(= (Deref p) (.. a b))");
+    }
+
+    #[test]
+    fn test_parse_rejects_chained_comparison() {
+        let (expr, bag) = parse_expr("a < b < c");
+        assert!(expr.is_none());
+        assert_eq!(bag.len(), 1);
+
+        let (_, bag) = parse_expr("1 = 2");
+        assert_eq!(bag.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_block_like_statement_ends_at_brace() {
+        let script = parse_ok("fn main() { loop {} *x = 1; match x {} - 1 }");
+
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        assert_eq!(main.body.stmts.len(), 3);
+        assert!(
+            matches!(&main.body.stmts[1], Stmt::Semi(e) if matches!(e.node, Expr::Assign { .. }))
+        );
+        assert!(matches!(tail(&main.body), Some(Expr::Prefix(..))));
+    }
+
+    #[test]
+    fn test_parse_fn_item() {
+        let script = parse_ok("fn add(x: Int, y: Int): Int { x } pub fn main() {}");
+
+        assert_eq!(script.root.name.node.as_str(), "test");
+        assert_eq!(script.root.items.len(), 2);
+
+        let Item::Fn(add) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        assert_eq!(add.name.node.as_str(), "add");
+        assert_eq!(add.vis, Visibility::Private);
+        assert_eq!(add.params.len(), 2);
+        assert!(add.ret.is_some());
+        assert!(matches!(tail(&add.body), Some(Expr::Variable(_))));
+
+        let Item::Fn(main) = &script.root.items[1] else {
+            panic!("expected fn item");
+        };
+        assert_eq!(main.vis, Visibility::Public);
+        assert!(main.params.is_empty());
+    }
+
+    #[test]
+    fn test_parse_struct_and_enum() {
+        let script = parse_ok(
+            "struct Point { x: Float, y: Float, }
+             enum Shape { Circle(Point, Float), Rect { min: Point, max: Point }, Empty }",
+        );
+
+        let Item::Struct(point) = &script.root.items[0] else {
+            panic!("expected struct item");
+        };
+        assert_eq!(point.fields.len(), 2);
+
+        let Item::Enum(shape) = &script.root.items[1] else {
+            panic!("expected enum item");
+        };
+        assert_eq!(shape.variants.len(), 3);
+        assert!(matches!(
+            &shape.variants[0].node,
+            EnumVariants::Tuple { types, .. } if types.len() == 2
+        ));
+        assert!(matches!(
+            &shape.variants[1].node,
+            EnumVariants::Struct { fields, .. } if fields.len() == 2
+        ));
+        assert!(matches!(
+            &shape.variants[2].node,
+            EnumVariants::Tuple { types, .. } if types.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_parse_nested_sections() {
+        let script = parse_ok(
+            "section math {
+                fn add(x: Int, y: Int): Int { x }
+                section internal { const identity = 0; }
+             }",
+        );
+
+        let Item::Section(math) = &script.root.items[0] else {
+            panic!("expected section item");
+        };
+        assert_eq!(math.sections.node.name.node.as_str(), "math");
+        assert_eq!(math.sections.node.items.len(), 2);
+
+        let Item::Section(internal) = &math.sections.node.items[1] else {
+            panic!("expected section item");
+        };
+        let Item::Const(identity) = &internal.sections.node.items[0] else {
+            panic!("expected const item");
+        };
+        assert!(identity.ty.is_none());
+        assert!(matches!(
+            identity.value.node,
+            Expr::Literal(Literal::UInt(0, None))
+        ));
+    }
+
+    #[test]
+    fn test_parse_external_section() {
+        let script = parse_ok("section math;\nsection util {}");
+
+        let Item::Section(math) = &script.root.items[0] else {
+            panic!("expected section item");
+        };
+        assert!(math.external);
+        assert!(math.sections.node.items.is_empty());
+
+        let Item::Section(util) = &script.root.items[1] else {
+            panic!("expected section item");
+        };
+        assert!(!util.external);
+    }
+
+    #[test]
+    fn test_parse_open_items() {
+        let script = parse_ok(
+            "open math::internal;
+             open math as m;
+             open math::*;
+             open root::math::{self, add, *};",
+        );
+
+        let opens: Vec<_> = script
+            .root
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Open(open) => open,
+                _ => panic!("expected open item"),
+            })
+            .collect();
+
+        assert_eq!(opens[0].path.len(), 2);
+        assert!(opens[0].imports.is_empty());
+        assert_eq!(opens[1].alias.map(|a| a.node.as_str()), Some("m"));
+        assert!(matches!(opens[2].imports[0].node, ImportItem::Star));
+        assert_eq!(opens[3].path[0].node.as_str(), "root");
+        assert!(matches!(opens[3].imports[0].node, ImportItem::SelfImport));
+        assert!(matches!(opens[3].imports[1].node, ImportItem::Ident(_)));
+        assert!(matches!(opens[3].imports[2].node, ImportItem::Star));
+    }
+
+    #[test]
+    fn test_parse_block_statements() {
+        let script = parse_ok(
+            "fn main() {
+                let x = 1;
+                let mut y: u8 = 'a';
+                loop { break x; }
+                if x { y } else { \"no\" };
+                match y {
+                    Shape::Circle(p, _) => p,
+                    Shape::Rect { min, max: m } => { m }
+                    0xff => none,
+                    z => z,
+                }
+             }",
+        );
+
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        let stmts = &main.body.stmts;
+        assert_eq!(stmts.len(), 4);
+        assert!(matches!(
+            &stmts[0],
+            Stmt::Let {
+                mutable: false,
+                ty: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &stmts[1],
+            Stmt::Let {
+                mutable: true,
+                ty: Some(_),
+                ..
+            }
+        ));
+        assert!(matches!(&stmts[2], Stmt::Expr(e) if matches!(e.node, Expr::Loop(_))));
+        assert!(matches!(&stmts[3], Stmt::Semi(e) if matches!(e.node, Expr::If { .. })));
+
+        let Some(Expr::Match { arms, .. }) = tail(&main.body) else {
+            panic!("expected trailing match");
+        };
+        assert_eq!(arms.len(), 4);
+        assert!(
+            matches!(&arms[0].pattern.node, Pattern::Tuple { path, elems } if path.len() == 2 && elems.len() == 2)
+        );
+        assert!(
+            matches!(&arms[1].pattern.node, Pattern::Struct { fields, .. } if fields.len() == 2)
+        );
+        assert!(matches!(
+            &arms[2].pattern.node,
+            Pattern::Literal(l) if matches!(l.node, Literal::UInt(255, None))
+        ));
+        assert!(matches!(&arms[3].pattern.node, Pattern::Variable(_)));
+    }
+
+    #[test]
+    fn test_parse_struct_init_restriction() {
+        let script = parse_ok("fn main() { while cond { Point { x: 1, y } } }");
+
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        let Some(Expr::While { cond, body }) = tail(&main.body) else {
+            panic!("expected while");
+        };
+        assert!(matches!(cond.node.as_ref(), Expr::Variable(_)));
+        assert!(matches!(
+            tail(body),
+            Some(Expr::StructInit { fields, .. }) if fields.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_recover_between_items() {
+        let (script, bag) = parse("fn a( {} ) struct B { x } fn c() {}");
+
+        assert_eq!(bag.len(), 3);
+        let items = &script.root.items;
+        assert_eq!(items.len(), 3);
+        assert!(matches!(items[0], Item::Error(_)));
+        assert!(matches!(&items[1], Item::Struct(b) if b.fields.is_empty()));
+        assert!(matches!(&items[2], Item::Fn(c) if c.name.node.as_str() == "c"));
+    }
+
+    #[test]
+    fn test_recover_at_item_after_broken_header() {
+        let (script, bag) =
+            parse("fn b( { }\nfn c() { 1 + ) ; }\nstruct D { f: + fn(i32), g: i32 }");
+
+        let labels: Vec<_> = bag
+            .0
+            .iter()
+            .map(|d| d.labels[0].message.clone().unwrap())
+            .collect();
+        assert_eq!(labels.len(), 3);
+        assert!(labels[0].starts_with("expected identifier"));
+        assert!(labels[1].starts_with("expected expression"));
+        assert!(labels[2].starts_with("expected identifier"));
+        let items = &script.root.items;
+        assert_eq!(items.len(), 3);
+        assert!(matches!(&items[1], Item::Fn(c) if c.name.node.as_str() == "c"));
+        assert!(matches!(&items[2], Item::Struct(d) if d.fields.len() == 1));
+    }
+
+    #[test]
+    fn test_recover_between_statements() {
+        let (script, bag) = parse("fn main() { let = 1; foo(1, +, 3); let y = { 2 3 }; }");
+
+        assert_eq!(bag.len(), 3);
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        let stmts = &main.body.stmts;
+        assert_eq!(stmts.len(), 3);
+        assert!(matches!(&stmts[0], Stmt::Semi(e) if matches!(e.node, Expr::Error(_))));
+        assert!(matches!(
+            &stmts[1],
+            Stmt::Semi(e) if matches!(&e.node, Expr::Call(_, args)
+                if args.len() == 3 && matches!(args[1].node, Expr::Error(_)))
+        ));
+        assert!(matches!(&stmts[2], Stmt::Let { value: Some(_), .. }));
+    }
+
+    #[test]
+    fn test_recover_in_match_arms() {
+        let (script, bag) = parse("fn main() { match x { + => 1, y => , z => z } }");
+
+        assert_eq!(bag.len(), 2);
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        let Some(Expr::Match { arms, .. }) = tail(&main.body) else {
+            panic!("expected trailing match");
+        };
+        assert_eq!(arms.len(), 3);
+        assert!(matches!(arms[0].pattern.node, Pattern::Error(_)));
+        assert!(matches!(arms[1].body.node, Expr::Error(_)));
+        assert!(matches!(arms[2].pattern.node, Pattern::Variable(_)));
+    }
+
+    #[test]
+    fn test_missing_semi_before_new_line_keeps_statement() {
+        let (script, bag) = parse("fn main() {\n  let x = 1\n  x = 2\n  x\n}");
+
+        assert_eq!(bag.len(), 2);
+        let Item::Fn(main) = &script.root.items[0] else {
+            panic!("expected fn item");
+        };
+        assert_eq!(main.body.stmts.len(), 2);
+        assert!(main.body.expr.is_some());
+    }
+
+    #[test]
+    fn test_recovery_terminates_on_garbage() {
+        for src in [
+            ")}]",
+            "fn",
+            "fn main() { ) ] } }",
+            "section a { fn b( } struct",
+            "fn main() { match { => } let",
+            "pub pub open ::;",
+        ] {
+            let (_, bag) = parse(src);
+            assert!(!bag.is_empty(), "expected diagnostics for {src:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_reports_missing_token() {
+        let (_, bag) = parse("fn main( {}");
+        assert_eq!(bag.len(), 1);
+    }
+
+    #[test]
+    fn test_identifiers_are_normalized() {
+        let script = parse_ok("fn cafe\u{0301}() {}");
+        let Item::Fn(item) = &script.root.items[0] else {
+            panic!("expected a function");
+        };
+        assert_eq!(item.name.node, "caf\u{00E9}".intern());
+    }
+
+    #[test]
+    fn test_doc_comments_attach_to_items() {
+        let script = parse_ok(
+            "//! the root\nscript demo;\n//! more\n\n/// adds\n/// two\npub fn add() {}\n\
+             section math { //! maths\n /// pi\n define const PI: f64 = 3.14; }",
+        );
+        let text = |docs: &[Spanned<Symbol>]| -> Vec<_> {
+            docs.iter()
+                .map(|line| line.node.as_str().to_string())
+                .collect()
+        };
+
+        assert_eq!(text(&script.root.docs), [" the root", " more"]);
+        assert_eq!(text(script.root.items[0].docs()), [" adds", " two"]);
+
+        let Item::Section(math) = &script.root.items[1] else {
+            panic!("expected a section");
+        };
+        assert!(math.docs.is_empty());
+        assert_eq!(text(&math.sections.node.docs), [" maths"]);
+        assert_eq!(text(math.sections.node.items[0].docs()), [" pi"]);
+    }
+
+    #[test]
+    fn test_misplaced_doc_comments() {
+        let (script, bag) = parse("fn f() {}\n/// nothing\n");
+        assert_eq!(script.root.items.len(), 2);
+        assert_eq!(bag.0[0].message, "expected item after doc comment");
+
+        let (script, bag) = parse("fn f() {}\n//! late\nfn g() {}");
+        assert_eq!(bag.len(), 1);
+        assert_eq!(
+            bag.0[0].message,
+            "inner doc comments only go at the start of a section"
+        );
+        assert!(script.root.items[1].docs().is_empty());
+
+        let (_, bag) = parse("/// a\nopen a::b;");
+        assert_eq!(bag.0[0].message, "`open` cannot be documented");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::tokens::TokenKind;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Precedence {
     /// =, +=, -=, etc
@@ -22,7 +24,7 @@ pub enum Precedence {
     Shift,
     /// +, -
     Addition,
-    /// * /
+    /// *, /, %
     Multiplication,
     /// as
     As,
@@ -43,3 +45,69 @@ pub enum Precedence {
     /// literals, idents
     Primary,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+    /// `a == b == c` and `a..b..c` are rejected
+    None,
+}
+
+impl Precedence {
+    /// The binding power of `kind` when it follows an operand.
+    pub fn of_infix(kind: TokenKind) -> Option<(Self, Associativity)> {
+        use Associativity::*;
+
+        let prec = match kind {
+            k if k.is_assignment() => (Self::Assignment, Right),
+            TokenKind::DotDot | TokenKind::DotDotEq => (Self::Range, None),
+            TokenKind::PipePipe => (Self::LogicalOr, Left),
+            TokenKind::AndAnd => (Self::LogicalAnd, Left),
+            TokenKind::Pipe => (Self::BitwiseOr, Left),
+            TokenKind::Caret => (Self::BitwiseXor, Left),
+            TokenKind::And => (Self::BitwiseAnd, Left),
+            TokenKind::EqEq | TokenKind::BangEq => (Self::Equality, None),
+            TokenKind::Lt | TokenKind::Gt | TokenKind::LtEq | TokenKind::GtEq => {
+                (Self::Comparison, None)
+            }
+            TokenKind::LShift | TokenKind::RShift => (Self::Shift, Left),
+            TokenKind::Plus | TokenKind::Minus => (Self::Addition, Left),
+            TokenKind::Star | TokenKind::Slash | TokenKind::Percent => (Self::Multiplication, Left),
+            TokenKind::As => (Self::As, Left),
+            TokenKind::PlusPlus | TokenKind::MinusMinus => (Self::Postfix, Left),
+            TokenKind::Dot => (Self::FieldAccess, Left),
+            TokenKind::LParen => (Self::Call, Left),
+            TokenKind::LBracket => (Self::Index, Left),
+            _ => return Option::None,
+        };
+
+        Some(prec)
+    }
+
+    /// The next tighter binding level.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Assignment => Self::Range,
+            Self::Range => Self::LogicalOr,
+            Self::LogicalOr => Self::LogicalAnd,
+            Self::LogicalAnd => Self::BitwiseOr,
+            Self::BitwiseOr => Self::BitwiseXor,
+            Self::BitwiseXor => Self::BitwiseAnd,
+            Self::BitwiseAnd => Self::Equality,
+            Self::Equality => Self::Comparison,
+            Self::Comparison => Self::Shift,
+            Self::Shift => Self::Addition,
+            Self::Addition => Self::Multiplication,
+            Self::Multiplication => Self::As,
+            Self::As => Self::Cast,
+            Self::Cast => Self::Prefix,
+            Self::Prefix => Self::Postfix,
+            Self::Postfix => Self::FieldAccess,
+            Self::FieldAccess => Self::Call,
+            Self::Call => Self::Index,
+            Self::Index => Self::Path,
+            Self::Path | Self::Primary => Self::Primary,
+        }
+    }
+}
@@ -0,0 +1,910 @@
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, EnumVariants, Expr, Field, FnItem, ImportItem,
+        Item, MatchArm, OpenItem, Path, Pattern, Script, Section, Spanned, Stmt, Visibility,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::Symbol,
+    lexer::Lexer,
+    parser::is_block_like,
+    precedence::Precedence,
+    source::Source,
+    span::Span,
+    tokens::TokenKind,
+};
+
+const INDENT: &str = "    ";
+
+/// blocks holding a single expression at most this long stay on one line
+const INLINE_WIDTH: usize = 60;
+
+/// Prints a script back as source in the canonical layout: four space
+/// indents, one item per paragraph and a statement per line. Literals keep
+/// their spelling. Doc comments are kept, but the lexer drops other
+/// comments, so a source with any is reported instead of silently losing
+/// them.
+pub struct Printer<'src> {
+    source: &'src Source,
+    pub bag: DiagnosticsBag,
+}
+
+impl<'src> Printer<'src> {
+    pub fn new(source: &'src Source) -> Self {
+        Self {
+            source,
+            bag: DiagnosticsBag::new(),
+        }
+    }
+
+    /// The formatted script, or `None` if formatting would lose text.
+    pub fn print_script(&mut self, script: &Script) -> Option<String> {
+        self.check_comments(script.root.name.span);
+        if self.bag.has_errors() {
+            return None;
+        }
+
+        let root = &script.root;
+        let mut out = docs(&root.docs, "//!", 0);
+
+        if !root.name.span.is_empty() {
+            out.push_str(&format!("script {};\n", root.name.node));
+        }
+        if !out.is_empty() && !root.items.is_empty() {
+            out.push('\n');
+        }
+
+        out.push_str(&self.items(&root.items, 0));
+        Some(out)
+    }
+
+    /// Reports the comments the syntax tree has no place for, and any text
+    /// past the last token, which formatting would drop.
+    fn check_comments(&mut self, root: Span) {
+        let mut lexer = Lexer::lossless(root.id, self.source);
+
+        let read = loop {
+            let token = lexer.next_token();
+            match token.kind {
+                TokenKind::LineComment | TokenKind::BlockComment => self.bag.push(
+                    Diagnostic::error("cannot format a file with comments")
+                        .with_label(token.span.primary("formatting would remove this")),
+                ),
+                TokenKind::Eof => break token.span.hi,
+                _ => (),
+            }
+        };
+
+        let end = self.source.content.len();
+        if read != end {
+            self.bag.push(
+                Diagnostic::error("cannot format a file the lexer stopped short of")
+                    .with_label(Span::new(root.id, read, end).primary("this text was not read")),
+            );
+        }
+    }
+
+    fn text(&self, span: Span) -> &'src str {
+        &self.source.content[span.to_range()]
+    }
+
+    fn items(&self, items: &[Item], depth: usize) -> String {
+        let mut out = String::new();
+
+        for (idx, item) in items.iter().enumerate() {
+            // consecutive `open`s form one paragraph
+            let grouped = matches!(
+                (item, idx.checked_sub(1).map(|prev| &items[prev])),
+                (Item::Open(_), Some(Item::Open(_)))
+            );
+            if idx != 0 && !grouped {
+                out.push('\n');
+            }
+
+            out.push_str(&docs(item.docs(), "///", depth));
+            out.push_str(&INDENT.repeat(depth));
+            out.push_str(&self.item(item, depth));
+            out.push('\n');
+        }
+
+        out
+    }
+
+    fn item(&self, item: &Item, depth: usize) -> String {
+        match item {
+            Item::Fn(item) => self.fn_item(item, depth),
+            Item::Struct(item) => format!(
+                "{}struct {} {}",
+                vis(item.vis),
+                item.name.node,
+                self.fields(&item.fields, depth)
+            ),
+            Item::Enum(item) => {
+                let variants: Vec<_> = item
+                    .variants
+                    .iter()
+                    .map(|variant| match &variant.node {
+                        EnumVariants::Tuple { name, types } if types.is_empty() => {
+                            name.node.to_string()
+                        }
+                        EnumVariants::Tuple { name, types } => {
+                            let types: Vec<_> =
+                                types.iter().map(|ty| ty.node.to_string()).collect();
+                            format!("{}({})", name.node, types.join(", "))
+                        }
+                        EnumVariants::Struct { name, fields } => {
+                            let fields: Vec<_> = fields.iter().map(field).collect();
+                            match fields.is_empty() {
+                                true => format!("{} {{}}", name.node),
+                                false => format!("{} {{ {} }}", name.node, fields.join(", ")),
+                            }
+                        }
+                    })
+                    .collect();
+
+                format!(
+                    "{}enum {} {}",
+                    vis(item.vis),
+                    item.name.node,
+                    lines(&variants, ",", depth)
+                )
+            }
+            Item::Section(item) => {
+                let Section {
+                    name,
+                    docs: inner,
+                    items,
+                } = &item.sections.node;
+
+                if item.external {
+                    format!("{}section {};", vis(item.vis), name.node)
+                } else if inner.is_empty() && items.is_empty() {
+                    format!("{}section {} {{}}", vis(item.vis), name.node)
+                } else {
+                    let mut body = docs(inner, "//!", depth + 1);
+                    if !inner.is_empty() && !items.is_empty() {
+                        body.push('\n');
+                    }
+                    body.push_str(&self.items(items, depth + 1));
+
+                    format!(
+                        "{}section {} {{\n{body}{}}}",
+                        vis(item.vis),
+                        name.node,
+                        INDENT.repeat(depth)
+                    )
+                }
+            }
+            Item::Const(item) => self.const_item(item, depth),
+            Item::Define(DefineItem::Fn(item)) => {
+                format!("{}define {}", vis(item.vis), self.fn_item_body(item, depth))
+            }
+            Item::Define(DefineItem::Const(item)) => {
+                format!(
+                    "{}define {}",
+                    vis(item.vis),
+                    self.const_item_body(item, depth)
+                )
+            }
+            Item::Open(item) => open(item),
+            Item::Error(span) => self.text(*span).to_string(),
+        }
+    }
+
+    fn fn_item(&self, item: &FnItem, depth: usize) -> String {
+        format!("{}{}", vis(item.vis), self.fn_item_body(item, depth))
+    }
+
+    fn fn_item_body(&self, item: &FnItem, depth: usize) -> String {
+        let params: Vec<_> = item.params.iter().map(field).collect();
+        let ret = match &item.ret {
+            Some(ty) => format!(": {}", ty.node),
+            None => String::new(),
+        };
+
+        format!(
+            "fn {}({}){ret} {}",
+            item.name.node,
+            params.join(", "),
+            self.block(&item.body, depth, false)
+        )
+    }
+
+    fn const_item(&self, item: &ConstItem, depth: usize) -> String {
+        format!("{}{}", vis(item.vis), self.const_item_body(item, depth))
+    }
+
+    fn const_item_body(&self, item: &ConstItem, depth: usize) -> String {
+        let ty = match &item.ty {
+            Some(ty) => format!(": {}", ty.node),
+            None => String::new(),
+        };
+
+        format!(
+            "const {}{ty} = {};",
+            item.name.node,
+            self.expr(
+                &item.value.node,
+                item.value.span,
+                Precedence::Assignment,
+                depth,
+                false
+            )
+        )
+    }
+
+    fn fields(&self, fields: &[Spanned<Field>], depth: usize) -> String {
+        let fields: Vec<_> = fields.iter().map(field).collect();
+        lines(&fields, ",", depth)
+    }
+
+    /// A block in braces. Blocks holding nothing but a short expression
+    /// stay on one line when `inline` allows it.
+    fn block(&self, block: &BlockExpr, depth: usize, inline: bool) -> String {
+        if block.stmts.is_empty() {
+            let Some(expr) = &block.expr else {
+                return "{}".to_string();
+            };
+
+            let tail = self.stmt_expr(&expr.node, expr.span, depth);
+            if inline && !tail.contains('\n') && tail.len() <= INLINE_WIDTH {
+                return format!("{{ {tail} }}");
+            }
+        }
+
+        let indent = INDENT.repeat(depth + 1);
+        let mut out = String::from("{\n");
+
+        for stmt in &block.stmts {
+            out.push_str(&indent);
+            out.push_str(&self.stmt(stmt, depth + 1));
+            out.push('\n');
+        }
+
+        if let Some(expr) = &block.expr {
+            out.push_str(&indent);
+            out.push_str(&self.stmt_expr(&expr.node, expr.span, depth + 1));
+            out.push('\n');
+        }
+
+        out.push_str(&INDENT.repeat(depth));
+        out.push('}');
+        out
+    }
+
+    fn stmt(&self, stmt: &Stmt, depth: usize) -> String {
+        match stmt {
+            Stmt::Let {
+                mutable,
+                name,
+                ty,
+                value,
+            } => {
+                let mut out = format!("let {}{}", if *mutable { "mut " } else { "" }, name.node);
+                if let Some(ty) = ty {
+                    out.push_str(&format!(": {}", ty.node));
+                }
+                if let Some(value) = value {
+                    let value = self.expr(
+                        &value.node,
+                        value.span,
+                        Precedence::Assignment,
+                        depth,
+                        false,
+                    );
+                    out.push_str(&format!(" = {value}"));
+                }
+                out.push(';');
+                out
+            }
+            Stmt::Open(item) => open(item),
+            Stmt::Expr(expr) => self.stmt_expr(&expr.node, expr.span, depth),
+            Stmt::Semi(expr) => format!("{};", self.stmt_expr(&expr.node, expr.span, depth)),
+        }
+    }
+
+    /// An expression at the start of a statement, where a leading block
+    /// would end the statement early, so `(if a { b } else { c }) + 1`
+    /// keeps its parentheses.
+    fn stmt_expr(&self, expr: &Expr, span: Span, depth: usize) -> String {
+        let printed = self.expr(expr, span, Precedence::Assignment, depth, false);
+
+        if !is_block_like(expr) && starts_with_block(expr) {
+            format!("({printed})")
+        } else {
+            printed
+        }
+    }
+
+    /// Prints `expr` as an operand that must bind at least as tightly as
+    /// `min`, adding parentheses where it does not. With `no_struct` the
+    /// expression is followed by a block, as in an `if` condition, and a
+    /// struct literal needs parentheses.
+    fn expr(
+        &self,
+        expr: &Expr,
+        span: Span,
+        min: Precedence,
+        depth: usize,
+        no_struct: bool,
+    ) -> String {
+        if precedence(expr) < min {
+            let inner = self.expr(expr, span, Precedence::Assignment, depth, false);
+            return format!("({inner})");
+        }
+
+        let operand =
+            |e: &Spanned<Box<Expr>>, min| self.expr(&e.node, e.span, min, depth, no_struct);
+        let list = |exprs: &[Spanned<Expr>]| {
+            let exprs: Vec<_> = exprs
+                .iter()
+                .map(|e| self.expr(&e.node, e.span, Precedence::Assignment, depth, false))
+                .collect();
+            exprs.join(", ")
+        };
+
+        match expr {
+            Expr::Literal(_) | Expr::Error(_) => self.text(span).to_string(),
+            Expr::Variable(name) => name.to_string(),
+            Expr::Path(path) => path_str(path),
+            Expr::Tuple(elems) if elems.len() == 1 => format!("({},)", list(elems)),
+            Expr::Tuple(elems) => format!("({})", list(elems)),
+            Expr::Array(elems) => format!("[{}]", list(elems)),
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let bound = Precedence::Range.next();
+                let start = start.as_ref().map_or(String::new(), |e| operand(e, bound));
+                let end = end.as_ref().map_or(String::new(), |e| operand(e, bound));
+                let dots = if *inclusive { "..=" } else { ".." };
+                format!("{start}{dots}{end}")
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let prec = binary_precedence(op.node);
+                let lhs_min = match op.node.is_comparison() {
+                    true => prec.next(),
+                    false => prec,
+                };
+                format!(
+                    "{} {} {}",
+                    operand(lhs, lhs_min),
+                    op.node,
+                    operand(rhs, prec.next())
+                )
+            }
+            Expr::Postfix(operand_, op) => {
+                format!("{}{}", operand(operand_, Precedence::Postfix), op.node)
+            }
+            Expr::Prefix(op, operand_) => {
+                format!("{}{}", op.node, operand(operand_, Precedence::Prefix))
+            }
+            Expr::Call(callee, args) => {
+                format!("{}({})", operand(callee, Precedence::Postfix), list(args))
+            }
+            Expr::Field(receiver, name) => {
+                format!("{}.{}", operand(receiver, Precedence::Postfix), name.node)
+            }
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => format!(
+                "{}.{}({})",
+                operand(receiver, Precedence::Postfix),
+                method.node,
+                list(args)
+            ),
+            Expr::Index(base, index) => format!(
+                "{}[{}]",
+                operand(base, Precedence::Postfix),
+                self.expr(
+                    &index.node,
+                    index.span,
+                    Precedence::Assignment,
+                    depth,
+                    false
+                )
+            ),
+            Expr::Cast(operand_, ty) => {
+                format!("{} as {}", operand(operand_, Precedence::As), ty.node)
+            }
+            Expr::Closure { params, ret, body } => {
+                let params: Vec<_> = params
+                    .iter()
+                    .map(|param| match &param.node.ty {
+                        Some(ty) => format!("{}: {}", param.node.name.node, ty.node),
+                        None => param.node.name.node.to_string(),
+                    })
+                    .collect();
+                let params = match params.is_empty() {
+                    true => "||".to_string(),
+                    false => format!("|{}|", params.join(", ")),
+                };
+
+                match (ret, &body.node.as_ref()) {
+                    (Some(ret), Expr::Block(block)) => {
+                        format!(
+                            "{params}: {} {}",
+                            ret.node,
+                            self.block(&block.node, depth, true)
+                        )
+                    }
+                    _ => format!(
+                        "{params} {}",
+                        self.expr(&body.node, body.span, Precedence::Assignment, depth, false)
+                    ),
+                }
+            }
+            Expr::If { cond, then, else_ } => {
+                let mut out = format!(
+                    "if {} {}",
+                    self.expr(&cond.node, cond.span, Precedence::Assignment, depth, true),
+                    self.expr(&then.node, then.span, Precedence::Assignment, depth, false)
+                );
+                if let Some(else_) = else_ {
+                    out.push_str(" else ");
+                    out.push_str(&self.expr(
+                        &else_.node,
+                        else_.span,
+                        Precedence::Assignment,
+                        depth,
+                        false,
+                    ));
+                }
+                out
+            }
+            Expr::Loop(body) => format!("loop {}", self.block(&body.node, depth, false)),
+            Expr::While { cond, body } => format!(
+                "while {} {}",
+                self.expr(&cond.node, cond.span, Precedence::Assignment, depth, true),
+                self.block(body, depth, false)
+            ),
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => format!(
+                "for {} in {} {}",
+                self.pattern(pattern),
+                self.expr(&iter.node, iter.span, Precedence::Assignment, depth, true),
+                self.block(body, depth, false)
+            ),
+            Expr::Break(value) => keyword(
+                "break",
+                value.as_ref().map(|e| operand(e, Precedence::Assignment)),
+            ),
+            Expr::Continue => "continue".to_string(),
+            Expr::Return(value) => keyword(
+                "return",
+                value.as_ref().map(|e| operand(e, Precedence::Assignment)),
+            ),
+            Expr::Block(block) => self.block(&block.node, depth, true),
+            Expr::Assign { target, value } => format!(
+                "{} = {}",
+                operand(target, Precedence::Assignment.next()),
+                operand(value, Precedence::Assignment)
+            ),
+            Expr::AssignEq { op, target, value } => format!(
+                "{} {}= {}",
+                operand(target, Precedence::Assignment.next()),
+                op.node,
+                operand(value, Precedence::Assignment)
+            ),
+            Expr::StructInit { path, fields } => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|init| {
+                        let init = &init.node;
+                        match &init.value.node {
+                            Expr::Variable(name) if *name == init.name.node => name.to_string(),
+                            _ => format!(
+                                "{}: {}",
+                                init.name.node,
+                                self.expr(
+                                    &init.value.node,
+                                    init.value.span,
+                                    Precedence::Assignment,
+                                    depth,
+                                    false
+                                )
+                            ),
+                        }
+                    })
+                    .collect();
+
+                let init = match fields.is_empty() {
+                    true => format!("{} {{}}", path_str(path)),
+                    false => format!("{} {{ {} }}", path_str(path), fields.join(", ")),
+                };
+                match no_struct {
+                    true => format!("({init})"),
+                    false => init,
+                }
+            }
+            Expr::Match { scrutinee, arms } => {
+                let arms: Vec<_> = arms.iter().map(|arm| self.arm(arm, depth + 1)).collect();
+                let indent = INDENT.repeat(depth + 1);
+                let mut out = format!(
+                    "match {} {{\n",
+                    self.expr(
+                        &scrutinee.node,
+                        scrutinee.span,
+                        Precedence::Assignment,
+                        depth,
+                        true
+                    )
+                );
+                for arm in arms {
+                    out.push_str(&format!("{indent}{arm}\n"));
+                }
+                out.push_str(&INDENT.repeat(depth));
+                out.push('}');
+                out
+            }
+        }
+    }
+
+    fn arm(&self, arm: &MatchArm, depth: usize) -> String {
+        let body = self.expr(
+            &arm.body.node,
+            arm.body.span,
+            Precedence::Assignment,
+            depth,
+            false,
+        );
+        let comma = match arm.body.node {
+            Expr::Block(_) => "",
+            _ => ",",
+        };
+        format!("{} => {body}{comma}", self.pattern(&arm.pattern))
+    }
+
+    fn pattern(&self, pattern: &Spanned<Pattern>) -> String {
+        match &pattern.node {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Literal(literal) => self.text(literal.span).to_string(),
+            Pattern::Variable(name) => name.node.to_string(),
+            // `Unit()` with one segment would read back as a binding
+            Pattern::Tuple { path, elems } if elems.is_empty() && path.len() > 1 => path_str(path),
+            Pattern::Tuple { path, elems } => {
+                let elems: Vec<_> = elems.iter().map(|elem| self.pattern(elem)).collect();
+                format!("{}({})", path_str(path), elems.join(", "))
+            }
+            Pattern::Struct { path, fields } => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|field| match &field.node.pattern.node {
+                        Pattern::Variable(name) if name.node == field.node.name.node => {
+                            name.node.to_string()
+                        }
+                        _ => format!(
+                            "{}: {}",
+                            field.node.name.node,
+                            self.pattern(&field.node.pattern)
+                        ),
+                    })
+                    .collect();
+
+                match fields.is_empty() {
+                    true => format!("{} {{}}", path_str(path)),
+                    false => format!("{} {{ {} }}", path_str(path), fields.join(", ")),
+                }
+            }
+            Pattern::Error(span) => self.text(*span).to_string(),
+        }
+    }
+}
+
+/// How tightly `expr` binds as an operand. The branches of an `if` are
+/// whole expressions, so like a closure body they take any operator that
+/// follows them.
+fn precedence(expr: &Expr) -> Precedence {
+    match expr {
+        Expr::If { .. }
+        | Expr::Assign { .. }
+        | Expr::AssignEq { .. }
+        | Expr::Closure { .. }
+        | Expr::Break(_)
+        | Expr::Return(_) => Precedence::Assignment,
+        Expr::Range { .. } => Precedence::Range,
+        Expr::Binary(_, op, _) => binary_precedence(op.node),
+        Expr::Cast(..) => Precedence::As,
+        Expr::Prefix(..) => Precedence::Prefix,
+        Expr::Postfix(..)
+        | Expr::Call(..)
+        | Expr::Field(..)
+        | Expr::MethodCall { .. }
+        | Expr::Index(..) => Precedence::Postfix,
+        _ => Precedence::Primary,
+    }
+}
+
+fn binary_precedence(op: BinaryOp) -> Precedence {
+    match op {
+        BinaryOp::Or => Precedence::LogicalOr,
+        BinaryOp::And => Precedence::LogicalAnd,
+        BinaryOp::BitOr => Precedence::BitwiseOr,
+        BinaryOp::BitXor => Precedence::BitwiseXor,
+        BinaryOp::BitAnd => Precedence::BitwiseAnd,
+        BinaryOp::Eq | BinaryOp::Ne => Precedence::Equality,
+        BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => Precedence::Comparison,
+        BinaryOp::Shl | BinaryOp::Shr => Precedence::Shift,
+        BinaryOp::Add | BinaryOp::Sub => Precedence::Addition,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => Precedence::Multiplication,
+    }
+}
+
+/// Whether the leftmost operand of `expr` is block-like. An `if` operand is
+/// always parenthesized.
+fn starts_with_block(expr: &Expr) -> bool {
+    let first = match expr {
+        Expr::Binary(lhs, ..)
+        | Expr::Postfix(lhs, _)
+        | Expr::Call(lhs, _)
+        | Expr::Field(lhs, _)
+        | Expr::Index(lhs, _)
+        | Expr::Cast(lhs, _)
+        | Expr::MethodCall { receiver: lhs, .. }
+        | Expr::Assign { target: lhs, .. }
+        | Expr::AssignEq { target: lhs, .. }
+        | Expr::Range {
+            start: Some(lhs), ..
+        } => lhs,
+        _ => return false,
+    };
+
+    match first.node.as_ref() {
+        Expr::If { .. } => false,
+        node => is_block_like(node) || starts_with_block(node),
+    }
+}
+
+/// Doc comment lines with `marker`, each ending in a newline.
+fn docs(docs: &[Spanned<Symbol>], marker: &str, depth: usize) -> String {
+    docs.iter()
+        .map(|line| format!("{}{marker}{}\n", INDENT.repeat(depth), line.node))
+        .collect()
+}
+
+fn vis(vis: Visibility) -> &'static str {
+    match vis {
+        Visibility::Public => "pub ",
+        Visibility::Private => "",
+    }
+}
+
+fn field(field: &Spanned<Field>) -> String {
+    format!("{}: {}", field.node.name.node, field.node.ty.node)
+}
+
+fn path_str(path: &Path) -> String {
+    let segments: Vec<_> = path.iter().map(|segment| segment.node.as_str()).collect();
+    segments.join("::")
+}
+
+fn keyword(keyword: &str, value: Option<String>) -> String {
+    match value {
+        Some(value) => format!("{keyword} {value}"),
+        None => keyword.to_string(),
+    }
+}
+
+/// `{}` or the entries one per line, each followed by `sep`.
+fn lines(entries: &[String], sep: &str, depth: usize) -> String {
+    if entries.is_empty() {
+        return "{}".to_string();
+    }
+
+    let indent = INDENT.repeat(depth + 1);
+    let mut out = String::from("{\n");
+    for entry in entries {
+        out.push_str(&format!("{indent}{entry}{sep}\n"));
+    }
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+    out
+}
+
+fn open(item: &OpenItem) -> String {
+    let mut out = format!("open {}", path_str(&item.path));
+
+    let imports: Vec<_> = item
+        .imports
+        .iter()
+        .map(|import| match import.node {
+            ImportItem::Star => "*",
+            ImportItem::SelfImport => "self",
+            ImportItem::Ident(name) => name.as_str(),
+        })
+        .collect();
+
+    match imports.as_slice() {
+        [] => (),
+        ["*"] => out.push_str("::*"),
+        _ => out.push_str(&format!("::{{{}}}", imports.join(", "))),
+    }
+
+    if let Some(alias) = item.alias {
+        out.push_str(&format!(" as {}", alias.node));
+    }
+
+    out.push(';');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, source::SourceMap};
+
+    fn format(src: &str) -> String {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", src);
+        let mut parser = Parser::new(id, &map[id]);
+        let script = parser.parse_script();
+        assert!(parser.bag.is_empty(), "parse errors: {:?}", parser.bag.0);
+
+        let mut printer = Printer::new(&map[id]);
+        let out = printer.print_script(&script);
+        assert!(printer.bag.is_empty(), "format errors: {:?}", printer.bag.0);
+        out.unwrap()
+    }
+
+    #[test]
+    fn test_format_items_and_blocks() {
+        let src = "script demo; open math::{self, add};open io::*;
+            pub struct P{x:i32,y:(i32,bool,)}
+            enum E{A,B(i32,u8?),C{v:&mut [u8]}}
+            section math{pub fn add(a:i32,b:i32):i32{a+b} section far;}
+            define const N:u8=0x1F;
+            fn main(){let mut p=P{x:1,y:(2,true)};
+            for i in 0..=N{if i%2==0{continue}else{p.x+=1;}}
+            let v=match E::A{E::B(n,_)=>n,E::C{v}=>{0}_=>-1};
+            let f=|a,b:i32|a*b;loop{break}}";
+
+        let expected = "script demo;
+
+open math::{self, add};
+open io::*;
+
+pub struct P {
+    x: i32,
+    y: (i32, bool),
+}
+
+enum E {
+    A,
+    B(i32, u8?),
+    C { v: &mut [u8] },
+}
+
+section math {
+    pub fn add(a: i32, b: i32): i32 {
+        a + b
+    }
+
+    section far;
+}
+
+define const N: u8 = 0x1F;
+
+fn main() {
+    let mut p = P { x: 1, y: (2, true) };
+    for i in 0..=N {
+        if i % 2 == 0 { continue } else {
+            p.x += 1;
+        }
+    }
+    let v = match E::A {
+        E::B(n, _) => n,
+        E::C { v } => { 0 }
+        _ => -1,
+    };
+    let f = |a, b: i32| a * b;
+    loop {
+        break
+    }
+}
+";
+
+        assert_eq!(format(src), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn test_parentheses_follow_precedence() {
+        let out = format(
+            "fn main() {
+                let a = ((1 + 2)) * 3 - (4 - 5) - 6;
+                let b = -(a as i64) as u8;
+                let c = (a == 1) == true;
+                let d = &(1..2);
+                (if c { 1 } else { 2 }) + 1;
+                ({ 1 }) + 1;
+                (|x| x)(1);
+                *p = (x = 2, 3);
+                if (P { x: 1 }).x == 1 {}
+                x = y = 3;
+             }",
+        );
+
+        assert_eq!(
+            out,
+            "fn main() {
+    let a = (1 + 2) * 3 - (4 - 5) - 6;
+    let b = -(a as i64) as u8;
+    let c = (a == 1) == true;
+    let d = &(1..2);
+    (if c { 1 } else { 2 }) + 1;
+    ({ 1 } + 1);
+    (|x| x)(1);
+    *p = (x = 2, 3);
+    if (P { x: 1 }).x == 1 {}
+    x = y = 3;
+}
+"
+        );
+        assert_eq!(format(&out), out);
+    }
+
+    #[test]
+    fn test_literals_keep_their_spelling() {
+        let out = format("const A: u8 = 0b1010; const S: str = \"a\\tb\"; const C: char = '\\n';");
+        assert_eq!(
+            out,
+            "const A: u8 = 0b1010;\n\nconst S: str = \"a\\tb\";\n\nconst C: char = '\\n';\n"
+        );
+    }
+
+    #[test]
+    fn test_doc_comments_are_kept() {
+        let src = "script demo;\n//! about\n/// one\nconst A: u8 = 1;\n\
+                   section s {\n//! inner\n///two\nfn f() {}\n}";
+        let out = format(src);
+        assert_eq!(
+            out,
+            "//! about
+script demo;
+
+/// one
+const A: u8 = 1;
+
+section s {
+    //! inner
+
+    ///two
+    fn f() {}
+}
+"
+        );
+        assert_eq!(format(&out), out);
+    }
+
+    #[test]
+    fn test_refuse_files_with_comments() {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", "fn f() {\n    // a\n    1 /* b */ + 2\n}\n");
+        let mut parser = Parser::new(id, &map[id]);
+        let script = parser.parse_script();
+        assert!(parser.bag.is_empty());
+
+        let mut printer = Printer::new(&map[id]);
+        assert!(printer.print_script(&script).is_none());
+
+        let spans: Vec<_> = printer.bag.0.iter().map(|d| d.labels[0].span).collect();
+        let text: Vec<_> = spans
+            .iter()
+            .map(|span| &map[id].content[span.to_range()])
+            .collect();
+        assert_eq!(text, ["// a", "/* b */"]);
+    }
+
+    #[test]
+    fn test_unit_variant_patterns_keep_their_parentheses() {
+        let out = format("fn f(e: E): i32 { match e { A() => 1, E::B => 2, x => 3 } }");
+        assert!(out.contains("A() => 1,\n"), "{out}");
+        assert!(out.contains("E::B => 2,\n"), "{out}");
+        assert!(out.contains("x => 3,\n"), "{out}");
+    }
+}
@@ -0,0 +1,514 @@
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use crate::{
+    ast::{BlockExpr, DefineItem, FnItem, Item, Script, Section, Spannable, Stmt, Visibility},
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    interp::Interpreter,
+    lexer::Lexer,
+    parser::Parser,
+    resolve::{Resolution, Resolver},
+    source::{FileId, Source, SourceMap},
+    span::Span,
+    tokens::TokenKind,
+    ty::Ty,
+    typeck::{TypeChecker, TypeckResults},
+    value::{Nested, Value},
+};
+
+pub const HELP: &str = "\
+enter items, statements or an expression to evaluate them
+    :type <expr>      show the type of an expression without running it
+    :tokens <text>    show the tokens of some text
+    :ast <text>       show the syntax tree of some text
+    :help             show this message
+    :quit             leave, as does ctrl-d
+";
+
+/// A variable defined by an earlier input.
+#[derive(Debug)]
+struct Binding {
+    name: Symbol,
+    mutable: bool,
+    ty: Ty,
+    cell: Rc<RefCell<Value>>,
+}
+
+/// An interactive session. Every input gets a file of its own in `map`, so
+/// diagnostics point at the input they are about.
+///
+/// Items persist by being kept in the script each input is checked as, and
+/// a `fn` or `const` typed again replaces the earlier one in place. Keeping
+/// the items in order keeps their ids stable between inputs, which struct
+/// and function values rely on. Variables persist by their cells: the next
+/// input declares them again and the interpreter is handed the old cells.
+pub struct Session {
+    pub map: SourceMap,
+    pub bag: DiagnosticsBag,
+    items: Vec<Item>,
+    bindings: Vec<Binding>,
+    /// how many inputs were read, which names them
+    inputs: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            map: SourceMap::fresh(),
+            bag: DiagnosticsBag::new(),
+            items: Vec::new(),
+            bindings: Vec::new(),
+            inputs: 0,
+        }
+    }
+
+    /// Whether `input` closes every bracket it opens, so that it can be run
+    /// rather than continued on the next line.
+    pub fn is_complete(input: &str) -> bool {
+        let source = Source::with_content("<input>", input);
+        let mut lexer = Lexer::new(FileId(0), &source);
+        let mut depth = 0i32;
+
+        loop {
+            match lexer.next_token().kind {
+                TokenKind::LParen | TokenKind::LBrace | TokenKind::LBracket => depth += 1,
+                TokenKind::RParen | TokenKind::RBrace | TokenKind::RBracket => depth -= 1,
+                TokenKind::Eof => return depth <= 0,
+                _ => (),
+            }
+        }
+    }
+
+    /// Runs a line of input, a `:command` or code. Returns `false` once the
+    /// session should end.
+    pub fn input(&mut self, input: &str, out: &mut dyn Write) -> bool {
+        let trimmed = input.trim();
+        let Some(command) = trimmed.strip_prefix(':') else {
+            if !trimmed.is_empty() {
+                self.eval(input, out);
+            }
+            return true;
+        };
+
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        match name {
+            "q" | "quit" => return false,
+            "h" | "help" => {
+                let _ = write!(out, "{HELP}");
+            }
+            "t" | "type" => self.type_of(arg, out),
+            "tokens" => self.tokens(arg, out),
+            "ast" => self.ast(arg, out),
+            _ => self.bag.push(Diagnostic::error(format!(
+                "unknown command `:{name}`, `:help` lists them"
+            ))),
+        }
+        true
+    }
+
+    fn eval(&mut self, input: &str, out: &mut dyn Write) {
+        let Some((items, body)) = self.parse(input) else {
+            return;
+        };
+        let user_stmts = body.stmts.len();
+        let has_tail = body.expr.is_some();
+
+        let (script, prelude) = self.script(items, body);
+        let Some((res, types)) = self.check(&script) else {
+            return;
+        };
+
+        let Some(Item::Fn(input)) = script.root.items.last() else {
+            unreachable!("the input is the last item");
+        };
+        let body = &input.body;
+        let declared: Vec<_> = body.stmts[prelude..].iter().take(user_stmts).collect();
+
+        let (value, bag) = {
+            let mut interp = Interpreter::new(&res, &types, &mut *out);
+            interp.load(&script.root);
+
+            let lets = body.stmts[..prelude].iter().filter_map(|stmt| match stmt {
+                Stmt::Let { name, .. } => Some(name.span),
+                _ => None,
+            });
+            for (binding, name) in self.bindings.iter().zip(lets) {
+                if let Some(def) = res.lookup(name) {
+                    interp.bind(def, binding.cell.clone());
+                }
+            }
+
+            let value = interp.run_block(body);
+            let mut kept = Vec::new();
+            if value.is_some() {
+                for stmt in &declared {
+                    if let Stmt::Let { mutable, name, .. } = stmt
+                        && let Some(def) = res.lookup(name.span)
+                        && let Some(cell) = interp.binding(def)
+                        && is_settled(&types.locals[&def])
+                    {
+                        kept.push((
+                            name.node,
+                            name.span,
+                            *mutable,
+                            types.locals[&def].clone(),
+                            cell,
+                        ));
+                    }
+                }
+            }
+            (value.map(|value| (value, kept)), interp.bag)
+        };
+
+        let Script { mut root } = script;
+        root.items.pop();
+        self.items = root.items;
+
+        if !bag.is_empty() {
+            self.bag.extend(bag);
+            return;
+        }
+        let Some((value, kept)) = value else {
+            return;
+        };
+
+        for (name, span, mutable, ty, cell) in kept {
+            self.bindings.retain(|binding| binding.name != name);
+            if contains_closure(&cell.borrow()) {
+                self.bag.push(
+                    Diagnostic::warning(format!(
+                        "`{name}` holds a closure and is not kept for the next input"
+                    ))
+                    .with_label(span.primary("defined here")),
+                );
+                continue;
+            }
+            self.bindings.push(Binding {
+                name,
+                mutable,
+                ty,
+                cell,
+            });
+        }
+
+        if has_tail && !matches!(value, Value::Unit) {
+            let _ = writeln!(out, "{}", Nested(&value));
+        }
+    }
+
+    fn type_of(&mut self, input: &str, out: &mut dyn Write) {
+        let Some((items, body)) = self.parse(input) else {
+            return;
+        };
+        let Some(tail) = body.expr.as_ref().map(|tail| tail.span) else {
+            self.bag.push(Diagnostic::error(
+                "`:type` needs an expression, as in `:type 1 + 2`",
+            ));
+            return;
+        };
+
+        let (script, _) = self.script(items, body);
+        if let Some((_, types)) = self.check(&script) {
+            let _ = writeln!(out, "{}", types.expr(tail));
+        }
+    }
+
+    fn tokens(&mut self, input: &str, out: &mut dyn Write) {
+        let id = self.chunk(input);
+        let source = &self.map[id];
+        let mut lexer = Lexer::new(id, source);
+
+        loop {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            let text = &source.content[token.span.to_range()];
+            let _ = writeln!(out, "{:?} {text:?}", token.kind);
+        }
+
+        self.bag.extend(lexer.bag);
+    }
+
+    fn ast(&mut self, input: &str, out: &mut dyn Write) {
+        let Some((items, body)) = self.parse(input) else {
+            return;
+        };
+
+        for item in &items {
+            let _ = writeln!(out, "{item:#?}");
+        }
+        match (body.stmts.is_empty(), &body.expr) {
+            (true, None) => (),
+            (true, Some(tail)) => {
+                let _ = writeln!(out, "{:#?}", tail.node);
+            }
+            (false, _) => {
+                let _ = writeln!(out, "{body:#?}");
+            }
+        }
+    }
+
+    fn chunk(&mut self, input: &str) -> FileId {
+        self.inputs += 1;
+        self.map
+            .with_content(format!("<input {}>", self.inputs), input)
+    }
+
+    fn parse(&mut self, input: &str) -> Option<(Vec<Item>, BlockExpr)> {
+        let id = self.chunk(input);
+        let mut parser = Parser::new(id, &self.map[id]);
+        let parsed = parser.parse_input();
+
+        let failed = parser.bag.has_errors();
+        self.bag.extend(parser.bag);
+        (!failed).then_some(parsed)
+    }
+
+    /// The script an input is checked as: the items so far with the ones it
+    /// defines, and a function holding its statements after declarations of
+    /// the variables so far. Also returns how many statements the
+    /// declarations take.
+    fn script(&mut self, items: Vec<Item>, body: BlockExpr) -> (Script, usize) {
+        let mut all = self.items.clone();
+        for item in items {
+            let replaces = item_name(&item).and_then(|name| {
+                all.iter()
+                    .position(|old| is_replaceable(old) && item_name(old) == Some(name))
+            });
+            match replaces {
+                Some(idx) if is_replaceable(&item) => all[idx] = item,
+                _ => all.push(item),
+            }
+        }
+
+        let prelude = self.prelude();
+        let count = prelude.stmts.len();
+        let mut stmts = prelude.stmts;
+        stmts.extend(body.stmts);
+
+        let id = FileId(self.map.source.len() as u32 - 1);
+        let here = Span::new(id, 0, 0);
+        all.push(Item::Fn(FnItem {
+            docs: Vec::new(),
+            vis: Visibility::Private,
+            name: "<input>".intern().spanned(here),
+            params: Vec::new(),
+            ret: Some(Ty::Infer.spanned(here)),
+            body: BlockExpr {
+                stmts,
+                expr: body.expr,
+            },
+        }));
+
+        let root = Section {
+            docs: Vec::new(),
+            name: "repl".intern().spanned(here),
+            items: all,
+        };
+        (Script { root }, count)
+    }
+
+    /// Declarations of the variables so far, parsed from source written for
+    /// them. An immutable one is assigned to itself, as a declaration without
+    /// a value may otherwise be assigned once.
+    fn prelude(&mut self) -> BlockExpr {
+        let mut text = String::new();
+        for binding in &self.bindings {
+            let name = binding.name;
+            let ty = rooted(&binding.ty);
+            match binding.mutable {
+                true => text.push_str(&format!("let mut {name}: {ty};\n")),
+                false => text.push_str(&format!("let {name}: {ty}; {name} = {name};\n")),
+            }
+        }
+
+        let id = self.map.with_content("<variables>", text);
+        let mut parser = Parser::new(id, &self.map[id]);
+        let (_, block) = parser.parse_input();
+        debug_assert!(parser.bag.is_empty(), "{:?}", parser.bag.0);
+        block
+    }
+
+    fn check(&mut self, script: &Script) -> Option<(Resolution, TypeckResults)> {
+        let mut resolver = Resolver::new();
+        let res = resolver.resolve_script(script);
+        if resolver.bag.has_errors() {
+            self.bag.extend(resolver.bag);
+            return None;
+        }
+
+        let mut checker = TypeChecker::new(&res);
+        let types = checker.check_script(script);
+        let failed = checker.bag.has_errors();
+        self.bag.extend(checker.bag);
+        (!failed).then_some((res, types))
+    }
+}
+
+/// Functions and constants can be defined again, other items only once.
+fn is_replaceable(item: &Item) -> bool {
+    matches!(item, Item::Fn(_) | Item::Const(_) | Item::Define(_))
+}
+
+fn item_name(item: &Item) -> Option<Symbol> {
+    let name = match item {
+        Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => item.name,
+        Item::Const(item) | Item::Define(DefineItem::Const(item)) => item.name,
+        Item::Struct(item) => item.name,
+        Item::Enum(item) => item.name,
+        Item::Section(item) => item.sections.node.name,
+        Item::Open(_) | Item::Error(_) => return None,
+    };
+    Some(name.node)
+}
+
+/// `ty` with its named types spelled from `root`, so that a later input
+/// cannot shadow them.
+fn rooted(ty: &Ty) -> Ty {
+    let path = |path: &[Symbol]| {
+        std::iter::once("root".intern())
+            .chain(path.iter().copied())
+            .collect()
+    };
+
+    match ty {
+        Ty::Path(segments) => Ty::Path(path(segments)),
+        Ty::Generic {
+            path: segments,
+            args,
+        } => Ty::Generic {
+            path: path(segments),
+            args: args.iter().map(rooted).collect(),
+        },
+        Ty::Ref { mutable, inner } => Ty::Ref {
+            mutable: *mutable,
+            inner: Box::new(rooted(inner)),
+        },
+        Ty::Optional(inner) => Ty::Optional(Box::new(rooted(inner))),
+        Ty::Tuple(elems) => Ty::Tuple(elems.iter().map(rooted).collect()),
+        Ty::Array(elem, len) => Ty::Array(Box::new(rooted(elem)), *len),
+        Ty::Slice(elem) => Ty::Slice(Box::new(rooted(elem))),
+        Ty::Fn { params, ret } => Ty::Fn {
+            params: params.iter().map(rooted).collect(),
+            ret: Box::new(rooted(ret)),
+        },
+        ty => ty.clone(),
+    }
+}
+
+/// Whether `ty` can be written down for the next input to declare.
+fn is_settled(ty: &Ty) -> bool {
+    match ty {
+        Ty::Infer | Ty::Var(_) | Ty::Never => false,
+        Ty::Ref { inner, .. } | Ty::Optional(inner) | Ty::Array(inner, _) | Ty::Slice(inner) => {
+            is_settled(inner)
+        }
+        Ty::Tuple(tys) | Ty::Generic { args: tys, .. } => tys.iter().all(is_settled),
+        Ty::Fn { params, ret } => params.iter().all(is_settled) && is_settled(ret),
+        _ => true,
+    }
+}
+
+/// Closures refer to code by the ids of one run, which the next does not share.
+fn contains_closure(value: &Value) -> bool {
+    match value {
+        Value::Closure(_) => true,
+        Value::Ref(place) => contains_closure(&place.get()),
+        Value::Range { start, end, .. } => [start, end]
+            .into_iter()
+            .flatten()
+            .any(|bound| contains_closure(bound)),
+        value => value.elems().iter().any(contains_closure),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(session: &mut Session, input: &str) -> String {
+        let mut out = Vec::new();
+        assert!(session.input(input, &mut out));
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_definitions_persist_across_inputs() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "let x = 40;"), "");
+        assert_eq!(
+            run(&mut session, "fn add(a: i32, b: i32): i32 { a + b }"),
+            ""
+        );
+        assert_eq!(run(&mut session, "add(x, 2)"), "42\n");
+
+        assert_eq!(
+            run(&mut session, "fn add(a: i32, b: i32): i32 { a - b }"),
+            ""
+        );
+        assert_eq!(run(&mut session, "add(x, 2)"), "38\n");
+
+        assert_eq!(run(&mut session, "let mut n = 1;"), "");
+        assert_eq!(run(&mut session, "n += x; n"), "41\n");
+        assert_eq!(run(&mut session, "n"), "41\n");
+
+        assert_eq!(run(&mut session, "struct P { a: i32 }"), "");
+        assert_eq!(run(&mut session, "let p = P { a: 3 };"), "");
+        assert_eq!(run(&mut session, "p.a + n"), "44\n");
+        assert!(session.bag.is_empty(), "{:?}", session.bag.0);
+    }
+
+    #[test]
+    fn test_errors_point_at_their_input() {
+        let mut session = Session::new();
+        run(&mut session, "let x = 1;");
+        run(&mut session, "x = 2;");
+
+        assert!(session.bag.has_errors());
+        let label = &session.bag.0[0].labels[0];
+        assert_eq!(session.map[label.span.id].content, "x = 2;");
+
+        // a failed input defines nothing, and the session goes on
+        session.bag.clear();
+        run(&mut session, "let y = x + true;");
+        assert!(session.bag.has_errors());
+        session.bag.clear();
+        assert_eq!(run(&mut session, "x"), "1\n");
+        assert!(session.bag.is_empty());
+    }
+
+    #[test]
+    fn test_unclosed_brackets_continue() {
+        assert!(Session::is_complete("let x = 1;"));
+        assert!(!Session::is_complete("fn f() {"));
+        assert!(!Session::is_complete("f(1,"));
+        assert!(Session::is_complete("fn f() {\n    g(1)\n}"));
+        assert!(Session::is_complete("\"{\""));
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut session = Session::new();
+        run(&mut session, "let x: u8 = 1;");
+        assert_eq!(run(&mut session, ":type x + 1"), "u8\n");
+        assert_eq!(
+            run(&mut session, ":tokens x + 1"),
+            "Ident \"x\"\nPlus \"+\"\nIntLiteral \"1\"\n"
+        );
+        assert!(run(&mut session, ":ast x + 1").starts_with("Binary"));
+        assert!(session.bag.is_empty(), "{:?}", session.bag.0);
+
+        run(&mut session, ":nope");
+        assert!(session.bag.has_errors());
+        assert!(!session.input(":quit", &mut Vec::new()));
+    }
+}
@@ -0,0 +1,1104 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    ast::{
+        BlockExpr, DefineItem, EnumVariants, Expr, FnItem, ImportItem, Item, OpenItem, Pattern,
+        Script, Section, Spannable, Spanned, Stmt,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    span::Span,
+    ty::Ty,
+};
+
+/// Functions every script can call without opening anything.
+pub const BUILTINS: &[&str] = &["print", "println", "assert"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DefId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+    Section,
+    Fn,
+    Struct,
+    Enum,
+    Variant,
+    Const,
+    Local { mutable: bool },
+    Builtin,
+}
+
+impl DefKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Section => "section",
+            Self::Fn => "function",
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::Variant => "variant",
+            Self::Const => "constant",
+            Self::Local { .. } => "local variable",
+            Self::Builtin => "builtin function",
+        }
+    }
+
+    /// Whether paths can continue past a definition of this kind.
+    pub fn is_namespace(&self) -> bool {
+        matches!(self, Self::Section | Self::Enum)
+    }
+
+    pub fn is_type(&self) -> bool {
+        matches!(self, Self::Struct | Self::Enum)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Def {
+    pub name: Symbol,
+    pub kind: DefKind,
+    /// the name at the definition site, the root span for builtins
+    pub span: Span,
+    /// the enclosing section, or the enum of a variant
+    pub parent: Option<DefId>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Namespace {
+    items: HashMap<Symbol, DefId>,
+    imports: HashMap<Symbol, (DefId, Span)>,
+    globs: Vec<(DefId, Span)>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    names: HashMap<Symbol, DefId>,
+    globs: Vec<(DefId, Span)>,
+}
+
+/// The result of name resolution. AST nodes carry no ids, so uses are keyed
+/// by the span of the name (or whole path) that refers to a definition.
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    pub defs: Vec<Def>,
+    /// every resolved name occurrence, including definition sites of locals
+    pub names: HashMap<Span, DefId>,
+    /// type annotations with their paths rewritten to canonical ones
+    pub types: HashMap<Span, Ty>,
+    pub root: DefId,
+    namespaces: HashMap<DefId, Namespace>,
+    prelude: HashMap<Symbol, DefId>,
+}
+
+impl Resolution {
+    pub fn def(&self, id: DefId) -> &Def {
+        &self.defs[id.0 as usize]
+    }
+
+    pub fn lookup(&self, span: Span) -> Option<DefId> {
+        self.names.get(&span).copied()
+    }
+
+    /// The item named `name` directly inside a section, or the variant of an enum.
+    pub fn member(&self, parent: DefId, name: Symbol) -> Option<DefId> {
+        self.namespaces.get(&parent)?.items.get(&name).copied()
+    }
+
+    pub fn members(&self, parent: DefId) -> impl Iterator<Item = (Symbol, DefId)> + '_ {
+        self.namespaces
+            .get(&parent)
+            .into_iter()
+            .flat_map(|ns| ns.items.iter().map(|(name, def)| (*name, *def)))
+    }
+
+    /// The path from the root section to `id`, without the root itself.
+    pub fn path_of(&self, id: DefId) -> Vec<Symbol> {
+        let mut path = Vec::new();
+        let mut current = Some(id);
+
+        while let Some(def) = current
+            && def != self.root
+        {
+            let def = self.def(def);
+            path.push(def.name);
+            current = def.parent;
+        }
+
+        path.reverse();
+        path
+    }
+
+    /// Looks up a definition by the path [`Resolution::path_of`] produces.
+    pub fn def_by_path(&self, path: &[Symbol]) -> Option<DefId> {
+        if let [name] = path
+            && let Some(builtin) = self.prelude.get(name)
+            && self.member(self.root, *name).is_none()
+        {
+            return Some(*builtin);
+        }
+
+        path.iter()
+            .try_fold(self.root, |def, name| self.member(def, *name))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Resolver {
+    pub res: Resolution,
+    pub bag: DiagnosticsBag,
+    section: DefId,
+    scopes: Vec<Scope>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resolve_script(&mut self, script: &Script) -> Resolution {
+        let root = &script.root;
+        let id = self.define(root.name.node, DefKind::Section, root.name.span, None);
+        self.res.root = id;
+        self.section = id;
+
+        for name in BUILTINS {
+            let builtin = self.define(name.intern(), DefKind::Builtin, root.name.span, None);
+            self.res.prelude.insert(name.intern(), builtin);
+        }
+
+        let mut opens = Vec::new();
+        self.collect_section(id, root, &mut opens);
+        self.resolve_opens(opens);
+        self.resolve_section(id, root);
+
+        mem::take(&mut self.res)
+    }
+
+    fn define(&mut self, name: Symbol, kind: DefKind, span: Span, parent: Option<DefId>) -> DefId {
+        let id = DefId(self.res.defs.len() as u32);
+        self.res.defs.push(Def {
+            name,
+            kind,
+            span,
+            parent,
+        });
+
+        if kind.is_namespace() {
+            self.res.namespaces.insert(id, Namespace::default());
+        }
+
+        id
+    }
+
+    /// Defines an item inside `parent`, reporting clashes with earlier items.
+    fn define_member(&mut self, parent: DefId, name: Spanned<Symbol>, kind: DefKind) -> DefId {
+        let id = self.define(name.node, kind, name.span, Some(parent));
+        self.res.names.insert(name.span, id);
+
+        let ns = self.res.namespaces.get_mut(&parent).expect("namespace");
+        if let Some(prev) = ns.items.insert(name.node, id) {
+            let prev = self.res.def(prev).span;
+            self.duplicate(name, prev);
+        }
+
+        id
+    }
+
+    fn duplicate(&mut self, name: Spanned<Symbol>, prev: Span) {
+        self.bag.push(
+            Diagnostic::error(format!(
+                "the name `{}` is defined multiple times",
+                name.node.as_str()
+            ))
+            .with_label(name.span.primary("redefined here"))
+            .with_label(prev.secondary("previous definition here")),
+        );
+    }
+
+    /// Defines every item of `section` before any body is looked at, so that
+    /// items can be used ahead of their definition.
+    fn collect_section(
+        &mut self,
+        id: DefId,
+        section: &Section,
+        opens: &mut Vec<(DefId, OpenItem)>,
+    ) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) => {
+                    self.define_member(id, item.name, DefKind::Fn);
+                }
+                Item::Struct(item) => {
+                    self.define_member(id, item.name, DefKind::Struct);
+                }
+                Item::Const(item) => {
+                    self.define_member(id, item.name, DefKind::Const);
+                }
+                Item::Define(DefineItem::Fn(item)) => {
+                    self.define_member(id, item.name, DefKind::Fn);
+                }
+                Item::Define(DefineItem::Const(item)) => {
+                    self.define_member(id, item.name, DefKind::Const);
+                }
+                Item::Enum(item) => {
+                    let enum_id = self.define_member(id, item.name, DefKind::Enum);
+
+                    for variant in &item.variants {
+                        let name = match &variant.node {
+                            EnumVariants::Tuple { name, .. }
+                            | EnumVariants::Struct { name, .. } => *name,
+                        };
+                        self.define_member(enum_id, name, DefKind::Variant);
+                    }
+                }
+                Item::Section(item) => {
+                    let section = &item.sections.node;
+                    let child = self.define_member(id, section.name, DefKind::Section);
+                    self.collect_section(child, section, opens);
+                }
+                Item::Open(open) => opens.push((id, open.clone())),
+                Item::Error(_) => (),
+            }
+        }
+    }
+
+    /// Section level `open`s may depend on each other, so they are retried
+    /// until no more can be resolved, and only then reported.
+    fn resolve_opens(&mut self, mut pending: Vec<(DefId, OpenItem)>) {
+        loop {
+            let before = pending.len();
+
+            pending.retain(|(section, open)| {
+                self.section = *section;
+                !self.resolve_open(open, false)
+            });
+
+            if pending.is_empty() || pending.len() == before {
+                break;
+            }
+        }
+
+        for (section, open) in pending {
+            self.section = section;
+            self.resolve_open(&open, true);
+        }
+    }
+
+    /// Returns whether the opened path resolved, errors in the imported
+    /// names themselves are always reported.
+    fn resolve_open(&mut self, open: &OpenItem, report: bool) -> bool {
+        let Some(target) = self.resolve_path(&open.path, report) else {
+            return false;
+        };
+
+        let last = open.path[open.path.len() - 1];
+        if open.imports.is_empty() {
+            let name = open.alias.unwrap_or(last);
+            self.bind_import(name, target);
+            return true;
+        }
+
+        for import in &open.imports {
+            match import.node {
+                ImportItem::SelfImport => self.bind_import(last.node.spanned(import.span), target),
+                ImportItem::Star => {
+                    if self.expect_namespace(target, last) {
+                        self.add_glob(target, import.span);
+                    }
+                }
+                ImportItem::Ident(name) => {
+                    let name = name.spanned(import.span);
+                    if let Some(def) = self.member(target, last, name, true) {
+                        self.res.names.insert(import.span, def);
+                        self.bind_import(name, def);
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    fn bind_import(&mut self, name: Spanned<Symbol>, def: DefId) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.names.insert(name.node, def);
+            return;
+        }
+
+        let ns = self
+            .res
+            .namespaces
+            .get_mut(&self.section)
+            .expect("namespace");
+        let clash = match ns.items.get(&name.node) {
+            Some(item) => Some(*item).filter(|item| *item != def),
+            None => ns
+                .imports
+                .insert(name.node, (def, name.span))
+                .filter(|(prev, _)| *prev != def)
+                .map(|(prev, _)| prev),
+        };
+
+        if let Some(prev) = clash {
+            let prev = self.res.def(prev).span;
+            self.duplicate(name, prev);
+        }
+    }
+
+    fn add_glob(&mut self, target: DefId, span: Span) {
+        match self.scopes.last_mut() {
+            Some(scope) => scope.globs.push((target, span)),
+            None => {
+                let ns = self
+                    .res
+                    .namespaces
+                    .get_mut(&self.section)
+                    .expect("namespace");
+                ns.globs.push((target, span));
+            }
+        }
+    }
+
+    fn expect_namespace(&mut self, def: DefId, name: Spanned<Symbol>) -> bool {
+        let kind = self.res.def(def).kind;
+        if !kind.is_namespace() {
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "expected section or enum, found {} `{}`",
+                    kind.describe(),
+                    name.node.as_str()
+                ))
+                .with_label(name.span.primary("not a section or enum")),
+            );
+        }
+
+        kind.is_namespace()
+    }
+
+    /// Resolves `a::b::c`. A leading `root` starts at the root section and
+    /// `self` at the current one, anything else is looked up lexically.
+    fn resolve_path(&mut self, path: &[Spanned<Symbol>], report: bool) -> Option<DefId> {
+        let first = path[0];
+
+        let mut def = match first.node.as_str() {
+            "root" => self.res.root,
+            "self" if path.len() > 1 => self.section,
+            _ => self.lookup(first, path.len() == 1, report)?,
+        };
+        self.res.names.insert(first.span, def);
+
+        for pair in path.windows(2) {
+            let (parent, segment) = (pair[0], pair[1]);
+
+            if report && !self.expect_namespace(def, parent) {
+                return None;
+            }
+
+            def = self.member(def, parent, segment, report)?;
+            self.res.names.insert(segment.span, def);
+        }
+
+        Some(def)
+    }
+
+    fn member(
+        &mut self,
+        parent: DefId,
+        parent_name: Spanned<Symbol>,
+        name: Spanned<Symbol>,
+        report: bool,
+    ) -> Option<DefId> {
+        let found = self.res.member(parent, name.node);
+
+        if found.is_none() && report {
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "cannot find `{}` in `{}`",
+                    name.node.as_str(),
+                    parent_name.node.as_str()
+                ))
+                .with_label(name.span.primary(format!(
+                    "not found in {} `{}`",
+                    self.res.def(parent).kind.describe(),
+                    parent_name.node.as_str()
+                ))),
+            );
+        }
+
+        found
+    }
+
+    /// Looks `name` up through the local scopes, then the enclosing sections
+    /// from the innermost outwards, then the builtins.
+    fn lookup(&mut self, name: Spanned<Symbol>, locals: bool, report: bool) -> Option<DefId> {
+        match self.try_lookup(name, locals, report) {
+            Ok(Some(def)) => Some(def),
+            Ok(None) => {
+                if report {
+                    self.bag.push(
+                        Diagnostic::error(format!(
+                            "cannot find `{}` in this scope",
+                            name.node.as_str()
+                        ))
+                        .with_label(name.span.primary("not found in this scope")),
+                    );
+                }
+                None
+            }
+            Err(()) => None,
+        }
+    }
+
+    /// `Err` means the name is ambiguous, which has already been reported.
+    fn try_lookup(
+        &mut self,
+        name: Spanned<Symbol>,
+        locals: bool,
+        report: bool,
+    ) -> Result<Option<DefId>, ()> {
+        for idx in (0..self.scopes.len()).rev() {
+            let scope = &self.scopes[idx];
+
+            if let Some(def) = scope.names.get(&name.node).copied()
+                && (locals || !matches!(self.res.def(def).kind, DefKind::Local { .. }))
+            {
+                return Ok(Some(def));
+            }
+
+            let globs = scope.globs.clone();
+            if let Some(def) = self.glob_lookup(&globs, name, report)? {
+                return Ok(Some(def));
+            }
+        }
+
+        let mut section = Some(self.section);
+        while let Some(id) = section {
+            let ns = &self.res.namespaces[&id];
+
+            if let Some(def) = ns.items.get(&name.node) {
+                return Ok(Some(*def));
+            }
+            if let Some((def, _)) = ns.imports.get(&name.node) {
+                return Ok(Some(*def));
+            }
+
+            let globs = ns.globs.clone();
+            if let Some(def) = self.glob_lookup(&globs, name, report)? {
+                return Ok(Some(def));
+            }
+
+            section = self.res.def(id).parent;
+        }
+
+        Ok(self.res.prelude.get(&name.node).copied())
+    }
+
+    fn glob_lookup(
+        &mut self,
+        globs: &[(DefId, Span)],
+        name: Spanned<Symbol>,
+        report: bool,
+    ) -> Result<Option<DefId>, ()> {
+        let mut found: Vec<(DefId, Span)> = Vec::new();
+
+        for (target, span) in globs {
+            if let Some(def) = self.res.member(*target, name.node)
+                && !found.iter().any(|(prev, _)| *prev == def)
+            {
+                found.push((def, *span));
+            }
+        }
+
+        match found.as_slice() {
+            [] => Ok(None),
+            [(def, _)] => Ok(Some(*def)),
+            [(_, first), (_, second), ..] => {
+                if report {
+                    self.bag.push(
+                        Diagnostic::error(format!("`{}` is ambiguous", name.node.as_str()))
+                            .with_label(name.span.primary("ambiguous name"))
+                            .with_label(first.secondary("it could refer to the item opened here"))
+                            .with_label(second.secondary("or to the item opened here")),
+                    );
+                }
+                Err(())
+            }
+        }
+    }
+
+    fn resolve_section(&mut self, id: DefId, section: &Section) {
+        for item in &section.items {
+            self.section = id;
+
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.resolve_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    if let Some(ty) = &item.ty {
+                        self.resolve_ty(ty);
+                    }
+                    self.resolve_expr(&item.value);
+                }
+                Item::Struct(item) => {
+                    for field in &item.fields {
+                        self.resolve_ty(&field.node.ty);
+                    }
+                }
+                Item::Enum(item) => {
+                    for variant in &item.variants {
+                        match &variant.node {
+                            EnumVariants::Tuple { types, .. } => {
+                                types.iter().for_each(|ty| self.resolve_ty(ty))
+                            }
+                            EnumVariants::Struct { fields, .. } => fields
+                                .iter()
+                                .for_each(|field| self.resolve_ty(&field.node.ty)),
+                        }
+                    }
+                }
+                Item::Section(item) => {
+                    let section = &item.sections.node;
+                    if let Some(child) = self.res.lookup(section.name.span) {
+                        self.resolve_section(child, section);
+                    }
+                }
+                Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn resolve_fn(&mut self, item: &FnItem) {
+        self.scopes.push(Scope::default());
+
+        for param in &item.params {
+            self.resolve_ty(&param.node.ty);
+            self.bind_local(param.node.name, false);
+        }
+        if let Some(ret) = &item.ret {
+            self.resolve_ty(ret);
+        }
+
+        self.resolve_block(&item.body);
+        self.scopes.pop();
+    }
+
+    fn bind_local(&mut self, name: Spanned<Symbol>, mutable: bool) -> DefId {
+        let id = self.define(name.node, DefKind::Local { mutable }, name.span, None);
+        self.res.names.insert(name.span, id);
+        self.scopes
+            .last_mut()
+            .expect("locals are bound inside a scope")
+            .names
+            .insert(name.node, id);
+        id
+    }
+
+    fn resolve_block(&mut self, block: &BlockExpr) {
+        self.scopes.push(Scope::default());
+
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let {
+                    mutable,
+                    name,
+                    ty,
+                    value,
+                } => {
+                    // the value is resolved first, so `let x = x + 1;` sees the old `x`
+                    if let Some(value) = value {
+                        self.resolve_expr(value);
+                    }
+                    if let Some(ty) = ty {
+                        self.resolve_ty(ty);
+                    }
+                    self.bind_local(*name, *mutable);
+                }
+                Stmt::Open(open) => {
+                    self.resolve_open(open, true);
+                }
+                Stmt::Expr(expr) | Stmt::Semi(expr) => self.resolve_expr(expr),
+            }
+        }
+
+        if let Some(expr) = &block.expr {
+            self.resolve_boxed(expr);
+        }
+
+        self.scopes.pop();
+    }
+
+    fn resolve_boxed(&mut self, expr: &Spanned<Box<Expr>>) {
+        self.resolve_expr_at(&expr.node, expr.span);
+    }
+
+    fn resolve_expr(&mut self, expr: &Spanned<Expr>) {
+        self.resolve_expr_at(&expr.node, expr.span);
+    }
+
+    fn resolve_expr_at(&mut self, expr: &Expr, span: Span) {
+        match expr {
+            Expr::Literal(_) | Expr::Continue | Expr::Error(_) => (),
+            Expr::Variable(name) => {
+                if let Some(def) = self.lookup((*name).spanned(span), true, true) {
+                    self.res.names.insert(span, def);
+                }
+            }
+            Expr::Path(path) => {
+                if let Some(def) = self.resolve_path(path, true) {
+                    self.res.names.insert(span, def);
+                }
+            }
+            Expr::Tuple(elems) | Expr::Array(elems) => {
+                elems.iter().for_each(|e| self.resolve_expr(e))
+            }
+            Expr::Range { start, end, .. } => {
+                start.iter().chain(end).for_each(|e| self.resolve_boxed(e))
+            }
+            Expr::Binary(lhs, _, rhs) | Expr::Index(lhs, rhs) => {
+                self.resolve_boxed(lhs);
+                self.resolve_boxed(rhs);
+            }
+            Expr::Assign { target, value } | Expr::AssignEq { target, value, .. } => {
+                self.resolve_boxed(target);
+                self.resolve_boxed(value);
+            }
+            Expr::Postfix(operand, _) | Expr::Prefix(_, operand) | Expr::Field(operand, _) => {
+                self.resolve_boxed(operand)
+            }
+            Expr::Cast(operand, ty) => {
+                self.resolve_boxed(operand);
+                self.resolve_ty(ty);
+            }
+            Expr::Call(callee, args) => {
+                self.resolve_boxed(callee);
+                args.iter().for_each(|arg| self.resolve_expr(arg));
+            }
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                self.resolve_boxed(receiver);
+                args.iter().for_each(|arg| self.resolve_expr(arg));
+
+                // `a.f(b)` calls `f(a, b)` when such a function is in scope;
+                // otherwise it is left for the type checker to explain
+                if let Ok(Some(def)) = self.try_lookup(*method, false, false)
+                    && matches!(self.res.def(def).kind, DefKind::Fn | DefKind::Builtin)
+                {
+                    self.res.names.insert(method.span, def);
+                }
+            }
+            Expr::Closure { params, ret, body } => {
+                self.scopes.push(Scope::default());
+                for param in params {
+                    if let Some(ty) = &param.node.ty {
+                        self.resolve_ty(ty);
+                    }
+                    self.bind_local(param.node.name, false);
+                }
+                if let Some(ret) = ret {
+                    self.resolve_ty(ret);
+                }
+                self.resolve_boxed(body);
+                self.scopes.pop();
+            }
+            Expr::If { cond, then, else_ } => {
+                self.resolve_boxed(cond);
+                self.resolve_boxed(then);
+                if let Some(else_) = else_ {
+                    self.resolve_boxed(else_);
+                }
+            }
+            Expr::Loop(body) => self.resolve_block(&body.node),
+            Expr::Block(body) => self.resolve_block(&body.node),
+            Expr::While { cond, body } => {
+                self.resolve_boxed(cond);
+                self.resolve_block(body);
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => {
+                self.resolve_boxed(iter);
+                self.scopes.push(Scope::default());
+                self.bind_pattern(pattern);
+                self.resolve_block(body);
+                self.scopes.pop();
+            }
+            Expr::Break(value) | Expr::Return(value) => {
+                if let Some(value) = value {
+                    self.resolve_boxed(value);
+                }
+            }
+            Expr::StructInit { path, fields } => {
+                if let Some(def) = self.resolve_path(path, true) {
+                    self.expect_kind(def, path, &[DefKind::Struct, DefKind::Variant]);
+                    self.res.names.insert(path_span(path), def);
+                }
+                fields.iter().for_each(|f| self.resolve_expr(&f.node.value));
+            }
+            Expr::Match { scrutinee, arms } => {
+                self.resolve_boxed(scrutinee);
+
+                for arm in arms {
+                    self.scopes.push(Scope::default());
+                    self.bind_pattern(&arm.pattern);
+                    self.resolve_expr(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+        }
+    }
+
+    /// Binds the variables a pattern introduces. A lone name that refers to a
+    /// variant or constant in scope is matched against instead of bound.
+    fn bind_pattern(&mut self, pattern: &Spanned<Pattern>) {
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Error(_) => (),
+            Pattern::Variable(name) => {
+                if let Ok(Some(def)) = self.try_lookup(*name, false, false)
+                    && matches!(self.res.def(def).kind, DefKind::Variant | DefKind::Const)
+                {
+                    self.res.names.insert(name.span, def);
+                } else {
+                    self.bind_local(*name, false);
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                if let Some(def) = self.resolve_path(path, true) {
+                    self.expect_kind(def, path, &[DefKind::Variant, DefKind::Struct]);
+                    self.res.names.insert(path_span(path), def);
+                }
+                elems.iter().for_each(|elem| self.bind_pattern(elem));
+            }
+            Pattern::Struct { path, fields } => {
+                if let Some(def) = self.resolve_path(path, true) {
+                    self.expect_kind(def, path, &[DefKind::Variant, DefKind::Struct]);
+                    self.res.names.insert(path_span(path), def);
+                }
+                fields
+                    .iter()
+                    .for_each(|field| self.bind_pattern(&field.node.pattern));
+            }
+        }
+    }
+
+    fn expect_kind(&mut self, def: DefId, path: &[Spanned<Symbol>], expected: &[DefKind]) {
+        let kind = self.res.def(def).kind;
+        if expected.contains(&kind) {
+            return;
+        }
+
+        let expected: Vec<_> = expected.iter().map(|k| k.describe()).collect();
+        self.bag.push(
+            Diagnostic::error(format!(
+                "expected {}, found {} `{}`",
+                expected.join(" or "),
+                kind.describe(),
+                self.res.def(def).name.as_str()
+            ))
+            .with_label(path_span(path).primary(format!("not a {}", expected.join(" or ")))),
+        );
+    }
+
+    fn resolve_ty(&mut self, ty: &Spanned<Ty>) {
+        let canonical = self.canonical_ty(&ty.node, ty.span);
+        self.res.types.insert(ty.span, canonical);
+    }
+
+    /// Rewrites the named types in `ty` to their path from the root, so that
+    /// later passes can compare them without knowing where they were written.
+    fn canonical_ty(&mut self, ty: &Ty, span: Span) -> Ty {
+        match ty {
+            Ty::Path(path) => match self.resolve_ty_path(path, span) {
+                Some(path) => Ty::Path(path),
+                None => ty.clone(),
+            },
+            Ty::Generic { path, args } => Ty::Generic {
+                path: self
+                    .resolve_ty_path(path, span)
+                    .unwrap_or_else(|| path.clone()),
+                args: args
+                    .iter()
+                    .map(|arg| self.canonical_ty(arg, span))
+                    .collect(),
+            },
+            Ty::Ref { mutable, inner } => Ty::Ref {
+                mutable: *mutable,
+                inner: Box::new(self.canonical_ty(inner, span)),
+            },
+            Ty::Optional(inner) => Ty::Optional(Box::new(self.canonical_ty(inner, span))),
+            Ty::Tuple(elems) => {
+                Ty::Tuple(elems.iter().map(|e| self.canonical_ty(e, span)).collect())
+            }
+            Ty::Array(elem, len) => Ty::Array(Box::new(self.canonical_ty(elem, span)), *len),
+            Ty::Slice(elem) => Ty::Slice(Box::new(self.canonical_ty(elem, span))),
+            Ty::Fn { params, ret } => Ty::Fn {
+                params: params.iter().map(|p| self.canonical_ty(p, span)).collect(),
+                ret: Box::new(self.canonical_ty(ret, span)),
+            },
+            Ty::Int(_)
+            | Ty::Float(_)
+            | Ty::Bool
+            | Ty::Char
+            | Ty::Str
+            | Ty::Infer
+            | Ty::Var(_)
+            | Ty::Never => ty.clone(),
+        }
+    }
+
+    fn resolve_ty_path(&mut self, path: &[Symbol], span: Span) -> Option<Vec<Symbol>> {
+        // inner types carry no spans of their own, so errors point at the annotation
+        let segments: Vec<_> = path.iter().map(|s| (*s).spanned(span)).collect();
+        let names = mem::take(&mut self.res.names);
+        let def = self.resolve_path(&segments, true);
+        self.res.names = names;
+
+        let def = def?;
+        let kind = self.res.def(def).kind;
+        if !kind.is_type() {
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "expected type, found {} `{}`",
+                    kind.describe(),
+                    self.res.def(def).name.as_str()
+                ))
+                .with_label(span.primary("not a type")),
+            );
+            return None;
+        }
+
+        Some(self.res.path_of(def))
+    }
+}
+
+/// The span from the first to the last segment of a path.
+pub fn path_span(path: &[Spanned<Symbol>]) -> Span {
+    path[0].span + path[path.len() - 1].span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, source::SourceMap};
+
+    fn resolve(src: &str) -> (Resolution, DiagnosticsBag, String) {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", src);
+        let mut parser = Parser::new(id, &map[id]);
+        let script = parser.parse_script();
+        assert!(parser.bag.is_empty(), "parse errors: {:?}", parser.bag.0);
+
+        let mut resolver = Resolver::new();
+        let res = resolver.resolve_script(&script);
+        (res, resolver.bag, src.to_owned())
+    }
+
+    fn resolve_ok(src: &str) -> (Resolution, String) {
+        let (res, bag, src) = resolve(src);
+        assert!(bag.is_empty(), "unexpected diagnostics: {:?}", bag.0);
+        (res, src)
+    }
+
+    /// The definition the `nth` occurrence of `needle` in the source refers to.
+    fn def_at<'a>(res: &'a Resolution, src: &str, needle: &str, nth: usize) -> &'a Def {
+        let lo = src.match_indices(needle).nth(nth).expect("needle").0;
+        let (_, def) = res
+            .names
+            .iter()
+            .find(|(span, _)| span.lo == lo && span.hi == lo + needle.len())
+            .unwrap_or_else(|| panic!("`{needle}` #{nth} is unresolved"));
+        res.def(*def)
+    }
+
+    fn path_str(res: &Resolution, def: &Def) -> String {
+        let id = res.defs.iter().position(|d| std::ptr::eq(d, def)).unwrap();
+        res.path_of(DefId(id as u32))
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+
+    #[test]
+    fn test_resolve_nested_section_paths() {
+        let (res, src) = resolve_ok(
+            "section math {
+                fn add(x: u8, y: u8): u8 { internal::identity }
+                section internal { const identity = 0; fn up(): u8 { self::identity } }
+             }
+             fn main() { math::internal::identity; root::math::add(1, 2); }",
+        );
+
+        let add_body = def_at(&res, &src, "internal::identity", 0);
+        assert_eq!(add_body.kind, DefKind::Const);
+        assert_eq!(path_str(&res, add_body), "math::internal::identity");
+
+        assert_eq!(def_at(&res, &src, "self::identity", 0).kind, DefKind::Const);
+        assert_eq!(
+            def_at(&res, &src, "math::internal::identity", 0).kind,
+            DefKind::Const
+        );
+        assert_eq!(def_at(&res, &src, "root::math::add", 0).kind, DefKind::Fn);
+        assert_eq!(
+            def_at(&res, &src, "x", 0).kind,
+            DefKind::Local { mutable: false }
+        );
+    }
+
+    #[test]
+    fn test_resolve_open_imports() {
+        let (res, src) = resolve_ok(
+            "open math::add;
+             open math::internal as inner;
+             open math::{self, sub};
+             fn main() { add; inner::identity; math::mul; sub; }
+             section math {
+                fn add() {} fn sub() {} fn mul() {}
+                section internal { const identity = 0; }
+             }",
+        );
+
+        assert_eq!(def_at(&res, &src, "add", 1).kind, DefKind::Fn);
+        assert_eq!(
+            def_at(&res, &src, "inner::identity", 0).kind,
+            DefKind::Const
+        );
+        assert_eq!(def_at(&res, &src, "math::mul", 0).kind, DefKind::Fn);
+        assert_eq!(path_str(&res, def_at(&res, &src, "sub", 1)), "math::sub");
+    }
+
+    #[test]
+    fn test_resolve_glob_and_chained_imports() {
+        let (res, src) = resolve_ok(
+            "open b::*;
+             section a { open root::b::inner; fn f() { inner::g(); } }
+             section b { fn from_b() {} section inner { fn g() {} } }
+             fn main() { from_b(); }",
+        );
+
+        assert_eq!(
+            path_str(&res, def_at(&res, &src, "inner::g", 0)),
+            "b::inner::g"
+        );
+        assert_eq!(def_at(&res, &src, "from_b", 1).kind, DefKind::Fn);
+    }
+
+    #[test]
+    fn test_resolve_shadowing() {
+        let (res, src) = resolve_ok(
+            "const x = 1;
+             fn main() {
+                x;
+                let x = x;
+                let mut x = x;
+                x
+             }",
+        );
+
+        assert_eq!(def_at(&res, &src, "x", 1).kind, DefKind::Const);
+        assert_eq!(def_at(&res, &src, "x", 3).kind, DefKind::Const);
+        assert_eq!(
+            def_at(&res, &src, "x", 5).kind,
+            DefKind::Local { mutable: false }
+        );
+        assert_eq!(
+            def_at(&res, &src, "x", 6).kind,
+            DefKind::Local { mutable: true }
+        );
+    }
+
+    #[test]
+    fn test_resolve_enum_variants_in_patterns() {
+        let (res, src) = resolve_ok(
+            "enum Shape { Circle(f32), Empty }
+             open Shape::*;
+             fn area(s: Shape): f32 {
+                match s { Shape::Circle(radius) => radius, Empty => 0.0, other => 1.0 }
+             }",
+        );
+
+        assert_eq!(
+            def_at(&res, &src, "Shape::Circle", 0).kind,
+            DefKind::Variant
+        );
+        assert_eq!(
+            def_at(&res, &src, "radius", 1).kind,
+            DefKind::Local { mutable: false }
+        );
+        assert_eq!(def_at(&res, &src, "Empty", 1).kind, DefKind::Variant);
+        assert_eq!(
+            def_at(&res, &src, "other", 0).kind,
+            DefKind::Local { mutable: false }
+        );
+    }
+
+    #[test]
+    fn test_resolve_canonical_types() {
+        let (res, src) = resolve_ok(
+            "section geo { struct Point { x: f32 } }
+             open geo::Point;
+             fn f(p: &Point?): [geo::Point] { f(p) }",
+        );
+
+        let lo = src.find("&Point?").unwrap();
+        let (_, ty) = res.types.iter().find(|(span, _)| span.lo == lo).unwrap();
+        assert_eq!(ty.to_string(), "&geo::Point?");
+    }
+
+    #[test]
+    fn test_report_unresolved_names() {
+        let (_, bag, _) = resolve(
+            "open nowhere::*;
+             fn main() { missing; math::nope; let y: Ghost = 1; }
+             section math {}",
+        );
+
+        let messages: Vec<_> = bag.0.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "cannot find `nowhere` in this scope",
+                "cannot find `missing` in this scope",
+                "cannot find `nope` in `math`",
+                "cannot find `Ghost` in this scope",
+            ]
+        );
+        assert!(bag.0.iter().all(|d| !d.labels.is_empty()));
+    }
+
+    #[test]
+    fn test_report_ambiguous_glob_imports() {
+        let (_, bag, _) = resolve(
+            "open a::*;
+             open b::*;
+             section a { fn f() {} }
+             section b { fn f() {} }
+             fn main() { f(); }",
+        );
+
+        assert_eq!(bag.len(), 1);
+        assert_eq!(bag.0[0].message, "`f` is ambiguous");
+        assert_eq!(bag.0[0].labels.len(), 3);
+    }
+
+    #[test]
+    fn test_report_duplicate_definitions() {
+        let (_, bag, _) = resolve(
+            "fn f() {}
+             struct f { x: u8 }
+             open s::g;
+             fn g() {}
+             section s { fn g() {} }",
+        );
+
+        assert_eq!(bag.len(), 2);
+        assert!(
+            bag.0
+                .iter()
+                .all(|d| d.message.contains("defined multiple times"))
+        );
+    }
+}
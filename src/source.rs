@@ -1,4 +1,4 @@
-use std::{fs, ops::Index};
+use std::{fs, io, ops::Index};
 
 use crate::span::Span;
 
@@ -10,18 +10,10 @@ pub struct Source {
 }
 
 impl Source {
-    pub fn new(name: impl Into<String>) -> Self {
+    pub fn read(name: impl Into<String>) -> io::Result<Self> {
         let name = name.into();
-        let content = fs::read_to_string(&name).expect("failed to read file");
-        let line_offsets = std::iter::once(0)
-            .chain(content.as_str().match_indices('\n').map(|(i, _)| i + 1))
-            .collect();
-
-        Self {
-            name,
-            content,
-            line_offsets,
-        }
+        let content = fs::read_to_string(&name)?;
+        Ok(Self::with_content(name, content))
     }
 
     pub fn with_content(name: impl Into<String>, content: impl Into<String>) -> Self {
@@ -38,30 +30,57 @@ impl Source {
         }
     }
 
+    /// The one based lines and columns of `span`, where columns count chars.
     pub fn get_pos(&self, span: Span) -> Position {
         let start_line = self.get_offset_line(span.lo);
         let end_line = self.get_offset_line(span.hi);
 
         if start_line == end_line {
-            let col = span.lo - self.line_offsets[start_line];
-            Position::Single(start_line + 1, col + 1)
+            Position::Single(start_line + 1, self.column(start_line, span.lo))
         } else {
             let lines = (start_line..=end_line)
                 .map(|line| {
-                    let line_start = self.line_offsets[line];
                     let col = match line {
-                        l if l == start_line => span.lo - line_start,
-                        l if l == end_line => span.hi - line_start,
-                        _ => 0,
+                        l if l == start_line => self.column(line, span.lo),
+                        l if l == end_line => self.column(line, span.hi),
+                        _ => 1,
                     };
-                    (line + 1, col + 1)
+                    (line + 1, col)
                 })
                 .collect();
             Position::Multi { lines }
         }
     }
 
-    fn get_offset_line(&self, offset: usize) -> usize {
+    /// The text under `span`, clamped to the end of the file.
+    pub fn snippet(&self, span: Span) -> &str {
+        let hi = span.hi.min(self.content.len());
+        self.content.get(span.lo.min(hi)..hi).unwrap_or("")
+    }
+
+    fn column(&self, line: usize, offset: usize) -> usize {
+        let start = self.line_offsets[line];
+        let before = self.content.get(start..offset);
+        before.map_or(offset - start, |text| text.chars().count()) + 1
+    }
+
+    /// The text of a zero based line, without its line break.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self
+            .line_offsets
+            .get(line)
+            .copied()
+            .unwrap_or(self.content.len());
+        let end = self
+            .line_offsets
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.content.len());
+        self.content[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    /// The zero based line `offset` is on.
+    pub fn get_offset_line(&self, offset: usize) -> usize {
         match self.line_offsets.binary_search(&offset) {
             Ok(idx) => idx,
             Err(idx) => idx - 1,
@@ -69,7 +88,7 @@ impl Source {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct FileId(pub u32);
 
 pub struct SourceMap {
@@ -81,10 +100,11 @@ impl SourceMap {
         Self { source: Vec::new() }
     }
 
-    pub fn insert(&mut self, name: impl Into<String>) -> FileId {
-        let source = Source::new(name);
+    /// Reads the file at `name` and adds it to the map.
+    pub fn insert(&mut self, name: impl Into<String>) -> io::Result<FileId> {
+        let source = Source::read(name)?;
         self.source.push(source);
-        FileId(self.source.len() as u32 - 1)
+        Ok(FileId(self.source.len() as u32 - 1))
     }
 
     pub fn with_content(&mut self, name: impl Into<String>, content: impl Into<String>) -> FileId {
@@ -163,6 +183,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_pos_counts_chars() {
+        let source = Source::with_content("pos.ak", "let ޢަދަދު = x;");
+        let span = Span {
+            id: FileId(0),
+            lo: 19,
+            hi: 20,
+        };
+
+        assert_eq!(&source.content[span.to_range()], "x");
+        assert_eq!(source.get_pos(span), Position::Single(1, 14));
+    }
+
     #[test]
     fn test_source_map_insert() {
         let mut map = SourceMap::fresh();
@@ -174,6 +207,15 @@ mod tests {
         assert_eq!(source.line_offsets, vec![0, 13]);
     }
 
+    #[test]
+    fn test_insert_missing_file_is_an_error() {
+        let mut map = SourceMap::fresh();
+        let err = map.insert("does/not/exist.ak").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(map.source.is_empty());
+    }
+
     #[test]
     fn test_get_offset_line_binary_search() {
         let content = "a\nb\nc\nd\n";
@@ -1,8 +1,11 @@
-use std::ops::{self, Index, IndexMut, Range};
+use std::{
+    fmt,
+    ops::{self, Index, IndexMut, Range},
+};
 
 use crate::source::FileId;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Span {
     pub id: FileId,
     pub lo: usize,
@@ -43,6 +46,13 @@ impl Span {
     }
 }
 
+/// As `file:lo..hi`, so dumped trees stay readable.
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}..{}", self.id.0, self.lo, self.hi)
+    }
+}
+
 impl Index<Span> for str {
     type Output = str;
 
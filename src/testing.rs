@@ -0,0 +1,75 @@
+//! The front end the tests of every later stage start from, and the
+//! interpreter their output is checked against.
+
+use crate::{
+    ast::Script,
+    diagnostics::Diagnostic,
+    interp::Interpreter,
+    parser::Parser,
+    resolve::{Resolution, Resolver},
+    source::SourceMap,
+    typeck::{TypeChecker, TypeckResults},
+};
+
+/// A script that parsed, resolved and type checked without errors.
+pub struct Checked {
+    pub map: SourceMap,
+    pub script: Script,
+    pub res: Resolution,
+    pub types: TypeckResults,
+}
+
+/// Runs the front end on `src`, failing the test on any diagnostic.
+pub fn check(src: &str) -> Checked {
+    let mut map = SourceMap::fresh();
+    let id = map.with_content("test.ak", src);
+    let mut parser = Parser::new(id, &map[id]);
+    let script = parser.parse_script();
+    assert!(parser.bag.is_empty(), "parse errors: {:?}", parser.bag.0);
+
+    let mut resolver = Resolver::new();
+    let res = resolver.resolve_script(&script);
+    assert!(
+        resolver.bag.is_empty(),
+        "resolve errors: {:?}",
+        resolver.bag.0
+    );
+
+    let mut checker = TypeChecker::new(&res);
+    let types = checker.check_script(&script);
+    assert!(checker.bag.is_empty(), "type errors: {:?}", checker.bag.0);
+
+    Checked {
+        map,
+        script,
+        res,
+        types,
+    }
+}
+
+impl Checked {
+    /// What the interpreter prints running the script, and the errors that
+    /// stopped it.
+    pub fn interpret(&self) -> (String, Vec<Diagnostic>) {
+        let mut out = Vec::new();
+        let bag = {
+            let mut interp = Interpreter::new(&self.res, &self.types, &mut out);
+            interp.run(&self.script);
+            interp.bag
+        };
+        (String::from_utf8(out).unwrap(), bag.0)
+    }
+}
+
+/// Runs `f` on a thread with room for the deep recursion the tree walking
+/// evaluators need.
+pub fn with_big_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn_scoped(scope, f)
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::span::Span;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -15,6 +17,7 @@ pub enum TokenKind {
     ColonColon, // ::
     Semi,       // ;
     Question,   // ?
+    FatArrow,   // =>
 
     LParen,   // (
     RParen,   // )
@@ -27,6 +30,7 @@ pub enum TokenKind {
     Minus,    // -
     Star,     // *
     Slash,    // /
+    Percent,  // %
     Bang,     // !
     LShift,   // <<
     RShift,   // >>
@@ -38,16 +42,17 @@ pub enum TokenKind {
     PlusPlus,
     MinusMinus,
 
-    Eq,       // =
-    PlusEq,   // +=
-    MinusEq,  // -=
-    StarEq,   // *=
-    SlashEq,  // /=
-    LShiftEq, // <<=
-    RShiftEq, // >>=
-    PipeEq,   // |=
-    AndEq,    // &=
-    CaretEq,  // ^=
+    Eq,        // =
+    PlusEq,    // +=
+    MinusEq,   // -=
+    StarEq,    // *=
+    SlashEq,   // /=
+    PercentEq, // %=
+    LShiftEq,  // <<=
+    RShiftEq,  // >>=
+    PipeEq,    // |=
+    AndEq,     // &=
+    CaretEq,   // ^=
 
     Lt,     // <
     Gt,     // >
@@ -60,6 +65,7 @@ pub enum TokenKind {
     Else,
     While,
     For,
+    In,
     Loop,
     Fn,
     Return,
@@ -68,6 +74,7 @@ pub enum TokenKind {
     Continue,
     True,
     False,
+    None,
     Struct,
     Enum,
     Match,
@@ -88,6 +95,16 @@ pub enum TokenKind {
     CharLiteral,
     StringLiteral,
 
+    DocComment,      // `/// ..`, documents the item after it
+    InnerDocComment, // `//! ..`, documents the section it is in
+
+    // trivia, only produced by a lossless lexer
+    Whitespace,   // spaces and tabs
+    Newline,      // `\n` or `\r\n`
+    LineComment,  // `// ..`
+    BlockComment, // `/* .. */`
+    Unknown,      // text that makes no token, already reported
+
     Ident, // identifier
 }
 
@@ -112,6 +129,48 @@ impl TokenKind {
         (Self::IntLiteral..=Self::StringLiteral).contains(self)
     }
 
+    #[inline]
+    pub fn is_trivia(&self) -> bool {
+        (Self::Whitespace..=Self::Unknown).contains(self)
+    }
+
+    /// Whether a token of this kind can start an expression.
+    pub fn can_begin_expr(&self) -> bool {
+        matches!(
+            self,
+            Self::IntLiteral
+                | Self::FloatLiteral
+                | Self::CharLiteral
+                | Self::StringLiteral
+                | Self::True
+                | Self::False
+                | Self::None
+                | Self::Ident
+                | Self::Root
+                | Self::SelfLow
+                | Self::SelfUp
+                | Self::LParen
+                | Self::LBracket
+                | Self::LBrace
+                | Self::If
+                | Self::Loop
+                | Self::While
+                | Self::For
+                | Self::Match
+                | Self::Break
+                | Self::Continue
+                | Self::Return
+                | Self::Minus
+                | Self::Bang
+                | Self::Star
+                | Self::And
+                | Self::DotDot
+                | Self::DotDotEq
+                | Self::Pipe
+                | Self::PipePipe
+        )
+    }
+
     #[inline(always)]
     pub fn correspond(content: &str) -> Self {
         match content {
@@ -119,6 +178,7 @@ impl TokenKind {
             "else" => TokenKind::Else,
             "while" => TokenKind::While,
             "for" => TokenKind::For,
+            "in" => TokenKind::In,
             "loop" => TokenKind::Loop,
             "fn" => TokenKind::Fn,
             "return" => TokenKind::Return,
@@ -127,6 +187,7 @@ impl TokenKind {
             "continue" => TokenKind::Continue,
             "true" => TokenKind::True,
             "false" => TokenKind::False,
+            "none" => TokenKind::None,
             "struct" => TokenKind::Struct,
             "enum" => TokenKind::Enum,
             "match" => TokenKind::Match,
@@ -146,6 +207,105 @@ impl TokenKind {
     }
 }
 
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Eof => return write!(f, "end of file"),
+            Self::Ident => return write!(f, "identifier"),
+            Self::IntLiteral => return write!(f, "integer literal"),
+            Self::FloatLiteral => return write!(f, "float literal"),
+            Self::CharLiteral => return write!(f, "char literal"),
+            Self::StringLiteral => return write!(f, "string literal"),
+            Self::DocComment => return write!(f, "doc comment"),
+            Self::InnerDocComment => return write!(f, "inner doc comment"),
+            Self::Whitespace => return write!(f, "whitespace"),
+            Self::Newline => return write!(f, "new line"),
+            Self::LineComment | Self::BlockComment => return write!(f, "comment"),
+            Self::Unknown => return write!(f, "unknown text"),
+
+            Self::Dot => ".",
+            Self::DotDot => "..",
+            Self::DotDotDot => "...",
+            Self::DotDotEq => "..=",
+            Self::Comma => ",",
+            Self::Colon => ":",
+            Self::ColonColon => "::",
+            Self::Semi => ";",
+            Self::Question => "?",
+            Self::FatArrow => "=>",
+            Self::LParen => "(",
+            Self::RParen => ")",
+            Self::LBrace => "{",
+            Self::RBrace => "}",
+            Self::LBracket => "[",
+            Self::RBracket => "]",
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Star => "*",
+            Self::Slash => "/",
+            Self::Percent => "%",
+            Self::Bang => "!",
+            Self::LShift => "<<",
+            Self::RShift => ">>",
+            Self::Pipe => "|",
+            Self::And => "&",
+            Self::Caret => "^",
+            Self::PipePipe => "||",
+            Self::AndAnd => "&&",
+            Self::PlusPlus => "++",
+            Self::MinusMinus => "--",
+            Self::Eq => "=",
+            Self::PlusEq => "+=",
+            Self::MinusEq => "-=",
+            Self::StarEq => "*=",
+            Self::SlashEq => "/=",
+            Self::PercentEq => "%=",
+            Self::LShiftEq => "<<=",
+            Self::RShiftEq => ">>=",
+            Self::PipeEq => "|=",
+            Self::AndEq => "&=",
+            Self::CaretEq => "^=",
+            Self::Lt => "<",
+            Self::Gt => ">",
+            Self::LtEq => "<=",
+            Self::GtEq => ">=",
+            Self::EqEq => "==",
+            Self::BangEq => "!=",
+
+            Self::If => "if",
+            Self::Else => "else",
+            Self::While => "while",
+            Self::For => "for",
+            Self::In => "in",
+            Self::Loop => "loop",
+            Self::Fn => "fn",
+            Self::Return => "return",
+            Self::Let => "let",
+            Self::Const => "const",
+            Self::Continue => "continue",
+            Self::True => "true",
+            Self::False => "false",
+            Self::None => "none",
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::Match => "match",
+            Self::Break => "break",
+            Self::Pub => "pub",
+            Self::Define => "define",
+            Self::Section => "section",
+            Self::Script => "script",
+            Self::Open => "open",
+            Self::Root => "root",
+            Self::SelfUp => "Self",
+            Self::SelfLow => "self",
+            Self::Mut => "mut",
+            Self::As => "as",
+        };
+
+        write!(f, "`{s}`")
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Token {
     pub kind: TokenKind,
@@ -166,6 +326,10 @@ impl Token {
         self.kind.is_literal()
     }
 
+    pub fn is_trivia(&self) -> bool {
+        self.kind.is_trivia()
+    }
+
     pub fn is_eof(&self) -> bool {
         self.kind == TokenKind::Eof
     }
@@ -185,6 +349,7 @@ impl Lexicable for char {
             'b' => Some('\x08'),
             'a' => Some('\x07'),
             '0' => Some('\0'),
+            '\\' | '\'' | '"' => Some(*self),
             _ => None,
         }
     }
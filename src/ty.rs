@@ -1,2 +1,289 @@
-#[derive(Debug, Clone)]
-pub enum Ty {}
+use std::fmt;
+
+use crate::interner::Symbol;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntTy {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl IntTy {
+    pub const ALL: [IntTy; 8] = [
+        Self::U8,
+        Self::U16,
+        Self::U32,
+        Self::U64,
+        Self::I8,
+        Self::I16,
+        Self::I32,
+        Self::I64,
+    ];
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Self::I8 | Self::I16 | Self::I32 | Self::I64)
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::U8 | Self::I8 => 8,
+            Self::U16 | Self::I16 => 16,
+            Self::U32 | Self::I32 => 32,
+            Self::U64 | Self::I64 => 64,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatTy {
+    F32,
+    F64,
+}
+
+impl FloatTy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ty {
+    Int(IntTy),
+    Float(FloatTy),
+    Bool,
+    Char,
+    Str,
+    /// `&T`, `&mut T`
+    Ref {
+        mutable: bool,
+        inner: Box<Ty>,
+    },
+    /// `T?`
+    Optional(Box<Ty>),
+    /// `(A, B)`, `()` is the unit type
+    Tuple(Vec<Ty>),
+    /// `[T; N]`
+    Array(Box<Ty>, u64),
+    /// `[T]`
+    Slice(Box<Ty>),
+    /// `fn(A, B): R`
+    Fn {
+        params: Vec<Ty>,
+        ret: Box<Ty>,
+    },
+    /// `Type`, `section::Type`
+    Path(Vec<Symbol>),
+    /// `Type<A, B>`
+    Generic {
+        path: Vec<Symbol>,
+        args: Vec<Ty>,
+    },
+    /// `_`, left for inference
+    Infer,
+    /// an inference variable, only seen while type checking
+    Var(u32),
+    /// the type of expressions that never produce a value, like `return`
+    Never,
+}
+
+impl Ty {
+    pub fn unit() -> Self {
+        Self::Tuple(Vec::new())
+    }
+
+    pub fn is_unit(&self) -> bool {
+        matches!(self, Self::Tuple(elems) if elems.is_empty())
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Self::Int(_) | Self::Float(_))
+    }
+
+    /// The type behind any number of references.
+    pub fn peel_refs(&self) -> &Self {
+        match self {
+            Self::Ref { inner, .. } => inner.peel_refs(),
+            _ => self,
+        }
+    }
+
+    /// The builtin type a single-segment path names, if any.
+    pub fn primitive(name: &str) -> Option<Self> {
+        let ty = match name {
+            "bool" => Self::Bool,
+            "char" => Self::Char,
+            "str" => Self::Str,
+            "f32" => Self::Float(FloatTy::F32),
+            "f64" => Self::Float(FloatTy::F64),
+            _ => {
+                return IntTy::ALL
+                    .into_iter()
+                    .find(|int| int.name() == name)
+                    .map(Self::Int);
+            }
+        };
+
+        Some(ty)
+    }
+}
+
+fn write_path(f: &mut fmt::Formatter<'_>, path: &[Symbol]) -> fmt::Result {
+    for (idx, segment) in path.iter().enumerate() {
+        if idx != 0 {
+            write!(f, "::")?;
+        }
+        write!(f, "{}", segment.as_str())?;
+    }
+
+    Ok(())
+}
+
+/// Names an inference variable while a type is displayed.
+type VarNamer<'a> = &'a dyn Fn(&mut fmt::Formatter<'_>, u32) -> fmt::Result;
+
+/// A type displayed with inference variables named by `var`.
+pub struct TyDisplay<'a> {
+    ty: &'a Ty,
+    var: VarNamer<'a>,
+}
+
+impl Ty {
+    /// Displays the type with `var` naming its inference variables, which
+    /// otherwise show as `?0`, `?1`, ...
+    pub fn display_with<'a>(&'a self, var: VarNamer<'a>) -> TyDisplay<'a> {
+        TyDisplay { ty: self, var }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, tys: &[Ty], var: VarNamer<'_>) -> fmt::Result {
+    for (idx, ty) in tys.iter().enumerate() {
+        if idx != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", ty.display_with(var))?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for TyDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let var = self.var;
+        match self.ty {
+            Ty::Int(int) => write!(f, "{}", int.name()),
+            Ty::Float(float) => write!(f, "{}", float.name()),
+            Ty::Bool => write!(f, "bool"),
+            Ty::Char => write!(f, "char"),
+            Ty::Str => write!(f, "str"),
+            Ty::Ref { mutable, inner } => {
+                write!(
+                    f,
+                    "&{}{}",
+                    if *mutable { "mut " } else { "" },
+                    inner.display_with(var)
+                )
+            }
+            Ty::Optional(inner) => match inner.as_ref() {
+                // `&T?` reads as `&(T?)`, so the reference needs grouping
+                Ty::Ref { .. } | Ty::Fn { .. } => write!(f, "({})?", inner.display_with(var)),
+                _ => write!(f, "{}?", inner.display_with(var)),
+            },
+            Ty::Tuple(elems) => {
+                write!(f, "(")?;
+                write_list(f, elems, var)?;
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Ty::Array(elem, len) => write!(f, "[{}; {len}]", elem.display_with(var)),
+            Ty::Slice(elem) => write!(f, "[{}]", elem.display_with(var)),
+            Ty::Fn { params, ret } => {
+                write!(f, "fn(")?;
+                write_list(f, params, var)?;
+                write!(f, ")")?;
+                if !ret.is_unit() {
+                    write!(f, ": {}", ret.display_with(var))?;
+                }
+                Ok(())
+            }
+            Ty::Path(path) => write_path(f, path),
+            Ty::Generic { path, args } => {
+                write_path(f, path)?;
+                write!(f, "<")?;
+                write_list(f, args, var)?;
+                write!(f, ">")
+            }
+            Ty::Infer => write!(f, "_"),
+            Ty::Var(idx) => var(f, *idx),
+            Ty::Never => write!(f, "!"),
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display_with(&|f, idx| write!(f, "?{idx}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interner::Internable;
+
+    #[test]
+    fn test_primitive_names() {
+        for int in IntTy::ALL {
+            assert_eq!(Ty::primitive(int.name()), Some(Ty::Int(int)));
+        }
+
+        assert_eq!(Ty::primitive("f64"), Some(Ty::Float(FloatTy::F64)));
+        assert_eq!(Ty::primitive("str"), Some(Ty::Str));
+        assert_eq!(Ty::primitive("u128"), None);
+        assert_eq!(Ty::primitive("Point"), None);
+    }
+
+    #[test]
+    fn test_int_ty_properties() {
+        assert!(IntTy::I16.is_signed());
+        assert!(!IntTy::U64.is_signed());
+        assert_eq!(IntTy::U32.bits(), 32);
+    }
+
+    #[test]
+    fn test_display_nested() {
+        let ty = Ty::Fn {
+            params: vec![Ty::Optional(Box::new(Ty::Ref {
+                mutable: false,
+                inner: Box::new(Ty::Path(vec!["math".intern(), "Vector".intern()])),
+            }))],
+            ret: Box::new(Ty::Tuple(vec![Ty::Bool, Ty::Slice(Box::new(Ty::Char))])),
+        };
+
+        assert_eq!(ty.to_string(), "fn((&math::Vector)?): (bool, [char])");
+        assert!(Ty::unit().is_unit());
+    }
+}
@@ -0,0 +1,2193 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, mem,
+};
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, EnumVariants, Expr, FnItem, Item, Literal,
+        Pattern, PostfixOp, PrefixOp, Script, Section, Spanned, Stmt,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::{Internable, Symbol},
+    resolve::{DefId, DefKind, Resolution, path_span},
+    span::Span,
+    ty::{FloatTy, IntTy, Ty},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnSig {
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariantShape {
+    Unit,
+    Tuple(Vec<Ty>),
+    Struct(Vec<(Symbol, Ty)>),
+}
+
+/// Everything later passes need to know about types. Expressions are keyed
+/// by their span, like names in [`Resolution`].
+#[derive(Debug, Clone, Default)]
+pub struct TypeckResults {
+    pub exprs: HashMap<Span, Ty>,
+    pub locals: HashMap<DefId, Ty>,
+    pub fns: HashMap<DefId, FnSig>,
+    pub consts: HashMap<DefId, Ty>,
+    pub structs: HashMap<DefId, Vec<(Symbol, Ty)>>,
+    /// the variants of each enum, in declaration order
+    pub enums: HashMap<DefId, Vec<DefId>>,
+    pub variants: HashMap<DefId, VariantShape>,
+}
+
+impl TypeckResults {
+    pub fn expr(&self, span: Span) -> &Ty {
+        &self.exprs[&span]
+    }
+}
+
+/// The type of `a..b`, `a..=b`, `a..` and `..b`.
+pub fn range_ty(elem: Ty) -> Ty {
+    Ty::Generic {
+        path: vec!["Range".intern()],
+        args: vec![elem],
+    }
+}
+
+/// The element type of a range type.
+pub fn range_elem(ty: &Ty) -> Option<&Ty> {
+    match ty {
+        Ty::Generic { path, args } if path.len() == 1 && path[0].as_str() == "Range" => {
+            args.first()
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VarKind {
+    General,
+    /// an integer or a float, from arithmetic on otherwise unknown types
+    Numeric,
+    Int,
+    Float,
+}
+
+#[derive(Debug, Clone)]
+struct Var {
+    kind: VarKind,
+    binding: Option<Ty>,
+}
+
+struct LoopCtx {
+    /// `None` for `while` and `for`, which cannot break with a value
+    value: Option<Ty>,
+    /// whether a `break` leaves this loop
+    broke: bool,
+}
+
+/// A write `check_place` checks a place for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Assign,
+    Increment,
+    Decrement,
+    BorrowMut,
+}
+
+impl Access {
+    /// "cannot assign to <what>", with `what` where the verb wants it.
+    fn cannot(self, what: &str) -> String {
+        match self {
+            Self::Assign => format!("cannot assign to {what}"),
+            Self::Increment => format!("cannot increment {what}"),
+            Self::Decrement => format!("cannot decrement {what}"),
+            Self::BorrowMut => format!("cannot borrow {what} as mutable"),
+        }
+    }
+}
+
+struct ReturnCtx {
+    ty: Ty,
+    because: Option<Span>,
+}
+
+struct IntLiteral {
+    span: Span,
+    value: u64,
+    negative: bool,
+    ty: Ty,
+}
+
+/// Infers and checks the types of a resolved script.
+///
+/// Inference is unification based: every unknown type starts as a variable
+/// and is refined by the expressions it meets. Closures are not generalised,
+/// so a closure has a single type fixed by its uses. Integer literals default
+/// to `i32` and float literals to `f64` when nothing else constrains them.
+pub struct TypeChecker<'a> {
+    res: &'a Resolution,
+    pub bag: DiagnosticsBag,
+    results: TypeckResults,
+    vars: Vec<Var>,
+    const_items: HashMap<DefId, &'a ConstItem>,
+    checking_consts: HashSet<DefId>,
+    returns: Vec<ReturnCtx>,
+    loops: Vec<LoopCtx>,
+    /// bindings whose type must be inferred, reported when it cannot be
+    inferred: Vec<(DefId, Span)>,
+    /// immutable bindings declared without a value, which may be assigned once
+    deferred: HashSet<DefId>,
+    literals: Vec<IntLiteral>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(res: &'a Resolution) -> Self {
+        Self {
+            res,
+            bag: DiagnosticsBag::new(),
+            results: TypeckResults::default(),
+            vars: Vec::new(),
+            const_items: HashMap::new(),
+            checking_consts: HashSet::new(),
+            returns: Vec::new(),
+            loops: Vec::new(),
+            inferred: Vec::new(),
+            deferred: HashSet::new(),
+            literals: Vec::new(),
+        }
+    }
+
+    pub fn check_script(&mut self, script: &'a Script) -> TypeckResults {
+        self.collect_section(&script.root);
+        self.check_section(&script.root);
+        self.finish()
+    }
+
+    // ---------------------------------------------------------------- items
+
+    fn collect_section(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => {
+                    let Some(def) = self.res.lookup(item.name.span) else {
+                        continue;
+                    };
+                    let params = item
+                        .params
+                        .iter()
+                        .map(|param| self.annotation(&param.node.ty))
+                        .collect();
+                    let ret = match &item.ret {
+                        Some(ret) => self.annotation(ret),
+                        None => Ty::unit(),
+                    };
+                    self.results.fns.insert(def, FnSig { params, ret });
+                }
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        self.const_items.insert(def, item);
+                    }
+                }
+                Item::Struct(item) => {
+                    let Some(def) = self.res.lookup(item.name.span) else {
+                        continue;
+                    };
+                    let fields = item
+                        .fields
+                        .iter()
+                        .map(|field| (field.node.name.node, self.annotation(&field.node.ty)))
+                        .collect();
+                    self.results.structs.insert(def, fields);
+                }
+                Item::Enum(item) => {
+                    let Some(def) = self.res.lookup(item.name.span) else {
+                        continue;
+                    };
+                    let mut variants = Vec::new();
+
+                    for variant in &item.variants {
+                        let (name, shape) = match &variant.node {
+                            EnumVariants::Tuple { name, types } if types.is_empty() => {
+                                (name, VariantShape::Unit)
+                            }
+                            EnumVariants::Tuple { name, types } => (
+                                name,
+                                VariantShape::Tuple(
+                                    types.iter().map(|ty| self.annotation(ty)).collect(),
+                                ),
+                            ),
+                            EnumVariants::Struct { name, fields } => (
+                                name,
+                                VariantShape::Struct(
+                                    fields
+                                        .iter()
+                                        .map(|f| (f.node.name.node, self.annotation(&f.node.ty)))
+                                        .collect(),
+                                ),
+                            ),
+                        };
+
+                        if let Some(variant) = self.res.lookup(name.span) {
+                            variants.push(variant);
+                            self.results.variants.insert(variant, shape);
+                        }
+                    }
+
+                    self.results.enums.insert(def, variants);
+                }
+                Item::Section(item) => self.collect_section(&item.sections.node),
+                Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn check_section(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.check_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    if let Some(def) = self.res.lookup(item.name.span) {
+                        self.const_ty(def, item.name.span);
+                    }
+                }
+                Item::Section(item) => self.check_section(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn check_fn(&mut self, item: &FnItem) {
+        let Some(sig) = self
+            .res
+            .lookup(item.name.span)
+            .and_then(|def| self.results.fns.get(&def))
+            .cloned()
+        else {
+            return;
+        };
+
+        for (param, ty) in item.params.iter().zip(&sig.params) {
+            self.bind(param.node.name.span, ty.clone());
+        }
+
+        let because = item.ret.as_ref().map(|ret| ret.span);
+        self.returns.push(ReturnCtx {
+            ty: sig.ret.clone(),
+            because,
+        });
+
+        let body = self.check_block(&item.body, Some((&sig.ret, because)));
+        if self.coerce(&body, &sig.ret).is_err() {
+            let label = match because {
+                Some(ret) => ret.secondary(format!(
+                    "expected `{}` because of the return type",
+                    self.display(&sig.ret)
+                )),
+                None => item.name.span.secondary("no return type means `()`"),
+            };
+            self.bag.push(
+                Diagnostic::error("mismatched types")
+                    .with_label(item.name.span.primary(format!(
+                        "the body of `{}` evaluates to `{}`",
+                        item.name.node.as_str(),
+                        self.display(&body)
+                    )))
+                    .with_label(label),
+            );
+        }
+
+        self.returns.pop();
+    }
+
+    /// Consts are checked on first use so that they may refer to each other
+    /// in any order.
+    fn const_ty(&mut self, def: DefId, span: Span) -> Ty {
+        if let Some(ty) = self.results.consts.get(&def) {
+            return ty.clone();
+        }
+
+        let Some(item) = self.const_items.get(&def).copied() else {
+            return self.fresh(VarKind::General);
+        };
+
+        if !self.checking_consts.insert(def) {
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "cycle detected when evaluating `{}`",
+                    item.name.node.as_str()
+                ))
+                .with_label(span.primary("used here"))
+                .with_label(item.name.span.secondary("while evaluating this constant")),
+            );
+            return self.fresh(VarKind::General);
+        }
+
+        // a const body is not inside the function that uses it
+        let returns = mem::take(&mut self.returns);
+        let loops = mem::take(&mut self.loops);
+
+        let ty = match &item.ty {
+            Some(ann) => {
+                let ty = self.annotation(ann);
+                self.check_expr_with(&item.value, &ty, Some(ann.span));
+                ty
+            }
+            None => self.check_expr(&item.value),
+        };
+
+        self.returns = returns;
+        self.loops = loops;
+        self.checking_consts.remove(&def);
+        self.results.consts.insert(def, ty.clone());
+        ty
+    }
+
+    /// The canonical form of a written type, with `_` made into a variable.
+    fn annotation(&mut self, ty: &Spanned<Ty>) -> Ty {
+        let ty = self
+            .res
+            .types
+            .get(&ty.span)
+            .cloned()
+            .unwrap_or_else(|| ty.node.clone());
+        self.instantiate(&ty)
+    }
+
+    fn instantiate(&mut self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Infer => self.fresh(VarKind::General),
+            Ty::Ref { mutable, inner } => Ty::Ref {
+                mutable: *mutable,
+                inner: Box::new(self.instantiate(inner)),
+            },
+            Ty::Optional(inner) => Ty::Optional(Box::new(self.instantiate(inner))),
+            Ty::Tuple(elems) => Ty::Tuple(elems.iter().map(|e| self.instantiate(e)).collect()),
+            Ty::Array(elem, len) => Ty::Array(Box::new(self.instantiate(elem)), *len),
+            Ty::Slice(elem) => Ty::Slice(Box::new(self.instantiate(elem))),
+            Ty::Fn { params, ret } => Ty::Fn {
+                params: params.iter().map(|p| self.instantiate(p)).collect(),
+                ret: Box::new(self.instantiate(ret)),
+            },
+            Ty::Generic { path, args } => Ty::Generic {
+                path: path.clone(),
+                args: args.iter().map(|a| self.instantiate(a)).collect(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    // ---------------------------------------------------------- unification
+
+    fn fresh(&mut self, kind: VarKind) -> Ty {
+        self.vars.push(Var {
+            kind,
+            binding: None,
+        });
+        Ty::Var(self.vars.len() as u32 - 1)
+    }
+
+    /// Follows variable bindings at the top of `ty`.
+    fn shallow(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(var) => match &self.vars[*var as usize].binding {
+                Some(bound) => self.shallow(bound),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    /// Replaces every bound variable in `ty`.
+    fn zonk(&self, ty: &Ty) -> Ty {
+        match self.shallow(ty) {
+            Ty::Ref { mutable, inner } => Ty::Ref {
+                mutable,
+                inner: Box::new(self.zonk(&inner)),
+            },
+            Ty::Optional(inner) => Ty::Optional(Box::new(self.zonk(&inner))),
+            Ty::Tuple(elems) => Ty::Tuple(elems.iter().map(|e| self.zonk(e)).collect()),
+            Ty::Array(elem, len) => Ty::Array(Box::new(self.zonk(&elem)), len),
+            Ty::Slice(elem) => Ty::Slice(Box::new(self.zonk(&elem))),
+            Ty::Fn { params, ret } => Ty::Fn {
+                params: params.iter().map(|p| self.zonk(p)).collect(),
+                ret: Box::new(self.zonk(&ret)),
+            },
+            Ty::Generic { path, args } => Ty::Generic {
+                path,
+                args: args.iter().map(|a| self.zonk(a)).collect(),
+            },
+            ty => ty,
+        }
+    }
+
+    fn kind(&self, ty: &Ty) -> Option<VarKind> {
+        match self.shallow(ty) {
+            Ty::Var(var) => Some(self.vars[var as usize].kind),
+            _ => None,
+        }
+    }
+
+    fn occurs(&self, var: u32, ty: &Ty) -> bool {
+        match self.shallow(ty) {
+            Ty::Var(other) => other == var,
+            Ty::Ref { inner, .. }
+            | Ty::Optional(inner)
+            | Ty::Slice(inner)
+            | Ty::Array(inner, _) => self.occurs(var, &inner),
+            Ty::Tuple(elems) | Ty::Generic { args: elems, .. } => {
+                elems.iter().any(|e| self.occurs(var, e))
+            }
+            Ty::Fn { params, ret } => {
+                params.iter().any(|p| self.occurs(var, p)) || self.occurs(var, &ret)
+            }
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, a: &Ty, b: &Ty) -> Result<(), ()> {
+        let (a, b) = (self.shallow(a), self.shallow(b));
+
+        match (&a, &b) {
+            (Ty::Never, _) | (_, Ty::Never) => Ok(()),
+            (Ty::Var(x), Ty::Var(y)) if x == y => Ok(()),
+            (Ty::Var(x), Ty::Var(y)) => {
+                let kind = match (self.vars[*x as usize].kind, self.vars[*y as usize].kind) {
+                    (VarKind::General, kind) | (kind, VarKind::General) => kind,
+                    (VarKind::Numeric, kind) | (kind, VarKind::Numeric) => kind,
+                    (x, y) if x == y => x,
+                    _ => return Err(()),
+                };
+                self.vars[*y as usize].kind = kind;
+                self.vars[*x as usize].binding = Some(b.clone());
+                Ok(())
+            }
+            (Ty::Var(var), ty) | (ty, Ty::Var(var)) => {
+                let fits = match self.vars[*var as usize].kind {
+                    VarKind::General => !self.occurs(*var, ty),
+                    VarKind::Numeric => ty.is_numeric(),
+                    VarKind::Int => matches!(ty, Ty::Int(_)),
+                    VarKind::Float => matches!(ty, Ty::Float(_)),
+                };
+                if !fits {
+                    return Err(());
+                }
+                self.vars[*var as usize].binding = Some(ty.clone());
+                Ok(())
+            }
+            (Ty::Int(x), Ty::Int(y)) if x == y => Ok(()),
+            (Ty::Float(x), Ty::Float(y)) if x == y => Ok(()),
+            (Ty::Bool, Ty::Bool) | (Ty::Char, Ty::Char) | (Ty::Str, Ty::Str) => Ok(()),
+            (Ty::Path(x), Ty::Path(y)) if x == y => Ok(()),
+            (
+                Ty::Ref {
+                    mutable: m1,
+                    inner: x,
+                },
+                Ty::Ref {
+                    mutable: m2,
+                    inner: y,
+                },
+            ) if m1 == m2 => self.unify(x, y),
+            (Ty::Optional(x), Ty::Optional(y)) | (Ty::Slice(x), Ty::Slice(y)) => self.unify(x, y),
+            (Ty::Array(x, n), Ty::Array(y, m)) if n == m => self.unify(x, y),
+            (Ty::Tuple(xs), Ty::Tuple(ys)) if xs.len() == ys.len() => self.unify_all(xs, ys),
+            (Ty::Generic { path: p, args: xs }, Ty::Generic { path: q, args: ys })
+                if p == q && xs.len() == ys.len() =>
+            {
+                self.unify_all(xs, ys)
+            }
+            (
+                Ty::Fn {
+                    params: xs,
+                    ret: r1,
+                },
+                Ty::Fn {
+                    params: ys,
+                    ret: r2,
+                },
+            ) if xs.len() == ys.len() => {
+                self.unify_all(xs, ys)?;
+                self.unify(r1, r2)
+            }
+            _ => Err(()),
+        }
+    }
+
+    fn unify_all(&mut self, xs: &[Ty], ys: &[Ty]) -> Result<(), ()> {
+        xs.iter().zip(ys).try_for_each(|(x, y)| self.unify(x, y))
+    }
+
+    /// Unifies, additionally allowing the implicit conversions of values to
+    /// the type a context expects: `T` to `T?`, `&mut T` to `&T` and
+    /// `&[T; N]` to `&[T]`.
+    fn coerce(&mut self, actual: &Ty, expected: &Ty) -> Result<(), ()> {
+        let (found, want) = (self.shallow(actual), self.shallow(expected));
+
+        match (&found, &want) {
+            (Ty::Optional(_) | Ty::Never, Ty::Optional(_)) => self.unify(&found, &want),
+            (Ty::Var(var), Ty::Optional(inner))
+                if self.vars[*var as usize].kind != VarKind::General =>
+            {
+                self.unify(&found, inner)
+            }
+            (Ty::Var(_), Ty::Optional(_)) => self.unify(&found, &want),
+            (_, Ty::Optional(inner)) => self.unify(&found, inner),
+            (
+                Ty::Ref {
+                    mutable: m1,
+                    inner: x,
+                },
+                Ty::Ref {
+                    mutable: m2,
+                    inner: y,
+                },
+            ) if *m1 || !*m2 => match (self.shallow(x), self.shallow(y)) {
+                (Ty::Array(x, _), Ty::Slice(y)) => self.unify(&x, &y),
+                _ => self.unify(x, y),
+            },
+            _ => self.unify(&found, &want),
+        }
+    }
+
+    /// A user facing rendering, with unresolved literals shown as such.
+    fn display(&self, ty: &Ty) -> String {
+        let name = |f: &mut fmt::Formatter<'_>, idx: u32| {
+            f.write_str(match self.vars[idx as usize].kind {
+                VarKind::General => "_",
+                VarKind::Numeric => "{number}",
+                VarKind::Int => "{integer}",
+                VarKind::Float => "{float}",
+            })
+        };
+        self.zonk(ty).display_with(&name).to_string()
+    }
+
+    fn mismatch(&mut self, span: Span, expected: &Ty, found: &Ty, because: Option<Span>) {
+        let mut diagnostic =
+            Diagnostic::error("mismatched types").with_label(span.primary(format!(
+                "expected `{}`, found `{}`",
+                self.display(expected),
+                self.display(found)
+            )));
+
+        if let Some(because) = because {
+            diagnostic = diagnostic.with_label(because.secondary("expected because of this"));
+        }
+
+        self.bag.push(diagnostic);
+    }
+
+    /// Records `actual` against `expected`, reporting a mismatch at `span`.
+    fn expect_ty(&mut self, span: Span, actual: &Ty, expected: &Ty, because: Option<Span>) {
+        if self.coerce(actual, expected).is_err() {
+            self.mismatch(span, expected, actual, because);
+        }
+    }
+
+    fn require(&mut self, span: Span, ty: &Ty, kind: VarKind, what: &str) -> bool {
+        let ok = match self.shallow(ty) {
+            Ty::Never => true,
+            Ty::Var(var) => {
+                let current = self.vars[var as usize].kind;
+                let merged = match (current, kind) {
+                    (VarKind::General, kind) => Some(kind),
+                    (current, VarKind::Numeric) if current != VarKind::General => Some(current),
+                    (VarKind::Numeric, kind) => Some(kind),
+                    (current, kind) if current == kind => Some(kind),
+                    _ => None,
+                };
+                if let Some(merged) = merged {
+                    self.vars[var as usize].kind = merged;
+                }
+                merged.is_some()
+            }
+            Ty::Int(_) => kind != VarKind::Float,
+            Ty::Float(_) => matches!(kind, VarKind::Numeric | VarKind::Float),
+            _ => false,
+        };
+
+        if !ok {
+            self.bag.push(
+                Diagnostic::error(format!("{what} on `{}`", self.display(ty))).with_label(
+                    span.primary(format!(
+                        "expected {}, found `{}`",
+                        match kind {
+                            VarKind::Int => "an integer",
+                            VarKind::Float => "a float",
+                            _ => "a number",
+                        },
+                        self.display(ty)
+                    )),
+                ),
+            );
+        }
+
+        ok
+    }
+
+    fn annotation_needed(&mut self, span: Span) -> Ty {
+        self.bag.push(
+            Diagnostic::error("type annotations needed")
+                .with_label(span.primary("type must be known at this point")),
+        );
+        self.fresh(VarKind::General)
+    }
+
+    // ---------------------------------------------------------- expressions
+
+    fn bind(&mut self, name: Span, ty: Ty) {
+        if let Some(def) = self.res.lookup(name) {
+            self.results.locals.insert(def, ty);
+        }
+    }
+
+    fn check_expr_with(&mut self, expr: &Spanned<Expr>, expected: &Ty, because: Option<Span>) {
+        let actual = self.infer(&expr.node, expr.span, Some(expected));
+        self.expect_ty(tail_span(&expr.node, expr.span), &actual, expected, because);
+    }
+
+    fn check_boxed_with(
+        &mut self,
+        expr: &Spanned<Box<Expr>>,
+        expected: &Ty,
+        because: Option<Span>,
+    ) {
+        let actual = self.infer(&expr.node, expr.span, Some(expected));
+        self.expect_ty(tail_span(&expr.node, expr.span), &actual, expected, because);
+    }
+
+    fn check_expr(&mut self, expr: &Spanned<Expr>) -> Ty {
+        self.infer(&expr.node, expr.span, None)
+    }
+
+    fn check_boxed(&mut self, expr: &Spanned<Box<Expr>>) -> Ty {
+        self.infer(&expr.node, expr.span, None)
+    }
+
+    /// Infers the type of `expr`. `expected` is only a hint, used to give
+    /// closure parameters their types; callers still coerce the result.
+    fn infer(&mut self, expr: &Expr, span: Span, expected: Option<&Ty>) -> Ty {
+        let ty = self.infer_inner(expr, span, expected);
+        self.results.exprs.insert(span, ty.clone());
+        ty
+    }
+
+    fn infer_inner(&mut self, expr: &Expr, span: Span, expected: Option<&Ty>) -> Ty {
+        match expr {
+            Expr::Literal(literal) => self.literal_ty(literal, span),
+            Expr::Variable(_) | Expr::Path(_) => match self.res.lookup(span) {
+                Some(def) => self.value_ty(def, span),
+                None => self.fresh(VarKind::General),
+            },
+            Expr::Tuple(elems) => Ty::Tuple(elems.iter().map(|e| self.check_expr(e)).collect()),
+            Expr::Array(elems) => {
+                let elem = self.fresh(VarKind::General);
+                let mut because = None;
+
+                for e in elems {
+                    self.check_expr_with(e, &elem, because);
+                    because.get_or_insert(e.span);
+                }
+
+                Ty::Array(Box::new(elem), elems.len() as u64)
+            }
+            Expr::Range { start, end, .. } => {
+                let elem = self.fresh(VarKind::General);
+                let mut because = None;
+
+                for bound in start.iter().chain(end) {
+                    self.check_boxed_with(bound, &elem, because);
+                    because.get_or_insert(bound.span);
+                }
+
+                range_ty(elem)
+            }
+            Expr::Binary(lhs, op, rhs) => self.binary(lhs, *op, rhs),
+            Expr::Prefix(op, operand) => self.prefix(*op, operand, span),
+            Expr::Postfix(operand, op) => {
+                let ty = self.check_boxed(operand);
+                let (verb, access) = match op.node {
+                    PostfixOp::Inc => ("increment", Access::Increment),
+                    PostfixOp::Dec => ("decrement", Access::Decrement),
+                };
+                self.require(op.span, &ty, VarKind::Int, &format!("cannot {verb}"));
+                self.check_place(operand, access);
+                ty
+            }
+            Expr::Call(callee, args) => {
+                let callee_ty = self.check_boxed(callee);
+                self.call(callee.span, callee_ty, None, args, span)
+            }
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let receiver_ty = self.check_boxed(receiver);
+
+                match self.res.lookup(method.span) {
+                    Some(def) => {
+                        let callee = self.value_ty(def, method.span);
+                        self.call(
+                            method.span,
+                            callee,
+                            Some((receiver, receiver_ty)),
+                            args,
+                            span,
+                        )
+                    }
+                    None => {
+                        self.bag.push(
+                            Diagnostic::error(format!(
+                                "no function `{}` in scope to call as a method",
+                                method.node.as_str()
+                            ))
+                            .with_label(method.span.primary(format!(
+                                "`a.{0}(..)` calls `{0}(a, ..)`",
+                                method.node.as_str()
+                            ))),
+                        );
+                        args.iter().for_each(|arg| _ = self.check_expr(arg));
+                        self.fresh(VarKind::General)
+                    }
+                }
+            }
+            Expr::Field(base, name) => {
+                let base_ty = self.check_boxed(base);
+                self.field(base.span, &base_ty, *name)
+            }
+            Expr::Index(base, index) => self.index(base, index),
+            Expr::Cast(operand, ty) => {
+                let from = self.check_boxed(operand);
+                let to = self.annotation(ty);
+                self.cast(operand.span, &from, &to, ty.span);
+                to
+            }
+            Expr::Closure { params, ret, body } => {
+                let hint = expected.map(|ty| self.shallow(ty));
+                let hinted = match &hint {
+                    Some(Ty::Fn { params: tys, .. }) if tys.len() == params.len() => Some(tys),
+                    _ => None,
+                };
+
+                let mut param_tys = Vec::new();
+                for (idx, param) in params.iter().enumerate() {
+                    let ty = match (&param.node.ty, hinted) {
+                        (Some(ann), _) => self.annotation(ann),
+                        (None, Some(tys)) => tys[idx].clone(),
+                        (None, None) => {
+                            let ty = self.fresh(VarKind::General);
+                            if let Some(def) = self.res.lookup(param.node.name.span) {
+                                self.inferred.push((def, param.node.name.span));
+                            }
+                            ty
+                        }
+                    };
+                    self.bind(param.node.name.span, ty.clone());
+                    param_tys.push(ty);
+                }
+
+                let ret_ty = match ret {
+                    Some(ann) => self.annotation(ann),
+                    None => self.fresh(VarKind::General),
+                };
+
+                let loops = mem::take(&mut self.loops);
+                self.returns.push(ReturnCtx {
+                    ty: ret_ty.clone(),
+                    because: ret.as_ref().map(|r| r.span),
+                });
+                self.check_boxed_with(body, &ret_ty, ret.as_ref().map(|r| r.span));
+                self.returns.pop();
+                self.loops = loops;
+
+                Ty::Fn {
+                    params: param_tys,
+                    ret: Box::new(ret_ty),
+                }
+            }
+            Expr::If { cond, then, else_ } => {
+                self.check_boxed_with(cond, &Ty::Bool, None);
+
+                match else_ {
+                    Some(else_) => {
+                        let then_ty = self.infer(&then.node, then.span, expected);
+                        let because = tail_span(&then.node, then.span);
+
+                        let else_ty = self.infer(&else_.node, else_.span, Some(&then_ty));
+                        let else_span = tail_span(&else_.node, else_.span);
+
+                        if matches!(self.shallow(&then_ty), Ty::Never) {
+                            else_ty
+                        } else {
+                            if self.coerce(&else_ty, &then_ty).is_err() {
+                                self.bag.push(
+                                    Diagnostic::error("`if` and `else` have incompatible types")
+                                        .with_label(else_span.primary(format!(
+                                            "expected `{}`, found `{}`",
+                                            self.display(&then_ty),
+                                            self.display(&else_ty)
+                                        )))
+                                        .with_label(because.secondary("expected because of this")),
+                                );
+                            }
+                            then_ty
+                        }
+                    }
+                    None => {
+                        self.check_boxed_with(then, &Ty::unit(), None);
+                        Ty::unit()
+                    }
+                }
+            }
+            Expr::Loop(body) => {
+                let value = self.fresh(VarKind::General);
+                self.loops.push(LoopCtx {
+                    value: Some(value.clone()),
+                    broke: false,
+                });
+                self.check_block(&body.node, None);
+                let ctx = self.loops.pop().expect("the loop pushed above");
+
+                if ctx.broke {
+                    value
+                } else {
+                    // nothing broke out, so the loop never finishes
+                    if let Ty::Var(var) = self.shallow(&value) {
+                        self.vars[var as usize].binding = Some(Ty::Never);
+                    }
+                    Ty::Never
+                }
+            }
+            Expr::While { cond, body } => {
+                self.check_boxed_with(cond, &Ty::Bool, None);
+                self.loops.push(LoopCtx {
+                    value: None,
+                    broke: false,
+                });
+                self.check_block(body, None);
+                self.loops.pop();
+                Ty::unit()
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => {
+                let iter_ty = self.check_boxed(iter);
+                let elem = self.iter_elem(iter, &iter_ty);
+                self.check_pattern(pattern, &elem);
+
+                self.loops.push(LoopCtx {
+                    value: None,
+                    broke: false,
+                });
+                self.check_block(body, None);
+                self.loops.pop();
+                Ty::unit()
+            }
+            Expr::Break(value) => {
+                if let Some(ctx) = self.loops.last_mut() {
+                    ctx.broke = true;
+                }
+                match (self.loops.last().map(|ctx| ctx.value.clone()), value) {
+                    (Some(Some(loop_ty)), Some(value)) => {
+                        self.check_boxed_with(value, &loop_ty, None)
+                    }
+                    (Some(Some(loop_ty)), None) => {
+                        self.expect_ty(span, &Ty::unit(), &loop_ty, None)
+                    }
+                    (Some(None), None) => (),
+                    (Some(None), Some(value)) => {
+                        self.check_boxed(value);
+                        self.bag.push(
+                            Diagnostic::error(
+                                "`break` with a value inside a `while` or `for` loop",
+                            )
+                            .with_label(span.primary("only `loop` can break with a value")),
+                        );
+                    }
+                    (None, value) => {
+                        if let Some(value) = value {
+                            self.check_boxed(value);
+                        }
+                        self.outside_loop("break", span);
+                    }
+                }
+
+                Ty::Never
+            }
+            Expr::Continue => {
+                if self.loops.is_empty() {
+                    self.outside_loop("continue", span);
+                }
+                Ty::Never
+            }
+            Expr::Return(value) => {
+                let Some(ctx) = self.returns.last() else {
+                    self.bag.push(
+                        Diagnostic::error("`return` outside of a function")
+                            .with_label(span.primary("cannot return from here")),
+                    );
+                    return Ty::Never;
+                };
+                let (ret, because) = (ctx.ty.clone(), ctx.because);
+
+                match value {
+                    Some(value) => self.check_boxed_with(value, &ret, because),
+                    None => self.expect_ty(span, &Ty::unit(), &ret, because),
+                }
+                Ty::Never
+            }
+            Expr::Block(block) => self.check_block(&block.node, None),
+            Expr::Assign { target, value } => {
+                let target_ty = self.check_boxed(target);
+                self.check_boxed_with(value, &target_ty, Some(target.span));
+                self.check_place(target, Access::Assign);
+                Ty::unit()
+            }
+            Expr::AssignEq { op, target, value } => {
+                let target_ty = self.check_boxed(target);
+                let value_ty = self.check_boxed(value);
+                self.binary_result(op.node, op.span, &target_ty, &value_ty, value.span);
+                self.check_place(target, Access::Assign);
+                Ty::unit()
+            }
+            Expr::StructInit { path, fields } => self.struct_init(path, fields),
+            Expr::Match { scrutinee, arms } => {
+                let scrutinee_ty = self.check_boxed(scrutinee);
+                let ty = self.fresh(VarKind::General);
+                let mut because = None;
+
+                for arm in arms {
+                    self.check_pattern(&arm.pattern, &scrutinee_ty);
+                    let arm_ty = self.infer(&arm.body.node, arm.body.span, expected);
+
+                    if self.coerce(&arm_ty, &ty).is_err() {
+                        self.bag.push(
+                            Diagnostic::error("`match` arms have incompatible types")
+                                .with_label(arm.body.span.primary(format!(
+                                    "expected `{}`, found `{}`",
+                                    self.display(&ty),
+                                    self.display(&arm_ty)
+                                )))
+                                .with_labels(
+                                    because.map(|b: Span| b.secondary("this arm sets the type")),
+                                ),
+                        );
+                    }
+
+                    if because.is_none() && !matches!(self.shallow(&arm_ty), Ty::Never) {
+                        because = Some(arm.body.span);
+                    }
+                }
+
+                if arms.is_empty() { Ty::Never } else { ty }
+            }
+            Expr::Error(_) => self.fresh(VarKind::General),
+        }
+    }
+
+    fn outside_loop(&mut self, what: &str, span: Span) {
+        self.bag.push(
+            Diagnostic::error(format!("`{what}` outside of a loop"))
+                .with_label(span.primary(format!("cannot `{what}` here"))),
+        );
+    }
+
+    fn literal_ty(&mut self, literal: &Literal, span: Span) -> Ty {
+        match literal {
+            Literal::UInt(value, suffix) => {
+                let ty = match suffix {
+                    Some(int) => Ty::Int(*int),
+                    None => self.fresh(VarKind::Int),
+                };
+                self.literals.push(IntLiteral {
+                    span,
+                    value: *value,
+                    negative: false,
+                    ty: ty.clone(),
+                });
+                ty
+            }
+            Literal::Int(_) => self.fresh(VarKind::Int),
+            Literal::Float(_, Some(float)) => Ty::Float(*float),
+            Literal::Float(_, None) => self.fresh(VarKind::Float),
+            Literal::Bool(_) => Ty::Bool,
+            Literal::Char(_) => Ty::Char,
+            Literal::String(_) => Ty::Str,
+            Literal::None => Ty::Optional(Box::new(self.fresh(VarKind::General))),
+        }
+    }
+
+    /// The type of a name used as a value.
+    fn value_ty(&mut self, def: DefId, span: Span) -> Ty {
+        let info = self.res.def(def);
+
+        match info.kind {
+            DefKind::Local { .. } => match self.results.locals.get(&def) {
+                Some(ty) => ty.clone(),
+                None => self.fresh(VarKind::General),
+            },
+            DefKind::Fn => match self.results.fns.get(&def) {
+                Some(sig) => Ty::Fn {
+                    params: sig.params.clone(),
+                    ret: Box::new(sig.ret.clone()),
+                },
+                None => self.fresh(VarKind::General),
+            },
+            DefKind::Const => self.const_ty(def, span),
+            DefKind::Builtin => match info.name.as_str() {
+                "assert" => Ty::Fn {
+                    params: vec![Ty::Bool],
+                    ret: Box::new(Ty::unit()),
+                },
+                // printing takes a value of any type, fresh at every use
+                _ => Ty::Fn {
+                    params: vec![self.fresh(VarKind::General)],
+                    ret: Box::new(Ty::unit()),
+                },
+            },
+            DefKind::Variant => {
+                let parent = info.parent.expect("variants belong to an enum");
+                let enum_ty = Ty::Path(self.res.path_of(parent));
+
+                match self.results.variants.get(&def) {
+                    Some(VariantShape::Unit) => enum_ty,
+                    Some(VariantShape::Tuple(tys)) => Ty::Fn {
+                        params: tys.clone(),
+                        ret: Box::new(enum_ty),
+                    },
+                    _ => self.not_a_value(def, span),
+                }
+            }
+            DefKind::Section | DefKind::Struct | DefKind::Enum => self.not_a_value(def, span),
+        }
+    }
+
+    fn not_a_value(&mut self, def: DefId, span: Span) -> Ty {
+        let info = self.res.def(def);
+        let hint = match info.kind {
+            DefKind::Struct | DefKind::Variant => "use `Name { field: value }` to build it",
+            _ => "not a value",
+        };
+
+        self.bag.push(
+            Diagnostic::error(format!(
+                "expected value, found {} `{}`",
+                info.kind.describe(),
+                info.name.as_str()
+            ))
+            .with_label(span.primary(hint)),
+        );
+        self.fresh(VarKind::General)
+    }
+
+    fn call(
+        &mut self,
+        callee_span: Span,
+        callee: Ty,
+        receiver: Option<(&Spanned<Box<Expr>>, Ty)>,
+        args: &[Spanned<Expr>],
+        span: Span,
+    ) -> Ty {
+        let callee = match self.shallow(&callee) {
+            Ty::Var(_) => {
+                let arity = args.len() + receiver.is_some() as usize;
+                let fn_ty = Ty::Fn {
+                    params: (0..arity).map(|_| self.fresh(VarKind::General)).collect(),
+                    ret: Box::new(self.fresh(VarKind::General)),
+                };
+                _ = self.unify(&callee, &fn_ty);
+                fn_ty
+            }
+            ty => ty,
+        };
+
+        let Ty::Fn { params, ret } = callee else {
+            if !matches!(callee, Ty::Never) {
+                self.bag.push(
+                    Diagnostic::error(format!(
+                        "expected function, found `{}`",
+                        self.display(&callee)
+                    ))
+                    .with_label(callee_span.primary("call expression requires a function")),
+                );
+            }
+            args.iter().for_each(|arg| _ = self.check_expr(arg));
+            return self.fresh(VarKind::General);
+        };
+
+        let mut params = params.iter();
+
+        if let Some((receiver, receiver_ty)) = receiver
+            && let Some(first) = params.next()
+        {
+            // the receiver is borrowed automatically when the function wants a reference
+            let found = match (self.shallow(first), self.shallow(&receiver_ty)) {
+                (Ty::Ref { mutable, .. }, found) if !matches!(found, Ty::Ref { .. }) => {
+                    if mutable {
+                        self.check_place(receiver, Access::BorrowMut);
+                    }
+                    Ty::Ref {
+                        mutable,
+                        inner: Box::new(receiver_ty.clone()),
+                    }
+                }
+                _ => receiver_ty.clone(),
+            };
+            self.expect_ty(receiver.span, &found, first, Some(callee_span));
+        }
+
+        let given = args.len();
+        let expected = params.len();
+        for (arg, param) in args.iter().zip(params) {
+            self.check_expr_with(arg, param, Some(callee_span));
+        }
+
+        if given != expected {
+            for arg in args.iter().skip(expected) {
+                self.check_expr(arg);
+            }
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "this function takes {expected} argument{} but {given} were supplied",
+                    if expected == 1 { "" } else { "s" }
+                ))
+                .with_label(span.primary(format!("expected {expected}, found {given}")))
+                .with_label(callee_span.secondary("called here")),
+            );
+        }
+
+        *ret
+    }
+
+    fn field(&mut self, base_span: Span, base: &Ty, name: Spanned<Symbol>) -> Ty {
+        let ty = self.zonk(base);
+
+        match ty.peel_refs() {
+            Ty::Var(_) => self.annotation_needed(base_span),
+            Ty::Path(path) => {
+                let field = self
+                    .res
+                    .def_by_path(path)
+                    .and_then(|def| self.results.structs.get(&def))
+                    .and_then(|fields| fields.iter().find(|(field, _)| *field == name.node))
+                    .map(|(_, ty)| ty.clone());
+
+                match field {
+                    Some(ty) => ty,
+                    None => self.no_field(&ty, name),
+                }
+            }
+            _ => self.no_field(&ty, name),
+        }
+    }
+
+    fn no_field(&mut self, ty: &Ty, name: Spanned<Symbol>) -> Ty {
+        self.bag.push(
+            Diagnostic::error(format!(
+                "no field `{}` on type `{}`",
+                name.node.as_str(),
+                self.display(ty)
+            ))
+            .with_label(name.span.primary("unknown field")),
+        );
+        self.fresh(VarKind::General)
+    }
+
+    fn index(&mut self, base: &Spanned<Box<Expr>>, index: &Spanned<Box<Expr>>) -> Ty {
+        let base_ty = self.check_boxed(base);
+        let index_ty = self.check_boxed(index);
+
+        let elem = match self.zonk(&base_ty).peel_refs() {
+            Ty::Array(elem, _) | Ty::Slice(elem) => (**elem).clone(),
+            Ty::Str => Ty::Str,
+            Ty::Var(_) => return self.annotation_needed(base.span),
+            ty => {
+                let ty = ty.clone();
+                self.bag.push(
+                    Diagnostic::error(format!(
+                        "cannot index into a value of type `{}`",
+                        self.display(&ty)
+                    ))
+                    .with_label(base.span.primary("not an array, slice or string")),
+                );
+                return self.fresh(VarKind::General);
+            }
+        };
+
+        if let Some(range) = range_elem(&self.zonk(&index_ty)) {
+            self.require(
+                index.span,
+                &range.clone(),
+                VarKind::Int,
+                "cannot slice with a range",
+            );
+            return match elem {
+                Ty::Str => Ty::Str,
+                elem => Ty::Slice(Box::new(elem)),
+            };
+        }
+
+        if elem == Ty::Str {
+            self.bag.push(
+                Diagnostic::error("strings cannot be indexed by position")
+                    .with_label(index.span.primary("use a range to take a substring")),
+            );
+            return self.fresh(VarKind::General);
+        }
+
+        self.require(index.span, &index_ty, VarKind::Int, "cannot index");
+        elem
+    }
+
+    fn iter_elem(&mut self, iter: &Spanned<Box<Expr>>, ty: &Ty) -> Ty {
+        let ty = self.zonk(ty);
+
+        if let Some(elem) = range_elem(&ty) {
+            if let Expr::Range { start: None, .. } = &*iter.node {
+                self.bag.push(
+                    Diagnostic::error("cannot iterate over a range without a start")
+                        .with_label(iter.span.primary("there is no first element")),
+                );
+            }
+            let elem = elem.clone();
+            self.require(iter.span, &elem, VarKind::Int, "cannot iterate a range");
+            return elem;
+        }
+
+        match ty.peel_refs() {
+            Ty::Array(elem, _) | Ty::Slice(elem) => (**elem).clone(),
+            Ty::Str => Ty::Char,
+            Ty::Var(_) => self.annotation_needed(iter.span),
+            Ty::Never => Ty::Never,
+            _ => {
+                self.bag.push(
+                    Diagnostic::error(format!("`{}` is not iterable", self.display(&ty)))
+                        .with_label(
+                            iter.span
+                                .primary("expected a range, array, slice or string"),
+                        ),
+                );
+                self.fresh(VarKind::General)
+            }
+        }
+    }
+
+    fn cast(&mut self, span: Span, from: &Ty, to: &Ty, to_span: Span) {
+        let kind = self.kind(from);
+        let from = self.zonk(from);
+
+        let ok = match (&from, to) {
+            _ if kind.is_some_and(|k| k != VarKind::General) => to.is_numeric(),
+            (from, to) if from.is_numeric() && to.is_numeric() => true,
+            (Ty::Bool | Ty::Char, Ty::Int(_)) => true,
+            (Ty::Int(IntTy::U8), Ty::Char) => true,
+            (Ty::Never, _) => true,
+            (from, to) => from == to,
+        };
+
+        if !ok {
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "cannot cast `{}` as `{}`",
+                    self.display(&from),
+                    self.display(to)
+                ))
+                .with_label(span.primary(format!("this is `{}`", self.display(&from))))
+                .with_label(to_span.secondary("only numbers, `bool` and `char` can be cast")),
+            );
+        }
+    }
+
+    fn binary(
+        &mut self,
+        lhs: &Spanned<Box<Expr>>,
+        op: Spanned<BinaryOp>,
+        rhs: &Spanned<Box<Expr>>,
+    ) -> Ty {
+        if op.node.is_logical() {
+            self.check_boxed_with(lhs, &Ty::Bool, None);
+            self.check_boxed_with(rhs, &Ty::Bool, None);
+            return Ty::Bool;
+        }
+
+        let lhs_ty = self.check_boxed(lhs);
+        let rhs_ty = self.check_boxed(rhs);
+        self.binary_result(op.node, op.span, &lhs_ty, &rhs_ty, rhs.span)
+    }
+
+    fn binary_result(
+        &mut self,
+        op: BinaryOp,
+        op_span: Span,
+        lhs: &Ty,
+        rhs: &Ty,
+        rhs_span: Span,
+    ) -> Ty {
+        if op.is_logical() {
+            self.expect_ty(op_span, lhs, &Ty::Bool, None);
+            self.expect_ty(rhs_span, rhs, &Ty::Bool, None);
+            return Ty::Bool;
+        }
+
+        // shifts accept any integer amount
+        if matches!(op, BinaryOp::Shl | BinaryOp::Shr) {
+            self.require(op_span, lhs, VarKind::Int, &format!("cannot apply `{op}`"));
+            self.require(rhs_span, rhs, VarKind::Int, &format!("cannot apply `{op}`"));
+            return lhs.clone();
+        }
+
+        if self.unify(rhs, lhs).is_err() {
+            self.mismatch(rhs_span, lhs, rhs, Some(op_span));
+        }
+
+        if op.is_comparison() {
+            return Ty::Bool;
+        }
+
+        let kind = if op.is_bitwise() {
+            VarKind::Int
+        } else {
+            VarKind::Numeric
+        };
+        self.require(op_span, lhs, kind, &format!("cannot apply `{op}`"));
+        lhs.clone()
+    }
+
+    fn prefix(&mut self, op: Spanned<PrefixOp>, operand: &Spanned<Box<Expr>>, span: Span) -> Ty {
+        match op.node {
+            PrefixOp::Neg => {
+                let ty = self.check_boxed(operand);
+                if let Expr::Literal(Literal::UInt(..)) = &*operand.node
+                    && let Some(literal) = self.literals.last_mut()
+                    && literal.span == operand.span
+                {
+                    literal.negative = true;
+                    literal.span = span;
+                }
+                self.require(op.span, &ty, VarKind::Numeric, "cannot negate");
+                ty
+            }
+            PrefixOp::Not => {
+                let ty = self.check_boxed(operand);
+                if self.shallow(&ty) != Ty::Bool {
+                    self.require(op.span, &ty, VarKind::Int, "cannot apply `!`");
+                }
+                ty
+            }
+            PrefixOp::Deref => {
+                let ty = self.check_boxed(operand);
+                match self.shallow(&ty) {
+                    Ty::Ref { inner, .. } => *inner,
+                    Ty::Var(_) => self.annotation_needed(operand.span),
+                    Ty::Never => Ty::Never,
+                    ty => {
+                        self.bag.push(
+                            Diagnostic::error(format!(
+                                "cannot dereference `{}`",
+                                self.display(&ty)
+                            ))
+                            .with_label(operand.span.primary("not a reference")),
+                        );
+                        self.fresh(VarKind::General)
+                    }
+                }
+            }
+            PrefixOp::Ref | PrefixOp::RefMut => {
+                let ty = self.check_boxed(operand);
+                let mutable = op.node == PrefixOp::RefMut;
+                if mutable {
+                    self.check_place(operand, Access::BorrowMut);
+                }
+                Ty::Ref {
+                    mutable,
+                    inner: Box::new(ty),
+                }
+            }
+        }
+    }
+
+    /// Reports writes through immutable bindings or shared references.
+    fn check_place(&mut self, place: &Spanned<Box<Expr>>, access: Access) {
+        match &*place.node {
+            Expr::Variable(name) => {
+                let Some(def) = self.res.lookup(place.span) else {
+                    return;
+                };
+                let info = self.res.def(def);
+
+                match info.kind {
+                    DefKind::Local { mutable: true } => (),
+                    DefKind::Local { mutable: false } if self.deferred.remove(&def) => (),
+                    DefKind::Local { mutable: false } => self.bag.push(
+                        Diagnostic::error(match access {
+                            Access::BorrowMut => format!(
+                                "{}, as it is not declared `mut`",
+                                access.cannot(&format!("`{}`", name.as_str()))
+                            ),
+                            _ => access.cannot(&format!("immutable variable `{}`", name.as_str())),
+                        })
+                        .with_label(place.span.primary(access.cannot("this")))
+                        .with_label(info.span.secondary(format!(
+                            "consider making this binding mutable: `mut {}`",
+                            name.as_str()
+                        ))),
+                    ),
+                    kind => self.bag.push(
+                        Diagnostic::error(access.cannot(&format!(
+                            "{} `{}`",
+                            kind.describe(),
+                            name.as_str()
+                        )))
+                        .with_label(place.span.primary("not a variable")),
+                    ),
+                }
+            }
+            Expr::Field(base, _) | Expr::Index(base, _) => {
+                match self.results.exprs.get(&base.span).map(|ty| self.zonk(ty)) {
+                    Some(Ty::Ref { mutable: false, .. }) => {
+                        self.behind_shared_ref(place.span, access)
+                    }
+                    Some(Ty::Ref { mutable: true, .. }) => (),
+                    _ => self.check_place(base, access),
+                }
+            }
+            Expr::Prefix(op, inner) if op.node == PrefixOp::Deref => {
+                if let Some(Ty::Ref { mutable: false, .. }) =
+                    self.results.exprs.get(&inner.span).map(|ty| self.zonk(ty))
+                {
+                    self.behind_shared_ref(place.span, access);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn behind_shared_ref(&mut self, span: Span, access: Access) {
+        self.bag.push(
+            Diagnostic::error(access.cannot("data behind a `&` reference"))
+                .with_label(span.primary("this is behind a shared reference")),
+        );
+    }
+
+    fn struct_init(
+        &mut self,
+        path: &[Spanned<Symbol>],
+        fields: &[Spanned<crate::ast::FieldInit>],
+    ) -> Ty {
+        let span = path_span(path);
+        let Some(def) = self.res.lookup(span) else {
+            fields
+                .iter()
+                .for_each(|f| _ = self.check_expr(&f.node.value));
+            return self.fresh(VarKind::General);
+        };
+        let info = self.res.def(def);
+
+        let (ty, expected) = match (info.kind, self.results.variants.get(&def)) {
+            (DefKind::Struct, _) => (
+                Ty::Path(self.res.path_of(def)),
+                self.results.structs.get(&def).cloned().unwrap_or_default(),
+            ),
+            (DefKind::Variant, Some(VariantShape::Struct(fields))) => (
+                Ty::Path(
+                    self.res
+                        .path_of(info.parent.expect("variants belong to an enum")),
+                ),
+                fields.clone(),
+            ),
+            _ => {
+                if info.kind == DefKind::Variant {
+                    self.bag.push(
+                        Diagnostic::error(format!(
+                            "variant `{}` has no named fields",
+                            info.name.as_str()
+                        ))
+                        .with_label(span.primary("not a struct variant")),
+                    );
+                }
+                fields
+                    .iter()
+                    .for_each(|f| _ = self.check_expr(&f.node.value));
+                return self.fresh(VarKind::General);
+            }
+        };
+
+        let mut seen = HashSet::new();
+        for field in fields {
+            let name = field.node.name;
+
+            match expected.iter().find(|(f, _)| *f == name.node) {
+                Some((_, field_ty)) => self.check_expr_with(&field.node.value, field_ty, None),
+                None => {
+                    self.check_expr(&field.node.value);
+                    self.bag.push(
+                        Diagnostic::error(format!(
+                            "`{}` has no field named `{}`",
+                            self.display(&ty),
+                            name.node.as_str()
+                        ))
+                        .with_label(name.span.primary("unknown field")),
+                    );
+                }
+            }
+
+            if !seen.insert(name.node) {
+                self.bag.push(
+                    Diagnostic::error(format!(
+                        "field `{}` specified more than once",
+                        name.node.as_str()
+                    ))
+                    .with_label(name.span.primary("used more than once")),
+                );
+            }
+        }
+
+        let missing: Vec<_> = expected
+            .iter()
+            .filter(|(f, _)| !seen.contains(f))
+            .map(|(f, _)| format!("`{}`", f.as_str()))
+            .collect();
+
+        if !missing.is_empty() {
+            self.bag.push(
+                Diagnostic::error(format!(
+                    "missing field{} {} in initializer of `{}`",
+                    if missing.len() == 1 { "" } else { "s" },
+                    missing.join(", "),
+                    self.display(&ty)
+                ))
+                .with_label(span.primary("missing fields")),
+            );
+        }
+
+        ty
+    }
+
+    fn check_block(&mut self, block: &BlockExpr, expected: Option<(&Ty, Option<Span>)>) -> Ty {
+        let mut diverges = false;
+
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let {
+                    name, ty, value, ..
+                } => {
+                    let annotated = ty.as_ref().map(|ann| (self.annotation(ann), ann.span));
+
+                    let ty = match (&annotated, value) {
+                        (Some((ty, because)), Some(value)) => {
+                            self.check_expr_with(value, ty, Some(*because));
+                            ty.clone()
+                        }
+                        (Some((ty, _)), None) => ty.clone(),
+                        (None, Some(value)) => self.check_expr(value),
+                        (None, None) => self.fresh(VarKind::General),
+                    };
+
+                    if let Some(def) = self.res.lookup(name.span) {
+                        if annotated.is_none() {
+                            self.inferred.push((def, name.span));
+                        }
+                        if value.is_none() {
+                            self.deferred.insert(def);
+                        }
+                    }
+
+                    diverges |= matches!(self.shallow(&ty), Ty::Never);
+                    self.bind(name.span, ty);
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => {
+                    let ty = self.check_expr(expr);
+                    diverges |= matches!(self.shallow(&ty), Ty::Never);
+                }
+            }
+        }
+
+        match (&block.expr, expected) {
+            (Some(tail), Some((expected, because))) => {
+                self.check_boxed_with(tail, expected, because);
+                self.results.exprs[&tail.span].clone()
+            }
+            (Some(tail), None) => self.check_boxed(tail),
+            (None, _) if diverges => Ty::Never,
+            (None, _) => Ty::unit(),
+        }
+    }
+
+    // ------------------------------------------------------------- patterns
+
+    fn check_pattern(&mut self, pattern: &Spanned<Pattern>, expected: &Ty) {
+        // patterns look through references and into optionals
+        let mut scrutinee = self.zonk(expected);
+        if !matches!(pattern.node, Pattern::Variable(_) | Pattern::Wildcard) {
+            scrutinee = scrutinee.peel_refs().clone();
+            if let (Ty::Optional(inner), false) = (&scrutinee, is_none_pattern(&pattern.node)) {
+                scrutinee = (**inner).clone();
+            }
+        }
+
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => (),
+            Pattern::Literal(literal) => {
+                let ty = self.literal_ty(&literal.node, literal.span);
+                self.results.exprs.insert(literal.span, ty.clone());
+                self.expect_ty(literal.span, &ty, &scrutinee, None);
+            }
+            Pattern::Variable(name) => match self.res.lookup(name.span) {
+                Some(def) if self.res.def(def).kind == DefKind::Const => {
+                    let ty = self.const_ty(def, name.span);
+                    self.expect_ty(name.span, &ty, &scrutinee, None);
+                }
+                Some(def) if self.res.def(def).kind == DefKind::Variant => {
+                    let ty = self.value_ty(def, name.span);
+                    self.expect_ty(name.span, &ty, &scrutinee, None);
+                }
+                _ => self.bind(name.span, expected.clone()),
+            },
+            Pattern::Tuple { path, elems } => {
+                let span = path_span(path);
+                let (enum_ty, tys) = match self.variant(span) {
+                    Some((ty, VariantShape::Tuple(tys))) => (ty, tys),
+                    // `Shape::Empty` is a path pattern without fields
+                    Some((ty, VariantShape::Unit)) if elems.is_empty() => (ty, Vec::new()),
+                    _ => {
+                        self.bad_pattern(span, "a tuple variant");
+                        return;
+                    }
+                };
+
+                self.expect_ty(span, &enum_ty, &scrutinee, None);
+                if tys.len() != elems.len() {
+                    self.bag.push(
+                        Diagnostic::error(format!(
+                            "this pattern has {} field{}, but the variant has {}",
+                            elems.len(),
+                            if elems.len() == 1 { "" } else { "s" },
+                            tys.len()
+                        ))
+                        .with_label(
+                            pattern
+                                .span
+                                .primary(format!("expected {} fields", tys.len())),
+                        ),
+                    );
+                }
+
+                for (elem, ty) in elems.iter().zip(tys) {
+                    self.check_pattern(elem, &ty);
+                }
+            }
+            Pattern::Struct { path, fields } => {
+                let span = path_span(path);
+                let (ty, expected_fields) = match self.res.lookup(span) {
+                    Some(def) if self.res.def(def).kind == DefKind::Struct => (
+                        Ty::Path(self.res.path_of(def)),
+                        self.results.structs.get(&def).cloned().unwrap_or_default(),
+                    ),
+                    _ => match self.variant(span) {
+                        Some((ty, VariantShape::Struct(fields))) => (ty, fields),
+                        _ => {
+                            self.bad_pattern(span, "a struct or struct variant");
+                            return;
+                        }
+                    },
+                };
+
+                self.expect_ty(span, &ty, &scrutinee, None);
+                for field in fields {
+                    match expected_fields
+                        .iter()
+                        .find(|(f, _)| *f == field.node.name.node)
+                    {
+                        Some((_, field_ty)) => self.check_pattern(&field.node.pattern, field_ty),
+                        None => {
+                            self.no_field(&ty, field.node.name);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn variant(&self, span: Span) -> Option<(Ty, VariantShape)> {
+        let def = self.res.lookup(span)?;
+        let shape = self.results.variants.get(&def)?.clone();
+        let parent = self.res.def(def).parent?;
+        Some((Ty::Path(self.res.path_of(parent)), shape))
+    }
+
+    fn bad_pattern(&mut self, span: Span, expected: &str) {
+        if self.res.lookup(span).is_some() {
+            self.bag.push(
+                Diagnostic::error(format!("expected {expected}"))
+                    .with_label(span.primary("cannot be matched with this pattern")),
+            );
+        }
+    }
+
+    // --------------------------------------------------------------- finish
+
+    fn finish(&mut self) -> TypeckResults {
+        if !self.bag.has_errors() {
+            for (def, span) in mem::take(&mut self.inferred) {
+                let ty = self.zonk(&self.results.locals[&def]);
+                if self.has_unknown(&ty) {
+                    self.bag
+                        .push(Diagnostic::error("type annotations needed").with_label(
+                            span.primary(format!(
+                                "cannot infer the type of `{}`",
+                                self.res.def(def).name.as_str()
+                            )),
+                        ));
+                }
+            }
+        }
+
+        // literals fall back to `i32` and `f64`, everything else to `()`
+        for var in 0..self.vars.len() {
+            if self.vars[var].binding.is_none() {
+                self.vars[var].binding = Some(match self.vars[var].kind {
+                    VarKind::General => Ty::unit(),
+                    VarKind::Numeric | VarKind::Int => Ty::Int(IntTy::I32),
+                    VarKind::Float => Ty::Float(FloatTy::F64),
+                });
+            }
+        }
+
+        for literal in mem::take(&mut self.literals) {
+            let Ty::Int(int) = self.zonk(&literal.ty) else {
+                continue;
+            };
+
+            let max = match (int.is_signed(), literal.negative) {
+                (true, false) => (1u64 << (int.bits() - 1)) - 1,
+                (true, true) => 1u64 << (int.bits() - 1),
+                (false, _) => u64::MAX >> (64 - int.bits()),
+            };
+
+            if literal.value > max || (literal.negative && !int.is_signed() && literal.value != 0) {
+                self.bag.push(
+                    Diagnostic::error(format!("literal out of range for `{}`", int.name()))
+                        .with_label(literal.span.primary(format!(
+                            "`{}` only holds values from {} to {}",
+                            int.name(),
+                            if int.is_signed() {
+                                format!("-{}", 1u64 << (int.bits() - 1))
+                            } else {
+                                "0".to_owned()
+                            },
+                            if int.is_signed() {
+                                (1u64 << (int.bits() - 1)) - 1
+                            } else {
+                                u64::MAX >> (64 - int.bits())
+                            }
+                        ))),
+                );
+            }
+        }
+
+        let mut results = mem::take(&mut self.results);
+        for ty in results
+            .exprs
+            .values_mut()
+            .chain(results.locals.values_mut())
+        {
+            *ty = self.zonk(ty);
+        }
+        for ty in results.consts.values_mut() {
+            *ty = self.zonk(ty);
+        }
+        for sig in results.fns.values_mut() {
+            sig.params = sig.params.iter().map(|p| self.zonk(p)).collect();
+            sig.ret = self.zonk(&sig.ret);
+        }
+        for fields in results.structs.values_mut() {
+            for (_, ty) in fields {
+                *ty = self.zonk(ty);
+            }
+        }
+
+        results
+    }
+
+    fn has_unknown(&self, ty: &Ty) -> bool {
+        match self.zonk(ty) {
+            Ty::Var(var) => self.vars[var as usize].kind == VarKind::General,
+            Ty::Ref { inner, .. }
+            | Ty::Optional(inner)
+            | Ty::Slice(inner)
+            | Ty::Array(inner, _) => self.has_unknown(&inner),
+            Ty::Tuple(elems) | Ty::Generic { args: elems, .. } => {
+                elems.iter().any(|e| self.has_unknown(e))
+            }
+            Ty::Fn { params, ret } => {
+                params.iter().any(|p| self.has_unknown(p)) || self.has_unknown(&ret)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_none_pattern(pattern: &Pattern) -> bool {
+    matches!(pattern, Pattern::Literal(literal) if matches!(literal.node, Literal::None))
+}
+
+/// Where to point when the value of `expr` has the wrong type: the tail of
+/// a block rather than the whole block.
+fn tail_span(expr: &Expr, span: Span) -> Span {
+    match expr {
+        Expr::Block(block) => match &block.node.expr {
+            Some(tail) => tail_span(&tail.node, tail.span),
+            None => span,
+        },
+        _ => span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, resolve::Resolver, source::SourceMap};
+
+    struct Checked {
+        res: Resolution,
+        results: TypeckResults,
+        bag: DiagnosticsBag,
+        src: String,
+    }
+
+    impl Checked {
+        /// The type of the first local binding called `name`.
+        fn local(&self, name: &str) -> String {
+            let (idx, _) = self
+                .res
+                .defs
+                .iter()
+                .enumerate()
+                .find(|(_, def)| {
+                    matches!(def.kind, DefKind::Local { .. }) && def.name.as_str() == name
+                })
+                .unwrap_or_else(|| panic!("no local `{name}`"));
+            self.results.locals[&DefId(idx as u32)].to_string()
+        }
+
+        fn messages(&self) -> Vec<&str> {
+            self.bag.0.iter().map(|d| d.message.as_str()).collect()
+        }
+
+        fn text(&self, span: Span) -> &str {
+            &self.src[span.lo..span.hi]
+        }
+    }
+
+    fn check(src: &str) -> Checked {
+        let mut map = SourceMap::fresh();
+        let id = map.with_content("test.ak", src);
+        let mut parser = Parser::new(id, &map[id]);
+        let script = parser.parse_script();
+        assert!(parser.bag.is_empty(), "parse errors: {:?}", parser.bag.0);
+
+        let mut resolver = Resolver::new();
+        let res = resolver.resolve_script(&script);
+        assert!(
+            resolver.bag.is_empty(),
+            "resolve errors: {:?}",
+            resolver.bag.0
+        );
+
+        let mut checker = TypeChecker::new(&res);
+        let results = checker.check_script(&script);
+        let bag = checker.bag;
+
+        Checked {
+            res,
+            results,
+            bag,
+            src: src.to_owned(),
+        }
+    }
+
+    fn check_ok(src: &str) -> Checked {
+        let checked = check(src);
+        assert!(
+            checked.bag.is_empty(),
+            "unexpected diagnostics: {:?}",
+            checked.bag.0
+        );
+        checked
+    }
+
+    #[test]
+    fn test_infer_let_bindings_and_literals() {
+        let checked = check_ok(
+            "fn main() {
+                let a = 1;
+                let b: u8 = 2;
+                let c = b + 3;
+                let d = 1.5;
+                let e = (a, [d, 2.0], 'x');
+                let mut f;
+                f = \"text\";
+             }",
+        );
+
+        assert_eq!(checked.local("a"), "i32");
+        assert_eq!(checked.local("c"), "u8");
+        assert_eq!(checked.local("d"), "f64");
+        assert_eq!(checked.local("e"), "(i32, [f64; 2], char)");
+        assert_eq!(checked.local("f"), "str");
+    }
+
+    #[test]
+    fn test_infer_literals_from_context() {
+        let checked = check_ok(
+            "fn take(x: u64, y: f32) {}
+             fn main() {
+                let n = 7;
+                let m = 0.5;
+                take(n, m);
+                let big = n * 1000;
+             }",
+        );
+
+        assert_eq!(checked.local("n"), "u64");
+        assert_eq!(checked.local("m"), "f32");
+        assert_eq!(checked.local("big"), "u64");
+    }
+
+    #[test]
+    fn test_infer_closures() {
+        let checked = check_ok(
+            "fn main() {
+                let mul = |x, y| x + y;
+                let r: u16 = mul(2, 3);
+                let div = |x: u8, y: u8|: u8 { return x / y; };
+                let apply = |f: fn(u8): u8, v| f(v);
+                let twice = apply(|v| v * 2, 4);
+             }",
+        );
+
+        assert_eq!(checked.local("mul"), "fn(u16, u16): u16");
+        assert_eq!(checked.local("div"), "fn(u8, u8): u8");
+        assert_eq!(checked.local("twice"), "u8");
+    }
+
+    #[test]
+    fn test_check_spec_examples() {
+        let checked = check_ok(
+            "open math::sub;
+             section math {
+                fn add(x: u8, y: u8): u8 { x + y }
+                fn sub(x: u8, y: u8): u8 { return x - y; }
+             }
+             struct Point { x: f32, y: f32 }
+             enum Shape { Circle(f32), Rect { w: f32, h: f32 }, Empty }
+             fn area(s: &Shape): f32 {
+                match s {
+                    Shape::Circle(r) => r * r * 3.14,
+                    Shape::Rect { w, h } => w * h,
+                    Shape::Empty => 0.0,
+                }
+             }
+             fn main() {
+                let p = Point { x: 1.0, y: 2.0 };
+                let total = math::add(1, 2).sub(1);
+                let shape = Shape::Rect { w: p.x, h: p.y };
+                let found = loop { if total > 2 { break p.x; } };
+                for i in 0..total { print(i); }
+                print(area(&shape));
+             }",
+        );
+
+        assert_eq!(checked.local("total"), "u8");
+        assert_eq!(checked.local("shape"), "Shape");
+        assert_eq!(checked.local("found"), "f32");
+        assert_eq!(checked.local("i"), "u8");
+        assert_eq!(checked.local("w"), "f32");
+    }
+
+    #[test]
+    fn test_loops_breaking_with_literals() {
+        let checked = check_ok(
+            "fn spin(): u8 { loop {} }
+             fn main() {
+                let found = loop { break 42; };
+                let half = loop { break 1.5; };
+                let unit = loop { break; };
+             }",
+        );
+
+        assert_eq!(checked.local("found"), "i32");
+        assert_eq!(checked.local("half"), "f64");
+        assert_eq!(checked.local("unit"), "()");
+    }
+
+    #[test]
+    fn test_report_mismatch_with_labels() {
+        let checked = check("fn main() { let x: bool = 1; }");
+
+        assert_eq!(checked.messages(), ["mismatched types"]);
+        let labels = &checked.bag.0[0].labels;
+        assert_eq!(labels.len(), 2);
+        assert_eq!(checked.text(labels[0].span), "1");
+        assert_eq!(
+            labels[0].message.as_deref(),
+            Some("expected `bool`, found `{integer}`")
+        );
+        assert_eq!(checked.text(labels[1].span), "bool");
+    }
+
+    #[test]
+    fn test_report_incompatible_branches() {
+        let checked = check(
+            "fn f(c: bool): u8 { if c { 1 } else { true } }
+             fn g(): u8 { return 'c'; }
+             fn h(): u8 { }",
+        );
+
+        assert_eq!(
+            checked.messages(),
+            [
+                "`if` and `else` have incompatible types",
+                "mismatched types",
+                "mismatched types"
+            ]
+        );
+
+        let labels = &checked.bag.0[0].labels;
+        assert_eq!(checked.text(labels[0].span), "true");
+        assert_eq!(checked.text(labels[1].span), "1");
+
+        let labels = &checked.bag.0[1].labels;
+        assert_eq!(checked.text(labels[0].span), "'c'");
+        assert_eq!(checked.text(labels[1].span), "u8");
+    }
+
+    #[test]
+    fn test_coerce_optionals_and_references() {
+        check_ok(
+            "fn show(value: u8?) {}
+             fn sum(values: &[u8]): u8 { values[0] }
+             fn main() {
+                show(1);
+                show(none);
+                let mut arr = [1, 2, 3];
+                sum(&arr);
+                sum(&mut arr);
+             }",
+        );
+    }
+
+    #[test]
+    fn test_report_immutable_assignment() {
+        let checked = check(
+            "fn main() {
+                let x = 1;
+                x = 2;
+                let y;
+                y = 3;
+                let mut z = 0;
+                z += 1;
+             }",
+        );
+
+        assert_eq!(
+            checked.messages(),
+            ["cannot assign to immutable variable `x`"]
+        );
+        let labels = &checked.bag.0[0].labels;
+        assert_eq!(checked.text(labels[1].span), "x");
+
+        let checked =
+            check("fn main() { let a = [1]; let r = &mut a; let s = &a; let t = &mut *s; }");
+        assert_eq!(
+            checked.messages(),
+            [
+                "cannot borrow `a` as mutable, as it is not declared `mut`",
+                "cannot borrow data behind a `&` reference as mutable",
+            ]
+        );
+        assert_eq!(
+            checked.bag.0[0].labels[0].message.as_deref(),
+            Some("cannot borrow this as mutable")
+        );
+    }
+
+    #[test]
+    fn test_display_names_every_inference_variable() {
+        // enough literals before the errors that `?1` is a prefix of `?14`
+        let checked = check(
+            "fn main() {
+                let a = (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12);
+                let g = [1, 'a'];
+                let q = 1 + 2.0;
+             }",
+        );
+        let labels: Vec<_> = checked
+            .bag
+            .0
+            .iter()
+            .map(|d| d.labels[0].message.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                "expected `{integer}`, found `char`",
+                "expected `{integer}`, found `{float}`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_literal_out_of_range() {
+        let checked = check("fn main() { let a: u8 = 256; let b: i8 = -128; let c: i8 = -129; }");
+        assert_eq!(
+            checked.messages(),
+            [
+                "literal out of range for `u8`",
+                "literal out of range for `i8`"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_literal_suffixes_fix_their_type() {
+        let checked = check_ok(
+            "fn main() {
+                let mask = 0b1010_0000u8;
+                let big = 1_000_000i64 * 3;
+                let half = 1f32 / 2.0;
+                let neg = -128i8;
+             }",
+        );
+        assert_eq!(checked.local("mask"), "u8");
+        assert_eq!(checked.local("big"), "i64");
+        assert_eq!(checked.local("half"), "f32");
+        assert_eq!(checked.local("neg"), "i8");
+
+        let checked = check("fn main() { let a = 256u8; let b: u16 = 1u8; }");
+        assert_eq!(
+            checked.messages(),
+            ["mismatched types", "literal out of range for `u8`"]
+        );
+    }
+
+    #[test]
+    fn test_report_annotations_needed() {
+        let checked = check("fn main() { let empty = []; let id = |x| x; }");
+        assert_eq!(
+            checked.messages(),
+            [
+                "type annotations needed",
+                "type annotations needed",
+                "type annotations needed"
+            ]
+        );
+    }
+}
@@ -0,0 +1,613 @@
+use std::{cell::RefCell, cmp::Ordering, fmt, rc::Rc};
+
+use crate::{
+    ast::{BinaryOp, Literal},
+    ty::{FloatTy, IntTy, Ty},
+};
+
+/// The shape of a struct or enum variant value, shared by all its values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// identifies the struct or variant, values with equal ids have equal layouts
+    pub id: u32,
+    /// `Point`, or `Shape::Circle` for a variant
+    pub name: String,
+    pub kind: LayoutKind,
+    /// the field names of structs and struct variants
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutKind {
+    Struct,
+    Unit,
+    Tuple,
+    StructVariant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Print,
+    Println,
+    Assert,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "print" => Some(Self::Print),
+            "println" => Some(Self::Println),
+            "assert" => Some(Self::Assert),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Print => "print",
+            Self::Println => "println",
+            Self::Assert => "assert",
+        }
+    }
+}
+
+/// A closure value: the code it runs and the variables it captured, which
+/// are shared with the scope that created it.
+#[derive(Debug)]
+pub struct Closure {
+    pub code: u32,
+    pub captures: Vec<(u32, Rc<RefCell<Value>>)>,
+}
+
+/// A location a reference points to: a variable and a path of field or
+/// element indices into it.
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub cell: Rc<RefCell<Value>>,
+    pub path: Vec<usize>,
+}
+
+impl Place {
+    pub fn new(cell: Rc<RefCell<Value>>) -> Self {
+        Self {
+            cell,
+            path: Vec::new(),
+        }
+    }
+
+    /// A place holding a value that has no variable of its own.
+    pub fn temporary(value: Value) -> Self {
+        Self::new(Rc::new(RefCell::new(value)))
+    }
+
+    pub fn project(mut self, idx: usize) -> Self {
+        self.path.push(idx);
+        self
+    }
+
+    pub fn get(&self) -> Value {
+        let value = self.cell.borrow();
+        let mut current = &*value;
+        for idx in &self.path {
+            current = &current.elems()[*idx];
+        }
+        current.clone()
+    }
+
+    pub fn set(&self, new: Value) {
+        let mut value = self.cell.borrow_mut();
+        let mut current = &mut *value;
+        for idx in &self.path {
+            current = &mut current.elems_mut()[*idx];
+        }
+        *current = new;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Int(i128, IntTy),
+    Float(f64, FloatTy),
+    Char(char),
+    Str(Rc<str>),
+    /// the empty optional, a present optional is the value itself
+    None,
+    Tuple(Vec<Value>),
+    Array(Vec<Value>),
+    Struct(Rc<Layout>, Vec<Value>),
+    Range {
+        start: Option<Box<Value>>,
+        end: Option<Box<Value>>,
+        inclusive: bool,
+    },
+    Ref(Place),
+    /// a function item, by the id its runtime gives it
+    Fn(u32),
+    Builtin(Builtin),
+    Closure(Rc<Closure>),
+    /// a tuple variant used as a function
+    Ctor(Rc<Layout>),
+}
+
+impl Value {
+    /// The fields of a struct, tuple or array.
+    pub fn elems(&self) -> &[Value] {
+        match self {
+            Self::Tuple(elems) | Self::Array(elems) | Self::Struct(_, elems) => elems,
+            _ => &[],
+        }
+    }
+
+    fn elems_mut(&mut self) -> &mut [Value] {
+        match self {
+            Self::Tuple(elems) | Self::Array(elems) | Self::Struct(_, elems) => elems,
+            _ => &mut [],
+        }
+    }
+
+    /// Follows references to the value they point at.
+    pub fn deref(self) -> Value {
+        match self {
+            Self::Ref(place) => place.get().deref(),
+            value => value,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i128> {
+        match self {
+            Self::Int(value, _) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        matches!(self, Self::Bool(true))
+    }
+}
+
+pub fn int_min(ty: IntTy) -> i128 {
+    if ty.is_signed() {
+        -(1i128 << (ty.bits() - 1))
+    } else {
+        0
+    }
+}
+
+pub fn int_max(ty: IntTy) -> i128 {
+    if ty.is_signed() {
+        (1i128 << (ty.bits() - 1)) - 1
+    } else {
+        (1i128 << ty.bits()) - 1
+    }
+}
+
+/// Wraps `value` into the range of `ty`, as casts between integers do.
+pub fn wrap_int(value: i128, ty: IntTy) -> i128 {
+    let bits = ty.bits();
+    let mask = (1i128 << bits) - 1;
+    let low = value & mask;
+
+    if ty.is_signed() && low >> (bits - 1) & 1 == 1 {
+        low - (1i128 << bits)
+    } else {
+        low
+    }
+}
+
+pub fn round_float(value: f64, ty: FloatTy) -> f64 {
+    match ty {
+        FloatTy::F32 => value as f32 as f64,
+        FloatTy::F64 => value,
+    }
+}
+
+/// The value of a literal whose type checked as `ty`.
+pub fn literal(literal: &Literal, ty: Option<&Ty>) -> Value {
+    match literal {
+        Literal::UInt(v, _) => match ty {
+            Some(Ty::Int(ty)) => Value::Int(*v as i128, *ty),
+            _ => Value::Int(*v as i128, IntTy::I32),
+        },
+        Literal::Int(v) => match ty {
+            Some(Ty::Int(ty)) => Value::Int(*v as i128, *ty),
+            _ => Value::Int(*v as i128, IntTy::I32),
+        },
+        Literal::Float(v, _) => match ty {
+            Some(Ty::Float(ty)) => Value::Float(round_float(*v, *ty), *ty),
+            _ => Value::Float(*v, FloatTy::F64),
+        },
+        Literal::Bool(b) => Value::Bool(*b),
+        Literal::Char(c) => Value::Char(*c),
+        Literal::String(s) => Value::Str(s.as_str().into()),
+        Literal::None => Value::None,
+    }
+}
+
+fn checked(value: i128, ty: IntTy, op: &str) -> Result<Value, String> {
+    if value < int_min(ty) || value > int_max(ty) {
+        return Err(format!("attempt to {op} with overflow"));
+    }
+    Ok(Value::Int(value, ty))
+}
+
+/// Evaluates a binary operator on two values of the same type. `&&` and
+/// `||` are not handled here since they short circuit.
+pub fn binary(op: BinaryOp, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    if op.is_comparison() {
+        let ordering = compare(lhs, rhs);
+        let result = match op {
+            BinaryOp::Eq => values_eq(lhs, rhs),
+            BinaryOp::Ne => !values_eq(lhs, rhs),
+            BinaryOp::Lt => ordering == Some(Ordering::Less),
+            BinaryOp::Gt => ordering == Some(Ordering::Greater),
+            BinaryOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            BinaryOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            _ => unreachable!(),
+        };
+        return Ok(Value::Bool(result));
+    }
+
+    match (lhs, rhs) {
+        (Value::Int(a, ty), Value::Int(b, _)) => {
+            let (a, b, ty) = (*a, *b, *ty);
+            match op {
+                BinaryOp::Add => checked(a + b, ty, "add"),
+                BinaryOp::Sub => checked(a - b, ty, "subtract"),
+                BinaryOp::Mul => checked(a * b, ty, "multiply"),
+                BinaryOp::Div | BinaryOp::Rem if b == 0 => Err(match op {
+                    BinaryOp::Div => "attempt to divide by zero".to_owned(),
+                    _ => "attempt to calculate the remainder with a divisor of zero".to_owned(),
+                }),
+                BinaryOp::Div => checked(a / b, ty, "divide"),
+                BinaryOp::Rem => checked(a % b, ty, "calculate the remainder"),
+                BinaryOp::BitAnd => Ok(Value::Int(a & b, ty)),
+                BinaryOp::BitOr => Ok(Value::Int(a | b, ty)),
+                BinaryOp::BitXor => Ok(Value::Int(a ^ b, ty)),
+                BinaryOp::Shl | BinaryOp::Shr if b < 0 || b >= ty.bits() as i128 => Err(format!(
+                    "attempt to shift {} with overflow",
+                    if op == BinaryOp::Shl { "left" } else { "right" }
+                )),
+                BinaryOp::Shl => Ok(Value::Int(wrap_int(a << b, ty), ty)),
+                BinaryOp::Shr => Ok(Value::Int(a >> b, ty)),
+                _ => Err(format!("cannot apply `{op}` to integers")),
+            }
+        }
+        (Value::Float(a, ty), Value::Float(b, _)) => {
+            let value = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Rem => a % b,
+                _ => return Err(format!("cannot apply `{op}` to floats")),
+            };
+            Ok(Value::Float(round_float(value, *ty), *ty))
+        }
+        (Value::Bool(a), Value::Bool(b)) => match op {
+            BinaryOp::BitAnd | BinaryOp::And => Ok(Value::Bool(*a & *b)),
+            BinaryOp::BitOr | BinaryOp::Or => Ok(Value::Bool(*a | *b)),
+            BinaryOp::BitXor => Ok(Value::Bool(*a ^ *b)),
+            _ => Err(format!("cannot apply `{op}` to booleans")),
+        },
+        (Value::Ref(_), _) | (_, Value::Ref(_)) => {
+            binary(op, &lhs.clone().deref(), &rhs.clone().deref())
+        }
+        _ => Err(format!("cannot apply `{op}` to these values")),
+    }
+}
+
+/// Indexes an array with an integer, or slices an array or string with a
+/// range.
+pub fn index(base: Value, index: &Value) -> Result<Value, String> {
+    if let Value::Range {
+        start,
+        end,
+        inclusive,
+    } = index
+    {
+        let len = match &base {
+            Value::Array(elems) => elems.len(),
+            Value::Str(s) => s.len(),
+            _ => return Err(format!("cannot slice `{base}`")),
+        };
+        let start = start.as_ref().and_then(|v| v.as_int()).unwrap_or(0);
+        let end = end
+            .as_ref()
+            .and_then(|v| v.as_int())
+            .map(|end| end + *inclusive as i128)
+            .unwrap_or(len as i128);
+
+        if start < 0 || start > end || end > len as i128 {
+            return Err(format!(
+                "range {start}..{end} out of bounds for length {len}"
+            ));
+        }
+        let (start, end) = (start as usize, end as usize);
+
+        return match base {
+            Value::Array(elems) => Ok(Value::Array(elems[start..end].to_vec())),
+            Value::Str(s) => match s.get(start..end) {
+                Some(sub) => Ok(Value::Str(sub.into())),
+                None => Err("string slice does not fall on character boundaries".to_owned()),
+            },
+            _ => unreachable!(),
+        };
+    }
+
+    let idx = index.as_int().unwrap_or(-1);
+    match base {
+        Value::Array(elems) => match usize::try_from(idx).ok().and_then(|idx| elems.get(idx)) {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!(
+                "index out of bounds: the length is {} but the index is {idx}",
+                elems.len()
+            )),
+        },
+        base => Err(format!("cannot index into `{base}`")),
+    }
+}
+
+pub fn negate(value: &Value) -> Result<Value, String> {
+    match value {
+        Value::Int(v, ty) => checked(-v, *ty, "negate"),
+        Value::Float(v, ty) => Ok(Value::Float(-v, *ty)),
+        _ => Err("cannot negate this value".to_owned()),
+    }
+}
+
+pub fn not(value: &Value) -> Result<Value, String> {
+    match value {
+        Value::Bool(b) => Ok(Value::Bool(!b)),
+        Value::Int(v, ty) => Ok(Value::Int(wrap_int(!v, *ty), *ty)),
+        _ => Err("cannot apply `!` to this value".to_owned()),
+    }
+}
+
+/// Converts a value with `as`. Integer casts wrap, float to integer casts
+/// saturate and `NaN` becomes zero.
+pub fn cast(value: &Value, to: &Ty) -> Value {
+    match (value, to) {
+        (Value::Int(v, _), Ty::Int(ty)) => Value::Int(wrap_int(*v, *ty), *ty),
+        (Value::Int(v, _), Ty::Float(ty)) => Value::Float(round_float(*v as f64, *ty), *ty),
+        (Value::Float(v, _), Ty::Int(ty)) => {
+            let v = if v.is_nan() { 0 } else { *v as i128 };
+            Value::Int(v.clamp(int_min(*ty), int_max(*ty)), *ty)
+        }
+        (Value::Float(v, _), Ty::Float(ty)) => Value::Float(round_float(*v, *ty), *ty),
+        (Value::Bool(b), Ty::Int(ty)) => Value::Int(*b as i128, *ty),
+        (Value::Char(c), Ty::Int(ty)) => Value::Int(wrap_int(*c as i128, *ty), *ty),
+        (Value::Int(v, _), Ty::Char) => Value::Char(char::from(*v as u8)),
+        (Value::Ref(_), _) => cast(&value.clone().deref(), to),
+        _ => value.clone(),
+    }
+}
+
+pub fn values_eq(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Unit, Value::Unit) | (Value::None, Value::None) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Int(a, _), Value::Int(b, _)) => a == b,
+        (Value::Float(a, _), Value::Float(b, _)) => a == b,
+        (Value::Char(a), Value::Char(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Tuple(a), Value::Tuple(b)) | (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_eq(a, b))
+        }
+        (Value::Struct(x, a), Value::Struct(y, b)) => {
+            x.id == y.id && a.iter().zip(b).all(|(a, b)| values_eq(a, b))
+        }
+        (
+            Value::Range {
+                start: s1,
+                end: e1,
+                inclusive: i1,
+            },
+            Value::Range {
+                start: s2,
+                end: e2,
+                inclusive: i2,
+            },
+        ) => {
+            i1 == i2
+                && option_eq(s1.as_deref(), s2.as_deref())
+                && option_eq(e1.as_deref(), e2.as_deref())
+        }
+        (Value::Fn(a), Value::Fn(b)) => a == b,
+        (Value::Builtin(a), Value::Builtin(b)) => a == b,
+        (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+        (Value::Ctor(a), Value::Ctor(b)) => a.id == b.id,
+        (Value::Ref(_), _) | (_, Value::Ref(_)) => {
+            values_eq(&lhs.clone().deref(), &rhs.clone().deref())
+        }
+        _ => false,
+    }
+}
+
+fn option_eq(a: Option<&Value>, b: Option<&Value>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => values_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+pub fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(a, _), Value::Int(b, _)) => a.partial_cmp(b),
+        (Value::Float(a, _), Value::Float(b, _)) => a.partial_cmp(b),
+        (Value::Char(a), Value::Char(b)) => a.partial_cmp(b),
+        (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+        (Value::Ref(_), _) | (_, Value::Ref(_)) => {
+            compare(&lhs.clone().deref(), &rhs.clone().deref())
+        }
+        _ => None,
+    }
+}
+
+/// Writes a value nested in another one, where strings and chars are quoted.
+pub struct Nested<'a>(pub &'a Value);
+
+impl fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Value::Str(s) => write!(f, "{s:?}"),
+            Value::Char(c) => write!(f, "{c:?}"),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, values: &[Value]) -> fmt::Result {
+    for (idx, value) in values.iter().enumerate() {
+        if idx != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", Nested(value))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "()"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Int(v, _) => write!(f, "{v}"),
+            Self::Float(v, FloatTy::F32) => write!(f, "{:?}", *v as f32),
+            Self::Float(v, FloatTy::F64) => write!(f, "{v:?}"),
+            Self::Char(c) => write!(f, "{c}"),
+            Self::Str(s) => write!(f, "{s}"),
+            Self::None => write!(f, "none"),
+            Self::Tuple(elems) => {
+                write!(f, "(")?;
+                write_list(f, elems)?;
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Self::Array(elems) => {
+                write!(f, "[")?;
+                write_list(f, elems)?;
+                write!(f, "]")
+            }
+            Self::Struct(layout, elems) => {
+                write!(f, "{}", layout.name)?;
+                match layout.kind {
+                    LayoutKind::Unit => Ok(()),
+                    LayoutKind::Tuple => {
+                        write!(f, "(")?;
+                        write_list(f, elems)?;
+                        write!(f, ")")
+                    }
+                    LayoutKind::Struct | LayoutKind::StructVariant => {
+                        write!(f, " {{ ")?;
+                        for (idx, (name, value)) in layout.fields.iter().zip(elems).enumerate() {
+                            if idx != 0 {
+                                write!(f, ", ")?;
+                            }
+                            write!(f, "{name}: {}", Nested(value))?;
+                        }
+                        write!(f, " }}")
+                    }
+                }
+            }
+            Self::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if let Some(start) = start {
+                    write!(f, "{start}")?;
+                }
+                write!(f, "{}", if *inclusive { "..=" } else { ".." })?;
+                if let Some(end) = end {
+                    write!(f, "{end}")?;
+                }
+                Ok(())
+            }
+            Self::Ref(place) => write!(f, "{}", Nested(&place.get())),
+            Self::Fn(_) | Self::Closure(_) => write!(f, "<fn>"),
+            Self::Builtin(builtin) => write!(f, "<fn {}>", builtin.name()),
+            Self::Ctor(layout) => write!(f, "<fn {}>", layout.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_overflow_is_reported() {
+        let max = Value::Int(255, IntTy::U8);
+        let one = Value::Int(1, IntTy::U8);
+
+        assert!(binary(BinaryOp::Add, &max, &one).is_err());
+        assert!(binary(BinaryOp::Sub, &Value::Int(0, IntTy::U8), &one).is_err());
+        assert!(binary(BinaryOp::Div, &one, &Value::Int(0, IntTy::U8)).is_err());
+        assert!(values_eq(
+            &binary(
+                BinaryOp::Mul,
+                &Value::Int(-8, IntTy::I8),
+                &Value::Int(16, IntTy::I8)
+            )
+            .unwrap(),
+            &Value::Int(-128, IntTy::I8)
+        ));
+    }
+
+    #[test]
+    fn test_casts_wrap_and_saturate() {
+        let cases = [
+            (Value::Int(300, IntTy::I32), Ty::Int(IntTy::U8), "44"),
+            (Value::Int(200, IntTy::U8), Ty::Int(IntTy::I8), "-56"),
+            (
+                Value::Float(1e10, FloatTy::F64),
+                Ty::Int(IntTy::I32),
+                "2147483647",
+            ),
+            (Value::Float(-1.5, FloatTy::F64), Ty::Int(IntTy::U8), "0"),
+            (Value::Char('a'), Ty::Int(IntTy::U8), "97"),
+            (Value::Int(3, IntTy::I32), Ty::Float(FloatTy::F32), "3.0"),
+        ];
+
+        for (value, ty, expected) in cases {
+            assert_eq!(cast(&value, &ty).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_nested_values() {
+        let layout = Rc::new(Layout {
+            id: 0,
+            name: "Point".to_owned(),
+            kind: LayoutKind::Struct,
+            fields: vec!["x".to_owned(), "name".to_owned()],
+        });
+        let value = Value::Array(vec![
+            Value::Struct(
+                layout,
+                vec![Value::Float(0.5, FloatTy::F32), Value::Str("a".into())],
+            ),
+            Value::Struct(
+                Rc::new(Layout {
+                    id: 1,
+                    name: "Shape::Empty".to_owned(),
+                    kind: LayoutKind::Unit,
+                    fields: Vec::new(),
+                }),
+                Vec::new(),
+            ),
+        ]);
+
+        assert_eq!(
+            value.to_string(),
+            "[Point { x: 0.5, name: \"a\" }, Shape::Empty]"
+        );
+        assert_eq!(Value::Tuple(vec![Value::Char('c')]).to_string(), "('c',)");
+    }
+}
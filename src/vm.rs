@@ -0,0 +1,682 @@
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use crate::{
+    bytecode::{Module, Op},
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    ty::IntTy,
+    value::{self, Builtin, Closure, Place, Value},
+};
+
+/// How deep calls may nest before the script is stopped.
+pub const MAX_FRAMES: usize = 10_000;
+
+struct Frame {
+    func: u32,
+    pc: usize,
+    /// where the callee was on the stack, the result replaces it
+    base: usize,
+    slots: Vec<Rc<RefCell<Value>>>,
+    /// the constant item this frame computes
+    global: Option<u32>,
+}
+
+/// A runtime error, reported at the span of the failing instruction.
+struct Fault {
+    message: String,
+    label: &'static str,
+}
+
+fn fault<T>(message: impl Into<String>, label: &'static str) -> Result<T, Fault> {
+    Err(Fault {
+        message: message.into(),
+        label,
+    })
+}
+
+/// Runs a compiled [`Module`] on an operand stack.
+pub struct Vm<'a> {
+    module: &'a Module,
+    pub bag: DiagnosticsBag,
+    out: Box<dyn Write + 'a>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    globals: Vec<Option<Value>>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(module: &'a Module, out: impl Write + 'a) -> Self {
+        Self {
+            module,
+            bag: DiagnosticsBag::new(),
+            out: Box::new(out),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: vec![None; module.globals.len()],
+        }
+    }
+
+    /// Runs the `main` function of the module.
+    pub fn run(&mut self) -> Option<Value> {
+        match self.module.main {
+            Some(main) => self.call(main, Vec::new()),
+            None => {
+                self.bag
+                    .push(Diagnostic::error("`main` function not found"));
+                None
+            }
+        }
+    }
+
+    /// Calls function `func` of the module and runs it to completion.
+    pub fn call(&mut self, func: u32, args: Vec<Value>) -> Option<Value> {
+        let floor = self.frames.len();
+        let base = self.stack.len();
+
+        let result = match self.push_frame(func, &[], args, base, None) {
+            Ok(()) => self.execute(floor),
+            Err(fault) => Err(Diagnostic::error(fault.message)),
+        };
+        let _ = self.out.flush();
+
+        match result {
+            Ok(value) => Some(value),
+            Err(diagnostic) => {
+                self.frames.truncate(floor);
+                self.stack.truncate(base);
+                self.bag.push(diagnostic);
+                None
+            }
+        }
+    }
+
+    fn push_frame(
+        &mut self,
+        func: u32,
+        captures: &[(u32, Rc<RefCell<Value>>)],
+        args: Vec<Value>,
+        base: usize,
+        global: Option<u32>,
+    ) -> Result<(), Fault> {
+        if self.frames.len() >= MAX_FRAMES {
+            return fault(
+                format!("stack overflow: calls nested more than {MAX_FRAMES} deep"),
+                "while calling this",
+            );
+        }
+
+        let function = &self.module.functions[func as usize];
+        if args.len() != function.arity as usize {
+            return fault(
+                format!(
+                    "`{}` takes {} arguments but {} were given",
+                    function.name,
+                    function.arity,
+                    args.len()
+                ),
+                "called here",
+            );
+        }
+        let mut slots: Vec<_> = (0..function.slots).map(|_| cell(Value::Unit)).collect();
+        for (slot, value) in captures {
+            slots[*slot as usize] = value.clone();
+        }
+        let params = function.captures.len();
+        for (idx, arg) in args.into_iter().enumerate() {
+            slots[params + idx] = cell(arg);
+        }
+
+        self.frames.push(Frame {
+            func,
+            pc: 0,
+            base,
+            slots,
+            global,
+        });
+        Ok(())
+    }
+
+    /// Runs until the frame above `floor` returns.
+    fn execute(&mut self, floor: usize) -> Result<Value, Diagnostic> {
+        loop {
+            let frame = self.frames.last_mut().expect("a frame is running");
+            let pc = frame.pc;
+            let op = self.module.functions[frame.func as usize].code[pc];
+            frame.pc += 1;
+
+            if op == Op::Return {
+                let value = self.pop();
+                let frame = self.frames.pop().expect("a frame is running");
+                self.stack.truncate(frame.base);
+
+                if let Some(global) = frame.global {
+                    self.globals[global as usize] = Some(value.clone());
+                }
+                if self.frames.len() == floor {
+                    return Ok(value);
+                }
+                self.stack.push(value);
+                continue;
+            }
+
+            if let Err(fault) = self.step(op) {
+                let frame = self.frames.last().expect("a frame is running");
+                let span = self.module.functions[frame.func as usize].span_at(pc);
+
+                let diagnostic = Diagnostic::error(fault.message);
+                return Err(match span {
+                    Some(span) => diagnostic.with_label(span.primary(fault.label)),
+                    None => diagnostic,
+                });
+            }
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a frame is running")
+    }
+
+    fn slot(&mut self, slot: u32) -> &Rc<RefCell<Value>> {
+        &self.frame().slots[slot as usize]
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    fn pop_n(&mut self, count: u32) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count as usize)
+    }
+
+    fn step(&mut self, op: Op) -> Result<(), Fault> {
+        match op {
+            Op::Const(idx) => self.stack.push(self.module.constants[idx as usize].clone()),
+            Op::Unit => self.stack.push(Value::Unit),
+            Op::True => self.stack.push(Value::Bool(true)),
+            Op::False => self.stack.push(Value::Bool(false)),
+            Op::None => self.stack.push(Value::None),
+            Op::Pop => _ = self.pop(),
+            Op::PopN(count) => _ = self.pop_n(count),
+            Op::Dup => {
+                let top = self.stack.last().expect("operand stack underflow").clone();
+                self.stack.push(top);
+            }
+            Op::Slide(count) => {
+                let top = self.pop();
+                self.pop_n(count);
+                self.stack.push(top);
+            }
+
+            Op::Load(slot) => {
+                let value = self.slot(slot).borrow().clone();
+                self.stack.push(value);
+            }
+            Op::Store(slot) => {
+                let value = self.pop();
+                *self.slot(slot).borrow_mut() = value;
+            }
+            Op::Define(slot) => {
+                let value = self.pop();
+                self.frame().slots[slot as usize] = cell(value);
+            }
+            Op::Ref(slot) => {
+                let place = Place::new(self.slot(slot).clone());
+                self.stack.push(Value::Ref(place));
+            }
+            Op::Global(idx) => match &self.globals[idx as usize] {
+                Some(value) => self.stack.push(value.clone()),
+                None => {
+                    let func = self.module.globals[idx as usize];
+                    let base = self.stack.len();
+                    self.push_frame(func, &[], Vec::new(), base, Some(idx))?;
+                }
+            },
+            Op::Func(idx) => self.stack.push(Value::Fn(idx)),
+            Op::Builtin(builtin) => self.stack.push(Value::Builtin(builtin)),
+            Op::Ctor(layout) => self
+                .stack
+                .push(Value::Ctor(self.module.layouts[layout as usize].clone())),
+            Op::Closure(func) => {
+                let outer = &self.module.functions[func as usize].captures;
+                let frame = self.frames.last().expect("a frame is running");
+                let captures = outer
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, slot)| (idx as u32, frame.slots[*slot as usize].clone()))
+                    .collect();
+                self.stack.push(Value::Closure(Rc::new(Closure {
+                    code: func,
+                    captures,
+                })));
+            }
+
+            Op::Tuple(len) => {
+                let elems = self.pop_n(len);
+                self.stack.push(Value::Tuple(elems));
+            }
+            Op::Array(len) => {
+                let elems = self.pop_n(len);
+                self.stack.push(Value::Array(elems));
+            }
+            Op::Struct { layout, len } => {
+                let fields = self.pop_n(len);
+                let layout = self.module.layouts[layout as usize].clone();
+                self.stack.push(Value::Struct(layout, fields));
+            }
+            Op::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let end = end.then(|| Box::new(self.pop()));
+                let start = start.then(|| Box::new(self.pop()));
+                self.stack.push(Value::Range {
+                    start,
+                    end,
+                    inclusive,
+                });
+            }
+
+            Op::Binary(op) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                match value::binary(op, &lhs, &rhs) {
+                    Ok(value) => self.stack.push(value),
+                    Err(err) => return fault(err, "in this operation"),
+                }
+            }
+            Op::Neg => {
+                let operand = self.pop();
+                match value::negate(&operand) {
+                    Ok(value) => self.stack.push(value),
+                    Err(err) => return fault(err, "negated here"),
+                }
+            }
+            Op::Not => {
+                let operand = self.pop();
+                match value::not(&operand) {
+                    Ok(value) => self.stack.push(value),
+                    Err(err) => return fault(err, "here"),
+                }
+            }
+            Op::Cast(to) => {
+                let operand = self.pop();
+                self.stack.push(value::cast(&operand, &to.ty()));
+            }
+            Op::Deref => match self.pop() {
+                Value::Ref(place) => self.stack.push(place.get()),
+                value => self.stack.push(value),
+            },
+            Op::Field(idx) => {
+                let base = self.pop().deref();
+                match base.elems().get(idx as usize) {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => return fault(format!("no field {idx} on `{base}`"), "unknown field"),
+                }
+            }
+            Op::Index => {
+                let index = self.pop();
+                let base = self.pop().deref();
+                match value::index(base, &index) {
+                    Ok(value) => self.stack.push(value),
+                    Err(err) => return fault(err, "this index"),
+                }
+            }
+
+            Op::Temp => {
+                let value = self.pop();
+                self.stack.push(Value::Ref(Place::temporary(value)));
+            }
+            Op::Project(idx) => {
+                let place = self.pop_place();
+                let len = place.get().elems().len();
+                if idx as usize >= len {
+                    return fault(format!("no field {idx}"), "unknown field");
+                }
+                self.stack.push(Value::Ref(place.project(idx as usize)));
+            }
+            Op::ProjectIndex => {
+                let index = self.pop();
+                let place = self.pop_place();
+                // a slice is a copy of the elements, as with `Index`
+                if let Value::Range { .. } = index {
+                    match value::index(place.get(), &index) {
+                        Ok(slice) => self.stack.push(Value::Ref(Place::temporary(slice))),
+                        Err(err) => return fault(err, "this index"),
+                    }
+                    return Ok(());
+                }
+                let idx = index.as_int().unwrap_or(-1);
+                let len = place.get().elems().len();
+
+                match usize::try_from(idx) {
+                    Ok(idx) if idx < len => self.stack.push(Value::Ref(place.project(idx))),
+                    _ => {
+                        return fault(
+                            format!(
+                                "index out of bounds: the length is {len} but the index is {idx}"
+                            ),
+                            "this index",
+                        );
+                    }
+                }
+            }
+            Op::Set => {
+                let place = self.pop_place();
+                let value = self.pop();
+                place.set(value);
+            }
+            Op::Update(op) => {
+                let place = self.pop_place();
+                let rhs = self.pop();
+                match value::binary(op, &place.get(), &rhs) {
+                    Ok(value) => place.set(value),
+                    Err(err) => return fault(err, "in this operation"),
+                }
+            }
+            Op::Bump(op) => {
+                let place = self.pop_place();
+                let old = place.get();
+                let Value::Int(_, ty) = old else {
+                    return fault("only integers can be incremented", "here");
+                };
+                match value::binary(op, &old, &Value::Int(1, ty)) {
+                    Ok(new) => place.set(new),
+                    Err(err) => return fault(err, "in this operation"),
+                }
+                self.stack.push(old);
+            }
+            Op::Tag(layout) => {
+                let value = self.pop().deref();
+                let is = matches!(&value, Value::Struct(l, _) if l.id == layout);
+                self.stack.push(Value::Bool(is));
+            }
+
+            Op::Jump(target) => self.frame().pc = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !self.pop().is_truthy() {
+                    self.frame().pc = target as usize;
+                }
+            }
+            Op::JumpIfTrue(target) => {
+                if self.pop().is_truthy() {
+                    self.frame().pc = target as usize;
+                }
+            }
+            Op::Iter(slot) => {
+                let (iterable, counter) = match self.pop().deref() {
+                    Value::Range {
+                        start: Some(start),
+                        end,
+                        inclusive,
+                    } if matches!(*start, Value::Int(..)) => {
+                        let counter = *start.clone();
+                        (
+                            Value::Range {
+                                start: Some(start),
+                                end,
+                                inclusive,
+                            },
+                            counter,
+                        )
+                    }
+                    Value::Array(elems) => (Value::Array(elems), Value::Int(0, IntTy::U64)),
+                    Value::Str(s) => (
+                        Value::Array(s.chars().map(Value::Char).collect()),
+                        Value::Int(0, IntTy::U64),
+                    ),
+                    _ => return fault("value is not iterable", "cannot loop over this"),
+                };
+
+                let frame = self.frame();
+                frame.slots[slot as usize] = cell(iterable);
+                frame.slots[slot as usize + 1] = cell(counter);
+            }
+            Op::Next { slot, exit } => {
+                let frame = self.frames.last_mut().expect("a frame is running");
+                let Value::Int(counter, ty) = *frame.slots[slot as usize + 1].borrow() else {
+                    return fault("value is not iterable", "cannot loop over this");
+                };
+
+                let next = match &*frame.slots[slot as usize].borrow() {
+                    Value::Range { end, inclusive, .. } => {
+                        let end = end
+                            .as_ref()
+                            .and_then(|end| end.as_int())
+                            .map(|end| end + *inclusive as i128);
+                        if end.is_some_and(|end| counter >= end) {
+                            None
+                        } else if counter > value::int_max(ty) {
+                            return fault(
+                                "attempt to iterate past the end of the integer range",
+                                "while iterating this",
+                            );
+                        } else {
+                            Some(Value::Int(counter, ty))
+                        }
+                    }
+                    Value::Array(elems) => elems.get(counter as usize).cloned(),
+                    _ => None,
+                };
+
+                match next {
+                    Some(value) => {
+                        *frame.slots[slot as usize + 1].borrow_mut() = Value::Int(counter + 1, ty);
+                        self.stack.push(value);
+                    }
+                    None => frame.pc = exit as usize,
+                }
+            }
+            Op::Call(argc) => {
+                let args = self.pop_n(argc);
+                let callee = self.pop();
+                self.call_value(callee, args)?;
+            }
+            Op::Return => unreachable!("returns are handled by `execute`"),
+            Op::NoMatch(slot) => {
+                let value = self.slot(slot).borrow().clone();
+                return fault(
+                    format!("no `match` arm matches `{value}`"),
+                    "this value was not matched",
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The place a reference on the stack points to, through any further
+    /// references. Other values are turned into temporaries.
+    fn pop_place(&mut self) -> Place {
+        let mut place = match self.pop() {
+            Value::Ref(place) => place,
+            value => Place::temporary(value),
+        };
+        while let Value::Ref(inner) = place.get() {
+            place = inner;
+        }
+        place
+    }
+
+    fn call_value(&mut self, callee: Value, args: Vec<Value>) -> Result<(), Fault> {
+        let base = self.stack.len();
+
+        match callee {
+            Value::Fn(func) => self.push_frame(func, &[], args, base, None),
+            Value::Closure(closure) => {
+                self.push_frame(closure.code, &closure.captures, args, base, None)
+            }
+            Value::Builtin(builtin) => {
+                let result = self.builtin(builtin, args)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::Ctor(layout) => {
+                self.stack.push(Value::Struct(layout, args));
+                Ok(())
+            }
+            value => fault(format!("`{value}` is not a function"), "called here"),
+        }
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: Vec<Value>) -> Result<Value, Fault> {
+        match builtin {
+            Builtin::Print | Builtin::Println => {
+                for arg in &args {
+                    let _ = write!(self.out, "{arg}");
+                }
+                if builtin == Builtin::Println {
+                    let _ = writeln!(self.out);
+                }
+                Ok(Value::Unit)
+            }
+            Builtin::Assert => match args.first() {
+                Some(Value::Bool(true)) => Ok(Value::Unit),
+                _ => fault("assertion failed", "this assertion does not hold"),
+            },
+        }
+    }
+}
+
+fn cell(value: Value) -> Rc<RefCell<Value>> {
+    Rc::new(RefCell::new(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        akc,
+        bytecode::disassemble,
+        compiler::Compiler,
+        diagnostics::Diagnostic,
+        testing::{self, with_big_stack},
+    };
+
+    struct Outcome {
+        out: String,
+        errors: Vec<Diagnostic>,
+    }
+
+    /// Runs `src` on the VM, and on the interpreter to check they agree.
+    fn run(src: &str) -> Outcome {
+        let checked = testing::check(src);
+        let mut compiler = Compiler::new(&checked.res, &checked.types);
+        let module = compiler.compile_script(&checked.script);
+        assert!(
+            compiler.bag.is_empty(),
+            "compile errors: {:?}",
+            compiler.bag.0
+        );
+        if let Err(err) = akc::validate(&module) {
+            panic!("{err}\n{}", disassemble(&module, &checked.map));
+        }
+
+        let mut out = Vec::new();
+        let bag = {
+            let mut vm = Vm::new(&module, &mut out);
+            vm.run();
+            // every path out of a call, error or not, leaves nothing behind
+            assert!(vm.stack.is_empty() && vm.frames.is_empty());
+            vm.bag
+        };
+
+        let (expected, expected_errors) = checked.interpret();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, expected, "the VM and the interpreter disagree");
+        assert_eq!(bag.len(), expected_errors.len());
+
+        Outcome { out, errors: bag.0 }
+    }
+
+    #[test]
+    fn test_jumps_out_of_expressions_keep_the_stack_balanced() {
+        let outcome = run("fn first_even(xs: [i32; 4]): i32 {
+                for x in xs { let y = 100 + { if x % 2 == 0 { return x; } x }; }
+                -1
+             }
+             fn main() {
+                let mut acc = 0;
+                let mut i = 0;
+                while i < 10000 {
+                    i += 1;
+                    acc += 1 + { if i % 3 == 0 { continue; } 1 } * (2 + { if i > 9990 { break; } 0 });
+                }
+                let v = 10 * loop { break 2 + 3; };
+                let w = match acc {
+                    0 => 0,
+                    n => n % 7 + { let mut k = 0; while true { k += 1; if k == 3 { break; } } k },
+                };
+                println((acc, v, w, first_even([1, 3, 4, 5])));
+             }");
+
+        assert_eq!(outcome.out, "(19980, 50, 5, 4)\n");
+    }
+
+    #[test]
+    fn test_closures_share_captured_variables() {
+        let outcome = run("const DOUBLE: i32 = BASE * 2;
+             const BASE: i32 = 21;
+             fn make_counter(start: i32): fn(): i32 {
+                let mut n = start;
+                || { n += 1; n }
+             }
+             fn apply(f: fn(i32): i32, x: i32): i32 { f(x) }
+             fn main() {
+                let next = make_counter(10);
+                next();
+                let mut total = 0;
+                let add = |x: i32| { total += x; total };
+                let twice = |x: i32| add(add(x));
+                apply(twice, 5);
+                let mut adders = [|x: i32| x, |x: i32| x, |x: i32| x];
+                for i in 0..3 { adders[i] = |x: i32| x + i * DOUBLE; }
+                println((next(), total, adders[0](1), adders[2](1)));
+             }");
+
+        assert_eq!(outcome.out, "(12, 10, 1, 85)\n");
+    }
+
+    #[test]
+    fn test_deep_recursion_faults_instead_of_crashing() {
+        let outcome = with_big_stack(|| {
+            run(
+                "fn depth(n: i32): i32 { if n == 0 { 0 } else { 1 + depth(n - 1) } }
+                 fn forever(n: i32): i32 { forever(n + 1) }
+                 fn main() { println(depth(1500)); forever(0); }",
+            )
+        });
+
+        assert_eq!(outcome.out, "1500\n");
+        assert!(outcome.errors[0].message.starts_with("stack overflow"));
+    }
+
+    #[test]
+    fn test_borrow_slices() {
+        let outcome = run("fn sum(values: &[i32]): i32 {
+                let mut total = 0;
+                for v in values { total += v; }
+                total
+             }
+             fn main() {
+                let a = [1, 2, 3, 4];
+                let s = &a[1..3];
+                println((sum(s), sum(&a[..]), s[0]));
+             }");
+
+        assert!(outcome.errors.is_empty());
+        assert_eq!(outcome.out, "(5, 10, 2)\n");
+    }
+
+    #[test]
+    fn test_runtime_errors_point_at_source() {
+        let outcome = run("fn main() {
+                let x: u8 = 200;
+                let y = x + 100;
+             }");
+
+        assert_eq!(outcome.errors.len(), 1);
+        assert_eq!(outcome.errors[0].message, "attempt to add with overflow");
+        let span = outcome.errors[0].labels[0].span;
+        assert_eq!(span.hi - span.lo, 1);
+    }
+}
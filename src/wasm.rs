@@ -0,0 +1,1307 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Write},
+};
+
+pub const MAGIC: [u8; 4] = *b"\0asm";
+pub const VERSION: u32 = 1;
+pub const PAGE_SIZE: u32 = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn code(self) -> u8 {
+        match self {
+            Self::I32 => 0x7f,
+            Self::I64 => 0x7e,
+            Self::F32 => 0x7d,
+            Self::F64 => 0x7c,
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I32 => write!(f, "i32"),
+            Self::I64 => write!(f, "i64"),
+            Self::F32 => write!(f, "f32"),
+            Self::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+/// The result of a `block`, `loop` or `if`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Empty,
+    Value(ValType),
+}
+
+impl BlockType {
+    fn results(self) -> Vec<ValType> {
+        match self {
+            Self::Empty => Vec::new(),
+            Self::Value(ty) => vec![ty],
+        }
+    }
+}
+
+macro_rules! mem_ops {
+    ($($name:ident = $code:literal, $text:literal, $ty:ident, $align:literal, $store:literal;)*) => {
+        /// A load or store, always at the natural alignment of its width.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum MemOp {
+            $($name,)*
+        }
+
+        impl MemOp {
+            fn code(self) -> u8 {
+                match self {
+                    $(Self::$name => $code,)*
+                }
+            }
+
+            fn text(self) -> &'static str {
+                match self {
+                    $(Self::$name => $text,)*
+                }
+            }
+
+            /// the type loaded or stored
+            pub fn ty(self) -> ValType {
+                match self {
+                    $(Self::$name => ValType::$ty,)*
+                }
+            }
+
+            /// log2 of the access width in bytes
+            fn align(self) -> u32 {
+                match self {
+                    $(Self::$name => $align,)*
+                }
+            }
+
+            pub fn is_store(self) -> bool {
+                match self {
+                    $(Self::$name => $store,)*
+                }
+            }
+        }
+    };
+}
+
+mem_ops! {
+    I32Load = 0x28, "i32.load", I32, 2, false;
+    I64Load = 0x29, "i64.load", I64, 3, false;
+    F32Load = 0x2a, "f32.load", F32, 2, false;
+    F64Load = 0x2b, "f64.load", F64, 3, false;
+    I32Load8S = 0x2c, "i32.load8_s", I32, 0, false;
+    I32Load8U = 0x2d, "i32.load8_u", I32, 0, false;
+    I32Load16S = 0x2e, "i32.load16_s", I32, 1, false;
+    I32Load16U = 0x2f, "i32.load16_u", I32, 1, false;
+    I32Store = 0x36, "i32.store", I32, 2, true;
+    I64Store = 0x37, "i64.store", I64, 3, true;
+    F32Store = 0x38, "f32.store", F32, 2, true;
+    F64Store = 0x39, "f64.store", F64, 3, true;
+    I32Store8 = 0x3a, "i32.store8", I32, 0, true;
+    I32Store16 = 0x3b, "i32.store16", I32, 1, true;
+}
+
+macro_rules! num_ops {
+    ($($name:ident = $code:literal, $text:literal, [$($param:ident),*] -> $result:ident;)*) => {
+        /// An instruction working only on the operand stack. Codes above
+        /// `0xff` are the `0xfc` prefixed ones.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum NumOp {
+            $($name,)*
+        }
+
+        impl NumOp {
+            fn code(self) -> u32 {
+                match self {
+                    $(Self::$name => $code,)*
+                }
+            }
+
+            fn text(self) -> &'static str {
+                match self {
+                    $(Self::$name => $text,)*
+                }
+            }
+
+            pub fn params(self) -> &'static [ValType] {
+                match self {
+                    $(Self::$name => &[$(ValType::$param),*],)*
+                }
+            }
+
+            pub fn result(self) -> ValType {
+                match self {
+                    $(Self::$name => ValType::$result,)*
+                }
+            }
+        }
+    };
+}
+
+num_ops! {
+    I32Eqz = 0x45, "i32.eqz", [I32] -> I32;
+    I32Eq = 0x46, "i32.eq", [I32, I32] -> I32;
+    I32Ne = 0x47, "i32.ne", [I32, I32] -> I32;
+    I32LtS = 0x48, "i32.lt_s", [I32, I32] -> I32;
+    I32LtU = 0x49, "i32.lt_u", [I32, I32] -> I32;
+    I32GtS = 0x4a, "i32.gt_s", [I32, I32] -> I32;
+    I32GtU = 0x4b, "i32.gt_u", [I32, I32] -> I32;
+    I32LeS = 0x4c, "i32.le_s", [I32, I32] -> I32;
+    I32LeU = 0x4d, "i32.le_u", [I32, I32] -> I32;
+    I32GeS = 0x4e, "i32.ge_s", [I32, I32] -> I32;
+    I32GeU = 0x4f, "i32.ge_u", [I32, I32] -> I32;
+    I64Eqz = 0x50, "i64.eqz", [I64] -> I32;
+    I64Eq = 0x51, "i64.eq", [I64, I64] -> I32;
+    I64Ne = 0x52, "i64.ne", [I64, I64] -> I32;
+    I64LtS = 0x53, "i64.lt_s", [I64, I64] -> I32;
+    I64LtU = 0x54, "i64.lt_u", [I64, I64] -> I32;
+    I64GtS = 0x55, "i64.gt_s", [I64, I64] -> I32;
+    I64GtU = 0x56, "i64.gt_u", [I64, I64] -> I32;
+    I64LeS = 0x57, "i64.le_s", [I64, I64] -> I32;
+    I64LeU = 0x58, "i64.le_u", [I64, I64] -> I32;
+    I64GeS = 0x59, "i64.ge_s", [I64, I64] -> I32;
+    I64GeU = 0x5a, "i64.ge_u", [I64, I64] -> I32;
+    F32Eq = 0x5b, "f32.eq", [F32, F32] -> I32;
+    F32Ne = 0x5c, "f32.ne", [F32, F32] -> I32;
+    F32Lt = 0x5d, "f32.lt", [F32, F32] -> I32;
+    F32Gt = 0x5e, "f32.gt", [F32, F32] -> I32;
+    F32Le = 0x5f, "f32.le", [F32, F32] -> I32;
+    F32Ge = 0x60, "f32.ge", [F32, F32] -> I32;
+    F64Eq = 0x61, "f64.eq", [F64, F64] -> I32;
+    F64Ne = 0x62, "f64.ne", [F64, F64] -> I32;
+    F64Lt = 0x63, "f64.lt", [F64, F64] -> I32;
+    F64Gt = 0x64, "f64.gt", [F64, F64] -> I32;
+    F64Le = 0x65, "f64.le", [F64, F64] -> I32;
+    F64Ge = 0x66, "f64.ge", [F64, F64] -> I32;
+    I32Add = 0x6a, "i32.add", [I32, I32] -> I32;
+    I32Sub = 0x6b, "i32.sub", [I32, I32] -> I32;
+    I32Mul = 0x6c, "i32.mul", [I32, I32] -> I32;
+    I32DivS = 0x6d, "i32.div_s", [I32, I32] -> I32;
+    I32DivU = 0x6e, "i32.div_u", [I32, I32] -> I32;
+    I32RemS = 0x6f, "i32.rem_s", [I32, I32] -> I32;
+    I32RemU = 0x70, "i32.rem_u", [I32, I32] -> I32;
+    I32And = 0x71, "i32.and", [I32, I32] -> I32;
+    I32Or = 0x72, "i32.or", [I32, I32] -> I32;
+    I32Xor = 0x73, "i32.xor", [I32, I32] -> I32;
+    I32Shl = 0x74, "i32.shl", [I32, I32] -> I32;
+    I32ShrS = 0x75, "i32.shr_s", [I32, I32] -> I32;
+    I32ShrU = 0x76, "i32.shr_u", [I32, I32] -> I32;
+    I64Add = 0x7c, "i64.add", [I64, I64] -> I64;
+    I64Sub = 0x7d, "i64.sub", [I64, I64] -> I64;
+    I64Mul = 0x7e, "i64.mul", [I64, I64] -> I64;
+    I64DivS = 0x7f, "i64.div_s", [I64, I64] -> I64;
+    I64DivU = 0x80, "i64.div_u", [I64, I64] -> I64;
+    I64RemS = 0x81, "i64.rem_s", [I64, I64] -> I64;
+    I64RemU = 0x82, "i64.rem_u", [I64, I64] -> I64;
+    I64And = 0x83, "i64.and", [I64, I64] -> I64;
+    I64Or = 0x84, "i64.or", [I64, I64] -> I64;
+    I64Xor = 0x85, "i64.xor", [I64, I64] -> I64;
+    I64Shl = 0x86, "i64.shl", [I64, I64] -> I64;
+    I64ShrS = 0x87, "i64.shr_s", [I64, I64] -> I64;
+    I64ShrU = 0x88, "i64.shr_u", [I64, I64] -> I64;
+    F32Neg = 0x8c, "f32.neg", [F32] -> F32;
+    F32Add = 0x92, "f32.add", [F32, F32] -> F32;
+    F32Sub = 0x93, "f32.sub", [F32, F32] -> F32;
+    F32Mul = 0x94, "f32.mul", [F32, F32] -> F32;
+    F32Div = 0x95, "f32.div", [F32, F32] -> F32;
+    F64Neg = 0x9a, "f64.neg", [F64] -> F64;
+    F64Add = 0xa0, "f64.add", [F64, F64] -> F64;
+    F64Sub = 0xa1, "f64.sub", [F64, F64] -> F64;
+    F64Mul = 0xa2, "f64.mul", [F64, F64] -> F64;
+    F64Div = 0xa3, "f64.div", [F64, F64] -> F64;
+    F64Min = 0xa4, "f64.min", [F64, F64] -> F64;
+    F64Max = 0xa5, "f64.max", [F64, F64] -> F64;
+    I32WrapI64 = 0xa7, "i32.wrap_i64", [I64] -> I32;
+    I64ExtendI32S = 0xac, "i64.extend_i32_s", [I32] -> I64;
+    I64ExtendI32U = 0xad, "i64.extend_i32_u", [I32] -> I64;
+    F32DemoteF64 = 0xb6, "f32.demote_f64", [F64] -> F32;
+    F64ConvertI32S = 0xb7, "f64.convert_i32_s", [I32] -> F64;
+    F64ConvertI32U = 0xb8, "f64.convert_i32_u", [I32] -> F64;
+    F64ConvertI64S = 0xb9, "f64.convert_i64_s", [I64] -> F64;
+    F64ConvertI64U = 0xba, "f64.convert_i64_u", [I64] -> F64;
+    F64PromoteF32 = 0xbb, "f64.promote_f32", [F32] -> F64;
+    I32Extend8S = 0xc0, "i32.extend8_s", [I32] -> I32;
+    I32Extend16S = 0xc1, "i32.extend16_s", [I32] -> I32;
+    I64TruncSatF64S = 0xfc06, "i64.trunc_sat_f64_s", [F64] -> I64;
+    I64TruncSatF64U = 0xfc07, "i64.trunc_sat_f64_u", [F64] -> I64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Nop,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    /// calls a function, imports first in the index space
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// a load or store with a constant offset added to the address
+    Mem(MemOp, u32),
+    /// copies bytes: destination, source and length
+    MemoryCopy,
+    I32Const(i32),
+    I64Const(i64),
+    F32Const(f32),
+    F64Const(f64),
+    Num(NumOp),
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub ty: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Func {
+    /// for listings and the name section
+    pub name: String,
+    pub ty: u32,
+    /// locals after the parameters
+    pub locals: Vec<ValType>,
+    /// the instructions, without the `end` closing the body
+    pub body: Vec<Instr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: String,
+    pub ty: ValType,
+    pub mutable: bool,
+    /// a constant instruction
+    pub init: Instr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Func(u32),
+    Memory(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExportKind,
+}
+
+/// Bytes copied into memory when the module is instantiated.
+#[derive(Debug, Clone)]
+pub struct Data {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// A WebAssembly module, with the functions it imports but no tables.
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub types: Vec<FuncType>,
+    /// imported functions
+    pub imports: Vec<Import>,
+    pub funcs: Vec<Func>,
+    /// the minimum size of the memory in pages, if there is one
+    pub memory: Option<u32>,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    pub data: Vec<Data>,
+}
+
+impl Module {
+    /// The index of `ty`, adding it if it is new.
+    pub fn type_index(&mut self, ty: FuncType) -> u32 {
+        match self.types.iter().position(|t| *t == ty) {
+            Some(idx) => idx as u32,
+            None => {
+                self.types.push(ty);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    /// The type of a function, imported or defined.
+    pub fn func_type(&self, func: u32) -> Option<&FuncType> {
+        let func = func as usize;
+        let ty = match func.checked_sub(self.imports.len()) {
+            None => self.imports[func].ty,
+            Some(idx) => self.funcs.get(idx)?.ty,
+        };
+        self.types.get(ty as usize)
+    }
+
+    fn func_name(&self, func: u32) -> String {
+        let func = func as usize;
+        match func.checked_sub(self.imports.len()) {
+            None => {
+                let import = &self.imports[func];
+                format!("{}.{}", import.module, import.name)
+            }
+            Some(idx) => match self.funcs.get(idx) {
+                Some(f) if !f.name.is_empty() => f.name.clone(),
+                _ => func.to_string(),
+            },
+        }
+    }
+
+    /// The binary encoding of the module.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+
+        let section = |out: &mut Vec<u8>, id: u8, body: Vec<u8>| {
+            out.push(id);
+            uleb(out, body.len() as u64);
+            out.extend(body);
+        };
+
+        if !self.types.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.types.len() as u64);
+            for ty in &self.types {
+                body.push(0x60);
+                uleb(&mut body, ty.params.len() as u64);
+                body.extend(ty.params.iter().map(|t| t.code()));
+                uleb(&mut body, ty.results.len() as u64);
+                body.extend(ty.results.iter().map(|t| t.code()));
+            }
+            section(&mut out, 1, body);
+        }
+
+        if !self.imports.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.imports.len() as u64);
+            for import in &self.imports {
+                name(&mut body, &import.module);
+                name(&mut body, &import.name);
+                body.push(0x00);
+                uleb(&mut body, import.ty as u64);
+            }
+            section(&mut out, 2, body);
+        }
+
+        if !self.funcs.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.funcs.len() as u64);
+            for func in &self.funcs {
+                uleb(&mut body, func.ty as u64);
+            }
+            section(&mut out, 3, body);
+        }
+
+        if let Some(pages) = self.memory {
+            let mut body = vec![1, 0x00];
+            uleb(&mut body, pages as u64);
+            section(&mut out, 5, body);
+        }
+
+        if !self.globals.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.globals.len() as u64);
+            for global in &self.globals {
+                body.push(global.ty.code());
+                body.push(global.mutable as u8);
+                instr(&mut body, &global.init);
+                body.push(0x0b);
+            }
+            section(&mut out, 6, body);
+        }
+
+        if !self.exports.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.exports.len() as u64);
+            for export in &self.exports {
+                name(&mut body, &export.name);
+                let (kind, idx) = match export.kind {
+                    ExportKind::Func(idx) => (0x00, idx),
+                    ExportKind::Memory(idx) => (0x02, idx),
+                };
+                body.push(kind);
+                uleb(&mut body, idx as u64);
+            }
+            section(&mut out, 7, body);
+        }
+
+        if !self.funcs.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.funcs.len() as u64);
+            for func in &self.funcs {
+                let mut code = Vec::new();
+                // locals are declared as runs of the same type
+                let mut runs: Vec<(u32, ValType)> = Vec::new();
+                for local in &func.locals {
+                    match runs.last_mut() {
+                        Some((count, ty)) if ty == local => *count += 1,
+                        _ => runs.push((1, *local)),
+                    }
+                }
+                uleb(&mut code, runs.len() as u64);
+                for (count, ty) in runs {
+                    uleb(&mut code, count as u64);
+                    code.push(ty.code());
+                }
+                for i in &func.body {
+                    instr(&mut code, i);
+                }
+                code.push(0x0b);
+
+                uleb(&mut body, code.len() as u64);
+                body.extend(code);
+            }
+            section(&mut out, 10, body);
+        }
+
+        if !self.data.is_empty() {
+            let mut body = Vec::new();
+            uleb(&mut body, self.data.len() as u64);
+            for data in &self.data {
+                body.push(0x00);
+                instr(&mut body, &Instr::I32Const(data.offset as i32));
+                body.push(0x0b);
+                uleb(&mut body, data.bytes.len() as u64);
+                body.extend(&data.bytes);
+            }
+            section(&mut out, 11, body);
+        }
+
+        // function names, for debuggers and stack traces
+        let mut names = Vec::new();
+        let named: Vec<_> = (0..(self.imports.len() + self.funcs.len()) as u32)
+            .map(|idx| (idx, self.func_name(idx)))
+            .collect();
+        uleb(&mut names, named.len() as u64);
+        for (idx, text) in named {
+            uleb(&mut names, idx as u64);
+            name(&mut names, &text);
+        }
+        let mut body = Vec::new();
+        name(&mut body, "name");
+        body.push(1);
+        uleb(&mut body, names.len() as u64);
+        body.extend(names);
+        section(&mut out, 0, body);
+
+        out
+    }
+
+    /// The module in the WebAssembly text format.
+    pub fn to_wat(&self) -> String {
+        let mut out = "(module\n".to_owned();
+
+        for (idx, ty) in self.types.iter().enumerate() {
+            _ = writeln!(out, "  (type (;{idx};) (func{}))", signature(ty));
+        }
+        for import in &self.imports {
+            _ = writeln!(
+                out,
+                "  (import {:?} {:?} (func ${}.{} (type {})))",
+                import.module,
+                import.name,
+                identifier(&import.module),
+                identifier(&import.name),
+                import.ty
+            );
+        }
+        if let Some(pages) = self.memory {
+            _ = writeln!(out, "  (memory (;0;) {pages})");
+        }
+        for global in &self.globals {
+            let ty = match global.mutable {
+                true => format!("(mut {})", global.ty),
+                false => global.ty.to_string(),
+            };
+            _ = writeln!(
+                out,
+                "  (global ${} {ty} ({}))",
+                identifier(&global.name),
+                self.instr_text(&global.init)
+            );
+        }
+        for export in &self.exports {
+            let kind = match export.kind {
+                ExportKind::Func(idx) => format!("func ${}", identifier(&self.func_name(idx))),
+                ExportKind::Memory(idx) => format!("memory {idx}"),
+            };
+            _ = writeln!(out, "  (export {:?} ({kind}))", export.name);
+        }
+
+        for (idx, func) in self.funcs.iter().enumerate() {
+            let idx = (self.imports.len() + idx) as u32;
+            let ty = self
+                .types
+                .get(func.ty as usize)
+                .cloned()
+                .unwrap_or_default();
+            _ = writeln!(
+                out,
+                "  (func ${} (type {}){}",
+                identifier(&self.func_name(idx)),
+                func.ty,
+                signature(&ty)
+            );
+            if !func.locals.is_empty() {
+                let locals: Vec<_> = func.locals.iter().map(|t| t.to_string()).collect();
+                _ = writeln!(out, "    (local {})", locals.join(" "));
+            }
+
+            let mut depth = 2;
+            for i in &func.body {
+                if matches!(i, Instr::End | Instr::Else) {
+                    depth -= 1;
+                }
+                _ = writeln!(out, "{}{}", "  ".repeat(depth), self.instr_text(i));
+                if matches!(
+                    i,
+                    Instr::Block(_) | Instr::Loop(_) | Instr::If(_) | Instr::Else
+                ) {
+                    depth += 1;
+                }
+            }
+            out.push_str("  )\n");
+        }
+
+        for data in &self.data {
+            let mut text = String::new();
+            for byte in &data.bytes {
+                match byte {
+                    b'"' | b'\\' => _ = write!(text, "\\{}", *byte as char),
+                    b' '..=b'~' => text.push(*byte as char),
+                    _ => _ = write!(text, "\\{byte:02x}"),
+                }
+            }
+            _ = writeln!(out, "  (data (i32.const {}) \"{text}\")", data.offset);
+        }
+
+        out.push_str(")\n");
+        out
+    }
+
+    fn instr_text(&self, i: &Instr) -> String {
+        let block = |name: &str, ty: &BlockType| match ty {
+            BlockType::Empty => name.to_owned(),
+            BlockType::Value(ty) => format!("{name} (result {ty})"),
+        };
+
+        match i {
+            Instr::Unreachable => "unreachable".to_owned(),
+            Instr::Nop => "nop".to_owned(),
+            Instr::Block(ty) => block("block", ty),
+            Instr::Loop(ty) => block("loop", ty),
+            Instr::If(ty) => block("if", ty),
+            Instr::Else => "else".to_owned(),
+            Instr::End => "end".to_owned(),
+            Instr::Br(depth) => format!("br {depth}"),
+            Instr::BrIf(depth) => format!("br_if {depth}"),
+            Instr::Return => "return".to_owned(),
+            Instr::Call(func) => format!("call ${}", identifier(&self.func_name(*func))),
+            Instr::Drop => "drop".to_owned(),
+            Instr::Select => "select".to_owned(),
+            Instr::LocalGet(idx) => format!("local.get {idx}"),
+            Instr::LocalSet(idx) => format!("local.set {idx}"),
+            Instr::LocalTee(idx) => format!("local.tee {idx}"),
+            Instr::GlobalGet(idx) => format!("global.get {idx}"),
+            Instr::GlobalSet(idx) => format!("global.set {idx}"),
+            Instr::Mem(op, 0) => op.text().to_owned(),
+            Instr::Mem(op, offset) => format!("{} offset={offset}", op.text()),
+            Instr::MemoryCopy => "memory.copy".to_owned(),
+            Instr::I32Const(v) => format!("i32.const {v}"),
+            Instr::I64Const(v) => format!("i64.const {v}"),
+            Instr::F32Const(v) => format!("f32.const {}", float_text(*v as f64, v.to_string())),
+            Instr::F64Const(v) => format!("f64.const {}", float_text(*v, format!("{v:?}"))),
+            Instr::Num(op) => op.text().to_owned(),
+        }
+    }
+
+    /// Checks that every function is well typed and every index refers to
+    /// something, as an engine would before running the module.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let module_error = |message: String| ValidationError {
+            func: None,
+            at: None,
+            message,
+        };
+
+        for (idx, import) in self.imports.iter().enumerate() {
+            if import.ty as usize >= self.types.len() {
+                return Err(module_error(format!(
+                    "import {idx} has unknown type {}",
+                    import.ty
+                )));
+            }
+        }
+        for global in &self.globals {
+            let ty = match global.init {
+                Instr::I32Const(_) => ValType::I32,
+                Instr::I64Const(_) => ValType::I64,
+                Instr::F32Const(_) => ValType::F32,
+                Instr::F64Const(_) => ValType::F64,
+                _ => {
+                    return Err(module_error(format!(
+                        "global `{}` is not initialized by a constant",
+                        global.name
+                    )));
+                }
+            };
+            if ty != global.ty {
+                return Err(module_error(format!(
+                    "global `{}` has type {} but is initialized with {ty}",
+                    global.name, global.ty
+                )));
+            }
+        }
+
+        let mut names = HashSet::new();
+        let func_count = self.imports.len() + self.funcs.len();
+        for export in &self.exports {
+            if !names.insert(export.name.as_str()) {
+                return Err(module_error(format!(
+                    "`{}` is exported more than once",
+                    export.name
+                )));
+            }
+            let valid = match export.kind {
+                ExportKind::Func(idx) => (idx as usize) < func_count,
+                ExportKind::Memory(idx) => idx == 0 && self.memory.is_some(),
+            };
+            if !valid {
+                return Err(module_error(format!(
+                    "export `{}` refers to something that does not exist",
+                    export.name
+                )));
+            }
+        }
+
+        for data in &self.data {
+            let size = self.memory.unwrap_or(0) as u64 * PAGE_SIZE as u64;
+            if data.offset as u64 + data.bytes.len() as u64 > size {
+                return Err(module_error(format!(
+                    "data at {} does not fit in memory",
+                    data.offset
+                )));
+            }
+        }
+
+        for (idx, func) in self.funcs.iter().enumerate() {
+            let Some(ty) = self.types.get(func.ty as usize) else {
+                return Err(module_error(format!(
+                    "function `{}` has unknown type {}",
+                    func.name, func.ty
+                )));
+            };
+            let mut validator = Validator {
+                module: self,
+                locals: ty.params.iter().chain(&func.locals).copied().collect(),
+                stack: Vec::new(),
+                frames: vec![Frame {
+                    kind: FrameKind::Func,
+                    results: ty.results.clone(),
+                    height: 0,
+                    unreachable: false,
+                }],
+            };
+            validator
+                .func(&func.body, &ty.results)
+                .map_err(|(at, message)| ValidationError {
+                    func: Some(self.func_name((self.imports.len() + idx) as u32)),
+                    at,
+                    message,
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a module is not valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub func: Option<String>,
+    /// the index of the offending instruction in the function's body
+    pub at: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.func, self.at) {
+            (Some(func), Some(at)) => write!(f, "in `{func}` at instruction {at}: ")?,
+            (Some(func), None) => write!(f, "in `{func}`: ")?,
+            _ => (),
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Func,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+struct Frame {
+    kind: FrameKind,
+    results: Vec<ValType>,
+    /// the height of the operand stack when the frame was entered
+    height: usize,
+    /// whether the rest of the frame can not be reached, in which case the
+    /// stack is treated as holding whatever is needed
+    unreachable: bool,
+}
+
+/// Type checks a function body with the algorithm from the specification's
+/// appendix.
+struct Validator<'m> {
+    module: &'m Module,
+    locals: Vec<ValType>,
+    /// `None` for values of unknown type, popped from unreachable code
+    stack: Vec<Option<ValType>>,
+    frames: Vec<Frame>,
+}
+
+type Check<T> = Result<T, String>;
+
+impl Validator<'_> {
+    fn func(&mut self, body: &[Instr], results: &[ValType]) -> Result<(), (Option<usize>, String)> {
+        for (at, i) in body.iter().enumerate() {
+            if self.frames.is_empty() {
+                return Err((Some(at), "instruction after the end of the body".to_owned()));
+            }
+            self.instr(i).map_err(|message| (Some(at), message))?;
+        }
+
+        match self.frames.len() {
+            0 => Ok(()),
+            1 => {
+                self.pop_all(results).map_err(|message| (None, message))?;
+                match self.stack.len() {
+                    0 => Ok(()),
+                    n => Err((None, format!("{n} values are left on the stack"))),
+                }
+            }
+            n => Err((None, format!("{} blocks are not closed", n - 1))),
+        }
+    }
+
+    fn push(&mut self, ty: ValType) {
+        self.stack.push(Some(ty));
+    }
+
+    fn pop(&mut self, expected: Option<ValType>) -> Check<Option<ValType>> {
+        let frame = self.frames.last().ok_or("no enclosing block")?;
+        if self.stack.len() == frame.height {
+            return match frame.unreachable {
+                true => Ok(expected),
+                false => Err(match expected {
+                    Some(ty) => format!("expected {ty} but the stack is empty"),
+                    None => "expected a value but the stack is empty".to_owned(),
+                }),
+            };
+        }
+
+        let actual = self.stack.pop().flatten();
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if actual != expected => {
+                Err(format!("expected {expected} but found {actual}"))
+            }
+            (None, _) => Ok(expected),
+            _ => Ok(actual),
+        }
+    }
+
+    fn pop_all(&mut self, tys: &[ValType]) -> Check<()> {
+        for ty in tys.iter().rev() {
+            self.pop(Some(*ty))?;
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, kind: FrameKind, ty: BlockType) {
+        self.frames.push(Frame {
+            kind,
+            results: ty.results(),
+            height: self.stack.len(),
+            unreachable: false,
+        });
+    }
+
+    /// Marks the rest of the current frame unreachable.
+    fn diverge(&mut self) -> Check<()> {
+        let frame = self.frames.last_mut().ok_or("no enclosing block")?;
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+        Ok(())
+    }
+
+    /// The types a branch to the label `depth` frames out carries.
+    fn label(&self, depth: u32) -> Check<Vec<ValType>> {
+        let frame = self
+            .frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .and_then(|idx| self.frames.get(idx))
+            .ok_or_else(|| format!("branch to unknown label {depth}"))?;
+        Ok(match frame.kind {
+            FrameKind::Loop => Vec::new(),
+            _ => frame.results.clone(),
+        })
+    }
+
+    /// Checks the results of the current frame are on the stack and leaves it.
+    fn leave(&mut self) -> Check<Frame> {
+        let results = self
+            .frames
+            .last()
+            .ok_or("no enclosing block")?
+            .results
+            .clone();
+        self.pop_all(&results)?;
+        let frame = self.frames.last().ok_or("no enclosing block")?;
+        if self.stack.len() != frame.height {
+            return Err(format!(
+                "{} values are left on the stack at the end of a block",
+                self.stack.len() - frame.height
+            ));
+        }
+        Ok(self.frames.pop().unwrap())
+    }
+
+    fn local(&self, idx: u32) -> Check<ValType> {
+        self.locals
+            .get(idx as usize)
+            .copied()
+            .ok_or_else(|| format!("unknown local {idx}"))
+    }
+
+    fn global(&self, idx: u32) -> Check<&Global> {
+        self.module
+            .globals
+            .get(idx as usize)
+            .ok_or_else(|| format!("unknown global {idx}"))
+    }
+
+    fn memory(&self) -> Check<()> {
+        match self.module.memory {
+            Some(_) => Ok(()),
+            None => Err("the module has no memory".to_owned()),
+        }
+    }
+
+    fn instr(&mut self, i: &Instr) -> Check<()> {
+        match *i {
+            Instr::Unreachable => self.diverge()?,
+            Instr::Nop => (),
+            Instr::Block(ty) => self.enter(FrameKind::Block, ty),
+            Instr::Loop(ty) => self.enter(FrameKind::Loop, ty),
+            Instr::If(ty) => {
+                self.pop(Some(ValType::I32))?;
+                self.enter(FrameKind::If, ty);
+            }
+            Instr::Else => {
+                let frame = self.leave()?;
+                if frame.kind != FrameKind::If {
+                    return Err("`else` outside an `if`".to_owned());
+                }
+                self.frames.push(Frame {
+                    kind: FrameKind::Else,
+                    unreachable: false,
+                    ..frame
+                });
+            }
+            Instr::End => {
+                let frame = self.leave()?;
+                if frame.kind == FrameKind::If && !frame.results.is_empty() {
+                    return Err("an `if` without an `else` can not have a result".to_owned());
+                }
+                if frame.kind != FrameKind::Func {
+                    for ty in frame.results {
+                        self.push(ty);
+                    }
+                }
+            }
+            Instr::Br(depth) => {
+                let tys = self.label(depth)?;
+                self.pop_all(&tys)?;
+                self.diverge()?;
+            }
+            Instr::BrIf(depth) => {
+                self.pop(Some(ValType::I32))?;
+                let tys = self.label(depth)?;
+                self.pop_all(&tys)?;
+                for ty in tys {
+                    self.push(ty);
+                }
+            }
+            Instr::Return => {
+                let results = self.frames[0].results.clone();
+                self.pop_all(&results)?;
+                self.diverge()?;
+            }
+            Instr::Call(func) => {
+                let ty = self
+                    .module
+                    .func_type(func)
+                    .ok_or_else(|| format!("call to unknown function {func}"))?;
+                self.pop_all(&ty.params)?;
+                for ty in &ty.results {
+                    self.push(*ty);
+                }
+            }
+            Instr::Drop => _ = self.pop(None)?,
+            Instr::Select => {
+                self.pop(Some(ValType::I32))?;
+                let a = self.pop(None)?;
+                let b = self.pop(a)?;
+                self.stack.push(a.or(b));
+            }
+            Instr::LocalGet(idx) => {
+                let ty = self.local(idx)?;
+                self.push(ty);
+            }
+            Instr::LocalSet(idx) => {
+                let ty = self.local(idx)?;
+                self.pop(Some(ty))?;
+            }
+            Instr::LocalTee(idx) => {
+                let ty = self.local(idx)?;
+                self.pop(Some(ty))?;
+                self.push(ty);
+            }
+            Instr::GlobalGet(idx) => {
+                let ty = self.global(idx)?.ty;
+                self.push(ty);
+            }
+            Instr::GlobalSet(idx) => {
+                let global = self.global(idx)?;
+                if !global.mutable {
+                    return Err(format!("global `{}` is immutable", global.name));
+                }
+                let ty = global.ty;
+                self.pop(Some(ty))?;
+            }
+            Instr::Mem(op, _) => {
+                self.memory()?;
+                if op.is_store() {
+                    self.pop(Some(op.ty()))?;
+                    self.pop(Some(ValType::I32))?;
+                } else {
+                    self.pop(Some(ValType::I32))?;
+                    self.push(op.ty());
+                }
+            }
+            Instr::MemoryCopy => {
+                self.memory()?;
+                self.pop_all(&[ValType::I32; 3])?;
+            }
+            Instr::I32Const(_) => self.push(ValType::I32),
+            Instr::I64Const(_) => self.push(ValType::I64),
+            Instr::F32Const(_) => self.push(ValType::F32),
+            Instr::F64Const(_) => self.push(ValType::F64),
+            Instr::Num(op) => {
+                self.pop_all(op.params())?;
+                self.push(op.result());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, text: &str) {
+    uleb(out, text.len() as u64);
+    out.extend(text.as_bytes());
+}
+
+fn instr(out: &mut Vec<u8>, i: &Instr) {
+    let block = |out: &mut Vec<u8>, code: u8, ty: &BlockType| {
+        out.push(code);
+        match ty {
+            BlockType::Empty => out.push(0x40),
+            BlockType::Value(ty) => out.push(ty.code()),
+        }
+    };
+
+    match i {
+        Instr::Unreachable => out.push(0x00),
+        Instr::Nop => out.push(0x01),
+        Instr::Block(ty) => block(out, 0x02, ty),
+        Instr::Loop(ty) => block(out, 0x03, ty),
+        Instr::If(ty) => block(out, 0x04, ty),
+        Instr::Else => out.push(0x05),
+        Instr::End => out.push(0x0b),
+        Instr::Br(depth) => {
+            out.push(0x0c);
+            uleb(out, *depth as u64);
+        }
+        Instr::BrIf(depth) => {
+            out.push(0x0d);
+            uleb(out, *depth as u64);
+        }
+        Instr::Return => out.push(0x0f),
+        Instr::Call(func) => {
+            out.push(0x10);
+            uleb(out, *func as u64);
+        }
+        Instr::Drop => out.push(0x1a),
+        Instr::Select => out.push(0x1b),
+        Instr::LocalGet(idx) | Instr::LocalSet(idx) | Instr::LocalTee(idx) => {
+            out.push(match i {
+                Instr::LocalGet(_) => 0x20,
+                Instr::LocalSet(_) => 0x21,
+                _ => 0x22,
+            });
+            uleb(out, *idx as u64);
+        }
+        Instr::GlobalGet(idx) | Instr::GlobalSet(idx) => {
+            out.push(if matches!(i, Instr::GlobalGet(_)) {
+                0x23
+            } else {
+                0x24
+            });
+            uleb(out, *idx as u64);
+        }
+        Instr::Mem(op, offset) => {
+            out.push(op.code());
+            uleb(out, op.align() as u64);
+            uleb(out, *offset as u64);
+        }
+        Instr::MemoryCopy => out.extend([0xfc, 10, 0x00, 0x00]),
+        Instr::I32Const(v) => {
+            out.push(0x41);
+            sleb(out, *v as i64);
+        }
+        Instr::I64Const(v) => {
+            out.push(0x42);
+            sleb(out, *v);
+        }
+        Instr::F32Const(v) => {
+            out.push(0x43);
+            out.extend(v.to_le_bytes());
+        }
+        Instr::F64Const(v) => {
+            out.push(0x44);
+            out.extend(v.to_le_bytes());
+        }
+        Instr::Num(op) => match op.code() {
+            code @ 0..=0xff => out.push(code as u8),
+            code => {
+                out.push((code >> 8) as u8);
+                uleb(out, (code & 0xff) as u64);
+            }
+        },
+    }
+}
+
+fn signature(ty: &FuncType) -> String {
+    let mut out = String::new();
+    if !ty.params.is_empty() {
+        let params: Vec<_> = ty.params.iter().map(|t| t.to_string()).collect();
+        _ = write!(out, " (param {})", params.join(" "));
+    }
+    if !ty.results.is_empty() {
+        let results: Vec<_> = ty.results.iter().map(|t| t.to_string()).collect();
+        _ = write!(out, " (result {})", results.join(" "));
+    }
+    out
+}
+
+/// `name` with the characters the text format does not allow in
+/// identifiers replaced.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c,
+            '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' | '-' | '.' | '/' | ':' | '<' | '='
+            | '>' | '?' | '@' | '\\' | '^' | '_' | '`' | '|' | '~' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn float_text(value: f64, digits: String) -> String {
+    match value {
+        v if v.is_nan() => "nan".to_owned(),
+        f64::INFINITY => "inf".to_owned(),
+        f64::NEG_INFINITY => "-inf".to_owned(),
+        _ => digits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_module() -> Module {
+        let mut module = Module::default();
+        let ty = module.type_index(FuncType {
+            params: vec![ValType::I32, ValType::I32],
+            results: vec![ValType::I32],
+        });
+        module.funcs.push(Func {
+            name: "add".to_owned(),
+            ty,
+            locals: Vec::new(),
+            body: vec![
+                Instr::LocalGet(0),
+                Instr::LocalGet(1),
+                Instr::Num(NumOp::I32Add),
+            ],
+        });
+        module.exports.push(Export {
+            name: "add".to_owned(),
+            kind: ExportKind::Func(0),
+        });
+        module
+    }
+
+    #[test]
+    fn test_encode_sections() {
+        let bytes = add_module().encode();
+        assert_eq!(
+            &bytes[..33],
+            &[
+                0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+                0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, // types
+                0x03, 0x02, 0x01, 0x00, // functions
+                0x07, 0x07, 0x01, 0x03, b'a', b'd', b'd', 0x00, 0x00, // exports
+                0x0a, 0x09, 0x01,
+            ]
+        );
+        assert_eq!(
+            &bytes[33..43],
+            &[0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b, 0x00, 0x0d]
+        );
+
+        let mut leb = Vec::new();
+        sleb(&mut leb, -129);
+        uleb(&mut leb, 624485);
+        assert_eq!(leb, [0xff, 0x7e, 0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn test_print_wat() {
+        let mut module = add_module();
+        module.funcs[0].body = vec![
+            Instr::LocalGet(0),
+            Instr::If(BlockType::Value(ValType::I32)),
+            Instr::LocalGet(1),
+            Instr::Else,
+            Instr::I32Const(-1),
+            Instr::End,
+        ];
+        assert_eq!(
+            module.to_wat(),
+            "(module
+  (type (;0;) (func (param i32 i32) (result i32)))
+  (export \"add\" (func $add))
+  (func $add (type 0) (param i32 i32) (result i32)
+    local.get 0
+    if (result i32)
+      local.get 1
+    else
+      i32.const -1
+    end
+  )
+)
+"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_ill_typed_code() {
+        assert_eq!(add_module().validate(), Ok(()));
+
+        let mut module = add_module();
+        module.funcs[0].body[1] = Instr::I64Const(1);
+        let err = module.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "in `add` at instruction 2: expected i32 but found i64"
+        );
+
+        // anything goes after an unconditional branch
+        module.funcs[0].body = vec![
+            Instr::Block(BlockType::Empty),
+            Instr::Br(0),
+            Instr::Num(NumOp::F64Add),
+            Instr::Drop,
+            Instr::End,
+            Instr::I32Const(0),
+        ];
+        assert_eq!(module.validate(), Ok(()));
+
+        module.funcs[0].body = vec![
+            Instr::I32Const(0),
+            Instr::If(BlockType::Value(ValType::I32)),
+            Instr::I32Const(1),
+            Instr::End,
+        ];
+        assert_eq!(
+            module.validate().unwrap_err().message,
+            "an `if` without an `else` can not have a result"
+        );
+
+        module.funcs[0].body = vec![Instr::Br(1)];
+        assert_eq!(
+            module.validate().unwrap_err().message,
+            "branch to unknown label 1"
+        );
+    }
+}
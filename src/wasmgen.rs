@@ -0,0 +1,3588 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{
+        BinaryOp, BlockExpr, ConstItem, DefineItem, Expr, FnItem, Item, Literal, Pattern,
+        PostfixOp, PrefixOp, Script, Section, Spanned, Stmt, Visibility,
+    },
+    diagnostics::{Diagnostic, DiagnosticsBag, Reportable},
+    interner::Symbol,
+    resolve::{DefId, DefKind, Resolution, path_span},
+    span::Span,
+    ty::{FloatTy, IntTy, Ty},
+    typeck::{TypeckResults, VariantShape, range_elem},
+    value::{self, Value},
+    wasm::{
+        BlockType, Data, Export, ExportKind, Func, FuncType, Global, Import, Instr as I, MemOp,
+        Module, NumOp as N, PAGE_SIZE, ValType,
+    },
+};
+
+/// The module generated code imports its host functions from.
+pub const IMPORT_MODULE: &str = "akuru";
+
+/// The functions the host provides. Text is passed as a pointer and length
+/// into the exported memory, `nested` asks for strings and chars to be
+/// quoted, and `panic` must not return.
+pub const IMPORTS: [(&str, &[ValType], &[ValType]); 8] = [
+    (
+        "print_str",
+        &[ValType::I32, ValType::I32, ValType::I32],
+        &[],
+    ),
+    ("print_char", &[ValType::I32, ValType::I32], &[]),
+    ("print_int", &[ValType::I64], &[]),
+    ("print_uint", &[ValType::I64], &[]),
+    ("print_f32", &[ValType::F32], &[]),
+    ("print_f64", &[ValType::F64], &[]),
+    ("panic", &[ValType::I32, ValType::I32], &[]),
+    ("fmod", &[ValType::F64, ValType::F64], &[ValType::F64]),
+];
+
+const PRINT_STR: u32 = 0;
+const PRINT_CHAR: u32 = 1;
+const PRINT_INT: u32 = 2;
+const PRINT_UINT: u32 = 3;
+const PRINT_F32: u32 = 4;
+const PRINT_F64: u32 = 5;
+const PANIC: u32 = 6;
+const FMOD: u32 = 7;
+
+/// the global holding the stack pointer
+const SP: u32 = 0;
+const STACK_SIZE: u32 = 1 << 20;
+/// the size of the buffer runtime error messages are written to
+const SCRATCH_SIZE: u32 = 256;
+
+/// Translates a resolved and type checked script to a WebAssembly module.
+///
+/// Scalars are wasm values, `u8` to `u32`, `bool` and `char` as `i32`.
+/// Everything else, structs, enums, tuples, arrays, optionals, strings and
+/// slices, lives in linear memory and is passed around as a pointer, copied
+/// whenever it is stored. Local variables live in a frame on a stack in
+/// memory, so they can be borrowed, and functions returning a value in
+/// memory are passed where to put it as their first parameter.
+pub struct WasmGen<'a> {
+    res: &'a Resolution,
+    types: &'a TypeckResults,
+    pub bag: DiagnosticsBag,
+    module: Module,
+    /// the functions of fn items and the functions computing constants
+    items: HashMap<DefId, u32>,
+    /// where each constant is cached, after a flag set once it is computed
+    consts: HashMap<DefId, u32>,
+    helpers: HashMap<String, u32>,
+    /// the address of the `str` of each string literal
+    strings: HashMap<String, u32>,
+    /// the initial contents of memory, from address zero
+    statics: Vec<u8>,
+    scratch: u32,
+    func: FnState,
+}
+
+/// The function being generated.
+#[derive(Default)]
+struct FnState {
+    code: Vec<I>,
+    params: u32,
+    locals: Vec<ValType>,
+    /// the number of enclosing blocks
+    depth: u32,
+    loops: Vec<LoopLabels>,
+    homes: HashMap<DefId, Home>,
+    /// the size of the frame
+    frame: u32,
+    /// the local holding the address of the frame
+    fp: u32,
+    /// the local holding the stack pointer on entry
+    saved: u32,
+    /// the parameter holding where to put the result
+    sret: Option<u32>,
+    ret: Option<Ty>,
+}
+
+struct LoopLabels {
+    /// the block `break` leaves, as the depth it was opened at
+    exit: u32,
+    /// the block `continue` goes to the end of
+    next: u32,
+    result: Option<Ty>,
+}
+
+/// Where a local variable lives.
+#[derive(Clone, Copy)]
+enum Home {
+    /// at an offset into the frame
+    Frame(u32),
+    /// at the address in a local, for parameters passed in memory
+    Ptr(u32),
+}
+
+/// How values of a type are represented.
+enum Repr {
+    /// types with a single value, which take no space
+    Unit,
+    /// a wasm value, with the instructions loading and storing it
+    Scalar(ValType, MemOp, MemOp),
+    /// a pointer to the bytes of the value
+    Memory(u32),
+}
+
+impl<'a> WasmGen<'a> {
+    pub fn new(res: &'a Resolution, types: &'a TypeckResults) -> Self {
+        let mut module = Module::default();
+        for (name, params, results) in IMPORTS {
+            let ty = module.type_index(FuncType {
+                params: params.to_vec(),
+                results: results.to_vec(),
+            });
+            module.imports.push(Import {
+                module: IMPORT_MODULE.to_owned(),
+                name: name.to_owned(),
+                ty,
+            });
+        }
+        module.globals.push(Global {
+            name: "sp".to_owned(),
+            ty: ValType::I32,
+            mutable: true,
+            init: I::I32Const(0),
+        });
+
+        // address zero stays unused, so it is never a valid pointer
+        let mut statics = vec![0; 16];
+        let scratch = statics.len() as u32;
+        statics.resize(statics.len() + SCRATCH_SIZE as usize, 0);
+
+        Self {
+            res,
+            types,
+            bag: DiagnosticsBag::new(),
+            module,
+            items: HashMap::new(),
+            consts: HashMap::new(),
+            helpers: HashMap::new(),
+            strings: HashMap::new(),
+            statics,
+            scratch,
+            func: FnState::default(),
+        }
+    }
+
+    /// The module for the script. It exports its memory as `memory`, and
+    /// the `pub fn` items of the root section by their names.
+    pub fn generate(&mut self, script: &'a Script) -> Module {
+        self.declare(&script.root);
+        self.define(&script.root);
+
+        for item in &script.root.items {
+            if let Item::Fn(item) | Item::Define(DefineItem::Fn(item)) = item
+                && item.vis == Visibility::Public
+                && let Some(def) = self.res.lookup(item.name.span)
+                && let Some(idx) = self.items.get(&def)
+            {
+                self.module.exports.push(Export {
+                    name: item.name.node.as_str().to_owned(),
+                    kind: ExportKind::Func(*idx),
+                });
+            }
+        }
+        self.module.exports.push(Export {
+            name: "memory".to_owned(),
+            kind: ExportKind::Memory(0),
+        });
+
+        // the stack grows down from the end of memory
+        let stack = align(self.statics.len() as u32, 16);
+        let pages = (stack + STACK_SIZE).div_ceil(PAGE_SIZE);
+        self.module.memory = Some(pages);
+        self.module.globals[SP as usize].init = I::I32Const((pages * PAGE_SIZE) as i32);
+        self.module.data.push(Data {
+            offset: 0,
+            bytes: std::mem::take(&mut self.statics),
+        });
+
+        std::mem::take(&mut self.module)
+    }
+
+    /// Creates the function of every fn item and constant, so calls can
+    /// refer to items defined after them.
+    fn declare(&mut self, section: &Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => {
+                    let Some(def) = self.res.lookup(item.name.span) else {
+                        continue;
+                    };
+                    let (params, ret) = self.signature(def);
+                    let mut wasm_params: Vec<_> =
+                        params.iter().filter_map(|p| self.val_type(p)).collect();
+                    if matches!(self.repr(&ret), Repr::Memory(_)) {
+                        wasm_params.insert(0, ValType::I32);
+                    }
+                    let results = self.val_type(&ret).into_iter().collect();
+                    let idx = self.new_func(self.path(def), wasm_params, results);
+                    self.items.insert(def, idx);
+                }
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => {
+                    let Some(def) = self.res.lookup(item.name.span) else {
+                        continue;
+                    };
+                    let Some(ty) = self.types.consts.get(&def) else {
+                        continue;
+                    };
+                    let (size, align) = self.size_align(ty);
+                    let cache = self.alloc_static(align + size, align.max(1));
+                    self.consts.insert(def, cache);
+
+                    let results = self.val_type(ty).into_iter().collect();
+                    let idx = self.new_func(self.path(def), Vec::new(), results);
+                    self.items.insert(def, idx);
+                }
+                Item::Section(item) => self.declare(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn define(&mut self, section: &'a Section) {
+        for item in &section.items {
+            match item {
+                Item::Fn(item) | Item::Define(DefineItem::Fn(item)) => self.gen_fn(item),
+                Item::Const(item) | Item::Define(DefineItem::Const(item)) => self.gen_const(item),
+                Item::Section(item) => self.define(&item.sections.node),
+                Item::Struct(_) | Item::Enum(_) | Item::Open(_) | Item::Error(_) => (),
+            }
+        }
+    }
+
+    fn signature(&self, def: DefId) -> (Vec<Ty>, Ty) {
+        match self.types.fns.get(&def) {
+            Some(sig) => (sig.params.clone(), sig.ret.clone()),
+            None => (Vec::new(), Ty::unit()),
+        }
+    }
+
+    fn path(&self, def: DefId) -> String {
+        let path: Vec<_> = self.res.path_of(def).iter().map(|s| s.as_str()).collect();
+        path.join("::")
+    }
+
+    fn new_func(&mut self, name: String, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let ty = self.module.type_index(FuncType { params, results });
+        self.module.funcs.push(Func {
+            name,
+            ty,
+            ..Func::default()
+        });
+        (self.module.imports.len() + self.module.funcs.len() - 1) as u32
+    }
+
+    /// Starts generating a function with `params` parameters.
+    fn begin(&mut self, params: u32) {
+        self.func = FnState {
+            params,
+            ..FnState::default()
+        };
+        self.func.fp = self.local(ValType::I32);
+        self.func.saved = self.local(ValType::I32);
+    }
+
+    /// Finishes the function being generated as the body of `idx`, adding
+    /// the code setting up and tearing down its frame.
+    fn finish(&mut self, idx: u32) {
+        self.epilogue();
+        let state = std::mem::take(&mut self.func);
+        let frame = align(state.frame, 16);
+
+        let mut body = vec![
+            I::GlobalGet(SP),
+            I::LocalTee(state.saved),
+            I::I32Const(frame as i32),
+            I::Num(N::I32Sub),
+            I::LocalTee(state.fp),
+            I::GlobalSet(SP),
+        ];
+        body.extend(state.code);
+
+        let func = &mut self.module.funcs[idx as usize - self.module.imports.len()];
+        func.locals = state.locals;
+        func.body = body;
+    }
+
+    fn epilogue(&mut self) {
+        self.emit(I::LocalGet(self.func.saved));
+        self.emit(I::GlobalSet(SP));
+    }
+
+    fn gen_fn(&mut self, item: &'a FnItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let Some(&idx) = self.items.get(&def) else {
+            return;
+        };
+        let (params, ret) = self.signature(def);
+
+        let sret = matches!(self.repr(&ret), Repr::Memory(_));
+        let count = params.iter().filter(|p| self.val_type(p).is_some()).count() as u32;
+        self.begin(count + sret as u32);
+        self.func.ret = Some(ret.clone());
+        if sret {
+            self.func.sret = Some(0);
+        }
+
+        let mut local = sret as u32;
+        for (param, ty) in item.params.iter().zip(&params) {
+            let Some(def) = self.res.lookup(param.node.name.span) else {
+                continue;
+            };
+            let home = match self.repr(ty) {
+                // the caller passes a copy, which is ours to keep
+                Repr::Memory(_) => Home::Ptr(local),
+                Repr::Scalar(..) => {
+                    let (size, align) = self.size_align(ty);
+                    let offset = self.slot(size, align);
+                    self.frame_addr(offset);
+                    self.emit(I::LocalGet(local));
+                    self.store(ty);
+                    Home::Frame(offset)
+                }
+                Repr::Unit => Home::Frame(0),
+            };
+            if self.val_type(ty).is_some() {
+                local += 1;
+            }
+            self.func.homes.insert(def, home);
+        }
+
+        let ty = self.block(&item.body);
+        self.coerce(&ty, &ret);
+        if sret && ty != Ty::Never {
+            self.copy_to_sret(&ret);
+        }
+        self.finish(idx);
+    }
+
+    /// Copies the value on the stack to where the caller wants the result,
+    /// leaving that address on the stack.
+    fn copy_to_sret(&mut self, ty: &Ty) {
+        let Some(sret) = self.func.sret else {
+            return;
+        };
+        let value = self.local(ValType::I32);
+        self.emit(I::LocalSet(value));
+        self.emit(I::LocalGet(sret));
+        self.emit(I::LocalGet(value));
+        self.store(ty);
+        self.emit(I::LocalGet(sret));
+    }
+
+    /// Constants are computed on first use, like the interpreter does.
+    fn gen_const(&mut self, item: &'a ConstItem) {
+        let Some(def) = self.res.lookup(item.name.span) else {
+            return;
+        };
+        let (Some(&idx), Some(&cache), Some(ty)) = (
+            self.items.get(&def),
+            self.consts.get(&def),
+            self.types.consts.get(&def),
+        ) else {
+            return;
+        };
+        let value = cache + self.size_align(ty).1.max(1);
+
+        self.begin(0);
+        self.emit(I::I32Const(cache as i32));
+        self.emit(I::Mem(MemOp::I32Load8U, 0));
+        self.emit(I::Num(N::I32Eqz));
+        self.open(I::If(BlockType::Empty));
+        self.emit(I::I32Const(value as i32));
+        self.expr(&item.value);
+        self.coerce(&self.ty(item.value.span), ty);
+        self.store(ty);
+        self.emit(I::I32Const(cache as i32));
+        self.emit(I::I32Const(1));
+        self.emit(I::Mem(MemOp::I32Store8, 0));
+        self.end();
+
+        self.emit(I::I32Const(value as i32));
+        self.load(ty);
+        self.finish(idx);
+    }
+
+    fn unsupported(&mut self, span: Span, what: &str, ty: &Ty) {
+        self.bag.push(
+            Diagnostic::error(format!(
+                "{what} are not supported by the WebAssembly backend"
+            ))
+            .with_label(span.primary("used here")),
+        );
+        self.zero(ty);
+    }
+
+    // ---------------------------------------------------------- emitting
+
+    fn emit(&mut self, i: I) {
+        self.func.code.push(i);
+    }
+
+    /// Opens a `block`, `loop` or `if`, returning its label.
+    fn open(&mut self, i: I) -> u32 {
+        self.emit(i);
+        self.func.depth += 1;
+        self.func.depth - 1
+    }
+
+    fn end(&mut self) {
+        self.emit(I::End);
+        self.func.depth -= 1;
+    }
+
+    fn br(&mut self, label: u32) {
+        self.emit(I::Br(self.func.depth - 1 - label));
+    }
+
+    fn br_if(&mut self, label: u32) {
+        self.emit(I::BrIf(self.func.depth - 1 - label));
+    }
+
+    fn local(&mut self, ty: ValType) -> u32 {
+        self.func.locals.push(ty);
+        self.func.params + self.func.locals.len() as u32 - 1
+    }
+
+    /// Stores the value on the stack in a new local.
+    fn spill(&mut self, ty: ValType) -> u32 {
+        let local = self.local(ty);
+        self.emit(I::LocalSet(local));
+        local
+    }
+
+    /// Reserves space in the frame, returning its offset.
+    fn slot(&mut self, size: u32, align_to: u32) -> u32 {
+        let offset = align(self.func.frame, align_to.max(1));
+        self.func.frame = offset + size;
+        offset
+    }
+
+    fn frame_addr(&mut self, offset: u32) {
+        self.emit(I::LocalGet(self.func.fp));
+        if offset != 0 {
+            self.emit(I::I32Const(offset as i32));
+            self.emit(I::Num(N::I32Add));
+        }
+    }
+
+    fn add_offset(&mut self, offset: u32) {
+        if offset != 0 {
+            self.emit(I::I32Const(offset as i32));
+            self.emit(I::Num(N::I32Add));
+        }
+    }
+
+    /// Runs `f`, returning the code it generates instead of emitting it.
+    fn capture(&mut self, f: impl FnOnce(&mut Self)) -> Vec<I> {
+        let code = std::mem::take(&mut self.func.code);
+        f(self);
+        std::mem::replace(&mut self.func.code, code)
+    }
+
+    fn alloc_static(&mut self, size: u32, align_to: u32) -> u32 {
+        let addr = align(self.statics.len() as u32, align_to);
+        self.statics.resize((addr + size) as usize, 0);
+        addr
+    }
+
+    /// The address of a `str` holding `text`.
+    fn string(&mut self, text: &str) -> u32 {
+        if let Some(addr) = self.strings.get(text) {
+            return *addr;
+        }
+        let bytes = self.alloc_static(text.len() as u32, 1);
+        self.statics[bytes as usize..].copy_from_slice(text.as_bytes());
+        let addr = self.alloc_static(8, 4);
+        self.statics[addr as usize..addr as usize + 4].copy_from_slice(&bytes.to_le_bytes());
+        self.statics[addr as usize + 4..addr as usize + 8]
+            .copy_from_slice(&(text.len() as u32).to_le_bytes());
+        self.strings.insert(text.to_owned(), addr);
+        addr
+    }
+
+    /// The address and length of the bytes of `text`.
+    fn bytes(&mut self, text: &str) -> (i32, i32) {
+        let addr = self.string(text) as usize;
+        let ptr = u32::from_le_bytes(self.statics[addr..addr + 4].try_into().unwrap());
+        (ptr as i32, text.len() as i32)
+    }
+
+    fn text(&mut self, text: &str) {
+        let (ptr, len) = self.bytes(text);
+        self.emit_all(&[
+            I::I32Const(ptr),
+            I::I32Const(len),
+            I::I32Const(0),
+            I::Call(PRINT_STR),
+        ]);
+    }
+
+    fn panic(&mut self, message: &str) {
+        let (ptr, len) = self.bytes(message);
+        self.emit_all(&[
+            I::I32Const(ptr),
+            I::I32Const(len),
+            I::Call(PANIC),
+            I::Unreachable,
+        ]);
+    }
+
+    fn emit_all(&mut self, code: &[I]) {
+        self.func.code.extend_from_slice(code);
+    }
+
+    // ------------------------------------------------------------- types
+
+    fn ty(&self, span: Span) -> Ty {
+        self.types
+            .exprs
+            .get(&span)
+            .cloned()
+            .unwrap_or_else(Ty::unit)
+    }
+
+    fn repr(&self, ty: &Ty) -> Repr {
+        use MemOp::*;
+
+        match ty {
+            Ty::Int(int) => match (int.bits(), int.is_signed()) {
+                (8, false) => Repr::Scalar(ValType::I32, I32Load8U, I32Store8),
+                (8, true) => Repr::Scalar(ValType::I32, I32Load8S, I32Store8),
+                (16, false) => Repr::Scalar(ValType::I32, I32Load16U, I32Store16),
+                (16, true) => Repr::Scalar(ValType::I32, I32Load16S, I32Store16),
+                (32, _) => Repr::Scalar(ValType::I32, I32Load, I32Store),
+                _ => Repr::Scalar(ValType::I64, I64Load, I64Store),
+            },
+            Ty::Float(FloatTy::F32) => Repr::Scalar(ValType::F32, F32Load, F32Store),
+            Ty::Float(FloatTy::F64) => Repr::Scalar(ValType::F64, F64Load, F64Store),
+            Ty::Bool => Repr::Scalar(ValType::I32, I32Load8U, I32Store8),
+            Ty::Char | Ty::Fn { .. } => Repr::Scalar(ValType::I32, I32Load, I32Store),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => {
+                Repr::Scalar(ValType::I32, I32Load, I32Store)
+            }
+            Ty::Tuple(elems) if elems.is_empty() => Repr::Unit,
+            Ty::Str
+            | Ty::Ref { .. }
+            | Ty::Slice(_)
+            | Ty::Tuple(_)
+            | Ty::Array(..)
+            | Ty::Optional(_)
+            | Ty::Path(_) => Repr::Memory(self.size_align(ty).0),
+            Ty::Generic { .. } | Ty::Infer | Ty::Var(_) | Ty::Never => Repr::Unit,
+        }
+    }
+
+    fn val_type(&self, ty: &Ty) -> Option<ValType> {
+        match self.repr(ty) {
+            Repr::Unit => None,
+            Repr::Scalar(ty, ..) => Some(ty),
+            Repr::Memory(_) => Some(ValType::I32),
+        }
+    }
+
+    fn block_type(&self, ty: &Ty) -> BlockType {
+        match self.val_type(ty) {
+            Some(ty) => BlockType::Value(ty),
+            None => BlockType::Empty,
+        }
+    }
+
+    fn size_align(&self, ty: &Ty) -> (u32, u32) {
+        match ty {
+            Ty::Int(int) => (int.bits() / 8, int.bits() / 8),
+            Ty::Float(FloatTy::F32) => (4, 4),
+            Ty::Float(FloatTy::F64) => (8, 8),
+            Ty::Bool => (1, 1),
+            Ty::Char | Ty::Fn { .. } => (4, 4),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => (4, 4),
+            Ty::Str | Ty::Ref { .. } | Ty::Slice(_) => (8, 4),
+            Ty::Tuple(elems) => {
+                let (_, size, align) = self.fields_layout(elems.iter());
+                (size, align)
+            }
+            Ty::Array(elem, len) => {
+                let (size, align) = self.size_align(elem);
+                (size * *len as u32, align)
+            }
+            Ty::Optional(inner) => {
+                let (size, align) = self.size_align(inner);
+                (self::align(align.max(1) + size, align.max(1)), align.max(1))
+            }
+            Ty::Path(path) => match self.res.def_by_path(path) {
+                Some(def) if self.res.def(def).kind == DefKind::Enum => {
+                    let (_, size, align) = self.enum_layout(def);
+                    (size, align)
+                }
+                Some(def) => {
+                    let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+                    let (_, size, align) = self.fields_layout(fields.iter().map(|(_, ty)| ty));
+                    (size, align)
+                }
+                None => (0, 1),
+            },
+            Ty::Generic { .. } | Ty::Infer | Ty::Var(_) | Ty::Never => (0, 1),
+        }
+    }
+
+    /// The offset of each field laid out in order, and the size and
+    /// alignment of the whole.
+    fn fields_layout<'t>(&self, tys: impl Iterator<Item = &'t Ty>) -> (Vec<u32>, u32, u32) {
+        let mut offsets = Vec::new();
+        let mut size = 0;
+        let mut max_align = 1;
+        for ty in tys {
+            let (field_size, field_align) = self.size_align(ty);
+            let offset = align(size, field_align);
+            offsets.push(offset);
+            size = offset + field_size;
+            max_align = max_align.max(field_align);
+        }
+        (offsets, align(size, max_align), max_align)
+    }
+
+    /// The offset of the fields after the tag, and the size and alignment
+    /// of an enum.
+    fn enum_layout(&self, def: DefId) -> (u32, u32, u32) {
+        let mut max_align = 4;
+        let mut max_size = 0;
+        for variant in self.types.enums.get(&def).cloned().unwrap_or_default() {
+            let tys: Vec<_> = self
+                .variant_fields(variant)
+                .into_iter()
+                .map(|(_, ty)| ty)
+                .collect();
+            let (_, size, align) = self.fields_layout(tys.iter());
+            max_align = max_align.max(align);
+            max_size = max_size.max(size);
+        }
+        let payload = align(4, max_align);
+        (payload, align(payload + max_size, max_align), max_align)
+    }
+
+    fn variant_fields(&self, def: DefId) -> Vec<(Option<Symbol>, Ty)> {
+        match self.types.variants.get(&def) {
+            Some(VariantShape::Tuple(tys)) => tys.iter().map(|ty| (None, ty.clone())).collect(),
+            Some(VariantShape::Struct(fields)) => fields
+                .iter()
+                .map(|(name, ty)| (Some(*name), ty.clone()))
+                .collect(),
+            Some(VariantShape::Unit) | None => Vec::new(),
+        }
+    }
+
+    /// The enum of a variant and its tag.
+    fn variant_tag(&self, def: DefId) -> Option<(DefId, u32)> {
+        let parent = self.res.def(def).parent?;
+        let tag = self
+            .types
+            .enums
+            .get(&parent)?
+            .iter()
+            .position(|v| *v == def)?;
+        Some((parent, tag as u32))
+    }
+
+    /// The offset and type of each field of a variant, from the start of
+    /// the enum.
+    fn variant_layout(&self, def: DefId) -> Vec<(Option<Symbol>, u32, Ty)> {
+        let Some((parent, _)) = self.variant_tag(def) else {
+            return Vec::new();
+        };
+        let (payload, ..) = self.enum_layout(parent);
+        let fields = self.variant_fields(def);
+        let (offsets, ..) = self.fields_layout(fields.iter().map(|(_, ty)| ty));
+        fields
+            .into_iter()
+            .zip(offsets)
+            .map(|((name, ty), offset)| (name, payload + offset, ty))
+            .collect()
+    }
+
+    /// The offset and type of each field of a struct.
+    fn struct_layout(&self, def: DefId) -> Vec<(Symbol, u32, Ty)> {
+        let fields = self.types.structs.get(&def).cloned().unwrap_or_default();
+        let (offsets, ..) = self.fields_layout(fields.iter().map(|(_, ty)| ty));
+        fields
+            .into_iter()
+            .zip(offsets)
+            .map(|((name, ty), offset)| (name, offset, ty))
+            .collect()
+    }
+
+    /// Replaces the address on the stack with the value there.
+    fn load(&mut self, ty: &Ty) {
+        match self.repr(ty) {
+            Repr::Unit => self.emit(I::Drop),
+            Repr::Scalar(_, load, _) => self.emit(I::Mem(load, 0)),
+            Repr::Memory(_) => (),
+        }
+    }
+
+    /// Stores the value on the stack at the address under it.
+    fn store(&mut self, ty: &Ty) {
+        match self.repr(ty) {
+            Repr::Unit => self.emit(I::Drop),
+            Repr::Scalar(_, _, store) => self.emit(I::Mem(store, 0)),
+            Repr::Memory(size) => {
+                self.emit(I::I32Const(size as i32));
+                self.emit(I::MemoryCopy);
+            }
+        }
+    }
+
+    /// Pushes some value of `ty`, for code that is never reached.
+    fn zero(&mut self, ty: &Ty) {
+        match self.val_type(ty) {
+            Some(ValType::I32) => self.emit(I::I32Const(0)),
+            Some(ValType::I64) => self.emit(I::I64Const(0)),
+            Some(ValType::F32) => self.emit(I::F32Const(0.0)),
+            Some(ValType::F64) => self.emit(I::F64Const(0.0)),
+            None => (),
+        }
+    }
+
+    /// Stores the value on the stack in a new slot of the frame, returning
+    /// its offset.
+    fn frame_slot(&mut self, ty: &Ty) -> u32 {
+        let (size, align) = self.size_align(ty);
+        let offset = self.slot(size, align);
+        if let Some(vt) = self.val_type(ty) {
+            let value = self.spill(vt);
+            self.frame_addr(offset);
+            self.emit(I::LocalGet(value));
+            self.store(ty);
+        }
+        offset
+    }
+
+    /// Applies the implicit conversions type checking allows to the value
+    /// on the stack: `T` to `T?` and `&[T; N]` to `&[T]`.
+    fn coerce(&mut self, from: &Ty, to: &Ty) {
+        if from == to || *from == Ty::Never {
+            return;
+        }
+
+        match (from, to) {
+            // the value of a block ending in a `return`
+            (from, to) if from.is_unit() => self.zero(to),
+            (Ty::Optional(_), Ty::Optional(_)) => (),
+            (_, Ty::Optional(inner)) => {
+                self.coerce(from, inner);
+                let value = self.val_type(inner).map(|vt| self.spill(vt));
+                let (size, align) = self.size_align(to);
+                let offset = self.slot(size, align);
+                self.frame_addr(offset);
+                self.emit(I::I32Const(1));
+                self.emit(I::Mem(MemOp::I32Store8, 0));
+                if let Some(value) = value {
+                    self.frame_addr(offset + self.size_align(inner).1.max(1));
+                    self.emit(I::LocalGet(value));
+                    self.store(inner);
+                }
+                self.frame_addr(offset);
+            }
+            (Ty::Ref { inner: x, .. }, Ty::Ref { inner: y, .. }) => {
+                if let (Ty::Array(_, len), Ty::Slice(_)) = (&**x, &**y) {
+                    let ptr = self.spill(ValType::I32);
+                    let offset = self.slot(8, 4);
+                    self.frame_addr(offset);
+                    self.emit(I::LocalGet(ptr));
+                    self.emit(I::Mem(MemOp::I32Store, 0));
+                    self.frame_addr(offset);
+                    self.emit(I::I32Const(*len as i32));
+                    self.emit(I::Mem(MemOp::I32Store, 4));
+                    self.frame_addr(offset);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Follows the references on the stack, except `&[T]` which are slices.
+    fn deref_all(&mut self, ty: &Ty) -> Ty {
+        let mut ty = ty.clone();
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            let inner = (**inner).clone();
+            self.load(&inner);
+            ty = inner;
+        }
+        ty
+    }
+
+    /// Truncates an `i32` to the width of a narrower integer type.
+    fn normalize(&mut self, int: IntTy) {
+        match (int.bits(), int.is_signed()) {
+            (8, true) => self.emit(I::Num(N::I32Extend8S)),
+            (16, true) => self.emit(I::Num(N::I32Extend16S)),
+            (8, false) => self.emit_all(&[I::I32Const(0xff), I::Num(N::I32And)]),
+            (16, false) => self.emit_all(&[I::I32Const(0xffff), I::Num(N::I32And)]),
+            _ => (),
+        }
+    }
+
+    /// Widens the integer on the stack to an `i64`.
+    fn widen(&mut self, ty: &Ty) {
+        match ty {
+            Ty::Int(int) if int.bits() == 64 => (),
+            Ty::Int(int) if int.is_signed() => self.emit(I::Num(N::I64ExtendI32S)),
+            _ => self.emit(I::Num(N::I64ExtendI32U)),
+        }
+    }
+
+    // ------------------------------------------------------- expressions
+
+    /// Generates the statements of a block, leaving the value of its tail
+    /// on the stack and returning its type.
+    fn block(&mut self, block: &'a BlockExpr) -> Ty {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { name, value, .. } => {
+                    let Some(def) = self.res.lookup(name.span) else {
+                        continue;
+                    };
+                    let ty = self
+                        .types
+                        .locals
+                        .get(&def)
+                        .cloned()
+                        .unwrap_or_else(Ty::unit);
+                    let (size, align) = self.size_align(&ty);
+                    let offset = self.slot(size, align);
+
+                    match value {
+                        Some(value) => {
+                            self.frame_addr(offset);
+                            self.expr(value);
+                            self.coerce(&self.ty(value.span), &ty);
+                            self.store(&ty);
+                        }
+                        None if matches!(self.repr(&ty), Repr::Scalar(..)) => {
+                            self.frame_addr(offset);
+                            self.zero(&ty);
+                            self.store(&ty);
+                        }
+                        None => (),
+                    }
+                    self.func.homes.insert(def, Home::Frame(offset));
+                }
+                Stmt::Open(_) => (),
+                Stmt::Expr(expr) | Stmt::Semi(expr) => {
+                    self.expr(expr);
+                    self.discard(&self.ty(expr.span));
+                }
+            }
+        }
+
+        match &block.expr {
+            Some(tail) => {
+                self.expr_at(&tail.node, tail.span);
+                self.ty(tail.span)
+            }
+            None => Ty::unit(),
+        }
+    }
+
+    fn discard(&mut self, ty: &Ty) {
+        if *ty != Ty::Never && self.val_type(ty).is_some() {
+            self.emit(I::Drop);
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Spanned<Expr>) {
+        self.expr_at(&expr.node, expr.span);
+    }
+
+    fn expr_boxed(&mut self, expr: &'a Spanned<Box<Expr>>) {
+        self.expr_at(&expr.node, expr.span);
+    }
+
+    /// Generates the code computing `expr`, which leaves its value on the
+    /// stack, if its type has values.
+    fn expr_at(&mut self, expr: &'a Expr, span: Span) {
+        let ty = self.ty(span);
+
+        match expr {
+            Expr::Literal(literal) => self.literal(literal, &ty),
+            Expr::Variable(_) | Expr::Path(_) => self.name(span, &ty),
+            Expr::Tuple(elems) => {
+                let Ty::Tuple(tys) = &ty else {
+                    return;
+                };
+                if elems.is_empty() {
+                    return;
+                }
+                let (offsets, size, align) = self.fields_layout(tys.iter());
+                let slot = self.slot(size, align);
+                for ((elem, elem_ty), offset) in elems.iter().zip(tys).zip(offsets) {
+                    self.frame_addr(slot + offset);
+                    self.expr(elem);
+                    self.coerce(&self.ty(elem.span), elem_ty);
+                    self.store(elem_ty);
+                }
+                self.frame_addr(slot);
+            }
+            Expr::Array(elems) => {
+                let Ty::Array(elem_ty, _) = &ty else {
+                    return;
+                };
+                let (size, align) = self.size_align(&ty);
+                let slot = self.slot(size, align);
+                let elem_size = self.size_align(elem_ty).0;
+                for (idx, elem) in elems.iter().enumerate() {
+                    self.frame_addr(slot + idx as u32 * elem_size);
+                    self.expr(elem);
+                    self.coerce(&self.ty(elem.span), elem_ty);
+                    self.store(elem_ty);
+                }
+                self.frame_addr(slot);
+            }
+            Expr::Range { .. } => {
+                self.unsupported(span, "ranges outside `for` loops and slicing", &ty)
+            }
+            Expr::Binary(lhs, op, rhs) if op.node.is_logical() => {
+                self.expr_boxed(lhs);
+                self.open(I::If(BlockType::Value(ValType::I32)));
+                match op.node {
+                    BinaryOp::And => {
+                        self.expr_boxed(rhs);
+                        self.emit(I::Else);
+                        self.emit(I::I32Const(0));
+                    }
+                    _ => {
+                        self.emit(I::I32Const(1));
+                        self.emit(I::Else);
+                        self.expr_boxed(rhs);
+                    }
+                }
+                self.end();
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                self.expr_boxed(lhs);
+                self.expr_boxed(rhs);
+                self.binary(op.node, &self.ty(lhs.span), &self.ty(rhs.span));
+            }
+            Expr::Prefix(op, operand) => self.prefix(op.node, operand),
+            Expr::Postfix(operand, op) => {
+                let operand_ty = self.ty(operand.span);
+                let Ty::Int(int) = operand_ty else {
+                    return;
+                };
+                let vt = self.val_type(&operand_ty).unwrap_or(ValType::I32);
+
+                self.place(operand);
+                let addr = self.spill(ValType::I32);
+                self.emit(I::LocalGet(addr));
+                self.load(&operand_ty);
+                let old = self.spill(vt);
+
+                self.emit(I::LocalGet(addr));
+                self.emit(I::LocalGet(old));
+                self.emit(match vt {
+                    ValType::I64 => I::I64Const(1),
+                    _ => I::I32Const(1),
+                });
+                let op = match op.node {
+                    PostfixOp::Inc => BinaryOp::Add,
+                    PostfixOp::Dec => BinaryOp::Sub,
+                };
+                let helper = self.arith(op, int);
+                self.emit(I::Call(helper));
+                self.store(&operand_ty);
+                self.emit(I::LocalGet(old));
+            }
+            Expr::Call(callee, args) => self.call(callee, args, &ty),
+            Expr::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let Some(def) = self.res.lookup(method.span) else {
+                    self.zero(&ty);
+                    return;
+                };
+                let (params, ret) = self.signature(def);
+                self.sret_arg(&ret);
+
+                // borrow the receiver when the function takes a reference
+                let receiver_ty = self.ty(receiver.span);
+                match params.first() {
+                    Some(Ty::Ref { .. }) if !matches!(receiver_ty, Ty::Ref { .. }) => {
+                        self.place(receiver)
+                    }
+                    Some(param) => {
+                        self.expr_boxed(receiver);
+                        self.coerce(&receiver_ty, param);
+                        self.copy_arg(param);
+                    }
+                    None => self.expr_boxed(receiver),
+                }
+                self.args(args, params.get(1..).unwrap_or_default());
+                self.call_item(def);
+            }
+            Expr::Field(base, name) => {
+                let field_ty = self.field_addr(base, name.node);
+                self.load(&field_ty);
+            }
+            Expr::Index(_, index) if matches!(&*index.node, Expr::Range { .. }) => {
+                self.slice(expr, &ty)
+            }
+            Expr::Index(base, index) => {
+                let elem = self.index_addr(base, index);
+                self.load(&elem);
+            }
+            Expr::Cast(operand, _) => {
+                self.expr_boxed(operand);
+                let from = self.deref_all(&self.ty(operand.span));
+                self.cast(&from, &ty);
+            }
+            Expr::Closure { .. } => self.unsupported(span, "closures", &ty),
+            Expr::If { cond, then, else_ } => {
+                self.expr_boxed(cond);
+                self.open(I::If(self.block_type(&ty)));
+                self.expr_boxed(then);
+                self.coerce(&self.ty(then.span), &ty);
+                if let Some(else_) = else_ {
+                    self.emit(I::Else);
+                    self.expr_boxed(else_);
+                    self.coerce(&self.ty(else_.span), &ty);
+                }
+                self.end();
+            }
+            Expr::Loop(body) => {
+                let exit = self.open(I::Block(self.block_type(&ty)));
+                let next = self.open(I::Loop(BlockType::Empty));
+                let result = self.val_type(&ty).map(|_| ty.clone());
+                self.func.loops.push(LoopLabels { exit, next, result });
+                let body_ty = self.block(&body.node);
+                self.discard(&body_ty);
+                self.br(next);
+                self.func.loops.pop();
+                self.end();
+                if self.val_type(&ty).is_some() {
+                    self.emit(I::Unreachable);
+                }
+                self.end();
+            }
+            Expr::While { cond, body } => {
+                let exit = self.open(I::Block(BlockType::Empty));
+                let next = self.open(I::Loop(BlockType::Empty));
+                self.expr_boxed(cond);
+                self.emit(I::Num(N::I32Eqz));
+                self.br_if(exit);
+                self.func.loops.push(LoopLabels {
+                    exit,
+                    next,
+                    result: None,
+                });
+                let body_ty = self.block(body);
+                self.discard(&body_ty);
+                self.func.loops.pop();
+                self.br(next);
+                self.end();
+                self.end();
+            }
+            Expr::For {
+                pattern,
+                iter,
+                body,
+            } => self.for_loop(pattern, iter, body),
+            Expr::Break(value) => {
+                let Some(labels) = self.func.loops.last() else {
+                    return;
+                };
+                let (exit, result) = (labels.exit, labels.result.clone());
+                if let Some(value) = value {
+                    self.expr_boxed(value);
+                    let value_ty = self.ty(value.span);
+                    match &result {
+                        Some(result) => self.coerce(&value_ty, result),
+                        None => self.discard(&value_ty),
+                    }
+                }
+                self.br(exit);
+            }
+            Expr::Continue => {
+                if let Some(labels) = self.func.loops.last() {
+                    let next = labels.next;
+                    self.br(next);
+                }
+            }
+            Expr::Return(value) => {
+                let ret = self.func.ret.clone().unwrap_or_else(Ty::unit);
+                let value_ty = match value {
+                    Some(value) => {
+                        self.expr_boxed(value);
+                        self.ty(value.span)
+                    }
+                    None => Ty::unit(),
+                };
+                if value_ty == Ty::Never {
+                    return;
+                }
+                self.coerce(&value_ty, &ret);
+                self.copy_to_sret(&ret);
+                self.epilogue();
+                self.emit(I::Return);
+            }
+            Expr::Block(block) => {
+                let tail = self.block(&block.node);
+                self.coerce(&tail, &ty);
+            }
+            Expr::Assign { target, value } => {
+                let target_ty = self.ty(target.span);
+                self.expr_boxed(value);
+                self.coerce(&self.ty(value.span), &target_ty);
+                let value = self.val_type(&target_ty).map(|vt| self.spill(vt));
+                self.place(target);
+                if let Some(value) = value {
+                    self.emit(I::LocalGet(value));
+                }
+                self.store(&target_ty);
+            }
+            Expr::AssignEq { op, target, value } => {
+                let target_ty = self.ty(target.span);
+                let value_ty = self.ty(value.span);
+                self.expr_boxed(value);
+                let value = self.val_type(&value_ty).map(|vt| self.spill(vt));
+                self.place(target);
+                let addr = self.spill(ValType::I32);
+                self.emit(I::LocalGet(addr));
+                self.emit(I::LocalGet(addr));
+                self.load(&target_ty);
+                if let Some(value) = value {
+                    self.emit(I::LocalGet(value));
+                }
+                self.binary(op.node, &target_ty, &value_ty);
+                self.store(&target_ty);
+            }
+            Expr::StructInit { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    self.zero(&ty);
+                    return;
+                };
+                let (size, align) = self.size_align(&ty);
+                let slot = self.slot(size, align);
+
+                let layout: Vec<_> = match self.variant_tag(def) {
+                    Some((_, tag)) => {
+                        self.frame_addr(slot);
+                        self.emit(I::I32Const(tag as i32));
+                        self.emit(I::Mem(MemOp::I32Store, 0));
+                        self.variant_layout(def)
+                    }
+                    None => self
+                        .struct_layout(def)
+                        .into_iter()
+                        .map(|(name, offset, ty)| (Some(name), offset, ty))
+                        .collect(),
+                };
+
+                for field in fields {
+                    let name = field.node.name.node;
+                    let Some((_, offset, field_ty)) =
+                        layout.iter().find(|(n, ..)| *n == Some(name)).cloned()
+                    else {
+                        continue;
+                    };
+                    self.frame_addr(slot + offset);
+                    self.expr(&field.node.value);
+                    self.coerce(&self.ty(field.node.value.span), &field_ty);
+                    self.store(&field_ty);
+                }
+                self.frame_addr(slot);
+            }
+            Expr::Match { scrutinee, arms } => {
+                let scrutinee_ty = self.ty(scrutinee.span);
+                self.expr_boxed(scrutinee);
+                let slot = self.frame_slot(&scrutinee_ty);
+                let fp = self.func.fp;
+                let addr = vec![I::LocalGet(fp), I::I32Const(slot as i32), I::Num(N::I32Add)];
+
+                // each arm that can fail is an `if` whose `else` holds the
+                // arms after it
+                let mut open = 0;
+                let mut exhaustive = false;
+                for arm in arms {
+                    let mut conds = Vec::new();
+                    let mut binds = Vec::new();
+                    self.pattern(&arm.pattern, &addr, &scrutinee_ty, &mut conds, &mut binds);
+
+                    let refutable = !conds.is_empty();
+                    if refutable {
+                        self.conds(conds);
+                        self.open(I::If(self.block_type(&ty)));
+                        open += 1;
+                    }
+                    self.emit_all(&binds);
+                    self.expr(&arm.body);
+                    self.coerce(&self.ty(arm.body.span), &ty);
+
+                    if !refutable {
+                        exhaustive = true;
+                        break;
+                    }
+                    self.emit(I::Else);
+                }
+
+                if !exhaustive {
+                    self.panic("no `match` arm matches the value");
+                }
+                for _ in 0..open {
+                    self.end();
+                }
+            }
+            Expr::Error(_) => self.zero(&ty),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal, ty: &Ty) {
+        match value::literal(literal, Some(ty)) {
+            Value::Int(value, int) if int.bits() == 64 => self.emit(I::I64Const(value as i64)),
+            Value::Int(value, _) => self.emit(I::I32Const(value as i32)),
+            Value::Float(value, FloatTy::F32) => self.emit(I::F32Const(value as f32)),
+            Value::Float(value, FloatTy::F64) => self.emit(I::F64Const(value)),
+            Value::Bool(b) => self.emit(I::I32Const(b as i32)),
+            Value::Char(c) => self.emit(I::I32Const(c as i32)),
+            Value::Str(s) => {
+                let addr = self.string(&s);
+                self.emit(I::I32Const(addr as i32));
+            }
+            Value::None => {
+                let (size, align) = self.size_align(ty);
+                let slot = self.slot(size, align);
+                self.frame_addr(slot);
+                self.emit(I::I32Const(0));
+                self.emit(I::Mem(MemOp::I32Store8, 0));
+                self.frame_addr(slot);
+            }
+            _ => self.zero(ty),
+        }
+    }
+
+    fn name(&mut self, span: Span, ty: &Ty) {
+        let Some(def) = self.res.lookup(span) else {
+            self.zero(ty);
+            return;
+        };
+
+        match self.res.def(def).kind {
+            DefKind::Local { .. } => {
+                self.home_addr(def);
+                self.load(ty);
+            }
+            DefKind::Const => match self.items.get(&def) {
+                Some(idx) => self.emit(I::Call(*idx)),
+                None => self.zero(ty),
+            },
+            DefKind::Variant
+                if matches!(self.types.variants.get(&def), Some(VariantShape::Unit)) =>
+            {
+                let (size, align) = self.size_align(ty);
+                let slot = self.slot(size, align);
+                if let Some((_, tag)) = self.variant_tag(def) {
+                    self.frame_addr(slot);
+                    self.emit(I::I32Const(tag as i32));
+                    self.emit(I::Mem(MemOp::I32Store, 0));
+                }
+                self.frame_addr(slot);
+            }
+            DefKind::Fn | DefKind::Variant => {
+                self.unsupported(span, "functions used as values", ty)
+            }
+            DefKind::Builtin => self.unsupported(span, "builtin functions used as values", ty),
+            DefKind::Section | DefKind::Struct | DefKind::Enum => self.zero(ty),
+        }
+    }
+
+    fn home_addr(&mut self, def: DefId) {
+        match self.func.homes.get(&def).copied() {
+            Some(Home::Frame(offset)) => self.frame_addr(offset),
+            Some(Home::Ptr(local)) => self.emit(I::LocalGet(local)),
+            None => self.emit(I::I32Const(0)),
+        }
+    }
+
+    /// Applies `op` to the two values on the stack.
+    fn binary(&mut self, op: BinaryOp, lhs: &Ty, rhs: &Ty) {
+        let rhs_vt = self.val_type(rhs);
+        let lhs = if matches!(lhs, Ty::Ref { .. }) {
+            let b = rhs_vt.map(|vt| self.spill(vt));
+            let lhs = self.deref_all(lhs);
+            if let Some(b) = b {
+                self.emit(I::LocalGet(b));
+            }
+            lhs
+        } else {
+            lhs.clone()
+        };
+        let rhs = self.deref_all(rhs);
+
+        if op.is_comparison() {
+            match (op, &lhs) {
+                (BinaryOp::Eq, _) => self.eq(&lhs),
+                (BinaryOp::Ne, _) => {
+                    self.eq(&lhs);
+                    self.emit(I::Num(N::I32Eqz));
+                }
+                (_, Ty::Str) => {
+                    let cmp = self.str_cmp();
+                    self.emit(I::Call(cmp));
+                    self.emit(I::I32Const(0));
+                    self.emit(I::Num(compare(op, ValType::I32, true)));
+                }
+                (_, Ty::Int(int)) => {
+                    let vt = self.val_type(&lhs).unwrap_or(ValType::I32);
+                    self.emit(I::Num(compare(op, vt, int.is_signed())));
+                }
+                (_, Ty::Float(_)) => {
+                    let vt = self.val_type(&lhs).unwrap_or(ValType::F64);
+                    self.emit(I::Num(compare(op, vt, true)));
+                }
+                _ => self.emit(I::Num(compare(op, ValType::I32, false))),
+            }
+            return;
+        }
+
+        match &lhs {
+            Ty::Int(int) => match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+                    let helper = self.arith(op, *int);
+                    self.emit(I::Call(helper));
+                }
+                BinaryOp::Shl | BinaryOp::Shr => {
+                    self.widen(&rhs);
+                    let helper = self.shift(op, *int);
+                    self.emit(I::Call(helper));
+                }
+                _ => {
+                    let wide = int.bits() == 64;
+                    self.emit(I::Num(match (op, wide) {
+                        (BinaryOp::BitAnd, false) => N::I32And,
+                        (BinaryOp::BitOr, false) => N::I32Or,
+                        (BinaryOp::BitXor, false) => N::I32Xor,
+                        (BinaryOp::BitAnd, true) => N::I64And,
+                        (BinaryOp::BitOr, true) => N::I64Or,
+                        _ => N::I64Xor,
+                    }));
+                }
+            },
+            Ty::Float(float) => {
+                let single = *float == FloatTy::F32;
+                match op {
+                    BinaryOp::Rem => {
+                        if single {
+                            let b = self.spill(ValType::F32);
+                            self.emit(I::Num(N::F64PromoteF32));
+                            self.emit(I::LocalGet(b));
+                            self.emit(I::Num(N::F64PromoteF32));
+                        }
+                        self.emit(I::Call(FMOD));
+                        if single {
+                            self.emit(I::Num(N::F32DemoteF64));
+                        }
+                    }
+                    _ => self.emit(I::Num(match (op, single) {
+                        (BinaryOp::Add, true) => N::F32Add,
+                        (BinaryOp::Sub, true) => N::F32Sub,
+                        (BinaryOp::Mul, true) => N::F32Mul,
+                        (BinaryOp::Div, true) => N::F32Div,
+                        (BinaryOp::Add, false) => N::F64Add,
+                        (BinaryOp::Sub, false) => N::F64Sub,
+                        (BinaryOp::Mul, false) => N::F64Mul,
+                        _ => N::F64Div,
+                    })),
+                }
+            }
+            _ => self.emit(I::Num(match op {
+                BinaryOp::BitAnd => N::I32And,
+                BinaryOp::BitOr => N::I32Or,
+                _ => N::I32Xor,
+            })),
+        }
+    }
+
+    fn prefix(&mut self, op: PrefixOp, operand: &'a Spanned<Box<Expr>>) {
+        let ty = self.ty(operand.span);
+
+        match op {
+            PrefixOp::Neg => {
+                // `-128` is in range for `i8` even though `128` is not
+                if let Expr::Literal(Literal::UInt(v, _)) = &*operand.node
+                    && let Ty::Int(int) = ty
+                {
+                    match int.bits() {
+                        64 => self.emit(I::I64Const((*v as i64).wrapping_neg())),
+                        _ => self.emit(I::I32Const((*v as i32).wrapping_neg())),
+                    }
+                    return;
+                }
+                self.expr_boxed(operand);
+                match ty {
+                    Ty::Int(int) => {
+                        let helper = self.neg(int);
+                        self.emit(I::Call(helper));
+                    }
+                    Ty::Float(FloatTy::F32) => self.emit(I::Num(N::F32Neg)),
+                    _ => self.emit(I::Num(N::F64Neg)),
+                }
+            }
+            PrefixOp::Not => {
+                self.expr_boxed(operand);
+                match ty {
+                    Ty::Int(int) if int.bits() == 64 => {
+                        self.emit_all(&[I::I64Const(-1), I::Num(N::I64Xor)])
+                    }
+                    Ty::Int(int) => {
+                        self.emit_all(&[I::I32Const(-1), I::Num(N::I32Xor)]);
+                        self.normalize(int);
+                    }
+                    _ => self.emit(I::Num(N::I32Eqz)),
+                }
+            }
+            PrefixOp::Deref => {
+                self.expr_boxed(operand);
+                if let Ty::Ref { inner, .. } = &ty
+                    && !matches!(**inner, Ty::Slice(_))
+                {
+                    self.load(inner);
+                }
+            }
+            PrefixOp::Ref | PrefixOp::RefMut => match ty {
+                Ty::Slice(_) => self.expr_boxed(operand),
+                _ => self.place(operand),
+            },
+        }
+    }
+
+    fn cast(&mut self, from: &Ty, to: &Ty) {
+        let from_vt = self.val_type(from);
+        match (from, to) {
+            (Ty::Int(_) | Ty::Bool | Ty::Char, Ty::Int(int)) => match (from_vt, int.bits() == 64) {
+                (Some(ValType::I64), false) => {
+                    self.emit(I::Num(N::I32WrapI64));
+                    self.normalize(*int);
+                }
+                (Some(ValType::I32), true) => {
+                    let signed = matches!(from, Ty::Int(int) if int.is_signed());
+                    self.emit(I::Num(match signed {
+                        true => N::I64ExtendI32S,
+                        false => N::I64ExtendI32U,
+                    }));
+                }
+                (Some(ValType::I32), false) => self.normalize(*int),
+                _ => (),
+            },
+            (Ty::Int(int), Ty::Float(float)) => {
+                self.emit(I::Num(match (int.bits() == 64, int.is_signed()) {
+                    (true, true) => N::F64ConvertI64S,
+                    (true, false) => N::F64ConvertI64U,
+                    (false, true) => N::F64ConvertI32S,
+                    (false, false) => N::F64ConvertI32U,
+                }));
+                if *float == FloatTy::F32 {
+                    self.emit(I::Num(N::F32DemoteF64));
+                }
+            }
+            (Ty::Float(FloatTy::F32), Ty::Float(FloatTy::F64)) => {
+                self.emit(I::Num(N::F64PromoteF32))
+            }
+            (Ty::Float(FloatTy::F64), Ty::Float(FloatTy::F32)) => {
+                self.emit(I::Num(N::F32DemoteF64))
+            }
+            (Ty::Float(float), Ty::Int(int)) => {
+                if *float == FloatTy::F32 {
+                    self.emit(I::Num(N::F64PromoteF32));
+                }
+                // saturating, with NaN becoming zero
+                if int.bits() == 64 {
+                    self.emit(I::Num(match int.is_signed() {
+                        true => N::I64TruncSatF64S,
+                        false => N::I64TruncSatF64U,
+                    }));
+                } else {
+                    self.emit_all(&[
+                        I::F64Const(value::int_min(*int) as f64),
+                        I::Num(N::F64Max),
+                        I::F64Const(value::int_max(*int) as f64),
+                        I::Num(N::F64Min),
+                        I::Num(N::I64TruncSatF64S),
+                        I::Num(N::I32WrapI64),
+                    ]);
+                }
+            }
+            (Ty::Int(_), Ty::Char) => {
+                if from_vt == Some(ValType::I64) {
+                    self.emit(I::Num(N::I32WrapI64));
+                }
+                self.emit_all(&[I::I32Const(0xff), I::Num(N::I32And)]);
+            }
+            _ => (),
+        }
+    }
+
+    /// Pushes where a call puts a result returned in memory.
+    fn sret_arg(&mut self, ret: &Ty) {
+        if let Repr::Memory(size) = self.repr(ret) {
+            let slot = self.slot(size, self.size_align(ret).1);
+            self.frame_addr(slot);
+        }
+    }
+
+    fn call_item(&mut self, def: DefId) {
+        match self.items.get(&def) {
+            Some(idx) => self.emit(I::Call(*idx)),
+            None => self.emit(I::Unreachable),
+        }
+    }
+
+    /// Copies an argument passed in memory, so the callee has its own.
+    fn copy_arg(&mut self, ty: &Ty) {
+        if let Repr::Memory(_) = self.repr(ty) {
+            let slot = self.frame_slot(ty);
+            self.frame_addr(slot);
+        }
+    }
+
+    fn args(&mut self, args: &'a [Spanned<Expr>], params: &[Ty]) {
+        for (idx, arg) in args.iter().enumerate() {
+            self.expr(arg);
+            let arg_ty = self.ty(arg.span);
+            if let Some(param) = params.get(idx) {
+                self.coerce(&arg_ty, param);
+                self.copy_arg(param);
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &'a Spanned<Box<Expr>>, args: &'a [Spanned<Expr>], ty: &Ty) {
+        let def = match &*callee.node {
+            Expr::Variable(_) | Expr::Path(_) => self.res.lookup(callee.span),
+            _ => None,
+        };
+        let Some(def) = def else {
+            for arg in args {
+                self.expr(arg);
+                self.discard(&self.ty(arg.span));
+            }
+            self.unsupported(callee.span, "calls of function values", ty);
+            return;
+        };
+
+        let info = self.res.def(def);
+        match info.kind {
+            DefKind::Builtin => {
+                let arg_ty = match args.first() {
+                    Some(arg) => {
+                        self.expr(arg);
+                        self.ty(arg.span)
+                    }
+                    None => Ty::unit(),
+                };
+                match info.name.as_str() {
+                    "assert" => {
+                        self.emit(I::Num(N::I32Eqz));
+                        self.open(I::If(BlockType::Empty));
+                        self.panic("assertion failed");
+                        self.end();
+                    }
+                    name => {
+                        if !args.is_empty() {
+                            self.print(&arg_ty, I::I32Const(0));
+                        }
+                        if name == "println" {
+                            self.text("\n");
+                        }
+                    }
+                }
+            }
+            DefKind::Variant => {
+                let (size, align) = self.size_align(ty);
+                let slot = self.slot(size, align);
+                if let Some((_, tag)) = self.variant_tag(def) {
+                    self.frame_addr(slot);
+                    self.emit(I::I32Const(tag as i32));
+                    self.emit(I::Mem(MemOp::I32Store, 0));
+                }
+                for (arg, (_, offset, field_ty)) in args.iter().zip(self.variant_layout(def)) {
+                    self.frame_addr(slot + offset);
+                    self.expr(arg);
+                    self.coerce(&self.ty(arg.span), &field_ty);
+                    self.store(&field_ty);
+                }
+                self.frame_addr(slot);
+            }
+            DefKind::Fn => {
+                let (params, ret) = self.signature(def);
+                self.sret_arg(&ret);
+                self.args(args, &params);
+                self.call_item(def);
+            }
+            _ => {
+                for arg in args {
+                    self.expr(arg);
+                    self.discard(&self.ty(arg.span));
+                }
+                self.unsupported(callee.span, "calls of function values", ty);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------ places
+
+    fn is_place(&self, expr: &Expr, span: Span) -> bool {
+        match expr {
+            Expr::Variable(_) => self
+                .res
+                .lookup(span)
+                .is_some_and(|def| matches!(self.res.def(def).kind, DefKind::Local { .. })),
+            Expr::Field(..) => true,
+            Expr::Index(_, index) => !matches!(&*index.node, Expr::Range { .. }),
+            Expr::Prefix(op, _) => op.node == PrefixOp::Deref,
+            _ => false,
+        }
+    }
+
+    fn place(&mut self, expr: &'a Spanned<Box<Expr>>) {
+        self.place_at(&expr.node, expr.span);
+    }
+
+    /// Pushes the address of the location `expr` denotes. Anything that is
+    /// not a variable, field, element or dereference is copied to the
+    /// frame first.
+    fn place_at(&mut self, expr: &'a Expr, span: Span) {
+        let ty = self.ty(span);
+        if !self.is_place(expr, span) {
+            self.expr_at(expr, span);
+            let slot = self.frame_slot(&ty);
+            self.frame_addr(slot);
+            return;
+        }
+
+        match expr {
+            Expr::Variable(_) => match self.res.lookup(span) {
+                Some(def) => self.home_addr(def),
+                None => self.emit(I::I32Const(0)),
+            },
+            Expr::Field(base, name) => _ = self.field_addr(base, name.node),
+            Expr::Index(base, index) => _ = self.index_addr(base, index),
+            Expr::Prefix(_, inner) => self.expr_boxed(inner),
+            _ => self.emit(I::I32Const(0)),
+        }
+    }
+
+    /// Pushes the address of a value of the type of `expr`, following
+    /// references, and returns the type found there.
+    fn operand_addr(&mut self, expr: &'a Spanned<Box<Expr>>) -> Ty {
+        let ty = self.ty(expr.span);
+        if self.is_place(&expr.node, expr.span) {
+            self.place(expr);
+        } else {
+            self.expr_boxed(expr);
+            if let Repr::Scalar(..) = self.repr(&ty) {
+                let slot = self.frame_slot(&ty);
+                self.frame_addr(slot);
+            }
+        }
+
+        let mut ty = ty;
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            let inner = (**inner).clone();
+            self.emit(I::Mem(MemOp::I32Load, 0));
+            ty = inner;
+        }
+        ty
+    }
+
+    /// Pushes the address of a field, returning its type.
+    fn field_addr(&mut self, base: &'a Spanned<Box<Expr>>, name: Symbol) -> Ty {
+        let ty = self.operand_addr(base);
+        let (offset, field_ty) = match &ty {
+            Ty::Tuple(elems) => {
+                let idx: usize = name.as_str().parse().unwrap_or(0);
+                let (offsets, ..) = self.fields_layout(elems.iter());
+                match (offsets.get(idx), elems.get(idx)) {
+                    (Some(offset), Some(elem)) => (*offset, elem.clone()),
+                    _ => (0, Ty::unit()),
+                }
+            }
+            Ty::Path(path) => match self.res.def_by_path(path) {
+                Some(def) => self
+                    .struct_layout(def)
+                    .into_iter()
+                    .find(|(field, ..)| *field == name)
+                    .map(|(_, offset, ty)| (offset, ty))
+                    .unwrap_or((0, Ty::unit())),
+                None => (0, Ty::unit()),
+            },
+            _ => (0, Ty::unit()),
+        };
+        self.add_offset(offset);
+        field_ty
+    }
+
+    /// Pushes the address of an element, checking the index, and returns
+    /// its type.
+    fn index_addr(&mut self, base: &'a Spanned<Box<Expr>>, index: &'a Spanned<Box<Expr>>) -> Ty {
+        let ty = self.operand_addr(base);
+        let base = self.spill(ValType::I32);
+
+        let (elem, len) = match &ty {
+            Ty::Array(elem, len) => ((**elem).clone(), Some(*len as i32)),
+            Ty::Slice(elem) => ((**elem).clone(), None),
+            Ty::Ref { inner, .. } => match &**inner {
+                Ty::Slice(elem) => ((**elem).clone(), None),
+                _ => (Ty::unit(), None),
+            },
+            _ => (Ty::unit(), None),
+        };
+
+        self.expr_boxed(index);
+        let index_ty = self.ty(index.span);
+        self.widen(&index_ty);
+        match len {
+            Some(len) => self.emit(I::I32Const(len)),
+            None => {
+                self.emit(I::LocalGet(base));
+                self.emit(I::Mem(MemOp::I32Load, 4));
+            }
+        }
+        let signed = matches!(index_ty, Ty::Int(int) if int.is_signed());
+        let helper = self.index_helper();
+        self.emit(I::I32Const(signed as i32));
+        self.emit(I::Call(helper));
+
+        let size = self.size_align(&elem).0;
+        self.emit_all(&[I::I32Const(size as i32), I::Num(N::I32Mul)]);
+        self.emit(I::LocalGet(base));
+        if len.is_none() {
+            self.emit(I::Mem(MemOp::I32Load, 0));
+        }
+        self.emit(I::Num(N::I32Add));
+        elem
+    }
+
+    /// `base[a..b]`, a slice into an array or a substring.
+    fn slice(&mut self, expr: &'a Expr, ty: &Ty) {
+        let Expr::Index(base, index) = expr else {
+            return;
+        };
+        let Expr::Range {
+            start,
+            end,
+            inclusive,
+        } = &*index.node
+        else {
+            return;
+        };
+
+        let base_ty = self.operand_addr(base);
+        let base = self.spill(ValType::I32);
+        let len = |this: &mut Self| match &base_ty {
+            Ty::Array(_, len) => this.emit(I::I32Const(*len as i32)),
+            _ => {
+                this.emit(I::LocalGet(base));
+                this.emit(I::Mem(MemOp::I32Load, 4));
+            }
+        };
+
+        match start {
+            Some(start) => {
+                self.expr_boxed(start);
+                self.widen(&self.ty(start.span));
+            }
+            None => self.emit(I::I64Const(0)),
+        }
+        let lo = self.spill(ValType::I64);
+        match end {
+            Some(end) => {
+                self.expr_boxed(end);
+                self.widen(&self.ty(end.span));
+                if *inclusive {
+                    self.emit_all(&[I::I64Const(1), I::Num(N::I64Add)]);
+                }
+            }
+            None => {
+                len(self);
+                self.emit(I::Num(N::I64ExtendI32U));
+            }
+        }
+        let hi = self.spill(ValType::I64);
+
+        let slot = self.slot(8, 4);
+        if base_ty == Ty::Str {
+            let helper = self.str_slice();
+            self.emit_all(&[I::LocalGet(base), I::LocalGet(lo), I::LocalGet(hi)]);
+            self.frame_addr(slot);
+            self.emit(I::Call(helper));
+            return;
+        }
+
+        let check = self.check_range();
+        self.emit_all(&[I::LocalGet(lo), I::LocalGet(hi)]);
+        len(self);
+        self.emit(I::Call(check));
+
+        let elem = match ty {
+            Ty::Slice(elem) => (**elem).clone(),
+            _ => Ty::unit(),
+        };
+        let size = self.size_align(&elem).0;
+        self.frame_addr(slot);
+        self.emit(I::LocalGet(base));
+        if !matches!(base_ty, Ty::Array(..)) {
+            self.emit(I::Mem(MemOp::I32Load, 0));
+        }
+        self.emit_all(&[
+            I::LocalGet(lo),
+            I::Num(N::I32WrapI64),
+            I::I32Const(size as i32),
+            I::Num(N::I32Mul),
+            I::Num(N::I32Add),
+            I::Mem(MemOp::I32Store, 0),
+        ]);
+        self.frame_addr(slot);
+        self.emit_all(&[
+            I::LocalGet(hi),
+            I::LocalGet(lo),
+            I::Num(N::I64Sub),
+            I::Num(N::I32WrapI64),
+            I::Mem(MemOp::I32Store, 4),
+        ]);
+        self.frame_addr(slot);
+    }
+
+    fn for_loop(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        iter: &'a Spanned<Box<Expr>>,
+        body: &'a BlockExpr,
+    ) {
+        let iter_ty = self.ty(iter.span);
+
+        if let Some(elem_ty) = range_elem(&iter_ty) {
+            let Expr::Range {
+                start: Some(start),
+                end,
+                inclusive,
+            } = &*iter.node
+            else {
+                self.unsupported(
+                    iter.span,
+                    "ranges outside `for` loops and slicing",
+                    &Ty::unit(),
+                );
+                return;
+            };
+            let Ty::Int(int) = elem_ty.clone() else {
+                return;
+            };
+            let vt = self.val_type(elem_ty).unwrap_or(ValType::I32);
+            let wide = vt == ValType::I64;
+            let signed = int.is_signed();
+
+            self.expr_boxed(start);
+            let counter = self.spill(vt);
+            let end = end.as_ref().map(|end| {
+                self.expr_boxed(end);
+                self.spill(vt)
+            });
+
+            // `a..=b` keeps a flag rather than stepping past `b`, which could
+            // overflow
+            let more = self.local(ValType::I32);
+            if let (Some(end), true) = (end, *inclusive) {
+                self.emit_all(&[
+                    I::LocalGet(counter),
+                    I::LocalGet(end),
+                    I::Num(compare(BinaryOp::Le, vt, signed)),
+                    I::LocalSet(more),
+                ]);
+            }
+
+            let exit = self.open(I::Block(BlockType::Empty));
+            let top = self.open(I::Loop(BlockType::Empty));
+            match (end, *inclusive) {
+                (Some(end), false) => {
+                    self.emit_all(&[
+                        I::LocalGet(counter),
+                        I::LocalGet(end),
+                        I::Num(compare(BinaryOp::Ge, vt, signed)),
+                    ]);
+                    self.br_if(exit);
+                }
+                (Some(_), true) => {
+                    self.emit_all(&[I::LocalGet(more), I::Num(N::I32Eqz)]);
+                    self.br_if(exit);
+                }
+                (None, _) => (),
+            }
+
+            self.emit(I::LocalGet(counter));
+            self.bind_element(pattern, elem_ty);
+            self.loop_body(exit, body);
+
+            let one = if wide { I::I64Const(1) } else { I::I32Const(1) };
+            let add = if wide { N::I64Add } else { N::I32Add };
+            match (end, *inclusive) {
+                (Some(end), true) => {
+                    let ne = if wide { N::I64Ne } else { N::I32Ne };
+                    self.emit_all(&[
+                        I::LocalGet(counter),
+                        I::LocalGet(end),
+                        I::Num(ne),
+                        I::LocalTee(more),
+                    ]);
+                    self.open(I::If(BlockType::Empty));
+                    self.emit_all(&[I::LocalGet(counter), one, I::Num(add), I::LocalSet(counter)]);
+                    self.end();
+                }
+                (Some(_), false) => {
+                    self.emit_all(&[I::LocalGet(counter), one, I::Num(add), I::LocalSet(counter)]);
+                }
+                (None, _) => {
+                    let max = value::int_max(int);
+                    let max = if wide {
+                        I::I64Const(max as i64)
+                    } else {
+                        I::I32Const(max as i32)
+                    };
+                    let eq = if wide { N::I64Eq } else { N::I32Eq };
+                    self.emit_all(&[I::LocalGet(counter), max, I::Num(eq)]);
+                    self.open(I::If(BlockType::Empty));
+                    self.panic("attempt to iterate past the end of the integer range");
+                    self.end();
+                    self.emit_all(&[I::LocalGet(counter), one, I::Num(add), I::LocalSet(counter)]);
+                }
+            }
+            self.br(top);
+            self.end();
+            self.end();
+            return;
+        }
+
+        // the loop visits a copy, like the interpreter does
+        self.expr_boxed(iter);
+        let ty = self.deref_all(&iter_ty);
+        let slot = self.frame_slot(&ty);
+        let idx = self.local(ValType::I32);
+        let (data, len) = (self.local(ValType::I32), self.local(ValType::I32));
+        match &ty {
+            Ty::Array(_, n) => {
+                self.frame_addr(slot);
+                self.emit_all(&[I::LocalSet(data), I::I32Const(*n as i32), I::LocalSet(len)]);
+            }
+            _ => {
+                self.frame_addr(slot);
+                self.emit(I::Mem(MemOp::I32Load, 0));
+                self.emit(I::LocalSet(data));
+                self.frame_addr(slot);
+                self.emit(I::Mem(MemOp::I32Load, 4));
+                self.emit(I::LocalSet(len));
+            }
+        }
+
+        let exit = self.open(I::Block(BlockType::Empty));
+        let top = self.open(I::Loop(BlockType::Empty));
+        self.emit_all(&[I::LocalGet(idx), I::LocalGet(len), I::Num(N::I32GeU)]);
+        self.br_if(exit);
+
+        let elem = match &ty {
+            Ty::Str => {
+                let next = self.str_next();
+                self.emit_all(&[
+                    I::LocalGet(data),
+                    I::LocalGet(idx),
+                    I::Call(next),
+                    I::LocalSet(idx),
+                ]);
+                Ty::Char
+            }
+            Ty::Array(elem, _) | Ty::Slice(elem) => {
+                let elem = (**elem).clone();
+                self.elem_at(data, idx, &elem);
+                elem
+            }
+            Ty::Ref { inner, .. } => match &**inner {
+                Ty::Slice(elem) => {
+                    let elem = (**elem).clone();
+                    self.elem_at(data, idx, &elem);
+                    elem
+                }
+                _ => Ty::unit(),
+            },
+            _ => Ty::unit(),
+        };
+        self.bind_element(pattern, &elem);
+        self.loop_body(exit, body);
+        self.br(top);
+        self.end();
+        self.end();
+    }
+
+    /// Pushes the element at `idx` and moves `idx` past it.
+    fn elem_at(&mut self, data: u32, idx: u32, elem: &Ty) {
+        let size = self.size_align(elem).0 as i32;
+        self.emit_all(&[
+            I::LocalGet(data),
+            I::LocalGet(idx),
+            I::I32Const(size),
+            I::Num(N::I32Mul),
+            I::Num(N::I32Add),
+        ]);
+        self.load(elem);
+        self.emit_all(&[
+            I::LocalGet(idx),
+            I::I32Const(1),
+            I::Num(N::I32Add),
+            I::LocalSet(idx),
+        ]);
+    }
+
+    /// Binds the loop pattern to the element on the stack.
+    fn bind_element(&mut self, pattern: &'a Spanned<Pattern>, elem: &Ty) {
+        let slot = self.frame_slot(elem);
+        let fp = self.func.fp;
+        let addr = vec![I::LocalGet(fp), I::I32Const(slot as i32), I::Num(N::I32Add)];
+        let mut conds = Vec::new();
+        let mut binds = Vec::new();
+        self.pattern(pattern, &addr, elem, &mut conds, &mut binds);
+        if !conds.is_empty() {
+            self.conds(conds);
+            self.emit(I::Num(N::I32Eqz));
+            self.open(I::If(BlockType::Empty));
+            self.panic("pattern did not match the element");
+            self.end();
+        }
+        self.emit_all(&binds);
+    }
+
+    /// The body of a `for` loop, which `continue` leaves.
+    fn loop_body(&mut self, exit: u32, body: &'a BlockExpr) {
+        let next = self.open(I::Block(BlockType::Empty));
+        self.func.loops.push(LoopLabels {
+            exit,
+            next,
+            result: None,
+        });
+        let ty = self.block(body);
+        self.discard(&ty);
+        self.func.loops.pop();
+        self.end();
+    }
+
+    // ---------------------------------------------------------- patterns
+
+    /// Tests the value at `addr`, of type `ty`, against `pattern`. The code
+    /// for each condition that must hold goes in `conds`, and the code
+    /// binding its variables, which may only run once they hold, in `binds`.
+    fn pattern(
+        &mut self,
+        pattern: &'a Spanned<Pattern>,
+        addr: &[I],
+        ty: &Ty,
+        conds: &mut Vec<Vec<I>>,
+        binds: &mut Vec<I>,
+    ) {
+        let binding = match &pattern.node {
+            Pattern::Wildcard => return,
+            Pattern::Variable(name) => self
+                .res
+                .lookup(name.span)
+                .is_none_or(|def| matches!(self.res.def(def).kind, DefKind::Local { .. })),
+            _ => false,
+        };
+        if binding {
+            if let Pattern::Variable(name) = &pattern.node
+                && let Some(def) = self.res.lookup(name.span)
+            {
+                let (size, align) = self.size_align(ty);
+                let offset = self.slot(size, align);
+                self.func.homes.insert(def, Home::Frame(offset));
+                let code = self.capture(|this| {
+                    this.frame_addr(offset);
+                    this.emit_all(addr);
+                    this.load(ty);
+                    this.store(ty);
+                });
+                binds.extend(code);
+            }
+            return;
+        }
+
+        // patterns look through references and into optionals
+        let mut addr = addr.to_vec();
+        let mut ty = ty.clone();
+        while let Ty::Ref { inner, .. } = &ty
+            && !matches!(**inner, Ty::Slice(_))
+        {
+            addr.push(I::Mem(MemOp::I32Load, 0));
+            ty = (**inner).clone();
+        }
+        let none = matches!(&pattern.node, Pattern::Literal(l) if matches!(l.node, Literal::None));
+        if let Ty::Optional(inner) = &ty
+            && !none
+        {
+            let mut cond = addr.clone();
+            cond.push(I::Mem(MemOp::I32Load8U, 0));
+            conds.push(cond);
+            let offset = self.size_align(inner).1.max(1);
+            addr.extend([I::I32Const(offset as i32), I::Num(N::I32Add)]);
+            ty = (**inner).clone();
+        }
+
+        match &pattern.node {
+            Pattern::Wildcard | Pattern::Error(_) => (),
+            Pattern::Literal(literal) => {
+                if none {
+                    let mut cond = addr;
+                    cond.extend([I::Mem(MemOp::I32Load8U, 0), I::Num(N::I32Eqz)]);
+                    conds.push(cond);
+                    return;
+                }
+                let literal_ty = self.ty(literal.span);
+                let cond = self.capture(|this| {
+                    this.emit_all(&addr);
+                    this.load(&ty);
+                    this.literal(&literal.node, &literal_ty);
+                    this.eq(&ty);
+                });
+                conds.push(cond);
+            }
+            Pattern::Variable(name) => {
+                let Some(def) = self.res.lookup(name.span) else {
+                    return;
+                };
+                if self.res.def(def).kind == DefKind::Const {
+                    let Some(&getter) = self.items.get(&def) else {
+                        return;
+                    };
+                    let cond = self.capture(|this| {
+                        this.emit_all(&addr);
+                        this.load(&ty);
+                        this.emit(I::Call(getter));
+                        this.eq(&ty);
+                    });
+                    conds.push(cond);
+                } else if let Some((_, tag)) = self.variant_tag(def) {
+                    conds.push(tag_test(&addr, tag));
+                }
+            }
+            Pattern::Tuple { path, elems } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let Some((_, tag)) = self.variant_tag(def) else {
+                    return;
+                };
+                conds.push(tag_test(&addr, tag));
+                for (elem, (_, offset, field_ty)) in elems.iter().zip(self.variant_layout(def)) {
+                    let mut field = addr.clone();
+                    field.extend([I::I32Const(offset as i32), I::Num(N::I32Add)]);
+                    self.pattern(elem, &field, &field_ty, conds, binds);
+                }
+            }
+            Pattern::Struct { path, fields } => {
+                let Some(def) = self.res.lookup(path_span(path)) else {
+                    return;
+                };
+                let layout: Vec<_> = match self.variant_tag(def) {
+                    Some((_, tag)) => {
+                        conds.push(tag_test(&addr, tag));
+                        self.variant_layout(def)
+                    }
+                    None => self
+                        .struct_layout(def)
+                        .into_iter()
+                        .map(|(name, offset, ty)| (Some(name), offset, ty))
+                        .collect(),
+                };
+
+                for field in fields {
+                    let name = field.node.name.node;
+                    let Some((_, offset, field_ty)) =
+                        layout.iter().find(|(n, ..)| *n == Some(name)).cloned()
+                    else {
+                        continue;
+                    };
+                    let mut field_addr = addr.clone();
+                    field_addr.extend([I::I32Const(offset as i32), I::Num(N::I32Add)]);
+                    self.pattern(&field.node.pattern, &field_addr, &field_ty, conds, binds);
+                }
+            }
+        }
+    }
+
+    /// Emits the conjunction of `conds`, each only tested once the ones
+    /// before it hold.
+    fn conds(&mut self, conds: Vec<Vec<I>>) {
+        let count = conds.len();
+        for (idx, cond) in conds.into_iter().enumerate() {
+            self.emit_all(&cond);
+            if idx + 1 < count {
+                self.emit(I::If(BlockType::Value(ValType::I32)));
+            }
+        }
+        for _ in 1..count {
+            self.emit_all(&[I::Else, I::I32Const(0), I::End]);
+        }
+    }
+
+    // ------------------------------------------------ printing and equality
+
+    /// Prints the value on the stack. `nested` pushes whether strings and
+    /// chars are quoted.
+    fn print(&mut self, ty: &Ty, nested: I) {
+        match ty {
+            Ty::Int(int) => {
+                self.widen(ty);
+                self.emit(I::Call(if int.is_signed() {
+                    PRINT_INT
+                } else {
+                    PRINT_UINT
+                }));
+            }
+            Ty::Float(FloatTy::F32) => self.emit(I::Call(PRINT_F32)),
+            Ty::Float(FloatTy::F64) => self.emit(I::Call(PRINT_F64)),
+            Ty::Bool => {
+                let helper = self.print_bool();
+                self.emit(I::Call(helper));
+            }
+            Ty::Char => self.emit_all(&[nested, I::Call(PRINT_CHAR)]),
+            Ty::Str => {
+                let helper = self.print_str();
+                self.emit_all(&[nested, I::Call(helper)]);
+            }
+            Ty::Fn { .. } => {
+                self.emit(I::Drop);
+                self.text("<fn>");
+            }
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => {
+                self.load(inner);
+                self.print(inner, I::I32Const(1));
+            }
+            Ty::Tuple(elems) if elems.is_empty() => self.text("()"),
+            Ty::Ref { .. }
+            | Ty::Slice(_)
+            | Ty::Tuple(_)
+            | Ty::Array(..)
+            | Ty::Optional(_)
+            | Ty::Path(_) => {
+                let helper = self.show(ty);
+                self.emit_all(&[nested, I::Call(helper)]);
+            }
+            _ => self.text("()"),
+        }
+    }
+
+    /// Compares the two values on the stack.
+    fn eq(&mut self, ty: &Ty) {
+        match ty {
+            Ty::Int(int) if int.bits() == 64 => self.emit(I::Num(N::I64Eq)),
+            Ty::Float(FloatTy::F32) => self.emit(I::Num(N::F32Eq)),
+            Ty::Float(FloatTy::F64) => self.emit(I::Num(N::F64Eq)),
+            Ty::Int(_) | Ty::Bool | Ty::Char | Ty::Fn { .. } => self.emit(I::Num(N::I32Eq)),
+            Ty::Ref { inner, .. } if !matches!(**inner, Ty::Slice(_)) => {
+                let b = self.spill(ValType::I32);
+                self.load(inner);
+                self.emit(I::LocalGet(b));
+                self.load(inner);
+                self.eq(inner);
+            }
+            Ty::Str => {
+                let helper = self.eq_helper(&Ty::Slice(Box::new(Ty::Int(IntTy::U8))));
+                self.emit(I::Call(helper));
+            }
+            Ty::Ref { .. }
+            | Ty::Slice(_)
+            | Ty::Tuple(_)
+            | Ty::Array(..)
+            | Ty::Optional(_)
+            | Ty::Path(_)
+                if self.val_type(ty).is_some() =>
+            {
+                let helper = self.eq_helper(ty);
+                self.emit(I::Call(helper));
+            }
+            _ => self.emit(I::I32Const(1)),
+        }
+    }
+
+    // ----------------------------------------------------------- helpers
+
+    /// A function of the runtime, generated by `build` on first use.
+    fn helper(
+        &mut self,
+        key: String,
+        params: &[ValType],
+        results: &[ValType],
+        build: impl FnOnce(&mut Self),
+    ) -> u32 {
+        if let Some(idx) = self.helpers.get(&key) {
+            return *idx;
+        }
+        let idx = self.new_func(format!("ak.{key}"), params.to_vec(), results.to_vec());
+        self.helpers.insert(key, idx);
+
+        let outer = std::mem::take(&mut self.func);
+        self.begin(params.len() as u32);
+        build(self);
+        self.finish(idx);
+        self.func = outer;
+        idx
+    }
+
+    /// Checked `+`, `-`, `*`, `/` and `%`, which panic on overflow like the
+    /// interpreter.
+    fn arith(&mut self, op: BinaryOp, int: IntTy) -> u32 {
+        let (name, verb) = match op {
+            BinaryOp::Add => ("add", "add"),
+            BinaryOp::Sub => ("sub", "subtract"),
+            BinaryOp::Mul => ("mul", "multiply"),
+            BinaryOp::Div => ("div", "divide"),
+            _ => ("rem", "calculate the remainder"),
+        };
+        let overflow = format!("attempt to {verb} with overflow");
+        let zero = match op {
+            BinaryOp::Div => Some("attempt to divide by zero"),
+            BinaryOp::Rem => Some("attempt to calculate the remainder with a divisor of zero"),
+            _ => None,
+        };
+        let wide = int.bits() == 64;
+        let vt = if wide { ValType::I64 } else { ValType::I32 };
+        let signed = int.is_signed();
+
+        self.helper(format!("{name}_{}", int.name()), &[vt, vt], &[vt], |this| {
+            let r = this.local(ValType::I64);
+            if let Some(zero) = zero {
+                this.emit(I::LocalGet(1));
+                this.emit(I::Num(if wide { N::I64Eqz } else { N::I32Eqz }));
+                this.open(I::If(BlockType::Empty));
+                this.panic(zero);
+                this.end();
+            }
+
+            if !wide {
+                // narrow integers are computed exactly in 64 bits
+                let ext = if signed {
+                    N::I64ExtendI32S
+                } else {
+                    N::I64ExtendI32U
+                };
+                this.emit_all(&[I::LocalGet(0), I::Num(ext), I::LocalGet(1), I::Num(ext)]);
+                this.emit(I::Num(match (op, signed) {
+                    (BinaryOp::Add, _) => N::I64Add,
+                    (BinaryOp::Sub, _) => N::I64Sub,
+                    (BinaryOp::Mul, _) => N::I64Mul,
+                    (BinaryOp::Div, _) => N::I64DivS,
+                    _ => N::I64RemS,
+                }));
+                this.emit_all(&[
+                    I::LocalTee(r),
+                    I::I64Const(value::int_min(int) as i64),
+                    I::Num(N::I64LtS),
+                    I::LocalGet(r),
+                    I::I64Const(value::int_max(int) as i64),
+                    I::Num(N::I64GtS),
+                    I::Num(N::I32Or),
+                ]);
+                this.open(I::If(BlockType::Empty));
+                this.panic(&overflow);
+                this.end();
+                this.emit_all(&[I::LocalGet(r), I::Num(N::I32WrapI64)]);
+                return;
+            }
+
+            let (a, b) = (I::LocalGet(0), I::LocalGet(1));
+            match op {
+                BinaryOp::Add | BinaryOp::Sub => {
+                    let add = op == BinaryOp::Add;
+                    this.emit_all(&[
+                        a,
+                        b,
+                        I::Num(if add { N::I64Add } else { N::I64Sub }),
+                        I::LocalSet(r),
+                    ]);
+                    if signed {
+                        // the result has the wrong sign: for `a + b` when it
+                        // differs from both operands, for `a - b` when the
+                        // operands differ in sign and the result is not `a`'s
+                        let (x, y) = if add { (b, I::LocalGet(r)) } else { (a, b) };
+                        this.emit_all(&[
+                            a,
+                            I::LocalGet(r),
+                            I::Num(N::I64Xor),
+                            x,
+                            y,
+                            I::Num(N::I64Xor),
+                        ]);
+                        this.emit_all(&[I::Num(N::I64And), I::I64Const(0), I::Num(N::I64LtS)]);
+                    } else if add {
+                        this.emit_all(&[I::LocalGet(r), a, I::Num(N::I64LtU)]);
+                    } else {
+                        this.emit_all(&[a, b, I::Num(N::I64LtU)]);
+                    }
+                }
+                BinaryOp::Mul => {
+                    this.emit_all(&[a, b, I::Num(N::I64Mul), I::LocalSet(r)]);
+                    // the product divided by one factor is not the other
+                    this.emit_all(&[a, I::Num(N::I64Eqz)]);
+                    this.emit(I::If(BlockType::Value(ValType::I32)));
+                    this.emit(I::I32Const(0));
+                    this.emit(I::Else);
+                    if signed {
+                        this.emit_all(&[
+                            a,
+                            I::I64Const(-1),
+                            I::Num(N::I64Eq),
+                            b,
+                            I::I64Const(i64::MIN),
+                            I::Num(N::I64Eq),
+                            I::Num(N::I32And),
+                            I::If(BlockType::Value(ValType::I32)),
+                            I::I32Const(1),
+                            I::Else,
+                            I::LocalGet(r),
+                            a,
+                            I::Num(N::I64DivS),
+                            b,
+                            I::Num(N::I64Ne),
+                            I::End,
+                        ]);
+                    } else {
+                        this.emit_all(&[
+                            I::LocalGet(r),
+                            a,
+                            I::Num(N::I64DivU),
+                            b,
+                            I::Num(N::I64Ne),
+                        ]);
+                    }
+                    this.emit(I::End);
+                }
+                _ => {
+                    // `i64.div_s` traps on `MIN / -1`, so the check comes
+                    // first; `MIN % -1` is 0 and cannot overflow
+                    if signed && op == BinaryOp::Div {
+                        this.emit_all(&[
+                            a,
+                            I::I64Const(i64::MIN),
+                            I::Num(N::I64Eq),
+                            b,
+                            I::I64Const(-1),
+                            I::Num(N::I64Eq),
+                            I::Num(N::I32And),
+                        ]);
+                        this.open(I::If(BlockType::Empty));
+                        this.panic(&overflow);
+                        this.end();
+                    }
+                    this.emit_all(&[
+                        a,
+                        b,
+                        I::Num(match (op, signed) {
+                            (BinaryOp::Div, true) => N::I64DivS,
+                            (BinaryOp::Div, false) => N::I64DivU,
+                            (_, true) => N::I64RemS,
+                            _ => N::I64RemU,
+                        }),
+                    ]);
+                    return;
+                }
+            }
+            this.open(I::If(BlockType::Empty));
+            this.panic(&overflow);
+            this.end();
+            this.emit(I::LocalGet(r));
+        })
+    }
+
+    fn neg(&mut self, int: IntTy) -> u32 {
+        let wide = int.bits() == 64;
+        let vt = if wide { ValType::I64 } else { ValType::I32 };
+        self.helper(format!("neg_{}", int.name()), &[vt], &[vt], |this| {
+            let (zero, sub, eq) = match wide {
+                true => (I::I64Const(0), N::I64Sub, N::I64Eq),
+                false => (I::I32Const(0), N::I32Sub, N::I32Eq),
+            };
+            if int.is_signed() {
+                let min = value::int_min(int);
+                let min = if wide {
+                    I::I64Const(min as i64)
+                } else {
+                    I::I32Const(min as i32)
+                };
+                this.emit_all(&[I::LocalGet(0), min, I::Num(eq)]);
+            } else {
+                this.emit_all(&[I::LocalGet(0), zero, I::Num(eq), I::Num(N::I32Eqz)]);
+            }
+            this.open(I::If(BlockType::Empty));
+            this.panic("attempt to negate with overflow");
+            this.end();
+            this.emit_all(&[zero, I::LocalGet(0), I::Num(sub)]);
+        })
+    }
+
+    /// `<<` and `>>`, taking the amount as an `i64`.
+    fn shift(&mut self, op: BinaryOp, int: IntTy) -> u32 {
+        let wide = int.bits() == 64;
+        let vt = if wide { ValType::I64 } else { ValType::I32 };
+        let (name, dir) = match op {
+            BinaryOp::Shl => ("shl", "left"),
+            _ => ("shr", "right"),
+        };
+        self.helper(
+            format!("{name}_{}", int.name()),
+            &[vt, ValType::I64],
+            &[vt],
+            |this| {
+                this.emit_all(&[
+                    I::LocalGet(1),
+                    I::I64Const(int.bits() as i64),
+                    I::Num(N::I64GeU),
+                ]);
+                this.open(I::If(BlockType::Empty));
+                this.panic(&format!("attempt to shift {dir} with overflow"));
+                this.end();
+
+                this.emit_all(&[I::LocalGet(0), I::LocalGet(1)]);
+                if !wide {
+                    this.emit(I::Num(N::I32WrapI64));
+                }
+                this.emit(I::Num(match (op, wide, int.is_signed()) {
+                    (BinaryOp::Shl, false, _) => N::I32Shl,
+                    (BinaryOp::Shl, true, _) => N::I64Shl,
+                    (_, false, true) => N::I32ShrS,
+                    (_, false, false) => N::I32ShrU,
+                    (_, true, true) => N::I64ShrS,
+                    (_, true, false) => N::I64ShrU,
+                }));
+                if !wide {
+                    this.normalize(int);
+                }
+            },
+        )
+    }
+
+    /// Copies bytes into memory, returning the address after them.
+    fn write_bytes(&mut self) -> u32 {
+        let i32 = ValType::I32;
+        self.helper("write_bytes".to_owned(), &[i32, i32, i32], &[i32], |this| {
+            this.emit_all(&[
+                I::LocalGet(2),
+                I::LocalGet(0),
+                I::LocalGet(1),
+                I::MemoryCopy,
+                I::LocalGet(2),
+                I::LocalGet(1),
+                I::Num(N::I32Add),
+            ]);
+        })
+    }
+
+    /// Writes an integer in decimal, returning the address after it.
+    fn write_int(&mut self) -> u32 {
+        let i32 = ValType::I32;
+        self.helper(
+            "write_int".to_owned(),
+            &[ValType::I64, i32, i32],
+            &[i32],
+            |this| {
+                let digits = this.slot(24, 1);
+                let (pos, neg) = (this.local(i32), this.local(i32));
+                let magnitude = this.local(ValType::I64);
+
+                this.emit_all(&[
+                    I::LocalGet(1),
+                    I::LocalGet(0),
+                    I::I64Const(0),
+                    I::Num(N::I64LtS),
+                    I::Num(N::I32And),
+                    I::LocalTee(neg),
+                    I::If(BlockType::Value(ValType::I64)),
+                    I::I64Const(0),
+                    I::LocalGet(0),
+                    I::Num(N::I64Sub),
+                    I::Else,
+                    I::LocalGet(0),
+                    I::End,
+                    I::LocalSet(magnitude),
+                ]);
+                this.frame_addr(digits + 24);
+                this.emit(I::LocalSet(pos));
+
+                // digits are written backwards from the end of the buffer
+                let top = this.open(I::Loop(BlockType::Empty));
+                this.emit_all(&[
+                    I::LocalGet(pos),
+                    I::I32Const(1),
+                    I::Num(N::I32Sub),
+                    I::LocalTee(pos),
+                    I::LocalGet(magnitude),
+                    I::I64Const(10),
+                    I::Num(N::I64RemU),
+                    I::Num(N::I32WrapI64),
+                    I::I32Const(b'0' as i32),
+                    I::Num(N::I32Add),
+                    I::Mem(MemOp::I32Store8, 0),
+                    I::LocalGet(magnitude),
+                    I::I64Const(10),
+                    I::Num(N::I64DivU),
+                    I::LocalTee(magnitude),
+                    I::Num(N::I64Eqz),
+                    I::Num(N::I32Eqz),
+                ]);
+                this.br_if(top);
+                this.end();
+
+                this.emit(I::LocalGet(neg));
+                this.open(I::If(BlockType::Empty));
+                this.emit_all(&[
+                    I::LocalGet(pos),
+                    I::I32Const(1),
+                    I::Num(N::I32Sub),
+                    I::LocalTee(pos),
+                    I::I32Const(b'-' as i32),
+                    I::Mem(MemOp::I32Store8, 0),
+                ]);
+                this.end();
+
+                let write = this.write_bytes();
+                this.emit(I::LocalGet(pos));
+                this.frame_addr(digits + 24);
+                this.emit_all(&[
+                    I::LocalGet(pos),
+                    I::Num(N::I32Sub),
+                    I::LocalGet(2),
+                    I::Call(write),
+                ]);
+            },
+        )
+    }
+
+    /// Panics with a message built from text and integers, each integer
+    /// given as the code pushing it as an `i64` and whether it is signed.
+    fn panic_with(&mut self, parts: &[Result<&str, &[I]>]) {
+        let write_bytes = self.write_bytes();
+        let write_int = self.write_int();
+        let scratch = self.scratch as i32;
+
+        self.emit(I::I32Const(scratch));
+        let dst = self.spill(ValType::I32);
+        for part in parts {
+            match part {
+                Ok(text) => {
+                    let (ptr, len) = self.bytes(text);
+                    self.emit_all(&[I::I32Const(ptr), I::I32Const(len), I::LocalGet(dst)]);
+                    self.emit(I::Call(write_bytes));
+                }
+                Err(int) => {
+                    self.emit_all(int);
+                    self.emit_all(&[I::LocalGet(dst), I::Call(write_int)]);
+                }
+            }
+            self.emit(I::LocalSet(dst));
+        }
+        self.emit_all(&[
+            I::I32Const(scratch),
+            I::LocalGet(dst),
+            I::I32Const(scratch),
+            I::Num(N::I32Sub),
+            I::Call(PANIC),
+            I::Unreachable,
+        ]);
+    }
+
+    /// Checks an index, an `i64` and whether it is signed, against a length.
+    fn index_helper(&mut self) -> u32 {
+        let i32 = ValType::I32;
+        self.helper(
+            "index".to_owned(),
+            &[ValType::I64, i32, i32],
+            &[i32],
+            |this| {
+                // a negative index is also too large, as an unsigned number
+                this.emit_all(&[
+                    I::LocalGet(0),
+                    I::LocalGet(1),
+                    I::Num(N::I64ExtendI32U),
+                    I::Num(N::I64GeU),
+                ]);
+                this.open(I::If(BlockType::Empty));
+                this.panic_with(&[
+                    Ok("index out of bounds: the length is "),
+                    Err(&[I::LocalGet(1), I::Num(N::I64ExtendI32U), I::I32Const(0)]),
+                    Ok(" but the index is "),
+                    Err(&[I::LocalGet(0), I::LocalGet(2)]),
+                ]);
+                this.end();
+                this.emit_all(&[I::LocalGet(0), I::Num(N::I32WrapI64)]);
+            },
+        )
+    }
+
+    fn check_range(&mut self) -> u32 {
+        let i64 = ValType::I64;
+        self.helper(
+            "check_range".to_owned(),
+            &[i64, i64, ValType::I32],
+            &[],
+            |this| {
+                this.emit_all(&[
+                    I::LocalGet(0),
+                    I::I64Const(0),
+                    I::Num(N::I64LtS),
+                    I::LocalGet(0),
+                    I::LocalGet(1),
+                    I::Num(N::I64GtS),
+                    I::Num(N::I32Or),
+                    I::LocalGet(1),
+                    I::LocalGet(2),
+                    I::Num(N::I64ExtendI32U),
+                    I::Num(N::I64GtS),
+                    I::Num(N::I32Or),
+                ]);
+                this.open(I::If(BlockType::Empty));
+                this.panic_with(&[
+                    Ok("range "),
+                    Err(&[I::LocalGet(0), I::I32Const(1)]),
+                    Ok(".."),
+                    Err(&[I::LocalGet(1), I::I32Const(1)]),
+                    Ok(" out of bounds for length "),
+                    Err(&[I::LocalGet(2), I::Num(N::I64ExtendI32U), I::I32Const(0)]),
+                ]);
+                this.end();
+            },
+        )
+    }
+
+    /// Writes the substring of a `str` between two byte offsets to the
+    /// `str` at the last parameter, returning its address.
+    fn str_slice(&mut self) -> u32 {
+        let (i32, i64) = (ValType::I32, ValType::I64);
+        self.helper(
+            "str_slice".to_owned(),
+            &[i32, i64, i64, i32],
+            &[i32],
+            |this| {
+                let check = this.check_range();
+                this.emit_all(&[
+                    I::LocalGet(1),
+                    I::LocalGet(2),
+                    I::LocalGet(0),
+                    I::Mem(MemOp::I32Load, 4),
+                    I::Call(check),
+                ]);
+
+                // an offset inside the string must be at the start of a char
+                for bound in [1, 2] {
+                    this.emit_all(&[
+                        I::LocalGet(bound),
+                        I::LocalGet(0),
+                        I::Mem(MemOp::I32Load, 4),
+                        I::Num(N::I64ExtendI32U),
+                        I::Num(N::I64LtU),
+                        I::If(BlockType::Value(i32)),
+                        I::LocalGet(0),
+                        I::Mem(MemOp::I32Load, 0),
+                        I::LocalGet(bound),
+                        I::Num(N::I32WrapI64),
+                        I::Num(N::I32Add),
+                        I::Mem(MemOp::I32Load8U, 0),
+                        I::I32Const(0xc0),
+                        I::Num(N::I32And),
+                        I::I32Const(0x80),
+                        I::Num(N::I32Eq),
+                        I::Else,
+                        I::I32Const(0),
+                        I::End,
+                    ]);
+                    this.open(I::If(BlockType::Empty));
+                    this.panic("string slice does not fall on character boundaries");
+                    this.end();
+                }
+
+                this.emit_all(&[
+                    I::LocalGet(3),
+                    I::LocalGet(0),
+                    I::Mem(MemOp::I32Load, 0),
+                    I::LocalGet(1),
+                    I::Num(N::I32WrapI64),
+                    I::Num(N::I32Add),
+                    I::Mem(MemOp::I32Store, 0),
+                    I::LocalGet(3),
+                    I::LocalGet(2),
+                    I::LocalGet(1),
+                    I::Num(N::I64Sub),
+                    I::Num(N::I32WrapI64),
+                    I::Mem(MemOp::I32Store, 4),
+                    I::LocalGet(3),
+                ]);
+            },
+        )
+    }
+
+    /// Compares two `str`s byte by byte, giving -1, 0 or 1.
+    fn str_cmp(&mut self) -> u32 {
+        let i32 = ValType::I32;
+        self.helper("str_cmp".to_owned(), &[i32, i32], &[i32], |this| {
+            let (idx, len, a, b) = (
+                this.local(i32),
+                this.local(i32),
+                this.local(i32),
+                this.local(i32),
+            );
+            let order = |this: &mut Self, x: u32, y: u32| {
+                this.emit_all(&[
+                    I::LocalGet(x),
+                    I::LocalGet(y),
+                    I::Num(N::I32GtU),
+                    I::LocalGet(x),
+                    I::LocalGet(y),
+                    I::Num(N::I32LtU),
+                    I::Num(N::I32Sub),
+                ]);
+            };
+
+            // the length of the shorter one
+            this.emit_all(&[
+                I::LocalGet(0),
+                I::Mem(MemOp::I32Load, 4),
+                I::LocalGet(1),
+                I::Mem(MemOp::I32Load, 4),
+                I::LocalGet(0),
+                I::Mem(MemOp::I32Load, 4),
+                I::LocalGet(1),
+                I::Mem(MemOp::I32Load, 4),
+                I::Num(N::I32LtU),
+                I::Select,
+                I::LocalSet(len),
+            ]);
+
+            let exit = this.open(I::Block(BlockType::Empty));
+            let top = this.open(I::Loop(BlockType::Empty));
+            this.emit_all(&[I::LocalGet(idx), I::LocalGet(len), I::Num(N::I32GeU)]);
+            this.br_if(exit);
+            for (s, byte) in [(0, a), (1, b)] {
+                this.emit_all(&[
+                    I::LocalGet(s),
+                    I::Mem(MemOp::I32Load, 0),
+                    I::LocalGet(idx),
+                    I::Num(N::I32Add),
+                    I::Mem(MemOp::I32Load8U, 0),
+                    I::LocalSet(byte),
+                ]);
+            }
+            this.emit_all(&[I::LocalGet(a), I::LocalGet(b), I::Num(N::I32Ne)]);
+            this.open(I::If(BlockType::Empty));
+            order(this, a, b);
+            this.epilogue();
+            this.emit(I::Return);
+            this.end();
+            this.emit_all(&[
+                I::LocalGet(idx),
+                I::I32Const(1),
+                I::Num(N::I32Add),
+                I::LocalSet(idx),
+            ]);
+            this.br(top);
+            this.end();
+            this.end();
+
+            for (s, local) in [(0, a), (1, b)] {
+                this.emit_all(&[
+                    I::LocalGet(s),
+                    I::Mem(MemOp::I32Load, 4),
+                    I::LocalSet(local),
+                ]);
+            }
+            order(this, a, b);
+        })
+    }
+
+    /// Decodes the UTF-8 char at an offset into bytes, returning it and the
+    /// offset after it.
+    fn str_next(&mut self) -> u32 {
+        let i32 = ValType::I32;
+        self.helper("str_next".to_owned(), &[i32, i32], &[i32, i32], |this| {
+            let (p, first) = (this.local(i32), this.local(i32));
+            this.emit_all(&[
+                I::LocalGet(0),
+                I::LocalGet(1),
+                I::Num(N::I32Add),
+                I::LocalTee(p),
+                I::Mem(MemOp::I32Load8U, 0),
+                I::LocalSet(first),
+            ]);
+
+            // the payload bits of the byte at `offset` after the first
+            let cont = |offset: u32, shift: i32| {
+                [
+                    I::LocalGet(p),
+                    I::Mem(MemOp::I32Load8U, offset),
+                    I::I32Const(0x3f),
+                    I::Num(N::I32And),
+                    I::I32Const(shift),
+                    I::Num(N::I32Shl),
+                    I::Num(N::I32Or),
+                ]
+            };
+            let lead = |mask: i32, shift: i32| {
+                [
+                    I::LocalGet(first),
+                    I::I32Const(mask),
+                    I::Num(N::I32And),
+                    I::I32Const(shift),
+                    I::Num(N::I32Shl),
+                ]
+            };
+
+            let len = this.local(i32);
+            this.emit_all(&[I::LocalGet(first), I::I32Const(0x80), I::Num(N::I32LtU)]);
+            this.emit(I::If(BlockType::Value(i32)));
+            this.emit_all(&[I::I32Const(1), I::LocalSet(len), I::LocalGet(first)]);
+            this.emit(I::Else);
+            this.emit_all(&[I::LocalGet(first), I::I32Const(0xe0), I::Num(N::I32LtU)]);
+            this.emit(I::If(BlockType::Value(i32)));
+            this.emit_all(&[I::I32Const(2), I::LocalSet(len)]);
+            this.emit_all(&lead(0x1f, 6));
+            this.emit_all(&cont(1, 0));
+            this.emit(I::Else);
+            this.emit_all(&[I::LocalGet(first), I::I32Const(0xf0), I::Num(N::I32LtU)]);
+            this.emit(I::If(BlockType::Value(i32)));
+            this.emit_all(&[I::I32Const(3), I::LocalSet(len)]);
+            this.emit_all(&lead(0x0f, 12));
+            this.emit_all(&cont(1, 6));
+            this.emit_all(&cont(2, 0));
+            this.emit(I::Else);
+            this.emit_all(&[I::I32Const(4), I::LocalSet(len)]);
+            this.emit_all(&lead(0x07, 18));
+            this.emit_all(&cont(1, 12));
+            this.emit_all(&cont(2, 6));
+            this.emit_all(&cont(3, 0));
+            this.emit_all(&[I::End, I::End, I::End]);
+            this.emit_all(&[I::LocalGet(1), I::LocalGet(len), I::Num(N::I32Add)]);
+        })
+    }
+
+    fn print_bool(&mut self) -> u32 {
+        self.helper("print_bool".to_owned(), &[ValType::I32], &[], |this| {
+            this.emit(I::LocalGet(0));
+            this.open(I::If(BlockType::Empty));
+            this.text("true");
+            this.emit(I::Else);
+            this.text("false");
+            this.end();
+        })
+    }
+
+    fn print_str(&mut self) -> u32 {
+        let i32 = ValType::I32;
+        self.helper("print_str".to_owned(), &[i32, i32], &[], |this| {
+            this.emit_all(&[
+                I::LocalGet(0),
+                I::Mem(MemOp::I32Load, 0),
+                I::LocalGet(0),
+                I::Mem(MemOp::I32Load, 4),
+                I::LocalGet(1),
+                I::Call(PRINT_STR),
+            ]);
+        })
+    }
+
+    /// A function printing values of a type kept in memory.
+    fn show(&mut self, ty: &Ty) -> u32 {
+        let i32 = ValType::I32;
+        let ty = ty.clone();
+        self.helper(format!("show {ty}"), &[i32, i32], &[], |this| {
+            let field = |this: &mut Self, offset: u32, ty: &Ty, nested: I| {
+                this.emit(I::LocalGet(0));
+                this.add_offset(offset);
+                this.load(ty);
+                this.print(ty, nested);
+            };
+
+            match &ty {
+                Ty::Tuple(elems) => {
+                    let (offsets, ..) = this.fields_layout(elems.iter());
+                    this.text("(");
+                    for (idx, (elem, offset)) in elems.iter().zip(offsets).enumerate() {
+                        if idx != 0 {
+                            this.text(", ");
+                        }
+                        field(this, offset, elem, I::I32Const(1));
+                    }
+                    this.text(if elems.len() == 1 { ",)" } else { ")" });
+                }
+                Ty::Optional(inner) => {
+                    this.emit_all(&[I::LocalGet(0), I::Mem(MemOp::I32Load8U, 0)]);
+                    this.open(I::If(BlockType::Empty));
+                    let offset = this.size_align(inner).1.max(1);
+                    field(this, offset, inner, I::LocalGet(1));
+                    this.emit(I::Else);
+                    this.text("none");
+                    this.end();
+                }
+                Ty::Array(elem, _) | Ty::Slice(elem) | Ty::Ref { inner: elem, .. } => {
+                    let elem = match &**elem {
+                        Ty::Slice(elem) => (**elem).clone(),
+                        elem => elem.clone(),
+                    };
+                    let (data, len, idx) = (this.local(i32), this.local(i32), this.local(i32));
+                    match &ty {
+                        Ty::Array(_, n) => this.emit_all(&[
+                            I::LocalGet(0),
+                            I::LocalSet(data),
+                            I::I32Const(*n as i32),
+                            I::LocalSet(len),
+                        ]),
+                        _ => this.emit_all(&[
+                            I::LocalGet(0),
+                            I::Mem(MemOp::I32Load, 0),
+                            I::LocalSet(data),
+                            I::LocalGet(0),
+                            I::Mem(MemOp::I32Load, 4),
+                            I::LocalSet(len),
+                        ]),
+                    }
+
+                    this.text("[");
+                    let exit = this.open(I::Block(BlockType::Empty));
+                    let top = this.open(I::Loop(BlockType::Empty));
+                    this.emit_all(&[I::LocalGet(idx), I::LocalGet(len), I::Num(N::I32GeU)]);
+                    this.br_if(exit);
+                    this.emit(I::LocalGet(idx));
+                    this.open(I::If(BlockType::Empty));
+                    this.text(", ");
+                    this.end();
+                    this.elem_at(data, idx, &elem);
+                    this.print(&elem, I::I32Const(1));
+                    this.br(top);
+                    this.end();
+                    this.end();
+                    this.text("]");
+                }
+                Ty::Path(path) => {
+                    let Some(def) = this.res.def_by_path(path) else {
+                        return;
+                    };
+                    let name = this.res.def(def).name.as_str();
+                    if this.res.def(def).kind != DefKind::Enum {
+                        let fields = this.struct_layout(def);
+                        this.text(&format!("{name} {{ "));
+                        for (idx, (field_name, offset, field_ty)) in fields.iter().enumerate() {
+                            let sep = if idx == 0 { "" } else { ", " };
+                            this.text(&format!("{sep}{}: ", field_name.as_str()));
+                            field(this, *offset, field_ty, I::I32Const(1));
+                        }
+                        this.text(" }");
+                        return;
+                    }
+
+                    let variants = this.types.enums.get(&def).cloned().unwrap_or_default();
+                    for (tag, variant) in variants.iter().enumerate() {
+                        this.emit_all(&tag_test(&[I::LocalGet(0)], tag as u32));
+                        this.open(I::If(BlockType::Empty));
+                        this.text(&format!("{name}::{}", this.res.def(*variant).name.as_str()));
+                        let fields = this.variant_layout(*variant);
+                        let named = matches!(
+                            this.types.variants.get(variant),
+                            Some(VariantShape::Struct(_))
+                        );
+                        if named {
+                            this.text(" { ");
+                        } else if !fields.is_empty() {
+                            this.text("(");
+                        }
+                        for (idx, (field_name, offset, field_ty)) in fields.iter().enumerate() {
+                            if idx != 0 {
+                                this.text(", ");
+                            }
+                            if let Some(field_name) = field_name {
+                                this.text(&format!("{}: ", field_name.as_str()));
+                            }
+                            field(this, *offset, field_ty, I::I32Const(1));
+                        }
+                        if named {
+                            this.text(" }");
+                        } else if !fields.is_empty() {
+                            this.text(")");
+                        }
+                        this.end();
+                    }
+                }
+                _ => (),
+            }
+        })
+    }
+
+    /// A function comparing values of a type kept in memory.
+    fn eq_helper(&mut self, ty: &Ty) -> u32 {
+        let i32 = ValType::I32;
+        let ty = ty.clone();
+        self.helper(format!("eq {ty}"), &[i32, i32], &[i32], |this| {
+            let result = this.local(i32);
+            // `result &= a.field == b.field`
+            let field = |this: &mut Self, offset: u32, ty: &Ty| {
+                this.emit(I::LocalGet(result));
+                for param in [0, 1] {
+                    this.emit(I::LocalGet(param));
+                    this.add_offset(offset);
+                    this.load(ty);
+                }
+                this.eq(ty);
+                this.emit_all(&[I::Num(N::I32And), I::LocalSet(result)]);
+            };
+            this.emit_all(&[I::I32Const(1), I::LocalSet(result)]);
+
+            match &ty {
+                Ty::Tuple(elems) => {
+                    let (offsets, ..) = this.fields_layout(elems.iter());
+                    for (elem, offset) in elems.iter().zip(offsets) {
+                        field(this, offset, elem);
+                    }
+                }
+                Ty::Optional(inner) => {
+                    this.emit_all(&[
+                        I::LocalGet(0),
+                        I::Mem(MemOp::I32Load8U, 0),
+                        I::LocalGet(1),
+                        I::Mem(MemOp::I32Load8U, 0),
+                        I::Num(N::I32Eq),
+                        I::LocalSet(result),
+                        I::LocalGet(result),
+                        I::LocalGet(0),
+                        I::Mem(MemOp::I32Load8U, 0),
+                        I::Num(N::I32And),
+                    ]);
+                    this.open(I::If(BlockType::Empty));
+                    let offset = this.size_align(inner).1.max(1);
+                    field(this, offset, inner);
+                    this.end();
+                }
+                Ty::Array(elem, _) | Ty::Slice(elem) | Ty::Ref { inner: elem, .. } => {
+                    let elem = match &**elem {
+                        Ty::Slice(elem) => (**elem).clone(),
+                        elem => elem.clone(),
+                    };
+                    let size = this.size_align(&elem).0;
+                    let (a, b, len, idx) = (
+                        this.local(i32),
+                        this.local(i32),
+                        this.local(i32),
+                        this.local(i32),
+                    );
+                    match &ty {
+                        Ty::Array(_, n) => this.emit_all(&[
+                            I::LocalGet(0),
+                            I::LocalSet(a),
+                            I::LocalGet(1),
+                            I::LocalSet(b),
+                            I::I32Const(*n as i32),
+                            I::LocalSet(len),
+                        ]),
+                        _ => this.emit_all(&[
+                            I::LocalGet(0),
+                            I::Mem(MemOp::I32Load, 0),
+                            I::LocalSet(a),
+                            I::LocalGet(1),
+                            I::Mem(MemOp::I32Load, 0),
+                            I::LocalSet(b),
+                            I::LocalGet(0),
+                            I::Mem(MemOp::I32Load, 4),
+                            I::LocalTee(len),
+                            I::LocalGet(1),
+                            I::Mem(MemOp::I32Load, 4),
+                            I::Num(N::I32Eq),
+                            I::LocalSet(result),
+                        ]),
+                    }
+
+                    let exit = this.open(I::Block(BlockType::Empty));
+                    let top = this.open(I::Loop(BlockType::Empty));
+                    this.emit_all(&[
+                        I::LocalGet(result),
+                        I::Num(N::I32Eqz),
+                        I::LocalGet(idx),
+                        I::LocalGet(len),
+                        I::Num(N::I32GeU),
+                        I::Num(N::I32Or),
+                    ]);
+                    this.br_if(exit);
+                    this.emit(I::LocalGet(result));
+                    for ptr in [a, b] {
+                        this.emit_all(&[
+                            I::LocalGet(ptr),
+                            I::LocalGet(idx),
+                            I::I32Const(size as i32),
+                            I::Num(N::I32Mul),
+                            I::Num(N::I32Add),
+                        ]);
+                        this.load(&elem);
+                    }
+                    this.eq(&elem);
+                    this.emit_all(&[
+                        I::Num(N::I32And),
+                        I::LocalSet(result),
+                        I::LocalGet(idx),
+                        I::I32Const(1),
+                        I::Num(N::I32Add),
+                        I::LocalSet(idx),
+                    ]);
+                    this.br(top);
+                    this.end();
+                    this.end();
+                }
+                Ty::Path(path) => {
+                    let Some(def) = this.res.def_by_path(path) else {
+                        this.emit(I::LocalGet(result));
+                        return;
+                    };
+                    if this.res.def(def).kind != DefKind::Enum {
+                        for (_, offset, field_ty) in this.struct_layout(def) {
+                            field(this, offset, &field_ty);
+                        }
+                    } else {
+                        this.emit_all(&[
+                            I::LocalGet(0),
+                            I::Mem(MemOp::I32Load, 0),
+                            I::LocalGet(1),
+                            I::Mem(MemOp::I32Load, 0),
+                            I::Num(N::I32Eq),
+                            I::LocalSet(result),
+                        ]);
+                        // only the fields of the variant both values are
+                        let variants = this.types.enums.get(&def).cloned().unwrap_or_default();
+                        for (tag, variant) in variants.iter().enumerate() {
+                            let fields = this.variant_layout(*variant);
+                            if fields.is_empty() {
+                                continue;
+                            }
+                            this.emit(I::LocalGet(result));
+                            this.emit_all(&tag_test(&[I::LocalGet(0)], tag as u32));
+                            this.emit(I::Num(N::I32And));
+                            this.open(I::If(BlockType::Empty));
+                            for (_, offset, field_ty) in fields {
+                                field(this, offset, &field_ty);
+                            }
+                            this.end();
+                        }
+                    }
+                }
+                _ => (),
+            }
+            this.emit(I::LocalGet(result));
+        })
+    }
+}
+
+fn align(value: u32, to: u32) -> u32 {
+    value.div_ceil(to) * to
+}
+
+/// The code testing whether the enum at `addr` is the variant `tag`.
+fn tag_test(addr: &[I], tag: u32) -> Vec<I> {
+    let mut code = addr.to_vec();
+    code.extend([
+        I::Mem(MemOp::I32Load, 0),
+        I::I32Const(tag as i32),
+        I::Num(N::I32Eq),
+    ]);
+    code
+}
+
+fn compare(op: BinaryOp, ty: ValType, signed: bool) -> N {
+    use BinaryOp::*;
+
+    match (ty, op, signed) {
+        (ValType::I32, Eq, _) => N::I32Eq,
+        (ValType::I32, Ne, _) => N::I32Ne,
+        (ValType::I32, Lt, true) => N::I32LtS,
+        (ValType::I32, Lt, false) => N::I32LtU,
+        (ValType::I32, Gt, true) => N::I32GtS,
+        (ValType::I32, Gt, false) => N::I32GtU,
+        (ValType::I32, Le, true) => N::I32LeS,
+        (ValType::I32, Le, false) => N::I32LeU,
+        (ValType::I32, _, true) => N::I32GeS,
+        (ValType::I32, _, false) => N::I32GeU,
+        (ValType::I64, Eq, _) => N::I64Eq,
+        (ValType::I64, Ne, _) => N::I64Ne,
+        (ValType::I64, Lt, true) => N::I64LtS,
+        (ValType::I64, Lt, false) => N::I64LtU,
+        (ValType::I64, Gt, true) => N::I64GtS,
+        (ValType::I64, Gt, false) => N::I64GtU,
+        (ValType::I64, Le, true) => N::I64LeS,
+        (ValType::I64, Le, false) => N::I64LeU,
+        (ValType::I64, _, true) => N::I64GeS,
+        (ValType::I64, _, false) => N::I64GeU,
+        (ValType::F32, Eq, _) => N::F32Eq,
+        (ValType::F32, Ne, _) => N::F32Ne,
+        (ValType::F32, Lt, _) => N::F32Lt,
+        (ValType::F32, Gt, _) => N::F32Gt,
+        (ValType::F32, Le, _) => N::F32Le,
+        (ValType::F32, _, _) => N::F32Ge,
+        (ValType::F64, Eq, _) => N::F64Eq,
+        (ValType::F64, Ne, _) => N::F64Ne,
+        (ValType::F64, Lt, _) => N::F64Lt,
+        (ValType::F64, Gt, _) => N::F64Gt,
+        (ValType::F64, Le, _) => N::F64Le,
+        (ValType::F64, _, _) => N::F64Ge,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        process::{Command, Output},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::{testing, wasm::MAGIC};
+
+    /// Runs a module exporting `main` with the imports implemented in
+    /// JavaScript.
+    const HARNESS: &str = r#"
+        const fs = require("fs");
+        let memory;
+        let out = "";
+        const text = (ptr, len) => Buffer.from(memory.buffer, ptr, len).toString("utf8");
+        const float = (v, single) => {
+            if (Number.isInteger(v)) return v.toFixed(1);
+            if (!single) return String(v);
+            for (let p = 1; ; p++) {
+                const s = String(Number(v.toPrecision(p)));
+                if (Math.fround(Number(s)) === v) return s;
+            }
+        };
+        const akuru = {
+            print_str: (ptr, len, nested) => {
+                const s = text(ptr, len);
+                out += nested ? JSON.stringify(s) : s;
+            },
+            print_char: (c, nested) => {
+                const s = String.fromCodePoint(c);
+                out += nested ? `'${s}'` : s;
+            },
+            print_int: v => { out += String(v); },
+            print_uint: v => { out += String(BigInt.asUintN(64, v)); },
+            print_f32: v => { out += float(v, true); },
+            print_f64: v => { out += float(v, false); },
+            panic: (ptr, len) => {
+                fs.writeSync(1, out);
+                fs.writeSync(2, `error: ${text(ptr, len)}\n`);
+                process.exit(101);
+            },
+            fmod: (a, b) => a % b,
+        };
+        const wasm = new WebAssembly.Module(fs.readFileSync(process.argv[2]));
+        const instance = new WebAssembly.Instance(wasm, { akuru });
+        memory = instance.exports.memory;
+        instance.exports.main();
+        fs.writeSync(1, out);
+    "#;
+
+    struct Outcome {
+        module: Module,
+        /// what running the module did, if node is installed
+        run: Option<Output>,
+        expected: String,
+    }
+
+    /// Compiles `src` to a module, which must validate, and runs it with
+    /// node next to the interpreter.
+    fn run(src: &str) -> Outcome {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let checked = testing::check(src);
+        let mut wasmgen = WasmGen::new(&checked.res, &checked.types);
+        let module = wasmgen.generate(&checked.script);
+        assert!(
+            wasmgen.bag.is_empty(),
+            "codegen errors: {:?}",
+            wasmgen.bag.0
+        );
+        if let Err(err) = module.validate() {
+            panic!("invalid module: {err}\n{}", module.to_wat());
+        }
+
+        let (expected, _) = checked.interpret();
+
+        let dir: PathBuf = std::env::temp_dir().join(format!(
+            "akuru-wasmgen-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.wasm"), module.encode()).unwrap();
+        std::fs::write(dir.join("run.cjs"), HARNESS).unwrap();
+
+        let run = Command::new("node")
+            .args(["run.cjs", "main.wasm"])
+            .current_dir(&dir)
+            .output()
+            .ok();
+        _ = std::fs::remove_dir_all(&dir);
+        Outcome {
+            module,
+            run,
+            expected,
+        }
+    }
+
+    fn assert_agrees(outcome: &Outcome) {
+        if let Some(run) = &outcome.run {
+            assert!(
+                run.status.success(),
+                "{}",
+                String::from_utf8_lossy(&run.stderr)
+            );
+            assert_eq!(String::from_utf8_lossy(&run.stdout), outcome.expected);
+        }
+    }
+
+    #[test]
+    fn test_aggregates_in_linear_memory_are_copied() {
+        // the recursion is deep enough to need room in the interpreter
+        let outcome = testing::with_big_stack(|| {
+            run("struct Grid { cells: [i32; 4], tag: u8 }
+             fn scribble(g: Grid): i32 {
+                let mut h = g;
+                h.cells[3] = 9;
+                h.cells[3] + g.cells[3]
+             }
+             fn fill(depth: i32): i32 {
+                let local = [depth, depth * 2, depth * 3];
+                if depth == 0 { return 0; }
+                let below = fill(depth - 1);
+                local[0] + local[1] + local[2] + below
+             }
+             pub fn main() {
+                let g = Grid { cells: [1, 2, 3, 4], tag: 255 };
+                let mut copy = g;
+                copy.cells[0] = 50;
+                println((scribble(g), g.cells, copy.cells[0], fill(200)));
+             }")
+        });
+
+        assert_eq!(outcome.expected, "(13, [1, 2, 3, 4], 50, 120600)\n");
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_wide_and_unsigned_integers() {
+        let outcome = run("pub fn main() {
+                let big: u64 = 18446744073709551615;
+                let wide: i64 = -9223372036854775807 - 1;
+                let x: u32 = 4294967295;
+                println((big, big / 3, wide, x, x / 2 + 1, 1 as u32 << 31 as u32));
+             }");
+
+        assert_eq!(
+            outcome.expected,
+            "(18446744073709551615, 6148914691236517205, -9223372036854775808, \
+             4294967295, 2147483648, 2147483648)\n"
+        );
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_slice_utf8_and_print_f32() {
+        let outcome = run("pub fn main() {
+                let s = \"añbçd\";
+                println((s[3..], s[1..3], 0.1 as f32 + 0.2 as f32, 16777217.0 as f32, 1e-3 as f32));
+             }");
+
+        assert_eq!(
+            outcome.expected,
+            "(\"bçd\", \"ñ\", 0.3, 16777216.0, 0.001)\n"
+        );
+        assert_agrees(&outcome);
+    }
+
+    #[test]
+    fn test_u32_overflow_is_caught() {
+        let outcome = run("pub fn main() {
+                let x: u32 = 4294967290;
+                println(x + 5);
+                println(x + 6);
+             }");
+
+        assert_eq!(outcome.expected, "4294967295\n");
+        if let Some(run) = &outcome.run {
+            assert_eq!(run.status.code(), Some(101));
+            assert_eq!(String::from_utf8_lossy(&run.stdout), "4294967295\n");
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors_call_panic() {
+        let outcome = run("pub fn main() {
+                println(1);
+                let xs = [1, 2, 3];
+                let i: i64 = -2;
+                println(xs[i]);
+             }");
+
+        assert_eq!(outcome.expected, "1\n");
+        if let Some(run) = &outcome.run {
+            assert_eq!(run.status.code(), Some(101));
+            assert_eq!(String::from_utf8_lossy(&run.stdout), "1\n");
+            assert_eq!(
+                String::from_utf8_lossy(&run.stderr),
+                "error: index out of bounds: the length is 3 but the index is -2\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_signed_division_overflow_panics_before_dividing() {
+        let outcome = run("pub fn main() {
+                let min: i64 = -9223372036854775808;
+                let d: i64 = -1;
+                println(min % d);
+                println(min / d);
+             }");
+
+        assert_eq!(outcome.expected, "0\n");
+        if let Some(run) = &outcome.run {
+            assert_eq!(run.status.code(), Some(101));
+            assert_eq!(String::from_utf8_lossy(&run.stdout), "0\n");
+            assert_eq!(
+                String::from_utf8_lossy(&run.stderr),
+                "error: attempt to divide with overflow\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_pub_fns_of_the_root() {
+        let outcome = run("pub fn add(a: i32, b: i32): i32 { a + b }
+             fn hidden(): i32 { 1 }
+             section math {
+                pub fn sub(a: i32, b: i32): i32 { a - b }
+             }
+             pub fn main() { println(add(hidden(), math::sub(5, 2))); }");
+
+        let wat = outcome.module.to_wat();
+        assert!(wat.contains("(export \"add\" (func"));
+        assert!(wat.contains("(export \"memory\" (memory 0))"));
+        assert!(!wat.contains("(export \"hidden\""));
+        assert!(!wat.contains("(export \"sub\""));
+        assert!(outcome.module.encode().starts_with(&MAGIC));
+        assert_agrees(&outcome);
+    }
+}